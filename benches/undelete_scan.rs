@@ -0,0 +1,83 @@
+// The inner loop of undelete, recovery::fragment::search_le_bytes_for_dnodes looking for dnodes and objsets in every sector of the data read from the disks
+// Run with: cargo bench --bench undelete_scan
+// To compare with another version, run it with --save-baseline on that version and then with --baseline on this one
+// NOTE: There are no vdevs, so the block pointers of what is found can't be read and nothing is kept
+// this measures the parsing, reading the disks to check the block pointers is a separate cost
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use szfs::{
+    dmu::{BonusType, ObjType},
+    recovery::fragment,
+    test_image::{self, BlockPointerSpec, DNodeSpec, DvaSpec},
+    zio::{ChecksumMethod, CompressionMethod, Vdevs},
+};
+
+const SCAN_SIZE: usize = 4 * 1024 * 1024;
+
+fn file_dnode(offset: u64) -> Vec<u8> {
+    let block_pointer = BlockPointerSpec {
+        dvas: vec![DvaSpec {
+            vdev_id: 0,
+            offset,
+            allocated_size: 4096,
+            is_gang: false,
+        }],
+        level: 0,
+        typ: ObjType::PlainFileContents,
+        checksum_method: ChecksumMethod::Fletcher4,
+        compression_method: CompressionMethod::Lz4,
+        physical_size: 4096,
+        logical_size: 128 * 1024,
+        birth_txg: 10,
+        fill: 1,
+        checksum: [offset, 2, 3, 4],
+    };
+    let bonus = test_image::znode_bonus(0o100644, 128 * 1024, 34, 1);
+    DNodeSpec {
+        typ: ObjType::PlainFileContents,
+        bonus_type: BonusType::ZNode,
+        n_indirect_levels: 1,
+        data_block_size: 128 * 1024,
+        max_block_id: 0,
+        used: 4096,
+        block_pointers: vec![block_pointer.to_bytes_le()],
+        n_block_pointer_slots: DNodeSpec::get_n_block_pointer_slots_for_bonus(bonus.len()),
+        bonus,
+        spill_block_pointer: None,
+    }
+    .to_bytes_le()
+}
+
+// Blocks of dnodes like in a freed metadnode, between data that isn't metadata at all
+fn scan_data() -> Vec<u8> {
+    let mut data = test_image::pattern(SCAN_SIZE, 1);
+    for (index, block) in data.chunks_mut(32 * 1024).enumerate() {
+        if index % 2 == 0 {
+            for (slot, dnode) in block.chunks_mut(512).enumerate() {
+                dnode.copy_from_slice(&file_dnode(((index * 64 + slot) as u64) << 20));
+            }
+        }
+    }
+    data
+}
+
+fn undelete_scan(c: &mut Criterion) {
+    let data = scan_data();
+
+    let mut group = c.benchmark_group("undelete_scan");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    for (name, search_objsets) in [("dnodes", false), ("dnodes_and_objsets", true)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                fragment::search_le_bytes_for_dnodes(
+                    black_box(&data),
+                    &mut Vdevs::new(),
+                    search_objsets,
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, undelete_scan);
+criterion_main!(benches);
@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use szfs::{byte_iter::FromSliceLE, zio::BlockPointer};
+
+fuzz_target!(|data: &[u8]| {
+    let Some(mut bp) = BlockPointer::from_slice_le(data) else {
+        return;
+    };
+    let _ = bp.parse_logical_size();
+    let _ = bp.parse_physical_size();
+
+    // Writing it back has to give bytes that parse to the same block pointer
+    let bytes = bp.to_bytes_le();
+    let reparsed_bp = BlockPointer::from_slice_le(&bytes)
+        .expect("A block pointer that was written back should parse!");
+    assert_eq!(bytes, reparsed_bp.to_bytes_le());
+
+    // Embedded block pointers can be read without any disks, which also decompresses the payload
+    if let BlockPointer::Embedded(embedded_bp) = &mut bp {
+        let _ = embedded_bp.dereference();
+    }
+});
@@ -0,0 +1,59 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use szfs::{
+    byte_iter::FromSliceLE,
+    dmu::{DNode, DNodeBase, ObjSet},
+    zio::Vdevs,
+    zpl,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(dnode) = DNode::from_slice_le(data) {
+        // The bonus buffers are parsed separately, so parse them too
+        match dnode {
+            DNode::DSLDirectory(dnode) => {
+                let _ = dnode.parse_bonus_data();
+            }
+            DNode::DSLDataset(dnode) => {
+                let _ = dnode.parse_bonus_data();
+            }
+            DNode::SpaceMap(dnode) => {
+                let _ = dnode.parse_bonus_data();
+            }
+            DNode::SpaHistory(dnode) => {
+                let _ = dnode.parse_bonus_data();
+            }
+            DNode::PackedNVList(mut dnode) => {
+                let _ = dnode.read_nvlist(&mut Vdevs::new());
+            }
+            // Without any disks only embedded block pointers can be read, which is how small (micro zap) directories are usually stored
+            DNode::DirectoryContents(mut dnode) => {
+                let _ = dnode.dump_zap_contents(&mut Vdevs::new());
+            }
+            DNode::ObjectDirectory(mut dnode) | DNode::MasterNode(mut dnode) => {
+                let _ = dnode.dump_zap_contents(&mut Vdevs::new());
+            }
+            // Old files have znode bonus buffers, which don't need the attribute registry of the dataset
+            DNode::PlainFileContents(dnode) => {
+                let _ = zpl::parse_zpl_attributes(None, dnode.0.get_bonus_data(), &dnode.1);
+            }
+            _ => (),
+        }
+    }
+
+    // Writing a dnode back has to give bytes that parse to the same dnode
+    if let Some((dnode, dnode_type, bonus_type)) = DNodeBase::from_slice_le(data) {
+        let bytes = dnode
+            .to_bytes_le(dnode_type, bonus_type)
+            .expect("A dnode that was parsed should fit in its slots!");
+        let (reparsed_dnode, _, _) =
+            DNodeBase::from_slice_le(&bytes).expect("A dnode that was written back should parse!");
+        assert_eq!(
+            Some(bytes),
+            reparsed_dnode.to_bytes_le(dnode_type, bonus_type)
+        );
+    }
+
+    let _ = ObjSet::from_slice_le(data);
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use szfs::{
+    lz4,
+    zio::{self, CompressionMethod},
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = lz4::lz4_decompress_blocks(&mut data.iter().copied(), None);
+    // With the big endian size header zfs puts in front
+    let _ = zio::try_decompress_block(data, CompressionMethod::Lz4, 128 * 1024);
+
+    let compressed = lz4::lz4_compress_blocks(data);
+    let decompressed = lz4::lz4_decompress_blocks(&mut compressed.iter().copied(), None);
+    assert!(decompressed.as_deref() == Ok(data));
+});
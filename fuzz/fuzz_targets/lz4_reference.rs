@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use szfs::lz4;
+
+// Every byte of the stream can at most add 255 bytes of output (an extended size byte of a lookback)
+const MAX_EXPANSION: usize = 256;
+
+fuzz_target!(|data: &[u8]| {
+    // The decoder has to agree with the reference implementation on what is a valid stream and on what it decompresses to
+    let mut reference_output = vec![0u8; data.len() * MAX_EXPANSION + 64];
+    let reference = lz4_flex::block::decompress_into(data, &mut reference_output)
+        .map(|size| &reference_output[..size]);
+    let ours = lz4::lz4_decompress_blocks(&mut data.iter().copied(), None);
+    match (reference, ours.as_deref()) {
+        (Ok(reference), Ok(ours)) => assert!(reference == ours),
+        (Err(_), Err(_)) => (),
+        (reference, ours) => panic!("Reference: {reference:?}, ours: {ours:?}"),
+    }
+
+    // And the reference implementation has to be able to read what we compress
+    let compressed = lz4::lz4_compress_blocks(data);
+    let mut decompressed = vec![0u8; data.len()];
+    let size = lz4_flex::block::decompress_into(&compressed, &mut decompressed).unwrap();
+    assert!(&decompressed[..size] == data);
+});
@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use szfs::lzjb;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = lzjb::lzjb_decompress(&mut data.iter().copied(), Some(128 * 1024));
+    let _ = lzjb::lzjb_decompress(&mut data.iter().copied(), None);
+
+    let compressed = lzjb::lzjb_compress(data);
+    let decompressed = lzjb::lzjb_decompress(&mut compressed.iter().copied(), Some(data.len()));
+    assert!(decompressed.as_deref() == Ok(data));
+    // The compressor never ends the stream with an empty copymap, so decompressing until the input runs out gives back the same data
+    let decompressed = lzjb::lzjb_decompress(&mut compressed.iter().copied(), None);
+    assert!(decompressed.as_deref() == Ok(data));
+
+    // Cutting the stream short has to be reported, not silently give back less data
+    if !data.is_empty() {
+        let truncated = &compressed[..compressed.len() - 1];
+        let decompressed = lzjb::lzjb_decompress(&mut truncated.iter().copied(), Some(data.len()));
+        assert!(matches!(
+            decompressed,
+            Err(lzjb::LzjbError::TruncatedInput { .. })
+        ));
+    }
+});
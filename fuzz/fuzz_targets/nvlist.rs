@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use szfs::nvlist;
+
+// The xdr encoding is what the vdev labels use, the native one is what the pool history uses
+fuzz_target!(|data: &[u8]| {
+    let _ = nvlist::from_bytes_xdr(&mut data.iter().copied());
+    let _ = nvlist::from_bytes_native(data);
+});
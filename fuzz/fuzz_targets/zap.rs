@@ -0,0 +1,41 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use libfuzzer_sys::fuzz_target;
+use szfs::{
+    byte_iter::FromSliceLE,
+    zap::{MicroZapEntry, ZapHeader, ZapLeaf},
+};
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the block size, zap blocks are between 512 bytes and 128K
+    let Some((&block_size_shift, data)) = data.split_first() else {
+        return;
+    };
+    let block_size = 512 << (block_size_shift % 9);
+
+    // The data after the header block is tried as a leaf of that fat zap, the leaf needs the header to check the hashes of the names
+    if let Some(ZapHeader::FatZap(header)) = ZapHeader::from_slice_le(data, block_size) {
+        if let Some(size) = header.get_hash_table_size() {
+            for i in 0..size {
+                let _ = header.read_hash_table_at(i);
+            }
+        }
+
+        let leaf_data = data.get(block_size..).unwrap_or_default();
+        if let Some(leaf) = ZapLeaf::from_slice_le(leaf_data, block_size) {
+            let _ = leaf.dump_contents_into(&mut HashMap::new(), &header);
+            let _ = leaf.get_raw_entries(1);
+            let _ = leaf.get_raw_entries(8);
+        }
+    }
+
+    // Micro zaps are just an array of entries after a 64 byte header
+    let mut result = HashMap::new();
+    for entry_data in data.chunks_exact(MicroZapEntry::get_ondisk_size()) {
+        if let Some(entry) = MicroZapEntry::from_slice_le(entry_data) {
+            let _ = entry.dump_contents_into(&mut result);
+        }
+    }
+});
@@ -0,0 +1,46 @@
+// C api of szfs, build it with: cargo build --release --features capi
+// and link against target/release/libszfs.so (or libszfs.a)
+// Functions that fail return NULL or -1, szfs_last_error then returns why
+// A panic inside szfs (ex. an assert a corrupt pool got to) is caught and is a failure too, it never unwinds into the caller
+
+#ifndef SZFS_H
+#define SZFS_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/types.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct PoolReader szfs_pool;
+typedef struct FileReader szfs_file;
+
+typedef void (*szfs_dataset_callback)(void *user_data, uint64_t object_id, const char *name, bool is_snapshot);
+
+// The message of the last error on this thread, or NULL, valid until the next call on this thread that fails
+const char *szfs_last_error(void);
+
+// Opens the pool on the disks (or images of them), in any order if their labels can be read, they are opened read only
+szfs_pool *szfs_pool_open(const char *const *paths, size_t npaths);
+void szfs_pool_close(szfs_pool *pool);
+
+// Calls callback for every dataset (including snapshots), name is only valid during the call
+// Returns the number of datasets
+int szfs_pool_list_datasets(szfs_pool *pool, szfs_dataset_callback callback, void *user_data);
+
+// path is relative to the root of the dataset, dataset is the full name (ex. "tank/home")
+// The file has to be closed before its pool
+szfs_file *szfs_file_open(szfs_pool *pool, const char *dataset, const char *path);
+uint64_t szfs_file_size(const szfs_file *file);
+// Like pread(2), returns the number of bytes read, 0 at the end of the file
+ssize_t szfs_file_pread(szfs_pool *pool, szfs_file *file, void *buf, size_t count, uint64_t offset);
+void szfs_file_close(szfs_file *file);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,109 @@
+// Async access to vdevs, for when szfs is used inside something that runs on tokio (ex. a web ui for a recovery)
+// reading a vdev blocks on file io, so the reads are done on tokio's blocking threads instead of the thread the future is polled on
+// NOTE: This is only compiled with the "async" feature
+// Source: https://docs.rs/tokio/latest/tokio/task/fn.spawn_blocking.html
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    zio::{BlockPointer, Vdevs},
+    Vdev,
+};
+
+// A vdev that can be shared between tasks, every operation locks it on a blocking thread
+#[derive(Clone)]
+pub struct AsyncVdev {
+    inner: Arc<Mutex<dyn Vdev>>,
+}
+
+pub type AsyncVdevs = HashMap<usize, AsyncVdev>;
+
+// A panic while a vdev was locked doesn't leave it in a state that can't be read from, so a poisoned lock is just used anyway
+fn lock_vdev(vdev: &Mutex<dyn Vdev>) -> MutexGuard<'_, dyn Vdev + 'static> {
+    vdev.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl AsyncVdev {
+    pub fn new(vdev: impl Vdev + 'static) -> AsyncVdev {
+        AsyncVdev {
+            inner: Arc::new(Mutex::new(vdev)),
+        }
+    }
+
+    pub fn from_shared(vdev: Arc<Mutex<dyn Vdev>>) -> AsyncVdev {
+        AsyncVdev { inner: vdev }
+    }
+
+    pub fn get_shared(&self) -> Arc<Mutex<dyn Vdev>> {
+        self.inner.clone()
+    }
+
+    // Runs f with the vdev locked, on a blocking thread
+    // Returns: Err if f panicked
+    pub async fn with_vdev<R: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut dyn Vdev) -> R + Send + 'static,
+    ) -> Result<R, ()> {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || f(&mut *lock_vdev(&inner)))
+            .await
+            .map_err(|_| ())
+    }
+
+    pub async fn get_size(&self) -> u64 {
+        // get_size doesn't do any io, so there is no need for a blocking thread
+        lock_vdev(&self.inner).get_size()
+    }
+
+    pub async fn read(&self, offset_in_bytes: u64, amount_in_bytes: usize) -> Result<Vec<u8>, ()> {
+        self.with_vdev(move |vdev| vdev.read(offset_in_bytes, amount_in_bytes))
+            .await?
+    }
+
+    pub async fn write(&self, offset_in_bytes: u64, data: Vec<u8>) -> Result<(), ()> {
+        self.with_vdev(move |vdev| vdev.write(offset_in_bytes, &data))
+            .await?
+    }
+
+    pub async fn read_raw_label(&self, label_index: usize) -> Result<Vec<u8>, ()> {
+        self.with_vdev(move |vdev| vdev.read_raw_label(label_index))
+            .await?
+    }
+}
+
+// Runs f on a blocking thread with all of the vdevs locked, so any of the normal (blocking) read paths can be used from async code
+// ex. with_vdevs(&vdevs, move |vdevs| dnode.read_block(0, vdevs)).await
+// Returns: Err if f panicked
+pub async fn with_vdevs<R: Send + 'static>(
+    vdevs: &AsyncVdevs,
+    f: impl FnOnce(&mut Vdevs) -> R + Send + 'static,
+) -> Result<R, ()> {
+    let mut shared_vdevs = vdevs
+        .iter()
+        .map(|(id, vdev)| (*id, vdev.get_shared()))
+        .collect::<Vec<_>>();
+    // Always lock in the same order, so two tasks using the same vdevs can't deadlock
+    shared_vdevs.sort_by_key(|(id, _)| *id);
+
+    tokio::task::spawn_blocking(move || {
+        let mut guards = shared_vdevs
+            .iter()
+            .map(|(id, vdev)| (*id, lock_vdev(vdev)))
+            .collect::<Vec<_>>();
+        let mut vdevs = guards
+            .iter_mut()
+            .map(|(id, guard)| (*id, &mut **guard as &mut dyn Vdev))
+            .collect::<Vdevs>();
+        f(&mut vdevs)
+    })
+    .await
+    .map_err(|_| ())
+}
+
+pub async fn dereference(bp: &BlockPointer, vdevs: &AsyncVdevs) -> Result<Vec<u8>, ()> {
+    let mut bp = bp.clone();
+    with_vdevs(vdevs, move |vdevs| bp.dereference(vdevs)).await?
+}
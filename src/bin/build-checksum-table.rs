@@ -1,23 +1,121 @@
-use serde::{Deserialize, Serialize};
-use std::{
-    env,
-    fmt::Debug,
-    fs::{File, OpenOptions},
-    io::{Seek, SeekFrom, Write},
+use clap::Parser;
+use std::path::PathBuf;
+use szfs::{
+    cli,
+    recovery::control::{CancellationToken, Completion},
+    yolo_block_recovery::{ChecksumTableHeader, ChecksumTableKind},
+    *,
 };
-use szfs::{zio::Vdevs, *};
-#[derive(Debug, Serialize, Deserialize)]
-struct IndirectBlock {
-    pub bps: Vec<Option<zio::BlockPointer>>,
+
+/// Builds checksum table used by find-block-with-checksum and yolo block recovery
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// Also build the secondary table, it makes yolo recovery faster at the cost of doubling the space used
+    #[arg(long)]
+    with_secondary: bool,
+    /// Read all of the disks at the same time, one thread per disk, then merge what was read from every disk into the table
+    /// this is about as many times faster as there are disks, but needs the space of the table twice while merging
+    #[arg(long)]
+    parallel: bool,
+}
+
+fn get_table_name(kind: ChecksumTableKind) -> &'static str {
+    match kind {
+        ChecksumTableKind::Primary => "checksum-map",
+        ChecksumTableKind::Secondary => "checksum-map-secondary",
+    }
 }
 
-type ChecksumTableEntry = u32;
+// Builds a shard of the table for every disk, each in its own thread, and merges them into the table of the raidz once they are all finished
+// The shards are resumed like the table itself, so a stopped build continues where every disk was
+fn build_table_in_parallel(
+    kind: ChecksumTableKind,
+    pool: &mut cli::Pool,
+    pool_args: &cli::PoolArgs,
+    top_level_guid: u64,
+    cancel: &CancellationToken,
+) -> Completion {
+    use szfs::ansi_color::*;
+    let header = ChecksumTableHeader::new(kind, pool.get_asize() as u64, top_level_guid);
+    let ndevices = pool.devices.len();
+    // The raidz only uses as much of every disk as the smallest one has
+    let device_size = pool.get_raidz().get_size() / ndevices as u64;
+    let table_name = get_table_name(kind);
+    let shard_paths = (0..ndevices)
+        .map(|index| pool_args.output_path(format!("{table_name}-shard{index}.bin")))
+        .collect::<Vec<PathBuf>>();
+
+    let results = std::thread::scope(|scope| {
+        let handles = pool
+            .devices
+            .iter_mut()
+            .zip(shard_paths.iter())
+            .enumerate()
+            .map(|(index, (device, shard_path))| {
+                scope.spawn(move || {
+                    let mut last_reported_off = 0;
+                    yolo_block_recovery::build_checksum_table_shard(
+                        header,
+                        device,
+                        device_size,
+                        shard_path,
+                        cancel,
+                        |off, device_size| {
+                            if off - last_reported_off >= 512 * 1024 * 1024 {
+                                // Every ~512 mb
+                                last_reported_off = off;
+                                println!(
+                                    "Disk {index}: {}% done building {table_name} ...",
+                                    ((off as f32) / (device_size as f32)) * 100.0
+                                );
+                            }
+                        },
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Building a shard should not panic!"))
+            .collect::<Vec<_>>()
+    });
+
+    let mut completion = Completion::Finished;
+    for (index, result) in results.into_iter().enumerate() {
+        match result {
+            Ok(Completion::Finished) => (),
+            Ok(Completion::Cancelled) => completion = Completion::Cancelled,
+            Err(()) => cli::exit_with_error(format!(
+                "Building the shard of {table_name} of disk {index} failed"
+            )),
+        }
+    }
+    if completion == Completion::Cancelled {
+        return completion;
+    }
+
+    println!("Merging the shards of {table_name} ...");
+    yolo_block_recovery::merge_checksum_table_shards(
+        &shard_paths,
+        header,
+        &pool_args.output_path(format!("{table_name}.bin")),
+    )
+    .unwrap_or_else(|()| {
+        cli::exit_with_error(format!("Merging the shards of {table_name} failed"))
+    });
+    for shard_path in shard_paths.iter() {
+        if let Err(err) = std::fs::remove_file(shard_path) {
+            println!("{YELLOW}Warning{WHITE}: Shard {shard_path:?} couldn't be removed: {err}");
+        }
+    }
+    Completion::Finished
+}
 
 fn main() {
-    // Builds checksum table used by find-block-with-checksum and yolo block recovery
-    // Note: The table is just a tightly packed array of ChecksumTableEntry's in little endian
-    // There is no extra data in the resulting file, the number of entries in the table
-    // is simply the size of the file / the size of a ChecksumTableEntry
+    // Note: The table is just a small header (see yolo_block_recovery::ChecksumTableHeader)
+    // followed by a tightly packed array of ChecksumTableEntry's in little endian
     // A ChecksumTableEntry is a truncated version of the full checksum
     // this is intentional so as to reduce the amount of space used.
     // Thus searching in the table for matches is akin to using a bloom filter.
@@ -27,91 +125,88 @@ fn main() {
     // checksum was perfect because there are only so many bits stored
     // collisions will occur.
     use szfs::ansi_color::*;
-    let usage = format!("Usage: {} (vdevs...)", env::args().next().unwrap());
-    let mut vdev0: VdevFile = File::open(env::args().nth(1).expect(&usage))
-        .expect("Vdev 0 should be able to be opened!")
-        .into();
-    let mut vdev1: VdevFile = File::open(env::args().nth(2).expect(&usage))
-        .expect("Vdev 1 should be able to be opened!")
-        .into();
-    let mut vdev2: VdevFile = File::open(env::args().nth(3).expect(&usage))
-        .expect("Vdev 2 should be able to be opened!")
-        .into();
-    let mut vdev3: VdevFile = File::open(env::args().nth(4).expect(&usage))
-        .expect("Vdev 3 should be able to be opened!")
-        .into();
-
-    // For now just use the first label
-    let mut label0 = VdevLabel::from_bytes(
-        &vdev0
-            .read_raw_label(0)
-            .expect("Vdev label 0 must be parsable!"),
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    println!(
+        "{CYAN}Info{WHITE}: Parsed nv_list, {:?}!",
+        pool.name_value_pairs
     );
-
-    let name_value_pairs =
-        nvlist::from_bytes_xdr(&mut label0.get_name_value_pairs_raw().iter().copied())
-            .expect("Name value pairs in the vdev label must be valid!");
-    let nvlist::Value::NVList(vdev_tree) = &name_value_pairs["vdev_tree"] else {
-        panic!("vdev_tree is not an nvlist!");
-    };
-
-    let nvlist::Value::U64(top_level_ashift) = vdev_tree["ashift"] else {
-        panic!("no ashift found for top level vdev!");
+    let nvlist::Value::U64(top_level_guid) = pool.get_vdev_tree()["guid"] else {
+        panic!("no guid found for top level vdev!");
     };
+    let cancel = cli::cancel_on_ctrl_c();
 
-    println!("{CYAN}Info{WHITE}: Parsed nv_list, {name_value_pairs:?}!");
-    println!("{RED}Important{WHITE}: Please make sure the disks are actually in the right order by using the nv_list, i can't actually check that in a reliable way!!!");
-
-    let mut devices = Vdevs::new();
-    devices.insert(0, &mut vdev0);
-    devices.insert(1, &mut vdev1);
-    devices.insert(2, &mut vdev2);
-    devices.insert(3, &mut vdev3);
-
-    let mut vdev_raidz: VdevRaidz =
-        VdevRaidz::from_vdevs(devices, 4, 1, 2_usize.pow(top_level_ashift as u32));
-
-    label0.set_raw_uberblock_size(2_usize.pow(top_level_ashift as u32));
+    if args.parallel {
+        let mut kinds = vec![ChecksumTableKind::Primary];
+        if args.with_secondary {
+            kinds.push(ChecksumTableKind::Secondary);
+        }
+        for kind in kinds {
+            let completion =
+                build_table_in_parallel(kind, &mut pool, &pool_args, top_level_guid, &cancel);
+            if completion == Completion::Cancelled {
+                println!("{CYAN}Info{WHITE}: Stopped, run the same command again to continue building the table");
+                return;
+            }
+        }
+        return;
+    }
 
+    let mut vdev_raidz = pool.get_raidz();
     let disk_size = vdev_raidz.get_size();
-    let sector_size = vdev_raidz.get_asize() as u64;
-
-    let mut checksum_map_file = OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open("checksum-map.bin")
-        .unwrap();
-    let checksum_map_file_size = checksum_map_file.seek(SeekFrom::End(0)).unwrap();
-    let last_off =
-        (checksum_map_file_size / core::mem::size_of::<ChecksumTableEntry>() as u64) * sector_size;
     println!(
         "RAIDZ total size (GB): {}",
         disk_size as f64 / 1024.0 / 1024.0 / 1024.0
     );
 
-    println!(
-        "Resuming from offset {}, which is sector {}, with sector size being: {}",
-        last_off,
-        last_off / sector_size,
-        sector_size
-    );
+    let mut last_reported_off = 0;
+    let completion = yolo_block_recovery::build_checksum_table(
+        &mut vdev_raidz,
+        top_level_guid,
+        &pool_args.output_path("checksum-map.bin"),
+        &cancel,
+        |off, disk_size| {
+            if off - last_reported_off >= 512 * 1024 * 1024 {
+                // Every ~512 mb
+                last_reported_off = off;
+                println!(
+                    "{}% done building table ...",
+                    ((off as f32) / (disk_size as f32)) * 100.0
+                );
+            }
+        },
+    )
+    .expect("Building the checksum table should work!");
+    if completion == Completion::Cancelled {
+        println!(
+            "{CYAN}Info{WHITE}: Stopped, run the same command again to continue building the table"
+        );
+        return;
+    }
 
-    for off in (last_off..disk_size).step_by(sector_size as usize) {
-        if off % (512 * 1024 * 1024) == 0 && off != 0 {
-            // Every ~512 mb
-            println!(
-                "{}% done building table ...",
-                ((off as f32) / (disk_size as f32)) * 100.0
-            );
+    // The secondary table is optional, it makes yolo recovery faster at the cost of doubling the space used
+    if args.with_secondary {
+        println!("Building secondary table ...");
+        let mut last_reported_off = 0;
+        let completion = yolo_block_recovery::build_secondary_checksum_table(
+            &mut vdev_raidz,
+            top_level_guid,
+            &pool_args.output_path("checksum-map-secondary.bin"),
+            &cancel,
+            |off, disk_size| {
+                if off - last_reported_off >= 512 * 1024 * 1024 {
+                    // Every ~512 mb
+                    last_reported_off = off;
+                    println!(
+                        "{}% done building secondary table ...",
+                        ((off as f32) / (disk_size as f32)) * 100.0
+                    );
+                }
+            },
+        )
+        .expect("Building the secondary checksum table should work!");
+        if completion == Completion::Cancelled {
+            println!("{CYAN}Info{WHITE}: Stopped, run the same command again to continue building the secondary table");
         }
-
-        let res = vdev_raidz.read(off, sector_size as usize).unwrap();
-        let checksum = fletcher::do_fletcher4(&res);
-
-        // Truncate to size
-        let to_write: ChecksumTableEntry = checksum[0] as ChecksumTableEntry;
-        checksum_map_file
-            .write_all(&to_write.to_le_bytes())
-            .unwrap();
     }
 }
@@ -0,0 +1,89 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{bookmark, cli, properties, rewind, *};
+
+/// Lists the snapshots and bookmarks of a dataset, and whether anything about it is redacted, by default for the root dataset
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// Object id of the dsl dataset, the root dataset by default
+    dataset: Option<u64>,
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let dataset_id = args.dataset;
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let (mut mos, mos_selection) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+        .expect("There should be at least one uberblock whose MOS can be read!");
+    mos_selection.print();
+
+    let dataset_id = dataset_id.unwrap_or_else(|| {
+        properties::get_root_dataset_id(&mut mos, &mut vdevs)
+            .expect("Root dataset should be readable!")
+    });
+    let dmu::DNode::DSLDataset(dataset) = mos
+        .get_dnode_at(dataset_id as usize, &mut vdevs)
+        .expect("Dataset should be readable!")
+    else {
+        panic!("DNode {dataset_id} is not a dsl dataset!");
+    };
+    let dataset = dataset
+        .parse_bonus_data()
+        .expect("Dataset bonus buffer should be readable!");
+
+    if let Some(guids) = bookmark::get_redacting_snapshots(&mut mos, dataset_id, &mut vdevs) {
+        println!("{CYAN}Info{WHITE}: Dataset {dataset_id} was received from a redacted send (redaction snapshots {guids:?}), its redacted blocks were never on this pool");
+    }
+
+    println!("Snapshots of dataset {dataset_id}:");
+    if dataset.get_snapshot_names_object_number() != 0 {
+        let (mut snapshot_names, _) = mos
+            .get_zap_dnode_at(
+                dataset.get_snapshot_names_object_number() as usize,
+                &mut vdevs,
+            )
+            .expect("Snapshot names should be readable!");
+        let snapshot_names = snapshot_names
+            .dump_zap_contents(&mut vdevs)
+            .expect("Snapshot names should be readable!");
+        for (name, value) in snapshot_names.iter() {
+            println!("    @{name}: {value:?}");
+        }
+    }
+
+    println!("Bookmarks of dataset {dataset_id}:");
+    let bookmarks = bookmark::read_bookmarks(&mut mos, dataset_id, &mut vdevs)
+        .expect("Bookmarks should be readable!");
+    for (name, bookmark) in bookmarks.iter() {
+        println!(
+            "    #{name}: guid {:#x}, txg {}, created at {}",
+            bookmark.guid, bookmark.creation_txg, bookmark.creation_time
+        );
+        if bookmark.redaction_object_number == 0 {
+            continue;
+        }
+
+        match bookmark::read_redaction_list(&mut mos, bookmark.redaction_object_number, &mut vdevs) {
+            Some((header, entries)) => println!(
+                "        redaction list {}: {} runs of redacted blocks ({} read), redaction snapshots {:?}",
+                bookmark.redaction_object_number,
+                header.n_entries,
+                entries.len(),
+                header.snapshots
+            ),
+            None => println!(
+                "{YELLOW}Warning{WHITE}: Couldn't read redaction list {} of bookmark {name}!",
+                bookmark.redaction_object_number
+            ),
+        }
+    }
+}
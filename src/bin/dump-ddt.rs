@@ -0,0 +1,64 @@
+use clap::Parser;
+use std::{collections::HashMap, path::PathBuf};
+use szfs::{cli, ddt, rewind, *};
+
+/// Dumps the dedup tables of the pool, recover uses the dump to find deduplicated blocks whose block pointers are damaged
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// Where to write the dump, ddt.json in the output directory by default
+    output: Option<PathBuf>,
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let output_path = args
+        .output
+        .unwrap_or_else(|| pool_args.output_path("ddt.json"));
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let (mut mos, mos_selection) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+        .expect("There should be at least one uberblock whose MOS can be read!");
+    mos_selection.print();
+
+    let tables =
+        ddt::find_dedup_tables(&mut mos, &mut vdevs).expect("Object directory should be readable!");
+    if tables.is_empty() {
+        println!("The pool doesn't have any dedup tables");
+        return;
+    }
+
+    let mut all_entries = Vec::new();
+    for table in tables.iter() {
+        let Ok(entries) = ddt::read_dedup_table(&mut mos, table, &mut vdevs) else {
+            println!(
+                "{YELLOW}Warning{WHITE}: Couldn't read dedup table {}!",
+                table.name
+            );
+            continue;
+        };
+
+        let nreferences = entries
+            .iter()
+            .map(|entry| entry.get_refcount())
+            .sum::<u64>();
+        println!(
+            "{}: {} entries, {} references",
+            table.name,
+            entries.len(),
+            nreferences
+        );
+        all_entries.extend(entries);
+    }
+
+    ddt::write_dedup_entries(&output_path, &all_entries)
+        .expect("Dedup table entries should be writable!");
+    println!("Wrote {} entries to {output_path:?}", all_entries.len());
+}
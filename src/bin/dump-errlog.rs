@@ -0,0 +1,60 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{
+    cli,
+    errlog::{self, ErrorLogSource},
+    rewind, *,
+};
+
+/// Lists the blocks zfs itself already found to be damaged, these are the ones worth looking at first
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+}
+
+fn main() {
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let (mut mos, mos_selection) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+        .expect("There should be at least one uberblock whose MOS can be read!");
+    mos_selection.print();
+
+    let entries =
+        errlog::read_error_logs(&mut mos, &mut vdevs).expect("Error logs should be readable!");
+    if entries.is_empty() {
+        println!("The pool doesn't know of any damaged blocks");
+        return;
+    }
+
+    // Same format as zpool status -v uses for objects it can't find the path of, but with the level and block id
+    for entry in entries.iter() {
+        let objset = match entry.objset {
+            traverse::ObjSetId::Mos => 0,
+            traverse::ObjSetId::Dataset(dataset) => dataset,
+        };
+        println!(
+            "<0x{:x}>:<0x{:x}> level {} block {}{}{}",
+            objset,
+            entry.object,
+            entry.level,
+            entry.block_id,
+            entry
+                .birth_txg
+                .map(|birth_txg| format!(", birth txg {birth_txg}"))
+                .unwrap_or_default(),
+            if entry.source == ErrorLogSource::CurrentScrub {
+                " (found by the current scrub)"
+            } else {
+                ""
+            }
+        );
+    }
+    println!("{} damaged blocks", entries.len());
+}
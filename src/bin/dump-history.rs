@@ -0,0 +1,35 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{cli, history, rewind, *};
+
+/// Prints the history of the pool like zpool history -il would, useful to find out what was done to the pool before it died
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+}
+
+fn main() {
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let (mut mos, mos_selection) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+        .expect("There should be at least one uberblock whose MOS can be read!");
+    mos_selection.print();
+
+    let records = history::read_history(&mut mos, &mut vdevs).expect("History should be readable!");
+    if records.is_empty() {
+        println!("The pool doesn't have any history");
+        return;
+    }
+
+    println!("History for pool:");
+    for record in records.iter() {
+        println!("{}", history::format_record(record));
+    }
+}
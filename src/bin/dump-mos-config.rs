@@ -0,0 +1,73 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{cli, inspect, nvlist, rewind, spa_config, *};
+
+/// Prints the copy of the pool config in the MOS like zdb -C would, and checks the config in the label against it
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+}
+
+// Returns: The parts of a top level vdev that the layout of the raidz depends on
+fn describe_top_level_vdev(vdev_tree: &nvlist::NVList) -> String {
+    let get_u64 = |name: &str| match vdev_tree.get(name) {
+        Some(nvlist::Value::U64(value)) => Some(*value),
+        _ => None,
+    };
+    let typ = match vdev_tree.get("type") {
+        Some(nvlist::Value::String(typ)) => typ.as_str(),
+        _ => "unknown",
+    };
+    let nchildren = match vdev_tree.get("children") {
+        Some(nvlist::Value::NVListArray(children)) => children.len(),
+        _ => 0,
+    };
+    format!(
+        "type {typ}, guid {:?}, ashift {:?}, nparity {:?}, {nchildren} children",
+        get_u64("guid"),
+        get_u64("ashift"),
+        get_u64("nparity")
+    )
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let label_top_level_vdev = describe_top_level_vdev(pool.get_vdev_tree());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let (mut mos, mos_selection) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+        .expect("There should be at least one uberblock whose MOS can be read!");
+    mos_selection.print();
+
+    let config = spa_config::read_mos_config(&mut mos, &mut vdevs)
+        .expect("The MOS should have a readable config!");
+    let mut output = String::new();
+    inspect::format_nvlist(&config, 1, &mut output);
+    println!("MOS config:\n{output}");
+
+    let Some(config) = spa_config::into_label_config(config, 0) else {
+        println!("{YELLOW}Warning{WHITE}: The MOS config has no top level vdevs!");
+        return;
+    };
+    let Some(nvlist::Value::NVList(vdev_tree)) = config.get("vdev_tree") else {
+        unreachable!();
+    };
+    let mos_top_level_vdev = describe_top_level_vdev(vdev_tree);
+    println!("Top level vdev in the label: {label_top_level_vdev}");
+    println!("Top level vdev in the MOS:   {mos_top_level_vdev}");
+    if label_top_level_vdev != mos_top_level_vdev {
+        println!("{YELLOW}Warning{WHITE}: The label and the MOS don't agree on the first top level vdev, the label might be stale or damaged!");
+    }
+    if let Some(nvlist::Value::U64(vdev_children)) = config.get("vdev_children") {
+        if *vdev_children != 1 {
+            println!("{RED}Important{WHITE}: The pool has {vdev_children} top level vdevs, only the first one is used!");
+        }
+    }
+}
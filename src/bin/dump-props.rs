@@ -0,0 +1,112 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{cli, dmu, properties, reader, rewind, zio::Vdevs, *};
+
+/// Prints the properties of a dataset like zfs get would, by default the ones of the root dataset
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// Object id of the dsl dataset, the root dataset by default
+    dataset: Option<u64>,
+    /// Also print the space every user, group and project uses, like zfs userspace, groupspace and projectspace would
+    #[arg(long)]
+    space_used: bool,
+}
+
+// The keys are the ids in hex, or the domain and the id for windows sids, with "obj-" in front for the number of objects
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zfs_quota.c (zpl_get_file_info, DMU_OBJACCT_PREFIX)
+fn print_space_used(objset: &mut dmu::ObjSet, vdevs: &mut Vdevs) {
+    use szfs::ansi_color::*;
+    if objset.userused.is_some() && objset.flags & dmu::objset_flag::USERACCOUNTING_COMPLETE == 0 {
+        println!("{YELLOW}Warning{WHITE}: The space accounting of the dataset isn't complete (ex. it was made before it existed and was never upgraded), it might be missing some of the space!");
+    }
+    for (kind, object_id) in [
+        ("user", dmu::USERUSED_OBJECT),
+        ("group", dmu::GROUPUSED_OBJECT),
+        ("project", dmu::PROJECTUSED_OBJECT),
+    ] {
+        let Some(accounting_zap_data) = objset.dump_accounting(object_id, vdevs) else {
+            println!("No {kind} space accounting");
+            continue;
+        };
+        let mut used = HashMap::<String, (Option<u64>, Option<u64>)>::new();
+        for (key, value) in accounting_zap_data {
+            let zap::Value::U64(value) = value else {
+                println!("{YELLOW}Warning{WHITE}: The {kind} space accounting of {key} is not a number, ignoring it!");
+                continue;
+            };
+            match key.strip_prefix("obj-") {
+                Some(id) => used.entry(id.to_string()).or_default().1 = Some(value),
+                None => used.entry(key).or_default().0 = Some(value),
+            }
+        }
+        let mut used = used.into_iter().collect::<Vec<_>>();
+        used.sort_unstable_by_key(|(id, _)| (u64::from_str_radix(id, 16).ok(), id.clone()));
+        println!("Space used by every {kind}:");
+        for (id, (bytes, objects)) in used {
+            let id = u64::from_str_radix(&id, 16).map_or(id, |id| id.to_string());
+            match objects {
+                Some(objects) => println!(
+                    "    {kind} {id}: {} bytes in {objects} objects",
+                    bytes.unwrap_or(0)
+                ),
+                None => println!("    {kind} {id}: {} bytes", bytes.unwrap_or(0)),
+            }
+        }
+    }
+}
+
+fn main() {
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let dataset_id = args.dataset;
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let (mut mos, mos_selection) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+        .expect("There should be at least one uberblock whose MOS can be read!");
+    mos_selection.print();
+
+    let dataset_id = dataset_id.unwrap_or_else(|| {
+        properties::get_root_dataset_id(&mut mos, &mut vdevs)
+            .expect("Root dataset should be readable!")
+    });
+
+    // The properties people usually care about when recovering data, anything else has to be looked up by name with get_property
+    let names = [
+        "recordsize",
+        "compression",
+        "checksum",
+        "copies",
+        "dedup",
+        "mountpoint",
+        "canmount",
+        "atime",
+        "xattr",
+        "sync",
+        "quota",
+        "reservation",
+        "keylocation",
+    ];
+    println!("Properties of dataset {dataset_id}:");
+    for name in names {
+        match properties::get_property(&mut mos, dataset_id, name, &mut vdevs) {
+            Some((value, source)) => println!(
+                "    {name}: {} ({source:?})",
+                properties::format_property(name, &value)
+            ),
+            None => println!("    {name}: default"),
+        }
+    }
+
+    if args.space_used {
+        match reader::open_dataset_by_id(&mut mos, dataset_id, &mut vdevs) {
+            Ok(mut objset) => print_space_used(&mut objset, &mut vdevs),
+            Err(err) => cli::exit_with_error(err),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use clap::Parser;
+use std::{collections::HashMap, fs::File, io::BufWriter, path::PathBuf};
+use szfs::{
+    recovery::{
+        export::{write_graph_dot, write_graph_graphml},
+        fragment::Fragment,
+    },
+    *,
+};
+
+/// Exports the fragment graph from an undelete checkpoint, the format is picked based on the extension of the output file
+#[derive(Parser)]
+struct Args {
+    checkpoint_path: PathBuf,
+    /// output.dot or output.graphml
+    output_path: PathBuf,
+}
+
+fn main() {
+    let args = Args::parse();
+    let (checkpoint_path, output_path) = (args.checkpoint_path, args.output_path);
+
+    let fragments: HashMap<[u64; 4], Fragment> =
+        recovery::checkpoint::read_checkpoint_entries::<([u64; 4], Fragment)>(&checkpoint_path)
+            .expect("Checkpoint should be readable!")
+            .into_iter()
+            .collect();
+    println!("Loaded {} fragments", fragments.len());
+
+    let mut output = BufWriter::new(File::create(&output_path).unwrap());
+    if output_path
+        .extension()
+        .is_some_and(|extension| extension == "graphml")
+    {
+        write_graph_graphml(&fragments, &mut output).unwrap();
+    } else {
+        write_graph_dot(&fragments, &mut output).unwrap();
+    }
+}
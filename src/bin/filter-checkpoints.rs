@@ -1,108 +1,43 @@
-use serde::{Deserialize, Serialize};
-use std::{
-    collections::{HashMap, HashSet},
-    env,
-    fmt::Debug,
-    fs::{File, OpenOptions},
-    io::Write,
-};
+use clap::Parser;
+use std::path::PathBuf;
 use szfs::{
-    dmu::{DNodeDirectoryContents, DNodePlainFileContents, ObjSet},
-    zio::Vdevs,
+    cli,
+    recovery::fragment::{Fragment, FragmentData},
     *,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
-struct IndirectBlock {
-    pub bps: Vec<Option<zio::BlockPointer>>,
-}
-
-#[derive(Serialize, Deserialize)]
-enum FragmentData {
-    FileDNode(DNodePlainFileContents),
-    DirectoryDNode(DNodeDirectoryContents, Vec<String>),
-    ObjSetDNode(ObjSet),
-    IndirectBlock(IndirectBlock),
-}
-
-impl Debug for FragmentData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            FragmentData::FileDNode(_) => write!(f, "File"),
-            FragmentData::DirectoryDNode(_, _) => write!(f, "Dir"),
-            FragmentData::ObjSetDNode(_) => write!(f, "ObjSet"),
-            FragmentData::IndirectBlock(_) => write!(f, "Indirect"),
-        }?;
-
-        Ok(())
-    }
-}
-
-#[derive(Serialize, Deserialize)]
-struct Fragment {
-    data: FragmentData,
-    children: HashSet<[u64; 4]>,
-}
-
-impl Debug for Fragment {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.data)?;
-        write!(f, "(")?;
-        for child in self.children.iter() {
-            write!(f, "{:?}, ", child[0])?;
-        }
-        write!(f, ")")?;
-        Ok(())
-    }
-}
-
-impl From<FragmentData> for Fragment {
-    fn from(frag: FragmentData) -> Self {
-        Self {
-            data: frag,
-            children: HashSet::new(),
-        }
-    }
+/// Keeps only the file fragments of undelete checkpoints, and merges them into undelete-filtered.ckpt
+#[derive(Parser)]
+struct Args {
+    /// The checkpoints to filter, undelete-step1.ckpt in the output directory by default
+    checkpoint_paths: Vec<PathBuf>,
+    /// Where to write (and look for) the checkpoints [default: the current directory]
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
 }
 
 fn main() {
     // NOTE: This was made as quick way to filter and merge outputs from undelete checkpoints
-    let mut recovered_fragments1: Vec<([u64; 4], Fragment)> =
-        serde_json::from_reader(File::open("undelete-step1-checkpoint-first-50%.json").unwrap())
-            .unwrap();
-    recovered_fragments1.retain(|(_, f)| matches!(f.data, FragmentData::FileDNode(_)));
-
-    let mut recovered_fragments2: Vec<([u64; 4], Fragment)> =
-        serde_json::from_reader(File::open("undelete-step1-checkpoint-upto-74%.json").unwrap())
-            .unwrap();
-    recovered_fragments2.retain(|(_, f)| matches!(f.data, FragmentData::FileDNode(_)));
-
-    let mut recovered_fragments3: Vec<([u64; 4], Fragment)> =
-        serde_json::from_reader(File::open("undelete-step1-checkpoint-upto-78%.json").unwrap())
-            .unwrap();
-    recovered_fragments3.retain(|(_, f)| matches!(f.data, FragmentData::FileDNode(_)));
-
-    let mut recovered_fragments4: Vec<([u64; 4], Fragment)> =
-        serde_json::from_reader(File::open("undelete-step1-checkpoint-upto-100%.json").unwrap())
-            .unwrap();
-    recovered_fragments4.retain(|(_, f)| matches!(f.data, FragmentData::FileDNode(_)));
+    // Checkpoints can be either the checkpoint logs undelete writes now or the json files it used to write
+    let args = Args::parse();
+    let output_dir = args.output_dir.as_deref();
+    let mut checkpoint_paths = args.checkpoint_paths;
+    if checkpoint_paths.is_empty() {
+        checkpoint_paths.push(cli::output_path(output_dir, "undelete-step1.ckpt"));
+    }
 
-    let recovered_fragments: Vec<([u64; 4], Fragment)> = recovered_fragments1
-        .into_iter()
-        .chain(recovered_fragments2.into_iter())
-        .chain(recovered_fragments3.into_iter())
-        .chain(recovered_fragments4.into_iter())
-        .collect();
+    let mut recovered_fragments = Vec::<([u64; 4], Fragment)>::new();
+    for checkpoint_path in checkpoint_paths {
+        let mut checkpoint_fragments: Vec<([u64; 4], Fragment)> =
+            recovery::checkpoint::read_checkpoint_entries(&checkpoint_path)
+                .expect("Checkpoint should be readable!");
+        checkpoint_fragments.retain(|(_, f)| matches!(f.data, FragmentData::FileDNode(_)));
+        recovered_fragments.extend(checkpoint_fragments);
+    }
 
-    write!(
-        OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open("undelete-filtered-checkpoint.json")
-            .unwrap(),
-        "{}",
-        &serde_json::to_string(&recovered_fragments.into_iter().collect::<Vec<(_, _)>>()).unwrap()
+    recovery::checkpoint::write_checkpoint_entries(
+        &cli::output_path(output_dir, recovery::select::FILTERED_CHECKPOINT_FILE_NAME),
+        &recovered_fragments,
     )
     .unwrap();
 }
@@ -1,12 +1,13 @@
+use clap::Parser;
 use std::{
     collections::{HashMap, HashSet},
-    env,
     fs::{File, OpenOptions},
     io::{Read, Seek, SeekFrom, Write},
+    path::PathBuf,
     sync::atomic::AtomicU64,
 };
 
-use szfs::yolo_block_recovery;
+use szfs::{cli, yolo_block_recovery};
 
 type ChecksumTableEntry = u32;
 
@@ -17,8 +18,22 @@ struct BlockInfo {
     main_offset: u64,
 }
 
+/// Looks for the blocks listed in bad-block-info.json in the checksum table, and writes the offsets that could be them to bad-block-extra-info.json
+#[derive(Parser)]
+struct Args {
+    /// Where build-checksum-table wrote its table, and where to write the results [default: the current directory]
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+    /// The guid of the top level vdev that is searched (zdb -l shows it), to check that the table was made for it
+    #[arg(long)]
+    vdev_guid: Option<u64>,
+}
+
 fn main() {
-    let mut checksum_map_file = File::open("checksum-map.bin").unwrap();
+    let args = Args::parse();
+    let output_dir = args.output_dir.as_deref();
+    let checksum_map_path = cli::output_path(output_dir, "checksum-map.bin");
+    let mut checksum_map_file = File::open(&checksum_map_path).unwrap();
     let checksum_map_file_size = checksum_map_file.seek(SeekFrom::End(0)).unwrap();
     let sector_size = 4096;
 
@@ -30,8 +45,10 @@ fn main() {
         disk_size as f64 / 1024.0 / 1024.0 / 1024.0
     );
 
-    let blocks_info: Vec<BlockInfo> =
-        serde_json::from_reader(File::open("bad-block-info.json").unwrap()).unwrap();
+    let blocks_info: Vec<BlockInfo> = serde_json::from_reader(
+        File::open(cli::output_path(output_dir, "bad-block-info.json")).unwrap(),
+    )
+    .unwrap();
 
     let block_checksums: Vec<(u32, [u64; 4])> = blocks_info
         .into_iter()
@@ -55,9 +72,10 @@ fn main() {
             4,
             1,
             sector_size,
+            args.vdev_guid,
             128 * 1024,
             block_checksums,
-            || File::open("checksum-map.bin").unwrap(),
+            || File::open(&checksum_map_path).unwrap(),
         )
         .unwrap()
         .collect();
@@ -65,7 +83,7 @@ fn main() {
         OpenOptions::new()
             .write(true)
             .create(true)
-            .open("bad-block-extra-info.json")
+            .open(cli::output_path(output_dir, "bad-block-extra-info.json"))
             .unwrap(),
         &res,
     )
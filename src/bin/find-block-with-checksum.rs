@@ -1,42 +1,186 @@
+use clap::{Parser, Subcommand};
 use std::{
     collections::HashMap,
-    env,
     fs::File,
-    io::{Read, Seek, SeekFrom, Write},
-    sync::atomic::AtomicU64,
+    io::{Seek, SeekFrom, Write},
+    ops::Range,
+    path::PathBuf,
 };
 
-use szfs::yolo_block_recovery;
+use szfs::{cli, fletcher::do_fletcher4, yolo_block_recovery, *};
 
 type ChecksumTableEntry = u32;
 
-fn main() {
-    let mut checksum_map_file = File::open("checksum-map.bin").unwrap();
-    let checksum_map_file_size = checksum_map_file.seek(SeekFrom::End(0)).unwrap();
-    let psize: usize = str::parse(env::args().nth(1).unwrap().trim())
-        .expect("Usage: find-block-with-checksum (psize) (sector_size)");
-    let sector_size: usize = str::parse(env::args().nth(2).unwrap().trim())
-        .expect("Usage: find-block-with-checksum (psize) (sector_size)");
-
-    let disk_size = (checksum_map_file_size / core::mem::size_of::<ChecksumTableEntry>() as u64)
-        * sector_size as u64;
-
-    println!(
-        "RAIDZ total size (GB): {}",
-        disk_size as f64 / 1024.0 / 1024.0 / 1024.0
-    );
+// How much of the disk a worker scans at a time when scanning without the checksum table
+const SCAN_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
 
+fn read_checksum_from_stdin() -> [u64; 4] {
     let mut input_line = String::new();
     std::io::stdout().flush().unwrap();
     print!("Please enter checksum of block to find: ");
     std::io::stdout().flush().unwrap();
-    input_line.clear();
     std::io::stdin()
         .read_line(&mut input_line)
         .expect("Reading a line should work!");
     let Ok(checksum) = parse_checksum_from_str(&input_line) else {
         panic!("Couldn't parse hash!");
     };
+    checksum
+}
+
+fn scan_main(pool_args: cli::PoolArgs, psize: usize) {
+    use szfs::ansi_color::*;
+    let (pool_args, mut pool) = cli::open_pool(pool_args);
+    let vdev_paths = &pool_args.vdevs;
+    let (nparity, asize) = (pool.nparity, pool.get_asize());
+    let rate_limiters = pool.get_rate_limiters();
+    let disk_size = pool.get_raidz().get_size();
+    println!(
+        "RAIDZ total size (GB): {}",
+        disk_size as f64 / 1024.0 / 1024.0 / 1024.0
+    );
+
+    let checksum = read_checksum_from_stdin();
+
+    // Allocations are always aligned to the sector size of the top level vdev, so there is no point in trying anything in between
+    let mut scan_config = recovery::scan::ScanConfig {
+        guesses: Vec::new(),
+        step_size: asize as u64,
+        offset_ranges: vec![Range {
+            start: 0,
+            end: disk_size,
+        }],
+        guess_lz4_psize_from_header: false,
+        birth_txgs: None,
+    };
+
+    // The checkpoint is only valid for the same search, so the checksum and psize are part of its name
+    let checkpoint_path =
+        pool_args.output_path(format!("find-checksum-{:016x}-{psize}.ckpt", checksum[0]));
+    let mut checkpoint = recovery::checkpoint::CheckpointLog::open(&checkpoint_path)
+        .expect("Checkpoint should be able to be opened!");
+    let mut matches = Vec::<u64>::new();
+    if checkpoint.get_nsegments() != 0 {
+        println!(
+            "{CYAN}Info{WHITE}: Resuming from checkpoint {checkpoint_path:?}, at offset {}",
+            checkpoint.get_resume_cursor()
+        );
+        for segment in checkpoint
+            .read_segments::<u64>()
+            .expect("Checkpoint should be readable!")
+        {
+            matches.extend(segment);
+        }
+        scan_config.skip_before(checkpoint.get_resume_cursor());
+    }
+
+    let cancel = cli::cancel_on_ctrl_c();
+    let completion = recovery::scan::parallel_scan(
+        &scan_config,
+        SCAN_CHUNK_SIZE,
+        &cancel,
+        || cli::open_worker_vdevs(vdev_paths, &rate_limiters),
+        |worker_vdevs, chunk| {
+            let mut vdev_raidz = cli::make_raidz(worker_vdevs, nparity, asize);
+            let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+            vdevs.insert(0usize, &mut vdev_raidz);
+
+            let mut chunk_matches = Vec::new();
+            for off in scan_config.get_offsets_in(chunk) {
+                let dva = zio::DataVirtualAddress::from(0, off, false);
+                let Ok(data) = dva.dereference(&mut vdevs, psize) else {
+                    continue;
+                };
+                if do_fletcher4(&data) == checksum {
+                    chunk_matches.push(off);
+                }
+            }
+            chunk_matches
+        },
+        |chunk, chunk_matches| {
+            // Chunks are passed in order, so everything before the end of this chunk has been scanned
+            checkpoint
+                .append(chunk.end, &chunk_matches)
+                .expect("Checkpoint should be writable!");
+            for off in chunk_matches.iter() {
+                println!(
+                    "{CYAN}Info{WHITE}: Found a match at {}!",
+                    device_map::format_offset(0, *off, psize as u64)
+                );
+            }
+            matches.extend(chunk_matches);
+            println!(
+                "{}% done scanning ...",
+                ((chunk.end as f32) / (disk_size as f32)) * 100.0
+            );
+        },
+    );
+    if completion == recovery::control::Completion::Cancelled {
+        println!("{CYAN}Info{WHITE}: Stopped, run the same command again to resume the scan");
+    }
+
+    println!("Found {} matches in total!", matches.len());
+    for pmatch in matches {
+        println!("- {}", pmatch);
+    }
+}
+
+/// Finds the offsets a block with a fletcher4 checksum could be at, the checksum is read from stdin
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Looks the checksum up in the checksum table made by build-checksum-table, this is fast but only finds potential matches
+    Table {
+        psize: usize,
+        sector_size: usize,
+        /// The guid of the top level vdev that is searched (zdb -l shows it), to check that the table was made for it
+        #[arg(long)]
+        vdev_guid: Option<u64>,
+        /// Where build-checksum-table wrote the table [default: the current directory]
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+    },
+    /// Checksums the data at every offset of the disk, this doesn't need the checksum table, but it has to read everything psize times
+    /// The scan is checkpointed after every chunk, so it can be resumed by running the same command again
+    Scan {
+        #[command(flatten)]
+        pool: cli::PoolArgs,
+        psize: usize,
+    },
+}
+
+fn main() {
+    let (psize, sector_size, vdev_guid, output_dir) = match Args::parse().command {
+        Command::Table {
+            psize,
+            sector_size,
+            vdev_guid,
+            output_dir,
+        } => (psize, sector_size, vdev_guid, output_dir),
+        Command::Scan { pool, psize } => {
+            scan_main(pool, psize);
+            return;
+        }
+    };
+
+    let checksum_map_path = cli::output_path(output_dir.as_deref(), "checksum-map.bin");
+    let mut checksum_map_file = File::open(&checksum_map_path).unwrap();
+    let checksum_map_file_size = checksum_map_file.seek(SeekFrom::End(0)).unwrap();
+
+    let disk_size = (checksum_map_file_size / core::mem::size_of::<ChecksumTableEntry>() as u64)
+        * sector_size as u64;
+
+    println!(
+        "RAIDZ total size (GB): {}",
+        disk_size as f64 / 1024.0 / 1024.0 / 1024.0
+    );
+
+    let checksum = read_checksum_from_stdin();
 
     let raidz_ndevices = 4;
     let raidz_nparity = 1;
@@ -47,9 +191,10 @@ fn main() {
             raidz_ndevices,
             raidz_nparity,
             sector_size,
+            vdev_guid,
             psize,
             HashMap::from([(checksum[0] as u32, checksum)]),
-            || File::open("checksum-map.bin").unwrap(),
+            || File::open(&checksum_map_path).unwrap(),
         )
         .unwrap()
         .map(|(_, potential_match)| potential_match)
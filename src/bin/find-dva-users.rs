@@ -0,0 +1,83 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{
+    cli,
+    reverse_map::{find_dva_users, raidz_offset_from_device_offset},
+    traverse::TraversedObject,
+    *,
+};
+
+/// The inverse of read-dva, finds out which objects use the data at an offset
+/// If a device index is given the offset is a byte offset on that device (ex. a SMART lba * 512) instead of an offset in the raidz
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// Offset in the raidz, or on the device if a device index is given
+    offset: u64,
+    /// Index of the device the offset is on, in the order of the --vdev options
+    device_index: Option<usize>,
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let raidz_offset = match args.device_index {
+        Some(device_index) => raidz_offset_from_device_offset(
+            device_index,
+            args.offset,
+            pool.devices.len(),
+            pool.get_asize(),
+        )
+        .expect("Offset should not be in the labels or the boot block!"),
+        None => args.offset,
+    };
+    println!(
+        "{CYAN}Info{WHITE}: Looking for blocks that use {}",
+        device_map::format_offset(0, raidz_offset, pool.get_asize() as u64)
+    );
+
+    let mut uberblocks = pool_args.filter_uberblocks(pool.get_label_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let mut active_uberblock = None;
+    for ub in uberblocks.iter_mut().rev() {
+        if ub.rootbp.dereference(&mut vdevs).is_ok() {
+            active_uberblock = Some(ub);
+            break;
+        }
+    }
+    let active_uberblock =
+        active_uberblock.expect("There should be at least one uberblock whose MOS can be read!");
+    println!("{CYAN}Info{WHITE}: Using {:?}", active_uberblock);
+
+    let users = find_dva_users(&mut active_uberblock.rootbp, raidz_offset, &mut vdevs)
+        .expect("MOS should be readable!");
+    if users.is_empty() {
+        println!("No reachable block uses offset {raidz_offset:#x}, it's probably free space");
+        return;
+    }
+
+    for user in users {
+        let object = match user.location.object {
+            TraversedObject::ObjSet => String::from("objset"),
+            TraversedObject::MetaDNode => String::from("meta dnode"),
+            TraversedObject::Object(object_id) => format!("object {object_id}"),
+        };
+        println!(
+            "{:?} {}, level {} block {} (object offset {}), copy {} at {}, birth txg {}",
+            user.location.objset,
+            object,
+            user.location.level,
+            user.location.block_id,
+            user.location.get_object_offset(),
+            user.dva_index,
+            user.dva,
+            user.logical_birth_txg
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{
+    cli,
+    rewind::{UberblockLocation, UberblockScan},
+    zdb, *,
+};
+
+/// Looks for uberblocks by their magic in the labels and optionally in the data of every disk, and ranks them by txg and by if their MOS can be read
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// Also scan the data of every disk starting at this offset (in bytes, from the start of the data, after the labels and the boot block)
+    #[arg(long, value_name = "OFFSET")]
+    start: Option<u64>,
+    /// Where to stop scanning the data of every disk, the end of the disk by default
+    #[arg(long, value_name = "OFFSET")]
+    end: Option<u64>,
+    /// Print every place each uberblock was found at
+    #[arg(long)]
+    locations: bool,
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (_, mut pool) = cli::open_pool(args.pool);
+
+    let mut scan = UberblockScan::default();
+    for (device, vdev) in pool.devices.iter_mut().enumerate() {
+        scan.scan_labels(vdev, device);
+        if let Some(start) = args.start {
+            let end = args.end.unwrap_or(vdev.get_size()).min(vdev.get_size());
+            println!("Scanning bytes {start}..{end} of vdev {device} ...");
+            scan.scan_range(vdev, device, start..end);
+        }
+    }
+    if !scan.big_endian.is_empty() {
+        println!(
+            "{YELLOW}Warning{WHITE}: Found {} big endian uberblocks, they can't be used: {:?}",
+            scan.big_endian.len(),
+            scan.big_endian
+        );
+    }
+
+    let mut vdev_raidz = pool.get_raidz();
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+    scan.rank(&mut vdevs);
+
+    println!(
+        "{CYAN}Info{WHITE}: Found {} uberblocks, {} of them have a readable MOS",
+        scan.found.len(),
+        scan.found
+            .iter()
+            .filter(|found| found.is_rootbp_readable)
+            .count()
+    );
+    for found in scan.found.iter() {
+        let ub = &found.uberblock;
+        let in_data = found
+            .locations
+            .iter()
+            .filter(|location| matches!(location, UberblockLocation::Data { .. }))
+            .count();
+        println!(
+            "txg {}, timestamp {}, version {}, guid sum {:#x}, MOS {}, {} copies ({in_data} outside of the labels)",
+            ub.txg,
+            ub.timestamp,
+            ub.version,
+            ub.guid_sum,
+            if found.is_rootbp_readable { "readable" } else { "unreadable" },
+            found.locations.len()
+        );
+        println!("    rootbp: {}", zdb::format_block_pointer(&ub.rootbp));
+        if args.locations {
+            for location in found.locations.iter() {
+                println!("    at {location:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{byte_iter::FromSliceLE, cli, dmu::ObjSet, *};
+
+// Every order has to be tried, so this gets slow fast, 10 disks is already 3628800 orders
+const MAX_DEVICES: usize = 10;
+// How many of the newest uberblocks are used, different txgs have their MOS in different places, so they are spread out over the disks
+const NUBERBLOCKS: usize = 4;
+// How many blocks of the meta dnode of the MOS are read for every uberblock
+const NMETADNODE_BLOCKS: u64 = 32;
+const NRESULTS: usize = 5;
+
+/// Finds the order the disks are in the raidz, for when the labels are too damaged to tell
+/// It tries every order of the disks and counts how many blocks of the MOS of the newest uberblocks can be read with their checksums intact
+/// the right order should be the only one that can read (almost) all of them
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+}
+
+// Returns: How many blocks could be read with the disks in this order
+fn score_order(
+    devices: &mut [ReadOnlyVdev<VdevFile>],
+    nparity: usize,
+    asize: usize,
+    uberblocks: &mut [Uberblock],
+) -> usize {
+    let mut vdev_raidz = cli::make_raidz(devices, nparity, asize);
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let mut score = 0;
+    for ub in uberblocks.iter_mut() {
+        let Ok(mos_data) = ub.rootbp.dereference(&mut vdevs) else {
+            continue;
+        };
+        score += 1;
+        let Some(mut mos) = ObjSet::from_slice_le(&mos_data) else {
+            continue;
+        };
+        let max_block_id = mos
+            .metadnode
+            .get_max_indirect_block_id()
+            .min(NMETADNODE_BLOCKS - 1);
+        for block_id in 0..=max_block_id {
+            // Holes don't count, they don't say anything about the order
+            if let Ok(Some(_)) = mos
+                .metadnode
+                .read_block_or_hole(block_id as usize, &mut vdevs)
+            {
+                score += 1;
+            }
+        }
+    }
+    score
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let (nparity, asize) = (pool.nparity, pool.get_asize());
+    let ndevices = pool.devices.len();
+    if ndevices > MAX_DEVICES {
+        cli::exit_with_error(format!(
+            "Trying every order of {ndevices} disks would take forever, at most {MAX_DEVICES} are supported"
+        ));
+    }
+
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let nskipped = uberblocks.len().saturating_sub(NUBERBLOCKS);
+    uberblocks.drain(..nskipped);
+    if uberblocks.is_empty() {
+        cli::exit_with_error("No uberblocks found, there is nothing to check the orders with");
+    }
+    println!(
+        "{CYAN}Info{WHITE}: Checking the orders using the uberblocks of txgs {:?}",
+        uberblocks.iter().map(|ub| ub.txg).collect::<Vec<_>>()
+    );
+
+    let norders = (1..=ndevices).product::<usize>();
+    let mut scores = Vec::<(usize, Vec<usize>)>::with_capacity(norders);
+    let mut order = (0..ndevices).collect::<Vec<_>>();
+    let mut score_current_order = |devices: &mut [ReadOnlyVdev<VdevFile>], order: &[usize]| {
+        let score = score_order(devices, nparity, asize, &mut uberblocks);
+        scores.push((score, order.to_vec()));
+        if scores.len().is_multiple_of(1000) {
+            println!(
+                "{}% done trying orders ...",
+                (scores.len() as f32 / norders as f32) * 100.0
+            );
+        }
+    };
+
+    // Heap's algorithm, every order is one swap away from the previous one
+    // Source: https://en.wikipedia.org/wiki/Heap%27s_algorithm
+    score_current_order(&mut pool.devices, &order);
+    let mut counters = vec![0; ndevices];
+    let mut i = 1;
+    while i < ndevices {
+        if counters[i] < i {
+            let j = if i % 2 == 0 { 0 } else { counters[i] };
+            pool.devices.swap(j, i);
+            order.swap(j, i);
+            score_current_order(&mut pool.devices, &order);
+            counters[i] += 1;
+            i = 1;
+        } else {
+            counters[i] = 0;
+            i += 1;
+        }
+    }
+
+    scores.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    let best_score = scores[0].0;
+    if best_score == 0 {
+        println!("{RED}Fatal{WHITE}: No order could read any block, are all the disks of the raidz there?");
+        return;
+    }
+    let nbest = scores
+        .iter()
+        .filter(|(score, _)| *score == best_score)
+        .count();
+    if nbest > 1 {
+        println!("{YELLOW}Warning{WHITE}: {nbest} orders read the same number of blocks, the sample wasn't enough to tell them apart!");
+    }
+
+    println!("Best orders (blocks read, order of the --vdev options):");
+    for (score, order) in scores.iter().take(NRESULTS) {
+        println!("- {score}: {order:?}");
+    }
+
+    println!("The disks in the best order:");
+    for index in scores[0].1.iter() {
+        println!("--vdev {:?}", pool_args.vdevs[*index]);
+    }
+}
@@ -1,86 +1,29 @@
-use std::{collections::HashMap, fs::OpenOptions, io::Write};
-use szfs::{
-    byte_iter::{FromBytes, FromBytesLE},
-    zio::Vdevs,
-    *,
-};
+use clap::Parser;
+use std::{collections::HashMap, fs::OpenOptions};
+use szfs::{byte_iter::FromSliceLE, cli, *};
+
+/// Walks from the newest readable uberblock to a file in the root directory of the root dataset, and extracts it
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// Name of the file in the root directory, it's extracted to a file with the same name
+    #[arg(default_value = "file.bin")]
+    file_name: String,
+}
 
 fn main() {
     use szfs::ansi_color::*;
-
-    let Ok(vdev0) = std::fs::OpenOptions::new().read(true).write(false).create(false).open("./test/vdev0.bin")
-    else {
-        println!("{RED}Fatal{WHITE}: Failed to open vdev0!");
-        return;
-    };
-    let mut vdev0: VdevFile = vdev0.into();
-
-    let Ok(vdev1) = std::fs::OpenOptions::new().read(true).write(false).create(false).open("./test/vdev1.bin")
-    else {
-        println!("{RED}Fatal{WHITE}: Failed to open vdev1!");
-        return;
-    };
-    let mut vdev1: VdevFile = vdev1.into();
-
-    let Ok(vdev2) = std::fs::OpenOptions::new().read(true).write(false).create(false).open("./test/vdev2.bin")
-    else {
-        println!("{RED}Fatal{WHITE}: Failed to open vdev2!");
-        return;
-    };
-    let mut vdev2: VdevFile = vdev2.into();
-
-    let Ok(vdev3) = std::fs::OpenOptions::new().read(true).write(false).create(false).open("./test/vdev3.bin")
-    else {
-        println!("{RED}Fatal{WHITE}: Failed to open vdev3!");
-        return;
-    };
-    let mut vdev3: VdevFile = vdev3.into();
-
-    // For now just use the first label
-    let mut label0 = VdevLabel::from_bytes(
-        &vdev0
-            .read_raw_label(0)
-            .expect("Vdev label 0 must be parsable!"),
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    println!(
+        "{CYAN}Info{WHITE}: Parsed nv_list, {:?}!",
+        pool.name_value_pairs
     );
-
-    let name_value_pairs =
-        nvlist::from_bytes_xdr(&mut label0.get_name_value_pairs_raw().iter().copied())
-            .expect("Name value pairs in the vdev label must be valid!");
-    let nvlist::Value::NVList(vdev_tree) = &name_value_pairs["vdev_tree"] else {
-        panic!("vdev_tree is not an nvlist!");
-    };
-
-    let nvlist::Value::U64(top_level_ashift) = vdev_tree["ashift"] else {
-        panic!("no ashift found for top level vdev!");
-    };
-
-    let nvlist::Value::U64(_label_txg) = name_value_pairs["txg"] else {
-        panic!("no txg found in label!");
-    };
-
-    println!("{CYAN}Info{WHITE}: Parsed nv_list, {:?}!", name_value_pairs);
-
-    let mut devices = Vdevs::new();
-    devices.insert(0, &mut vdev0);
-    devices.insert(1, &mut vdev1);
-    devices.insert(2, &mut vdev2);
-    devices.insert(3, &mut vdev3);
-
-    let mut vdev_raidz: VdevRaidz =
-        VdevRaidz::from_vdevs(devices, 4, 1, 2_usize.pow(top_level_ashift as u32));
-
-    label0.set_raw_uberblock_size(2_usize.pow(top_level_ashift as u32));
-
-    let mut uberblocks = Vec::<Uberblock>::new();
-    for i in 0..label0.get_raw_uberblock_count() {
-        let raw_uberblock = label0.get_raw_uberblock(i);
-        if let Some(uberblock) = Uberblock::from_bytes(&mut raw_uberblock.iter().copied()) {
-            uberblocks.push(uberblock);
-        }
-    }
+    let mut uberblocks = pool_args.filter_uberblocks(pool.get_label_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
 
     println!("{CYAN}Info{WHITE}: Found {} uberblocks!", uberblocks.len());
-    uberblocks.sort_unstable_by(|a, b| a.txg.cmp(&b.txg));
 
     let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
     vdevs.insert(0usize, &mut vdev_raidz);
@@ -96,8 +39,7 @@ fn main() {
     let (active_uberblock, mos_data) = uberblock_search_info.unwrap();
     println!("{CYAN}Info{WHITE}: Using {:?}", active_uberblock);
 
-    let mut meta_object_set =
-        dmu::ObjSet::from_bytes_le(&mut mos_data.iter().copied()).expect("Mos should be valid!");
+    let mut meta_object_set = dmu::ObjSet::from_slice_le(&mos_data).expect("Mos should be valid!");
 
     let dmu::DNode::ObjectDirectory(mut object_directory) = meta_object_set.get_dnode_at(1, &mut vdevs).expect("Object directory should be valid!")
     else {panic!("DNode 1 is not an object directory!"); };
@@ -131,14 +73,9 @@ fn main() {
         head_dataset_blockpointer
     );
     // Now we have access to the dataset we are interested in
-    let mut head_dataset_object_set = dmu::ObjSet::from_bytes_le(
-        &mut head_dataset_blockpointer
-            .dereference(&mut vdevs)
-            .unwrap()
-            .iter()
-            .copied(),
-    )
-    .unwrap();
+    let mut head_dataset_object_set =
+        dmu::ObjSet::from_slice_le(&head_dataset_blockpointer.dereference(&mut vdevs).unwrap())
+            .unwrap();
 
     let dmu::DNode::MasterNode(mut head_dataset_master_node) = head_dataset_object_set.get_dnode_at(1, &mut vdevs).unwrap() else {
         panic!("DNode 1 which is the master_node is not a master node!");
@@ -153,16 +90,30 @@ fn main() {
         master_node_zap_data
     );
 
-    let zap::Value::U64(system_attributes_info_number) = master_node_zap_data["SA_ATTRS"] else {
-        panic!("SA_ATTRS entry is not a number!");
-    };
+    let zpl_version = zpl::get_zpl_version(&master_node_zap_data);
+    match zpl_version {
+        Some(version) if version < zpl::ZPL_VERSION_SA => println!("{CYAN}Info{WHITE}: The dataset has zpl version {version}, it's from before system attributes, so files only have the old znode attributes"),
+        Some(version) => println!("{CYAN}Info{WHITE}: The dataset has zpl version {version}"),
+        None => println!("{YELLOW}Warning{WHITE}: The master node has no zpl version, it might be damaged!"),
+    }
 
-    let mut system_attributes = zpl::SystemAttributes::from_attributes_node_number(
-        system_attributes_info_number as usize,
+    // Datasets made before system attributes (zpl version 5) don't have SA_ATTRS, their files have the old znode attributes
+    // so if the registry can't be read the files that have those can still be walked
+    let mut system_attributes = match zpl::open_system_attributes(
+        &master_node_zap_data,
         &mut head_dataset_object_set,
         &mut vdevs,
-    )
-    .unwrap();
+    ) {
+        Ok(None) if zpl_version.is_some_and(|version| version >= zpl::ZPL_VERSION_SA) => {
+            println!("{YELLOW}Warning{WHITE}: The dataset has zpl version {}, but no system attribute registry, only files with the old znode attributes can be read!", zpl_version.unwrap());
+            None
+        }
+        Ok(system_attributes) => system_attributes,
+        Err(err) => {
+            println!("{YELLOW}Warning{WHITE}: The system attribute registry can't be read ({err:?}), only files with the old znode attributes can be read!");
+            None
+        }
+    };
 
     let zap::Value::U64(root_number) = master_node_zap_data["ROOT"] else {
         panic!("ROOT zap entry is not a number!");
@@ -175,35 +126,46 @@ fn main() {
     let root_node_zap_data = root_node.dump_zap_contents(&mut vdevs).unwrap();
     println!("Root directory data zap: {:?}", root_node_zap_data);
 
-    let zap::Value::U64(mut file_node_number) = root_node_zap_data["file.bin"] else {
-        panic!("File entry is not a number!");
-    };
-
-    // Only bottom 48 bits are the actual object id
-    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h#L152
-    file_node_number &= (1 << 48) - 1;
+    // The dataset might be case insensitive or normalize names, so look the file up like zfs would
+    let name_matching = zpl::NameMatching::from_master_node(&master_node_zap_data);
+    let file_node_number = name_matching
+        .lookup_directory_entry(&root_node_zap_data, &args.file_name)
+        .expect("File entry should exist and be a number!");
 
     let szfs::dmu::DNode::PlainFileContents(mut file_node) = head_dataset_object_set.get_dnode_at(file_node_number as usize, &mut vdevs).unwrap() else {
         panic!("DNode {} which is the file node is not a plain file contents node!", file_node_number);
     };
 
-    let file_info = system_attributes
-        .parse_system_attributes_bytes_le(&mut file_node.0.get_bonus_data().iter().copied())
-        .unwrap();
-    let zpl::Value::U64(file_len) = file_info["ZPL_SIZE"] else {
+    let file_info = zpl::parse_zpl_attributes(
+        system_attributes.as_mut(),
+        file_node.0.get_bonus_data(),
+        &file_node.1,
+    )
+    .expect("Attributes of the file should be readable!");
+    let Some(zpl::Value::U64(file_len)) = file_info.get("ZPL_SIZE") else {
         panic!("File length is not a number!");
     };
     println!("File size: {:?}", file_len);
-    OpenOptions::new()
+    let mut output = OpenOptions::new()
         .create(true)
         .write(true)
-        .open("file.bin")
-        .unwrap()
-        .write_all(
-            &file_node
-                .0
-                .read(0, usize::try_from(file_len).unwrap(), &mut vdevs)
-                .unwrap(),
-        )
+        .truncate(true)
+        .open(pool_args.output_path(&args.file_name))
         .unwrap();
+    let options = dmu::ExtractOptions {
+        size: Some(*file_len),
+        sparse: true,
+        ..Default::default()
+    };
+    let report = file_node
+        .extract_to(&mut output, &options, &mut vdevs)
+        .expect("File should be writable!");
+    for bad_range in report.bad_ranges {
+        println!(
+            "{YELLOW}Warning{WHITE}: Bytes {}..{} of the file couldn't be read, they are zeros in the output!",
+            bad_range.start, bad_range.end
+        );
+    }
+
+    warnings::print_warning_summary();
 }
@@ -0,0 +1,105 @@
+use clap::Parser;
+use std::{collections::HashMap, fs::File};
+use szfs::{census, cli, rewind, zdb, *};
+
+/// Counts everything reachable from the newest readable uberblock, prints a summary and writes all of it to census.json
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let (_, mos_selection) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+        .expect("There should be at least one uberblock whose MOS can be read!");
+    mos_selection.print();
+    let uberblock = uberblocks
+        .iter_mut()
+        .find(|ub| ub.txg == mos_selection.used_txg)
+        .unwrap();
+
+    let census =
+        census::take_census(&mut uberblock.rootbp, &mut vdevs).expect("MOS should be readable!");
+
+    println!("Objects per type:");
+    for (obj_type, nobjects) in census.objects_per_type.iter() {
+        println!("    {obj_type}: {nobjects}");
+    }
+
+    println!("Blocks per type:");
+    for (obj_type, stats) in census.blocks_per_type.iter() {
+        println!(
+            "    {obj_type}: {} blocks, lsize {}, psize {}, asize {}",
+            stats.nblocks,
+            zdb::nicenum(stats.lsize),
+            zdb::nicenum(stats.psize),
+            zdb::nicenum(stats.asize)
+        );
+    }
+
+    println!("Compression methods: {:?}", census.compression_methods);
+    println!("Checksum methods: {:?}", census.checksum_methods);
+    println!("Data block sizes:");
+    for (size, nblocks) in census.data_block_sizes.iter() {
+        println!("    {}: {nblocks}", zdb::nicenum(*size));
+    }
+    println!(
+        "{} embedded blocks ({} redacted), {} gang blocks",
+        census.nembedded_blocks, census.nredacted_blocks, census.ngang_blocks
+    );
+    for (types, nblocks) in census.type_mismatches.iter() {
+        println!("{YELLOW}Warning{WHITE}: {nblocks} block pointers have the wrong type ({types}), they are probably corrupted!");
+    }
+    if census.nlevel_mismatches != 0 {
+        println!(
+            "{YELLOW}Warning{WHITE}: {} block pointers have the wrong level, they are probably corrupted!",
+            census.nlevel_mismatches
+        );
+    }
+
+    println!("Datasets:");
+    for (dataset, dataset_census) in census.datasets.iter() {
+        let name = if *dataset == 0 {
+            String::from("MOS")
+        } else if dataset_census.is_snapshot {
+            format!("snapshot {dataset}")
+        } else {
+            format!("dataset {dataset}")
+        };
+        println!(
+            "    {name}: {} objects, found {} blocks (lsize {}, psize {}, asize {})",
+            dataset_census.nobjects,
+            dataset_census.blocks.nblocks,
+            zdb::nicenum(dataset_census.blocks.lsize),
+            zdb::nicenum(dataset_census.blocks.psize),
+            zdb::nicenum(dataset_census.blocks.asize)
+        );
+        if let (Some(used), Some(compressed), Some(uncompressed)) = (
+            dataset_census.used_bytes,
+            dataset_census.compressed_bytes,
+            dataset_census.uncompressed_bytes,
+        ) {
+            println!(
+                "        dsl says: used {}, compressed {}, uncompressed {}",
+                zdb::nicenum(used),
+                zdb::nicenum(compressed),
+                zdb::nicenum(uncompressed)
+            );
+        }
+    }
+
+    let census_path = pool_args.output_path("census.json");
+    serde_json::to_writer_pretty(File::create(&census_path).unwrap(), &census).unwrap();
+    println!("{CYAN}Info{WHITE}: Wrote the full census to {census_path:?}");
+
+    warnings::print_warning_summary();
+}
@@ -0,0 +1,85 @@
+use clap::Parser;
+use std::{collections::HashMap, fs::OpenOptions, path::PathBuf};
+use szfs::{cli, rewind, *};
+
+/// Recovers a file by its object id, ex. from an old zdb listing or the delete queue, using older uberblocks
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    object_id: usize,
+    /// Only use uberblocks from this txg on
+    #[arg(long, default_value_t = 0)]
+    first_txg: u64,
+    /// Only use uberblocks up to this txg
+    #[arg(long, default_value_t = u64::MAX)]
+    last_txg: u64,
+    /// Blocks that are gone from the pool's disks might still be on its cache device
+    #[arg(long, value_name = "PATH")]
+    cache_device: Option<PathBuf>,
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let object_id = args.object_id;
+    let mut uberblocks = pool_args.filter_uberblocks(pool.get_label_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    // Blocks that are gone from the pool's disks might still be on its cache device
+    if let Some(cache_device_path) = args.cache_device {
+        let mut cache_device = cli::open_vdev(
+            &cache_device_path,
+            pool_args.allow_write,
+            pool_args.make_rate_limiter(),
+        )
+        .expect("Cache device should be able to be opened!");
+        match l2arc::L2ArcIndex::build(&mut cache_device) {
+            Some((_, index)) => {
+                println!(
+                    "{CYAN}Info{WHITE}: The cache device has {} blocks in {} log blocks",
+                    index.n_entries, index.n_log_blocks
+                );
+                l2arc::set_cache_device(Box::new(cache_device), index);
+            }
+            None => println!("{YELLOW}Warning{WHITE}: The cache device doesn't have a persistent L2ARC header, not using it!"),
+        }
+    }
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let output_path = pool_args.output_path(format!("object-{object_id}.bin"));
+    let mut output = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&output_path)
+        .expect("Output file should be able to be created!");
+
+    let Some((uberblock_txg, report)) = rewind::recover_file_by_object_id(
+        &mut uberblocks,
+        args.first_txg..=args.last_txg,
+        object_id,
+        &mut output,
+        &mut vdevs,
+    ) else {
+        println!("{RED}Fatal{WHITE}: No version of object {object_id} that is a plain file could be found!");
+        return;
+    };
+
+    println!(
+        "{CYAN}Info{WHITE}: Recovered object {object_id} as it was at txg {uberblock_txg} to {output_path:?}, {} bytes of data and {} bytes of holes",
+        report.bytes_written, report.hole_bytes
+    );
+    for bad_range in report.bad_ranges {
+        println!(
+            "{YELLOW}Warning{WHITE}: Bytes {}..{} of the file couldn't be read, they are zeros in the output!",
+            bad_range.start, bad_range.end
+        );
+    }
+
+    warnings::print_warning_summary();
+}
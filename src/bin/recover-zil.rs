@@ -0,0 +1,128 @@
+use clap::Parser;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+};
+use szfs::{binpatch::PatchWriter, byte_iter::FromSliceLE, cli, rewind, traverse, zil, *};
+
+/// Reads the zil of every dataset and writes the writes that never made it into a txg as a binpatch per file
+/// Apply those on top of a recovered file (ex. from recover-object) with szfs-patch to get the synchronous writes back
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// If the pool has a separate log device, the zil is on it, so it has to be given too
+    #[arg(long, value_name = "PATH")]
+    log_device: Option<PathBuf>,
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let mut log_device = args.log_device.map(|path| {
+        cli::open_vdev(&path, pool_args.allow_write, pool_args.make_rate_limiter())
+            .expect("Log device should be able to be opened!")
+    });
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    // The dvas of the log blocks use the id of the log device as their vdev, that is in its own label
+    if let Some(log_device) = log_device.as_mut() {
+        let label0 = VdevLabel::from_bytes(
+            &log_device
+                .read_raw_label(0)
+                .expect("Log device label 0 must be parsable!"),
+        );
+        let name_value_pairs =
+            nvlist::from_bytes_xdr(&mut label0.get_name_value_pairs_raw().iter().copied())
+                .expect("Name value pairs in the log device label must be valid!");
+        let nvlist::Value::NVList(vdev_tree) = &name_value_pairs["vdev_tree"] else {
+            panic!("vdev_tree of the log device is not an nvlist!");
+        };
+        let nvlist::Value::U64(log_device_id) = vdev_tree["id"] else {
+            panic!("no id found for the log device!");
+        };
+        println!("{CYAN}Info{WHITE}: Log device is top level vdev {log_device_id}");
+        vdevs.insert(log_device_id as usize, log_device);
+    }
+
+    let (mut mos, mos_selection) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+        .expect("There should be at least one uberblock whose MOS can be read!");
+    mos_selection.print();
+
+    for (dataset_id, mut dataset) in traverse::find_datasets(&mut mos, &mut vdevs) {
+        // Snapshots can't be written to, so they don't have a zil
+        if dataset.is_snapshot() {
+            continue;
+        }
+        let Some(objset) = dataset
+            .get_block_pointer()
+            .dereference(&mut vdevs)
+            .ok()
+            .and_then(|objset_data| dmu::ObjSet::from_slice_le(&objset_data))
+        else {
+            println!("{YELLOW}Warning{WHITE}: Couldn't read the objset of dataset {dataset_id}, skipping it!");
+            continue;
+        };
+        let Some(zil_header) = objset.zil else {
+            continue;
+        };
+
+        let records = zil::read_log_records(&zil_header, &mut vdevs);
+        if records.is_empty() {
+            continue;
+        }
+
+        // Records from txgs that were synced are already in the dataset, replaying them could undo later changes
+        let (synced, unsynced): (Vec<_>, Vec<_>) = records
+            .into_iter()
+            .partition(|record| record.txg <= mos_selection.used_txg);
+        println!(
+            "Dataset {dataset_id}: {} log records from synced txgs, {} from txgs after {}",
+            synced.len(),
+            unsynced.len(),
+            mos_selection.used_txg
+        );
+
+        let mut patches = BTreeMap::<u64, PatchWriter<BufWriter<File>>>::new();
+        for record in unsynced.iter() {
+            let Some(write) = &record.write else {
+                println!(
+                    "    {YELLOW}Warning{WHITE}: Record {} (txg {}) is of type {}, only writes are recovered!",
+                    record.seq, record.txg, record.txtype
+                );
+                continue;
+            };
+            let Some(data) = write.read_data(&mut vdevs) else {
+                println!(
+                    "    {YELLOW}Warning{WHITE}: Couldn't read the data of the write to object {} at offset {}!",
+                    write.object, write.offset
+                );
+                continue;
+            };
+
+            let patch = patches.entry(write.object).or_insert_with(|| {
+                PatchWriter::new(BufWriter::new(
+                    File::create(
+                        pool_args
+                            .output_path(format!("zil-{dataset_id}-{}.binpatch", write.object)),
+                    )
+                    .unwrap(),
+                ))
+                .unwrap()
+            });
+            patch.write_entry(write.offset, &data).unwrap();
+        }
+
+        for (object, patch) in patches {
+            patch.finish().unwrap();
+            println!("{CYAN}Info{WHITE}: Wrote the logged writes to object {object} to zil-{dataset_id}-{object}.binpatch");
+        }
+    }
+}
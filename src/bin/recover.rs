@@ -1,190 +1,218 @@
-use lru::LruCache;
-use serde::{Deserialize, Serialize};
+use clap::Parser;
 use std::{
-    cmp::Reverse,
-    collections::{HashMap, HashSet},
-    env,
-    fmt::Debug,
-    fs::{File, OpenOptions},
+    collections::HashMap,
+    fs::OpenOptions,
     io::{Seek, SeekFrom, Write},
 };
 use szfs::{
-    dmu::{DNodeDirectoryContents, DNodePlainFileContents, ObjSet},
+    cli,
+    ddt::DedupTableEntry,
+    dmu::IndirectTreeCursor,
+    recovery::{
+        block_index::BlockIndex,
+        fragment::{Fragment, FragmentData},
+        select::{FileAttributes, FileSelection},
+    },
     zio::Vdevs,
     *,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
-struct IndirectBlock {
-    pub bps: Vec<Option<zio::BlockPointer>>,
-}
-
-#[derive(Serialize, Deserialize)]
-enum FragmentData {
-    FileDNode(DNodePlainFileContents),
-    DirectoryDNode(DNodeDirectoryContents, Vec<String>),
-    ObjSetDNode(ObjSet),
-    IndirectBlock(IndirectBlock),
-}
+// Written by dump-ddt
+const DDT_PATH: &str = "ddt.json";
 
-impl Debug for FragmentData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            FragmentData::FileDNode(_) => write!(f, "File"),
-            FragmentData::DirectoryDNode(_, _) => write!(f, "Dir"),
-            FragmentData::ObjSetDNode(_) => write!(f, "ObjSet"),
-            FragmentData::IndirectBlock(_) => write!(f, "Indirect"),
-        }?;
+// The data, the fragment it was read from, and which copy of the block it was if it's from a single block of the fragment
+type AggregatedBlock = (Vec<u8>, [u64; 4], Option<zio::BlockSource>);
 
-        Ok(())
-    }
-}
-
-#[derive(Serialize, Deserialize)]
-struct Fragment {
-    data: FragmentData,
-    children: HashSet<[u64; 4]>,
-}
-
-impl Debug for Fragment {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.data)?;
-        write!(f, "(")?;
-        for child in self.children.iter() {
-            write!(f, "{:?}, ", child[0])?;
-        }
-        write!(f, ")")?;
-        Ok(())
-    }
-}
-
-impl From<FragmentData> for Fragment {
-    fn from(frag: FragmentData) -> Self {
-        Self {
-            data: frag,
-            children: HashSet::new(),
+// The fragment the previous block was read from is tried first, as consecutive blocks usually come from the same fragment
+// and then the rest of the fragments that have the block, in order of priority
+// Returns: The first `amount` bytes of the block
+// NOTE: Fragments with a different block size are read by offset, so older versions of a file that had another block size can still fill in blocks
+// Every fragment has its own cursor, so the indirect blocks of a fragment are only read once for all the blocks under them, and its next blocks are prefetched
+#[allow(clippy::too_many_arguments)]
+fn aggregated_read_block(
+    block_id: usize,
+    block_size: usize,
+    amount: usize,
+    block_index: &BlockIndex,
+    last_used: &mut [u64; 4],
+    fragments: &mut HashMap<[u64; 4], Fragment>,
+    cursors: &mut HashMap<[u64; 4], IndirectTreeCursor>,
+    vdevs: &mut Vdevs,
+) -> Result<AggregatedBlock, ()> {
+    let previous = *last_used;
+    let candidates = block_index.get_candidates(block_id as u64);
+    let ordered_candidates = candidates
+        .iter()
+        .filter(|hsh| **hsh == previous)
+        .chain(candidates.iter().filter(|hsh| **hsh != previous));
+    for hsh in ordered_candidates {
+        let Some(FragmentData::FileDNode(file)) = fragments.get_mut(hsh).map(|f| &mut f.data)
+        else {
+            continue;
+        };
+        if file.0.parse_data_block_size() == block_size {
+            let cursor = cursors.entry(*hsh).or_default();
+            if let Ok((mut block_data, source)) =
+                cursor.read_block_with_source(&file.0, block_id, vdevs)
+            {
+                block_data.truncate(amount);
+                *last_used = *hsh;
+                return Ok((block_data, *hsh, Some(source)));
+            }
+        } else if let Ok(block_data) = file.0.read((block_id * block_size) as u64, amount, vdevs) {
+            *last_used = *hsh;
+            return Ok((block_data, *hsh, None));
         }
     }
+    Err(())
 }
 
-fn aggregated_read_block(
+// If the block was deduplicated the dedup table has its own copy of the dvas, which might still be readable
+// Only fragments with the same block size are used, as the dedup table has whole blocks
+fn read_block_from_dedup_table(
     block_id: usize,
-    fragments: &mut LruCache<[u64; 4], Fragment>,
+    block_size: usize,
+    block_index: &BlockIndex,
+    fragments: &mut HashMap<[u64; 4], Fragment>,
+    dedup_entries: &HashMap<[u64; 4], DedupTableEntry>,
     vdevs: &mut Vdevs,
-) -> Result<(Vec<u8>, [u64; 4]), ()> {
-    let mut res = Err(());
-    for f in fragments.iter_mut() {
-        if let FragmentData::FileDNode(file) = &mut f.1.data {
-            if let Ok(res_block_data) = file.0.read_block(block_id, vdevs) {
-                res = Ok((res_block_data, *f.0));
-                // I just realized why my code is slow
-                // i forgot to break, *facepalm*
-                break;
-            }
+) -> Result<Vec<u8>, ()> {
+    for hsh in block_index.get_candidates(block_id as u64) {
+        let Some(FragmentData::FileDNode(file)) = fragments.get_mut(hsh).map(|f| &mut f.data)
+        else {
+            continue;
+        };
+        if file.0.parse_data_block_size() != block_size {
+            continue;
         }
-    }
+        let Ok(zio::BlockPointer::Normal(bp)) = file.0.get_data_block_pointer(block_id, vdevs)
+        else {
+            continue;
+        };
+        let Some(entry) = dedup_entries.get(&bp.get_checksum()) else {
+            continue;
+        };
 
-    if let Ok((_, hsh)) = res {
-        fragments.get(&hsh); // Update LRU
+        // NOTE: Dedup usually uses sha256 which we can't verify, but data from a dva the dedup table points to is better than zeros
+        if let Ok(block_data) = entry.read(true, vdevs) {
+            if block_data.len() == block_size {
+                return Ok(block_data);
+            }
+        }
     }
+    Err(())
+}
 
-    res
+/// Recovers a file from the fragments undelete found, merging the blocks of all the versions of it that match the selection
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    #[command(flatten)]
+    selection: FileSelection,
+    /// How many blocks to prefetch ahead of the one being recovered, so the disks read them in big batches instead of seeking for every block, 0 to turn it off
+    #[arg(long, value_name = "NBLOCKS", default_value_t = 32)]
+    read_ahead: usize,
 }
 
 fn main() {
     use szfs::ansi_color::*;
-    let usage = format!("Usage: {} (vdevs...)", env::args().next().unwrap());
-    let mut vdev0: VdevFile = File::open(env::args().nth(1).expect(&usage))
-        .expect("Vdev 0 should be able to be opened!")
-        .into();
-    let mut vdev1: VdevFile = File::open(env::args().nth(2).expect(&usage))
-        .expect("Vdev 1 should be able to be opened!")
-        .into();
-    let mut vdev2: VdevFile = File::open(env::args().nth(3).expect(&usage))
-        .expect("Vdev 2 should be able to be opened!")
-        .into();
-    let mut vdev3: VdevFile = File::open(env::args().nth(4).expect(&usage))
-        .expect("Vdev 3 should be able to be opened!")
-        .into();
-
-    // For now just use the first label
-    let mut label0 = VdevLabel::from_bytes(
-        &vdev0
-            .read_raw_label(0)
-            .expect("Vdev label 0 must be parsable!"),
-    );
-
-    let name_value_pairs =
-        nvlist::from_bytes_xdr(&mut label0.get_name_value_pairs_raw().iter().copied())
-            .expect("Name value pairs in the vdev label must be valid!");
-    let nvlist::Value::NVList(vdev_tree) = &name_value_pairs["vdev_tree"] else {
-        panic!("vdev_tree is not an nvlist!");
-    };
-
-    let nvlist::Value::U64(top_level_ashift) = vdev_tree["ashift"] else {
-        panic!("no ashift found for top level vdev!");
-    };
+    let args = Args::parse();
+    let selection = args.selection;
+    if !selection.needs_bonus_attributes()
+        && selection.object_id.is_none()
+        && selection.hash.is_none()
+    {
+        println!("{YELLOW}Warning{WHITE}: No file selected, the blocks of all recovered files will be merged together!");
+    }
 
-    println!("{CYAN}Info{WHITE}: Parsed nv_list, {name_value_pairs:?}!");
-    println!("{RED}Important{WHITE}: Please make sure the disks are actually in the right order by using the nv_list, i can't actually check that in a reliable way!!!");
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    println!(
+        "{CYAN}Info{WHITE}: Parsed nv_list, {:?}!",
+        pool.name_value_pairs
+    );
 
     if cfg!(debug_assertions) {
         println!("{RED}Important{WHITE}: This is not an optimized binary!");
     }
 
-    let mut devices = Vdevs::new();
-    devices.insert(0, &mut vdev0);
-    devices.insert(1, &mut vdev1);
-    devices.insert(2, &mut vdev2);
-    devices.insert(3, &mut vdev3);
-
-    let mut vdev_raidz: VdevRaidz =
-        VdevRaidz::from_vdevs(devices, 4, 1, 2_usize.pow(top_level_ashift as u32));
-
-    label0.set_raw_uberblock_size(2_usize.pow(top_level_ashift as u32));
+    let mut vdev_raidz = pool.get_raidz();
 
     let disk_size = vdev_raidz.get_size();
     let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
     vdevs.insert(0usize, &mut vdev_raidz);
 
-    let mut recovered_fragments: Vec<([u64; 4], Fragment)> =
-        serde_json::from_reader(File::open("undelete-filtered-checkpoint.json").unwrap()).unwrap();
-
-    recovered_fragments.retain_mut(|frag| {
-        if let FragmentData::FileDNode(file) = &mut frag.1.data {
-            let file_cr_time_unix_timestamp = u64::from_le_bytes(
-                file.0.get_bonus_data()[14 * 8..14 * 8 + 8]
-                    .try_into()
-                    .unwrap(),
-            );
-            // Note: I happen to have reliably recovered the creation time
-            // and I also am pretty sure that no other files were created in the same second
-            // So for my use case this is a pretty good filter
-            file_cr_time_unix_timestamp == 1674749006
-        } else {
-            false
-        }
-    });
-
-    recovered_fragments.sort_unstable_by_key(|f| {
-        let FragmentData::FileDNode(f) = &f.1.data else {panic!("");};
-        Reverse(f.0.get_data_size())
-    });
+    let mut recovered_fragments = recovery::select::read_selected_fragments(&pool_args, &selection)
+        .unwrap_or_else(|err| cli::exit_with_error(err));
+
+    let ddt_path = pool_args.output_path(DDT_PATH);
+    let dedup_entries = if ddt_path.exists() {
+        let entries =
+            ddt::read_dedup_entries(&ddt_path).expect("Dedup table dump should be readable!");
+        println!(
+            "{CYAN}Info{WHITE}: Loaded {} dedup table entries, they will be used for bad blocks",
+            entries.len()
+        );
+        ddt::index_by_checksum(entries)
+    } else {
+        HashMap::new()
+    };
+    if recovered_fragments.is_empty() {
+        println!("{YELLOW}Warning{WHITE}: No recovered file matches the selection!");
+        return;
+    }
 
     for res in recovered_fragments.iter() {
         println!("{:?}", res);
     }
 
     let biggest_file_hsh = recovered_fragments[0].0;
-    let mut recovered_fragments: LruCache<[u64; 4], Fragment> = {
-        let mut res = LruCache::unbounded();
-        for e in recovered_fragments {
-            res.put(e.0, e.1);
-        }
-        res
+
+    // The biggest version of the file is the most complete one, so the size and block size are taken from it
+    // The block size comes from the dnode, it's the recordsize of the dataset when the file was written, or the size of the file if it was only ever one block
+    let (file_size, file_block_size) = {
+        let FragmentData::FileDNode(file) = &recovered_fragments[0].1.data else {
+            unreachable!();
+        };
+        let data_size = file.0.get_data_size();
+        let file_size = match FileAttributes::guess_from_dnode(file) {
+            Some(attributes) if attributes.size <= data_size as u64 => attributes.size as usize,
+            // The layout of the attributes is guessed, a size that doesn't fit in the blocks of the file means the guess was wrong
+            Some(attributes) => {
+                println!("{YELLOW}Warning{WHITE}: The size of the file in its attributes ({}) is bigger than its data ({data_size}), using the size of its data!", attributes.size);
+                data_size
+            }
+            None => {
+                println!("{YELLOW}Warning{WHITE}: Couldn't figure out the size of the file, using the size of its data!");
+                data_size
+            }
+        };
+        (file_size, file.0.parse_data_block_size())
     };
+    if file_block_size == 0 {
+        cli::exit_with_error("The newest version of the file has a block size of 0");
+    }
+    println!("Recovering {file_size} bytes in blocks of {file_block_size} bytes");
 
-    recovered_fragments.get(&biggest_file_hsh); // Update LRU
+    // The fragments are sorted biggest first, so that's also the order they are tried in
+    println!(
+        "Indexing the blocks of {} fragments ...",
+        recovered_fragments.len()
+    );
+    let block_index =
+        BlockIndex::build(&mut recovered_fragments, file_block_size as u64, &mut vdevs);
+    println!(
+        "{CYAN}Info{WHITE}: Indexed the blocks of all fragments, found {} runs of blocks that are in the same fragments",
+        block_index.get_n_runs()
+    );
+
+    let mut recovered_fragments: HashMap<[u64; 4], Fragment> =
+        recovered_fragments.into_iter().collect();
+    let mut last_used_hsh = biggest_file_hsh;
+    let mut cursors: HashMap<[u64; 4], IndirectTreeCursor> = recovered_fragments
+        .keys()
+        .map(|hsh| (*hsh, IndirectTreeCursor::with_read_ahead(args.read_ahead)))
+        .collect();
 
     println!(
         "N fragments loaded form checkpoint: {}",
@@ -193,43 +221,33 @@ fn main() {
 
     println!("RAIDZ total size (GB): {}", disk_size / 1024 / 1024 / 1024);
 
-    // NOTE: This is specifically meant for my scenario
-    // where i lost a big file that i have recovered the size of
-    // in a fs that only ever had 2-3 files
-    let file_size: usize = 1084546955827;
-
-    // I know the block size of the file system i'm recovering from
-    let file_block_size: usize = 128 * 1024;
-
+    // NOTE: Not truncated, so an interrupted recovery can be resumed
     let mut output_file = OpenOptions::new()
-        .append(true)
+        .write(true)
         .create(true)
-        .open("recovered-file.bin")
+        .truncate(false)
+        .open(pool_args.output_path("recovered-file.bin"))
         .unwrap();
 
-    let mut resuming_block = 0;
-    // NOTE: A file where offset 0 is the last offset is of size 1
-    if output_file.metadata().unwrap().len() > 0 {
-        let resuming_offset = output_file.metadata().unwrap().len() - 1;
-        output_file.seek(SeekFrom::Start(resuming_offset)).unwrap();
-        resuming_block = (resuming_offset / (file_block_size as u64))
-            .try_into()
-            .unwrap();
-    }
+    // Every block but the last one is written whole, so a partially written block is just written again
+    let resuming_block = (output_file.metadata().unwrap().len() / (file_block_size as u64))
+        .try_into()
+        .unwrap();
+    output_file
+        .seek(SeekFrom::Start((resuming_block * file_block_size) as u64))
+        .unwrap();
     println!("Resuming from block {resuming_block}!");
 
-    let nblocks_in_file = file_size / file_block_size
-        + if file_size % file_block_size != 0 {
-            1
-        } else {
-            0
-        };
+    let nblocks_in_file = file_size.div_ceil(file_block_size);
+    // Every ~512 mb
+    let progress_interval = (512 * 1024 * 1024 / file_block_size).max(1);
 
     let mut nbad_blocks = 0;
 
     for block_id in resuming_block..nblocks_in_file {
-        if block_id % (4 * 1024) == 0 {
-            // Every ~512 mb
+        // The last block only goes up to the end of the file
+        let amount = file_block_size.min(file_size - block_id * file_block_size);
+        if block_id % progress_interval == 0 {
             println!(
                 "Copying data {}% done, {} bad blocks so far ...",
                 (block_id as f32 / nblocks_in_file as f32) * 100.0,
@@ -237,17 +255,52 @@ fn main() {
             );
         }
 
-        if let Ok((block_data, _)) =
-            aggregated_read_block(block_id, &mut recovered_fragments, &mut vdevs)
-        {
-            assert!(block_data.len() == file_block_size);
+        if let Ok((block_data, fragment_hash, source)) = aggregated_read_block(
+            block_id,
+            file_block_size,
+            amount,
+            &block_index,
+            &mut last_used_hsh,
+            &mut recovered_fragments,
+            &mut cursors,
+            &mut vdevs,
+        ) {
+            assert!(block_data.len() == amount);
+            // The first copy of the newest version is the normal case, anything else is worth knowing about
+            match source {
+                Some(zio::BlockSource::Dva(0)) if fragment_hash == biggest_file_hsh => (),
+                Some(source) => {
+                    println!(
+                        "Block {block_id} was read from {source} of fragment {fragment_hash:?}"
+                    );
+                }
+                None => {
+                    println!("Block {block_id} was read from fragment {fragment_hash:?}, which has a different block size");
+                }
+            }
             output_file.write_all(&block_data).unwrap();
+        } else if let Ok(block_data) = read_block_from_dedup_table(
+            block_id,
+            file_block_size,
+            &block_index,
+            &mut recovered_fragments,
+            &dedup_entries,
+            &mut vdevs,
+        ) {
+            println!("Block {block_id} was recovered from the dedup table!");
+            output_file.write_all(&block_data[..amount]).unwrap();
         } else {
             println!("Block {block_id} is bad!");
             nbad_blocks += 1;
 
             // Just write 0s
-            output_file.write_all(&vec![0u8; file_block_size]).unwrap();
+            output_file.write_all(&vec![0u8; amount]).unwrap();
         }
     }
+
+    // Older versions of recover wrote the last block whole, so a resumed file can be too long
+    output_file.set_len(file_size as u64).unwrap();
+    println!("Recovered {file_size} bytes, {nbad_blocks} blocks are bad");
+
+    warnings::print_warning_summary();
 }
@@ -0,0 +1,316 @@
+use clap::Parser;
+use std::collections::{hash_map::Entry, HashMap};
+use szfs::{
+    cli,
+    diff::{self, ObjectChange, ObjectDiff, ObjectVersion},
+    dmu::{DNode, DNodeBase, ObjSet, ObjType},
+    reader, rewind,
+    zio::Vdevs,
+    *,
+};
+
+/// Lists what changed between two versions of a dataset, like zfs diff, but read only and without needing the pool to import
+/// The versions can be two snapshots (ex. tank/home@monday tank/home), or the same dataset in an older uberblock (--old-txg)
+/// - deleted, + created, M modified, R renamed (old path -> new path)
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// The full name of the old version, like zfs list shows it, ex. tank/home@monday
+    old: String,
+    /// The full name of the new version, the same as the old one by default (then --old-txg is needed)
+    new: Option<String>,
+    /// Open the old version from the uberblock with this txg, instead of the newest one
+    #[arg(long)]
+    old_txg: Option<u64>,
+    /// Also list the objects that aren't files or directories (ex. the master node or the delete queue), only filesystems have anything else
+    #[arg(long)]
+    all_objects: bool,
+}
+
+// A loop in the parents of a corrupted file would otherwise never end
+const MAX_PATH_DEPTH: usize = 256;
+
+// What is needed to find the paths and attributes of the files in a version of a filesystem
+struct Filesystem {
+    // None on datasets from before system attributes, their files have the old znode attributes which don't need it
+    system_attributes: Option<zpl::SystemAttributes>,
+    root_number: u64,
+    // The name of every entry of the directories that were looked up, by the object id of the directory and then of the entry
+    names: HashMap<u64, HashMap<u64, String>>,
+}
+
+impl Filesystem {
+    // Returns: None if the dataset is not a filesystem (ex. a volume) or its master node can't be read
+    fn open(dataset: &mut ObjSet, vdevs: &mut Vdevs) -> Option<Filesystem> {
+        let DNode::MasterNode(mut master_node) = dataset.get_dnode_at(1, vdevs)? else {
+            return None;
+        };
+        let master_node_zap_data = master_node.dump_zap_contents(vdevs)?;
+        let Some(zap::Value::U64(root_number)) = master_node_zap_data.get("ROOT") else {
+            return None;
+        };
+        let system_attributes = zpl::open_system_attributes(&master_node_zap_data, dataset, vdevs)
+            .ok()
+            .flatten();
+        Some(Filesystem {
+            system_attributes,
+            root_number: *root_number,
+            names: HashMap::new(),
+        })
+    }
+
+    fn get_attribute(&mut self, version: &ObjectVersion, name: &str) -> Option<u64> {
+        let attributes = zpl::parse_zpl_attributes(
+            self.system_attributes.as_mut(),
+            version.dnode.get_bonus_data(),
+            &version.bonus_type,
+        )
+        .ok()?;
+        match attributes.get(name) {
+            Some(zpl::Value::U64(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn get_name(
+        &mut self,
+        dataset: &mut ObjSet,
+        directory_id: u64,
+        object_id: u64,
+        vdevs: &mut Vdevs,
+    ) -> Option<String> {
+        if let Entry::Vacant(entry) = self.names.entry(directory_id) {
+            let (mut directory, ObjType::DirectoryContents) =
+                dataset.get_zap_dnode_at(directory_id as usize, vdevs)?
+            else {
+                return None;
+            };
+            let names = zpl::parse_directory_entries(&directory.dump_zap_contents(vdevs)?)
+                .into_iter()
+                .map(|entry| (entry.object_id, entry.name))
+                .collect();
+            entry.insert(names);
+        }
+        self.names.get(&directory_id)?.get(&object_id).cloned()
+    }
+
+    // Returns: The path of the object from the root of the dataset, by following the parents in the attributes and looking up the name in every one of them
+    // NOTE: A file with more than one hard link gets the path of the link its parent attribute points to
+    // Source: https://github.com/openzfs/zfs/blob/master/module/os/linux/zfs/zfs_znode_os.c (zfs_obj_to_path)
+    fn get_path(
+        &mut self,
+        dataset: &mut ObjSet,
+        object_id: u64,
+        vdevs: &mut Vdevs,
+    ) -> Option<String> {
+        let mut components = Vec::new();
+        let mut current_id = object_id;
+        for _ in 0..MAX_PATH_DEPTH {
+            if current_id == self.root_number {
+                components.reverse();
+                return Some(format!("/{}", components.join("/")));
+            }
+            let raw_dnode = dataset.get_raw_dnode_at(current_id as usize, vdevs)?;
+            let (dnode, obj_type, bonus_type) = DNodeBase::from_slice_le(&raw_dnode)?;
+            let parent_id = self.get_attribute(
+                &ObjectVersion {
+                    dnode,
+                    obj_type,
+                    bonus_type,
+                },
+                "ZPL_PARENT",
+            )?;
+            components.push(self.get_name(dataset, parent_id, current_id, vdevs)?);
+            current_id = parent_id;
+        }
+        None
+    }
+}
+
+// One side of the diff
+struct Side {
+    dataset: ObjSet,
+    filesystem: Option<Filesystem>,
+}
+
+impl Side {
+    fn describe(&mut self, object_id: u64, version: &ObjectVersion, vdevs: &mut Vdevs) -> String {
+        self.filesystem
+            .as_mut()
+            .and_then(|filesystem| filesystem.get_path(&mut self.dataset, object_id, vdevs))
+            .unwrap_or_else(|| format!("<object {object_id} ({:?})>", version.obj_type))
+    }
+
+    fn get_generation(&mut self, version: &ObjectVersion) -> Option<u64> {
+        self.filesystem.as_mut()?.get_attribute(version, "ZPL_GEN")
+    }
+}
+
+#[derive(Debug, Default)]
+struct DiffReport {
+    created: usize,
+    deleted: usize,
+    modified: usize,
+    renamed: usize,
+}
+
+fn print_object_diff(
+    object_diff: &mut ObjectDiff,
+    old: &mut Side,
+    new: &mut Side,
+    report: &mut DiffReport,
+    vdevs: &mut Vdevs,
+) {
+    let object_id = object_diff.object_id;
+    let mut change = object_diff.change;
+    // The type is the same, but a zpl file that was deleted and made again has another generation
+    if let (ObjectChange::Modified, Some(old_version), Some(new_version)) =
+        (change, &object_diff.old, &object_diff.new)
+    {
+        let old_generation = old.get_generation(old_version);
+        let new_generation = new.get_generation(new_version);
+        if old_generation.is_some() && new_generation.is_some() && old_generation != new_generation
+        {
+            change = ObjectChange::Replaced;
+        }
+    }
+
+    match change {
+        ObjectChange::Created => {
+            let description = new.describe(object_id, object_diff.new.as_ref().unwrap(), vdevs);
+            println!("+\t{description}");
+            report.created += 1;
+        }
+        ObjectChange::Deleted => {
+            let description = old.describe(object_id, object_diff.old.as_ref().unwrap(), vdevs);
+            println!("-\t{description}");
+            report.deleted += 1;
+        }
+        // Like zfs diff, a new object with an old id is a deleted one and a created one
+        ObjectChange::Replaced => {
+            let old_description = old.describe(object_id, object_diff.old.as_ref().unwrap(), vdevs);
+            let new_description = new.describe(object_id, object_diff.new.as_ref().unwrap(), vdevs);
+            println!("-\t{old_description}");
+            println!("+\t{new_description}");
+            report.deleted += 1;
+            report.created += 1;
+        }
+        ObjectChange::Modified => {
+            let old_description = old.describe(object_id, object_diff.old.as_ref().unwrap(), vdevs);
+            let new_description = new.describe(object_id, object_diff.new.as_ref().unwrap(), vdevs);
+            let data_diff = object_diff.diff_data(vdevs).unwrap_or_default();
+            let mut details = Vec::new();
+            if !data_diff.changed_block_ids.is_empty() {
+                details.push(format!(
+                    "{} blocks changed",
+                    data_diff.changed_block_ids.len()
+                ));
+            }
+            if !data_diff.unknown_block_ids.is_empty() {
+                details.push(String::from("some blocks can't be read"));
+            }
+            if object_diff.is_bonus_changed() {
+                details.push(String::from("attributes changed"));
+            }
+            let details = if details.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", details.join(", "))
+            };
+
+            if old_description != new_description {
+                println!("R\t{old_description} -> {new_description}{details}");
+                report.renamed += 1;
+            } else {
+                println!("M\t{new_description}{details}");
+            }
+            report.modified += 1;
+        }
+    }
+}
+
+fn open_side(mos: &mut ObjSet, pool_name: &str, dataset_name: &str, vdevs: &mut Vdevs) -> Side {
+    let mut dataset = reader::open_dataset(mos, pool_name, dataset_name, vdevs)
+        .unwrap_or_else(|err| cli::exit_with_error(err));
+    let filesystem = Filesystem::open(&mut dataset, vdevs);
+    Side {
+        dataset,
+        filesystem,
+    }
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let pool_name = match pool.name_value_pairs.get("name") {
+        Some(nvlist::Value::String(name)) => name.clone(),
+        _ => cli::exit_with_error("The pool config has no name"),
+    };
+    let new_name = args.new.clone().unwrap_or_else(|| args.old.clone());
+    if new_name == args.old && args.old_txg.is_none() {
+        cli::exit_with_error(
+            "Comparing a dataset with itself, give another dataset or an --old-txg",
+        );
+    }
+
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let mut old_uberblocks = pool.collect_uberblocks();
+    old_uberblocks.retain(|ub| Some(ub.txg) == args.old_txg);
+    let mut vdev_raidz = pool.get_raidz();
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let Some((mut mos, mos_selection)) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+    else {
+        cli::exit_with_error("There is no uberblock whose MOS can be read");
+    };
+    mos_selection.print();
+    let mut old = match args.old_txg {
+        Some(old_txg) => {
+            let Some((mut old_mos, _)) = rewind::open_newest_mos(&mut old_uberblocks, &mut vdevs)
+            else {
+                cli::exit_with_error(format!(
+                    "There is no uberblock with txg {old_txg} whose MOS can be read"
+                ));
+            };
+            open_side(&mut old_mos, &pool_name, &args.old, &mut vdevs)
+        }
+        None => open_side(&mut mos, &pool_name, &args.old, &mut vdevs),
+    };
+    let mut new = open_side(&mut mos, &pool_name, &new_name, &mut vdevs);
+    let is_filesystem = old.filesystem.is_some() && new.filesystem.is_some();
+
+    let mut objset_diff = diff::diff_objsets(&mut old.dataset, &mut new.dataset, &mut vdevs)
+        .unwrap_or_else(|()| {
+            cli::exit_with_error("The meta dnodes of the two versions have different block sizes, at least one of them is corrupt")
+        });
+
+    let mut report = DiffReport::default();
+    for object_diff in objset_diff.objects.iter_mut() {
+        let is_file_or_directory = matches!(
+            object_diff.get_obj_type(),
+            Some(ObjType::PlainFileContents | ObjType::DirectoryContents)
+        );
+        if is_filesystem && !is_file_or_directory && !args.all_objects {
+            continue;
+        }
+        print_object_diff(object_diff, &mut old, &mut new, &mut report, &mut vdevs);
+    }
+
+    println!(
+        "{} created, {} deleted, {} modified ({} of them renamed)",
+        report.created, report.deleted, report.modified, report.renamed
+    );
+    if !objset_diff.unknown_object_ids.is_empty() {
+        let nunknown = objset_diff
+            .unknown_object_ids
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum::<u64>();
+        println!("{YELLOW}Warning{WHITE}: Up to {nunknown} objects couldn't be compared, the parts of the meta dnode they are in can't be read in one of the versions: {:?}", objset_diff.unknown_object_ids);
+    }
+
+    warnings::print_warning_summary();
+}
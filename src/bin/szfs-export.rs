@@ -0,0 +1,472 @@
+use clap::Parser;
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs::{self, File, FileTimes, OpenOptions, Permissions},
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use szfs::{
+    cli,
+    dmu::{self, BonusType, DNode, DNodeBase, DNodeDirectoryContents, DNodePlainFileContents},
+    reader,
+    recovery::select::FileAttributes,
+    rewind,
+    zio::Vdevs,
+    *,
+};
+
+/// Copies a whole dataset (or snapshot) out of the pool, with its directory structure, symlinks, hard links, permissions and timestamps
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// The full name of the dataset, like zfs list shows it, ex. tank/home or tank/home@monday
+    dataset: String,
+    /// Where to copy the dataset to, it's created if it doesn't exist
+    destination: PathBuf,
+    /// Also set the owner and group of everything like they are in the dataset, this usually needs root
+    #[arg(long)]
+    preserve_owner: bool,
+    /// How many blocks of a file to prefetch ahead of the one being copied, so the disks read them in big batches instead of seeking for every block, 0 to turn it off
+    #[arg(long, value_name = "NBLOCKS", default_value_t = 32)]
+    read_ahead: usize,
+}
+
+// The file type bits of the mode
+// Source: https://github.com/openzfs/zfs/blob/master/include/os/linux/spl/sys/stat.h (the S_IF* values are the same as linux's)
+const S_IFMT: u64 = 0o170000;
+const S_IFDIR: u64 = 0o040000;
+const S_IFREG: u64 = 0o100000;
+const S_IFLNK: u64 = 0o120000;
+
+// What is restored of a file, directory or symlink
+struct Attributes {
+    mode: u64,
+    size: u64,
+    uid: u64,
+    gid: u64,
+    atime: SystemTime,
+    mtime: SystemTime,
+    // Only set if the target is in the attributes (or right after the old znode attributes), otherwise it's in the data of the symlink
+    symlink_target: Option<Vec<u8>>,
+}
+
+// ZPL timestamps are [seconds, nanoseconds] with signed seconds
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h (ZFS_TIME_DECODE)
+fn parse_timestamp(value: Option<&zpl::Value>) -> Option<SystemTime> {
+    let Some(zpl::Value::U64Array(value)) = value else {
+        return None;
+    };
+    let (seconds, nanoseconds) = (*value.first()? as i64, *value.get(1)?);
+    let since_epoch = Duration::from_secs(seconds.unsigned_abs())
+        .checked_add(Duration::from_nanos(nanoseconds.min(999_999_999)))?;
+    if seconds >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(since_epoch)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(since_epoch)
+    }
+}
+
+fn timestamp_from_seconds(seconds: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH
+        .checked_add(Duration::from_secs(seconds))
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+#[derive(Debug, Default)]
+struct ExportReport {
+    files: usize,
+    directories: usize,
+    symlinks: usize,
+    hard_links: usize,
+    // Fifos, sockets and devices
+    skipped: usize,
+    // Files with blocks that couldn't be read, they are zeros in the copy
+    damaged_files: usize,
+    errors: usize,
+}
+
+struct Exporter {
+    dataset: dmu::ObjSet,
+    // None on datasets from before system attributes, their files have the old znode attributes which don't need it
+    system_attributes: Option<zpl::SystemAttributes>,
+    preserve_owner: bool,
+    read_ahead: usize,
+    // Where every file with more than one link was copied to, so the other links become hard links to it
+    exported_files: HashMap<u64, PathBuf>,
+    // A corrupted directory could contain one of its parents
+    visited_directories: HashSet<u64>,
+    report: ExportReport,
+}
+
+impl Exporter {
+    fn read_attributes(&mut self, dnode: &DNodeBase, bonus_type: &BonusType) -> Option<Attributes> {
+        if let Ok(values) = zpl::parse_zpl_attributes(
+            self.system_attributes.as_mut(),
+            dnode.get_bonus_data(),
+            bonus_type,
+        ) {
+            let get_u64 = |name: &str| match values.get(name) {
+                Some(zpl::Value::U64(value)) => Some(*value),
+                _ => None,
+            };
+            if let Some(mode) = get_u64("ZPL_MODE") {
+                return Some(Attributes {
+                    mode,
+                    size: get_u64("ZPL_SIZE").unwrap_or(0),
+                    uid: get_u64("ZPL_UID").unwrap_or(0),
+                    gid: get_u64("ZPL_GID").unwrap_or(0),
+                    atime: parse_timestamp(values.get("ZPL_ATIME"))
+                        .unwrap_or(SystemTime::UNIX_EPOCH),
+                    mtime: parse_timestamp(values.get("ZPL_MTIME"))
+                        .unwrap_or(SystemTime::UNIX_EPOCH),
+                    symlink_target: match values.get("ZPL_SYMLINK") {
+                        Some(zpl::Value::Bytes(target)) => Some(target.clone()),
+                        _ => None,
+                    },
+                });
+            }
+        }
+
+        let guessed = FileAttributes::guess_from_bonus_data(dnode.get_bonus_data(), bonus_type)?;
+        Some(Attributes {
+            mode: guessed.mode,
+            size: guessed.size,
+            uid: guessed.uid,
+            gid: guessed.gid,
+            atime: timestamp_from_seconds(guessed.atime),
+            mtime: timestamp_from_seconds(guessed.mtime),
+            symlink_target: None,
+        })
+    }
+
+    // The times are set before the permissions, as the permissions might not allow opening it anymore
+    // and the owner before the permissions, as changing the owner clears the setuid and setgid bits
+    // NOTE: The times of symlinks are not restored, the standard library can't set them without following the link
+    fn apply_attributes(&mut self, path: &Path, attributes: &Attributes, is_symlink: bool) {
+        use szfs::ansi_color::*;
+        if !is_symlink {
+            let times = FileTimes::new()
+                .set_accessed(attributes.atime)
+                .set_modified(attributes.mtime);
+            if let Err(err) = File::open(path).and_then(|file| file.set_times(times)) {
+                println!("{YELLOW}Warning{WHITE}: The times of {path:?} can't be set: {err}");
+            }
+        }
+
+        if self.preserve_owner {
+            let (uid, gid) = (Some(attributes.uid as u32), Some(attributes.gid as u32));
+            let result = if is_symlink {
+                std::os::unix::fs::lchown(path, uid, gid)
+            } else {
+                std::os::unix::fs::chown(path, uid, gid)
+            };
+            if let Err(err) = result {
+                println!("{YELLOW}Warning{WHITE}: The owner of {path:?} can't be set: {err}");
+            }
+        }
+
+        // Symlinks don't have permissions of their own on linux
+        if !is_symlink {
+            let permissions = Permissions::from_mode((attributes.mode & 0o7777) as u32);
+            if let Err(err) = fs::set_permissions(path, permissions) {
+                println!("{YELLOW}Warning{WHITE}: The permissions of {path:?} can't be set: {err}");
+            }
+        }
+    }
+
+    fn export_directory(
+        &mut self,
+        object_id: u64,
+        mut directory: DNodeDirectoryContents,
+        path: &Path,
+        vdevs: &mut Vdevs,
+    ) {
+        use szfs::ansi_color::*;
+        if !self.visited_directories.insert(object_id) {
+            println!("{YELLOW}Warning{WHITE}: Directory {object_id} ({path:?}) was already copied, the directory tree has a loop, skipping it!");
+            self.report.errors += 1;
+            return;
+        }
+        if let Err(err) = fs::create_dir_all(path) {
+            println!("{RED}Important{WHITE}: Directory {path:?} can't be created: {err}, skipping everything in it!");
+            self.report.errors += 1;
+            return;
+        }
+        self.report.directories += 1;
+
+        match directory.dump_zap_contents(vdevs) {
+            Some(directory_zap_data) => {
+                for entry in zpl::parse_directory_entries(&directory_zap_data) {
+                    // Names come from the disk, so they are checked before being used as a path
+                    if entry.name.is_empty()
+                        || entry.name == "."
+                        || entry.name == ".."
+                        || entry.name.contains('/')
+                    {
+                        println!("{YELLOW}Warning{WHITE}: Skipping entry {:?} of {path:?}, it's not a valid name!", entry.name);
+                        self.report.errors += 1;
+                        continue;
+                    }
+                    self.export_entry(&entry, &path.join(&entry.name), vdevs);
+                }
+            }
+            None => {
+                println!("{RED}Important{WHITE}: The entries of directory {object_id} ({path:?}) can't be read!");
+                self.report.errors += 1;
+            }
+        }
+
+        // Done after the entries, as adding them changes the mtime of the directory
+        if let Some(attributes) = self.read_attributes(&directory.0, &directory.1) {
+            self.apply_attributes(path, &attributes, false);
+        }
+    }
+
+    fn export_entry(&mut self, entry: &zpl::DirectoryEntry, path: &Path, vdevs: &mut Vdevs) {
+        use szfs::ansi_color::*;
+        match self.dataset.get_dnode_at(entry.object_id as usize, vdevs) {
+            Some(DNode::DirectoryContents(directory)) => {
+                self.export_directory(entry.object_id, directory, path, vdevs);
+            }
+            Some(DNode::PlainFileContents(mut file)) => {
+                let Some(attributes) = self.read_attributes(&file.0, &file.1) else {
+                    println!("{RED}Important{WHITE}: The attributes of {path:?} (object {}) can't be read, skipping it!", entry.object_id);
+                    self.report.errors += 1;
+                    return;
+                };
+                match attributes.mode & S_IFMT {
+                    S_IFREG => {
+                        self.export_file(entry.object_id, &mut file, &attributes, path, vdevs)
+                    }
+                    S_IFLNK => self.export_symlink(&mut file, &attributes, path, vdevs),
+                    // Directories are directory contents objects, so this only happens if the mode is corrupted
+                    S_IFDIR => {
+                        println!("{YELLOW}Warning{WHITE}: {path:?} (object {}) is a file with the mode of a directory, skipping it!", entry.object_id);
+                        self.report.errors += 1;
+                    }
+                    _ => {
+                        println!(
+                            "{CYAN}Info{WHITE}: Skipping {path:?}, it's a {}",
+                            entry.kind.get_name()
+                        );
+                        self.report.skipped += 1;
+                    }
+                }
+            }
+            Some(dnode) => {
+                println!("{YELLOW}Warning{WHITE}: {path:?} is object {} which is a {} instead of a file or directory, skipping it!", entry.object_id, dnode.get_obj_type().get_name());
+                self.report.errors += 1;
+            }
+            None => {
+                println!(
+                    "{RED}Important{WHITE}: {path:?} (object {}) can't be read!",
+                    entry.object_id
+                );
+                self.report.errors += 1;
+            }
+        }
+    }
+
+    fn export_file(
+        &mut self,
+        object_id: u64,
+        file: &mut DNodePlainFileContents,
+        attributes: &Attributes,
+        path: &Path,
+        vdevs: &mut Vdevs,
+    ) {
+        use szfs::ansi_color::*;
+        if let Some(first_link) = self.exported_files.get(&object_id) {
+            // Re-running over an earlier copy would otherwise fail because the link already exists
+            let _ = fs::remove_file(path);
+            match fs::hard_link(first_link, path) {
+                Ok(()) => self.report.hard_links += 1,
+                Err(err) => {
+                    println!(
+                        "{YELLOW}Warning{WHITE}: {path:?} can't be linked to {first_link:?}: {err}"
+                    );
+                    self.report.errors += 1;
+                }
+            }
+            return;
+        }
+
+        let mut output = match OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+        {
+            Ok(output) => output,
+            Err(err) => {
+                println!("{RED}Important{WHITE}: {path:?} can't be created: {err}");
+                self.report.errors += 1;
+                return;
+            }
+        };
+        let options = dmu::ExtractOptions {
+            size: Some(attributes.size),
+            sparse: true,
+            read_ahead: self.read_ahead,
+            ..Default::default()
+        };
+        match file.extract_to(&mut output, &options, vdevs) {
+            Ok(report) => {
+                if !report.bad_ranges.is_empty() {
+                    println!("{YELLOW}Warning{WHITE}: {} parts of {path:?} can't be read, they are zeros in the copy: {:?}", report.bad_ranges.len(), report.bad_ranges);
+                    self.report.damaged_files += 1;
+                }
+            }
+            Err(()) => {
+                println!("{RED}Important{WHITE}: {path:?} can't be written!");
+                self.report.errors += 1;
+                return;
+            }
+        }
+        drop(output);
+
+        self.report.files += 1;
+        self.exported_files.insert(object_id, path.to_path_buf());
+        self.apply_attributes(path, attributes, false);
+    }
+
+    // Returns: The target of the symlink, from wherever the version of zfs that made it put it
+    fn read_symlink_target(
+        file: &mut DNodePlainFileContents,
+        attributes: &Attributes,
+        vdevs: &mut Vdevs,
+    ) -> Option<Vec<u8>> {
+        if let Some(target) = &attributes.symlink_target {
+            return Some(target.clone());
+        }
+        let size = attributes.size as usize;
+        // Long targets are in the data of the symlink, and they always fit in the first block
+        file.0
+            .read_block_allow_embedded_size(0, vdevs)
+            .ok()?
+            .get(..size)
+            .map(|target| target.to_vec())
+    }
+
+    fn export_symlink(
+        &mut self,
+        file: &mut DNodePlainFileContents,
+        attributes: &Attributes,
+        path: &Path,
+        vdevs: &mut Vdevs,
+    ) {
+        use szfs::ansi_color::*;
+        let Some(target) = Self::read_symlink_target(file, attributes, vdevs) else {
+            println!("{RED}Important{WHITE}: The target of symlink {path:?} can't be read!");
+            self.report.errors += 1;
+            return;
+        };
+        // Re-running over an earlier copy would otherwise fail because the symlink already exists
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink()) {
+            let _ = fs::remove_file(path);
+        }
+        if let Err(err) = std::os::unix::fs::symlink(OsStr::from_bytes(&target), path) {
+            println!("{RED}Important{WHITE}: Symlink {path:?} can't be created: {err}");
+            self.report.errors += 1;
+            return;
+        }
+        self.report.symlinks += 1;
+        self.apply_attributes(path, attributes, true);
+    }
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let pool_name = match pool.name_value_pairs.get("name") {
+        Some(nvlist::Value::String(name)) => name.clone(),
+        _ => cli::exit_with_error("The pool config has no name"),
+    };
+    let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let Some((mut mos, _)) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs) else {
+        cli::exit_with_error("There is no uberblock whose MOS can be read");
+    };
+    let mut dataset = reader::open_dataset(&mut mos, &pool_name, &args.dataset, &mut vdevs)
+        .unwrap_or_else(|err| cli::exit_with_error(err));
+
+    let Some(DNode::MasterNode(mut master_node)) = dataset.get_dnode_at(1, &mut vdevs) else {
+        cli::exit_with_error(format!(
+            "{} has no master node, it's probably not a filesystem (ex. a volume)",
+            args.dataset
+        ));
+    };
+    let Some(master_node_zap_data) = master_node.dump_zap_contents(&mut vdevs) else {
+        cli::exit_with_error("The master node can't be read");
+    };
+    let Some(zap::Value::U64(root_number)) = master_node_zap_data.get("ROOT") else {
+        cli::exit_with_error("The master node has no root directory");
+    };
+    let root_number = *root_number;
+
+    // Datasets made before system attributes (zpl version 5) don't have SA_ATTRS
+    let system_attributes = match master_node_zap_data.get("SA_ATTRS") {
+        Some(zap::Value::U64(system_attributes_info_number)) => {
+            match zpl::SystemAttributes::from_attributes_node_number(
+                *system_attributes_info_number as usize,
+                &mut dataset,
+                &mut vdevs,
+            ) {
+                Ok(system_attributes) => Some(system_attributes),
+                Err(err) => {
+                    println!("{YELLOW}Warning{WHITE}: The system attribute registry can't be read ({err:?}), the attributes will be guessed!");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let Some(DNode::DirectoryContents(root_directory)) =
+        dataset.get_dnode_at(root_number as usize, &mut vdevs)
+    else {
+        cli::exit_with_error(format!(
+            "The root directory (object {root_number}) can't be read"
+        ));
+    };
+
+    println!("Copying {} to {:?}", args.dataset, args.destination);
+    let mut exporter = Exporter {
+        dataset,
+        system_attributes,
+        preserve_owner: args.preserve_owner,
+        read_ahead: args.read_ahead,
+        exported_files: HashMap::new(),
+        visited_directories: HashSet::new(),
+        report: ExportReport::default(),
+    };
+    exporter.export_directory(root_number, root_directory, &args.destination, &mut vdevs);
+
+    let report = exporter.report;
+    println!(
+        "Copied {} files, {} directories, {} symlinks and {} hard links, skipped {} special files",
+        report.files, report.directories, report.symlinks, report.hard_links, report.skipped
+    );
+    if report.damaged_files != 0 {
+        println!(
+            "{YELLOW}Warning{WHITE}: {} files are missing parts, they are zeros in the copy",
+            report.damaged_files
+        );
+    }
+    if report.errors != 0 {
+        println!(
+            "{RED}Important{WHITE}: {} things couldn't be copied, see above",
+            report.errors
+        );
+    }
+
+    warnings::print_warning_summary();
+}
@@ -0,0 +1,238 @@
+use clap::{Parser, Subcommand};
+use std::{collections::HashMap, net::TcpListener};
+use szfs::{
+    cli,
+    dmu::{DNodeBase, IndirectTreeCursor, ObjType},
+    nbd, reader,
+    recovery::{
+        fragment::FragmentData,
+        select::{FileAttributes, FileSelection},
+    },
+    rewind,
+    zio::Vdevs,
+    *,
+};
+
+/// Serves a zvol, or a file undelete found, read only over the network block device protocol, so the filesystem or vm image in it can be used without copying it out first
+/// Connect to it with ex. nbd-client -N <name> 127.0.0.1 /dev/nbd0 (the name is printed on start) and then mount /dev/nbd0 read only
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    #[command(subcommand)]
+    source: Source,
+    #[arg(long, default_value = "127.0.0.1")]
+    address: String,
+    #[arg(long, default_value_t = nbd::DEFAULT_PORT)]
+    port: u16,
+    /// Send zeros for blocks that can't be read instead of an io error, some filesystems refuse to mount at all after an io error
+    #[arg(long)]
+    zero_bad_blocks: bool,
+    /// How many blocks to prefetch ahead of the one being read when the reads are sequential, 0 to turn it off
+    #[arg(long, value_name = "NBLOCKS", default_value_t = 32)]
+    read_ahead: usize,
+}
+
+#[derive(Subcommand)]
+enum Source {
+    /// A zvol, by its full name like zfs list shows it, ex. tank/vm-disk or tank/vm-disk@monday
+    Volume { dataset: String },
+    /// The biggest version of a file undelete found that matches the selection, the versions are not merged like recover does
+    File {
+        #[command(flatten)]
+        selection: FileSelection,
+    },
+}
+
+// Reads the data of an object by offset, like DNodeBase::read, but holes and anything past the end of the data are zeros
+// which is what a zvol or a sparse file is supposed to read as
+struct ObjectReader {
+    dnode: DNodeBase,
+    cursor: IndirectTreeCursor,
+    zero_bad_blocks: bool,
+    nbad_blocks: usize,
+}
+
+impl ObjectReader {
+    fn read_block(&mut self, block_id: usize, vdevs: &mut Vdevs) -> Result<Vec<u8>, ()> {
+        use szfs::ansi_color::*;
+        let block_size = self.dnode.parse_data_block_size();
+        if block_id * block_size >= self.dnode.get_data_size() {
+            return Ok(vec![0u8; block_size]);
+        }
+        match self.cursor.read_block_or_hole(&self.dnode, block_id, vdevs) {
+            Ok(Some(block_data)) => Ok(block_data),
+            Ok(None) => Ok(vec![0u8; block_size]),
+            Err(()) => {
+                self.nbad_blocks += 1;
+                if !self.zero_bad_blocks {
+                    println!("{YELLOW}Warning{WHITE}: Block {block_id} can't be read, the read failed with an io error!");
+                    return Err(());
+                }
+                println!(
+                    "{YELLOW}Warning{WHITE}: Block {block_id} can't be read, sent zeros instead!"
+                );
+                Ok(vec![0u8; block_size])
+            }
+        }
+    }
+
+    fn read(&mut self, offset: u64, size: usize, vdevs: &mut Vdevs) -> Result<Vec<u8>, ()> {
+        let block_size = self.dnode.parse_data_block_size() as u64;
+        let mut result = Vec::with_capacity(size);
+        let mut position = offset;
+        while result.len() < size {
+            let mut block_data = self.read_block((position / block_size) as usize, vdevs)?;
+            // A block can come back shorter than the block size (ex. an embedded one), the rest of it is zeros
+            block_data.resize(block_size as usize, 0);
+            let start = (position % block_size) as usize;
+            let amount = (block_data.len() - start).min(size - result.len());
+            result.extend_from_slice(&block_data[start..start + amount]);
+            position += amount as u64;
+        }
+        Ok(result)
+    }
+}
+
+// Returns: The zvol object and the size of the volume
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zvol_impl.h (ZVOL_OBJ, ZVOL_ZAP_OBJ)
+fn open_volume(dataset: &mut dmu::ObjSet, vdevs: &mut Vdevs) -> Result<(DNodeBase, u64), String> {
+    let Some((zvol, ObjType::ZVol)) = dataset.get_dnode_base_at(1, vdevs) else {
+        return Err(String::from(
+            "The dataset has no zvol object, it's probably not a volume (ex. a filesystem)",
+        ));
+    };
+    let Some((mut properties, ObjType::ZVolProperties)) = dataset.get_zap_dnode_at(2, vdevs) else {
+        return Err(String::from("The dataset has no zvol properties object"));
+    };
+    let Some(properties_zap_data) = properties.dump_zap_contents(vdevs) else {
+        return Err(String::from("The zvol properties can't be read"));
+    };
+    let Some(zap::Value::U64(size)) = properties_zap_data.get("size") else {
+        return Err(String::from("The zvol properties have no size"));
+    };
+    Ok((zvol, *size))
+}
+
+// Returns: The dnode of the biggest recovered file that matches the selection and the size of the file
+fn open_recovered_file(
+    pool_args: &cli::PoolArgs,
+    selection: &FileSelection,
+) -> Result<(DNodeBase, u64), String> {
+    use szfs::ansi_color::*;
+    let mut recovered_fragments = recovery::select::read_selected_fragments(pool_args, selection)?;
+    if recovered_fragments.len() > 1 {
+        println!("{YELLOW}Warning{WHITE}: {} recovered files match the selection, serving the biggest one, use recover to merge them!", recovered_fragments.len());
+    }
+    if recovered_fragments.is_empty() {
+        return Err(String::from("No recovered file matches the selection"));
+    }
+
+    let (hash, fragment) = recovered_fragments.swap_remove(0);
+    let FragmentData::FileDNode(file) = fragment.data else {
+        unreachable!();
+    };
+    let data_size = file.0.get_data_size() as u64;
+    let size = match FileAttributes::guess_from_dnode(&file) {
+        Some(attributes) if attributes.size <= data_size => attributes.size,
+        _ => {
+            println!("{YELLOW}Warning{WHITE}: Couldn't figure out the size of the file, using the size of its data!");
+            data_size
+        }
+    };
+    println!("{CYAN}Info{WHITE}: Serving recovered file {hash:?}");
+    Ok((file.0, size))
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+
+    let (export_name, (dnode, size)) = match &args.source {
+        Source::Volume { dataset } => {
+            let pool_name = match pool.name_value_pairs.get("name") {
+                Some(nvlist::Value::String(name)) => name.clone(),
+                _ => cli::exit_with_error("The pool config has no name"),
+            };
+            let mut uberblocks = pool_args.filter_uberblocks(pool.collect_uberblocks());
+            let mut vdev_raidz = pool.get_raidz();
+            let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+            vdevs.insert(0usize, &mut vdev_raidz);
+
+            let Some((mut mos, _)) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs) else {
+                cli::exit_with_error("There is no uberblock whose MOS can be read");
+            };
+            let mut objset = reader::open_dataset(&mut mos, &pool_name, dataset, &mut vdevs)
+                .unwrap_or_else(|err| cli::exit_with_error(err));
+            let volume = open_volume(&mut objset, &mut vdevs)
+                .unwrap_or_else(|err| cli::exit_with_error(err));
+            (dataset.clone(), volume)
+        }
+        Source::File { selection } => (
+            String::from("recovered-file"),
+            open_recovered_file(&pool_args, selection)
+                .unwrap_or_else(|err| cli::exit_with_error(err)),
+        ),
+    };
+    let block_size = dnode.parse_data_block_size();
+    if block_size == 0 {
+        cli::exit_with_error("The object has a block size of 0");
+    }
+
+    let mut vdev_raidz = pool.get_raidz();
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let export = nbd::Export {
+        name: export_name,
+        size,
+        preferred_block_size: block_size as u32,
+    };
+    let mut object_reader = ObjectReader {
+        dnode,
+        cursor: IndirectTreeCursor::with_read_ahead(args.read_ahead),
+        zero_bad_blocks: args.zero_bad_blocks,
+        nbad_blocks: 0,
+    };
+
+    let listener = TcpListener::bind((args.address.as_str(), args.port)).unwrap_or_else(|err| {
+        cli::exit_with_error(format!(
+            "Can't listen on {}:{}: {err}",
+            args.address, args.port
+        ))
+    });
+    println!(
+        "Serving {:?} ({size} bytes in blocks of {block_size} bytes) read only on {}:{}",
+        export.name, args.address, args.port
+    );
+
+    // One client at a time, the kernel only opens one connection unless the server says it can handle more
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("{YELLOW}Warning{WHITE}: Couldn't accept a connection: {err}");
+                continue;
+            }
+        };
+        let peer = stream.peer_addr().ok();
+        println!("{CYAN}Info{WHITE}: Client {peer:?} connected");
+        let _ = stream.set_nodelay(true);
+        match nbd::serve(&mut stream, &export, |offset, size| {
+            object_reader.read(offset, size, &mut vdevs)
+        }) {
+            Ok(report) => println!(
+                "{CYAN}Info{WHITE}: Client {peer:?} disconnected after {} reads ({} bytes, {} failed), {} write requests were refused",
+                report.reads, report.bytes_read, report.failed_reads, report.refused_requests
+            ),
+            Err(err) => println!("{YELLOW}Warning{WHITE}: Lost the connection to client {peer:?}: {err}"),
+        }
+        if object_reader.nbad_blocks != 0 {
+            println!(
+                "{YELLOW}Warning{WHITE}: {} blocks couldn't be read so far",
+                object_reader.nbad_blocks
+            );
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use clap::{Parser, Subcommand};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufReader, BufWriter},
+    path::PathBuf,
+};
+
+use szfs::binpatch::{self, PatchReader};
+
+/// Applies or checks a binpatch, like the ones recover-zil and surgeon write
+#[derive(Parser)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Writes the entries of the patch into the target, entries with a bad checksum are skipped
+    Apply {
+        patch_path: PathBuf,
+        target_path: PathBuf,
+    },
+    /// Only checks the checksums of the entries
+    Check { patch_path: PathBuf },
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let (patch_path, target_path) = match Args::parse().command {
+        Command::Apply {
+            patch_path,
+            target_path,
+        } => (patch_path, Some(target_path)),
+        Command::Check { patch_path } => (patch_path, None),
+    };
+
+    let patch = File::open(patch_path).expect("Patch should be able to be opened!");
+    let mut patch = PatchReader::new(BufReader::new(patch))
+        .expect("Patch was made by a newer version of szfs!");
+    if patch.get_version() == 0 {
+        println!(
+            "{YELLOW}Warning{WHITE}: This is an old patch without checksums, it can't be checked!"
+        );
+    }
+
+    let dry_run = target_path.is_none();
+    let report = if let Some(target_path) = target_path {
+        let target = OpenOptions::new()
+            .write(true)
+            .create(false)
+            .open(target_path)
+            .expect("Target should be able to be opened!");
+        binpatch::apply_patch(&mut patch, &mut BufWriter::new(target), false)
+    } else {
+        binpatch::apply_patch(&mut patch, &mut io::Cursor::new(Vec::new()), true)
+    }
+    .expect("Writing to the target should work!");
+
+    for (target_offset, size) in report.bad_entries.iter() {
+        println!("{YELLOW}Warning{WHITE}: Entry at target offset {target_offset} ({size} bytes) has a bad checksum, it was skipped!");
+    }
+    if report.truncated {
+        println!("{YELLOW}Warning{WHITE}: The patch is truncated, the last entry is missing!");
+    }
+    println!(
+        "{} {} entries ({} bytes), {} bad entries",
+        if dry_run { "Checked" } else { "Applied" },
+        report.entries_applied,
+        report.bytes_written,
+        report.bad_entries.len()
+    );
+}
@@ -1,70 +1,18 @@
-use serde::{Deserialize, Serialize};
+use clap::Parser;
 use std::{
-    cmp::Reverse,
     collections::{HashMap, HashSet},
-    env,
-    fmt::Debug,
     fs::File,
 };
 use szfs::{
-    dmu::{DNodeDirectoryContents, DNodePlainFileContents, ObjSet},
+    cli,
+    recovery::{
+        fragment::{Fragment, FragmentData},
+        select::{FileAttributes, FileSelection},
+    },
     zio::Vdevs,
     *,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
-struct IndirectBlock {
-    pub bps: Vec<Option<zio::BlockPointer>>,
-}
-
-#[derive(Serialize, Deserialize)]
-enum FragmentData {
-    FileDNode(DNodePlainFileContents),
-    DirectoryDNode(DNodeDirectoryContents, Vec<String>),
-    ObjSetDNode(ObjSet),
-    IndirectBlock(IndirectBlock),
-}
-
-impl Debug for FragmentData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            FragmentData::FileDNode(_) => write!(f, "File"),
-            FragmentData::DirectoryDNode(_, _) => write!(f, "Dir"),
-            FragmentData::ObjSetDNode(_) => write!(f, "ObjSet"),
-            FragmentData::IndirectBlock(_) => write!(f, "Indirect"),
-        }?;
-
-        Ok(())
-    }
-}
-
-#[derive(Serialize, Deserialize)]
-struct Fragment {
-    data: FragmentData,
-    children: HashSet<[u64; 4]>,
-}
-
-impl Debug for Fragment {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.data)?;
-        write!(f, "(")?;
-        for child in self.children.iter() {
-            write!(f, "{:?}, ", child[0])?;
-        }
-        write!(f, ")")?;
-        Ok(())
-    }
-}
-
-impl From<FragmentData> for Fragment {
-    fn from(frag: FragmentData) -> Self {
-        Self {
-            data: frag,
-            children: HashSet::new(),
-        }
-    }
-}
-
 fn aggregated_lookup_block(
     block_id: usize,
     fragments: &mut [([u64; 4], Fragment)],
@@ -92,92 +40,57 @@ fn aggregated_lookup_block(
     Ok((hashes, offsets))
 }
 
-fn main() {
-    // Used to gather metadata about the blocks of the recovered file
-    // Useful for carrying out special recovery on blocks that failed the checksum (a.k.a bad blocks)
+/// Used to gather metadata about the blocks of the recovered file
+/// Useful for carrying out special recovery on blocks that failed the checksum (a.k.a bad blocks)
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    #[command(flatten)]
+    selection: FileSelection,
+}
 
+fn main() {
     use szfs::ansi_color::*;
-    let usage = format!("Usage: {} (vdevs...)", env::args().next().unwrap());
-    let mut vdev0: VdevFile = File::open(env::args().nth(1).expect(&usage))
-        .expect("Vdev 0 should be able to be opened!")
-        .into();
-    let mut vdev1: VdevFile = File::open(env::args().nth(2).expect(&usage))
-        .expect("Vdev 1 should be able to be opened!")
-        .into();
-    let mut vdev2: VdevFile = File::open(env::args().nth(3).expect(&usage))
-        .expect("Vdev 2 should be able to be opened!")
-        .into();
-    let mut vdev3: VdevFile = File::open(env::args().nth(4).expect(&usage))
-        .expect("Vdev 3 should be able to be opened!")
-        .into();
-
-    // For now just use the first label
-    let mut label0 = VdevLabel::from_bytes(
-        &vdev0
-            .read_raw_label(0)
-            .expect("Vdev label 0 must be parsable!"),
-    );
-
-    let name_value_pairs =
-        nvlist::from_bytes_xdr(&mut label0.get_name_value_pairs_raw().iter().copied())
-            .expect("Name value pairs in the vdev label must be valid!");
-    let nvlist::Value::NVList(vdev_tree) = &name_value_pairs["vdev_tree"] else {
-        panic!("vdev_tree is not an nvlist!");
-    };
-
-    let nvlist::Value::U64(top_level_ashift) = vdev_tree["ashift"] else {
-        panic!("no ashift found for top level vdev!");
-    };
+    let args = Args::parse();
+    let selection = args.selection;
+    if !selection.needs_bonus_attributes()
+        && selection.object_id.is_none()
+        && selection.hash.is_none()
+    {
+        println!("{YELLOW}Warning{WHITE}: No file selected, the blocks of all recovered files will be merged together!");
+    }
 
-    println!("{CYAN}Info{WHITE}: Parsed nv_list, {name_value_pairs:?}!");
-    println!("{RED}Important{WHITE}: Please make sure the disks are actually in the right order by using the nv_list, i can't actually check that in a reliable way!!!");
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    println!(
+        "{CYAN}Info{WHITE}: Parsed nv_list, {:?}!",
+        pool.name_value_pairs
+    );
 
     if cfg!(debug_assertions) {
         println!("{RED}Important{WHITE}: This is not an optimized binary!");
     }
 
-    let mut devices = Vdevs::new();
-    devices.insert(0, &mut vdev0);
-    devices.insert(1, &mut vdev1);
-    devices.insert(2, &mut vdev2);
-    devices.insert(3, &mut vdev3);
-
-    let mut vdev_raidz: VdevRaidz =
-        VdevRaidz::from_vdevs(devices, 4, 1, 2_usize.pow(top_level_ashift as u32));
-
-    label0.set_raw_uberblock_size(2_usize.pow(top_level_ashift as u32));
+    let mut vdev_raidz = pool.get_raidz();
 
     let disk_size = vdev_raidz.get_size();
     let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
     vdevs.insert(0usize, &mut vdev_raidz);
 
-    let mut recovered_fragments: Vec<([u64; 4], Fragment)> =
-        serde_json::from_reader(File::open("undelete-filtered-checkpoint.json").unwrap()).unwrap();
-
-    recovered_fragments.retain_mut(|frag| {
-        if let FragmentData::FileDNode(file) = &mut frag.1.data {
-            let file_cr_time_unix_timestamp = u64::from_le_bytes(
-                file.0.get_bonus_data()[14 * 8..14 * 8 + 8]
-                    .try_into()
-                    .unwrap(),
-            );
-            file_cr_time_unix_timestamp == 1674749006
-        } else {
-            false
-        }
-    });
-
-    recovered_fragments.sort_unstable_by_key(|f| {
-        let FragmentData::FileDNode(f) = &f.1.data else {panic!("");};
-        Reverse(f.0.get_data_size())
-    });
+    let mut recovered_fragments = recovery::select::read_selected_fragments(&pool_args, &selection)
+        .unwrap_or_else(|err| cli::exit_with_error(err));
+    if recovered_fragments.is_empty() {
+        println!("{YELLOW}Warning{WHITE}: No recovered file matches the selection!");
+        return;
+    }
 
     for res in recovered_fragments.iter() {
         println!("{:?}", res);
     }
 
     let bad_blocks: Vec<usize> =
-        serde_json::from_reader(File::open("bad_blocks.json").unwrap()).unwrap();
+        serde_json::from_reader(File::open(pool_args.output_path("bad_blocks.json")).unwrap())
+            .unwrap();
 
     let bad_blocks: HashSet<usize> = bad_blocks.into_iter().collect();
 
@@ -188,13 +101,21 @@ fn main() {
 
     println!("RAIDZ total size (GB): {}", disk_size / 1024 / 1024 / 1024);
 
-    // NOTE: This is specifically meant for my scenario
-    // where i lost a big file that i have recovered the size of
-    // in a fs that only ever had 2-3 files
-    let file_size: usize = 1084546955827;
-
-    // I know the block size of the file system i'm recovering from
-    let file_block_size: usize = 128 * 1024;
+    // The biggest version of the file is the most complete one, so the size and block size are taken from it
+    let (file_size, file_block_size) = {
+        let biggest_file = &recovered_fragments[0].1;
+        let FragmentData::FileDNode(file) = &biggest_file.data else {
+            unreachable!();
+        };
+        let file_size = FileAttributes::guess_from_dnode(file)
+            .map(|attributes| attributes.size as usize)
+            .unwrap_or_else(|| {
+                println!("{YELLOW}Warning{WHITE}: Couldn't figure out the size of the file, using the size of its data!");
+                file.0.get_data_size()
+            });
+        (file_size, file.0.parse_data_block_size())
+    };
+    println!("Recovering {file_size} bytes in blocks of {file_block_size} bytes");
 
     let nblocks_in_file = file_size / file_block_size
         + if file_size % file_block_size != 0 {
@@ -1,217 +1,55 @@
-#![feature(map_many_mut)]
-
-use serde::{Deserialize, Serialize};
-use std::{
-    collections::{HashMap, HashSet},
-    env,
-    fmt::Debug,
-    fs::{File, OpenOptions},
-    io::Write,
-};
+use clap::Parser;
+use std::{collections::HashMap, ops::RangeInclusive};
 use szfs::{
-    byte_iter::FromBytesLE,
-    dmu::{DNode, DNodeDirectoryContents, DNodePlainFileContents, ObjSet},
-    zio::{CompressionMethod, Vdevs},
+    cli,
+    recovery::fragment::{search_le_bytes_for_dnodes, Fragment},
     *,
 };
 
-// NOTE: This code assumes the hash function is perfect
-const hash_function: fn(data: &[u8]) -> [u64; 4] = fletcher::do_fletcher4;
-
-#[derive(Debug, Serialize, Deserialize)]
-struct IndirectBlock {
-    pub bps: Vec<Option<zio::BlockPointer>>,
-}
-
-#[derive(Serialize, Deserialize)]
-enum FragmentData {
-    FileDNode(DNodePlainFileContents),
-    DirectoryDNode(DNodeDirectoryContents, Vec<String>),
-    ObjSetDNode(ObjSet),
-    IndirectBlock(IndirectBlock),
-}
-
-impl Debug for FragmentData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            FragmentData::FileDNode(_) => write!(f, "File"),
-            FragmentData::DirectoryDNode(_, _) => write!(f, "Dir"),
-            FragmentData::ObjSetDNode(_) => write!(f, "ObjSet"),
-            FragmentData::IndirectBlock(_) => write!(f, "Indirect"),
-        }?;
-
-        Ok(())
-    }
-}
-
-#[derive(Serialize, Deserialize)]
-struct Fragment {
-    data: FragmentData,
-    children: HashSet<[u64; 4]>,
-}
-
-impl Debug for Fragment {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.data)?;
-        write!(f, "(")?;
-        for child in self.children.iter() {
-            write!(f, "{:?}, ", child[0])?;
-        }
-        write!(f, ")")?;
-        Ok(())
-    }
-}
-
-impl From<FragmentData> for Fragment {
-    fn from(frag: FragmentData) -> Self {
-        Self {
-            data: frag,
-            children: HashSet::new(),
-        }
-    }
-}
-
-// Note: 'data' must be from a 512-byte aligned offset of the original device
-//       This is because of an optimization taking advantage of the fact that dva offsets are always multiples of 512 and a dnode "slot" is 512 bytes in size in the Objset
-// Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L407 which uses SPA_MINBLOCKSHIFT and DVA_GET_OFFSET
-// SPA_MINBLOCKSHIFT and DVA_GET_OFFSET can be found at: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h#L1783 and https://github.com/openzfs/zfs/blob/master/include/sys/bitops.h#L66
-// As you can see SPA_MINBLOCKSHIFT is 9 and the macro shifts by 9
-// Thus proving that the current code is shifting the offset read from disk by 9
-// thus meaning that all DVA offsets are multiples of 512
-fn search_le_bytes_for_dnodes(data: &[u8], vdevs: &mut Vdevs) -> HashMap<[u64; 4], Fragment> {
-    let mut res = HashMap::<[u64; 4], Fragment>::new();
-    if data.len() % 512 != 0 {
-        if cfg!(feature = "debug") {
-            use crate::ansi_color::*;
-            println!("{YELLOW}Warning{WHITE}: Can't search data that is not a multiple of 512 bytes in size, ignoring extra bytes!");
-        }
-    }
-
-    let mut data = data.chunks_exact(512);
-    while let Some(sector) = data.next() {
-        // Try to parse file or directory dnode
-        let nsectors = dmu::DNode::get_n_slots_from_bytes_le(sector.iter().copied()).unwrap(); // NOTE: Unwrap should always succeed here, because we always have enough data
-        let nextra_sectors_to_read = nsectors - 1;
-
-        let mut dnode_data = Vec::<u8>::new();
-        dnode_data.extend(sector);
-        // We use a clone so as not to advance the actual iterator
-        // so we don't accidentally ignore some sectors
-        // because we read an invalid nsectors from one sector
-        let mut data_iterator_clone = data.clone();
-        for _ in 0..nextra_sectors_to_read {
-            if let Some(extra_sector) = data_iterator_clone.next() {
-                dnode_data.extend(extra_sector);
-            } else {
-                // If a Chunks Iterator returns None once, it will never return Some again, so no point in continuing
-                break;
-            }
-        }
-
-        let dnode_data_hash = hash_function(&dnode_data);
-        // Note: This tries to parse it even if we don't have enough data, for a data recovery tool this seems like the better option
-        let dnode = dmu::DNode::from_bytes_le(&mut dnode_data.into_iter());
-        match dnode {
-            Some(DNode::PlainFileContents(mut dnode)) => {
-                if dnode
-                    .0
-                    .get_block_pointers()
-                    .iter_mut()
-                    .any(|bp| bp.dereference(vdevs).is_ok())
-                {
-                    res.insert(dnode_data_hash, FragmentData::FileDNode(dnode).into());
-                }
-            }
-            Some(DNode::DirectoryContents(mut dnode)) => {
-                if dnode
-                    .0
-                    .get_block_pointers()
-                    .iter_mut()
-                    .any(|bp| bp.dereference(vdevs).is_ok())
-                {
-                    let Some(contents) = dnode.dump_zap_contents(vdevs) else { continue; };
-                    let contents = contents
-                        .iter()
-                        .map(|(name, _)| name)
-                        .cloned()
-                        .collect::<Vec<String>>();
-
-                    res.insert(
-                        dnode_data_hash,
-                        FragmentData::DirectoryDNode(dnode, contents).into(),
-                    );
-                }
-            }
-            _ => (),
-        }
-    }
-
-    res
+// How much of the disk a worker scans at a time
+const SCAN_CHUNK_SIZE: u64 = 256 * 1024 * 1024;
+const STEP1_CHECKPOINT_PATH: &str = "undelete-step1.ckpt";
+
+/// A simplified version of undelete for the times when you don't need *all* of the metadata
+/// or don't really care about reconstructing the original relationships between the metadata
+/// Useful if you don't mind loosing directory structure/other useful data
+/// and want a simple quick search for data
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// metadata only tries the sizes compressed indirect blocks and dnode blocks usually have, exhaustive tries every possible size (*a lot* slower)
+    #[arg(default_value = "metadata")]
+    scan_profile: recovery::scan::ScanProfile,
+    /// Only keep the structures whose newest block pointer was born in these txgs, ex. the ones just before the files were deleted
+    #[arg(long, value_name = "MIN:MAX", value_parser = recovery::select::parse_range_arg)]
+    birth_txgs: Option<RangeInclusive<u64>>,
 }
 
 fn main() {
-    // A simplified version of undelete for the times when you don't need *all* of the metadata
-    // or don't really care about reconstructing the original relationships between the metadata
-    // Useful if you don't mind loosing directory structure/other useful data
-    // and want a simple quick search for data
-
     use szfs::ansi_color::*;
-    let usage = format!("Usage: {} (vdevs...)", env::args().next().unwrap());
-    let mut vdev0: VdevFile = File::open(env::args().nth(1).expect(&usage))
-        .expect("Vdev 0 should be able to be opened!")
-        .into();
-    let mut vdev1: VdevFile = File::open(env::args().nth(2).expect(&usage))
-        .expect("Vdev 1 should be able to be opened!")
-        .into();
-    let mut vdev2: VdevFile = File::open(env::args().nth(3).expect(&usage))
-        .expect("Vdev 2 should be able to be opened!")
-        .into();
-    let mut vdev3: VdevFile = File::open(env::args().nth(4).expect(&usage))
-        .expect("Vdev 3 should be able to be opened!")
-        .into();
-
-    // For now just use the first label
-    let mut label0 = VdevLabel::from_bytes(
-        &vdev0
-            .read_raw_label(0)
-            .expect("Vdev label 0 must be parsable!"),
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    println!(
+        "{CYAN}Info{WHITE}: Parsed nv_list, {:?}!",
+        pool.name_value_pairs
     );
-
-    let name_value_pairs =
-        nvlist::from_bytes_xdr(&mut label0.get_name_value_pairs_raw().iter().copied())
-            .expect("Name value pairs in the vdev label must be valid!");
-    let nvlist::Value::NVList(vdev_tree) = &name_value_pairs["vdev_tree"] else {
-        panic!("vdev_tree is not an nvlist!");
-    };
-
-    let nvlist::Value::U64(top_level_ashift) = vdev_tree["ashift"] else {
-        panic!("no ashift found for top level vdev!");
-    };
-
-    println!("{CYAN}Info{WHITE}: Parsed nv_list, {name_value_pairs:?}!");
-    println!("{RED}Important{WHITE}: Please make sure the disks are actually in the right order by using the nv_list, i can't actually check that in a reliable way!!!");
-
-    let mut devices = Vdevs::new();
-    devices.insert(0, &mut vdev0);
-    devices.insert(1, &mut vdev1);
-    devices.insert(2, &mut vdev2);
-    devices.insert(3, &mut vdev3);
-
-    let mut vdev_raidz: VdevRaidz =
-        VdevRaidz::from_vdevs(devices, 4, 1, 2_usize.pow(top_level_ashift as u32));
-
-    label0.set_raw_uberblock_size(2_usize.pow(top_level_ashift as u32));
+    let (nparity, asize) = (pool.nparity, pool.get_asize());
+    let rate_limiters = pool.get_rate_limiters();
+    let mut vdev_raidz = pool.get_raidz();
 
     let disk_size = vdev_raidz.get_size();
+    let vdev_paths = &pool_args.vdevs;
     let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
     vdevs.insert(0usize, &mut vdev_raidz);
 
-    // The sizes are just the most common sizes i have seen while looking at the sizes of compressed indirect blocks
-    let compression_methods_and_sizes_to_try = [(
-        CompressionMethod::Lz4,
-        [512 * 2, 512 * 3, 512 * 21, 512 * 256],
-        [0], /* irrelevant for lz4 */
-    )];
+    let scan_profile = args.scan_profile;
+    let mut scan_config = recovery::scan::ScanConfig::from_profile(scan_profile, disk_size);
+    println!("{CYAN}Info{WHITE}: Using scan profile {scan_profile:?}");
+    if let Some(birth_txgs) = &args.birth_txgs {
+        println!("{CYAN}Info{WHITE}: Only keeping what was born in txgs {birth_txgs:?}");
+    }
+    scan_config.birth_txgs = args.birth_txgs;
 
     // This is the main graph
     let mut recovered_fragments = HashMap::<[u64; 4], Fragment>::new();
@@ -219,70 +57,66 @@ fn main() {
     println!("RAIDZ total size (GB): {}", disk_size / 1024 / 1024 / 1024);
     println!("Step 1. Gathering basic fragments");
 
-    let mut checkpoint_number = 0;
-    for off in (0..disk_size).step_by(512) {
-        if off % (128 * 1024 * 1024) == 0 && off != 0 {
-            println!(
-                "{}% done gathering basic fragments ...",
-                ((off as f32) / (disk_size as f32)) * 100.0
-            );
+    // Every chunk that is scanned gets appended to the checkpoint, so if the scan is interrupted it can be resumed from there
+    let mut step1_checkpoint =
+        recovery::checkpoint::CheckpointLog::open(&pool_args.output_path(STEP1_CHECKPOINT_PATH))
+            .expect("Step 1 checkpoint should be able to be opened!");
+    if step1_checkpoint.get_nsegments() != 0 {
+        println!(
+            "{CYAN}Info{WHITE}: Resuming from checkpoint, at offset {}",
+            step1_checkpoint.get_resume_cursor()
+        );
+        for segment in step1_checkpoint
+            .read_segments::<([u64; 4], Fragment)>()
+            .expect("Step 1 checkpoint should be readable!")
+        {
+            recovered_fragments.extend(segment);
         }
+        scan_config.skip_before(step1_checkpoint.get_resume_cursor());
+    }
 
-        if off % (100 * 1024 * 1024 * 1024) == 0 && off != 0 {
-            // Every ~100 GB
-            println!("Saving checkpoint...");
-            write!(
-                OpenOptions::new()
-                    .create(true)
-                    .truncate(true)
-                    .write(true)
-                    .open(format!("undelete-step1-checkpoint{checkpoint_number}.json"))
-                    .unwrap(),
-                "{}",
-                &serde_json::to_string(&recovered_fragments.iter().collect::<Vec<(_, _)>>())
-                    .unwrap()
-            )
-            .unwrap();
-            checkpoint_number += 1;
-            println!("Done!");
-        }
-
-        // NOTE: Currently asize is just not used even though it's part of the data structure, because we read it form disk
-        let dva = szfs::zio::DataVirtualAddress::from(0, off, false);
-
-        // Since we don't know what the size of the block(if there is any) at this offset might be
-        // we just try all possible options
-        for compression_method_and_sizes in compression_methods_and_sizes_to_try {
-            for possible_comp_size in compression_method_and_sizes.1 {
-                let Ok(data) = dva.dereference(&mut vdevs, possible_comp_size) else {
-                    continue;
-                };
-
-                for possible_decomp_size in compression_method_and_sizes.2 {
-                    let decomp_data = zio::try_decompress_block(
-                        &data,
-                        compression_method_and_sizes.0,
-                        possible_decomp_size,
-                    )
-                    .unwrap_or_else(|partial_data| partial_data);
-                    let res = search_le_bytes_for_dnodes(&decomp_data, &mut vdevs);
-                    recovered_fragments.extend(res);
+    let cancel = cli::cancel_on_ctrl_c();
+    let completion = recovery::scan::parallel_scan(
+        &scan_config,
+        SCAN_CHUNK_SIZE,
+        &cancel,
+        || cli::open_worker_vdevs(vdev_paths, &rate_limiters),
+        |worker_vdevs, chunk| {
+            let mut vdev_raidz = cli::make_raidz(worker_vdevs, nparity, asize);
+            let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+            vdevs.insert(0usize, &mut vdev_raidz);
+
+            let mut chunk_fragments = HashMap::<[u64; 4], Fragment>::new();
+            for off in scan_config.get_offsets_in(chunk) {
+                // Since we don't know what the size of the block(if there is any) at this offset might be
+                // we just try all the options in the scan config
+                for decomp_data in scan_config.read_candidate_blocks(off, &mut vdevs) {
+                    let res = search_le_bytes_for_dnodes(&decomp_data, &mut vdevs, false);
+                    chunk_fragments.extend(res);
                 }
             }
-        }
+            chunk_fragments
+                .retain(|_, fragment| scan_config.keeps_birth_txg(fragment.get_newest_birth_txg()));
+            chunk_fragments
+        },
+        |chunk, chunk_fragments| {
+            // Chunks are passed in order, so everything before the end of this chunk has been scanned
+            step1_checkpoint
+                .append(chunk.end, &chunk_fragments.iter().collect::<Vec<(_, _)>>())
+                .expect("Step 1 checkpoint should be writable!");
+            recovered_fragments.extend(chunk_fragments);
+            println!(
+                "{}% done gathering basic fragments ...",
+                ((chunk.end as f32) / (disk_size as f32)) * 100.0
+            );
+        },
+    );
+    if completion == recovery::control::Completion::Cancelled {
+        println!("{CYAN}Info{WHITE}: Stopped, run the same command again to resume the scan");
+        return;
     }
 
     println!("Found {} basic fragments", recovered_fragments.len());
-    println!("Saving checkpoint...");
-    write!(
-        OpenOptions::new()
-            .create(true)
-            .truncate(true)
-            .write(true)
-            .open(format!("undelete-step1-checkpoint{checkpoint_number}.json"))
-            .unwrap(),
-        "{}",
-        &serde_json::to_string(&recovered_fragments.iter().collect::<Vec<(_, _)>>()).unwrap()
-    )
-    .unwrap();
+
+    warnings::print_warning_summary();
 }
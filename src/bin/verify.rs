@@ -0,0 +1,132 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{cli, traverse::TraversedObject, verify::verify_pool, *};
+
+/// Reads every copy of every block reachable from an uberblock and reports the damaged ones, like a scrub that can't repair anything
+/// By default the newest uberblock whose MOS can be read is used
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// Also compare the copies of blocks with more than one to each other, and report the ones that don't have the same data (and on which vdev and offset every copy is)
+    #[arg(long)]
+    compare_copies: bool,
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let mut uberblocks = pool_args.filter_uberblocks(pool.get_label_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let mut active_uberblock = None;
+    for ub in uberblocks.iter_mut().rev() {
+        if ub.rootbp.dereference(&mut vdevs).is_ok() {
+            active_uberblock = Some(ub);
+            break;
+        }
+    }
+    let active_uberblock = active_uberblock
+        .expect("There should be an uberblock (with the requested txg) whose MOS can be read!");
+    println!("{CYAN}Info{WHITE}: Using {:?}", active_uberblock);
+
+    let reports = verify_pool(
+        &mut active_uberblock.rootbp,
+        &mut vdevs,
+        args.compare_copies,
+    )
+    .expect("MOS should be readable!");
+
+    let mut nlost_blocks = 0;
+    for (objset, report) in reports.iter() {
+        let nlost = report.bad_blocks.iter().filter(|bad| bad.is_lost()).count();
+        nlost_blocks += nlost;
+        println!(
+            "{:?}: {} blocks ({} bytes) verified, {} damaged, {} lost, {} couldn't be verified",
+            objset,
+            report.nblocks,
+            report.nbytes,
+            report.bad_blocks.len(),
+            nlost,
+            report.nunverifiable
+        );
+
+        for bad_block in report.bad_blocks.iter() {
+            let object = match bad_block.location.object {
+                TraversedObject::ObjSet => String::from("objset"),
+                TraversedObject::MetaDNode => String::from("meta dnode"),
+                TraversedObject::Object(object_id) => format!("object {object_id}"),
+            };
+            println!(
+                "    {}{}{}, level {} block {} (object offset {}), copies: {:?}",
+                if bad_block.is_lost() { RED } else { YELLOW },
+                object,
+                WHITE,
+                bad_block.location.level,
+                bad_block.location.block_id,
+                bad_block.location.get_object_offset(),
+                bad_block.copies
+            );
+            for (dva, status) in bad_block.dvas.iter().zip(bad_block.copies.iter()) {
+                println!("        {dva}: {status:?}");
+            }
+        }
+    }
+
+    for (objset, report) in reports.iter() {
+        for diverging_block in report.diverging_blocks.iter() {
+            let object = match diverging_block.location.object {
+                TraversedObject::ObjSet => String::from("objset"),
+                TraversedObject::MetaDNode => String::from("meta dnode"),
+                TraversedObject::Object(object_id) => format!("object {object_id}"),
+            };
+            println!(
+                "{YELLOW}Warning{WHITE}: The copies of {:?} {}, level {} block {} don't have the same data:",
+                objset,
+                object,
+                diverging_block.location.level,
+                diverging_block.location.block_id
+            );
+            for (index, copy) in diverging_block.copies.iter().enumerate() {
+                println!(
+                    "    copy {} at {}: {:?}, same data as copy {:?}",
+                    index, copy.dva, copy.status, copy.same_data_as
+                );
+            }
+        }
+    }
+
+    for (objset, report) in reports.iter() {
+        for mismatched_block in report.mismatched_blocks.iter() {
+            let object = match mismatched_block.location.object {
+                TraversedObject::ObjSet => String::from("objset"),
+                TraversedObject::MetaDNode => String::from("meta dnode"),
+                TraversedObject::Object(object_id) => format!("object {object_id}"),
+            };
+            let mismatch = &mismatched_block.mismatch;
+            println!(
+                "{YELLOW}Warning{WHITE}: The block pointer of {:?} {}, level {} block {} says it's a level {} {} block, but it should be a level {} {} block!",
+                objset,
+                object,
+                mismatched_block.location.level,
+                mismatched_block.location.block_id,
+                mismatch.found_level,
+                mismatch.found_type.get_name(),
+                mismatch.expected_level,
+                mismatch.expected_type.get_name()
+            );
+        }
+    }
+
+    if nlost_blocks == 0 {
+        println!("{CYAN}Info{WHITE}: No data was lost");
+    } else {
+        println!("{RED}Important{WHITE}: {nlost_blocks} blocks can't be read anymore!");
+    }
+
+    warnings::print_warning_summary();
+}
@@ -0,0 +1,110 @@
+use clap::Parser;
+use std::collections::HashMap;
+use szfs::{byte_iter::FromSliceLE, cli, *};
+
+/// Prints the objects of the MOS and of the root dataset in the same layout as zdb -dd (or zdb -ddddd with the --indirect flag)
+/// so the output can be diffed against the output of zdb when validating recovery results
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    pool: cli::PoolArgs,
+    /// Also print the indirect blocks of every object, like zdb -ddddd
+    #[arg(long = "indirect")]
+    with_indirect_blocks: bool,
+}
+
+fn main() {
+    use szfs::ansi_color::*;
+    let args = Args::parse();
+    let (pool_args, mut pool) = cli::open_pool(args.pool);
+    let with_indirect_blocks = args.with_indirect_blocks;
+    let mut uberblocks = pool_args.filter_uberblocks(pool.get_label_uberblocks());
+    let mut vdev_raidz = pool.get_raidz();
+
+    let mut vdevs = HashMap::<usize, &mut dyn Vdev>::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+
+    let mut uberblock_search_info = None;
+    for ub in uberblocks.iter_mut().rev() {
+        if let Ok(data) = ub.rootbp.dereference(&mut vdevs) {
+            uberblock_search_info = Some((ub, data));
+            break;
+        }
+    }
+
+    let Some((active_uberblock, mos_data)) = uberblock_search_info else {
+        println!("{RED}Fatal{WHITE}: No uberblock has a readable MOS!");
+        return;
+    };
+
+    println!("Uberblock:");
+    println!("\ttxg = {}", active_uberblock.txg);
+    println!("\tguid_sum = {}", active_uberblock.guid_sum);
+    println!("\ttimestamp = {}", active_uberblock.timestamp);
+    println!(
+        "\trootbp = {}",
+        zdb::format_block_pointer(&active_uberblock.rootbp)
+    );
+    println!();
+
+    let mut meta_object_set = dmu::ObjSet::from_slice_le(&mos_data).expect("Mos should be valid!");
+
+    println!("Dataset mos [META]");
+    println!(
+        "{}",
+        zdb::format_objset(&mut meta_object_set, with_indirect_blocks, &mut vdevs)
+    );
+
+    let dmu::DNode::ObjectDirectory(mut object_directory) = meta_object_set
+        .get_dnode_at(1, &mut vdevs)
+        .expect("Object directory should be valid!")
+    else {
+        panic!("DNode 1 is not an object directory!");
+    };
+    let objdir_zap_data = object_directory.dump_zap_contents(&mut vdevs).unwrap();
+    println!("Object directory:");
+    println!("{}", zdb::format_zap(&objdir_zap_data));
+
+    let zap::Value::U64(root_dataset_number) = objdir_zap_data["root_dataset"] else {
+        panic!("Couldn't read root_dataset id!");
+    };
+
+    let dmu::DNode::DSLDirectory(root_dataset) = meta_object_set
+        .get_dnode_at(root_dataset_number as usize, &mut vdevs)
+        .unwrap()
+    else {
+        panic!(
+            "DNode {} which is the root_dataset is not a dsl directory!",
+            root_dataset_number
+        );
+    };
+
+    let head_dataset_number = root_dataset
+        .parse_bonus_data()
+        .unwrap()
+        .get_head_dataset_object_number();
+    let dmu::DNode::DSLDataset(head_dataset) = meta_object_set
+        .get_dnode_at(head_dataset_number as usize, &mut vdevs)
+        .unwrap()
+    else {
+        panic!(
+            "DNode {} whichs is the head_dataset is not a dsl dataset!",
+            head_dataset_number
+        );
+    };
+    let mut head_dataset_bonus = head_dataset.parse_bonus_data().unwrap();
+    let head_dataset_blockpointer = head_dataset_bonus.get_block_pointer();
+    let mut head_dataset_object_set =
+        dmu::ObjSet::from_slice_le(&head_dataset_blockpointer.dereference(&mut vdevs).unwrap())
+            .unwrap();
+
+    println!("Dataset {} [ZPL]", head_dataset_number);
+    println!(
+        "{}",
+        zdb::format_objset(
+            &mut head_dataset_object_set,
+            with_indirect_blocks,
+            &mut vdevs
+        )
+    );
+}
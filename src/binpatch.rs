@@ -0,0 +1,194 @@
+// Binary patches, a list of (offset, data) entries that get written over a file
+// This is how the recovery tools (ex. surgeon) hand out the blocks they recovered, so the recovered file itself is only touched when applying the patch
+//
+// Format (all integers are little endian):
+// Header: magic (8 bytes) | version (u32)
+// Entry:  target offset (u64) | size (u64) | crc32 of the data (u32) | data (size bytes)
+//
+// NOTE: Old patches (version 0) don't have a header or checksums, the entries are just target offset | size | data
+//       Those are still read, but there is no way to check them
+
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+pub const MAGIC: [u8; 8] = *b"SZFSPTCH";
+pub const VERSION: u32 = 1;
+
+// The same crc32 zip, png and ethernet use, it's only there to catch a corrupt patch file, so speed doesn't matter
+// Source: https://en.wikipedia.org/wiki/Cyclic_redundancy_check (CRC-32, reversed polynomial 0xEDB88320)
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+pub struct PatchWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PatchWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<PatchWriter<W>> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(PatchWriter { writer })
+    }
+
+    pub fn write_entry(&mut self, target_offset: u64, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(&target_offset.to_le_bytes())?;
+        self.writer.write_all(&(data.len() as u64).to_le_bytes())?;
+        self.writer.write_all(&crc32(data).to_le_bytes())?;
+        self.writer.write_all(data)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[derive(Debug)]
+pub struct PatchEntry {
+    pub target_offset: u64,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum PatchEntryError {
+    // The patch ends in the middle of an entry, nothing after it can be read
+    Truncated,
+    // The entry was read completely, so the entries after it can still be read
+    ChecksumMismatch { target_offset: u64, size: u64 },
+}
+
+pub struct PatchReader<R: Read + Seek> {
+    reader: R,
+    version: u32,
+}
+
+impl<R: Read + Seek> PatchReader<R> {
+    // Returns: None if the patch has a header with a version this code doesn't know
+    pub fn new(mut reader: R) -> Option<PatchReader<R>> {
+        let mut magic = [0u8; 8];
+        let has_magic = reader.read_exact(&mut magic).is_ok() && magic == MAGIC;
+        if !has_magic {
+            reader.seek(SeekFrom::Start(0)).ok()?;
+            return Some(PatchReader { reader, version: 0 });
+        }
+
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version).ok()?;
+        let version = u32::from_le_bytes(version);
+        if version > VERSION {
+            return None;
+        }
+        Some(PatchReader { reader, version })
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    fn read_u64(&mut self) -> Result<u64, PatchEntryError> {
+        let mut buf = [0u8; 8];
+        self.reader
+            .read_exact(&mut buf)
+            .map_err(|_| PatchEntryError::Truncated)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    // Returns: Ok(None) at the end of the patch
+    pub fn next_entry(&mut self) -> Result<Option<PatchEntry>, PatchEntryError> {
+        let mut target_offset = [0u8; 8];
+        match self.reader.read(&mut target_offset) {
+            Ok(0) => return Ok(None),
+            Ok(n) => self.reader.read_exact(&mut target_offset[n..]),
+            Err(err) => Err(err),
+        }
+        .map_err(|_| PatchEntryError::Truncated)?;
+        let target_offset = u64::from_le_bytes(target_offset);
+        let size = self.read_u64()?;
+
+        let checksum = if self.version >= 1 {
+            let mut checksum = [0u8; 4];
+            self.reader
+                .read_exact(&mut checksum)
+                .map_err(|_| PatchEntryError::Truncated)?;
+            Some(u32::from_le_bytes(checksum))
+        } else {
+            None
+        };
+
+        // Don't trust the size to allocate the whole thing up front, a corrupt size would just make us run out of memory
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(size)
+            .read_to_end(&mut data)
+            .map_err(|_| PatchEntryError::Truncated)?;
+        if data.len() as u64 != size {
+            return Err(PatchEntryError::Truncated);
+        }
+
+        if checksum.is_some_and(|checksum| checksum != crc32(&data)) {
+            return Err(PatchEntryError::ChecksumMismatch {
+                target_offset,
+                size,
+            });
+        }
+        Ok(Some(PatchEntry {
+            target_offset,
+            data,
+        }))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ApplyReport {
+    pub entries_applied: usize,
+    pub bytes_written: u64,
+    // (target offset, size) of the entries that were skipped because their checksum didn't match
+    pub bad_entries: Vec<(u64, u64)>,
+    pub truncated: bool,
+}
+
+// Applies every entry with a correct checksum, entries with a wrong checksum are skipped and reported
+// If dry_run is true nothing is written, this can be used to just check a patch
+pub fn apply_patch<R: Read + Seek, W: Write + Seek>(
+    patch: &mut PatchReader<R>,
+    target: &mut W,
+    dry_run: bool,
+) -> Result<ApplyReport, ()> {
+    let mut report = ApplyReport::default();
+    loop {
+        match patch.next_entry() {
+            Ok(Some(entry)) => {
+                if !dry_run {
+                    target
+                        .seek(SeekFrom::Start(entry.target_offset))
+                        .map_err(|_| ())?;
+                    target.write_all(&entry.data).map_err(|_| ())?;
+                }
+                report.entries_applied += 1;
+                report.bytes_written += entry.data.len() as u64;
+            }
+            Ok(None) => break,
+            Err(PatchEntryError::ChecksumMismatch {
+                target_offset,
+                size,
+            }) => report.bad_entries.push((target_offset, size)),
+            Err(PatchEntryError::Truncated) => {
+                report.truncated = true;
+                break;
+            }
+        }
+    }
+    target.flush().map_err(|_| ())?;
+    Ok(report)
+}
@@ -0,0 +1,206 @@
+// Bookmarks and redaction lists
+// A bookmark remembers the guid and txg of a snapshot, so it can still be used as the source of an incremental send after the snapshot is gone
+// Redacted sends leave out the blocks listed in a redaction list, the receiving side gets redacted block pointers in their place
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dsl_bookmark.h
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dsl_bookmark.c
+
+use std::collections::HashMap;
+
+use crate::{
+    byte_iter::FromBytesLE,
+    dmu::{DNode, ObjSet, ObjType},
+    zap,
+    zio::Vdevs,
+};
+
+// Bookmarks aren't in the dsl dataset bonus buffer, the dataset object is also a zap with these extra fields
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dsl_dataset.h (DS_FIELD_BOOKMARK_NAMES, DS_FIELD_REDACTING_SNAPS)
+const DS_FIELD_BOOKMARK_NAMES: &str = "com.delphix:bookmarks";
+const DS_FIELD_REDACTING_SNAPS: &str = "com.delphix:redacting_snaps";
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dsl_bookmark.h (zfs_bookmark_phys_t)
+#[derive(Debug)]
+pub struct Bookmark {
+    pub guid: u64,
+    pub creation_txg: u64,
+    pub creation_time: u64,
+    // Only set by newer versions (BOOKMARK_PHYS_SIZE_V2), 0 if there is none
+    pub redaction_object_number: u64,
+    pub flags: u64,
+    pub referenced_bytes: Option<u64>,
+}
+
+impl Bookmark {
+    // Old bookmarks only have the first 3 fields
+    pub fn from_values(values: &[u64]) -> Option<Bookmark> {
+        if values.len() < 3 {
+            return None;
+        }
+        let get = |index: usize| values.get(index).copied();
+        Some(Bookmark {
+            guid: values[0],
+            creation_txg: values[1],
+            creation_time: values[2],
+            redaction_object_number: get(3).unwrap_or(0),
+            flags: get(4).unwrap_or(0),
+            referenced_bytes: get(5),
+        })
+    }
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dmu_redact.h (redaction_list_phys_t)
+#[derive(Debug)]
+pub struct RedactionListHeader {
+    // How far the redaction got, everything after this wasn't looked at
+    pub last_object: u64,
+    pub last_block_id: u64,
+    pub n_entries: u64,
+    // Guids of the snapshots the redaction was done with
+    pub snapshots: Vec<u64>,
+}
+
+impl RedactionListHeader {
+    pub fn from_bytes_le<Iter>(data: &mut Iter) -> Option<RedactionListHeader>
+    where
+        Iter: Iterator<Item = u8>,
+    {
+        let last_object = u64::from_bytes_le(data)?;
+        let last_block_id = u64::from_bytes_le(data)?;
+        let n_entries = u64::from_bytes_le(data)?;
+        let n_snapshots = u64::from_bytes_le(data)?;
+        let mut snapshots = Vec::new();
+        for _ in 0..n_snapshots {
+            snapshots.push(u64::from_bytes_le(data)?);
+        }
+        Some(RedactionListHeader {
+            last_object,
+            last_block_id,
+            n_entries,
+            snapshots,
+        })
+    }
+}
+
+// A run of `count` redacted blocks of one object, starting at `block_id`
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (redact_block_phys_t, redact_block_get_size, redact_block_get_count)
+#[derive(Debug)]
+pub struct RedactedBlocks {
+    pub object: u64,
+    pub block_id: u64,
+    pub block_size: u64,
+    pub count: u64,
+}
+
+impl RedactedBlocks {
+    pub const fn get_ondisk_size() -> usize {
+        core::mem::size_of::<u64>() * 3
+    }
+
+    pub fn from_bytes_le<Iter>(data: &mut Iter) -> Option<RedactedBlocks>
+    where
+        Iter: Iterator<Item = u8>,
+    {
+        let object = u64::from_bytes_le(data)?;
+        let block_id = u64::from_bytes_le(data)?;
+        let size_count = u64::from_bytes_le(data)?;
+        Some(RedactedBlocks {
+            object,
+            block_id,
+            // Stored in 512 byte sectors (SPA_MINBLOCKSHIFT)
+            block_size: (size_count >> 48) << 9,
+            count: size_count & ((1 << 48) - 1),
+        })
+    }
+}
+
+// Returns: The zap that is the dsl dataset object, or None if it isn't one (datasets created before the extensible_dataset feature)
+fn read_dataset_zap(
+    mos: &mut ObjSet,
+    dataset_id: u64,
+    vdevs: &mut Vdevs,
+) -> Option<HashMap<String, zap::Value>> {
+    let (mut dataset_zap, obj_type) = mos.get_zap_dnode_at(dataset_id as usize, vdevs)?;
+    if obj_type != ObjType::DSLDataset {
+        return None;
+    }
+    dataset_zap.dump_zap_contents(vdevs)
+}
+
+// Returns: The bookmarks of the head dataset with object number `dataset_id` by name, empty if it has none
+//          None if the dataset or its bookmarks can't be read
+pub fn read_bookmarks(
+    mos: &mut ObjSet,
+    dataset_id: u64,
+    vdevs: &mut Vdevs,
+) -> Option<HashMap<String, Bookmark>> {
+    let DNode::DSLDataset(_) = mos.get_dnode_at(dataset_id as usize, vdevs)? else {
+        return None;
+    };
+    let Some(dataset_zap) = read_dataset_zap(mos, dataset_id, vdevs) else {
+        return Some(HashMap::new());
+    };
+    let Some(zap::Value::U64(bookmarks_id)) = dataset_zap.get(DS_FIELD_BOOKMARK_NAMES) else {
+        return Some(HashMap::new());
+    };
+
+    let (mut bookmarks_zap, _) = mos.get_zap_dnode_at(*bookmarks_id as usize, vdevs)?;
+    let mut bookmarks = HashMap::new();
+    for (name, value) in bookmarks_zap.dump_zap_contents(vdevs)? {
+        let bookmark = match value {
+            zap::Value::U64Array(values) => Bookmark::from_values(&values),
+            _ => None,
+        };
+        let Some(bookmark) = bookmark else {
+            use crate::ansi_color::*;
+            println!("{YELLOW}Warning{WHITE}: Bookmark {name} of dataset {dataset_id} has an unexpected value, skipping it!");
+            continue;
+        };
+        bookmarks.insert(name, bookmark);
+    }
+    Some(bookmarks)
+}
+
+// Returns: The guids of the snapshots the dataset was redacted with, or None if the dataset was not received from a redacted send
+// NOTE: Every redacted block pointer in a dataset like this is expected, they aren't damage
+pub fn get_redacting_snapshots(
+    mos: &mut ObjSet,
+    dataset_id: u64,
+    vdevs: &mut Vdevs,
+) -> Option<Vec<u64>> {
+    match read_dataset_zap(mos, dataset_id, vdevs)?.remove(DS_FIELD_REDACTING_SNAPS)? {
+        zap::Value::U64(guid) => Some(vec![guid]),
+        zap::Value::U64Array(guids) => Some(guids),
+        _ => None,
+    }
+}
+
+// Returns: The header of the redaction list and all the runs of blocks in it
+pub fn read_redaction_list(
+    mos: &mut ObjSet,
+    redaction_list_id: u64,
+    vdevs: &mut Vdevs,
+) -> Option<(RedactionListHeader, Vec<RedactedBlocks>)> {
+    let (mut redaction_list, obj_type) =
+        mos.get_dnode_base_at(redaction_list_id as usize, vdevs)?;
+    if obj_type != ObjType::NewUInt64Metadata {
+        return None;
+    }
+    let header =
+        RedactionListHeader::from_bytes_le(&mut redaction_list.get_bonus_data().iter().copied())?;
+
+    let data = redaction_list.read(
+        0,
+        usize::try_from(header.n_entries).ok()? * RedactedBlocks::get_ondisk_size(),
+        vdevs,
+    );
+    let Ok(data) = data else {
+        use crate::ansi_color::*;
+        println!("{YELLOW}Warning{WHITE}: Couldn't read the entries of redaction list {redaction_list_id}!");
+        return Some((header, Vec::new()));
+    };
+    let entries = data
+        .chunks(RedactedBlocks::get_ondisk_size())
+        .filter_map(|entry| RedactedBlocks::from_bytes_le(&mut entry.iter().copied()))
+        .collect();
+    Some((header, entries))
+}
@@ -96,3 +96,163 @@ where
         Some(())
     }
 }
+
+// An iterator over a byte slice that keeps track of how many bytes were consumed
+// It can be passed to any of the from_bytes functions, and afterwards you can ask it where parsing stopped
+// so you don't have to hand compute how big the thing you just parsed was on disk
+#[derive(Clone)]
+pub struct ByteReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub offset: usize, // The offset (relative to the start of the reader) at which parsing failed
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { data, offset: 0 }
+    }
+
+    // Returns: how many bytes were consumed so far
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn get_remaining_len(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.get_remaining_len() == 0
+    }
+
+    pub fn peek(&self) -> Option<u8> {
+        self.data.get(self.offset).copied()
+    }
+
+    pub fn peek_n(&self, n_bytes: usize) -> Option<&'a [u8]> {
+        self.data
+            .get(self.offset..self.offset.checked_add(n_bytes)?)
+    }
+
+    pub fn read_slice(&mut self, n_bytes: usize) -> Option<&'a [u8]> {
+        let res = self.peek_n(n_bytes)?;
+        self.offset += n_bytes;
+        Some(res)
+    }
+
+    // Splits off the next n_bytes as their own reader (with offsets starting from 0) and skips past them
+    // Useful for fixed size structures, as whatever happens while parsing the sub reader, this reader ends up right after the structure
+    pub fn read_sub_reader(&mut self, n_bytes: usize) -> Option<ByteReader<'a>> {
+        Some(ByteReader::new(self.read_slice(n_bytes)?))
+    }
+
+    // Parses a T, on failure the offset is reset to where it was before parsing started
+    // and the error contains the offset at which the parser ran out of data or gave up
+    pub fn parse_le<T>(&mut self) -> Result<T, ParseError>
+    where
+        T: FromBytesLE<ByteReader<'a>>,
+    {
+        let start_offset = self.offset;
+        match T::from_bytes_le(self) {
+            Some(res) => Ok(res),
+            None => {
+                let err = ParseError {
+                    offset: self.offset,
+                };
+                self.offset = start_offset;
+                Err(err)
+            }
+        }
+    }
+
+    pub fn parse_be<T>(&mut self) -> Result<T, ParseError>
+    where
+        T: FromBytesBE<ByteReader<'a>>,
+    {
+        let start_offset = self.offset;
+        match T::from_bytes_be(self) {
+            Some(res) => Ok(res),
+            None => {
+                let err = ParseError {
+                    offset: self.offset,
+                };
+                self.offset = start_offset;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for ByteReader<'a> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let res = self.peek()?;
+        self.offset += 1;
+        Some(res)
+    }
+
+    // Makes skip_n_bytes O(1)
+    fn nth(&mut self, n: usize) -> Option<u8> {
+        if n >= self.get_remaining_len() {
+            self.offset = self.data.len();
+            return None;
+        }
+        self.offset += n;
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.get_remaining_len(), Some(self.get_remaining_len()))
+    }
+}
+
+impl<'a> ExactSizeIterator for ByteReader<'a> {}
+
+// Parses a structure straight out of a byte slice
+// The fields are read at their offsets instead of byte by byte, which is what makes scanning whole disks for structures fast
+// The FromBytesLE impls of these structures just copy their bytes out of the iterator and parse them with this
+// NOTE: The slice can be longer than the structure, the rest is ignored
+pub trait FromSliceLE
+where
+    Self: Sized,
+{
+    fn from_slice_le(data: &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_from_slice_le_for {
+    ($name: ident) => {
+        impl FromSliceLE for $name {
+            fn from_slice_le(data: &[u8]) -> Option<Self> {
+                let bytes = data.get(..core::mem::size_of::<Self>())?;
+                Some(Self::from_le_bytes(bytes.try_into().ok()?))
+            }
+        }
+    };
+}
+
+impl_from_slice_le_for!(u8);
+impl_from_slice_le_for!(i16);
+impl_from_slice_le_for!(u16);
+impl_from_slice_le_for!(i32);
+impl_from_slice_le_for!(u32);
+impl_from_slice_le_for!(i64);
+impl_from_slice_le_for!(u64);
+
+// Returns: The T at `offset` in the slice, None if the slice is too short
+pub fn read_le_at<T: FromSliceLE>(data: &[u8], offset: usize) -> Option<T> {
+    T::from_slice_le(data.get(offset..)?)
+}
+
+// Returns: The next N bytes of the iterator, so FromBytesLE impls of fixed size structures can parse them with FromSliceLE
+pub fn read_array<const N: usize>(data: &mut impl Iterator<Item = u8>) -> Option<[u8; N]> {
+    let mut res = [0u8; N];
+    for byte in res.iter_mut() {
+        *byte = data.next()?;
+    }
+    Some(res)
+}
@@ -0,0 +1,221 @@
+// A c api for reading files out of a pool, so tools that aren't written in rust (ex. python with ctypes) can use szfs
+// the declarations are in include/szfs.h
+// NOTE: This is only compiled with the "capi" feature
+// Functions that fail return NULL or -1, szfs_last_error then returns why
+// A panic (ex. an assert a corrupt pool got to) can't unwind into c, that would abort the host process, so it's caught and is a failure too
+// The pointers have to be ones these functions returned (or NULL), like with any c api, so the functions don't have safety docs of their own
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    ptr,
+};
+
+use crate::reader::{FileReader, PoolReader};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl Into<Vec<u8>>) {
+    // Messages can't contain nul bytes, but just in case one does it's better to lose the message than to panic across the ffi boundary
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = CString::new(message).ok());
+}
+
+// Runs the body of an exported function, if it panics the message becomes the last error and error_value is returned
+// NOTE: The pool can be left in the middle of something after a panic, but reading from it again is no worse than the corrupt data that caused the panic
+fn catch_panic<T>(error_value: T, body: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(res) => res,
+        Err(payload) => {
+            let message = if let Some(message) = payload.downcast_ref::<&str>() {
+                message.to_string()
+            } else if let Some(message) = payload.downcast_ref::<String>() {
+                message.clone()
+            } else {
+                String::from("unknown panic")
+            };
+            set_last_error(format!("szfs panicked: {message}"));
+            error_value
+        }
+    }
+}
+
+// Returns: None if the pointer is null or the string isn't utf-8
+unsafe fn str_from_ptr<'a>(string: *const c_char) -> Option<&'a str> {
+    if string.is_null() {
+        return None;
+    }
+    CStr::from_ptr(string).to_str().ok()
+}
+
+// Returns: The message of the last error on this thread, or NULL if there wasn't one
+// The string is valid until the next call on this thread that fails
+#[no_mangle]
+pub extern "C" fn szfs_last_error() -> *const c_char {
+    catch_panic(ptr::null(), || {
+        LAST_ERROR.with(|last_error| {
+            last_error
+                .borrow()
+                .as_ref()
+                .map_or(ptr::null(), |message| message.as_ptr())
+        })
+    })
+}
+
+// Opens the pool on the disks (or images of them) at the npaths paths, they are opened read only
+// Returns: NULL on failure, otherwise a pool that has to be closed with szfs_pool_close
+#[no_mangle]
+pub unsafe extern "C" fn szfs_pool_open(
+    paths: *const *const c_char,
+    npaths: usize,
+) -> *mut PoolReader {
+    catch_panic(ptr::null_mut(), || {
+        if paths.is_null() || npaths == 0 {
+            set_last_error("No vdev paths given");
+            return ptr::null_mut();
+        }
+
+        let mut vdev_paths = Vec::with_capacity(npaths);
+        for index in 0..npaths {
+            let Some(path) = str_from_ptr(*paths.add(index)) else {
+                set_last_error(format!("Vdev path {index} is null or not utf-8"));
+                return ptr::null_mut();
+            };
+            vdev_paths.push(PathBuf::from(path));
+        }
+
+        match PoolReader::open(&vdev_paths) {
+            Ok(pool) => Box::into_raw(Box::new(pool)),
+            Err(err) => {
+                set_last_error(err);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn szfs_pool_close(pool: *mut PoolReader) {
+    catch_panic((), || {
+        if !pool.is_null() {
+            drop(Box::from_raw(pool));
+        }
+    })
+}
+
+pub type SzfsDatasetCallback =
+    extern "C" fn(user_data: *mut c_void, object_id: u64, name: *const c_char, is_snapshot: bool);
+
+// Calls the callback once for every dataset (including snapshots), the name is only valid during the call
+// Returns: The number of datasets, -1 on failure
+#[no_mangle]
+pub unsafe extern "C" fn szfs_pool_list_datasets(
+    pool: *mut PoolReader,
+    callback: Option<SzfsDatasetCallback>,
+    user_data: *mut c_void,
+) -> c_int {
+    catch_panic(-1, || {
+        let (Some(pool), Some(callback)) = (pool.as_mut(), callback) else {
+            set_last_error("The pool or the callback is null");
+            return -1;
+        };
+
+        let datasets = pool.list_datasets();
+        for dataset in datasets.iter() {
+            let Ok(name) = CString::new(dataset.name.as_str()) else {
+                continue;
+            };
+            callback(
+                user_data,
+                dataset.object_id,
+                name.as_ptr(),
+                dataset.is_snapshot,
+            );
+        }
+        datasets.len().try_into().unwrap_or(c_int::MAX)
+    })
+}
+
+// Opens the plain file at path (relative to the root of the dataset) in the dataset with the name dataset (ex. "tank/home")
+// Returns: NULL on failure, otherwise a file that has to be closed with szfs_file_close before its pool is closed
+#[no_mangle]
+pub unsafe extern "C" fn szfs_file_open(
+    pool: *mut PoolReader,
+    dataset: *const c_char,
+    path: *const c_char,
+) -> *mut FileReader {
+    catch_panic(ptr::null_mut(), || {
+        let Some(pool) = pool.as_mut() else {
+            set_last_error("The pool is null");
+            return ptr::null_mut();
+        };
+        let (Some(dataset), Some(path)) = (str_from_ptr(dataset), str_from_ptr(path)) else {
+            set_last_error("The dataset or path is null or not utf-8");
+            return ptr::null_mut();
+        };
+
+        match pool.open_file(dataset, path) {
+            Ok(file) => Box::into_raw(Box::new(file)),
+            Err(err) => {
+                set_last_error(err);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+// Returns: The size of the file in bytes, 0 if file is null or on failure
+#[no_mangle]
+pub unsafe extern "C" fn szfs_file_size(file: *const FileReader) -> u64 {
+    catch_panic(0, || file.as_ref().map_or(0, |file| file.get_size()))
+}
+
+// Like pread(2), reads up to count bytes of the file at offset into buf
+// Returns: The number of bytes read, 0 at the end of the file, -1 on failure
+#[no_mangle]
+pub unsafe extern "C" fn szfs_file_pread(
+    pool: *mut PoolReader,
+    file: *mut FileReader,
+    buf: *mut u8,
+    count: usize,
+    offset: u64,
+) -> isize {
+    catch_panic(-1, || {
+        let (Some(pool), Some(file)) = (pool.as_mut(), file.as_mut()) else {
+            set_last_error("The pool or the file is null");
+            return -1;
+        };
+        if buf.is_null() && count != 0 {
+            set_last_error("The buffer is null");
+            return -1;
+        }
+
+        // The return value has to fit the count
+        let count = count.min(isize::MAX as usize);
+        match pool.read_file(file, offset, count) {
+            Ok(data) => {
+                ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+                data.len() as isize
+            }
+            Err(()) => {
+                set_last_error(format!(
+                    "{count} bytes at offset {offset} of the file can't be read"
+                ));
+                -1
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn szfs_file_close(file: *mut FileReader) {
+    catch_panic((), || {
+        if !file.is_null() {
+            drop(Box::from_raw(file));
+        }
+    })
+}
@@ -0,0 +1,185 @@
+// Statistics about everything that can be reached from an uberblock: how many objects of every type there are,
+// which compression and checksum methods the blocks use, how big they are and how much each dataset uses
+// This is useful to get an idea of what's on a pool, and on healthy pools the numbers can be compared with zdb -bb to check szfs
+// Source: https://github.com/openzfs/zfs/blob/master/cmd/zdb/zdb.c (dump_block_stats)
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::{
+    dmu::ObjType,
+    dsl::DSLDatasetData,
+    traverse::{self, ObjSetId},
+    zio::{BlockPointer, EmbeddedType, Vdevs},
+};
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct BlockStats {
+    pub nblocks: u64,
+    pub lsize: u64,
+    pub psize: u64,
+    // Allocated size of all copies, embedded blocks don't take up any space
+    pub asize: u64,
+}
+
+impl BlockStats {
+    fn add(&mut self, bp: &BlockPointer) {
+        self.nblocks += 1;
+        self.lsize += bp.parse_logical_size();
+        self.psize += bp.parse_physical_size();
+        if let BlockPointer::Normal(bp) = bp {
+            self.asize += bp
+                .get_dvas()
+                .iter()
+                .flatten()
+                .map(|dva| dva.parse_allocated_size())
+                .sum::<u64>();
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DatasetCensus {
+    pub nobjects: u64,
+    // What we found by walking the objset
+    pub blocks: BlockStats,
+    // What the dsl dataset says, there is none for the MOS
+    pub used_bytes: Option<u64>,
+    pub compressed_bytes: Option<u64>,
+    pub uncompressed_bytes: Option<u64>,
+    pub is_snapshot: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PoolCensus {
+    pub objects_per_type: BTreeMap<String, u64>,
+    // By the type in the block pointer, which is the type of the object the block belongs to
+    pub blocks_per_type: BTreeMap<String, BlockStats>,
+    pub compression_methods: BTreeMap<String, u64>,
+    // Embedded blocks don't have a checksum, so they aren't counted here
+    pub checksum_methods: BTreeMap<String, u64>,
+    // Logical size -> number of data (level 0) blocks of that size
+    pub data_block_sizes: BTreeMap<u64, u64>,
+    pub nembedded_blocks: u64,
+    pub ngang_blocks: u64,
+    // Redacted blocks are embedded block pointers without data, they are counted as embedded blocks too
+    pub nredacted_blocks: u64,
+    // Block pointers whose type doesn't match the object they belong to (see dmu::TypeMismatch), by "<type of the object> -> <type in the block pointer>"
+    // On a healthy pool this is empty
+    pub type_mismatches: BTreeMap<String, u64>,
+    // Block pointers whose level doesn't match where they are in the tree
+    pub nlevel_mismatches: u64,
+    // Key is the object id of the dsl dataset in the MOS, the MOS itself is 0 (like in the error log)
+    pub datasets: BTreeMap<u64, DatasetCensus>,
+}
+
+fn get_objset_key(objset: ObjSetId) -> u64 {
+    match objset {
+        ObjSetId::Mos => 0,
+        ObjSetId::Dataset(dataset) => dataset,
+    }
+}
+
+// NOTE: Blocks shared between datasets (ex. with snapshots) are counted once for every dataset, like traverse_pool visits them
+pub fn take_census(rootbp: &mut BlockPointer, vdevs: &mut Vdevs) -> Result<PoolCensus, ()> {
+    // Both visitors need to update the census, but only one of them is ever running
+    let census = std::cell::RefCell::new(PoolCensus::default());
+    let mut nvisited: u64 = 0;
+    traverse::traverse_pool_with_objects(
+        rootbp,
+        vdevs,
+        &mut |location, bp, _| {
+            nvisited += 1;
+            if nvisited.is_multiple_of(64 * 1024) {
+                println!("Counted {nvisited} blocks so far ...");
+            }
+
+            let mut census = census.borrow_mut();
+            census
+                .blocks_per_type
+                .entry(String::from(bp.get_type().get_name()))
+                .or_default()
+                .add(bp);
+            census
+                .datasets
+                .entry(get_objset_key(location.objset))
+                .or_default()
+                .blocks
+                .add(bp);
+            if let Some(mismatch) = location.check_block_pointer(bp) {
+                if mismatch.is_type_wrong() {
+                    *census
+                        .type_mismatches
+                        .entry(format!(
+                            "{} -> {}",
+                            mismatch.expected_type.get_name(),
+                            mismatch.found_type.get_name()
+                        ))
+                        .or_default() += 1;
+                }
+                if mismatch.is_level_wrong() {
+                    census.nlevel_mismatches += 1;
+                }
+            }
+            if location.level == 0 {
+                *census
+                    .data_block_sizes
+                    .entry(bp.parse_logical_size())
+                    .or_default() += 1;
+            }
+
+            match bp {
+                BlockPointer::Normal(bp) => {
+                    *census
+                        .compression_methods
+                        .entry(String::from(bp.get_compression_method().get_name()))
+                        .or_default() += 1;
+                    *census
+                        .checksum_methods
+                        .entry(String::from(bp.get_checksum_method().get_name()))
+                        .or_default() += 1;
+                    if bp.get_dvas().iter().flatten().any(|dva| dva.is_gang()) {
+                        census.ngang_blocks += 1;
+                    }
+                }
+                BlockPointer::Embedded(bp) => {
+                    *census
+                        .compression_methods
+                        .entry(String::from(bp.get_compression_method().get_name()))
+                        .or_default() += 1;
+                    census.nembedded_blocks += 1;
+                    if bp.get_embedded_data_type() == EmbeddedType::Redacted {
+                        census.nredacted_blocks += 1;
+                    }
+                }
+            }
+        },
+        &mut |objset, object_id, obj_type, dnode| {
+            let mut census = census.borrow_mut();
+            *census
+                .objects_per_type
+                .entry(String::from(obj_type.get_name()))
+                .or_default() += 1;
+            census
+                .datasets
+                .entry(get_objset_key(objset))
+                .or_default()
+                .nobjects += 1;
+
+            if objset == ObjSetId::Mos && obj_type == ObjType::DSLDataset {
+                let Some(dataset) =
+                    DSLDatasetData::from_bytes_le(&mut dnode.get_bonus_data().iter().copied())
+                else {
+                    return;
+                };
+                let dataset_census = census.datasets.entry(object_id).or_default();
+                dataset_census.used_bytes = Some(dataset.get_used_bytes());
+                dataset_census.compressed_bytes = Some(dataset.get_compressed_bytes());
+                dataset_census.uncompressed_bytes = Some(dataset.get_uncompressed_bytes());
+                dataset_census.is_snapshot = dataset.is_snapshot();
+            }
+        },
+    )?;
+    Ok(census.into_inner())
+}
@@ -0,0 +1,703 @@
+// Command line handling that is shared by the binaries
+// Every binary that reads a pool takes its disks with --vdev (once per disk), they are put in the order they are in the raidz using the ids in their labels
+// The pool options can also be put in a toml config file (--config), so they don't have to be repeated for every binary, options given on the command line win
+// Example config:
+//     vdevs = ["/dev/sda", "/dev/sdb", "/dev/sdc", "/dev/sdd"]
+//     label_index = 0
+//     output_dir = "recovered"
+
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    damage_map::{self, DamageMap},
+    device_map::{self, DeviceLayout},
+    features, nvlist,
+    recovery::control::{CancellationToken, RateLimiter},
+    rewind, spa_config, yolo_block_recovery,
+    zio::{self, ReadPolicy},
+    ReadOnlyVdev, Uberblock, Vdev, VdevFile, VdevGeometry, VdevLabel, VdevRaidz,
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub vdevs: Vec<PathBuf>,
+    pub label_index: Option<usize>,
+    pub txg: Option<u64>,
+    pub output_dir: Option<PathBuf>,
+    pub max_read_rate: Option<u64>,
+    pub damage_maps: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|err| format!("Config file {path:?} can't be read: {err}"))?;
+        toml::from_str(&contents).map_err(|err| format!("Config file {path:?} is invalid: {err}"))
+    }
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct PoolArgs {
+    /// A disk of the pool, give it once for every disk, if their labels can be read they are put in the right order automatically, otherwise give them in the order they are in the raidz
+    #[arg(long = "vdev", value_name = "PATH")]
+    pub vdevs: Vec<PathBuf>,
+
+    /// Which label of the first disk to read the pool config from (0-3) [default: 0]
+    #[arg(long, value_name = "INDEX")]
+    pub label_index: Option<usize>,
+
+    /// Only use the uberblock with this txg, instead of the newest one that can be read
+    #[arg(long)]
+    pub txg: Option<u64>,
+
+    /// Where to write (and read back) the files made by the binaries [default: the current directory]
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Read at most this many MiB per second from every disk, so a disk that is failing isn't made worse by the recovery
+    #[arg(long, value_name = "MIB_PER_SECOND")]
+    pub max_read_rate: Option<u64>,
+
+    /// A ddrescue (or HDDSuperClone) map file of a disk, give one for every disk in the same order as --vdev, the copies of blocks on the parts it couldn't read are tried last
+    #[arg(long = "damage-map", value_name = "PATH")]
+    pub damage_maps: Vec<PathBuf>,
+
+    /// A toml file with values for the options above
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Open the disks read-write, without this every write to them is refused (this can't be set in the config file)
+    #[arg(long)]
+    pub allow_write: bool,
+
+    /// When no copy of a block has the right checksum, use one anyway instead of zeros, its data is probably damaged (this can't be set in the config file)
+    #[arg(long)]
+    pub no_verify_checksums: bool,
+
+    /// When no copy of a block decompresses fully, use the part that does instead of zeros (this can't be set in the config file)
+    #[arg(long)]
+    pub accept_partial_decompression: bool,
+
+    /// How many more times to read a block when a disk returns an error [default: 0]
+    #[arg(long, value_name = "N")]
+    pub read_retries: Option<usize>,
+}
+
+impl PoolArgs {
+    // Fills in the options that weren't given on the command line from the config file, if there is one
+    pub fn load_config(mut self) -> Result<PoolArgs, String> {
+        let Some(config_path) = &self.config else {
+            return Ok(self);
+        };
+        let config = Config::from_file(config_path)?;
+        if self.vdevs.is_empty() {
+            self.vdevs = config.vdevs;
+        }
+        self.label_index = self.label_index.or(config.label_index);
+        self.txg = self.txg.or(config.txg);
+        self.output_dir = self.output_dir.or(config.output_dir);
+        self.max_read_rate = self.max_read_rate.or(config.max_read_rate);
+        if self.damage_maps.is_empty() {
+            self.damage_maps = config.damage_maps;
+        }
+        Ok(self)
+    }
+
+    pub fn output_path(&self, file_name: impl AsRef<Path>) -> PathBuf {
+        output_path(self.output_dir.as_deref(), file_name)
+    }
+
+    // Returns: Only the uberblocks with the requested txg, if one was requested
+    // Returns: None if there is no limit, it's made for every disk, the workers of a scan then share the one of their disk (see Pool::get_rate_limiters)
+    pub fn make_rate_limiter(&self) -> Option<RateLimiter> {
+        RateLimiter::new(self.max_read_rate?.saturating_mul(1024 * 1024))
+    }
+
+    pub fn read_policy(&self) -> ReadPolicy {
+        ReadPolicy {
+            verify_checksums: !self.no_verify_checksums,
+            accept_partial_decompression: self.accept_partial_decompression,
+            max_retries: self.read_retries.unwrap_or(0),
+        }
+    }
+
+    pub fn filter_uberblocks(&self, uberblocks: Vec<Uberblock>) -> Vec<Uberblock> {
+        match self.txg {
+            Some(txg) => uberblocks.into_iter().filter(|ub| ub.txg == txg).collect(),
+            None => uberblocks,
+        }
+    }
+}
+
+// The guids in the config in the label of a disk, they say which disk it is and which pool and top level vdev it is a part of
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h (ZPOOL_CONFIG_GUID, ZPOOL_CONFIG_TOP_GUID, ZPOOL_CONFIG_POOL_GUID)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceGuids {
+    pub guid: u64,
+    pub top_guid: Option<u64>,
+    pub pool_guid: Option<u64>,
+}
+
+impl DeviceGuids {
+    // Returns: None if the label can't be read or has no guid
+    pub fn read(device: &mut dyn Vdev, label_index: usize) -> Option<DeviceGuids> {
+        let label = VdevLabel::from_bytes(&device.read_raw_label(label_index).ok()?);
+        let name_value_pairs =
+            nvlist::from_bytes_xdr(&mut label.get_name_value_pairs_raw().iter().copied())?;
+        let get_u64 = |name: &str| match name_value_pairs.get(name) {
+            Some(nvlist::Value::U64(value)) => Some(*value),
+            _ => None,
+        };
+        Some(DeviceGuids {
+            guid: get_u64("guid")?,
+            top_guid: get_u64("top_guid"),
+            pool_guid: get_u64("pool_guid"),
+        })
+    }
+}
+
+// Returns: true if the vdev or one of the vdevs under it (ex. if a disk was being replaced) has this guid
+fn vdev_tree_contains_guid(vdev_tree: &nvlist::NVList, guid: u64) -> bool {
+    if let Some(nvlist::Value::U64(vdev_guid)) = vdev_tree.get("guid") {
+        if *vdev_guid == guid {
+            return true;
+        }
+    }
+    match vdev_tree.get("children") {
+        Some(nvlist::Value::NVListArray(children)) => children
+            .iter()
+            .any(|child| vdev_tree_contains_guid(child, guid)),
+        _ => false,
+    }
+}
+
+// Returns: The sum of the guids of the vdev and all the vdevs under it, None if one of them has no guid
+fn sum_vdev_tree_guids(vdev_tree: &nvlist::NVList) -> Option<u64> {
+    let Some(nvlist::Value::U64(guid)) = vdev_tree.get("guid") else {
+        return None;
+    };
+    let mut sum = *guid;
+    if let Some(nvlist::Value::NVListArray(children)) = vdev_tree.get("children") {
+        for child in children {
+            sum = sum.wrapping_add(sum_vdev_tree_guids(child)?);
+        }
+    }
+    Some(sum)
+}
+
+// The disks of the pool and the config from one of their labels, the raidz itself borrows the disks so it's made with get_raidz
+pub struct Pool {
+    pub devices: Vec<ReadOnlyVdev<VdevFile>>,
+    pub label: VdevLabel,
+    pub name_value_pairs: nvlist::NVList,
+    pub ashift: u64,
+    pub nparity: usize,
+}
+
+impl Pool {
+    pub fn open(args: &PoolArgs) -> Result<Pool, String> {
+        if args.vdevs.is_empty() {
+            return Err(String::from(
+                "No vdevs given, use --vdev once for every disk of the pool (or put them in the config file)",
+            ));
+        }
+
+        let mut devices = Vec::new();
+        for (index, path) in args.vdevs.iter().enumerate() {
+            devices.push(
+                open_vdev(path, args.allow_write, args.make_rate_limiter())
+                    .map_err(|err| format!("Vdev {index} ({path:?}) can't be opened: {err}"))?,
+            );
+        }
+
+        for (index, device) in devices.iter().enumerate() {
+            let geometry = device.get_inner().get_geometry();
+            if geometry != VdevGeometry::default() {
+                use crate::ansi_color::*;
+                println!("{CYAN}Info{WHITE}: Vdev {index} doesn't start at the start of its file (ex. it is in a partition), using {geometry:?}");
+            }
+        }
+
+        let label_index = args.label_index.unwrap_or(0);
+        if label_index >= devices[0].get_nlables() {
+            return Err(format!(
+                "There is no label {label_index}, a vdev only has {} labels",
+                devices[0].get_nlables()
+            ));
+        }
+        let label = VdevLabel::from_bytes(
+            &devices[0]
+                .read_raw_label(label_index)
+                .map_err(|_| format!("Vdev label {label_index} can't be read"))?,
+        );
+
+        let name_value_pairs = match nvlist::from_bytes_xdr(
+            &mut label.get_name_value_pairs_raw().iter().copied(),
+        ) {
+            Some(name_value_pairs) => name_value_pairs,
+            None => {
+                use crate::ansi_color::*;
+                println!("{YELLOW}Warning{WHITE}: Name value pairs in vdev label {label_index} aren't valid, looking for the config in the MOS instead");
+                find_config_in_mos(&mut devices, args).ok_or(format!(
+                        "Name value pairs in vdev label {label_index} aren't valid, and the MOS couldn't be read to get the config from it"
+                    ))?
+            }
+        };
+        let Some(nvlist::Value::NVList(vdev_tree)) = name_value_pairs.get("vdev_tree") else {
+            return Err(String::from("vdev_tree is not an nvlist"));
+        };
+        let Some(nvlist::Value::U64(ashift)) = vdev_tree.get("ashift") else {
+            return Err(String::from("No ashift found for the top level vdev"));
+        };
+        // Labels of older pools might not have it, 1 is what this used to always assume
+        let nparity = match vdev_tree.get("nparity") {
+            Some(nvlist::Value::U64(nparity)) => *nparity as usize,
+            _ => 1,
+        };
+        if devices.len() <= nparity {
+            return Err(format!(
+                "A raidz with {nparity} parity disks needs more than {} vdevs",
+                devices.len()
+            ));
+        }
+
+        Ok(Pool {
+            ashift: *ashift,
+            nparity,
+            devices,
+            label,
+            name_value_pairs,
+        })
+    }
+
+    // Checks that the disks are all different disks of the raidz in the config, and puts them in the order they are in the raidz
+    // the order is the id of the child of the top level vdev the disk is under, so it doesn't matter in what order they were given
+    // Returns: For every disk, the index it had before, None if the labels of the disks couldn't be read so their order couldn't be checked
+    //          Err if a disk was given twice or isn't a part of the raidz
+    pub fn order_devices(&mut self, label_index: usize) -> Result<Option<Vec<usize>>, String> {
+        use crate::ansi_color::*;
+        let get_u64 = |name: &str| match self.name_value_pairs.get(name) {
+            Some(nvlist::Value::U64(value)) => Some(*value),
+            _ => None,
+        };
+        let (pool_guid, top_guid) = (get_u64("pool_guid"), get_u64("top_guid"));
+
+        let mut device_guids = Vec::new();
+        for (index, device) in self.devices.iter_mut().enumerate() {
+            let Some(guids) = DeviceGuids::read(device, label_index) else {
+                println!("{YELLOW}Warning{WHITE}: Label {label_index} of vdev {index} can't be read, so the order of the disks can't be checked!");
+                return Ok(None);
+            };
+            device_guids.push(guids);
+        }
+
+        let children = match self.get_vdev_tree().get("children") {
+            Some(nvlist::Value::NVListArray(children)) => Some(children),
+            _ => None,
+        };
+        let mut ids = Vec::new();
+        let mut seen_guids = HashMap::<u64, usize>::new();
+        for (index, guids) in device_guids.iter().enumerate() {
+            if let Some(other_index) = seen_guids.insert(guids.guid, index) {
+                return Err(format!(
+                    "Vdev {other_index} and vdev {index} are the same disk (guid {}), was it given twice",
+                    guids.guid
+                ));
+            }
+            if guids.pool_guid != pool_guid {
+                return Err(format!(
+                    "Vdev {index} is a part of another pool (pool guid {:?} instead of {pool_guid:?})",
+                    guids.pool_guid
+                ));
+            }
+            if guids.top_guid != top_guid {
+                return Err(format!(
+                    "Vdev {index} is a part of another top level vdev (top guid {:?} instead of {top_guid:?})",
+                    guids.top_guid
+                ));
+            }
+
+            let Some(children) = children else {
+                // The top level vdev is the disk itself
+                ids.push(0);
+                continue;
+            };
+            let child = children
+                .iter()
+                .find(|child| vdev_tree_contains_guid(child, guids.guid))
+                .ok_or_else(|| {
+                    format!(
+                        "Vdev {index} (guid {}) isn't in the vdev tree of the pool",
+                        guids.guid
+                    )
+                })?;
+            let Some(nvlist::Value::U64(id)) = child.get("id") else {
+                println!("{YELLOW}Warning{WHITE}: The vdev tree has no id for vdev {index}, so the order of the disks can't be checked!");
+                return Ok(None);
+            };
+            ids.push(*id);
+        }
+
+        if let Some(children) = children {
+            if children.len() != self.devices.len() {
+                println!(
+                    "{YELLOW}Warning{WHITE}: The raidz has {} disks, but {} were given!",
+                    children.len(),
+                    self.devices.len()
+                );
+            }
+        }
+
+        let mut order = (0..self.devices.len()).collect::<Vec<_>>();
+        order.sort_by_key(|index| ids[*index]);
+        let mut devices = std::mem::take(&mut self.devices)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        self.devices = order
+            .iter()
+            .map(|index| devices[*index].take().unwrap())
+            .collect();
+        Ok(Some(order))
+    }
+
+    // The uberblocks have the sum of the guids of every vdev in the pool, that can be checked against the config in the label
+    // Returns: None if the pool has more than one top level vdev, then the label only has the part of the vdev tree the disk is in
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/vdev.c (vdev_guid_sum is the sum over the whole tree, starting at the root whose guid is the pool guid)
+    pub fn get_label_guid_sum(&self) -> Option<u64> {
+        let get_u64 = |name: &str| match self.name_value_pairs.get(name) {
+            Some(nvlist::Value::U64(value)) => Some(*value),
+            _ => None,
+        };
+        if get_u64("vdev_children")? != 1 {
+            return None;
+        }
+        Some(get_u64("pool_guid")?.wrapping_add(sum_vdev_tree_guids(self.get_vdev_tree())?))
+    }
+
+    pub fn get_vdev_tree(&self) -> &nvlist::NVList {
+        let Some(nvlist::Value::NVList(vdev_tree)) = self.name_value_pairs.get("vdev_tree") else {
+            unreachable!("Pool::open checks that there is a vdev_tree");
+        };
+        vdev_tree
+    }
+
+    pub fn get_asize(&self) -> usize {
+        2_usize.pow(self.ashift as u32)
+    }
+
+    // In the order of the disks, for cli::open_worker_vdevs
+    pub fn get_rate_limiters(&self) -> Vec<Option<RateLimiter>> {
+        self.devices
+            .iter()
+            .map(|device| device.get_inner().get_rate_limiter().cloned())
+            .collect()
+    }
+
+    pub fn get_raidz(&mut self) -> VdevRaidz<'_> {
+        let asize = self.get_asize();
+        make_raidz(&mut self.devices, self.nparity, asize)
+    }
+
+    // Returns: The spa version of the pool, 5000 if it uses feature flags, None if the config doesn't have it
+    pub fn get_version(&self) -> Option<u64> {
+        match self.name_value_pairs.get("version") {
+            Some(nvlist::Value::U64(version)) => Some(*version),
+            _ => None,
+        }
+    }
+
+    // Returns: The uberblocks of all the labels of the first disk, sorted by txg
+    // NOTE: The config is rewritten when the pool is upgraded, so an uberblock of a newer version than the config isn't from this pool
+    // (or the config is damaged), and what it points at might use structures the config doesn't say are there, so it's skipped
+    pub fn collect_uberblocks(&mut self) -> Vec<Uberblock> {
+        let mut uberblocks = rewind::collect_uberblocks(&mut self.devices[0]);
+        let nuberblocks = uberblocks.len();
+        let version = self
+            .get_version()
+            .filter(|version| features::is_valid_version(*version));
+        uberblocks.retain(|ub| {
+            features::is_valid_version(ub.version)
+                && version.is_none_or(|version| ub.version <= version)
+        });
+        if uberblocks.len() != nuberblocks {
+            use crate::ansi_color::*;
+            println!("{YELLOW}Warning{WHITE}: Skipped {} uberblocks with an unknown version or a newer version than the pool ({version:?})!", nuberblocks - uberblocks.len());
+        }
+        uberblocks
+    }
+
+    // Returns: The uberblocks of just the label the config was read from, sorted by txg
+    pub fn get_label_uberblocks(&self) -> Vec<Uberblock> {
+        use crate::byte_iter::FromBytes;
+        let mut uberblocks = Vec::new();
+        for i in 0..self.label.get_raw_uberblock_count() {
+            let Some(raw_uberblock) = self.label.get_raw_uberblock(i) else {
+                continue;
+            };
+            if let Some(uberblock) = Uberblock::from_bytes(&mut raw_uberblock.iter().copied()) {
+                uberblocks.push(uberblock);
+            }
+        }
+        uberblocks.sort_unstable_by_key(|ub| ub.txg);
+        uberblocks
+    }
+}
+
+// Returns: The disk, which will refuse writes unless allow_write is set
+// NOTE: The file is only opened with write access if allow_write is set, so even the os will refuse writes otherwise
+// NOTE: If there is no label at the start of the file, ex. because it's an image of a whole disk with a partition table
+// the vdev is the zfs partition in the partition table, and if there is none the labels are searched for
+pub fn open_vdev(
+    path: &Path,
+    allow_write: bool,
+    rate_limiter: Option<RateLimiter>,
+) -> std::io::Result<ReadOnlyVdev<VdevFile>> {
+    let file = if allow_write {
+        OpenOptions::new().read(true).write(true).open(path)?
+    } else {
+        File::open(path)?
+    };
+    let mut vdev = VdevFile::from(file);
+    vdev.set_rate_limiter(rate_limiter);
+    #[cfg(feature = "mmap")]
+    if vdev.map_into_memory().is_err() {
+        use crate::ansi_color::*;
+        println!(
+            "{YELLOW}Warning{WHITE}: {} can't be memory mapped, it will be read normally!",
+            path.display()
+        );
+    }
+    if !vdev.has_label_at_start() {
+        if let Some(geometry) = vdev
+            .find_partition_geometry()
+            .or_else(|| vdev.detect_geometry())
+        {
+            vdev.set_geometry(geometry);
+        }
+    }
+
+    if allow_write {
+        Ok(ReadOnlyVdev::allowing_writes(vdev))
+    } else {
+        Ok(ReadOnlyVdev::new(vdev))
+    }
+}
+
+// Parallel scans open the disks again for every worker, they only read so they are always opened read only
+// the rate limiters are the ones of the disks of the pool (from Pool::get_rate_limiters), so the limit is for all of the workers together
+pub fn open_worker_vdevs(
+    vdev_paths: &[PathBuf],
+    rate_limiters: &[Option<RateLimiter>],
+) -> Vec<ReadOnlyVdev<VdevFile>> {
+    vdev_paths
+        .iter()
+        .enumerate()
+        .map(|(index, path)| {
+            open_vdev(path, false, rate_limiters.get(index).cloned().flatten())
+                .expect("Vdev should be able to be opened!")
+        })
+        .collect()
+}
+
+// Without a config the layout of the raidz isn't known, but only the right layout gives a MOS whose checksum matches
+// so every likely layout is tried, with the uberblocks of the labels (they are usually still there when the config isn't)
+// Returns: The MOS config as if it was in the label of the disks, see spa_config::into_label_config
+// NOTE: The disks have to be given in the order they are in the raidz, their labels can't say it anymore
+fn find_config_in_mos(
+    devices: &mut [ReadOnlyVdev<VdevFile>],
+    args: &PoolArgs,
+) -> Option<nvlist::NVList> {
+    use crate::ansi_color::*;
+    let mut uberblocks = args.filter_uberblocks(rewind::collect_uberblocks(&mut devices[0]));
+    if uberblocks.is_empty() {
+        return None;
+    }
+    // 4k sectors are the most common nowadays, then 512 byte and 8k ones
+    for ashift in [12, 9, 13] {
+        for nparity in 1..devices.len().min(4) {
+            let mut vdev_raidz = make_raidz(devices, nparity, 1 << ashift);
+            let mut vdevs = zio::Vdevs::new();
+            vdevs.insert(0usize, &mut vdev_raidz);
+            let Some((mut mos, mos_selection)) =
+                rewind::open_newest_mos(&mut uberblocks, &mut vdevs)
+            else {
+                continue;
+            };
+            let Ok(config) = spa_config::read_mos_config(&mut mos, &mut vdevs) else {
+                println!("{YELLOW}Warning{WHITE}: The MOS of txg {} can be read with ashift {ashift} and {nparity} parity disks, but it has no readable config!", mos_selection.used_txg);
+                continue;
+            };
+            println!("{CYAN}Info{WHITE}: Using the config in the MOS of txg {}, it was found with ashift {ashift} and {nparity} parity disks", mos_selection.used_txg);
+            return spa_config::into_label_config(config, 0);
+        }
+    }
+    None
+}
+
+// Parallel scans open the disks again for every worker, this makes the raidz for those
+pub fn make_raidz<'a, V: Vdev + 'a>(
+    devices: &'a mut [V],
+    nparity: usize,
+    asize: usize,
+) -> VdevRaidz<'a> {
+    let ndevices = devices.len();
+    let mut raidz_devices = crate::zio::Vdevs::new();
+    for (index, device) in devices.iter_mut().enumerate() {
+        raidz_devices.insert(index, device);
+    }
+    VdevRaidz::from_vdevs(raidz_devices, ndevices, nparity, asize)
+}
+
+// Returns: Where the binaries should put (or look for) the file with this name, the output directory is created if it doesn't exist yet
+pub fn output_path(output_dir: Option<&Path>, file_name: impl AsRef<Path>) -> PathBuf {
+    let Some(output_dir) = output_dir else {
+        return file_name.as_ref().to_path_buf();
+    };
+    if let Err(err) = fs::create_dir_all(output_dir) {
+        use crate::ansi_color::*;
+        println!("{YELLOW}Warning{WHITE}: Output directory {output_dir:?} can't be created: {err}");
+    }
+    output_dir.join(file_name)
+}
+
+// Prints the error and exits, for errors the binaries can't continue after
+pub fn exit_with_error(message: impl std::fmt::Display) -> ! {
+    use crate::ansi_color::*;
+    println!("{RED}Fatal{WHITE}: {message}!");
+    std::process::exit(1);
+}
+
+// For the binaries that run for a long time, so stopping them with Ctrl-C doesn't lose the progress since the last checkpoint
+pub fn cancel_on_ctrl_c() -> CancellationToken {
+    use crate::ansi_color::*;
+    let cancel = CancellationToken::new();
+    match cancel.cancel_on_ctrl_c() {
+        Ok(()) => println!("{CYAN}Info{WHITE}: Press Ctrl-C to stop after the current chunk (the progress is saved), press it again to stop right away"),
+        Err(()) => println!("{YELLOW}Warning{WHITE}: The Ctrl-C handler can't be installed, Ctrl-C will stop right away and lose the progress since the last checkpoint"),
+    }
+    cancel
+}
+
+// Parses the arguments and the config file and opens the pool, this is what most binaries start with
+// The maps are in the order of the disks after open_pool put them in order
+fn load_damage_maps(args: &PoolArgs, ndevices: usize) {
+    use crate::ansi_color::*;
+    if args.damage_maps.is_empty() {
+        return;
+    }
+    if args.damage_maps.len() != ndevices {
+        exit_with_error(format!(
+            "There are {ndevices} disks but {} damage maps, give one for every disk",
+            args.damage_maps.len()
+        ));
+    }
+    for (device_index, path) in args.damage_maps.iter().enumerate() {
+        let contents = fs::read_to_string(path).unwrap_or_else(|err| {
+            exit_with_error(format!("Damage map {path:?} can't be read: {err}"))
+        });
+        let map = DamageMap::from_ddrescue_map(&contents).unwrap_or_else(|| {
+            exit_with_error(format!("Damage map {path:?} isn't a ddrescue map file"))
+        });
+        println!(
+            "{CYAN}Info{WHITE}: Disk {device_index} has {} bad bytes in {} ranges according to {path:?}",
+            map.get_bad_size(),
+            map.get_bad_ranges().len()
+        );
+        damage_map::set_damage_map(0, device_index, map);
+    }
+}
+
+pub fn open_pool(args: PoolArgs) -> (PoolArgs, Pool) {
+    let mut args = args
+        .load_config()
+        .unwrap_or_else(|err| exit_with_error(err));
+    let mut pool = Pool::open(&args).unwrap_or_else(|err| exit_with_error(err));
+    use crate::ansi_color::*;
+    match pool
+        .order_devices(args.label_index.unwrap_or(0))
+        .unwrap_or_else(|err| exit_with_error(err))
+    {
+        Some(order) => {
+            if order.iter().enumerate().any(|(new_index, index)| new_index != *index) {
+                println!("{CYAN}Info{WHITE}: The disks weren't given in the order they are in the raidz, using {order:?} instead");
+            }
+            // The paths are used to open the disks again (ex. by the workers of parallel scans) so they have to be in the same order
+            args.vdevs = order.iter().map(|index| args.vdevs[*index].clone()).collect();
+            if args.damage_maps.len() == order.len() {
+                args.damage_maps = order
+                    .iter()
+                    .map(|index| args.damage_maps[*index].clone())
+                    .collect();
+            }
+        }
+        None => println!("{RED}Important{WHITE}: Please make sure the disks are actually in the right order by using the nv_list, i can't actually check that in a reliable way!!!"),
+    }
+
+    if let (Some(label_guid_sum), Some(uberblock)) = (
+        pool.get_label_guid_sum(),
+        pool.get_label_uberblocks().last(),
+    ) {
+        if label_guid_sum != uberblock.guid_sum {
+            println!("{YELLOW}Warning{WHITE}: The sum of the guids in the vdev tree ({label_guid_sum}) doesn't match the one in the newest uberblock ({}), the config in the label might be stale or from another pool!", uberblock.guid_sum);
+        }
+    }
+    if args.allow_write {
+        println!("{RED}Important{WHITE}: The disks were opened with --allow-write, so they can be modified, make sure you have a backup!");
+    }
+    let read_policy = args.read_policy();
+    if !read_policy.is_strict() {
+        println!("{RED}Important{WHITE}: Blocks that fail to verify will be used when there is nothing better, their data is probably damaged, every one of them is printed as a warning!");
+    }
+    zio::set_read_policy(read_policy);
+    // So the dvas in the warnings and reports say where they are on the disks
+    device_map::set_device_layout(
+        0,
+        DeviceLayout {
+            asize: pool.get_asize(),
+            data_starts: pool
+                .devices
+                .iter()
+                .map(|device| {
+                    let geometry = device.get_inner().get_geometry();
+                    geometry.start + geometry.data_offset
+                })
+                .collect(),
+        },
+    );
+    load_damage_maps(&args, pool.devices.len());
+    // So yolo recovery can check that the checksum tables were made for this pool
+    if let Some(nvlist::Value::U64(vdev_guid)) = pool.get_vdev_tree().get("guid") {
+        let mut yolo_config = yolo_block_recovery::get_yolo_config();
+        yolo_config.vdev_guid = Some(*vdev_guid);
+        yolo_block_recovery::set_yolo_config(yolo_config);
+    }
+    match pool.get_version() {
+        Some(version) if version < features::SPA_VERSION_SA => {
+            println!("{CYAN}Info{WHITE}: The pool has version {version}, it's from before system attributes, so files only have the old znode attributes");
+        }
+        Some(version) if !features::is_valid_version(version) => {
+            println!("{YELLOW}Warning{WHITE}: The pool has version {version}, which isn't a known version, the config might be damaged!");
+        }
+        _ => (),
+    }
+    let unsupported_features = features::get_unsupported_read_features(&pool.name_value_pairs);
+    if !unsupported_features.is_empty() {
+        println!("{YELLOW}Warning{WHITE}: The pool uses features that aren't supported: {unsupported_features:?}, some of it might not be readable!");
+    }
+    // Only the first top level vdev is opened, but the dvas of blocks with more than one copy can be on the others
+    if let Some(nvlist::Value::U64(vdev_children)) = pool.name_value_pairs.get("vdev_children") {
+        zio::set_top_level_vdev_count(*vdev_children);
+        if *vdev_children > 1 {
+            println!("{CYAN}Info{WHITE}: The pool has {vdev_children} top level vdevs but only the first one is read, copies of blocks on the others are skipped!");
+        }
+    }
+    (args, pool)
+}
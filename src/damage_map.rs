@@ -0,0 +1,124 @@
+// Which parts of the disks are known to be bad, from the map files ddrescue (or HDDSuperClone, which uses the same format) writes while copying a failing disk
+// the parts it couldn't read are zeros in the copy, so the copies of blocks on them are damaged even if reading them works
+// The read pipeline tries the copies that aren't on bad parts first, and blocks that only have copies on bad parts go to yolo recovery before their copies are read
+// Source: https://www.gnu.org/software/ddrescue/manual/ddrescue_manual.html#Mapfile-structure
+// NOTE: The maps are global like the device layouts, cli::open_pool sets them for the disks of the top level vdev it opens, see device_map
+
+use lazy_static::lazy_static;
+use std::{collections::HashMap, ops::Range, sync::RwLock};
+
+use crate::{device_map, zio::DataVirtualAddress};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DamageMap {
+    // In bytes from the start of the disk (or the image of it), sorted and not overlapping
+    bad_ranges: Vec<Range<u64>>,
+}
+
+impl DamageMap {
+    // Everything that isn't finished ('+') is bad, the parts that weren't tried yet ('?') weren't copied either
+    // Returns: None if a line can't be parsed
+    // Example:
+    //     # Mapfile. Created by GNU ddrescue version 1.27
+    //     # current_pos  current_status  current_pass
+    //     0x1B5E0000     +               1
+    //     #      pos        size  status
+    //     0x00000000  0x1B5E0000  +
+    //     0x1B5E0000  0x00001000  -
+    pub fn from_ddrescue_map(contents: &str) -> Option<DamageMap> {
+        let mut bad_ranges = Vec::new();
+        // The first line that isn't a comment is the status of ddrescue itself
+        let lines = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .skip(1);
+        for line in lines {
+            let mut fields = line.split_whitespace();
+            let pos = parse_number(fields.next()?)?;
+            let size = parse_number(fields.next()?)?;
+            let status = fields.next()?;
+            if status != "+" && size != 0 {
+                bad_ranges.push(pos..pos.checked_add(size)?);
+            }
+        }
+        Some(DamageMap::from_ranges(bad_ranges))
+    }
+
+    pub fn from_ranges(mut bad_ranges: Vec<Range<u64>>) -> DamageMap {
+        bad_ranges.sort_unstable_by_key(|range| range.start);
+        let mut merged: Vec<Range<u64>> = Vec::new();
+        for range in bad_ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        DamageMap { bad_ranges: merged }
+    }
+
+    pub fn get_bad_ranges(&self) -> &[Range<u64>] {
+        &self.bad_ranges
+    }
+
+    pub fn get_bad_size(&self) -> u64 {
+        self.bad_ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum()
+    }
+
+    pub fn is_range_damaged(&self, range: Range<u64>) -> bool {
+        // The first bad range that ends after the start is the only one that can overlap
+        let index = self
+            .bad_ranges
+            .partition_point(|bad_range| bad_range.end <= range.start);
+        self.bad_ranges
+            .get(index)
+            .is_some_and(|bad_range| bad_range.start < range.end)
+    }
+}
+
+// ddrescue writes hex with 0x, but decimal is valid as well
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or(s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+lazy_static! {
+    // By the id of the top level vdev and the index of the disk in it
+    static ref DAMAGE_MAPS: RwLock<HashMap<(u32, usize), DamageMap>> = RwLock::new(HashMap::new());
+}
+
+pub fn set_damage_map(vdev_id: u32, device_index: usize, map: DamageMap) {
+    if let Ok(mut lock) = DAMAGE_MAPS.write() {
+        lock.insert((vdev_id, device_index), map);
+    }
+}
+
+pub fn has_damage_maps() -> bool {
+    DAMAGE_MAPS.read().is_ok_and(|lock| !lock.is_empty())
+}
+
+// Returns: true if a part of the dva (including its parity) is on a bad part of one of the disks
+// NOTE: Without the layout of the vdev (see device_map) it's not known where the dva is, so it's assumed to be fine
+pub fn is_dva_damaged(dva: &DataVirtualAddress) -> bool {
+    if !has_damage_maps() {
+        return false;
+    }
+    let Some(layout) = device_map::get_device_layout(dva.get_vdev_id()) else {
+        return false;
+    };
+    let Ok(lock) = DAMAGE_MAPS.read() else {
+        return false;
+    };
+    layout
+        .map_range(dva.parse_offset(), dva.parse_allocated_size())
+        .into_iter()
+        .any(|range| {
+            lock.get(&(dva.get_vdev_id(), range.device_index))
+                .is_some_and(|map| map.is_range_damaged(range.offset..range.offset + range.size))
+        })
+}
@@ -0,0 +1,300 @@
+// The dedup tables (DDT) map the checksum of every deduplicated block to where it's stored and how many block pointers reference it
+// Since the tables have their own copy of the dvas (and ditto copies for heavily referenced blocks) they can be used to find a block
+// even if the block pointers that point to it are damaged
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/ddt.h
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/ddt_zap.c
+// NOTE: Only the classic on disk format is supported, not the one from the "fast dedup" feature
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    byte_iter::{ByteReader, FromBytesBE, FromBytesLE},
+    dmu::{DNode, DNodeBase, ObjSet, ObjType},
+    zap::{self, ZapLeaf},
+    zio::{self, ChecksumMethod, CompressionMethod, DataVirtualAddress, Vdevs},
+};
+
+// Ditto, single, double and triple copies
+const DDT_PHYS_TYPES: usize = 4;
+
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/ddt_zap.c (ddt_zap_decompress)
+const DDT_COMPRESS_BYTEORDER_MASK: u8 = 0x80;
+const DDT_COMPRESS_FUNCTION_MASK: u8 = 0x7f;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupKey {
+    // Same as the checksum in the block pointers
+    pub checksum: [u64; 4],
+    pub logical_size: u64,
+    pub physical_size: u64,
+    pub compression_method: CompressionMethod,
+}
+
+impl DedupKey {
+    // The key is stored as 5 u64s in the name of the zap entry
+    fn from_bytes_be(data: &mut impl Iterator<Item = u8>) -> Option<DedupKey> {
+        let checksum = [
+            u64::from_bytes_be(data)?,
+            u64::from_bytes_be(data)?,
+            u64::from_bytes_be(data)?,
+            u64::from_bytes_be(data)?,
+        ];
+        let props = u64::from_bytes_be(data)?;
+        Some(DedupKey {
+            checksum,
+            logical_size: ((props & 0xFFFF) + 1) * 512,
+            physical_size: (((props >> 16) & 0xFFFF) + 1) * 512,
+            compression_method: CompressionMethod::from_value(((props >> 32) & 0x7F) as usize)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupPhys {
+    pub dvas: [Option<DataVirtualAddress>; 3],
+    pub refcount: u64,
+    pub phys_birth_txg: u64,
+}
+
+impl<It> FromBytesLE<It> for DedupPhys
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<DedupPhys> {
+        // NOTE: DataVirtualAddress::from_bytes_le always consumes the whole dva, even if it's empty
+        Some(DedupPhys {
+            dvas: [
+                DataVirtualAddress::from_bytes_le(data),
+                DataVirtualAddress::from_bytes_le(data),
+                DataVirtualAddress::from_bytes_le(data),
+            ],
+            refcount: u64::from_bytes_le(data)?,
+            phys_birth_txg: u64::from_bytes_le(data)?,
+        })
+    }
+}
+
+impl DedupPhys {
+    pub const fn get_ondisk_size() -> usize {
+        DataVirtualAddress::get_ondisk_size() * 3 + core::mem::size_of::<u64>() * 2
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DedupTableEntry {
+    pub key: DedupKey,
+    pub checksum_method: ChecksumMethod,
+    // Indexed by the number of copies, index 0 is the ditto copy zfs makes of blocks with a lot of references
+    pub phys: Vec<DedupPhys>,
+}
+
+impl DedupTableEntry {
+    pub fn get_refcount(&self) -> u64 {
+        self.phys.iter().map(|phys| phys.refcount).sum()
+    }
+
+    pub fn get_dvas(&self) -> impl Iterator<Item = &DataVirtualAddress> {
+        self.phys.iter().flat_map(|phys| phys.dvas.iter().flatten())
+    }
+
+    // Tries every copy the entry knows about
+    // If we don't implement the checksum method (ex. sha256, which is what dedup usually uses), accept_unverifiable decides if
+    // the first copy that decompresses to the right size is good enough
+    pub fn read(&self, accept_unverifiable: bool, vdevs: &mut Vdevs) -> Result<Vec<u8>, ()> {
+        for dva in self.get_dvas() {
+            let Ok(data) = dva.dereference(vdevs, self.key.physical_size as usize) else {
+                continue;
+            };
+
+            let checksum_matches = match zio::try_checksum_block(&data, self.checksum_method) {
+                Some(checksum) => checksum == self.key.checksum,
+                None => accept_unverifiable,
+            };
+            if !checksum_matches {
+                continue;
+            }
+
+            let Ok(data) = zio::try_decompress_block(
+                &data,
+                self.key.compression_method,
+                self.key.logical_size as usize,
+            ) else {
+                continue;
+            };
+            if data.len() == self.key.logical_size as usize {
+                return Ok(data);
+            }
+        }
+        Err(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DedupTable {
+    // The name in the object directory ex. "DDT-sha256-zap-duplicate"
+    pub name: String,
+    pub checksum_method: ChecksumMethod,
+    pub object_id: u64,
+}
+
+// Returns: All dedup tables listed in the object directory of the MOS
+pub fn find_dedup_tables(mos: &mut ObjSet, vdevs: &mut Vdevs) -> Result<Vec<DedupTable>, ()> {
+    let Some(DNode::ObjectDirectory(mut object_directory)) = mos.get_dnode_at(1, vdevs) else {
+        return Err(());
+    };
+    let objdir_zap_data = object_directory.dump_zap_contents(vdevs).ok_or(())?;
+
+    let mut tables = Vec::new();
+    for (name, value) in objdir_zap_data.iter() {
+        // The names are DDT-<checksum>-<type>-<class>, there is also a DDT-statistics object which is not a table
+        let Some(parts) = name.strip_prefix("DDT-") else {
+            continue;
+        };
+        let parts = parts.split('-').collect::<Vec<&str>>();
+        let [checksum_name, "zap", _] = parts[..] else {
+            continue;
+        };
+        let zap::Value::U64(object_id) = value else {
+            continue;
+        };
+
+        let Some(checksum_method) = (0..=u8::MAX as usize)
+            .filter_map(ChecksumMethod::from_value)
+            .find(|method| method.get_name() == checksum_name)
+        else {
+            use crate::ansi_color::*;
+            println!("{YELLOW}Warning{WHITE}: Dedup table {name} uses unknown checksum {checksum_name}, skipping it!");
+            continue;
+        };
+
+        tables.push(DedupTable {
+            name: name.clone(),
+            checksum_method,
+            object_id: *object_id,
+        });
+    }
+
+    tables.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    Ok(tables)
+}
+
+fn parse_entry(
+    entry: &zap::ZapRawEntry,
+    checksum_method: ChecksumMethod,
+) -> Option<DedupTableEntry> {
+    use crate::ansi_color::*;
+    let key = DedupKey::from_bytes_be(&mut entry.name.iter().copied())?;
+
+    // The value is compressed, the first byte says how
+    let (header, compressed_phys) = entry.value.split_first()?;
+    if header & DDT_COMPRESS_BYTEORDER_MASK == 0 {
+        if cfg!(feature = "debug") {
+            println!("{YELLOW}Warning{WHITE}: Dedup table entry was written by a big endian machine, this is not supported!");
+        }
+        return None;
+    }
+    let compression_method =
+        CompressionMethod::from_value(usize::from(header & DDT_COMPRESS_FUNCTION_MASK))?;
+    let phys_size = DedupPhys::get_ondisk_size() * DDT_PHYS_TYPES;
+    let phys_data =
+        zio::try_decompress_block(compressed_phys, compression_method, phys_size).ok()?;
+    if phys_data.len() < phys_size {
+        return None;
+    }
+
+    let mut phys_data = ByteReader::new(&phys_data);
+    let mut phys = Vec::new();
+    for _ in 0..DDT_PHYS_TYPES {
+        phys.push(DedupPhys::from_bytes_le(&mut phys_data)?);
+    }
+
+    Some(DedupTableEntry {
+        key,
+        checksum_method,
+        phys,
+    })
+}
+
+// Returns: All entries of the table
+// NOTE: Instead of following the pointer table of the zap every block is tried as a leaf, so this also works if the pointer table is damaged
+pub fn read_dedup_table(
+    mos: &mut ObjSet,
+    table: &DedupTable,
+    vdevs: &mut Vdevs,
+) -> Result<Vec<DedupTableEntry>, ()> {
+    use crate::ansi_color::*;
+    let raw_dnode = mos
+        .get_raw_dnode_at(table.object_id as usize, vdevs)
+        .ok_or(())?;
+    let (mut dnode, obj_type, _) = DNodeBase::from_slice_le(&raw_dnode).ok_or(())?;
+    if obj_type != ObjType::DDTZap {
+        return Err(());
+    }
+
+    let mut entries = Vec::new();
+    let mut nbad_entries = 0;
+    // Block 0 is the zap header
+    for block_id in 1..=dnode.get_max_indirect_block_id() as usize {
+        let Ok(block_data) = dnode.read_block(block_id, vdevs) else {
+            continue;
+        };
+        let Some(leaf) = ZapLeaf::from_slice_le(&block_data, dnode.parse_data_block_size()) else {
+            continue;
+        };
+        let Some(raw_entries) = leaf.get_raw_entries(core::mem::size_of::<u64>()) else {
+            continue;
+        };
+
+        for raw_entry in raw_entries.iter() {
+            match parse_entry(raw_entry, table.checksum_method) {
+                Some(entry) => entries.push(entry),
+                None => nbad_entries += 1,
+            }
+        }
+    }
+
+    if nbad_entries != 0 {
+        println!(
+            "{YELLOW}Warning{WHITE}: Couldn't parse {nbad_entries} entries of dedup table {}!",
+            table.name
+        );
+    }
+    Ok(entries)
+}
+
+// Returns: The entries of every dedup table of the pool
+pub fn read_all_dedup_tables(
+    mos: &mut ObjSet,
+    vdevs: &mut Vdevs,
+) -> Result<Vec<DedupTableEntry>, ()> {
+    let mut entries = Vec::new();
+    for table in find_dedup_tables(mos, vdevs)? {
+        entries.extend(read_dedup_table(mos, &table, vdevs)?);
+    }
+    Ok(entries)
+}
+
+// Returns: The entries indexed by the checksum of the block, so they can be looked up with the checksum in a block pointer
+pub fn index_by_checksum(entries: Vec<DedupTableEntry>) -> HashMap<[u64; 4], DedupTableEntry> {
+    entries
+        .into_iter()
+        .map(|entry| (entry.key.checksum, entry))
+        .collect()
+}
+
+pub fn write_dedup_entries(path: &Path, entries: &[DedupTableEntry]) -> Result<(), ()> {
+    let file = File::create(path).map_err(|_| ())?;
+    serde_json::to_writer_pretty(BufWriter::new(file), entries).map_err(|_| ())
+}
+
+pub fn read_dedup_entries(path: &Path) -> Result<Vec<DedupTableEntry>, ()> {
+    let file = File::open(path).map_err(|_| ())?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|_| ())
+}
@@ -0,0 +1,102 @@
+// Where the data of a dva is on the disks themselves, so the warnings and reports can be matched up with ex. the bad sectors in a ddrescue map file, or read with dd
+// A dva offset is in the allocatable space of a top level vdev, which starts after the first 2 labels and the boot block (4M) of every disk
+// in a raidz the sectors of that space go round robin over the disks, sector n is on disk n % ndevices at sector n / ndevices of the space of that disk
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/vdev_raidz.c (vdev_raidz_map_alloc)
+// NOTE: The layouts are global like the read policy, cli::open_pool sets the one of the top level vdev it opens, dvas on other vdevs are printed without disk offsets
+// NOTE: The offsets are in the files that were given for the disks, so for an image of a whole disk they include everything in front of the zfs partition
+
+use lazy_static::lazy_static;
+use std::{collections::HashMap, fmt::Write, sync::RwLock};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceLayout {
+    pub asize: usize,
+    // Where the allocatable space of every disk starts in its file, in the order of the raidz (a single disk vdev has one)
+    pub data_starts: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceRange {
+    // In the order of the raidz, which is the order of the --vdev options after cli::open_pool put them in order
+    pub device_index: usize,
+    // In bytes from the start of the file
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl DeviceLayout {
+    // Returns: The parts of the disks that `size` bytes at `offset` of the vdev are on, at most one per disk, in the order of the disks
+    // NOTE: Only whole sectors are mapped, and for raidz the size should be the allocated size of the dva, which includes the parity sectors
+    pub fn map_range(&self, offset: u64, size: u64) -> Vec<DeviceRange> {
+        let ndevices = self.data_starts.len() as u64;
+        let asize = self.asize as u64;
+        if ndevices == 0 || asize == 0 {
+            return Vec::new();
+        }
+        let first_sector = offset / asize;
+        // Even 0 bytes are somewhere
+        let end_sector = offset.saturating_add(size.max(1)).div_ceil(asize);
+        let last_sector = end_sector - 1;
+
+        let mut ranges = Vec::new();
+        for (device_index, data_start) in self.data_starts.iter().enumerate() {
+            let device = device_index as u64;
+            // The first and the last sector of the range that are on this disk
+            let first = first_sector + (device + ndevices - first_sector % ndevices) % ndevices;
+            if first > last_sector {
+                continue;
+            }
+            let last = last_sector - (last_sector % ndevices + ndevices - device) % ndevices;
+            ranges.push(DeviceRange {
+                device_index,
+                offset: data_start + first / ndevices * asize,
+                size: (last / ndevices - first / ndevices + 1) * asize,
+            });
+        }
+        ranges
+    }
+}
+
+lazy_static! {
+    // By the id of the top level vdev
+    static ref DEVICE_LAYOUTS: RwLock<HashMap<u32, DeviceLayout>> = RwLock::new(HashMap::new());
+}
+
+pub fn set_device_layout(vdev_id: u32, layout: DeviceLayout) {
+    if let Ok(mut lock) = DEVICE_LAYOUTS.write() {
+        lock.insert(vdev_id, layout);
+    }
+}
+
+pub fn get_device_layout(vdev_id: u32) -> Option<DeviceLayout> {
+    DEVICE_LAYOUTS.read().ok()?.get(&vdev_id).cloned()
+}
+
+// Returns: ex. "disk 0 at 0x401000 (lba 8200) for 0x1000 bytes, disk 1 at ...", None if the layout of the vdev isn't known
+// the lba is in 512 byte sectors, like dd with bs=512 and most disk tools use, ddrescue map files use the byte offsets in hex
+pub fn format_device_ranges(vdev_id: u32, offset: u64, size: u64) -> Option<String> {
+    let ranges = get_device_layout(vdev_id)?.map_range(offset, size);
+    let mut res = String::new();
+    for (index, range) in ranges.iter().enumerate() {
+        if index != 0 {
+            res += ", ";
+        }
+        let _ = write!(
+            res,
+            "disk {} at {:#x} (lba {}) for {:#x} bytes",
+            range.device_index,
+            range.offset,
+            range.offset / 512,
+            range.size
+        );
+    }
+    Some(res)
+}
+
+// Returns: An offset in a vdev (ex. where a scan found a block) and where it is on the disks, if that's known
+pub fn format_offset(vdev_id: u32, offset: u64, size: u64) -> String {
+    match format_device_ranges(vdev_id, offset, size) {
+        Some(ranges) => format!("vdev {vdev_id} offset {offset:#x} ({ranges})"),
+        None => format!("vdev {vdev_id} offset {offset:#x}"),
+    }
+}
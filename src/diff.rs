@@ -0,0 +1,280 @@
+// Comparing two versions of an objset, ex. two snapshots of a dataset or the same dataset in two uberblocks, like a read only zfs diff
+// A block pointer only stays the same if nothing under it changed (its checksum covers the data, and the data of an indirect block is the block pointers under it)
+// so only the parts of the block trees that differ have to be read, for snapshots that share most of their blocks that's a small part of them
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dmu_diff.c
+// NOTE: Unlike zfs diff the two versions don't have to be related, and parts that can't be read are reported instead of failing the whole diff
+
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::{
+    byte_iter::FromSliceLE,
+    dmu::{BonusType, DNodeBase, ObjSet, ObjType},
+    zio::{BlockPointer, Vdevs},
+};
+
+#[derive(Debug, Default)]
+pub struct BlockTreeDiff {
+    // The ids of the data blocks that are different, or only in one of the trees, sorted
+    pub changed_block_ids: Vec<u64>,
+    // The data blocks under indirect blocks that couldn't be read, so it's not known whether they changed
+    pub unknown_block_ids: Vec<Range<u64>>,
+}
+
+// Returns: The block pointers in the indirect block, None for holes, and nothing if the indirect block itself is a hole
+fn read_indirect_block(
+    bp: Option<&BlockPointer>,
+    vdevs: &mut Vdevs,
+) -> Result<Vec<Option<BlockPointer>>, ()> {
+    let Some(bp) = bp else {
+        return Ok(Vec::new());
+    };
+    let indirect_block_data = bp.clone().dereference(vdevs)?;
+    // NOTE: Holes are all zeros (or only have a birth txg), which won't parse
+    Ok(indirect_block_data
+        .chunks(BlockPointer::get_ondisk_size())
+        .map(BlockPointer::from_slice_le)
+        .collect())
+}
+
+fn diff_block_pointers(
+    old: Option<&BlockPointer>,
+    new: Option<&BlockPointer>,
+    level: usize,
+    block_id: u64,
+    bps_per_indirect_block: u64,
+    vdevs: &mut Vdevs,
+    res: &mut BlockTreeDiff,
+) {
+    if old.map(|bp| bp.to_bytes_le()) == new.map(|bp| bp.to_bytes_le()) {
+        return;
+    }
+    if level == 0 {
+        res.changed_block_ids.push(block_id);
+        return;
+    }
+
+    let (Ok(old_children), Ok(new_children)) = (
+        read_indirect_block(old, vdevs),
+        read_indirect_block(new, vdevs),
+    ) else {
+        let nblocks = bps_per_indirect_block.saturating_pow(level as u32);
+        res.unknown_block_ids
+            .push(block_id.saturating_mul(nblocks)..(block_id + 1).saturating_mul(nblocks));
+        return;
+    };
+    for index in 0..old_children.len().max(new_children.len()) {
+        diff_block_pointers(
+            old_children.get(index).and_then(Option::as_ref),
+            new_children.get(index).and_then(Option::as_ref),
+            level - 1,
+            block_id * bps_per_indirect_block + index as u64,
+            bps_per_indirect_block,
+            vdevs,
+            res,
+        );
+    }
+}
+
+// Returns: Which data blocks differ between the data of two dnodes, without reading anything under block pointers that are the same
+// NOTE: If the trees don't have the same shape (ex. a file that grew another indirect level) there is nothing to line up, so every block is counted as changed
+pub fn diff_block_trees(
+    old: &mut DNodeBase,
+    new: &mut DNodeBase,
+    vdevs: &mut Vdevs,
+) -> BlockTreeDiff {
+    let mut res = BlockTreeDiff::default();
+    let n_indirect_levels = old.get_n_indirect_levels();
+    let bps_per_indirect_block =
+        (old.parse_indirect_block_size() / BlockPointer::get_ondisk_size()) as u64;
+    let is_same_shape = n_indirect_levels == new.get_n_indirect_levels()
+        && old.parse_indirect_block_size() == new.parse_indirect_block_size()
+        && old.parse_data_block_size() == new.parse_data_block_size();
+    if !is_same_shape || bps_per_indirect_block == 0 {
+        let max_block_id = old
+            .get_max_indirect_block_id()
+            .max(new.get_max_indirect_block_id());
+        res.changed_block_ids = (0..=max_block_id).collect();
+        return res;
+    }
+    if n_indirect_levels == 0 {
+        return res;
+    }
+
+    let old_bps = old.get_block_pointers().clone();
+    let new_bps = new.get_block_pointers().clone();
+    for index in 0..old_bps.len().max(new_bps.len()) {
+        diff_block_pointers(
+            old_bps.get(index),
+            new_bps.get(index),
+            n_indirect_levels - 1,
+            index as u64,
+            bps_per_indirect_block,
+            vdevs,
+            &mut res,
+        );
+    }
+    res
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectChange {
+    Created,
+    Deleted,
+    Modified,
+    // The object was freed and its id was used again for a new one, this is only known when the type changed
+    // for zpl files the generation in their attributes has to be compared as well, see szfs-diff
+    Replaced,
+}
+
+pub struct ObjectVersion {
+    pub dnode: DNodeBase,
+    pub obj_type: ObjType,
+    pub bonus_type: BonusType,
+}
+
+pub struct ObjectDiff {
+    pub object_id: u64,
+    pub change: ObjectChange,
+    // None if the object isn't in that version
+    pub old: Option<ObjectVersion>,
+    pub new: Option<ObjectVersion>,
+}
+
+impl ObjectDiff {
+    // Returns: Which data blocks changed, only if the object is in both versions
+    pub fn diff_data(&mut self, vdevs: &mut Vdevs) -> Option<BlockTreeDiff> {
+        let (Some(old), Some(new)) = (self.old.as_mut(), self.new.as_mut()) else {
+            return None;
+        };
+        Some(diff_block_trees(&mut old.dnode, &mut new.dnode, vdevs))
+    }
+
+    // The bonus buffer has the attributes, ex. the znode or the system attributes of zpl files
+    pub fn is_bonus_changed(&self) -> bool {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => old.dnode.get_bonus_data() != new.dnode.get_bonus_data(),
+            _ => true,
+        }
+    }
+
+    pub fn get_obj_type(&self) -> Option<ObjType> {
+        self.new
+            .as_ref()
+            .or(self.old.as_ref())
+            .map(|version| version.obj_type)
+    }
+}
+
+#[derive(Default)]
+pub struct ObjSetDiff {
+    // Sorted by object id
+    pub objects: Vec<ObjectDiff>,
+    // The objects in blocks of the meta dnode that couldn't be read, or dnodes that don't make sense, so it's not known whether they changed
+    pub unknown_object_ids: Vec<Range<u64>>,
+}
+
+// Returns: The dnodes in a block of the meta dnode by the slot they start at, the extra slots of big dnodes are skipped
+fn parse_dnode_block(block: &[u8]) -> BTreeMap<usize, &[u8]> {
+    let mut dnodes = BTreeMap::new();
+    let mut slot = 0;
+    while slot * 512 < block.len() {
+        let data = &block[slot * 512..];
+        // Freed dnodes are zeroed, so their type is none
+        if data[0] == ObjType::None as u8 {
+            slot += 1;
+            continue;
+        }
+        let nslots = DNodeBase::get_n_slots_from_bytes_le(data.iter().copied())
+            .filter(|nslots| *nslots != 0 && nslots * 512 <= data.len())
+            .unwrap_or(1);
+        dnodes.insert(slot, &data[..nslots * 512]);
+        slot += nslots;
+    }
+    dnodes
+}
+
+fn parse_object_version(raw_dnode: &[u8]) -> Option<ObjectVersion> {
+    let (dnode, obj_type, bonus_type) = DNodeBase::from_slice_le(raw_dnode)?;
+    Some(ObjectVersion {
+        dnode,
+        obj_type,
+        bonus_type,
+    })
+}
+
+// Returns: The objects that are different in the two versions of the objset, Err if the meta dnodes don't have the same block size (they always have 16K blocks, so at least one of them is corrupt)
+// NOTE: The space accounting dnodes and the zil aren't compared, zfs diff doesn't either
+pub fn diff_objsets(
+    old: &mut ObjSet,
+    new: &mut ObjSet,
+    vdevs: &mut Vdevs,
+) -> Result<ObjSetDiff, ()> {
+    let block_size = old.metadnode.parse_data_block_size();
+    let slots_per_block = (block_size / 512) as u64;
+    if block_size != new.metadnode.parse_data_block_size() || slots_per_block == 0 {
+        return Err(());
+    }
+
+    let metadnode_diff = diff_block_trees(&mut old.metadnode, &mut new.metadnode, vdevs);
+    let mut res = ObjSetDiff::default();
+    for range in metadnode_diff.unknown_block_ids {
+        res.unknown_object_ids.push(
+            range.start.saturating_mul(slots_per_block)..range.end.saturating_mul(slots_per_block),
+        );
+    }
+
+    for block_id in metadnode_diff.changed_block_ids {
+        let first_object_id = block_id * slots_per_block;
+        let (Ok(old_block), Ok(new_block)) = (
+            old.metadnode.read_block_or_hole(block_id as usize, vdevs),
+            new.metadnode.read_block_or_hole(block_id as usize, vdevs),
+        ) else {
+            res.unknown_object_ids
+                .push(first_object_id..first_object_id + slots_per_block);
+            continue;
+        };
+        let old_block = old_block.unwrap_or_else(|| vec![0u8; block_size]);
+        let new_block = new_block.unwrap_or_else(|| vec![0u8; block_size]);
+        let old_dnodes = parse_dnode_block(&old_block);
+        let new_dnodes = parse_dnode_block(&new_block);
+
+        let mut slots = old_dnodes
+            .keys()
+            .chain(new_dnodes.keys())
+            .collect::<Vec<_>>();
+        slots.sort_unstable();
+        slots.dedup();
+        for slot in slots {
+            let object_id = first_object_id + *slot as u64;
+            let (old_raw, new_raw) = (old_dnodes.get(slot), new_dnodes.get(slot));
+            if old_raw == new_raw {
+                continue;
+            }
+            let old_version = old_raw.map(|raw| parse_object_version(raw));
+            let new_version = new_raw.map(|raw| parse_object_version(raw));
+            if matches!(old_version, Some(None)) || matches!(new_version, Some(None)) {
+                res.unknown_object_ids.push(object_id..object_id + 1);
+                continue;
+            }
+            let (old_version, new_version) = (old_version.flatten(), new_version.flatten());
+
+            let change = match (&old_version, &new_version) {
+                (None, Some(_)) => ObjectChange::Created,
+                (Some(_), None) => ObjectChange::Deleted,
+                (Some(old_version), Some(new_version))
+                    if old_version.obj_type != new_version.obj_type =>
+                {
+                    ObjectChange::Replaced
+                }
+                _ => ObjectChange::Modified,
+            };
+            res.objects.push(ObjectDiff {
+                object_id,
+                change,
+                old: old_version,
+                new: new_version,
+            });
+        }
+    }
+    Ok(res)
+}
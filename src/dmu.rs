@@ -1,12 +1,17 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    byte_iter::{ByteIter, FromBytes, FromBytesLE},
-    dsl, zap,
+    byte_iter::{read_le_at, ByteIter, FromBytesLE, FromSliceLE},
+    dsl, history, nvlist, spacemap, zap,
     zil::ZilHeader,
     zio::{self, BlockPointer, ChecksumMethod, CompressionMethod, Vdevs},
 };
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{Seek, SeekFrom, Write},
+    ops::Range,
+};
 
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
 pub enum ObjType {
@@ -66,6 +71,19 @@ pub enum ObjType {
     DeadListHeader = 51,
     DSLClones = 52,
     BlockPointerObjectSubObject = 53,
+
+    // Newer object types don't get their own number anymore, only how to byteswap them and if they are metadata is stored
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/dmu.h#L93 (DMU_OTN_*)
+    NewUInt8Data = 0x80,
+    NewUInt16Data = 0x81,
+    NewUInt32Data = 0x82,
+    NewUInt64Data = 0x83,
+    NewZapData = 0x84,
+    NewUInt8Metadata = 0xc0,
+    NewUInt16Metadata = 0xc1,
+    NewUInt32Metadata = 0xc2,
+    NewUInt64Metadata = 0xc3,
+    NewZapMetadata = 0xc4,
 }
 
 impl ObjType {
@@ -127,12 +145,128 @@ impl ObjType {
             51 => Self::DeadListHeader,
             52 => Self::DSLClones,
             53 => Self::BlockPointerObjectSubObject,
+            0x80 => Self::NewUInt8Data,
+            0x81 => Self::NewUInt16Data,
+            0x82 => Self::NewUInt32Data,
+            0x83 => Self::NewUInt64Data,
+            0x84 => Self::NewZapData,
+            0xc0 => Self::NewUInt8Metadata,
+            0xc1 => Self::NewUInt16Metadata,
+            0xc2 => Self::NewUInt32Metadata,
+            0xc3 => Self::NewUInt64Metadata,
+            0xc4 => Self::NewZapMetadata,
             _ => return None,
         })
     }
+
+    // Returns: The name zdb uses for this object type
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dmu.c#L88 (dmu_ot)
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::None => "unallocated",
+            Self::ObjectDirectory => "object directory",
+            Self::ObjectArray => "object array",
+            Self::PackedNVList => "packed nvlist",
+            Self::PackedNVListSize => "packed nvlist size",
+            Self::BlockPointerList => "bpobj",
+            Self::BlockPointerListHeader => "bpobj header",
+            Self::SpaceMapHeader => "SPA space map header",
+            Self::SpaceMap => "SPA space map",
+            Self::IntentLog => "ZIL intent log",
+            Self::DNode => "DMU dnode",
+            Self::ObjSet => "DMU objset",
+            Self::DSLDirectory => "DSL directory",
+            Self::DSLDirectoryChildMap => "DSL directory child map",
+            Self::DSLDataSetSnapshotMap => "DSL dataset snap map",
+            Self::DSLProperties => "DSL props",
+            Self::DSLDataset => "DSL dataset",
+            Self::ZNode => "ZFS znode",
+            Self::OldAccessControlList => "ZFS V0 ACL",
+            Self::PlainFileContents => "ZFS plain file",
+            Self::DirectoryContents => "ZFS directory",
+            Self::MasterNode => "ZFS master node",
+            Self::DeleteQueue => "ZFS delete queue",
+            Self::ZVol => "zvol object",
+            Self::ZVolProperties => "zvol prop",
+            Self::PlainOther => "other uint8[]",
+            Self::U64Other => "other uint64[]",
+            Self::ZapOther => "other ZAP",
+            Self::ErrorLog => "persistent error log",
+            Self::SpaHistory => "SPA history",
+            Self::SpaHistoryOffsets => "SPA history offsets",
+            Self::PoolProperties => "Pool properties",
+            Self::DSLPermissions => "DSL permissions",
+            Self::AccessControlList => "ZFS ACL",
+            Self::SystemAccessControlList => "ZFS SYSACL",
+            Self::FUidTable => "FUID table",
+            Self::FUidSize => "FUID table size",
+            Self::NextClones => "DSL dataset next clones",
+            Self::ScanQueue => "scan work queue",
+            Self::UserGroupUsed => "ZFS user/group/project used",
+            Self::UserGroupQuota => "ZFS user/group/project quota",
+            Self::UserRefs => "snapshot refcount tags",
+            Self::DDTZap => "DDT ZAP algorithm",
+            Self::DDTStats => "DDT statistics",
+            Self::SystemAttributes => "System attributes",
+            Self::SystemAttributesMasterNode => "SA master node",
+            Self::SystemAttributesRegistrations => "SA attr registration",
+            Self::SystemAttributesLayouts => "SA attr layouts",
+            Self::ScanXLate => "scan translations",
+            Self::Dedup => "deduplicated block",
+            Self::DeadList => "DSL deadlist map",
+            Self::DeadListHeader => "DSL deadlist map hdr",
+            Self::DSLClones => "DSL dir clones",
+            Self::BlockPointerObjectSubObject => "bpobj subobj",
+            // zdb uses the name of the byteswap function for these
+            Self::NewUInt8Data | Self::NewUInt8Metadata => "uint8",
+            Self::NewUInt16Data | Self::NewUInt16Metadata => "uint16",
+            Self::NewUInt32Data | Self::NewUInt32Metadata => "uint32",
+            Self::NewUInt64Data | Self::NewUInt64Metadata => "uint64",
+            Self::NewZapData | Self::NewZapMetadata => "zap",
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+// A block pointer whose type or level isn't what its place in the tree of its object says it should be
+// zfs sets the type of every block pointer in the tree of an object (indirect blocks included) to the type of the object
+// and the level to how many indirect blocks are between it and the data, so a mismatch means the block pointer (or the indirect block it's in) is corrupted
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dbuf.c (dbuf_write_ready)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeMismatch {
+    pub expected_type: ObjType,
+    pub found_type: ObjType,
+    pub expected_level: usize,
+    pub found_level: usize,
+}
+
+impl TypeMismatch {
+    // Returns: None if the block pointer has the expected type and level
+    pub fn check(
+        bp: &BlockPointer,
+        expected_type: ObjType,
+        expected_level: usize,
+    ) -> Option<TypeMismatch> {
+        if bp.get_type() == expected_type && bp.get_level() == expected_level {
+            return None;
+        }
+        Some(TypeMismatch {
+            expected_type,
+            found_type: bp.get_type(),
+            expected_level,
+            found_level: bp.get_level(),
+        })
+    }
+
+    pub fn is_type_wrong(&self) -> bool {
+        self.expected_type != self.found_type
+    }
+
+    pub fn is_level_wrong(&self) -> bool {
+        self.expected_level != self.found_level
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone, Copy)]
 pub enum BonusType {
     None = 0,
     PackedNVListSize = 4,
@@ -140,8 +274,11 @@ pub enum BonusType {
     DSLDirectory = 12,
     DSLDataset = 16,
     ZNode = 17,
+    SpaHistoryOffsets = 30,
     // Source: https://github.com/openzfs/zfs/blob/master/include/sys/dmu.h#L226
     SystemAttributes = 44,
+    // Used by redaction lists
+    NewUInt64Metadata = 0xc3,
 }
 
 impl BonusType {
@@ -153,7 +290,9 @@ impl BonusType {
             12 => Self::DSLDirectory,
             16 => Self::DSLDataset,
             17 => Self::ZNode,
+            30 => Self::SpaHistoryOffsets,
             44 => Self::SystemAttributes,
+            0xc3 => Self::NewUInt64Metadata,
             _ => return None,
         })
     }
@@ -178,6 +317,16 @@ pub struct DNodeBase {
     total_allocated_is_in_bytes: bool, // if false then it is in sectors
     block_pointers: Vec<zio::BlockPointer>,
     bonus_data: Vec<u8>,
+    // Only needed to write the dnode back, they aren't saved so checkpoints made before they were added can still be read
+    // 0 if it's not known (the dnode was deserialized)
+    #[serde(skip)]
+    n_block_pointer_slots: u8,
+    #[serde(skip)]
+    flags: u8,
+    // Where the system attributes that don't fit in the bonus buffer are, it's the last 128 bytes of the dnode
+    // None if the dnode doesn't have one, or it was deserialized
+    #[serde(skip)]
+    spill_block_pointer: Option<zio::BlockPointer>,
 }
 
 impl Debug for DNodeBase {
@@ -197,6 +346,7 @@ impl Debug for DNodeBase {
             )
             .field("block_pointers", &self.block_pointers)
             .field("bonus_data", &self.bonus_data)
+            .field("spill_block_pointer", &self.spill_block_pointer)
             .finish()
     }
 }
@@ -207,7 +357,221 @@ struct IndirectBlockTag {
     offset: usize, // At what index in the upper layer block can you find the pointer to the this layer's block (the block that we want)
 }
 
+// Remembers the indirect blocks on the path to the last block that was looked up, so looking up a block next to it only reads the indirect blocks that are different
+// Without it every block reads the whole path from the top of the tree, that is up to 6 indirect blocks per data block (the block cache helps, but it still has to decompress and check them every time)
+// NOTE: A cursor only works for the dnode it was first used with, as the indirect blocks are only told apart by their level and id
+// It can also read ahead: the blocks after the one that was looked up are prefetched in batches, which hides the seek time of the disks on long sequential reads
+#[derive(Debug, Default)]
+pub struct IndirectTreeCursor {
+    // The indirect block at level i + 1 that was last read
+    path: Vec<Option<CachedIndirectBlock>>,
+    // How many blocks after the one that was looked up to prefetch, 0 to not prefetch
+    read_ahead: usize,
+    // Every block before this one was already prefetched
+    prefetched_until: usize,
+    // The blocks to prefetch are looked up with their own path, so looking ahead doesn't throw away the indirect blocks of the block that is being read
+    read_ahead_path: Vec<Option<CachedIndirectBlock>>,
+}
+
+// The id of the indirect block in its level, and its data, Err if it couldn't be read (so it isn't read again for every block under it)
+type CachedIndirectBlock = (usize, Result<Vec<u8>, ()>);
+
+impl IndirectTreeCursor {
+    pub fn new() -> IndirectTreeCursor {
+        IndirectTreeCursor::default()
+    }
+
+    // NOTE: The prefetched data is kept in the sector cache of the raidz, so a read ahead that doesn't fit in it just reads the disks twice
+    pub fn with_read_ahead(read_ahead: usize) -> IndirectTreeCursor {
+        IndirectTreeCursor {
+            read_ahead,
+            ..Default::default()
+        }
+    }
+
+    // See DNodeBase::get_data_block_pointer_or_hole
+    pub fn get_data_block_pointer_or_hole(
+        &mut self,
+        dnode: &DNodeBase,
+        block_id: usize,
+        vdevs: &mut zio::Vdevs,
+    ) -> Result<Option<BlockPointer>, ()> {
+        let block_pointer = Self::lookup(&mut self.path, dnode, block_id, vdevs);
+        if block_pointer.is_ok() {
+            self.prefetch_after(dnode, block_id, vdevs);
+        }
+        block_pointer
+    }
+
+    // Prefetches the blocks from block_id to block_id + read_ahead, but only once less than half of them are left from the last time
+    // so the disks get big batches of reads instead of one block at a time
+    fn prefetch_after(&mut self, dnode: &DNodeBase, block_id: usize, vdevs: &mut zio::Vdevs) {
+        if self.read_ahead == 0 {
+            return;
+        }
+        let window_end = block_id
+            .saturating_add(self.read_ahead)
+            .min((dnode.max_indirect_block_id as usize).saturating_add(1));
+        // Anything outside of the window means the reads aren't sequential (anymore), so start over from this block
+        let start = if self.prefetched_until <= block_id || self.prefetched_until > window_end {
+            block_id
+        } else if self.prefetched_until - block_id > self.read_ahead / 2 {
+            return;
+        } else {
+            self.prefetched_until
+        };
+
+        let block_pointers = (start..window_end)
+            .filter_map(|id| Self::lookup(&mut self.read_ahead_path, dnode, id, vdevs).ok()?)
+            .collect::<Vec<BlockPointer>>();
+        zio::prefetch(&block_pointers, vdevs);
+        self.prefetched_until = window_end;
+    }
+
+    // path is the cached path of the cursor, see IndirectTreeCursor::path
+    fn lookup(
+        path: &mut Vec<Option<CachedIndirectBlock>>,
+        dnode: &DNodeBase,
+        block_id: usize,
+        vdevs: &mut zio::Vdevs,
+    ) -> Result<Option<BlockPointer>, ()> {
+        if block_id > dnode.max_indirect_block_id as usize {
+            return Ok(None);
+        }
+
+        let blocks_per_indirect_block =
+            dnode.parse_indirect_block_size() / BlockPointer::get_ondisk_size();
+        // These come straight from the disk, so don't trust them
+        if dnode.n_indirect_levels < 1
+            || blocks_per_indirect_block == 0
+            || dnode.block_pointers.is_empty()
+        {
+            return Err(());
+        }
+
+        let mut levels: Vec<IndirectBlockTag> = Vec::new();
+        // Note: We are traversing the tree backwards from the leafs to the root
+        for level in 1..=dnode.n_indirect_levels {
+            let actual_id = if level == 1 {
+                block_id
+            } else {
+                levels.last().unwrap().parent_id
+            };
+
+            let actual_blocks_per_indirect_block = if level == dnode.n_indirect_levels {
+                dnode.block_pointers.len()
+            } else {
+                blocks_per_indirect_block
+            };
+
+            levels
+                .push(dnode.next_level_id_and_offset(actual_id, actual_blocks_per_indirect_block));
+        }
+
+        // Travel back down to the leafs
+        let top_level = levels.pop().unwrap();
+        // Note: Holes in the dnode's own block pointers are dropped when parsing it, so a missing one is a hole
+        let Some(top_level_block_pointer) = dnode.block_pointers.get(top_level.offset) else {
+            return Ok(None);
+        };
+        let mut next_block_pointer = top_level_block_pointer.clone();
+        path.resize(levels.len(), None);
+        // levels[i] is where the block pointer to the block at level i is, in the indirect block at level i + 1
+        while let Some(cur_level) = levels.pop() {
+            let cached = &mut path[levels.len()];
+            if !matches!(cached, Some((id, _)) if *id == cur_level.parent_id) {
+                *cached = Some((cur_level.parent_id, next_block_pointer.dereference(vdevs)));
+            }
+            let Some((_, indirect_block_data)) = cached else {
+                unreachable!();
+            };
+            let indirect_block_data = indirect_block_data.as_ref().map_err(|_| ())?;
+
+            next_block_pointer = {
+                let bp_data = indirect_block_data
+                    .get(BlockPointer::get_ondisk_size() * cur_level.offset..)
+                    .ok_or(())?;
+                if is_hole(bp_data) {
+                    return Ok(None);
+                }
+                BlockPointer::from_slice_le(bp_data).ok_or(())?
+            };
+        }
+
+        Ok(Some(next_block_pointer))
+    }
+
+    // See DNodeBase::read_block_or_hole
+    pub fn read_block_or_hole(
+        &mut self,
+        dnode: &DNodeBase,
+        block_id: usize,
+        vdevs: &mut zio::Vdevs,
+    ) -> Result<Option<Vec<u8>>, ()> {
+        let Some(mut block_pointer) =
+            self.get_data_block_pointer_or_hole(dnode, block_id, vdevs)?
+        else {
+            return Ok(None);
+        };
+        let block_data = block_pointer.dereference(vdevs)?;
+        if block_data.len() != dnode.parse_data_block_size() {
+            return Err(());
+        }
+        Ok(Some(block_data))
+    }
+
+    // See DNodeBase::read_block
+    pub fn read_block(
+        &mut self,
+        dnode: &DNodeBase,
+        block_id: usize,
+        vdevs: &mut zio::Vdevs,
+    ) -> Result<Vec<u8>, ()> {
+        self.read_block_or_hole(dnode, block_id, vdevs)?.ok_or(())
+    }
+
+    // See DNodeBase::read_block_with_source
+    pub fn read_block_with_source(
+        &mut self,
+        dnode: &DNodeBase,
+        block_id: usize,
+        vdevs: &mut zio::Vdevs,
+    ) -> Result<(Vec<u8>, zio::BlockSource), ()> {
+        let (block_data, source) = self
+            .get_data_block_pointer_or_hole(dnode, block_id, vdevs)?
+            .ok_or(())?
+            .dereference_with_source(vdevs)?;
+        if block_data.len() != dnode.parse_data_block_size() {
+            return Err(());
+        }
+        Ok((block_data, source))
+    }
+}
+
+// A hole is a block pointer whose first dva is empty, hole_birth pools still fill in the birth txg and some of the properties
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (BP_IS_HOLE)
+fn is_hole(raw_block_pointer: &[u8]) -> bool {
+    let (Some(first_dva), Some(info)) =
+        (raw_block_pointer.get(0..16), raw_block_pointer.get(48..56))
+    else {
+        return false;
+    };
+    let is_embedded = (u64::from_le_bytes(info.try_into().unwrap()) >> 39) & 1 != 0;
+    !is_embedded && first_dva.iter().all(|b| *b == 0)
+}
+
+// Limits used to check that a dnode makes sense
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dnode.h (DN_MIN_INDBLKSHIFT, DN_MAX_INDBLKSHIFT, DN_MAX_LEVELS)
+const DN_MIN_INDBLKSHIFT: u8 = 12;
+const DN_MAX_INDBLKSHIFT: u8 = 17;
+const DN_MAX_LEVELS: u8 = 12;
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h (SPA_MAXBLOCKSIZE)
+const SPA_MAXBLOCKSIZE: usize = 16 * 1024 * 1024;
+
 impl DNodeBase {
+    // The number of checks get_sanity_score does, so the best score a dnode can get
+    pub const MAX_SANITY_SCORE: usize = 7;
+
     pub fn get_ondisk_size(&self) -> usize {
         usize::from(self.num_slots) * 512
     }
@@ -225,31 +589,30 @@ impl DNodeBase {
     where
         Iter: Iterator<Item = u8> + Clone,
     {
-        let dnode_type = ObjType::from_value(data.next()?.into())?;
-        let indirect_blocksize_log2 = data.next()?;
-        let n_indirect_levels = data.next()?;
-        let n_block_pointers = data.next()?;
-        let bonus_data_type = BonusType::from_value(data.next()?.into())?;
-        let checksum_method = ChecksumMethod::from_value(data.next()?.into())?;
-        let compression_method = CompressionMethod::from_value(data.next()?.into())?;
-        let flags = data.next()?; // Ignore 1 padding byte ( dn_flags in newer versions )
-        let data_blocksize_in_512b_sectors = u16::from_bytes_le(data)?;
-        let bonus_data_len = u16::from_bytes_le(data)?;
-        let extra_slots = u8::from_bytes(data)?;
-        data.skip_n_bytes(3)?; // Ignore 3 padding bytes
-                               // We have read 16 bytes up until now
-
-        let max_indirect_block_id = u64::from_bytes_le(data)?;
-        let total_allocated = u64::from_bytes_le(data)?; /* bytes (or sectors, depending on a flag) of disk space */
-        data.skip_n_bytes(4 * core::mem::size_of::<u64>())?; // Ignore 4 u64 paddings
-
-        if flags & dnode_flag::HAS_SPILL_BLKPTR != 0 {
-            use crate::ansi_color::*;
-            if cfg!(feature = "debug") {
-                println!("{YELLOW}Warning{WHITE}: Tried to read a dnode with spill block, this is not supported!");
-            }
-            return None;
-        }
+        let size = Self::get_n_slots_from_bytes_le(data.clone())? * 512;
+        Self::from_slice_le(&data.by_ref().take(size).collect::<Vec<u8>>())
+    }
+
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/dnode.h (dnode_phys_t)
+    pub fn from_slice_le(data: &[u8]) -> Option<(DNodeBase, ObjType, BonusType)> {
+        let dnode_type = ObjType::from_value((*data.first()?).into())?;
+        let indirect_blocksize_log2 = *data.get(1)?;
+        let n_indirect_levels = *data.get(2)?;
+        let n_block_pointers = *data.get(3)?;
+        let bonus_data_type = BonusType::from_value((*data.get(4)?).into())?;
+        let checksum_method = ChecksumMethod::from_value((*data.get(5)?).into())?;
+        let compression_method = CompressionMethod::from_value((*data.get(6)?).into())?;
+        let flags = *data.get(7)?; // dn_flags in newer versions, it used to be padding
+        let data_blocksize_in_512b_sectors = read_le_at::<u16>(data, 8)?;
+        let bonus_data_len = read_le_at::<u16>(data, 10)?;
+        let extra_slots = *data.get(12)?;
+        // 3 padding bytes, so the first 16 bytes end here
+
+        let max_indirect_block_id = read_le_at::<u64>(data, 16)?;
+        let total_allocated = read_le_at::<u64>(data, 24)?; /* bytes (or sectors, depending on a flag) of disk space */
+        // Followed by 4 u64 paddings
+
+        let has_spill_block_pointer = flags & dnode_flag::HAS_SPILL_BLKPTR != 0;
 
         // Currently there must be at least one block pointer and at most 3
         if !(1..=3).contains(&n_block_pointers) {
@@ -260,40 +623,43 @@ impl DNodeBase {
             return None;
         }
 
-        // So far we have read 64 bytes, this is where the tail starts
+        // The tail starts after 64 bytes
         // The tail contains the variably sized data like the blkptrs, the bonus_data
-        // and the padding needed to reach a multiple of 512 bytes
+        // and the padding needed to reach a multiple of 512 bytes, followed by the spill block pointer if there is one
+        // Source: https://github.com/openzfs/zfs/blob/master/include/sys/dnode.h (DN_SPILL_BLKPTR)
+        let block_pointers_end =
+            64 + usize::from(n_block_pointers) * zio::BlockPointer::get_ondisk_size();
 
         // Read n_block_pointers block pointers
         let mut block_pointers = Vec::new();
-        for _ in 0..n_block_pointers {
+        for bp_data in data
+            .get(64..block_pointers_end)?
+            .chunks_exact(zio::BlockPointer::get_ondisk_size())
+        {
             // NOTE: We try to read the block pointers even if we are not going to need them
             // This means that we sometimes try to parse "unallocated" block pointers that might be all zeros
             // but because we check the checksum and the endianness this will fail so it's fine
-            if let Some(bp) = zio::BlockPointer::from_bytes_le(&mut data.clone()) {
+            if let Some(bp) = zio::BlockPointer::from_slice_le(bp_data) {
                 block_pointers.push(bp);
             }
-            data.skip_n_bytes(zio::BlockPointer::get_ondisk_size())?;
         }
 
-        let mut bonus_data = Vec::new();
-
         // Read bonus_data
-        for _ in 0..bonus_data_len {
-            bonus_data.push(data.next()?);
-        }
+        let bonus_data = data
+            .get(block_pointers_end..block_pointers_end + usize::from(bonus_data_len))?
+            .to_vec();
 
-        // Read remaining padding until the next multiple of 512 bytes
-        let total_size: usize = 64
-            + usize::from(n_block_pointers) * zio::BlockPointer::get_ondisk_size()
-            + usize::from(bonus_data_len);
-
-        // Round up the size to the next multiple of 512 bytes
-        let rounded_up_total_size = if total_size % 512 == 0 {
-            total_size
+        // The remaining padding goes until the next multiple of 512 bytes
+        let spill_block_pointer_size = if has_spill_block_pointer {
+            zio::BlockPointer::get_ondisk_size()
         } else {
-            ((total_size / 512) + 1) * 512
+            0
         };
+        let total_size: usize =
+            block_pointers_end + usize::from(bonus_data_len) + spill_block_pointer_size;
+
+        // Round up the size to the next multiple of 512 bytes
+        let rounded_up_total_size = total_size.next_multiple_of(512);
 
         // Sanity check that the size of the dnode calculated using the n_block_pointers and bonus_data_len is the same as the one calculated form the number of slots this dnode takes up
         if rounded_up_total_size != (usize::from(extra_slots) + 1) * 512 {
@@ -304,17 +670,28 @@ impl DNodeBase {
             return None;
         }
 
-        let tail_padding_size = rounded_up_total_size - total_size;
-        // We have all the data, and we don't need any data after the tail padding bytes
-        // So if we can't read the tail padding bytes it's not the end of the world
+        let tail_padding_end = rounded_up_total_size - spill_block_pointer_size;
+        // Without a spill block pointer we have all the data, and we don't need any data after the bonus data
+        // So if the tail padding bytes are missing it's not the end of the world
         // Just log it
-        if data.skip_n_bytes(tail_padding_size).is_none() {
+        if data.len() < tail_padding_end {
             use crate::ansi_color::*;
+            if has_spill_block_pointer {
+                return None;
+            }
             if cfg!(feature = "debug") {
                 println!("{YELLOW}Warning{WHITE}: Tried to parse dnode whose size is smaller than expected, thankfully all the data is still there ( the only missing part is in the padding in the tail ) so we won't error out!")
             }
         }
 
+        // Like the other block pointers, a spill block pointer that can't be parsed is dropped
+        let spill_block_pointer = if has_spill_block_pointer {
+            data.get(tail_padding_end..)
+                .and_then(zio::BlockPointer::from_slice_le)
+        } else {
+            None
+        };
+
         Some((
             DNodeBase {
                 indirect_blocksize_log2,
@@ -328,18 +705,104 @@ impl DNodeBase {
                 total_allocated_is_in_bytes: (flags & dnode_flag::USED_AMOUNT_IS_IN_BYTES) != 0,
                 block_pointers,
                 bonus_data,
+                n_block_pointer_slots: n_block_pointers,
+                flags,
+                spill_block_pointer,
             },
             dnode_type,
             bonus_data_type,
         ))
     }
 
+    // How many block pointers fit in the dnode, holes included
+    fn get_n_block_pointer_slots(&self) -> usize {
+        if self.n_block_pointer_slots != 0 {
+            return usize::from(self.n_block_pointer_slots);
+        }
+        // Deserialized dnodes don't know it, so it's worked out like zfs does when allocating the dnode
+        // NOTE: This is wrong for dnodes whose bonus data shrunk after they were allocated (ex. system attributes), those have 1 block pointer
+        // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dnode.c (dnode_allocate)
+        let max_bonus_len =
+            (self.get_num_slots() * 512).saturating_sub(64 + BlockPointer::get_ondisk_size());
+        let n_block_pointer_slots = 1 + max_bonus_len.saturating_sub(self.bonus_data.len())
+            / BlockPointer::get_ondisk_size();
+        n_block_pointer_slots.min(3).max(self.block_pointers.len())
+    }
+
+    // The inverse of from_bytes_le, the result is num_slots * 512 bytes so it can be written over the dnode in its dnode block
+    // This is what makes repairing a dnode possible, ex. parse it, fix what is wrong (like the max_indirect_block_id) and write it back
+    // Returns: None if the block pointers and the bonus data don't fit in the slots of the dnode
+    // NOTE: Block pointers that were holes are dropped when parsing, so the others are written first and the rest of the block pointers are zeros
+    // the same goes for a spill block pointer that couldn't be parsed, the dnode is written without it
+    pub fn to_bytes_le(&self, dnode_type: ObjType, bonus_type: BonusType) -> Option<Vec<u8>> {
+        let size = self.get_num_slots() * 512;
+        let n_block_pointer_slots = self.get_n_block_pointer_slots();
+        let bonus_data_len = u16::try_from(self.bonus_data.len()).ok()?;
+        let spill_block_pointer_size = if self.spill_block_pointer.is_some() {
+            BlockPointer::get_ondisk_size()
+        } else {
+            0
+        };
+        if size == 0
+            || self.block_pointers.len() > n_block_pointer_slots
+            || 64
+                + n_block_pointer_slots * BlockPointer::get_ondisk_size()
+                + self.bonus_data.len()
+                + spill_block_pointer_size
+                > size
+        {
+            return None;
+        }
+
+        let mut flags =
+            self.flags & !(dnode_flag::USED_AMOUNT_IS_IN_BYTES | dnode_flag::HAS_SPILL_BLKPTR);
+        if self.total_allocated_is_in_bytes {
+            flags |= dnode_flag::USED_AMOUNT_IS_IN_BYTES;
+        }
+        if self.spill_block_pointer.is_some() {
+            flags |= dnode_flag::HAS_SPILL_BLKPTR;
+        }
+
+        let mut res = Vec::with_capacity(size);
+        res.push(dnode_type as u8);
+        res.push(self.indirect_blocksize_log2);
+        res.push(self.n_indirect_levels);
+        res.push(n_block_pointer_slots as u8);
+        res.push(bonus_type as u8);
+        res.push(self.checksum_method as u8);
+        res.push(self.compression_method as u8);
+        res.push(flags);
+        res.extend(self.data_blocksize_in_512b_sectors.to_le_bytes());
+        res.extend(bonus_data_len.to_le_bytes());
+        res.push(self.num_slots - 1);
+        res.extend([0u8; 3]);
+        res.extend(self.max_indirect_block_id.to_le_bytes());
+        res.extend(self.total_allocated.to_le_bytes());
+        res.extend([0u8; 4 * core::mem::size_of::<u64>()]);
+
+        for block_pointer in self.block_pointers.iter() {
+            res.extend(block_pointer.to_bytes_le());
+        }
+        res.resize(
+            64 + n_block_pointer_slots * BlockPointer::get_ondisk_size(),
+            0,
+        );
+        res.extend(self.bonus_data.iter());
+        res.resize(size - spill_block_pointer_size, 0);
+        if let Some(spill_block_pointer) = &self.spill_block_pointer {
+            res.extend(spill_block_pointer.to_bytes_le());
+        }
+        Some(res)
+    }
+
     pub fn parse_data_block_size(&self) -> usize {
         usize::from(self.data_blocksize_in_512b_sectors) * 512
     }
 
     pub fn parse_indirect_block_size(&self) -> usize {
-        2usize.pow(u32::from(self.indirect_blocksize_log2))
+        1usize
+            .checked_shl(u32::from(self.indirect_blocksize_log2))
+            .unwrap_or(0)
     }
 
     // blocks_per_indirect_block is the branching factor of the upper layer
@@ -365,58 +828,81 @@ impl DNodeBase {
         block_id: usize,
         vdevs: &mut zio::Vdevs,
     ) -> Result<BlockPointer, ()> {
-        if block_id > self.max_indirect_block_id as usize {
-            return Err(());
-        }
-
-        assert!(self.n_indirect_levels >= 1);
-        let blocks_per_indirect_block =
-            self.parse_indirect_block_size() / BlockPointer::get_ondisk_size();
-
-        let mut levels: Vec<IndirectBlockTag> = Vec::new();
-        // Note: We are traversing the tree backwards from the leafs to the root
-        for level in 1..=self.n_indirect_levels {
-            let actual_id = if level == 1 {
-                block_id
-            } else {
-                levels.last().unwrap().parent_id
-            };
-
-            let actual_blocks_per_indirect_block = if level == self.n_indirect_levels {
-                self.block_pointers.len()
-            } else {
-                blocks_per_indirect_block
-            };
+        self.get_data_block_pointer_or_hole(block_id, vdevs)?
+            .ok_or(())
+    }
 
-            levels.push(self.next_level_id_and_offset(actual_id, actual_blocks_per_indirect_block));
-        }
+    // Like get_data_block_pointer, but tells holes apart from blocks that couldn't be read
+    // Returns: Ok(None) if the block is a hole (never written or past the end of the data)
+    pub fn get_data_block_pointer_or_hole(
+        &mut self,
+        block_id: usize,
+        vdevs: &mut zio::Vdevs,
+    ) -> Result<Option<BlockPointer>, ()> {
+        IndirectTreeCursor::default().get_data_block_pointer_or_hole(self, block_id, vdevs)
+    }
 
-        // Travel back down to the leafs
-        let top_level = levels.pop().unwrap();
-        let mut indirect_block_data;
-        let mut next_block_pointer = self.block_pointers[top_level.offset].clone();
-        for _ in 0..self.n_indirect_levels - 1 {
-            indirect_block_data = next_block_pointer.dereference(vdevs)?;
-            let cur_level = levels.pop().unwrap();
-            next_block_pointer = {
-                let mut iter = indirect_block_data.iter().copied();
-                iter.skip_n_bytes(BlockPointer::get_ondisk_size() * cur_level.offset)
-                    .ok_or(())?;
-                BlockPointer::from_bytes_le(&mut iter).ok_or(())?
-            };
+    // Returns: Ok(None) if the block is a hole, which reads as all zeros
+    pub fn read_block_or_hole(
+        &mut self,
+        block_id: usize,
+        vdevs: &mut zio::Vdevs,
+    ) -> Result<Option<Vec<u8>>, ()> {
+        let Some(mut block_pointer) = self.get_data_block_pointer_or_hole(block_id, vdevs)? else {
+            return Ok(None);
+        };
+        let block_data = block_pointer.dereference(vdevs)?;
+        if block_data.len() != self.parse_data_block_size() {
+            return Err(());
         }
-
-        Ok(next_block_pointer)
+        Ok(Some(block_data))
     }
 
     pub fn read_block(&mut self, block_id: usize, vdevs: &mut zio::Vdevs) -> Result<Vec<u8>, ()> {
         let block_data = self
             .get_data_block_pointer(block_id, vdevs)?
             .dereference(vdevs)?;
-        assert!(block_data.len() == self.parse_data_block_size());
+        if block_data.len() != self.parse_data_block_size() {
+            return Err(());
+        }
         Ok(block_data)
     }
 
+    // Like read_block, but a block from an embedded block pointer can be shorter than the data block size
+    // Embedded block pointers store their logical size in bytes instead of sectors, so small blocks (ex. the micro zap of a small directory) can come back with any size
+    // NOTE: Short blocks from normal block pointers are still an error, their size can only be wrong if they are corrupted
+    pub fn read_block_allow_embedded_size(
+        &mut self,
+        block_id: usize,
+        vdevs: &mut zio::Vdevs,
+    ) -> Result<Vec<u8>, ()> {
+        let mut block_pointer = self.get_data_block_pointer(block_id, vdevs)?;
+        let block_data = block_pointer.dereference(vdevs)?;
+        let is_size_ok = match block_pointer {
+            BlockPointer::Normal(_) => block_data.len() == self.parse_data_block_size(),
+            BlockPointer::Embedded(_) => block_data.len() <= self.parse_data_block_size(),
+        };
+        if !is_size_ok {
+            return Err(());
+        }
+        Ok(block_data)
+    }
+
+    // Like read_block, but also returns which copy of the block the data came from
+    pub fn read_block_with_source(
+        &mut self,
+        block_id: usize,
+        vdevs: &mut zio::Vdevs,
+    ) -> Result<(Vec<u8>, zio::BlockSource), ()> {
+        let (block_data, source) = self
+            .get_data_block_pointer(block_id, vdevs)?
+            .dereference_with_source(vdevs)?;
+        if block_data.len() != self.parse_data_block_size() {
+            return Err(());
+        }
+        Ok((block_data, source))
+    }
+
     // Note: Reading 0 bytes will *always* succeed
     pub fn read(
         &mut self,
@@ -427,11 +913,16 @@ impl DNodeBase {
         if size == 0 {
             return Ok(Vec::new());
         }
+        if self.parse_data_block_size() == 0 {
+            return Err(());
+        }
 
+        // The blocks are read in order, so most of them are under the same indirect blocks
+        let mut cursor = IndirectTreeCursor::new();
         let mut result: Vec<u8> = Vec::with_capacity(size);
         let first_data_block_index = offset / (self.parse_data_block_size() as u64);
         let first_data_block_offset = offset % (self.parse_data_block_size() as u64);
-        let first_data_block = self.read_block(first_data_block_index as usize, vdevs)?;
+        let first_data_block = cursor.read_block(self, first_data_block_index as usize, vdevs)?;
         result.extend(
             first_data_block
                 .iter()
@@ -450,7 +941,8 @@ impl DNodeBase {
             (size_remaining / self.parse_data_block_size()) + 1
         };
         for block_index in 1..=blocks_to_read {
-            result.extend(self.read_block(
+            result.extend(cursor.read_block(
+                self,
                 (first_data_block_index + block_index as u64) as usize,
                 vdevs,
             )?);
@@ -471,6 +963,106 @@ impl DNodeBase {
     pub fn get_bonus_data(&self) -> &[u8] {
         &self.bonus_data
     }
+
+    pub fn get_spill_block_pointer(&mut self) -> Option<&mut BlockPointer> {
+        self.spill_block_pointer.as_mut()
+    }
+
+    pub fn get_n_indirect_levels(&self) -> usize {
+        usize::from(self.n_indirect_levels)
+    }
+
+    pub fn get_max_indirect_block_id(&self) -> u64 {
+        self.max_indirect_block_id
+    }
+
+    pub fn set_max_indirect_block_id(&mut self, max_indirect_block_id: u64) {
+        self.max_indirect_block_id = max_indirect_block_id;
+    }
+
+    pub fn get_num_slots(&self) -> usize {
+        usize::from(self.num_slots)
+    }
+
+    pub fn get_checksum_method(&self) -> ChecksumMethod {
+        self.checksum_method
+    }
+
+    pub fn get_compression_method(&self) -> CompressionMethod {
+        self.compression_method
+    }
+
+    // Returns: The amount of disk space used by this dnode, in bytes
+    pub fn parse_total_allocated(&self) -> u64 {
+        if self.total_allocated_is_in_bytes {
+            self.total_allocated
+        } else {
+            self.total_allocated * 512
+        }
+    }
+
+    // Parsing only checks what it has to, so when scanning random data a lot of junk still parses as a dnode
+    // This does the checks that a real dnode always passes, but random data usually doesn't
+    // `newest_txg` is the txg of the newest uberblock of the pool, no block can be born after it, if it's None only that the birth txgs are not 0 is checked
+    // Returns: How many of the checks passed, out of MAX_SANITY_SCORE, so scanners can rank candidates and throw away junk
+    pub fn get_sanity_score(&self, expects_bonus_data: bool, newest_txg: Option<u64>) -> usize {
+        let mut score = 0;
+
+        // The methods that are only used internally by zfs are never set on a dnode
+        if !matches!(
+            self.checksum_method,
+            ChecksumMethod::Label
+                | ChecksumMethod::GangHeader
+                | ChecksumMethod::Zilog
+                | ChecksumMethod::Zilog2
+                | ChecksumMethod::NoParity
+        ) {
+            score += 1;
+        }
+
+        if self.compression_method != CompressionMethod::Empty {
+            score += 1;
+        }
+
+        if (1..=DN_MAX_LEVELS).contains(&self.n_indirect_levels)
+            && (DN_MIN_INDBLKSHIFT..=DN_MAX_INDBLKSHIFT).contains(&self.indirect_blocksize_log2)
+        {
+            score += 1;
+        }
+
+        // Only objects with a single block can have a block size that isn't a power of 2
+        let data_block_size = self.parse_data_block_size();
+        if (512..=SPA_MAXBLOCKSIZE).contains(&data_block_size)
+            && (self.max_indirect_block_id == 0 || data_block_size.is_power_of_two())
+        {
+            score += 1;
+        }
+
+        if expects_bonus_data != self.bonus_data.is_empty() {
+            score += 1;
+        }
+
+        // The block pointers in the dnode point to the top level of the tree
+        if !self.block_pointers.is_empty()
+            && self
+                .block_pointers
+                .iter()
+                .all(|bp| bp.get_level() + 1 == self.get_n_indirect_levels())
+        {
+            score += 1;
+        }
+
+        if !self.block_pointers.is_empty()
+            && self.block_pointers.iter().all(|bp| {
+                let birth_txg = bp.get_logical_birth_txg();
+                birth_txg != 0 && newest_txg.is_none_or(|newest_txg| birth_txg <= newest_txg)
+            })
+        {
+            score += 1;
+        }
+
+        score
+    }
 }
 
 pub struct DNodeDSLDirectory(pub DNodeBase);
@@ -513,12 +1105,85 @@ impl DNodeDSLDataset {
     }
 }
 
+pub struct DNodeSpaceMap(pub DNodeBase);
+
+impl Debug for DNodeSpaceMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DNodeSpaceMap")
+            .field("checksum_method", &self.0.checksum_method)
+            .field("compression_method", &self.0.compression_method)
+            .field("data_blocksize", &self.0.parse_data_block_size())
+            .field("num_slots", &self.0.num_slots)
+            .field("bonus", &self.parse_bonus_data())
+            .finish()
+    }
+}
+
+impl DNodeSpaceMap {
+    pub fn parse_bonus_data(&self) -> Option<spacemap::SpaceMapHeader> {
+        spacemap::SpaceMapHeader::from_bytes_le(&mut self.0.bonus_data.iter().copied())
+    }
+
+    // Returns: The raw entries of the space map, the length is taken from the header in the bonus buffer
+    pub fn read_raw_entries(&mut self, vdevs: &mut Vdevs) -> Result<Vec<u8>, ()> {
+        let header = self.parse_bonus_data().ok_or(())?;
+        self.0.read(0, header.length as usize, vdevs)
+    }
+}
+
+pub struct DNodeSpaHistory(pub DNodeBase);
+
+impl Debug for DNodeSpaHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DNodeSpaHistory")
+            .field("checksum_method", &self.0.checksum_method)
+            .field("compression_method", &self.0.compression_method)
+            .field("data_blocksize", &self.0.parse_data_block_size())
+            .field("num_slots", &self.0.num_slots)
+            .field("bonus", &self.parse_bonus_data())
+            .finish()
+    }
+}
+
+impl DNodeSpaHistory {
+    pub fn parse_bonus_data(&self) -> Option<history::SpaHistoryHeader> {
+        history::SpaHistoryHeader::from_bytes_le(&mut self.0.bonus_data.iter().copied())
+    }
+}
+
+// An nvlist packed into the data of an object, the bonus buffer has the size of the packed nvlist
+// The copy of the pool config in the MOS is one (see spa_config::read_mos_config)
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa.c (spa_sync_nvlist)
+#[derive(Debug)]
+pub struct DNodePackedNVList(pub DNodeBase);
+
+impl DNodePackedNVList {
+    // Returns: The size of the packed nvlist in bytes
+    pub fn parse_bonus_data(&self) -> Option<u64> {
+        u64::from_bytes_le(&mut self.0.bonus_data.iter().copied())
+    }
+
+    // NOTE: zfs always packs them with the xdr encoding, but the header says which encoding it is, so the native one works too
+    pub fn read_nvlist(&mut self, vdevs: &mut Vdevs) -> Option<nvlist::NVList> {
+        let size = usize::try_from(self.parse_bonus_data()?).ok()?;
+        // A corrupted size shouldn't make us read way past the end of the object
+        if size > self.0.get_data_size() {
+            return None;
+        }
+        let data = self.0.read(0, size, vdevs).ok()?;
+        match data.first()? {
+            0 => nvlist::from_bytes_native(&data),
+            _ => nvlist::from_bytes_xdr(&mut data.iter().copied()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ZapDNode(pub DNodeBase);
 impl ZapDNode {
     pub fn get_zap_header(&mut self, vdevs: &mut Vdevs) -> Option<zap::ZapHeader> {
-        zap::ZapHeader::from_bytes_le(
-            &mut self.0.read_block(0, vdevs).ok()?.iter().copied(),
+        zap::ZapHeader::from_slice_le(
+            &self.0.read_block_allow_embedded_size(0, vdevs).ok()?,
             self.0.parse_data_block_size(),
         )
     }
@@ -534,8 +1199,8 @@ pub struct DNodeDirectoryContents(pub DNodeBase, pub BonusType);
 
 impl DNodeDirectoryContents {
     pub fn get_zap_header(&mut self, vdevs: &mut Vdevs) -> Option<zap::ZapHeader> {
-        zap::ZapHeader::from_bytes_le(
-            &mut self.0.read_block(0, vdevs).ok()?.iter().copied(),
+        zap::ZapHeader::from_slice_le(
+            &self.0.read_block_allow_embedded_size(0, vdevs).ok()?,
             self.0.parse_data_block_size(),
         )
     }
@@ -549,6 +1214,332 @@ impl DNodeDirectoryContents {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DNodePlainFileContents(pub DNodeBase, pub BonusType);
 
+#[derive(Debug, Default, Clone)]
+pub struct ExtractOptions {
+    // Offset in the file to start extracting at, everything before it is assumed to already be in the output
+    // This is used to resume an interrupted extraction
+    pub start_offset: u64,
+    // The dnode only knows how many blocks the file has, the real size is in the system attributes
+    // Defaults to the size of all the blocks
+    pub size: Option<u64>,
+    // Seek over holes instead of writing zeros, so the output is a sparse file
+    pub sparse: bool,
+    // How many blocks ahead of the one that is being extracted to prefetch, 0 to not prefetch, see IndirectTreeCursor::with_read_ahead
+    pub read_ahead: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct ExtractReport {
+    pub bytes_written: u64,
+    pub hole_bytes: u64,
+    // Parts of the file that couldn't be read and are zeros in the output
+    pub bad_ranges: Vec<Range<u64>>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MergeOptions {
+    pub extract: ExtractOptions,
+    // Take the data of a hole from an older generation, for when the hole was punched (or the file truncated) after the data we want was written
+    pub fill_holes: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct MergeReport {
+    pub extract: ExtractReport,
+    // How many data blocks were taken from every generation, in the same order as the generations
+    pub blocks_per_generation: Vec<u64>,
+}
+
+enum MergedBlock {
+    // The data and the index of the generation it was read from
+    Data(Vec<u8>, usize),
+    Hole,
+}
+
+// Where a data block of a file is on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockMapping {
+    pub file_offset: u64,
+    pub psize: u64,
+    // Empty for embedded blocks, their data is in the block pointer itself so they aren't anywhere on disk
+    pub dvas: Vec<zio::DataVirtualAddress>,
+    // Embedded blocks don't have a checksum
+    pub checksum: Option<[u64; 4]>,
+}
+
+impl BlockMapping {
+    fn from_block_pointer(block_pointer: &BlockPointer, file_offset: u64) -> BlockMapping {
+        match block_pointer {
+            BlockPointer::Normal(block_pointer) => BlockMapping {
+                file_offset,
+                psize: block_pointer.parse_physical_size(),
+                dvas: block_pointer.get_dvas().iter().flatten().cloned().collect(),
+                checksum: Some(block_pointer.get_checksum()),
+            },
+            BlockPointer::Embedded(block_pointer) => BlockMapping {
+                file_offset,
+                psize: block_pointer.parse_physical_size(),
+                dvas: Vec::new(),
+                checksum: None,
+            },
+        }
+    }
+}
+
+// Walks the part of the indirect tree under block_pointer, which is at `level` and whose first data block is first_block_id
+fn map_blocks_under(
+    dnode: &DNodeBase,
+    block_pointer: &mut BlockPointer,
+    level: u32,
+    first_block_id: u64,
+    mappings: &mut Vec<BlockMapping>,
+    vdevs: &mut Vdevs,
+) {
+    use crate::ansi_color::*;
+    if first_block_id > dnode.max_indirect_block_id {
+        return;
+    }
+
+    let block_size = dnode.parse_data_block_size() as u64;
+    if level == 0 {
+        mappings.push(BlockMapping::from_block_pointer(
+            block_pointer,
+            first_block_id * block_size,
+        ));
+        return;
+    }
+
+    let blocks_per_indirect_block =
+        (dnode.parse_indirect_block_size() / BlockPointer::get_ondisk_size()) as u64;
+    let blocks_per_block_pointer = blocks_per_indirect_block.pow(level - 1);
+    let Ok(indirect_block_data) = block_pointer.dereference(vdevs) else {
+        println!(
+            "{YELLOW}Warning{WHITE}: Couldn't read level {level} indirect block at file offset {}, the blocks under it are missing from the mapping!",
+            first_block_id * block_size
+        );
+        return;
+    };
+
+    for (index, raw_block_pointer) in indirect_block_data
+        .chunks_exact(BlockPointer::get_ondisk_size())
+        .enumerate()
+    {
+        if is_hole(raw_block_pointer) {
+            continue;
+        }
+
+        let block_id = first_block_id + index as u64 * blocks_per_block_pointer;
+        let Some(mut child) = BlockPointer::from_slice_le(raw_block_pointer) else {
+            println!(
+                "{YELLOW}Warning{WHITE}: Couldn't parse block pointer at file offset {}, the blocks under it are missing from the mapping!",
+                block_id * block_size
+            );
+            continue;
+        };
+        map_blocks_under(dnode, &mut child, level - 1, block_id, mappings, vdevs);
+    }
+}
+
+impl DNodePlainFileContents {
+    // Returns: Where every data block of the file is on disk, in file order
+    // This only reads the indirect blocks, so it's fast enough to plan reads of a whole file (ex. as a ddrescue domain)
+    // Note: Holes aren't in the mapping, and neither are the blocks under an indirect block that couldn't be read
+    pub fn map_blocks(&mut self, vdevs: &mut Vdevs) -> Vec<BlockMapping> {
+        let dnode = &self.0;
+        let blocks_per_indirect_block =
+            (dnode.parse_indirect_block_size() / BlockPointer::get_ondisk_size()) as u64;
+        // These come straight from the disk, so don't trust them
+        if dnode.n_indirect_levels < 1 || blocks_per_indirect_block == 0 {
+            return Vec::new();
+        }
+
+        let top_level = u32::from(dnode.n_indirect_levels) - 1;
+        let Some(blocks_per_top_level_block_pointer) =
+            blocks_per_indirect_block.checked_pow(top_level)
+        else {
+            return Vec::new();
+        };
+
+        let mut mappings = Vec::new();
+        for (index, block_pointer) in dnode.block_pointers.iter().enumerate() {
+            map_blocks_under(
+                dnode,
+                &mut block_pointer.clone(),
+                top_level,
+                index as u64 * blocks_per_top_level_block_pointer,
+                &mut mappings,
+                vdevs,
+            );
+        }
+        mappings
+    }
+
+    // Extracts the file block by block, so it never needs to be in memory all at once
+    // Offsets in the output are the same as in the file, so the writer should be at the start of the output file
+    // Note: Bad blocks don't stop the extraction, they are filled with zeros and reported in bad_ranges
+    pub fn extract_to<W: Write + Seek>(
+        &mut self,
+        writer: &mut W,
+        options: &ExtractOptions,
+        vdevs: &mut Vdevs,
+    ) -> Result<ExtractReport, ()> {
+        Self::extract_merged_to(
+            std::slice::from_mut(self),
+            writer,
+            &MergeOptions {
+                extract: options.clone(),
+                ..Default::default()
+            },
+            vdevs,
+        )
+        .map(|report| report.extract)
+    }
+
+    // Returns: Where the block comes from, None if it couldn't be read from any generation
+    // cursors has one cursor for every generation
+    fn read_merged_block(
+        generations: &mut [DNodePlainFileContents],
+        cursors: &mut [IndirectTreeCursor],
+        block_id: u64,
+        fill_holes: bool,
+        vdevs: &mut Vdevs,
+    ) -> Option<MergedBlock> {
+        let block_size = generations[0].0.parse_data_block_size();
+        let mut is_hole = false;
+        // A generation that didn't change the block has the exact same block pointer, no need to read it again
+        let mut failed_checksums = Vec::new();
+        for (generation, (file, cursor)) in generations.iter_mut().zip(cursors).enumerate() {
+            if file.0.parse_data_block_size() != block_size {
+                continue;
+            }
+
+            let Ok(block_pointer) =
+                cursor.get_data_block_pointer_or_hole(&file.0, block_id as usize, vdevs)
+            else {
+                continue;
+            };
+            let Some(mut block_pointer) = block_pointer else {
+                is_hole = true;
+                if fill_holes {
+                    continue;
+                }
+                break;
+            };
+
+            let checksum = match &block_pointer {
+                BlockPointer::Normal(block_pointer) => Some(block_pointer.get_checksum()),
+                BlockPointer::Embedded(_) => None,
+            };
+            if checksum.is_some() && failed_checksums.contains(&checksum) {
+                continue;
+            }
+            match block_pointer.dereference(vdevs) {
+                Ok(block_data) if block_data.len() == block_size => {
+                    return Some(MergedBlock::Data(block_data, generation))
+                }
+                _ => failed_checksums.push(checksum),
+            }
+        }
+        is_hole.then_some(MergedBlock::Hole)
+    }
+
+    // Like extract_to, but every block is taken from the first of the generations that has it, so they should be sorted from newest to oldest
+    // This is useful when a file was partially overwritten (or damaged) in the newest generation, but older generations still have the blocks that are gone
+    // The generations should all be of the same file (ex. the same object at different txgs), the first one decides the size and the data block size
+    // A hole in a generation is kept as a hole, unless options.fill_holes is set, then the data from an older generation is used
+    // Note: The checksum of every block is checked when it is read, so a bad block is never taken over a good one from an older generation
+    pub fn extract_merged_to<W: Write + Seek>(
+        generations: &mut [DNodePlainFileContents],
+        writer: &mut W,
+        options: &MergeOptions,
+        vdevs: &mut Vdevs,
+    ) -> Result<MergeReport, ()> {
+        let Some(newest) = generations.first() else {
+            return Err(());
+        };
+        let block_size = newest.0.parse_data_block_size() as u64;
+        if block_size == 0 {
+            return Err(());
+        }
+        let size = options
+            .extract
+            .size
+            .unwrap_or(newest.0.get_data_size() as u64);
+
+        let mut report = MergeReport {
+            extract: ExtractReport::default(),
+            blocks_per_generation: vec![0; generations.len()],
+        };
+        let mut offset = options.extract.start_offset.min(size);
+        writer.seek(SeekFrom::Start(offset)).map_err(|_| ())?;
+        let zeros = vec![0u8; block_size as usize];
+        let mut ended_with_seek = false;
+        // Older generations are only read when the newer ones don't have a block, so prefetching them would mostly be wasted
+        let mut cursors: Vec<IndirectTreeCursor> = (0..generations.len())
+            .map(|generation| match generation {
+                0 => IndirectTreeCursor::with_read_ahead(options.extract.read_ahead),
+                _ => IndirectTreeCursor::new(),
+            })
+            .collect();
+        while offset < size {
+            let block_id = offset / block_size;
+            let offset_in_block = (offset % block_size) as usize;
+            let len = (block_size - offset_in_block as u64).min(size - offset) as usize;
+
+            let block_data = match Self::read_merged_block(
+                generations,
+                &mut cursors,
+                block_id,
+                options.fill_holes,
+                vdevs,
+            ) {
+                Some(MergedBlock::Data(block_data, generation)) => {
+                    report.blocks_per_generation[generation] += 1;
+                    Some(block_data)
+                }
+                Some(MergedBlock::Hole) => {
+                    report.extract.hole_bytes += len as u64;
+                    None
+                }
+                None => {
+                    match report.extract.bad_ranges.last_mut() {
+                        Some(last) if last.end == offset => last.end += len as u64,
+                        _ => report.extract.bad_ranges.push(offset..offset + len as u64),
+                    }
+                    None
+                }
+            };
+
+            match block_data {
+                Some(block_data) => {
+                    writer
+                        .write_all(&block_data[offset_in_block..offset_in_block + len])
+                        .map_err(|_| ())?;
+                    report.extract.bytes_written += len as u64;
+                    ended_with_seek = false;
+                }
+                None if options.extract.sparse => {
+                    writer.seek(SeekFrom::Current(len as i64)).map_err(|_| ())?;
+                    ended_with_seek = true;
+                }
+                None => {
+                    writer.write_all(&zeros[..len]).map_err(|_| ())?;
+                    ended_with_seek = false;
+                }
+            }
+            offset += len as u64;
+        }
+
+        // Seeking past the end doesn't make the file any bigger, so write the last byte to get the right size
+        if ended_with_seek {
+            writer.seek(SeekFrom::Current(-1)).map_err(|_| ())?;
+            writer.write_all(&[0]).map_err(|_| ())?;
+        }
+        writer.flush().map_err(|_| ())?;
+        Ok(report)
+    }
+}
+
 #[derive(Debug)]
 pub enum DNode {
     ObjectDirectory(ZapDNode),
@@ -560,6 +1551,16 @@ pub enum DNode {
     SystemAttributesMasterNode(ZapDNode),
     SystemAttributesLayouts(ZapDNode),
     SystemAttributesRegistrations(ZapDNode),
+    SpaceMap(DNodeSpaceMap),
+    SpaHistory(DNodeSpaHistory),
+    PackedNVList(DNodePackedNVList),
+}
+
+impl FromSliceLE for DNode {
+    fn from_slice_le(data: &[u8]) -> Option<DNode> {
+        let (dnode_base, dnode_type, bonus_data_type) = DNodeBase::from_slice_le(data)?;
+        DNode::from_parts(dnode_base, dnode_type, bonus_data_type)
+    }
 }
 
 impl<It> FromBytesLE<It> for DNode
@@ -568,6 +1569,16 @@ where
 {
     fn from_bytes_le(data: &mut It) -> Option<DNode> {
         let (dnode_base, dnode_type, bonus_data_type) = DNodeBase::from_bytes_le(data)?;
+        DNode::from_parts(dnode_base, dnode_type, bonus_data_type)
+    }
+}
+
+impl DNode {
+    fn from_parts(
+        dnode_base: DNodeBase,
+        dnode_type: ObjType,
+        bonus_data_type: BonusType,
+    ) -> Option<DNode> {
         Some(match (dnode_type, bonus_data_type) {
             (ObjType::ObjectDirectory, BonusType::None) => {
                 DNode::ObjectDirectory(ZapDNode(dnode_base))
@@ -594,6 +1605,15 @@ where
             (ObjType::SystemAttributesRegistrations, BonusType::None) => {
                 DNode::SystemAttributesRegistrations(ZapDNode(dnode_base))
             }
+            (ObjType::SpaceMap, BonusType::SpaceMapHeader) => {
+                DNode::SpaceMap(DNodeSpaceMap(dnode_base))
+            }
+            (ObjType::SpaHistory, BonusType::SpaHistoryOffsets) => {
+                DNode::SpaHistory(DNodeSpaHistory(dnode_base))
+            }
+            (ObjType::PackedNVList, BonusType::PackedNVListSize) => {
+                DNode::PackedNVList(DNodePackedNVList(dnode_base))
+            }
             (obj_type, bonus_type) => {
                 use crate::ansi_color::*;
                 if cfg!(feature = "debug") {
@@ -603,13 +1623,58 @@ where
             }
         })
     }
-}
 
-impl DNode {
     pub fn get_n_slots_from_bytes_le(data: impl Iterator<Item = u8>) -> Option<usize> {
         DNodeBase::get_n_slots_from_bytes_le(data)
     }
 
+    pub fn get_obj_type(&self) -> ObjType {
+        match self {
+            DNode::ObjectDirectory(_) => ObjType::ObjectDirectory,
+            DNode::DSLDirectory(_) => ObjType::DSLDirectory,
+            DNode::DSLDataset(_) => ObjType::DSLDataset,
+            DNode::MasterNode(_) => ObjType::MasterNode,
+            DNode::DirectoryContents(_) => ObjType::DirectoryContents,
+            DNode::PlainFileContents(_) => ObjType::PlainFileContents,
+            DNode::SystemAttributesMasterNode(_) => ObjType::SystemAttributesMasterNode,
+            DNode::SystemAttributesLayouts(_) => ObjType::SystemAttributesLayouts,
+            DNode::SystemAttributesRegistrations(_) => ObjType::SystemAttributesRegistrations,
+            DNode::SpaceMap(_) => ObjType::SpaceMap,
+            DNode::SpaHistory(_) => ObjType::SpaHistory,
+            DNode::PackedNVList(_) => ObjType::PackedNVList,
+        }
+    }
+
+    pub fn get_bonus_type(&self) -> &BonusType {
+        match self {
+            DNode::DSLDirectory(_) => &BonusType::DSLDirectory,
+            DNode::DSLDataset(_) => &BonusType::DSLDataset,
+            DNode::DirectoryContents(d) => &d.1,
+            DNode::PlainFileContents(d) => &d.1,
+            DNode::SpaceMap(_) => &BonusType::SpaceMapHeader,
+            DNode::SpaHistory(_) => &BonusType::SpaHistoryOffsets,
+            DNode::PackedNVList(_) => &BonusType::PackedNVListSize,
+            DNode::ObjectDirectory(_)
+            | DNode::MasterNode(_)
+            | DNode::SystemAttributesMasterNode(_)
+            | DNode::SystemAttributesLayouts(_)
+            | DNode::SystemAttributesRegistrations(_) => &BonusType::None,
+        }
+    }
+
+    // See DNodeBase::to_bytes_le
+    pub fn to_bytes_le(&mut self) -> Option<Vec<u8>> {
+        let (dnode_type, bonus_type) = (self.get_obj_type(), *self.get_bonus_type());
+        self.get_inner().to_bytes_le(dnode_type, bonus_type)
+    }
+
+    // See DNodeBase::get_sanity_score
+    pub fn get_sanity_score(&mut self, newest_txg: Option<u64>) -> usize {
+        let expects_bonus_data = *self.get_bonus_type() != BonusType::None;
+        self.get_inner()
+            .get_sanity_score(expects_bonus_data, newest_txg)
+    }
+
     pub fn get_inner(&mut self) -> &mut DNodeBase {
         match self {
             DNode::ObjectDirectory(d) => &mut d.0,
@@ -621,6 +1686,9 @@ impl DNode {
             DNode::SystemAttributesMasterNode(d) => &mut d.0,
             DNode::SystemAttributesLayouts(d) => &mut d.0,
             DNode::SystemAttributesRegistrations(d) => &mut d.0,
+            DNode::SpaceMap(d) => &mut d.0,
+            DNode::SpaHistory(d) => &mut d.0,
+            DNode::PackedNVList(d) => &mut d.0,
         }
     }
 }
@@ -645,19 +1713,70 @@ impl ObjSetType {
     }
 }
 
+// Why a dnode couldn't be read, so code going through the objects can tell free objects apart from broken ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DNodeReadError {
+    // The object id is past the end of the objset
+    OutOfBounds,
+    // There is no object with this id, it was never allocated or it was freed
+    Unallocated,
+    // The block of the metadnode the dnode is in couldn't be read
+    Unreadable,
+    // The dnode was read but it doesn't make sense
+    Corrupt,
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dmu_objset.h (OBJSET_FLAG_*)
+pub mod objset_flag {
+    pub const USERACCOUNTING_COMPLETE: u64 = 1 << 0;
+    pub const USEROBJACCOUNTING_COMPLETE: u64 = 1 << 1;
+    pub const PROJECTQUOTA_COMPLETE: u64 = 1 << 2;
+}
+
+// The space accounting objects aren't in the metadnode, they are dnodes of the objset itself with these special object ids
+// Their zaps map the ids (in hex) to the bytes used, and with "obj-" in front of the id to the number of objects
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dmu.h (DMU_USERUSED_OBJECT, DMU_GROUPUSED_OBJECT, DMU_PROJECTUSED_OBJECT)
+pub const USERUSED_OBJECT: u64 = -1i64 as u64;
+pub const GROUPUSED_OBJECT: u64 = -2i64 as u64;
+pub const PROJECTUSED_OBJECT: u64 = -3i64 as u64;
+
+// Newer objsets are bigger, the first 1024 bytes are always the same, after that come the space accounting dnodes
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dmu_objset.h (objset_phys_t, OBJSET_PHYS_SIZE_V1/V2/V3)
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ObjSet {
     pub metadnode: DNodeBase,
     pub zil: Option<ZilHeader>,
     pub typ: ObjSetType,
+    // NOTE: The flags and the macs are only there since encryption, they are zeros in older objsets
+    #[serde(default)]
+    pub flags: u64,
+    // The macs of encrypted datasets, the portable one covers the metadnode and the local one the accounting dnodes
+    #[serde(default)]
+    pub portable_mac: [u8; 32],
+    #[serde(default)]
+    pub local_mac: [u8; 32],
+    // None if the objset is too small to have them or they were never allocated
+    #[serde(default)]
+    pub userused: Option<DNodeBase>,
+    #[serde(default)]
+    pub groupused: Option<DNodeBase>,
+    #[serde(default)]
+    pub projectused: Option<DNodeBase>,
 }
 
-impl<It> FromBytesLE<It> for ObjSet
-where
-    It: Iterator<Item = u8> + Clone,
-{
-    fn from_bytes_le(data: &mut It) -> Option<ObjSet> {
-        let (metadnode, metadnode_type, _) = DNodeBase::from_bytes_le(data)?;
+// Returns: None if there is no dnode at the start of the data, or it's not a space accounting object (ex. it's zeros because it was never allocated)
+fn parse_accounting_dnode(data: &[u8]) -> Option<DNodeBase> {
+    let (dnode, dnode_type, _) = DNodeBase::from_slice_le(data)?;
+    if dnode_type != ObjType::UserGroupUsed {
+        return None;
+    }
+    Some(dnode)
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dmu_objset.h (objset_phys_t)
+impl FromSliceLE for ObjSet {
+    fn from_slice_le(data: &[u8]) -> Option<ObjSet> {
+        let (metadnode, metadnode_type, _) = DNodeBase::from_slice_le(data)?;
         if metadnode_type != ObjType::DNode {
             use crate::ansi_color::*;
             if cfg!(feature = "debug") {
@@ -666,27 +1785,66 @@ where
             return None;
         }
 
-        let zil = ZilHeader::from_bytes_le(&mut data.clone());
-        data.skip_n_bytes(ZilHeader::get_ondisk_size())?;
-
-        let typ = ObjSetType::from_value(u64::from_bytes_le(data)?.try_into().ok()?)?;
-        // Consume padding
-        let size_read = metadnode.get_ondisk_size()
-            + ZilHeader::get_ondisk_size()
-            + core::mem::size_of::<u64>();
-        let remaining = Self::get_ondisk_size() - size_read;
-        if data.skip_n_bytes(remaining).is_none() {
+        let zil_offset = metadnode.get_ondisk_size();
+        let zil = data.get(zil_offset..).and_then(ZilHeader::from_slice_le);
+        let typ_offset = zil_offset + ZilHeader::get_ondisk_size();
+        let typ = ObjSetType::from_value(read_le_at::<u64>(data, typ_offset)?.try_into().ok()?)?;
+        let mut res = ObjSet {
+            metadnode,
+            zil,
+            typ,
+            flags: 0,
+            portable_mac: [0u8; 32],
+            local_mac: [0u8; 32],
+            userused: None,
+            groupused: None,
+            projectused: None,
+        };
+        // The flags, the macs and the padding after them
+        let flags_offset = typ_offset + core::mem::size_of::<u64>();
+        let macs_offset = flags_offset + core::mem::size_of::<u64>();
+        if macs_offset + 2 * 32 > Self::get_ondisk_size() {
+            return None;
+        }
+        if data.len() < Self::get_ondisk_size() {
             use crate::ansi_color::*;
             if cfg!(feature = "debug") {
                 println!("{YELLOW}Warning{WHITE}: Tried to parse objset whose size is smaller than expected, thankfully all the data is still there ( the only missing part is in the padding in the tail ) so we won't error out!")
             }
+            return Some(res);
         }
+        res.flags = read_le_at::<u64>(data, flags_offset)?;
+        res.portable_mac = data[macs_offset..macs_offset + 32].try_into().ok()?;
+        res.local_mac = data[macs_offset + 32..macs_offset + 64].try_into().ok()?;
+
+        // Only in the bigger objsets, every dnode is parsed on its own so a missing one doesn't shift the ones after it
+        let accounting_dnode = |index: usize| {
+            data.get(Self::get_ondisk_size() + index * 512..)
+                .and_then(parse_accounting_dnode)
+        };
+        res.userused = accounting_dnode(0);
+        if data.len() >= Self::get_ondisk_size() + 512 {
+            res.groupused = accounting_dnode(1);
+            if data.len() >= Self::get_ondisk_size() + 2 * 512 {
+                res.projectused = accounting_dnode(2);
+            }
+        }
+        Some(res)
+    }
+}
 
-        Some(ObjSet {
-            metadnode,
-            zil,
-            typ,
-        })
+impl<It> FromBytesLE<It> for ObjSet
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<ObjSet> {
+        // The objset and its 3 accounting dnodes
+        Self::from_slice_le(
+            &data
+                .by_ref()
+                .take(Self::get_ondisk_size() + 3 * 512)
+                .collect::<Vec<u8>>(),
+        )
     }
 }
 
@@ -695,17 +1853,112 @@ impl ObjSet {
         1024
     }
 
+    // Returns: The space accounting dnode with this special object id, see USERUSED_OBJECT
+    pub fn get_accounting_dnode(&mut self, object_id: u64) -> Option<&mut DNodeBase> {
+        match object_id {
+            USERUSED_OBJECT => self.userused.as_mut(),
+            GROUPUSED_OBJECT => self.groupused.as_mut(),
+            PROJECTUSED_OBJECT => self.projectused.as_mut(),
+            _ => None,
+        }
+    }
+
+    // Returns: The contents of the zap of a space accounting object, see USERUSED_OBJECT for what's in it
+    pub fn dump_accounting(
+        &mut self,
+        object_id: u64,
+        vdevs: &mut Vdevs,
+    ) -> Option<HashMap<String, zap::Value>> {
+        let dnode = self.get_accounting_dnode(object_id)?;
+        let header = zap::ZapHeader::from_slice_le(
+            &dnode.read_block_allow_embedded_size(0, vdevs).ok()?,
+            dnode.parse_data_block_size(),
+        )?;
+        header.dump_contents(dnode, vdevs)
+    }
+
+    // Returns: How many object ids the objset has room for, there are no objects with bigger ids
+    // NOTE: A DNode slot is 512 bytes in size, and every slot has its own object id
+    pub fn get_object_count(&self) -> u64 {
+        (self.metadnode.get_max_indirect_block_id() + 1)
+            .saturating_mul(self.metadnode.parse_data_block_size() as u64 / 512)
+    }
+
+    // Returns: The raw bytes of the dnode at the given index, including all of its extra slots
+    // A dnode never crosses the end of a block of the metadnode, so it's read out of a single block
+    // that way the number of slots and the slots themselves always come from the same read
+    pub fn try_get_raw_dnode_at(
+        &mut self,
+        index: u64,
+        vdevs: &mut Vdevs,
+    ) -> Result<Vec<u8>, DNodeReadError> {
+        if index >= self.get_object_count() {
+            return Err(DNodeReadError::OutOfBounds);
+        }
+
+        // get_object_count is 0 if the block size is less than a slot, so this can't be 0
+        let slots_per_block = self.metadnode.parse_data_block_size() as u64 / 512;
+        let block_id =
+            usize::try_from(index / slots_per_block).map_err(|_| DNodeReadError::OutOfBounds)?;
+        let first_slot = (index % slots_per_block) as usize;
+        let block = match self.metadnode.read_block_or_hole(block_id, vdevs) {
+            Ok(Some(block)) => block,
+            // Nothing was ever allocated in this part of the objset
+            Ok(None) => return Err(DNodeReadError::Unallocated),
+            Err(()) => return Err(DNodeReadError::Unreadable),
+        };
+
+        let data = &block[first_slot * 512..];
+        // Freed dnodes are zeroed, so their type is none
+        if data[0] == ObjType::None as u8 {
+            return Err(DNodeReadError::Unallocated);
+        }
+
+        let dnode_slots = DNodeBase::get_n_slots_from_bytes_le(data.iter().copied())
+            .ok_or(DNodeReadError::Corrupt)?;
+        if first_slot + dnode_slots > slots_per_block as usize {
+            return Err(DNodeReadError::Corrupt);
+        }
+        Ok(data[..dnode_slots * 512].to_vec())
+    }
+
+    pub fn get_raw_dnode_at(&mut self, index: usize, vdevs: &mut Vdevs) -> Option<Vec<u8>> {
+        self.try_get_raw_dnode_at(index as u64, vdevs).ok()
+    }
+
+    pub fn try_get_dnode_at(
+        &mut self,
+        index: u64,
+        vdevs: &mut Vdevs,
+    ) -> Result<DNode, DNodeReadError> {
+        DNode::from_slice_le(&self.try_get_raw_dnode_at(index, vdevs)?)
+            .ok_or(DNodeReadError::Corrupt)
+    }
+
     pub fn get_dnode_at(&mut self, index: usize, vdevs: &mut Vdevs) -> Option<DNode> {
-        // A DNode slot is 512 bytes in size
-
-        let mut data = self.metadnode.read((index * 512) as u64, 512, vdevs).ok()?;
-        let dnode_slots = DNodeBase::get_n_slots_from_bytes_le(data.iter().copied())?;
-        data.extend(
-            self.metadnode
-                .read(((index + 1) * 512) as u64, (dnode_slots - 1) * 512, vdevs)
-                .ok()?
-                .iter(),
-        );
-        DNode::from_bytes_le(&mut data.iter().copied())
+        self.try_get_dnode_at(index as u64, vdevs).ok()
+    }
+
+    // Returns: The dnode at the given index and its type, for objects that aren't zaps and don't have a DNode variant of their own
+    pub fn get_dnode_base_at(
+        &mut self,
+        index: usize,
+        vdevs: &mut Vdevs,
+    ) -> Option<(DNodeBase, ObjType)> {
+        let raw_dnode = self.get_raw_dnode_at(index, vdevs)?;
+        let (dnode, obj_type, _) = DNodeBase::from_slice_le(&raw_dnode)?;
+        Some((dnode, obj_type))
+    }
+
+    // Returns: The dnode at the given index as a zap and its type, for the many kinds of zap objects that don't have a DNode variant of their own
+    // NOTE: It's up to the caller to check the type, if it's not actually a zap reading it will just fail
+    pub fn get_zap_dnode_at(
+        &mut self,
+        index: usize,
+        vdevs: &mut Vdevs,
+    ) -> Option<(ZapDNode, ObjType)> {
+        let raw_dnode = self.get_raw_dnode_at(index, vdevs)?;
+        let (dnode, obj_type, _) = DNodeBase::from_slice_le(&raw_dnode)?;
+        Some((ZapDNode(dnode), obj_type))
     }
 }
@@ -61,6 +61,20 @@ impl DSLDirectoryData {
     pub fn get_head_dataset_object_number(&self) -> u64 {
         self.head_dataset_object_number
     }
+
+    // Returns: 0 for the root dsl directory
+    pub fn get_parent_object_number(&self) -> u64 {
+        self.parent_object_number
+    }
+
+    pub fn get_props_object_number(&self) -> u64 {
+        self.props_object_number
+    }
+
+    // The zap with the names of the child directories, 0 if there is none
+    pub fn get_children_directory_object_number(&self) -> u64 {
+        self.children_directory_object_number
+    }
 }
 
 #[derive(Debug)]
@@ -152,4 +166,37 @@ impl DSLDatasetData {
     pub fn get_block_pointer(&mut self) -> &mut BlockPointer {
         &mut self.block_pointer
     }
+
+    pub fn get_parent_directory_object_number(&self) -> u64 {
+        self.parent_directory_object_number
+    }
+
+    // Returns: The object number of the zap mapping snapshot names to their dsl dataset, 0 for snapshots
+    pub fn get_snapshot_names_object_number(&self) -> u64 {
+        self.snapshot_names_object_number
+    }
+
+    pub fn get_guid(&self) -> u64 {
+        self.guid
+    }
+
+    pub fn get_creation_txg(&self) -> u64 {
+        self.creation_txg
+    }
+
+    pub fn get_used_bytes(&self) -> u64 {
+        self.used_bytes
+    }
+
+    pub fn get_compressed_bytes(&self) -> u64 {
+        self.compressed_bytes
+    }
+
+    pub fn get_uncompressed_bytes(&self) -> u64 {
+        self.uncompressed_bytes
+    }
+
+    pub fn is_snapshot(&self) -> bool {
+        self.num_references != 0
+    }
 }
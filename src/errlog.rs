@@ -0,0 +1,138 @@
+// The persistent error log, the list of blocks zfs already knows are damaged (what zpool status -v shows)
+// errlog_last has the errors of the last completed scrub, errlog_scrub the ones found since the current scrub started
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa_errlog.c
+
+use std::collections::HashMap;
+
+use crate::{
+    dmu::{DNode, ObjSet, ObjType},
+    traverse::ObjSetId,
+    zap,
+    zio::Vdevs,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorLogSource {
+    LastScrub,
+    CurrentScrub,
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorLogEntry {
+    pub source: ErrorLogSource,
+    pub objset: ObjSetId,
+    pub object: u64,
+    // -1 is used for errors in the dnode itself
+    pub level: i64,
+    pub block_id: u64,
+    // Only pools with the head_errlog feature record the birth txg of the block
+    pub birth_txg: Option<u64>,
+}
+
+fn parse_hex_fields<const N: usize>(name: &str) -> Option<[u64; N]> {
+    let fields = name
+        .split(':')
+        .map(|field| u64::from_str_radix(field, 16).ok())
+        .collect::<Option<Vec<u64>>>()?;
+    fields.try_into().ok()
+}
+
+fn objset_id_from_value(value: u64) -> ObjSetId {
+    if value == 0 {
+        ObjSetId::Mos
+    } else {
+        ObjSetId::Dataset(value)
+    }
+}
+
+fn read_error_log_zap(
+    mos: &mut ObjSet,
+    object_id: u64,
+    vdevs: &mut Vdevs,
+) -> Result<HashMap<String, zap::Value>, ()> {
+    let (mut zap_dnode, obj_type) = mos.get_zap_dnode_at(object_id as usize, vdevs).ok_or(())?;
+    if obj_type != ObjType::ErrorLog {
+        return Err(());
+    }
+    zap_dnode.dump_zap_contents(vdevs).ok_or(())
+}
+
+// Returns: The entries of one of the error logs
+// Without head_errlog the names are "objset:object:level:blkid" (in hex)
+// With head_errlog the names are head dataset numbers and the values are zaps with names of the form "object:level:blkid:birth"
+fn read_error_log(
+    mos: &mut ObjSet,
+    object_id: u64,
+    source: ErrorLogSource,
+    vdevs: &mut Vdevs,
+) -> Result<Vec<ErrorLogEntry>, ()> {
+    use crate::ansi_color::*;
+    let mut entries = Vec::new();
+    for (name, value) in read_error_log_zap(mos, object_id, vdevs)? {
+        if let Some([objset, object, level, block_id]) = parse_hex_fields::<4>(&name) {
+            entries.push(ErrorLogEntry {
+                source,
+                objset: objset_id_from_value(objset),
+                object,
+                level: level as i64,
+                block_id,
+                birth_txg: None,
+            });
+            continue;
+        }
+
+        let (Some([head_dataset]), zap::Value::U64(dataset_log_id)) =
+            (parse_hex_fields::<1>(&name), value)
+        else {
+            println!("{YELLOW}Warning{WHITE}: Couldn't parse error log entry {name}, skipping it!");
+            continue;
+        };
+
+        let Ok(dataset_log) = read_error_log_zap(mos, dataset_log_id, vdevs) else {
+            println!("{YELLOW}Warning{WHITE}: Couldn't read the error log of dataset {head_dataset}, skipping it!");
+            continue;
+        };
+        for name in dataset_log.keys() {
+            let Some([object, level, block_id, birth_txg]) = parse_hex_fields::<4>(name) else {
+                println!("{YELLOW}Warning{WHITE}: Couldn't parse error log entry {name} of dataset {head_dataset}, skipping it!");
+                continue;
+            };
+            entries.push(ErrorLogEntry {
+                source,
+                objset: objset_id_from_value(head_dataset),
+                object,
+                level: level as i64,
+                block_id,
+                birth_txg: Some(birth_txg),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+// Returns: The entries of both error logs, sorted so that the entries of the same object are next to each other
+// NOTE: A pool that never had errors doesn't have error logs at all, this is not an error
+pub fn read_error_logs(mos: &mut ObjSet, vdevs: &mut Vdevs) -> Result<Vec<ErrorLogEntry>, ()> {
+    let Some(DNode::ObjectDirectory(mut object_directory)) = mos.get_dnode_at(1, vdevs) else {
+        return Err(());
+    };
+    let objdir_zap_data = object_directory.dump_zap_contents(vdevs).ok_or(())?;
+
+    let mut entries = Vec::new();
+    for (name, source) in [
+        ("errlog_last", ErrorLogSource::LastScrub),
+        ("errlog_scrub", ErrorLogSource::CurrentScrub),
+    ] {
+        // The objects are only created once there is something to log, and can be 0 after being cleared
+        let Some(zap::Value::U64(object_id)) = objdir_zap_data.get(name) else {
+            continue;
+        };
+        if *object_id == 0 {
+            continue;
+        }
+        entries.extend(read_error_log(mos, *object_id, source, vdevs)?);
+    }
+
+    entries.sort_by_key(|entry| (entry.objset, entry.object, entry.level, entry.block_id));
+    Ok(entries)
+}
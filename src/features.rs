@@ -0,0 +1,71 @@
+// The pool version and the feature flags say which on disk structures a pool can have
+// Up to version 28 a pool is described by its version alone, after that every pool has version 5000 and what it uses is in its feature flags instead
+// The features a pool needs to be read at all are in the features_for_read nvlist of the config, the names are the keys
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h (SPA_VERSION_*)
+// Source: https://github.com/openzfs/zfs/blob/master/module/zcommon/zfeature_common.c (zpool_feature_init)
+
+use crate::nvlist::{self, NVList};
+
+pub const SPA_VERSION_FEATURES: u64 = 5000;
+// The last version before feature flags, it's what pools made with the old solaris releases have
+pub const SPA_VERSION_BEFORE_FEATURES: u64 = 28;
+// System attributes came with version 24, before that files only have znode bonus buffers
+// NOTE: A pool that was upgraded still has files with znode bonus buffers, they are only rewritten when their attributes change
+pub const SPA_VERSION_SA: u64 = 24;
+
+// The features that change something we read, and that we can read (though ex. the sha512 checksums can't be verified yet)
+// or that only change things we never read, ex. the space maps or the zil
+// Everything else in features_for_read means some of the pool might not make sense to us
+const SUPPORTED_READ_FEATURES: &[&str] = &[
+    "org.illumos:lz4_compress",
+    "com.delphix:hole_birth",
+    "com.delphix:embedded_data",
+    "com.delphix:extensible_dataset",
+    "com.delphix:empty_bpobj",
+    "com.delphix:enabled_txg",
+    "com.delphix:spacemap_histogram",
+    "com.delphix:spacemap_v2",
+    "com.delphix:zpool_checkpoint",
+    "com.delphix:bookmarks",
+    "com.datto:bookmark_v2",
+    "com.delphix:bookmark_written",
+    "com.delphix:livelist",
+    "com.delphix:log_spacemap",
+    "com.delphix:head_errlog",
+    "com.delphix:zilsaxattr",
+    "org.open-zfs:large_blocks",
+    "org.zfsonlinux:large_dnode",
+    "org.zfsonlinux:userobj_accounting",
+    "org.zfsonlinux:project_quota",
+    "org.zfsonlinux:allocation_classes",
+    "com.joyent:multi_vdev_crash_dump",
+    "org.illumos:sha512",
+    "org.illumos:skein",
+    "org.illumos:edonr",
+    "org.openzfs:blake3",
+    "org.freebsd:zstd_compress",
+    "com.klarasystems:vdev_zaps_v2",
+    "org.openzfs:longname",
+];
+
+pub fn is_valid_version(version: u64) -> bool {
+    (1..=SPA_VERSION_BEFORE_FEATURES).contains(&version) || version == SPA_VERSION_FEATURES
+}
+
+pub fn is_supported_read_feature(name: &str) -> bool {
+    SUPPORTED_READ_FEATURES.contains(&name)
+}
+
+// Returns: The features the pool needs to be read that we don't know how to read, sorted by name
+pub fn get_unsupported_read_features(config: &NVList) -> Vec<String> {
+    let Some(nvlist::Value::NVList(features_for_read)) = config.get("features_for_read") else {
+        return Vec::new();
+    };
+    let mut unsupported = features_for_read
+        .keys()
+        .filter(|name| !is_supported_read_feature(name))
+        .cloned()
+        .collect::<Vec<_>>();
+    unsupported.sort_unstable();
+    unsupported
+}
@@ -0,0 +1,195 @@
+// The pool history, every zpool/zfs command that changed the pool and (for newer pools) the internal events they caused
+// This is what zpool history shows, it's very useful to find out what happened to a pool before it died
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa_history.c
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h (ZPOOL_HIST_*)
+
+use crate::{
+    byte_iter::FromBytesLE,
+    dmu::{DNode, DNodeBase, ObjSet},
+    nvlist::{self, NVList},
+    zap,
+    zio::Vdevs,
+};
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (spa_history_phys_t)
+#[derive(Debug)]
+pub struct SpaHistoryHeader {
+    // The records of the pool creation are at the start of the object and never overwritten
+    pub pool_create_len: u64,
+    // Size of the object, after this the history wraps around to pool_create_len
+    pub phys_max_off: u64,
+    // Logical offsets of the first and one past the last byte of the history that is still there
+    pub bof: u64,
+    pub eof: u64,
+    // Number of records that were overwritten because the history was full
+    pub records_lost: u64,
+}
+
+impl<It> FromBytesLE<It> for SpaHistoryHeader
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<SpaHistoryHeader> {
+        Some(SpaHistoryHeader {
+            pool_create_len: u64::from_bytes_le(data)?,
+            phys_max_off: u64::from_bytes_le(data)?,
+            bof: u64::from_bytes_le(data)?,
+            eof: u64::from_bytes_le(data)?,
+            records_lost: u64::from_bytes_le(data)?,
+        })
+    }
+}
+
+impl SpaHistoryHeader {
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa_history.c (spa_history_log_to_phys)
+    fn logical_to_physical(&self, logical_offset: u64) -> u64 {
+        let ring_size = self.phys_max_off - self.pool_create_len;
+        (logical_offset - self.pool_create_len) % ring_size + self.pool_create_len
+    }
+
+    // Reads `size` bytes starting at a logical offset in the ring part of the history, wrapping around if needed
+    fn read_ring(
+        &self,
+        dnode: &mut DNodeBase,
+        logical_offset: u64,
+        size: u64,
+        vdevs: &mut Vdevs,
+    ) -> Result<Vec<u8>, ()> {
+        if self.phys_max_off <= self.pool_create_len {
+            return Err(());
+        }
+
+        let mut data = Vec::new();
+        while (data.len() as u64) < size {
+            let physical_offset = self.logical_to_physical(logical_offset + data.len() as u64);
+            let chunk_size = (size - data.len() as u64).min(self.phys_max_off - physical_offset);
+            data.extend(dnode.read(physical_offset, chunk_size as usize, vdevs)?);
+        }
+        Ok(data)
+    }
+}
+
+// Every record is its size as a u64 followed by a packed nvlist
+fn parse_records(mut data: &[u8]) -> Vec<NVList> {
+    use crate::ansi_color::*;
+    let mut records = Vec::new();
+    while data.len() >= 8 {
+        let record_size = u64::from_le_bytes(data[0..8].try_into().unwrap()) as usize;
+        let Some(record_data) = data.get(8..8 + record_size) else {
+            println!("{YELLOW}Warning{WHITE}: History record is bigger than the history, ignoring the rest of the history!");
+            break;
+        };
+
+        match nvlist::from_bytes_native(record_data) {
+            Some(record) => records.push(record),
+            None => println!("{YELLOW}Warning{WHITE}: Couldn't parse history record, skipping it!"),
+        }
+        data = &data[8 + record_size..];
+    }
+    records
+}
+
+// Returns: All history records that are still there, from oldest to newest
+pub fn read_history(mos: &mut ObjSet, vdevs: &mut Vdevs) -> Result<Vec<NVList>, ()> {
+    use crate::ansi_color::*;
+    let Some(DNode::ObjectDirectory(mut object_directory)) = mos.get_dnode_at(1, vdevs) else {
+        return Err(());
+    };
+    let objdir_zap_data = object_directory.dump_zap_contents(vdevs).ok_or(())?;
+    let Some(zap::Value::U64(history_id)) = objdir_zap_data.get("history") else {
+        // Very old pools don't have a history
+        return Ok(Vec::new());
+    };
+
+    let Some(DNode::SpaHistory(mut history)) = mos.get_dnode_at(*history_id as usize, vdevs) else {
+        return Err(());
+    };
+    let header = history.parse_bonus_data().ok_or(())?;
+    if header.records_lost != 0 {
+        println!(
+            "{YELLOW}Warning{WHITE}: {} history records were overwritten because the history was full!",
+            header.records_lost
+        );
+    }
+
+    let mut records = parse_records(&history.0.read(0, header.pool_create_len as usize, vdevs)?);
+
+    // Anything between the pool creation records and bof was overwritten
+    let ring_start = header.bof.max(header.pool_create_len);
+    if header.eof > ring_start {
+        let ring_data =
+            header.read_ring(&mut history.0, ring_start, header.eof - ring_start, vdevs)?;
+        records.extend(parse_records(&ring_data));
+    }
+    Ok(records)
+}
+
+// Returns: The time as YYYY-MM-DD.HH:MM:SS, like zpool history, but in UTC
+// Source: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn format_unix_time(seconds: u64) -> String {
+    let z = seconds / 86400 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let seconds_in_day = seconds % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02}.{:02}:{:02}:{:02}",
+        seconds_in_day / 3600,
+        seconds_in_day / 60 % 60,
+        seconds_in_day % 60
+    )
+}
+
+fn get_string<'a>(record: &'a NVList, name: &str) -> Option<&'a str> {
+    match record.get(name) {
+        Some(nvlist::Value::String(value)) => Some(value),
+        _ => None,
+    }
+}
+
+fn get_u64(record: &NVList, name: &str) -> Option<u64> {
+    match record.get(name) {
+        Some(nvlist::Value::U64(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+// Returns: The record in the format zpool history -il uses
+pub fn format_record(record: &NVList) -> String {
+    let time = get_u64(record, "history time")
+        .map(format_unix_time)
+        .unwrap_or(String::from("????-??-??.??:??:??"));
+    let txg = get_u64(record, "history txg").unwrap_or(0);
+    let internal_str = get_string(record, "history internal str").unwrap_or("");
+
+    let mut line = if let Some(command) = get_string(record, "history command") {
+        format!("{time} {command}")
+    } else if let Some(internal_name) = get_string(record, "internal_name") {
+        format!(
+            "{time} [txg:{txg}] {internal_name} {} ({}) {internal_str}",
+            get_string(record, "dsname").unwrap_or(""),
+            get_u64(record, "dsid").unwrap_or(0)
+        )
+    } else if let Some(ioctl) = get_string(record, "ioctl") {
+        format!("{time} ioctl {ioctl}")
+    } else if let Some(event) = get_u64(record, "history internal event") {
+        // Old pools log internal events as numbers, the names are in zfs_history_event_names
+        format!("{time} [internal event {event} txg:{txg}] {internal_str}")
+    } else {
+        format!("{time} {record:?}")
+    };
+
+    if let (Some(who), Some(host)) = (
+        get_u64(record, "history who"),
+        get_string(record, "history hostname"),
+    ) {
+        line += &format!(" [user {who} on {host}]");
+    }
+    line
+}
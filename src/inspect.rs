@@ -0,0 +1,93 @@
+// Parsing dumps of on disk structures given as bytes, nothing here reads from a disk or a file
+// so this also works without the disk feature, ex. in a browser where someone drops in a label they dumped (see wasm.rs)
+
+use crate::{byte_iter::FromBytes, nvlist, zdb, Uberblock, VdevLabel, VDEV_LABEL_SIZE};
+
+pub struct LabelReport {
+    // None if the config in the label can't be parsed
+    pub name_value_pairs: Option<nvlist::NVList>,
+    // The uberblocks that could be parsed and the slot of the ring they are in, sorted by txg
+    pub uberblocks: Vec<(usize, Uberblock)>,
+    pub nslots: usize,
+}
+
+// Returns: Err if the dump isn't the size of a label
+pub fn inspect_label(label: &[u8]) -> Result<LabelReport, String> {
+    if label.len() != VDEV_LABEL_SIZE as usize {
+        return Err(format!(
+            "A label is {VDEV_LABEL_SIZE} bytes, but the dump is {} bytes",
+            label.len()
+        ));
+    }
+
+    let label = VdevLabel::from_bytes(label);
+    let name_value_pairs =
+        nvlist::from_bytes_xdr(&mut label.get_name_value_pairs_raw().iter().copied());
+    let mut uberblocks = Vec::new();
+    for slot in 0..label.get_raw_uberblock_count() {
+        let Some(raw_uberblock) = label.get_raw_uberblock(slot) else {
+            continue;
+        };
+        if let Some(uberblock) = Uberblock::from_bytes(&mut raw_uberblock.iter().copied()) {
+            uberblocks.push((slot, uberblock));
+        }
+    }
+    uberblocks.sort_unstable_by_key(|(_, uberblock)| uberblock.txg);
+
+    Ok(LabelReport {
+        name_value_pairs,
+        uberblocks,
+        nslots: label.get_raw_uberblock_count(),
+    })
+}
+
+// Appends the nvlist to output, one pair per line, the nested nvlists indented like zdb -l shows them
+pub fn format_nvlist(nvlist: &nvlist::NVList, indent: usize, output: &mut String) {
+    let mut names = nvlist.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        let padding = "    ".repeat(indent);
+        match &nvlist[name] {
+            nvlist::Value::NVList(child) => {
+                output.push_str(&format!("{padding}{name}:\n"));
+                format_nvlist(child, indent + 1, output);
+            }
+            nvlist::Value::NVListArray(children) => {
+                for (index, child) in children.iter().enumerate() {
+                    output.push_str(&format!("{padding}{name}[{index}]:\n"));
+                    format_nvlist(child, indent + 1, output);
+                }
+            }
+            value => output.push_str(&format!("{padding}{name}: {value:?}\n")),
+        }
+    }
+}
+
+// Returns: The config and the uberblock ring as text, the nvlists are indented like zdb -l shows them
+pub fn format_label_report(report: &LabelReport) -> String {
+    let mut output = String::new();
+    match &report.name_value_pairs {
+        Some(name_value_pairs) => {
+            output.push_str("Config:\n");
+            format_nvlist(name_value_pairs, 1, &mut output);
+        }
+        None => output.push_str("Config: can't be parsed\n"),
+    }
+
+    output.push_str(&format!(
+        "Uberblocks ({} of {} slots):\n",
+        report.uberblocks.len(),
+        report.nslots
+    ));
+    for (slot, uberblock) in report.uberblocks.iter() {
+        output.push_str(&format!(
+            "    slot {slot}: txg {} timestamp {} guid_sum {} version {}\n        rootbp {}\n",
+            uberblock.txg,
+            uberblock.timestamp,
+            uberblock.guid_sum,
+            uberblock.version,
+            zdb::format_block_pointer(&uberblock.rootbp)
+        ));
+    }
+    output
+}
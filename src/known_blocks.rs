@@ -0,0 +1,194 @@
+// What is known to be at which place on disk, filled in by every read, so the same data isn't read over and over again
+// Undelete finds many versions of the same structures, and their block pointers often point to the same places
+// ex. an old and a new version of an indirect block whose block pointers point to the same dva, but only one of them has the right checksum
+// The block cache only remembers blocks by their checksum, and forgets them when it's full, so the dva that is known to hold something else is read again for every version
+// With this a dva that is known to hold data with another checksum is skipped without reading it, and a block whose dvas are all bad
+// is read from any other place it was already found at (ex. a copy that was written again at another offset)
+// NOTE: It's off by default, it grows with every dva that is read, so it's only meant for the graph building of undelete and the like, not for scanning whole disks
+
+use lazy_static::lazy_static;
+use std::{collections::HashMap, sync::RwLock};
+
+use crate::zio::{ChecksumMethod, DataVirtualAddress};
+
+// The vdev id, the offset and the physical size of the data, the same data can be read with different sizes, so it's part of the key
+pub type Location = (u32, u64, usize);
+
+#[derive(Debug, Default)]
+pub struct KnownBlocks {
+    // Where data with this checksum was found
+    locations_by_checksum: HashMap<([u64; 4], ChecksumMethod), Vec<Location>>,
+    // The checksum of the data at this location
+    checksums_by_location: HashMap<(Location, ChecksumMethod), [u64; 4]>,
+    nskipped_reads: u64,
+    nredirected_reads: u64,
+}
+
+// Gang dvas point to a gang header and not the data itself, so they are never remembered
+fn get_location(dva: &DataVirtualAddress, psize: usize) -> Option<Location> {
+    if dva.is_gang() {
+        return None;
+    }
+    Some((dva.get_vdev_id(), dva.parse_offset(), psize))
+}
+
+impl KnownBlocks {
+    pub fn remember(
+        &mut self,
+        dva: &DataVirtualAddress,
+        psize: usize,
+        checksum_method: ChecksumMethod,
+        checksum: [u64; 4],
+    ) {
+        let Some(location) = get_location(dva, psize) else {
+            return;
+        };
+        if self
+            .checksums_by_location
+            .insert((location, checksum_method), checksum)
+            == Some(checksum)
+        {
+            return;
+        }
+        self.locations_by_checksum
+            .entry((checksum, checksum_method))
+            .or_default()
+            .push(location);
+    }
+
+    // Returns: true if the dva is known to hold data with another checksum, so reading it would be pointless
+    pub fn is_known_mismatch(
+        &self,
+        dva: &DataVirtualAddress,
+        psize: usize,
+        checksum_method: ChecksumMethod,
+        checksum: &[u64; 4],
+    ) -> bool {
+        get_location(dva, psize)
+            .and_then(|location| self.checksums_by_location.get(&(location, checksum_method)))
+            .is_some_and(|known_checksum| known_checksum != checksum)
+    }
+
+    // Returns: The places data with this checksum and size was found at
+    pub fn get_locations(
+        &self,
+        checksum_method: ChecksumMethod,
+        checksum: &[u64; 4],
+        psize: usize,
+    ) -> Vec<Location> {
+        self.locations_by_checksum
+            .get(&(*checksum, checksum_method))
+            .map(|locations| {
+                locations
+                    .iter()
+                    .filter(|location| location.2 == psize)
+                    .copied()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn get_nlocations(&self) -> usize {
+        self.checksums_by_location.len()
+    }
+
+    pub fn get_nskipped_reads(&self) -> u64 {
+        self.nskipped_reads
+    }
+
+    pub fn get_nredirected_reads(&self) -> u64 {
+        self.nredirected_reads
+    }
+}
+
+lazy_static! {
+    // None means it's off
+    static ref KNOWN_BLOCKS: RwLock<Option<KnownBlocks>> = RwLock::new(None);
+}
+
+// After this every read pipeline made with ReadPipeline::default remembers what it read and uses it, see ReadPipeline::use_known_blocks
+pub fn enable() {
+    if let Ok(mut lock) = KNOWN_BLOCKS.write() {
+        lock.get_or_insert_with(KnownBlocks::default);
+    }
+}
+
+// Forgets everything, ex. when the pool is opened at another txg
+pub fn disable() {
+    if let Ok(mut lock) = KNOWN_BLOCKS.write() {
+        *lock = None;
+    }
+}
+
+pub fn is_enabled() -> bool {
+    KNOWN_BLOCKS.read().is_ok_and(|lock| lock.is_some())
+}
+
+pub fn remember(
+    dva: &DataVirtualAddress,
+    psize: usize,
+    checksum_method: ChecksumMethod,
+    checksum: [u64; 4],
+) {
+    if let Ok(mut lock) = KNOWN_BLOCKS.write() {
+        if let Some(known_blocks) = lock.as_mut() {
+            known_blocks.remember(dva, psize, checksum_method, checksum);
+        }
+    }
+}
+
+// Same as KnownBlocks::is_known_mismatch, but also counts the read as skipped
+pub fn should_skip(
+    dva: &DataVirtualAddress,
+    psize: usize,
+    checksum_method: ChecksumMethod,
+    checksum: &[u64; 4],
+) -> bool {
+    let is_known_mismatch = KNOWN_BLOCKS.read().is_ok_and(|lock| {
+        lock.as_ref().is_some_and(|known_blocks| {
+            known_blocks.is_known_mismatch(dva, psize, checksum_method, checksum)
+        })
+    });
+    if is_known_mismatch {
+        if let Ok(mut lock) = KNOWN_BLOCKS.write() {
+            if let Some(known_blocks) = lock.as_mut() {
+                known_blocks.nskipped_reads += 1;
+            }
+        }
+    }
+    is_known_mismatch
+}
+
+pub fn get_locations(
+    checksum_method: ChecksumMethod,
+    checksum: &[u64; 4],
+    psize: usize,
+) -> Vec<Location> {
+    KNOWN_BLOCKS
+        .read()
+        .ok()
+        .and_then(|lock| {
+            lock.as_ref()
+                .map(|known_blocks| known_blocks.get_locations(checksum_method, checksum, psize))
+        })
+        .unwrap_or_default()
+}
+
+pub fn count_redirected_read() {
+    if let Ok(mut lock) = KNOWN_BLOCKS.write() {
+        if let Some(known_blocks) = lock.as_mut() {
+            known_blocks.nredirected_reads += 1;
+        }
+    }
+}
+
+// Returns: (how many locations are known, how many reads were skipped, how many blocks were read from another location)
+pub fn get_stats() -> Option<(usize, u64, u64)> {
+    let lock = KNOWN_BLOCKS.read().ok()?;
+    let known_blocks = lock.as_ref()?;
+    Some((
+        known_blocks.get_nlocations(),
+        known_blocks.get_nskipped_reads(),
+        known_blocks.get_nredirected_reads(),
+    ))
+}
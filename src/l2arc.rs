@@ -0,0 +1,350 @@
+// Persistent L2ARC (cache device) support
+// A cache device holds copies of blocks that were read recently, and since OpenZFS 2.0 it also has log blocks that say which block is where,
+// so the cache can be used again after a reboot. Those copies are still there if the pool's own disks got damaged, so they are one more place to look
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/arc_impl.h (l2arc_dev_hdr_phys_t, l2arc_log_blk_phys_t, l2arc_log_ent_phys_t)
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/arc.c (l2arc_rebuild, l2arc_log_blk_read)
+// NOTE: The log entries don't have the checksum of the block, only its first dva and birth txg, so that is what the index is keyed by
+//       The checksum is still checked when the data is read, so a stale entry can't return the wrong data
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, RwLock},
+};
+
+use lazy_static::lazy_static;
+
+use crate::{
+    byte_iter::{ByteIter, FromBytesLE},
+    zio::{self, ChecksumMethod, CompressionMethod, DataVirtualAddress},
+    Vdev,
+};
+
+// "ZFSCACHE" in ascii
+pub const DEVICE_HEADER_MAGIC: u64 = 0x5A46534341434845;
+// "LOGBLKHD" in ascii
+pub const LOG_BLOCK_MAGIC: u64 = 0x4C4F47424C4B4844;
+
+// Both the header and the blocks are in the part of the device after the labels and the boot block
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/vdev_impl.h (VDEV_LABEL_START_SIZE)
+const VDEV_LABEL_START_SIZE: u64 = 4 * 1024 * 1024;
+
+// The properties of a log block or of a cached block, packed like in a block pointer
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/arc_impl.h (L2BLK_GET_LSIZE, L2BLK_GET_PSIZE, ...)
+#[derive(Debug, Clone, Copy)]
+pub struct L2BlockProperties {
+    pub lsize: u64,
+    pub psize: u64,
+    pub compression_method: Option<CompressionMethod>,
+    pub checksum_method: Option<ChecksumMethod>,
+    // Encrypted or authenticated, the data on the cache device is exactly what's in the dvas
+    pub is_protected: bool,
+}
+
+impl L2BlockProperties {
+    pub fn from_value(value: u64) -> L2BlockProperties {
+        L2BlockProperties {
+            lsize: ((value & 0xFFFF) + 1) * 512,
+            psize: (((value >> 16) & 0xFFFF) + 1) * 512,
+            compression_method: CompressionMethod::from_value(((value >> 32) & 0x7F) as usize),
+            checksum_method: ChecksumMethod::from_value(((value >> 40) & 0xFF) as usize),
+            is_protected: (value >> 56) & 1 != 0,
+        }
+    }
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/arc_impl.h (l2arc_log_blkptr_t)
+#[derive(Debug, Clone, Copy)]
+pub struct LogBlockPointer {
+    // Offset from the start of the device
+    pub device_address: u64,
+    // How much space the log block takes up on the device
+    pub payload_asize: u64,
+    // Offset of the first cached block the log block describes
+    pub payload_start: u64,
+    pub properties: L2BlockProperties,
+    pub checksum: [u64; 4],
+}
+
+impl<It> FromBytesLE<It> for LogBlockPointer
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<LogBlockPointer> {
+        Some(LogBlockPointer {
+            device_address: u64::from_bytes_le(data)?,
+            payload_asize: u64::from_bytes_le(data)?,
+            payload_start: u64::from_bytes_le(data)?,
+            properties: L2BlockProperties::from_value(u64::from_bytes_le(data)?),
+            checksum: [
+                u64::from_bytes_le(data)?,
+                u64::from_bytes_le(data)?,
+                u64::from_bytes_le(data)?,
+                u64::from_bytes_le(data)?,
+            ],
+        })
+    }
+}
+
+impl LogBlockPointer {
+    pub const fn get_ondisk_size() -> usize {
+        core::mem::size_of::<u64>() * 8
+    }
+
+    // The last log blocks get overwritten when the device wraps around, those pointers point to zeros
+    pub fn is_valid(&self, header: &DeviceHeader) -> bool {
+        self.device_address >= header.start
+            && self.device_address.saturating_add(self.payload_asize) <= header.end
+            && self.payload_asize != 0
+    }
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/arc_impl.h (l2arc_dev_hdr_phys_t)
+#[derive(Debug)]
+pub struct DeviceHeader {
+    pub version: u64,
+    pub pool_guid: u64,
+    pub vdev_guid: u64,
+    // How many entries every log block has
+    pub log_entries: u64,
+    // The part of the device that holds cached blocks
+    pub start: u64,
+    pub end: u64,
+    // The two newest log blocks, every log block points to the one before it
+    pub start_log_block_pointers: [LogBlockPointer; 2],
+    pub log_block_count: u64,
+}
+
+impl<It> FromBytesLE<It> for DeviceHeader
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<DeviceHeader> {
+        if u64::from_bytes_le(data)? != DEVICE_HEADER_MAGIC {
+            return None;
+        }
+        let version = u64::from_bytes_le(data)?;
+        let pool_guid = u64::from_bytes_le(data)?;
+        let vdev_guid = u64::from_bytes_le(data)?;
+        let log_entries = u64::from_bytes_le(data)?;
+        let _evict = u64::from_bytes_le(data)?;
+        let _flags = u64::from_bytes_le(data)?;
+        let start = u64::from_bytes_le(data)?;
+        let end = u64::from_bytes_le(data)?;
+        let start_log_block_pointers = [
+            LogBlockPointer::from_bytes_le(data)?,
+            LogBlockPointer::from_bytes_le(data)?,
+        ];
+        let _log_blocks_asize = u64::from_bytes_le(data)?;
+        let log_block_count = u64::from_bytes_le(data)?;
+        Some(DeviceHeader {
+            version,
+            pool_guid,
+            vdev_guid,
+            log_entries,
+            start,
+            end,
+            start_log_block_pointers,
+            log_block_count,
+        })
+    }
+}
+
+impl DeviceHeader {
+    pub const fn get_ondisk_size() -> usize {
+        512
+    }
+}
+
+// Where a copy of a block is on the cache device
+#[derive(Debug, Clone, Copy)]
+pub struct L2ArcEntry {
+    pub birth_txg: u64,
+    pub device_address: u64,
+    pub properties: L2BlockProperties,
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/arc_impl.h (l2arc_log_ent_phys_t)
+fn parse_log_entry(
+    data: &mut impl Iterator<Item = u8>,
+) -> Option<(DataVirtualAddress, L2ArcEntry)> {
+    let dva = DataVirtualAddress::from_bytes_le(data);
+    let birth_txg = u64::from_bytes_le(data)?;
+    let properties = L2BlockProperties::from_value(u64::from_bytes_le(data)?);
+    let device_address = u64::from_bytes_le(data)?;
+    data.skip_n_bytes(3 * core::mem::size_of::<u64>())?; // complevel and padding
+    Some((
+        dva?,
+        L2ArcEntry {
+            birth_txg,
+            device_address,
+            properties,
+        },
+    ))
+}
+
+const LOG_ENTRY_SIZE: usize = 64;
+// Magic, the pointer to the previous log block and padding
+const LOG_BLOCK_HEADER_SIZE: usize = 128;
+
+fn read_from_device(
+    device: &mut dyn Vdev,
+    device_address: u64,
+    size: usize,
+) -> Result<Vec<u8>, ()> {
+    device.read(
+        device_address
+            .checked_sub(VDEV_LABEL_START_SIZE)
+            .ok_or(())?,
+        size,
+    )
+}
+
+// Returns: The log block, decompressed, if its checksum is right
+fn read_log_block(device: &mut dyn Vdev, pointer: &LogBlockPointer) -> Option<Vec<u8>> {
+    let data = read_from_device(
+        device,
+        pointer.device_address,
+        usize::try_from(pointer.payload_asize).ok()?,
+    )
+    .ok()?;
+    let data = &data[..(pointer.properties.psize as usize).min(data.len())];
+    if zio::try_checksum_block(data, pointer.properties.checksum_method?)? != pointer.checksum {
+        return None;
+    }
+    zio::try_decompress_block(
+        data,
+        pointer.properties.compression_method?,
+        pointer.properties.lsize as usize,
+    )
+    .ok()
+}
+
+#[derive(Debug, Default)]
+pub struct L2ArcIndex {
+    // The first dva of a block -> the copies the cache device has of it
+    entries: HashMap<(u32, u64), Vec<L2ArcEntry>>,
+    pub n_log_blocks: usize,
+    pub n_entries: usize,
+}
+
+impl L2ArcIndex {
+    // Reads the device header and follows the log blocks from the newest one back, like l2arc_rebuild does
+    // NOTE: The log blocks are in two interleaved chains, the previous pointer of a log block points two log blocks back, so the walk alternates between them
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/arc.c (l2arc_rebuild)
+    // Returns: None if the device doesn't have a persistent L2ARC header
+    pub fn build(device: &mut dyn Vdev) -> Option<(DeviceHeader, L2ArcIndex)> {
+        use crate::ansi_color::*;
+        let header_data = device.read(0, DeviceHeader::get_ondisk_size()).ok()?;
+        let header = DeviceHeader::from_bytes_le(&mut header_data.into_iter())?;
+        if header.version != 1 {
+            println!(
+                "{YELLOW}Warning{WHITE}: L2ARC header has version {}, i only know version 1!",
+                header.version
+            );
+            return None;
+        }
+
+        let mut index = L2ArcIndex::default();
+        let mut pointers = header.start_log_block_pointers;
+        while pointers[0].is_valid(&header) && (index.n_log_blocks as u64) < header.log_block_count
+        {
+            let pointer = pointers[0];
+            let Some(log_block) = read_log_block(device, &pointer) else {
+                // This is where the device wrapped around, nothing before this can be trusted
+                if cfg!(feature = "debug") {
+                    println!(
+                        "{YELLOW}Warning{WHITE}: L2ARC log block at {} couldn't be read, stopping there!",
+                        pointer.device_address
+                    );
+                }
+                break;
+            };
+            let mut data = log_block.iter().copied();
+            if u64::from_bytes_le(&mut data) != Some(LOG_BLOCK_MAGIC) {
+                break;
+            }
+            let Some(previous_pointer) = LogBlockPointer::from_bytes_le(&mut data) else {
+                break;
+            };
+
+            for entry_data in log_block[LOG_BLOCK_HEADER_SIZE..]
+                .chunks_exact(LOG_ENTRY_SIZE)
+                .take(header.log_entries as usize)
+            {
+                let Some((dva, entry)) = parse_log_entry(&mut entry_data.iter().copied()) else {
+                    continue;
+                };
+                index
+                    .entries
+                    .entry((dva.get_vdev_id(), dva.parse_offset()))
+                    .or_default()
+                    .push(entry);
+                index.n_entries += 1;
+            }
+            index.n_log_blocks += 1;
+            pointers = [pointers[1], previous_pointer];
+        }
+        Some((header, index))
+    }
+
+    // Returns: The copies of the block whose first dva is `dva`, the ones with the right birth txg first
+    pub fn get_entries(&self, dva: &DataVirtualAddress, birth_txg: u64) -> Vec<L2ArcEntry> {
+        let mut entries = self
+            .entries
+            .get(&(dva.get_vdev_id(), dva.parse_offset()))
+            .cloned()
+            .unwrap_or_default();
+        entries.sort_by_key(|entry| entry.birth_txg != birth_txg);
+        entries
+    }
+}
+
+struct CacheDevice {
+    device: Box<dyn Vdev>,
+    index: L2ArcIndex,
+}
+
+lazy_static! {
+    static ref CACHE_DEVICE: Mutex<Option<CacheDevice>> = Mutex::new(None);
+    static ref IS_LOADED: RwLock<bool> = RwLock::new(false);
+}
+
+// After this every read pipeline that has use_l2arc set will also look on the cache device when all the dvas of a block fail
+pub fn set_cache_device(device: Box<dyn Vdev>, index: L2ArcIndex) {
+    if let Ok(mut lock) = CACHE_DEVICE.lock() {
+        *lock = Some(CacheDevice { device, index });
+        if let Ok(mut is_loaded) = IS_LOADED.write() {
+            *is_loaded = true;
+        }
+    }
+}
+
+pub fn is_loaded() -> bool {
+    IS_LOADED.read().map(|lock| *lock).unwrap_or(false)
+}
+
+// Returns: The raw (still compressed) data of every copy of the block the cache device has, with its device address and properties
+// It's up to the caller to check the data, the entries could be stale
+pub fn read_cached_copies(dva: &DataVirtualAddress, birth_txg: u64) -> Vec<(Vec<u8>, L2ArcEntry)> {
+    let Ok(mut lock) = CACHE_DEVICE.lock() else {
+        return Vec::new();
+    };
+    let Some(cache_device) = lock.as_mut() else {
+        return Vec::new();
+    };
+
+    cache_device
+        .index
+        .get_entries(dva, birth_txg)
+        .into_iter()
+        .filter_map(|entry| {
+            let data = read_from_device(
+                cache_device.device.as_mut(),
+                entry.device_address,
+                entry.properties.psize as usize,
+            )
+            .ok()?;
+            Some((data, entry))
+        })
+        .collect()
+}
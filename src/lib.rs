@@ -8,28 +8,73 @@
     clippy::unusual_byte_groupings
 )]
 
+use std::{borrow::Cow, fmt::Debug, time};
+
+#[cfg(feature = "disk")]
 use std::{
-    fmt::Debug,
     fs::File,
     io::{Read, Seek, SeekFrom, Write},
-    time,
 };
 
-use byte_iter::{FromBytes, FromBytesLE};
+use byte_iter::{FromBytes, FromBytesBE, FromBytesLE};
 use lru::LruCache;
 use zio::Vdevs;
 
+#[cfg(feature = "async")]
+pub mod async_vdev;
+pub mod binpatch;
+pub mod bookmark;
 pub mod byte_iter;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod census;
+#[cfg(feature = "disk")]
+pub mod cli;
+pub mod damage_map;
+pub mod ddt;
+pub mod device_map;
+pub mod diff;
 pub mod dmu;
 pub mod dsl;
+pub mod errlog;
+pub mod features;
 pub mod fletcher;
+pub mod history;
+pub mod inspect;
+pub mod known_blocks;
+pub mod l2arc;
 pub mod lz4;
 pub mod lzjb;
+pub mod nbd;
 pub mod nvlist;
+pub mod parity;
+pub mod partition;
+pub mod properties;
+#[cfg(feature = "python")]
+pub mod pyszfs;
+#[cfg(feature = "disk")]
+pub mod reader;
+#[cfg(feature = "disk")]
+pub mod recovery;
+pub mod reverse_map;
+#[cfg(feature = "disk")]
+pub mod rewind;
+pub mod spa_config;
+pub mod spacemap;
+#[cfg(feature = "test-image")]
+pub mod test_image;
+pub mod traverse;
+pub mod verify;
+pub mod warnings;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "disk")]
 pub mod yolo_block_recovery;
 pub mod zap;
+pub mod zdb;
 pub mod zil;
 pub mod zio;
+pub mod zle;
 pub mod zpl;
 
 pub mod ansi_color {
@@ -48,7 +93,6 @@ pub mod ansi_color {
 // 5. Implement all system attributes
 // 6. Don't just skip the parity sectors in RAIDZ
 // 7. Test RAIDZ writing, and in general implement writing
-// 8. Figure out why dvas at the end of a plain file contents indirect block tree have vdev id 1
 // 9. Make sure usage of "as" is correct ( probably should use .try_into()? or something similar in some places )
 
 pub struct RaidzInfo {
@@ -73,43 +117,246 @@ pub trait Vdev: Send {
     // of the beginning of the vdev
     fn read(&mut self, offset_in_bytes: u64, amount_in_bytes: usize) -> Result<Vec<u8>, ()>;
 
+    // Same as read, but vdevs that have the data in memory anyway (ex. a memory mapped VdevFile) can return it without copying
+    // this adds up for big sequential scans, so they should use this
+    fn read_borrowed(
+        &mut self,
+        offset_in_bytes: u64,
+        amount_in_bytes: usize,
+    ) -> Result<Cow<'_, [u8]>, ()> {
+        self.read(offset_in_bytes, amount_in_bytes).map(Cow::Owned)
+    }
+
+    // Reads `amount_in_bytes` starting at the sector `sector_index` of a raidz, continuing down the disk that sector is on instead of across the disks
+    // that's where raidz puts the sectors of one column of a block, so a block that only has one data column can be read without copying it
+    // NOTE: Only raidz vdevs have columns, for the others this always fails
+    fn read_raidz_column_borrowed(
+        &mut self,
+        _sector_index: u64,
+        _amount_in_bytes: usize,
+    ) -> Result<Cow<'_, [u8]>, ()> {
+        Err(())
+    }
+
     fn write(&mut self, offset_in_bytes: u64, data: &[u8]) -> Result<(), ()>;
 
+    // A hint that these (offset, amount) ranges are going to be read soon, so vdevs that can (ex. VdevRaidz) read them ahead of time in as few reads as possible
+    // NOTE: Errors are ignored, the real read will run into them again
+    fn prefetch(&mut self, _ranges: &[(u64, usize)]) {}
+
     fn read_raw_label(&mut self, label_index: usize) -> Result<Vec<u8>, ()>;
     fn get_nlables(&mut self) -> usize;
     fn get_asize(&self) -> usize;
     fn get_raidz_info(&self) -> Option<RaidzInfo>;
 }
 
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/vdev_impl.h (VDEV_LABEL_START_SIZE, VDEV_LABEL_END_SIZE)
+pub const VDEV_LABEL_SIZE: u64 = 256 * 1024;
+// 2 labels and the boot block
+pub const VDEV_LABEL_START_SIZE: u64 = 4 * 1024 * 1024;
+pub const VDEV_LABEL_END_SIZE: u64 = 2 * VDEV_LABEL_SIZE;
+
+// How much of the start and the end of a file is searched for labels when the vdev isn't where it should be
+// partitions usually start at 1 mb, and zfs puts an 8 mb partition after its own one when it gets a whole disk
+const LABEL_SEARCH_SIZE: u64 = 32 * 1024 * 1024;
+
+// Where the vdev is in the file, normally the file is the vdev
+// but ex. an image of a whole disk has a partition table in front of it, and maybe other partitions after it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VdevGeometry {
+    // Where label 0 is in the file
+    pub start: u64,
+    // How much of the file after start is the vdev, the last 2 labels are at the end of this, None means until the end of the file
+    pub size: Option<u64>,
+    // Where the data starts, counting from start, so after the first 2 labels and the boot block
+    pub data_offset: u64,
+}
+
+impl Default for VdevGeometry {
+    fn default() -> Self {
+        Self {
+            start: 0,
+            size: None,
+            data_offset: VDEV_LABEL_START_SIZE,
+        }
+    }
+}
+
+#[cfg(feature = "disk")]
 #[derive(Debug)]
 pub struct VdevFile {
     device: File,
     file_size: u64,
+    geometry: VdevGeometry,
+    // Set by map_into_memory, then reads come from here instead of seeking and reading the file
+    #[cfg(feature = "mmap")]
+    map: Option<memmap2::Mmap>,
+    rate_limiter: Option<recovery::control::RateLimiter>,
 }
 
+#[cfg(feature = "disk")]
 impl From<File> for VdevFile {
-    fn from(mut f: File) -> Self {
+    fn from(f: File) -> Self {
+        Self::with_geometry(f, VdevGeometry::default())
+    }
+}
+
+#[cfg(feature = "disk")]
+impl VdevFile {
+    pub fn with_geometry(mut f: File, geometry: VdevGeometry) -> Self {
         let file_size = f.seek(SeekFrom::End(0)).unwrap();
         Self {
             device: f,
             file_size,
+            geometry,
+            #[cfg(feature = "mmap")]
+            map: None,
+            rate_limiter: None,
         }
     }
-}
 
-impl VdevFile {
+    // Every read (including the ones of the labels) waits for the rate limiter first, so a failing disk isn't read faster than it can take
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<recovery::control::RateLimiter>) {
+        self.rate_limiter = rate_limiter;
+    }
+
+    pub fn get_rate_limiter(&self) -> Option<&recovery::control::RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    // Maps the whole file into memory, so reads don't need a syscall and an allocation every time
+    // this is a lot faster for image files on fast ssds, especially when scanning
+    // NOTE: Writes still go through the file, the map is shared so it sees them
+    #[cfg(feature = "mmap")]
+    pub fn map_into_memory(&mut self) -> Result<(), ()> {
+        // SAFETY: The map is only ever read, if something else truncates the file while it's mapped reads past the new end will crash
+        // but nothing should be changing the disks that are being recovered from anyway
+        let map = unsafe { memmap2::Mmap::map(&self.device) }.map_err(|_| ())?;
+        if map.len() as u64 != self.file_size {
+            return Err(());
+        }
+        self.map = Some(map);
+        Ok(())
+    }
+
+    #[cfg(feature = "mmap")]
+    pub fn is_mapped(&self) -> bool {
+        self.map.is_some()
+    }
+
+    pub fn get_geometry(&self) -> VdevGeometry {
+        self.geometry
+    }
+
+    pub fn set_geometry(&mut self, geometry: VdevGeometry) {
+        self.geometry = geometry;
+    }
+
+    // A label has the embedded checksum magic at the end of its config and at least one uberblock
+    // the rest of it could be damaged, but this is only used to find where the labels are
+    fn looks_like_label(label: &[u8]) -> bool {
+        let has_config = EmbeddedChecksum::from_region_tail(&label[16 * 1024..128 * 1024])
+            .is_some_and(|checksum| checksum.has_valid_magic());
+        let uberblock_size = VdevLabel::get_uberblock_size_for_ashift(0);
+        has_config
+            && label[128 * 1024..]
+                .chunks_exact(uberblock_size)
+                .any(|uberblock| {
+                    u64::from_bytes_le(&mut uberblock.iter().copied()) == Some(UBERBLOCK_MAGIC)
+                })
+    }
+
+    // Returns: The offsets of everything that looks like a label in that part of the file
+    // NOTE: Labels are at least sector aligned, so only offsets that are multiples of 512 are checked
+    fn find_labels_in(&mut self, range: std::ops::Range<u64>) -> Vec<u64> {
+        let range = range.start..range.end.min(self.get_raw_size());
+        let Ok(data) = self.read_raw(range.start, range.end.saturating_sub(range.start) as usize)
+        else {
+            return Vec::new();
+        };
+        (0..data.len().saturating_sub(VDEV_LABEL_SIZE as usize - 1))
+            .step_by(512)
+            .filter(|off| Self::looks_like_label(&data[*off..*off + VDEV_LABEL_SIZE as usize]))
+            .map(|off| range.start + off as u64)
+            .collect()
+    }
+
+    pub fn has_label_at_start(&mut self) -> bool {
+        self.read_raw_label(0)
+            .is_ok_and(|label| Self::looks_like_label(&label))
+    }
+
+    // Looks in the partition table at the start of the file for a zfs partition, for images of whole disks
+    // Returns: The geometry of the first zfs partition with a label at its start, or just the first zfs partition if none have one
+    pub fn find_partition_geometry(&mut self) -> Option<VdevGeometry> {
+        let partitions =
+            partition::find_zfs_partitions(&mut |offset, amount| self.read_raw(offset, amount));
+        let partition = partitions
+            .iter()
+            .find(|partition| {
+                self.read_raw(partition.start, VDEV_LABEL_SIZE as usize)
+                    .is_ok_and(|label| Self::looks_like_label(&label))
+            })
+            .or(partitions.first())?;
+        Some(VdevGeometry {
+            start: partition.start,
+            size: Some(partition.size),
+            data_offset: VDEV_LABEL_START_SIZE,
+        })
+    }
+
+    // Searches the start and the end of the file for labels, for when the vdev isn't the whole file
+    // Returns: None if there is no label near the start of the file
+    // NOTE: If label 0 is damaged label 1 will be taken for it, there is no way to tell them apart
+    pub fn detect_geometry(&mut self) -> Option<VdevGeometry> {
+        let start = *self
+            .find_labels_in(0..LABEL_SEARCH_SIZE + VDEV_LABEL_SIZE)
+            .first()?;
+        // The last label that can be found is label 3, the vdev ends right after it
+        let tail_start = self
+            .get_raw_size()
+            .saturating_sub(LABEL_SEARCH_SIZE + VDEV_LABEL_SIZE)
+            .max(start + VDEV_LABEL_START_SIZE);
+        let size = self
+            .find_labels_in(tail_start..self.get_raw_size())
+            .last()
+            .map(|label3_start| label3_start + VDEV_LABEL_SIZE - start);
+        Some(VdevGeometry {
+            start,
+            size,
+            data_offset: VDEV_LABEL_START_SIZE,
+        })
+    }
+
     fn read_raw(&mut self, offset_in_bytes: u64, amount_in_bytes: usize) -> Result<Vec<u8>, ()> {
+        #[cfg(feature = "mmap")]
+        if self.map.is_some() {
+            return self
+                .read_raw_borrowed(offset_in_bytes, amount_in_bytes)
+                .map(|data| data.into_owned());
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.wait_for(amount_in_bytes);
+        }
         let mut buf = vec![0u8; amount_in_bytes];
         self.device
             .seek(SeekFrom::Start(offset_in_bytes))
             .map_err(|_| {
+                warnings::count_warning(warnings::WarningKind::DeviceIo);
                 if cfg!(feature = "debug") {
                     use crate::ansi_color::*;
                     println!("{YELLOW}Warning{WHITE}: The read at offset {:?} for device {:?} failed to seek!", offset_in_bytes, self);
                 }
             })?;
 
-        if self.device.read(&mut buf).map_err(|_| ())? != amount_in_bytes {
+        if self
+            .device
+            .read(&mut buf)
+            .map_err(|_| warnings::count_warning(warnings::WarningKind::DeviceIo))?
+            != amount_in_bytes
+        {
+            warnings::count_warning(warnings::WarningKind::DeviceIo);
             if cfg!(feature = "debug") {
                 use crate::ansi_color::*;
                 println!(
@@ -124,6 +371,40 @@ impl VdevFile {
         Ok(buf)
     }
 
+    // Returns: A slice of the map if the file is mapped, otherwise the data is read like read_raw does
+    fn read_raw_borrowed(
+        &mut self,
+        offset_in_bytes: u64,
+        amount_in_bytes: usize,
+    ) -> Result<Cow<'_, [u8]>, ()> {
+        #[cfg(feature = "mmap")]
+        if self.map.is_some() {
+            // Checked like this instead of with if let, so returning the borrowed data doesn't keep self borrowed on the other path
+            #[allow(clippy::unnecessary_unwrap)]
+            let map = self.map.as_ref().unwrap();
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.wait_for(amount_in_bytes);
+            }
+            let start = usize::try_from(offset_in_bytes).map_err(|_| ())?;
+            return match map.get(start..start.checked_add(amount_in_bytes).ok_or(())?) {
+                Some(data) => Ok(Cow::Borrowed(data)),
+                None => {
+                    if cfg!(feature = "debug") {
+                        use crate::ansi_color::*;
+                        println!(
+                            "{YELLOW}Warning{WHITE}: The read at {:?} for device {:?} is past the end of the map!",
+                            offset_in_bytes, self.device
+                        );
+                    }
+                    Err(())
+                }
+            };
+        }
+
+        self.read_raw(offset_in_bytes, amount_in_bytes)
+            .map(Cow::Owned)
+    }
+
     fn write_raw(&mut self, offset_in_bytes: u64, data: &[u8]) -> Result<(), ()> {
         self.device
             .seek(SeekFrom::Start(offset_in_bytes))
@@ -152,8 +433,51 @@ impl VdevFile {
     fn get_raw_size(&self) -> u64 {
         self.file_size
     }
+
+    // Returns: Where in the file the data read at that offset of the vdev is, Err if the read would go outside of the data part of the vdev
+    fn get_read_offset_in_file(
+        &self,
+        mut offset_in_bytes: u64,
+        amount_in_bytes: usize,
+    ) -> Result<u64, ()> {
+        offset_in_bytes = offset_in_bytes
+            .checked_add(self.geometry.data_offset)
+            .ok_or(())?;
+
+        // The boot block and 2 labels at the beginning and 2 labels at the end
+        if offset_in_bytes.saturating_add(amount_in_bytes as u64)
+            > self
+                .get_label_aligned_size()
+                .saturating_sub(VDEV_LABEL_END_SIZE)
+        {
+            use ansi_color::*;
+            println!(
+                "{YELLOW}Warning{WHITE}: Trying to read {:?} bytes from offset: {:?} would go outside the device {:?}!",
+                amount_in_bytes,
+                offset_in_bytes,
+                self
+            );
+
+            return Err(());
+        }
+
+        Ok(self.geometry.start + offset_in_bytes)
+    }
+
+    // zfs only uses the part of the device that is a whole number of labels long, so the ending labels are not always at the very end
+    // this matters for file backed vdevs, whose size can be anything
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/vdev.c (vdev_open, osize = P2ALIGN(osize, sizeof (vdev_label_t)))
+    // NOTE: This is the size of the vdev, so it doesn't count whatever is before the start of the vdev in the file
+    fn get_label_aligned_size(&self) -> u64 {
+        let size = self
+            .geometry
+            .size
+            .unwrap_or(self.get_raw_size().saturating_sub(self.geometry.start));
+        size / VDEV_LABEL_SIZE * VDEV_LABEL_SIZE
+    }
 }
 
+#[cfg(feature = "disk")]
 impl Vdev for VdevFile {
     fn get_from_block_cache(
         &mut self,
@@ -177,33 +501,30 @@ impl Vdev for VdevFile {
         unimplemented!()
     }
 
-    fn read(&mut self, mut offset_in_bytes: u64, amount_in_bytes: usize) -> Result<Vec<u8>, ()> {
-        offset_in_bytes += 4 * 1024 * 1024;
-
-        // 4 mb at the beginning and 2 labels at the end
-        if offset_in_bytes + amount_in_bytes as u64
-            > self.get_raw_size() - /* ending lables */ 2 * 256 * 1024
-        {
-            use ansi_color::*;
-            println!(
-                "{YELLOW}Warning{WHITE}: Trying to read {:?} bytes from offset: {:?} would go outside the device {:?}!",
-                amount_in_bytes,
-                offset_in_bytes,
-                self
-            );
-
-            return Err(());
-        }
-
+    fn read(&mut self, offset_in_bytes: u64, amount_in_bytes: usize) -> Result<Vec<u8>, ()> {
+        let offset_in_bytes = self.get_read_offset_in_file(offset_in_bytes, amount_in_bytes)?;
         self.read_raw(offset_in_bytes, amount_in_bytes)
     }
 
-    fn write(&mut self, mut offset_in_bytes: u64, data: &[u8]) -> Result<(), ()> {
-        offset_in_bytes += 4 * 1024 * 1024;
+    fn read_borrowed(
+        &mut self,
+        offset_in_bytes: u64,
+        amount_in_bytes: usize,
+    ) -> Result<Cow<'_, [u8]>, ()> {
+        let offset_in_bytes = self.get_read_offset_in_file(offset_in_bytes, amount_in_bytes)?;
+        self.read_raw_borrowed(offset_in_bytes, amount_in_bytes)
+    }
 
-        // 4 mb at the beginning and 2 labels at the end
-        if offset_in_bytes + data.len() as u64
-            > self.get_raw_size() - /* ending lables */ 2*256*1024
+    fn write(&mut self, mut offset_in_bytes: u64, data: &[u8]) -> Result<(), ()> {
+        offset_in_bytes = offset_in_bytes
+            .checked_add(self.geometry.data_offset)
+            .ok_or(())?;
+
+        // The boot block and 2 labels at the beginning and 2 labels at the end
+        if offset_in_bytes.saturating_add(data.len() as u64)
+            > self
+                .get_label_aligned_size()
+                .saturating_sub(VDEV_LABEL_END_SIZE)
         {
             use ansi_color::*;
             println!(
@@ -212,26 +533,34 @@ impl Vdev for VdevFile {
             );
             return Err(());
         }
-        self.write_raw(offset_in_bytes, data)
+        self.write_raw(self.geometry.start + offset_in_bytes, data)
     }
 
     fn get_size(&self) -> u64 {
-        self.get_raw_size()
-        -4*1024*1024 /* beginning boot block and labels */
-        -2*256*1024 /* ending labels */
+        self.get_label_aligned_size()
+            .saturating_sub(self.geometry.data_offset)
+            .saturating_sub(VDEV_LABEL_END_SIZE)
     }
 
     // Source: http://www.giis.co.in/Zfs_ondiskformat.pdf
     // Section 1.2.1
 
     fn read_raw_label(&mut self, label_index: usize) -> Result<Vec<u8>, ()> {
-        match label_index {
-            0 => self.read_raw(0, 256 * 1024),
-            1 => self.read_raw(256 * 1024, 256 * 1024),
-            2 => self.read_raw(self.get_raw_size() - 2 * 256 * 1024, 256 * 1024),
-            3 => self.read_raw(self.get_raw_size() - 1 * 256 * 1024, 256 * 1024),
-            _ => Err(()),
-        }
+        let label_offset = match label_index {
+            0 => 0,
+            1 => VDEV_LABEL_SIZE,
+            // A device that is too small to even hold the labels is definitely not a vdev
+            2 => self
+                .get_label_aligned_size()
+                .checked_sub(2 * VDEV_LABEL_SIZE)
+                .ok_or(())?,
+            3 => self
+                .get_label_aligned_size()
+                .checked_sub(VDEV_LABEL_SIZE)
+                .ok_or(())?,
+            _ => return Err(()),
+        };
+        self.read_raw(self.geometry.start + label_offset, VDEV_LABEL_SIZE as usize)
     }
 
     fn get_nlables(&mut self) -> usize {
@@ -239,8 +568,141 @@ impl Vdev for VdevFile {
     }
 }
 
+// Recovery should never modify the disks it is recovering from, even a stray write can destroy the only copy of something
+// so the disks are wrapped in this, which refuses all writes unless they were explicitly allowed (ex. with --allow-write)
+#[derive(Debug)]
+pub struct ReadOnlyVdev<V: Vdev> {
+    vdev: V,
+    allow_write: bool,
+}
+
+impl<V: Vdev> ReadOnlyVdev<V> {
+    pub fn new(vdev: V) -> ReadOnlyVdev<V> {
+        ReadOnlyVdev {
+            vdev,
+            allow_write: false,
+        }
+    }
+
+    // NOTE: Only use this when the user asked for it
+    pub fn allowing_writes(vdev: V) -> ReadOnlyVdev<V> {
+        ReadOnlyVdev {
+            vdev,
+            allow_write: true,
+        }
+    }
+
+    pub fn allows_writes(&self) -> bool {
+        self.allow_write
+    }
+
+    pub fn get_inner(&self) -> &V {
+        &self.vdev
+    }
+
+    pub fn into_inner(self) -> V {
+        self.vdev
+    }
+}
+
+impl<V: Vdev> Vdev for ReadOnlyVdev<V> {
+    fn get_from_block_cache(
+        &mut self,
+        key: &([u64; 4], zio::ChecksumMethod),
+    ) -> Option<Option<&[u8]>> {
+        self.vdev.get_from_block_cache(key)
+    }
+
+    fn put_in_block_cache(&mut self, key: ([u64; 4], zio::ChecksumMethod), value: Option<Vec<u8>>) {
+        self.vdev.put_in_block_cache(key, value)
+    }
+
+    fn get_size(&self) -> u64 {
+        self.vdev.get_size()
+    }
+
+    fn read(&mut self, offset_in_bytes: u64, amount_in_bytes: usize) -> Result<Vec<u8>, ()> {
+        self.vdev.read(offset_in_bytes, amount_in_bytes)
+    }
+
+    fn read_borrowed(
+        &mut self,
+        offset_in_bytes: u64,
+        amount_in_bytes: usize,
+    ) -> Result<Cow<'_, [u8]>, ()> {
+        self.vdev.read_borrowed(offset_in_bytes, amount_in_bytes)
+    }
+
+    fn read_raidz_column_borrowed(
+        &mut self,
+        sector_index: u64,
+        amount_in_bytes: usize,
+    ) -> Result<Cow<'_, [u8]>, ()> {
+        self.vdev
+            .read_raidz_column_borrowed(sector_index, amount_in_bytes)
+    }
+
+    fn write(&mut self, offset_in_bytes: u64, data: &[u8]) -> Result<(), ()> {
+        if !self.allow_write {
+            use ansi_color::*;
+            println!(
+                "{RED}Important{WHITE}: Refusing to write {} bytes at offset {:?}, the vdev is read only (writes have to be explicitly allowed)!",
+                data.len(),
+                offset_in_bytes
+            );
+            return Err(());
+        }
+        self.vdev.write(offset_in_bytes, data)
+    }
+
+    fn prefetch(&mut self, ranges: &[(u64, usize)]) {
+        self.vdev.prefetch(ranges)
+    }
+
+    fn read_raw_label(&mut self, label_index: usize) -> Result<Vec<u8>, ()> {
+        self.vdev.read_raw_label(label_index)
+    }
+
+    fn get_nlables(&mut self) -> usize {
+        self.vdev.get_nlables()
+    }
+
+    fn get_asize(&self) -> usize {
+        self.vdev.get_asize()
+    }
+
+    fn get_raidz_info(&self) -> Option<RaidzInfo> {
+        self.vdev.get_raidz_info()
+    }
+}
+
+// What is known about one of the disks of a raidz
+// NOTE: zfs uses the ashift of the top level vdev for all of its children, so the only thing that can differ between them is the size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaidzChildInfo {
+    // Declared missing, every read from it fails
+    Missing,
+    // The size of the part of the disk after the labels and the boot block
+    Present { size: u64 },
+}
+
+// Why VdevRaidz::from_vdevs_checked refused the devices it was given
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaidzLayoutError {
+    // The key of a device isn't a valid child index (0..ndevices)
+    KeyOutOfRange(usize),
+    // A child was declared missing but a device was given for it anyway
+    MissingAndPresent(usize),
+    // A child has no device, and wasn't declared missing
+    Unaccounted(usize),
+    // More children are missing than the parity could make up for, so there is no point
+    TooManyMissing { nmissing: usize, nparity: usize },
+    NoDevices,
+}
+
 pub struct VdevRaidz<'a> {
     devices: Vdevs<'a>,
+    children: Vec<RaidzChildInfo>,
     size: u64,
     ndevices: usize,
     nparity: usize,
@@ -263,10 +725,21 @@ impl<'a> VdevRaidz<'a> {
         nparity: usize,
         asize: usize,
     ) -> VdevRaidz {
+        let children = (0..ndevices)
+            .map(|index| match devices.get(&index) {
+                Some(device) => RaidzChildInfo::Present {
+                    size: device.get_size(),
+                },
+                None => RaidzChildInfo::Missing,
+            })
+            .collect();
         let device_size = devices.iter().map(|dev| dev.1.get_size()).min().unwrap();
+        // Only whole sectors can be used, which matters for sector sizes bigger than 512
+        let device_size = device_size / (asize as u64) * (asize as u64);
         let size = device_size * (ndevices as u64);
         VdevRaidz {
             devices,
+            children,
             size,
             ndevices,
             nparity,
@@ -283,6 +756,124 @@ impl<'a> VdevRaidz<'a> {
         }
     }
 
+    // Like from_vdevs, but checks that every child of the raidz either has a device or was declared missing in `missing`
+    // NOTE: Reads from missing children fail, so until parity is used to rebuild them only the blocks that are all on the present ones can be read
+    pub fn from_vdevs_checked(
+        devices: Vdevs<'a>,
+        ndevices: usize,
+        nparity: usize,
+        asize: usize,
+        missing: &[usize],
+    ) -> Result<VdevRaidz<'a>, RaidzLayoutError> {
+        if devices.is_empty() {
+            return Err(RaidzLayoutError::NoDevices);
+        }
+        if let Some(key) = devices.keys().find(|key| **key >= ndevices) {
+            return Err(RaidzLayoutError::KeyOutOfRange(*key));
+        }
+        if let Some(index) = missing.iter().find(|index| **index >= ndevices) {
+            return Err(RaidzLayoutError::KeyOutOfRange(*index));
+        }
+        if let Some(index) = missing.iter().find(|index| devices.contains_key(index)) {
+            return Err(RaidzLayoutError::MissingAndPresent(*index));
+        }
+        if let Some(index) =
+            (0..ndevices).find(|index| !devices.contains_key(index) && !missing.contains(index))
+        {
+            return Err(RaidzLayoutError::Unaccounted(index));
+        }
+        // The same child could be in missing more than once, so they are counted using the devices instead
+        let nmissing = ndevices - devices.len();
+        if nmissing > nparity {
+            return Err(RaidzLayoutError::TooManyMissing { nmissing, nparity });
+        }
+
+        let raidz = Self::from_vdevs(devices, ndevices, nparity, asize);
+        let sizes = raidz.children.iter().filter_map(|child| match child {
+            RaidzChildInfo::Present { size } => Some(*size),
+            RaidzChildInfo::Missing => None,
+        });
+        if let (Some(min_size), Some(max_size)) = (sizes.clone().min(), sizes.max()) {
+            if max_size - min_size >= asize as u64 {
+                use crate::ansi_color::*;
+                println!("{YELLOW}Warning{WHITE}: The disks of the raidz aren't the same size (from {min_size} to {max_size} bytes), only the first {min_size} bytes of every disk are used!");
+            }
+        }
+        Ok(raidz)
+    }
+
+    pub fn get_children_info(&self) -> &[RaidzChildInfo] {
+        &self.children
+    }
+
+    // Returns: The columns of the block at that offset, parity columns included, in the order zfs lays them out (the parity columns first)
+    // NOTE: The last columns are one sector shorter than the rest if the data doesn't fill the last row
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/vdev_raidz.c (vdev_raidz_map_alloc)
+    pub fn read_columns(
+        &mut self,
+        offset_in_bytes: u64,
+        psize: usize,
+    ) -> Result<Vec<parity::RaidzColumn>, ()> {
+        let asize = self.get_asize();
+        if !offset_in_bytes.is_multiple_of(asize as u64) || psize == 0 {
+            return Err(());
+        }
+
+        let first_sector_index = offset_in_bytes / asize as u64;
+        let ndata_sectors = psize.div_ceil(asize);
+        let ndata_columns = self.ndevices - self.nparity;
+        let full_rows = ndata_sectors / ndata_columns;
+        let remainder = ndata_sectors % ndata_columns;
+        // The columns that have one more sector than the rest
+        let nbig_columns = if remainder == 0 {
+            0
+        } else {
+            remainder + self.nparity
+        };
+        let ncolumns = if full_rows == 0 {
+            nbig_columns
+        } else {
+            self.ndevices
+        };
+
+        // The same raidz1 quirk as in DataVirtualAddress::dereference_raw, on odd megabyte offsets the parity and the first data column switch places
+        let mut column_mapping = (0..ncolumns).collect::<Vec<usize>>();
+        if self.nparity == 1 && !(offset_in_bytes / (1024 * 1024)).is_multiple_of(2) {
+            column_mapping.swap(0, 1);
+        }
+
+        let mut columns = Vec::with_capacity(ncolumns);
+        for (column_index, actual_column) in column_mapping.into_iter().enumerate() {
+            let nrows = if column_index < nbig_columns {
+                full_rows + 1
+            } else {
+                full_rows
+            };
+            let mut data = Vec::with_capacity(nrows * asize);
+            for row in 0..nrows {
+                data.extend(self.read_sector(
+                    first_sector_index + (actual_column + row * self.ndevices) as u64,
+                )?);
+            }
+            columns.push(parity::RaidzColumn {
+                device: ((first_sector_index + actual_column as u64) % self.ndevices as u64)
+                    as usize,
+                data,
+            });
+        }
+        Ok(columns)
+    }
+
+    // Recomputes the parity of the block at that offset, to find out which disk has the wrong data when the checksum of a block fails
+    pub fn verify_stripe(
+        &mut self,
+        offset_in_bytes: u64,
+        psize: usize,
+    ) -> Result<parity::StripeCheck, ()> {
+        let columns = self.read_columns(offset_in_bytes, psize)?;
+        Ok(parity::check_columns(&columns, self.nparity))
+    }
+
     pub fn read_sector(&mut self, sector_index: u64) -> Result<Vec<u8>, ()> {
         if let Some(res) = self.sector_cache.get_mut(&sector_index).cloned() {
             if cfg!(feature = "debug") {
@@ -322,6 +913,44 @@ impl<'a> VdevRaidz<'a> {
         Ok(res)
     }
 
+    // Reads `amount_in_bytes` starting `offset_in_sector` bytes into the sector, continuing down the disk the sector is on
+    // so this is one read from one child, and the data is only copied if the child doesn't have it in memory
+    // NOTE: Whole sectors that had to be copied are put in the sector cache, like read_sector does
+    fn read_child_borrowed(
+        &mut self,
+        sector_index: u64,
+        offset_in_sector: usize,
+        amount_in_bytes: usize,
+    ) -> Result<Cow<'_, [u8]>, ()> {
+        let asize = self.get_asize();
+        if offset_in_sector + amount_in_bytes <= asize && self.sector_cache.contains(&sector_index)
+        {
+            let sector = self.sector_cache.get(&sector_index).unwrap();
+            return Ok(Cow::Borrowed(
+                &sector[offset_in_sector..offset_in_sector + amount_in_bytes],
+            ));
+        }
+
+        let ndevices = self.ndevices as u64;
+        let device_sector_index = sector_index / ndevices;
+        let device_number = (sector_index % ndevices) as usize;
+        let data = self
+            .devices
+            .get_mut(&device_number)
+            .ok_or(())?
+            .read_borrowed(
+                device_sector_index * (asize as u64) + offset_in_sector as u64,
+                amount_in_bytes,
+            )?;
+        if let (Cow::Owned(data), 0) = (&data, offset_in_sector) {
+            for (row, sector) in data.chunks_exact(asize).enumerate() {
+                self.sector_cache
+                    .put(sector_index + row as u64 * ndevices, sector.to_vec());
+            }
+        }
+        Ok(data)
+    }
+
     pub fn write_sector(&mut self, sector_index: u64, data: &[u8]) -> Result<(), ()> {
         let device_sector_index = sector_index / (self.ndevices as u64);
         let device_number = (sector_index % (self.ndevices as u64)) as usize;
@@ -425,6 +1054,33 @@ impl Vdev for VdevRaidz<'_> {
         Ok(result)
     }
 
+    // Reads that stay inside one sector are read from the child that sector is on (or the sector cache) without copying them
+    // reads that go past the end of the sector continue on the next disk, so they have to be put together like read does
+    fn read_borrowed(
+        &mut self,
+        offset_in_bytes: u64,
+        amount_in_bytes: usize,
+    ) -> Result<Cow<'_, [u8]>, ()> {
+        let asize = self.get_asize();
+        let offset_in_sector = (offset_in_bytes % (asize as u64)) as usize;
+        if amount_in_bytes == 0 || offset_in_sector + amount_in_bytes > asize {
+            return self.read(offset_in_bytes, amount_in_bytes).map(Cow::Owned);
+        }
+        self.read_child_borrowed(
+            offset_in_bytes / (asize as u64),
+            offset_in_sector,
+            amount_in_bytes,
+        )
+    }
+
+    fn read_raidz_column_borrowed(
+        &mut self,
+        sector_index: u64,
+        amount_in_bytes: usize,
+    ) -> Result<Cow<'_, [u8]>, ()> {
+        self.read_child_borrowed(sector_index, 0, amount_in_bytes)
+    }
+
     fn write(&mut self, offset_in_bytes: u64, data: &[u8]) -> Result<(), ()> {
         if data.is_empty() {
             return Ok(());
@@ -485,6 +1141,57 @@ impl Vdev for VdevRaidz<'_> {
         Ok(())
     }
 
+    // Reads the sectors of all of the ranges that aren't in the sector cache, the sectors that are next to each other on a disk are read at once
+    // so a batch of blocks costs about one read per disk, instead of one read per sector
+    // NOTE: At most half of the sector cache is filled, otherwise the prefetched sectors would push each other out before they are used
+    fn prefetch(&mut self, ranges: &[(u64, usize)]) {
+        let asize = self.get_asize() as u64;
+        let ndevices = self.ndevices as u64;
+        let max_sectors = self.sector_cache.cap().get() / 2;
+        let mut nsectors = 0;
+        let mut sectors_per_device = vec![Vec::<u64>::new(); self.ndevices];
+        'ranges: for &(offset_in_bytes, amount_in_bytes) in ranges {
+            if amount_in_bytes == 0 {
+                continue;
+            }
+            let first_sector_index = offset_in_bytes / asize;
+            let last_sector_index = (offset_in_bytes + amount_in_bytes as u64 - 1) / asize;
+            for sector_index in first_sector_index..=last_sector_index {
+                if self.sector_cache.contains(&sector_index) {
+                    continue;
+                }
+                if nsectors >= max_sectors {
+                    break 'ranges;
+                }
+                nsectors += 1;
+                sectors_per_device[(sector_index % ndevices) as usize]
+                    .push(sector_index / ndevices);
+            }
+        }
+
+        for (device_number, mut device_sectors) in sectors_per_device.into_iter().enumerate() {
+            // Missing disks can't be read anyway
+            let Some(device) = self.devices.get_mut(&device_number) else {
+                continue;
+            };
+            device_sectors.sort_unstable();
+            device_sectors.dedup();
+            for run in device_sectors.chunk_by(|a, b| *b == a + 1) {
+                let Ok(data) = device.read(run[0] * asize, run.len() * asize as usize) else {
+                    continue;
+                };
+                for (device_sector_index, sector) in
+                    run.iter().zip(data.chunks_exact(asize as usize))
+                {
+                    self.sector_cache.put(
+                        device_sector_index * ndevices + device_number as u64,
+                        sector.to_vec(),
+                    );
+                }
+            }
+        }
+    }
+
     // Maps label_index to the devices
     // 0..=3 => first device
     // 4..=7 => second device
@@ -502,41 +1209,191 @@ impl Vdev for VdevRaidz<'_> {
     }
 }
 
+// Labels, the boot environment and the config have a checksum at the end of their region instead of in a block pointer
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zio.h (zio_eck_t)
+pub const EMBEDDED_CHECKSUM_MAGIC: u64 = 0x0210da7ab10c7a11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmbeddedChecksum {
+    pub magic: u64,
+    pub checksum: [u64; 4],
+}
+
+impl EmbeddedChecksum {
+    pub const fn get_ondisk_size() -> usize {
+        core::mem::size_of::<u64>() * 5
+    }
+
+    // Returns: The embedded checksum at the end of the region
+    pub fn from_region_tail(region: &[u8]) -> Option<EmbeddedChecksum> {
+        let mut data = region
+            .get(region.len().checked_sub(Self::get_ondisk_size())?..)?
+            .iter()
+            .copied();
+        Some(EmbeddedChecksum {
+            magic: u64::from_bytes_le(&mut data)?,
+            checksum: [
+                u64::from_bytes_le(&mut data)?,
+                u64::from_bytes_le(&mut data)?,
+                u64::from_bytes_le(&mut data)?,
+                u64::from_bytes_le(&mut data)?,
+            ],
+        })
+    }
+
+    // NOTE: The checksum itself is sha256 with the offset of the label as the verifier, which we can't check
+    pub fn has_valid_magic(&self) -> bool {
+        self.magic == EMBEDDED_CHECKSUM_MAGIC
+    }
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/vdev_impl.h (vdev_boot_envblock_t, VB_RAW, VB_NVLIST)
+#[derive(Debug)]
+pub enum BootEnvironment {
+    // Used by grub, this is just its environment block
+    Raw(Vec<u8>),
+    NVList(nvlist::NVList),
+    Unknown { version: u64, data: Vec<u8> },
+}
+
+// The second 8k of the label
+// Older pools had a boot header here that pointed to the boot block, newer ones have the boot environment
+#[derive(Debug)]
+pub enum BootBlock {
+    // Source: https://github.com/illumos/illumos-gate/blob/master/usr/src/uts/common/fs/zfs/sys/vdev_impl.h (vdev_boot_header_t)
+    LegacyHeader {
+        version: u64,
+        boot_block_offset: u64,
+        boot_block_size: u64,
+    },
+    Environment {
+        environment: BootEnvironment,
+        embedded_checksum: EmbeddedChecksum,
+    },
+}
+
+const LEGACY_BOOT_HEADER_MAGIC: u64 = 0x2f5b007b10c;
+
 #[derive(Debug)]
 pub struct VdevLabel {
+    // Left empty so vtoc labels and the like don't get overwritten, but it's still interesting for forensics
+    blank_raw: Vec<u8>,
+    boot_block_raw: Vec<u8>,
     name_value_pairs_raw: Vec<u8>,
     uberblocks_raw: Vec<u8>,
-    uberblock_size: Option<usize>,
+    uberblock_size: usize,
 }
 
 impl VdevLabel {
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/vdev_impl.h (vdev_label_t)
     pub fn from_bytes(data: &[u8]) -> VdevLabel {
+        let name_value_pairs_raw = data[16 * 1024..128 * 1024].to_owned();
+        // The size of the uberblock slots depends on the ashift of the top level vdev, which is in the config
+        // if the config can't be read, the smallest possible size is used, so every uberblock is still found
+        let uberblock_size = match Self::parse_top_level_ashift(&name_value_pairs_raw) {
+            Some(ashift) => Self::get_uberblock_size_for_ashift(ashift),
+            None => {
+                use crate::ansi_color::*;
+                if cfg!(feature = "debug") {
+                    println!("{YELLOW}Warning{WHITE}: Couldn't get the ashift from the label, assuming the smallest uberblock size!");
+                }
+                Self::get_uberblock_size_for_ashift(0)
+            }
+        };
+
         VdevLabel {
-            name_value_pairs_raw: data[16 * 1024..128 * 1024].to_owned(),
+            blank_raw: data[0..8 * 1024].to_owned(),
+            boot_block_raw: data[8 * 1024..16 * 1024].to_owned(),
+            name_value_pairs_raw,
             uberblocks_raw: data[128 * 1024..].to_owned(),
-            uberblock_size: None,
+            uberblock_size,
         }
     }
 
-    pub fn set_raw_uberblock_size(&mut self, uberblock_size: usize) {
-        if self.uberblock_size.is_some() {
-            panic!("Can't set uberblock size twice!");
-        } else {
-            self.uberblock_size = Some(uberblock_size);
+    fn parse_top_level_ashift(name_value_pairs_raw: &[u8]) -> Option<u64> {
+        let name_value_pairs = nvlist::from_bytes_xdr(&mut name_value_pairs_raw.iter().copied())?;
+        let Some(nvlist::Value::NVList(vdev_tree)) = name_value_pairs.get("vdev_tree") else {
+            return None;
+        };
+        match vdev_tree.get("ashift") {
+            Some(nvlist::Value::U64(ashift)) => Some(*ashift),
+            _ => None,
+        }
+    }
+
+    pub fn get_blank_raw(&self) -> &[u8] {
+        &self.blank_raw
+    }
+
+    pub fn get_boot_block_raw(&self) -> &[u8] {
+        &self.boot_block_raw
+    }
+
+    // Returns: None if the boot block has never been written (it's all zeros) or is corrupted
+    pub fn parse_boot_block(&self) -> Option<BootBlock> {
+        let mut data = self.boot_block_raw.iter().copied();
+        let first_word = u64::from_bytes_le(&mut data)?;
+        if first_word == LEGACY_BOOT_HEADER_MAGIC {
+            return Some(BootBlock::LegacyHeader {
+                version: u64::from_bytes_le(&mut data)?,
+                boot_block_offset: u64::from_bytes_le(&mut data)?,
+                boot_block_size: u64::from_bytes_le(&mut data)?,
+            });
         }
+
+        let embedded_checksum = EmbeddedChecksum::from_region_tail(&self.boot_block_raw)?;
+        if !embedded_checksum.has_valid_magic() {
+            return None;
+        }
+
+        let version = first_word;
+        let env_end = self.boot_block_raw.len() - EmbeddedChecksum::get_ondisk_size();
+        let env_data = self.boot_block_raw.get(8..env_end)?;
+        let environment = match version {
+            // The environment is a nul terminated string
+            0 => BootEnvironment::Raw(
+                env_data
+                    .iter()
+                    .copied()
+                    .take_while(|byte| *byte != 0)
+                    .collect(),
+            ),
+            1 => BootEnvironment::NVList(nvlist::from_bytes_xdr(&mut env_data.iter().copied())?),
+            _ => BootEnvironment::Unknown {
+                version,
+                data: env_data.to_owned(),
+            },
+        };
+        Some(BootBlock::Environment {
+            environment,
+            embedded_checksum,
+        })
+    }
+
+    // Returns: The embedded checksum at the end of the config, None if the label is too short
+    pub fn get_name_value_pairs_checksum(&self) -> Option<EmbeddedChecksum> {
+        EmbeddedChecksum::from_region_tail(&self.name_value_pairs_raw)
+    }
+
+    // Overrides the uberblock size that was figured out from the config, ex. if the config is damaged
+    pub fn set_raw_uberblock_size(&mut self, uberblock_size: usize) {
+        self.uberblock_size = uberblock_size;
+    }
+
+    // The uberblock slots are at least 1k, even with 512 byte sectors, and at most 8k
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/vdev_impl.h (VDEV_UBERBLOCK_SHIFT)
+    pub fn get_uberblock_size_for_ashift(ashift: u64) -> usize {
+        1 << ashift.clamp(10, 13)
     }
 
     pub fn get_raw_uberblock_size(&self) -> usize {
         self.uberblock_size
-            .expect("Uberblock size should be initialised!")
     }
 
-    pub fn get_raw_uberblock(&self, index: usize) -> &[u8] {
-        if index >= self.get_raw_uberblock_count() {
-            panic!("Attempt to get uberblock past the end of the uberblock array!");
-        }
-        &self.uberblocks_raw
-            [index * self.get_raw_uberblock_size()..(index + 1) * self.get_raw_uberblock_size()]
+    // Returns: None if the index is past the end of the uberblock array
+    pub fn get_raw_uberblock(&self, index: usize) -> Option<&[u8]> {
+        self.uberblocks_raw
+            .get(index * self.get_raw_uberblock_size()..(index + 1) * self.get_raw_uberblock_size())
     }
 
     pub fn get_raw_uberblock_count(&self) -> usize {
@@ -548,6 +1405,64 @@ impl VdevLabel {
     }
 }
 
+#[derive(Debug)]
+pub struct LabelCheck {
+    pub index: usize,
+    pub readable: bool,
+    // The config has an embedded checksum with the right magic, so this really is a label
+    pub has_checksum_magic: bool,
+    pub guid: Option<u64>,
+    pub txg: Option<u64>,
+}
+
+impl LabelCheck {
+    // Returns: true if this label agrees with the other one, a label that disagrees is stale, missing or from another device
+    pub fn matches(&self, other: &LabelCheck) -> bool {
+        self.readable
+            && self.has_checksum_magic
+            && self.guid.is_some()
+            && self.guid == other.guid
+            && self.txg == other.txg
+    }
+}
+
+// Reads all the labels of the vdev, L0 and L1 at the start, L2 and L3 at the end
+// since they all have a copy of the same config, a label that doesn't agree with the rest is either not where it should be, or stale
+pub fn check_labels(vdev: &mut dyn Vdev) -> Vec<LabelCheck> {
+    let mut checks = Vec::new();
+    for index in 0..vdev.get_nlables() {
+        let Ok(raw_label) = vdev.read_raw_label(index) else {
+            checks.push(LabelCheck {
+                index,
+                readable: false,
+                has_checksum_magic: false,
+                guid: None,
+                txg: None,
+            });
+            continue;
+        };
+
+        let label = VdevLabel::from_bytes(&raw_label);
+        let has_checksum_magic = label
+            .get_name_value_pairs_checksum()
+            .is_some_and(|checksum| checksum.has_valid_magic());
+        let name_value_pairs =
+            nvlist::from_bytes_xdr(&mut label.get_name_value_pairs_raw().iter().copied());
+        let get_u64 = |name: &str| match name_value_pairs.as_ref()?.get(name) {
+            Some(nvlist::Value::U64(value)) => Some(*value),
+            _ => None,
+        };
+        checks.push(LabelCheck {
+            index,
+            readable: true,
+            has_checksum_magic,
+            guid: get_u64("guid"),
+            txg: get_u64("txg"),
+        });
+    }
+    checks
+}
+
 #[derive(Debug)]
 pub struct Uberblock {
     pub version: u64,
@@ -557,7 +1472,7 @@ pub struct Uberblock {
     pub rootbp: zio::BlockPointer,
 }
 
-const UBERBLOCK_MAGIC: u64 = 0x00bab10c;
+pub const UBERBLOCK_MAGIC: u64 = 0x00bab10c;
 
 impl<It> FromBytesLE<It> for Uberblock
 where
@@ -589,14 +1504,18 @@ where
 {
     fn from_bytes(data: &mut It) -> Option<Uberblock> {
         let ub_magic_le = u64::from_bytes_le(&mut data.clone())?;
-        let ub_magic_be = u64::from_bytes_le(&mut data.clone())?;
+        let ub_magic_be = u64::from_bytes_be(&mut data.clone())?;
 
         if ub_magic_le == UBERBLOCK_MAGIC {
             // Little-endian
             Self::from_bytes_le(data)
         } else if ub_magic_be == UBERBLOCK_MAGIC {
             // Big-endian
-            todo!("Implement big endian support!");
+            use crate::ansi_color::*;
+            if cfg!(feature = "debug") {
+                println!("{YELLOW}Warning{WHITE}: Found a big endian uberblock, big endian pools are not supported!");
+            }
+            None
         } else {
             // Invalid magic
             return None;
@@ -1,5 +1,3 @@
-use crate::byte_iter::FromBytesLE;
-
 // Warning: The size of input is relevant as the lz4 format may not be able to figure out when the stream ends
 // due to 00 00 00 being a valid block that means copy the last byte 4 times
 // NOTE: The hint output size is used to presize the output vector
@@ -36,17 +34,15 @@ pub fn lz4_decompress_blocks(
             output_buf.push(data.next().ok_or_else(|| output_buf.clone())?);
         }
 
-        let Some(lookback) = u16::from_bytes_le(data) else {
-            if lookback_size == 0 {
-                // Reached end of all lz4 blocks
-                // This is not an error
-                break;
-            }else{
-                // Stream ended abruptly, since the lookback_size was not 0 this could not have been the last block
-                // so it must have a lookback, but we couldn't read it because the stream ended
-                return Err(output_buf);
-            }
+        // The last sequence only has literals, so if the stream ends here we are done
+        // NOTE: The lookback size in the token of the last sequence is meaningless, the reference decoder ignores it too
+        // Source: https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md#end-of-block-conditions
+        let Some(lookback_low) = data.next() else {
+            break;
         };
+        // But a lookback that was cut in half means the stream ended abruptly
+        let lookback_high = data.next().ok_or_else(|| output_buf.clone())?;
+        let lookback = u16::from_le_bytes([lookback_low, lookback_high]);
 
         if usize::try_from(lookback).unwrap() > output_buf.len() || lookback == 0 {
             // Invalid lz4 block
@@ -79,3 +75,90 @@ pub fn lz4_decompress_blocks(
 
     Ok(output_buf)
 }
+
+// Source: https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md
+const MIN_MATCH: usize = 4;
+const LAST_LITERALS: usize = 5; // The last 5 bytes are always literals
+const MF_LIMIT: usize = 12; // The last match must start at least 12 bytes before the end
+const MAX_LOOKBACK: usize = 0xFFFF;
+const HASH_LOG: u32 = 12;
+
+fn lz4_push_extended_size(output_buf: &mut Vec<u8>, mut size: usize) {
+    while size >= 0xFF {
+        output_buf.push(0xFF);
+        size -= 0xFF;
+    }
+    output_buf.push(size as u8);
+}
+
+fn lz4_push_sequence(
+    output_buf: &mut Vec<u8>,
+    literals: &[u8],
+    lookback_and_size: Option<(usize, usize)>,
+) {
+    let literal_size = literals.len();
+    let lookback_size = lookback_and_size
+        .map(|(_, size)| size - MIN_MATCH)
+        .unwrap_or(0);
+    let token = (literal_size.min(0xF) << 4) | lookback_size.min(0xF);
+    output_buf.push(token as u8);
+    if literal_size >= 0xF {
+        lz4_push_extended_size(output_buf, literal_size - 0xF);
+    }
+    output_buf.extend_from_slice(literals);
+
+    // The last sequence has no lookback
+    let Some((lookback, _)) = lookback_and_size else {
+        return;
+    };
+    output_buf.extend((lookback as u16).to_le_bytes());
+    if lookback_size >= 0xF {
+        lz4_push_extended_size(output_buf, lookback_size - 0xF);
+    }
+}
+
+// A simple greedy lz4 compressor, the output can be read by lz4_decompress_blocks (and by any other lz4 decompressor)
+// NOTE: This doesn't add the big endian size header zfs puts in front of the lz4 stream, see zio::try_compress_block for that
+pub fn lz4_compress_blocks(data: &[u8]) -> Vec<u8> {
+    let mut output_buf = Vec::with_capacity(data.len() + data.len() / 0xFF + 16);
+    let mut hash_table = vec![usize::MAX; 1 << HASH_LOG];
+    let mut literal_start = 0;
+    let mut pos = 0;
+
+    if data.len() > MF_LIMIT {
+        let match_start_limit = data.len() - MF_LIMIT;
+        let match_end_limit = data.len() - LAST_LITERALS;
+        while pos < match_start_limit {
+            let sequence = u32::from_le_bytes(data[pos..pos + MIN_MATCH].try_into().unwrap());
+            let hash = (sequence.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize;
+            let candidate = hash_table[hash];
+            hash_table[hash] = pos;
+
+            if candidate == usize::MAX
+                || pos - candidate > MAX_LOOKBACK
+                || data[candidate..candidate + MIN_MATCH] != data[pos..pos + MIN_MATCH]
+            {
+                pos += 1;
+                continue;
+            }
+
+            let mut lookback_size = MIN_MATCH;
+            while pos + lookback_size < match_end_limit
+                && data[candidate + lookback_size] == data[pos + lookback_size]
+            {
+                lookback_size += 1;
+            }
+
+            lz4_push_sequence(
+                &mut output_buf,
+                &data[literal_start..pos],
+                Some((pos - candidate, lookback_size)),
+            );
+            pos += lookback_size;
+            literal_start = pos;
+        }
+    }
+
+    lz4_push_sequence(&mut output_buf, &data[literal_start..], None);
+    output_buf
+}
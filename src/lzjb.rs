@@ -1,43 +1,134 @@
 // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/lzjb.c
 pub const MATCH_BITS: usize = 6;
 pub const MATCH_MIN: usize = 3;
+pub const MATCH_MAX: usize = (1 << MATCH_BITS) + (MATCH_MIN - 1);
 pub const OFFSET_MASK: usize = (1 << (16 - MATCH_BITS)) - 1;
+pub const LEMPEL_SIZE: usize = 1024;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LzjbError {
+    // The input ended before the output was complete, or in the middle of a match
+    TruncatedInput { output_len: usize },
+    // A match points before the start of the output (or at the current position), so the data can't be lzjb
+    BadLookback { lookback: usize, output_len: usize },
+}
+
+// NOTE: If output_length is None this decompresses until the input runs out
+// which is useful when scanning for blocks whose size isn't known
+// but lzjb has no end marker, so any trailing garbage will end up in the output
 pub fn lzjb_decompress(
     data: &mut impl Iterator<Item = u8>,
-    output_length: usize,
-) -> Result<Vec<u8>, ()> {
+    output_length: Option<usize>,
+) -> Result<Vec<u8>, LzjbError> {
     let mut copymap: u8 = 0;
     let mut copymask: usize = 1 << 7;
-    let mut output_buf = Vec::new();
+    let mut output_buf = Vec::with_capacity(output_length.unwrap_or(0));
+    let is_done = |output_buf: &Vec<u8>| output_length.is_some_and(|len| output_buf.len() >= len);
+
+    // Running out of input is only fine if we weren't told how much output to expect
+    let next_item_byte = |data: &mut dyn Iterator<Item = u8>, output_len: usize| match data.next() {
+        Some(byte) => Ok(Some(byte)),
+        None if output_length.is_none() => Ok(None),
+        None => Err(LzjbError::TruncatedInput { output_len }),
+    };
 
-    while output_buf.len() < output_length {
+    while !is_done(&output_buf) {
         copymask <<= 1;
         if copymask == (1 << 8) {
             copymask = 1;
-            copymap = data.next().ok_or(())?;
+            let Some(byte) = next_item_byte(data, output_buf.len())? else {
+                break;
+            };
+            copymap = byte;
         }
 
+        let Some(byte0) = next_item_byte(data, output_buf.len())? else {
+            break;
+        };
+
         if copymap & (copymask as u8) != 0 {
             // Repeat "lookback_size" bytes from "lookback" bytes ago in the buffer
-            let byte0 = data.next().ok_or(())?;
-            let byte1 = data.next().ok_or(())?;
+            // A match is always 2 bytes, so the input can't end after the first one
+            let byte1 = data.next().ok_or(LzjbError::TruncatedInput {
+                output_len: output_buf.len(),
+            })?;
             let lookback_size = usize::from(byte0 >> (8 - MATCH_BITS)) + MATCH_MIN;
             let lookback = ((((byte0 as u16) << 8) | (byte1 as u16)) as usize) & OFFSET_MASK;
             if lookback > output_buf.len() || lookback == 0 {
-                return Err(());
+                return Err(LzjbError::BadLookback {
+                    lookback,
+                    output_len: output_buf.len(),
+                });
             }
             let mut lookback_pos = output_buf.len() - lookback;
             for _ in 0..lookback_size {
-                if output_buf.len() >= output_length {
+                if is_done(&output_buf) {
                     break;
                 }
                 output_buf.push(output_buf[lookback_pos]);
                 lookback_pos += 1;
             }
         } else {
-            output_buf.push(data.next().ok_or(())?);
+            output_buf.push(byte0);
         }
     }
     Ok(output_buf)
 }
+
+// A straight port of lzjb_compress
+// NOTE: Unlike the zfs version this never gives up if the output gets too big
+// it's up to the caller to decide if the result is worth storing compressed
+pub fn lzjb_compress(data: &[u8]) -> Vec<u8> {
+    let mut copymap_pos: usize = 0;
+    let mut copymask: usize = 1 << 7;
+    let mut lempel = [0u16; LEMPEL_SIZE];
+    let mut output_buf = Vec::with_capacity(data.len() + data.len() / 8 + 1);
+
+    let mut pos = 0;
+    while pos < data.len() {
+        copymask <<= 1;
+        if copymask == (1 << 8) {
+            copymask = 1;
+            copymap_pos = output_buf.len();
+            output_buf.push(0);
+        }
+
+        // Not enough data left for a match, just copy the rest
+        if pos + MATCH_MAX > data.len() {
+            output_buf.push(data[pos]);
+            pos += 1;
+            continue;
+        }
+
+        let mut hash = (usize::from(data[pos]) << 16)
+            + (usize::from(data[pos + 1]) << 8)
+            + usize::from(data[pos + 2]);
+        hash += hash >> 9;
+        hash += hash >> 5;
+        let hash = hash & (LEMPEL_SIZE - 1);
+        let lookback = usize::from((pos as u16).wrapping_sub(lempel[hash])) & OFFSET_MASK;
+        lempel[hash] = pos as u16;
+
+        if lookback != 0
+            && lookback <= pos
+            && data[pos..pos + MATCH_MIN] == data[pos - lookback..pos - lookback + MATCH_MIN]
+        {
+            output_buf[copymap_pos] |= copymask as u8;
+            let mut lookback_size = MATCH_MIN;
+            while lookback_size < MATCH_MAX
+                && data[pos + lookback_size] == data[pos - lookback + lookback_size]
+            {
+                lookback_size += 1;
+            }
+            output_buf
+                .push((((lookback_size - MATCH_MIN) << (8 - MATCH_BITS)) | (lookback >> 8)) as u8);
+            output_buf.push((lookback & 0xFF) as u8);
+            pos += lookback_size;
+        } else {
+            output_buf.push(data[pos]);
+            pos += 1;
+        }
+    }
+
+    output_buf
+}
@@ -0,0 +1,292 @@
+// A read only server for the network block device protocol, so the kernel (nbd-client, qemu-nbd --connect) can use a zvol or a recovered file as a block device
+// Only the fixed newstyle handshake is supported, which is all current clients use
+//
+// Handshake:    "NBDMAGIC" | IHAVEOPT | handshake flags (u16), then the client sends its flags (u32)
+// Option:       IHAVEOPT | option (u32) | length (u32) | data, until an option starts the transmission
+// Option reply: OPTION_REPLY_MAGIC | option (u32) | reply type (u32) | length (u32) | data
+// Request:      REQUEST_MAGIC (u32) | command flags (u16) | type (u16) | handle (u64) | offset (u64) | length (u32) | data (only for writes)
+// Reply:        SIMPLE_REPLY_MAGIC (u32) | error (u32) | handle (u64) | data (only for reads without an error)
+//
+// NOTE: Everything is big endian
+// Source: https://github.com/NetworkBlockDevice/nbd/blob/master/doc/proto.md
+
+use std::io::{self, Read, Write};
+
+pub const DEFAULT_PORT: u16 = 10809;
+
+const NBD_MAGIC: &[u8; 8] = b"NBDMAGIC";
+const IHAVEOPT: u64 = 0x49484156454F5054;
+const OPTION_REPLY_MAGIC: u64 = 0x3e889045565a9;
+const REQUEST_MAGIC: u32 = 0x25609513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x67446698;
+
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+const CLIENT_FLAG_NO_ZEROES: u32 = 1 << 1;
+
+const TRANSMISSION_FLAG_HAS_FLAGS: u16 = 1 << 0;
+const TRANSMISSION_FLAG_READ_ONLY: u16 = 1 << 1;
+
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_ABORT: u32 = 2;
+const OPT_LIST: u32 = 3;
+const OPT_INFO: u32 = 6;
+const OPT_GO: u32 = 7;
+
+const REP_ACK: u32 = 1;
+const REP_SERVER: u32 = 2;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = (1 << 31) + 1;
+const REP_ERR_INVALID: u32 = (1 << 31) + 3;
+const REP_ERR_UNKNOWN: u32 = (1 << 31) + 6;
+
+const INFO_EXPORT: u16 = 0;
+const INFO_BLOCK_SIZE: u16 = 3;
+
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+
+// The errno values the protocol uses, they are the same as linux's
+const EPERM: u32 = 1;
+const EIO: u32 = 5;
+const EINVAL: u32 = 22;
+
+// Bigger reads are refused, so a broken client can't make us allocate as much as it wants
+const MAX_REQUEST_SIZE: u32 = 32 * 1024 * 1024;
+// Options are only names and a few info requests
+const MAX_OPTION_SIZE: u32 = 64 * 1024;
+
+pub struct Export {
+    pub name: String,
+    pub size: u64,
+    // The block size reads are best done in, ex. the volblocksize of a zvol
+    pub preferred_block_size: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct ServeReport {
+    pub reads: u64,
+    pub bytes_read: u64,
+    pub failed_reads: u64,
+    pub refused_requests: u64,
+}
+
+fn read_u16(stream: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    stream.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn read_u32(stream: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    stream.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(stream: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    stream.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+// Reads and throws away the data of a request or an option we don't care about
+fn skip(stream: &mut impl Read, length: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut stream.take(length), &mut io::sink())?;
+    if skipped != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+fn write_option_reply(
+    stream: &mut impl Write,
+    option: u32,
+    reply_type: u32,
+    data: &[u8],
+) -> io::Result<()> {
+    stream.write_all(&OPTION_REPLY_MAGIC.to_be_bytes())?;
+    stream.write_all(&option.to_be_bytes())?;
+    stream.write_all(&reply_type.to_be_bytes())?;
+    stream.write_all(&(data.len() as u32).to_be_bytes())?;
+    stream.write_all(data)
+}
+
+fn write_simple_reply(stream: &mut impl Write, error: u32, handle: u64) -> io::Result<()> {
+    stream.write_all(&SIMPLE_REPLY_MAGIC.to_be_bytes())?;
+    stream.write_all(&error.to_be_bytes())?;
+    stream.write_all(&handle.to_be_bytes())
+}
+
+fn get_transmission_flags() -> u16 {
+    TRANSMISSION_FLAG_HAS_FLAGS | TRANSMISSION_FLAG_READ_ONLY
+}
+
+// Returns: If the client asked for the export, false if the name is wrong
+// NOTE: An empty name is the default export, so it always matches
+fn is_export(export: &Export, name: &[u8]) -> bool {
+    name.is_empty() || name == export.name.as_bytes()
+}
+
+// Returns: true if the client went on to the transmission, false if it gave up
+fn negotiate(stream: &mut (impl Read + Write), export: &Export) -> io::Result<bool> {
+    stream.write_all(NBD_MAGIC)?;
+    stream.write_all(&IHAVEOPT.to_be_bytes())?;
+    stream.write_all(&(FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES).to_be_bytes())?;
+    stream.flush()?;
+    let client_flags = read_u32(stream)?;
+
+    loop {
+        if read_u64(stream)? != IHAVEOPT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The client sent an option without the option magic",
+            ));
+        }
+        let option = read_u32(stream)?;
+        let length = read_u32(stream)?;
+        if length > MAX_OPTION_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The client sent an option that is too big",
+            ));
+        }
+        let mut data = vec![0u8; length as usize];
+        stream.read_exact(&mut data)?;
+
+        match option {
+            // The old way of starting the transmission, there is no way to refuse it but to hang up
+            OPT_EXPORT_NAME => {
+                if !is_export(export, &data) {
+                    return Ok(false);
+                }
+                stream.write_all(&export.size.to_be_bytes())?;
+                stream.write_all(&get_transmission_flags().to_be_bytes())?;
+                if client_flags & CLIENT_FLAG_NO_ZEROES == 0 {
+                    stream.write_all(&[0u8; 124])?;
+                }
+                stream.flush()?;
+                return Ok(true);
+            }
+            OPT_ABORT => {
+                write_option_reply(stream, option, REP_ACK, &[])?;
+                stream.flush()?;
+                return Ok(false);
+            }
+            OPT_LIST => {
+                let mut reply = (export.name.len() as u32).to_be_bytes().to_vec();
+                reply.extend_from_slice(export.name.as_bytes());
+                write_option_reply(stream, option, REP_SERVER, &reply)?;
+                write_option_reply(stream, option, REP_ACK, &[])?;
+            }
+            // Data: name length (u32) | name | number of info requests (u16) | info requests (u16 each)
+            // The export and block size info are always sent, clients ignore the ones they didn't ask for
+            OPT_INFO | OPT_GO => {
+                let name_length = data
+                    .get(..4)
+                    .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as usize);
+                let Some(name) = name_length.and_then(|name_length| data.get(4..4 + name_length))
+                else {
+                    write_option_reply(stream, option, REP_ERR_INVALID, &[])?;
+                    stream.flush()?;
+                    continue;
+                };
+                if !is_export(export, name) {
+                    write_option_reply(stream, option, REP_ERR_UNKNOWN, &[])?;
+                    stream.flush()?;
+                    continue;
+                }
+
+                let mut export_info = INFO_EXPORT.to_be_bytes().to_vec();
+                export_info.extend_from_slice(&export.size.to_be_bytes());
+                export_info.extend_from_slice(&get_transmission_flags().to_be_bytes());
+                write_option_reply(stream, option, REP_INFO, &export_info)?;
+
+                let mut block_size_info = INFO_BLOCK_SIZE.to_be_bytes().to_vec();
+                block_size_info.extend_from_slice(&1u32.to_be_bytes());
+                block_size_info.extend_from_slice(&export.preferred_block_size.to_be_bytes());
+                block_size_info.extend_from_slice(&MAX_REQUEST_SIZE.to_be_bytes());
+                write_option_reply(stream, option, REP_INFO, &block_size_info)?;
+
+                write_option_reply(stream, option, REP_ACK, &[])?;
+                stream.flush()?;
+                if option == OPT_GO {
+                    return Ok(true);
+                }
+            }
+            _ => {
+                write_option_reply(stream, option, REP_ERR_UNSUP, &[])?;
+            }
+        }
+        stream.flush()?;
+    }
+}
+
+// Serves one client until it disconnects
+// `read` gets the offset and length of a read, which is always inside the export, and has to return exactly that many bytes, otherwise the client gets EIO
+// NOTE: Writes (and trims, etc.) are refused with EPERM, the client was told the export is read only so it shouldn't send them anyway
+pub fn serve(
+    stream: &mut (impl Read + Write),
+    export: &Export,
+    mut read: impl FnMut(u64, usize) -> Result<Vec<u8>, ()>,
+) -> io::Result<ServeReport> {
+    let mut report = ServeReport::default();
+    if !negotiate(stream, export)? {
+        return Ok(report);
+    }
+
+    loop {
+        if read_u32(stream)? != REQUEST_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "The client sent a request without the request magic",
+            ));
+        }
+        let _command_flags = read_u16(stream)?;
+        let command = read_u16(stream)?;
+        let handle = read_u64(stream)?;
+        let offset = read_u64(stream)?;
+        let length = read_u32(stream)?;
+
+        match command {
+            CMD_READ => {
+                if length > MAX_REQUEST_SIZE
+                    || offset
+                        .checked_add(u64::from(length))
+                        .is_none_or(|end| end > export.size)
+                {
+                    write_simple_reply(stream, EINVAL, handle)?;
+                } else {
+                    report.reads += 1;
+                    match read(offset, length as usize) {
+                        // The client expects exactly length bytes after the reply, anything else would break the stream
+                        Ok(data) if data.len() == length as usize => {
+                            report.bytes_read += u64::from(length);
+                            write_simple_reply(stream, 0, handle)?;
+                            stream.write_all(&data)?;
+                        }
+                        _ => {
+                            report.failed_reads += 1;
+                            write_simple_reply(stream, EIO, handle)?;
+                        }
+                    }
+                }
+            }
+            CMD_DISC => return Ok(report),
+            // Nothing is ever written, so there is nothing to flush
+            CMD_FLUSH => write_simple_reply(stream, 0, handle)?,
+            // Writes are the only requests that have data after them
+            CMD_WRITE => {
+                skip(stream, u64::from(length))?;
+                report.refused_requests += 1;
+                write_simple_reply(stream, EPERM, handle)?;
+            }
+            _ => {
+                report.refused_requests += 1;
+                write_simple_reply(stream, EPERM, handle)?;
+            }
+        }
+        stream.flush()?;
+    }
+}
@@ -135,11 +135,7 @@ fn read_string_raw(data: &mut impl Iterator<Item = u8>, size: usize) -> Option<S
 // Returns: The string and the amount of bytes read including the bytes of the size
 fn read_string_and_size(data: &mut impl Iterator<Item = u8>) -> Option<(String, usize)> {
     let result_size = u32::from_bytes_be(data)?;
-    let result_size_aligned = if result_size % 4 == 0 {
-        result_size
-    } else {
-        ((result_size / 4) + 1) * 4
-    };
+    let result_size_aligned = result_size.checked_next_multiple_of(4)?;
     let result = read_string_raw(data, result_size as usize);
     let padding_bytes = result_size_aligned - result_size;
     data.skip_n_bytes(padding_bytes as usize)?; // Consume the padding bytes
@@ -161,7 +157,6 @@ pub fn from_bytes_xdr(data: &mut impl Iterator<Item = u8>) -> Option<NVList> {
 
 // TODO:
 // 1. Support arrays as values and other esoteric value types
-// 2. Support writing nvlists
 
 fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Option<NVList> {
     if recursion_depth >= 128 {
@@ -187,11 +182,11 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
 
         let Some(value_type) = ValueType::from_value(u32::from_bytes_be(data)?) else {
             println!("Unknown nvlist value type with name: \"{}\", ignoring entry, which was {} bytes in size!", name, decode_size);
-            let value_size = decode_size-(
+            let value_size = decode_size.checked_sub(
                 string_bytes_read as u32
                 +4 /*size of decode_size*/
-                +4 /*size of value_type*/
-            );
+                +4, /*size of value_type*/
+            )?;
             data.skip_n_bytes(value_size as usize)?; // Consume value bytes
 
             continue;
@@ -204,15 +199,18 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
             continue;
         }
 
-        let nvpair_name_repeated = || {
-            panic!("NVPair Name was repeated, this is not supported!");
+        let nvpair_name_repeated = || -> Option<NVList> {
+            if cfg!(feature = "debug") {
+                println!("NVPair Name was repeated, this is not supported!");
+            }
+            None
         };
 
         match value_type {
             ValueType::Boolean => {
                 let value = u8::from_bytes(data)?;
                 if nv_list.insert(name, Value::Boolean(value != 0)).is_some() {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
                 }
             }
             ValueType::Byte => {
@@ -220,7 +218,7 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
                     .insert(name, Value::Byte(u8::from_bytes(data)?))
                     .is_some()
                 {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
                 }
             }
             ValueType::I16 => {
@@ -228,7 +226,7 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
                     .insert(name, Value::I16(i16::from_bytes_be(data)?))
                     .is_some()
                 {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
                 }
             }
             ValueType::U16 => {
@@ -236,7 +234,7 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
                     .insert(name, Value::U16(u16::from_bytes_be(data)?))
                     .is_some()
                 {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
                 }
             }
             ValueType::I32 => {
@@ -244,7 +242,7 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
                     .insert(name, Value::I32(i32::from_bytes_be(data)?))
                     .is_some()
                 {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
                 }
             }
             ValueType::U32 => {
@@ -252,7 +250,7 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
                     .insert(name, Value::U32(u32::from_bytes_be(data)?))
                     .is_some()
                 {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
                 }
             }
             ValueType::I64 => {
@@ -260,7 +258,7 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
                     .insert(name, Value::I64(i64::from_bytes_be(data)?))
                     .is_some()
                 {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
                 }
             }
             ValueType::U64 => {
@@ -268,28 +266,19 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
                     .insert(name, Value::U64(u64::from_bytes_be(data)?))
                     .is_some()
                 {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
                 }
             }
             ValueType::String => {
                 let (value, _) = read_string_and_size(data)?;
                 nv_list.insert(name, Value::String(value));
             }
-            ValueType::ByteArray => todo!(),
-            ValueType::I16Array => todo!(),
-            ValueType::U16Array => todo!(),
-            ValueType::I32Array => todo!(),
-            ValueType::U32Array => todo!(),
-            ValueType::I64Array => todo!(),
-            ValueType::U64Array => todo!(),
-            ValueType::StringArray => todo!(),
-            ValueType::HRTime => todo!(),
             ValueType::NVList => {
                 if nv_list
                     .insert(name, Value::NVList(from_bytes(data, recursion_depth + 1)?))
                     .is_some()
                 {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
                 }
             }
             ValueType::NVListArray => {
@@ -300,16 +289,254 @@ fn from_bytes(data: &mut impl Iterator<Item = u8>, recursion_depth: usize) -> Op
                 }
 
                 if nv_list.insert(name, Value::NVListArray(values)).is_some() {
-                    nvpair_name_repeated()
+                    return nvpair_name_repeated();
+                }
+            }
+            ValueType::ByteArray
+            | ValueType::I16Array
+            | ValueType::U16Array
+            | ValueType::I32Array
+            | ValueType::U32Array
+            | ValueType::I64Array
+            | ValueType::U64Array
+            | ValueType::StringArray
+            | ValueType::HRTime
+            | ValueType::BooleanValue
+            | ValueType::I8
+            | ValueType::U8
+            | ValueType::BooleanArray
+            | ValueType::I8Array
+            | ValueType::U8Array => {
+                // Not supported yet, skip the value so the rest of the nvlist can still be read
+                if cfg!(feature = "debug") {
+                    println!(
+                        "Unsupported nvlist value type with name: \"{}\", ignoring entry!",
+                        name
+                    );
+                }
+                let value_size = usize::try_from(encode_size).ok()?.checked_sub(
+                    4 /*size of encode_size*/
+                    + 4 /*size of decode_size*/
+                    + string_bytes_read
+                    + 4 /*size of value_type*/
+                    + 4, /*size of nvalues*/
+                )?;
+                data.skip_n_bytes(value_size)?;
+                if nv_list.insert(name, Value::Unknown).is_some() {
+                    return nvpair_name_repeated();
                 }
             }
-            ValueType::BooleanValue => todo!(),
-            ValueType::I8 => todo!(),
-            ValueType::U8 => todo!(),
-            ValueType::BooleanArray => todo!(),
-            ValueType::I8Array => todo!(),
-            ValueType::U8Array => todo!(),
         }
     }
     Some(nv_list)
 }
+
+// The inverse of from_bytes_xdr, ex. for the config in the labels of the pools made by test_image
+// The pairs are written sorted by name, so the same nvlist is always written the same way
+// Returns: None if a value can't be written so it's read back the same, xdr stores booleans, bytes and 16 bit integers as 32 bit integers but from_bytes_xdr doesn't read them like that (yet)
+// NOTE: Value::Unknown is written as a boolean without a value (ex. the features in features_for_read are stored like that), which is what it's read back as
+// NOTE: The decode size is the size of the pair in the native encoding, only zfs uses it (to allocate the pair), so the encode size is written there too
+pub fn to_bytes_xdr(nv_list: &NVList) -> Option<Vec<u8>> {
+    // xdr encoding, big endian, and the two reserved bytes
+    let mut res = vec![1u8, 1, 0, 0];
+    write_xdr(nv_list, &mut res, 0)?;
+    Some(res)
+}
+
+fn write_string_and_size(res: &mut Vec<u8>, string: &str) -> Option<()> {
+    res.extend(u32::try_from(string.len()).ok()?.to_be_bytes());
+    res.extend(string.as_bytes());
+    // Everything before the string is a multiple of 4 bytes, so this pads the string itself
+    res.resize(res.len().next_multiple_of(4), 0);
+    Some(())
+}
+
+fn write_xdr(nv_list: &NVList, res: &mut Vec<u8>, recursion_depth: usize) -> Option<()> {
+    // The same limit as from_bytes, anything deeper couldn't be read back
+    if recursion_depth >= 128 {
+        return None;
+    }
+
+    res.extend(0u32.to_be_bytes()); // version
+    res.extend(1u32.to_be_bytes()); // NV_UNIQUE_NAME, names are unique because they are the keys of the hashmap
+
+    let mut names = nv_list.keys().collect::<Vec<_>>();
+    names.sort_unstable();
+    for name in names {
+        let mut value = Vec::new();
+        let (value_type, nvalues) = match &nv_list[name] {
+            Value::Unknown => (ValueType::Boolean, 0),
+            Value::I32(v) => {
+                value.extend(v.to_be_bytes());
+                (ValueType::I32, 1)
+            }
+            Value::U32(v) => {
+                value.extend(v.to_be_bytes());
+                (ValueType::U32, 1)
+            }
+            Value::I64(v) => {
+                value.extend(v.to_be_bytes());
+                (ValueType::I64, 1)
+            }
+            Value::U64(v) => {
+                value.extend(v.to_be_bytes());
+                (ValueType::U64, 1)
+            }
+            Value::String(v) => {
+                write_string_and_size(&mut value, v)?;
+                (ValueType::String, 1)
+            }
+            Value::NVList(v) => {
+                write_xdr(v, &mut value, recursion_depth + 1)?;
+                (ValueType::NVList, 1)
+            }
+            Value::NVListArray(v) => {
+                for embedded in v {
+                    write_xdr(embedded, &mut value, recursion_depth + 1)?;
+                }
+                (ValueType::NVListArray, u32::try_from(v.len()).ok()?)
+            }
+            Value::Boolean(_) | Value::Byte(_) | Value::I16(_) | Value::U16(_) => return None,
+        };
+
+        let mut pair = Vec::new();
+        write_string_and_size(&mut pair, name)?;
+        pair.extend((value_type as u32).to_be_bytes());
+        pair.extend(nvalues.to_be_bytes());
+        pair.extend(value);
+
+        let size = u32::try_from(pair.len() + 4 /*size of encode_size*/ + 4 /*size of decode_size*/)
+            .ok()?;
+        res.extend(size.to_be_bytes()); // encode_size
+        res.extend(size.to_be_bytes()); // decode_size
+        res.extend(pair);
+    }
+
+    // The nv_list has 8 bytes of zeroes at the end
+    res.extend([0u8; 8]);
+    Some(())
+}
+
+// The native encoding is basically a copy of the in memory structures, zfs uses it for nvlists that are stored in objects (ex. the pool history)
+// Source: https://github.com/openzfs/zfs/blob/master/module/nvpair/nvpair.c (nvs_native_nvlist, nvs_native_nvpair, nvpair_native_embedded)
+// NOTE: Only little endian is supported, like everywhere else
+pub fn from_bytes_native(data: &[u8]) -> Option<NVList> {
+    // first byte is the encoding, second byte is the endianness, and the last two are reserved
+    let native_encoding = *data.first()?;
+    let native_endian = *data.get(1)?;
+    if native_encoding != 0 || native_endian != 1 {
+        if cfg!(feature = "debug") {
+            println!("Expected native encoding 0, and endian 1 (a.k.a little-endian)!");
+        }
+        return None;
+    }
+    from_bytes_native_nvlist(data.get(4..)?, 0).map(|(nv_list, _)| nv_list)
+}
+
+fn read_native_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().unwrap(),
+    ))
+}
+
+// Returns: The nvlist and the amount of bytes read, embedded nvlists are stored right after the pair they are the value of
+fn from_bytes_native_nvlist(data: &[u8], recursion_depth: usize) -> Option<(NVList, usize)> {
+    if recursion_depth >= 128 {
+        println!("NVList recursion limit of 128 nvlists nested in the main nvlist reached, i will not be parsing any more, deal with it!");
+        return None;
+    }
+
+    // The nvlist_t struct: version (4 bytes), flags (4 bytes), a pointer (8 bytes, zeroed), flag (4 bytes), padding (4 bytes)
+    let mut offset = 24;
+    let mut nv_list: NVList = NVList::new();
+    loop {
+        // The nv_list ends with 4 bytes of zeroes
+        let pair_size = read_native_u32(data, offset)? as usize;
+        if pair_size == 0 {
+            offset += 4;
+            break;
+        }
+
+        // The nvpair_t struct: size (4 bytes), name size (2 bytes), reserved (2 bytes), number of values (4 bytes), type (4 bytes)
+        // followed by the name and the value, which is 8 byte aligned
+        let pair = data.get(offset..offset + pair_size)?;
+        offset += pair_size;
+        let name_size = usize::from(u16::from_le_bytes(pair.get(4..6)?.try_into().unwrap()));
+        let nvalues = read_native_u32(pair, 8)?;
+        let value_type = ValueType::from_value(read_native_u32(pair, 12)?);
+        let name_bytes = pair.get(16..16 + name_size)?;
+        let name = String::from_utf8(
+            name_bytes
+                .iter()
+                .copied()
+                .take_while(|byte| *byte != 0)
+                .collect(),
+        )
+        .ok()?;
+        let value_offset = (16 + name_size).div_ceil(8) * 8;
+        let value_data = pair.get(value_offset..).unwrap_or_default();
+
+        let value = match value_type {
+            // Booleans without a value are true just by being there
+            Some(ValueType::Boolean) => Value::Boolean(true),
+            Some(ValueType::BooleanValue) => {
+                Value::Boolean(u32::from_le_bytes(value_data.get(0..4)?.try_into().unwrap()) != 0)
+            }
+            Some(ValueType::Byte) | Some(ValueType::U8) => Value::Byte(*value_data.first()?),
+            Some(ValueType::I16) => Value::I16(i16::from_le_bytes(
+                value_data.get(0..2)?.try_into().unwrap(),
+            )),
+            Some(ValueType::U16) => Value::U16(u16::from_le_bytes(
+                value_data.get(0..2)?.try_into().unwrap(),
+            )),
+            Some(ValueType::I32) => Value::I32(i32::from_le_bytes(
+                value_data.get(0..4)?.try_into().unwrap(),
+            )),
+            Some(ValueType::U32) => Value::U32(u32::from_le_bytes(
+                value_data.get(0..4)?.try_into().unwrap(),
+            )),
+            Some(ValueType::I64) | Some(ValueType::HRTime) => Value::I64(i64::from_le_bytes(
+                value_data.get(0..8)?.try_into().unwrap(),
+            )),
+            Some(ValueType::U64) => Value::U64(u64::from_le_bytes(
+                value_data.get(0..8)?.try_into().unwrap(),
+            )),
+            Some(ValueType::String) => Value::String(
+                String::from_utf8(
+                    value_data
+                        .iter()
+                        .copied()
+                        .take_while(|byte| *byte != 0)
+                        .collect(),
+                )
+                .ok()?,
+            ),
+            Some(ValueType::NVList) => {
+                let (embedded, size) =
+                    from_bytes_native_nvlist(data.get(offset..)?, recursion_depth + 1)?;
+                offset += size;
+                Value::NVList(embedded)
+            }
+            Some(ValueType::NVListArray) => {
+                let mut values = Vec::<NVList>::new();
+                for _ in 0..nvalues {
+                    let (embedded, size) =
+                        from_bytes_native_nvlist(data.get(offset..)?, recursion_depth + 1)?;
+                    offset += size;
+                    values.push(embedded);
+                }
+                Value::NVListArray(values)
+            }
+            // Arrays (other than of nvlists) are not supported yet, they don't have anything stored after the pair so they can just be skipped
+            _ => Value::Unknown,
+        };
+
+        if nv_list.insert(name, value).is_some() {
+            if cfg!(feature = "debug") {
+                println!("NVPair Name was repeated, ignoring the nvlist!");
+            }
+            return None;
+        }
+    }
+    Some((nv_list, offset))
+}
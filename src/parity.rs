@@ -0,0 +1,126 @@
+// Raidz parity, P is the xor of the data columns, Q and R are sums over GF(2^8) with the generators 2 and 4
+// this is used to check which column of a block is the one that is wrong, when a block is read but its checksum fails
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/vdev_raidz.c (vdev_raidz_generate_parity_p, vdev_raidz_generate_parity_pq, vdev_raidz_generate_parity_pqr)
+
+// A column of a block on a raidz, the part of the block that is on one disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RaidzColumn {
+    // The index of the disk the column is on
+    pub device: usize,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StripeCheck {
+    // For every parity column, if it matches the parity computed from the data columns
+    pub parity_matches: Vec<bool>,
+    // The disks whose column could be the wrong one, because assuming it is makes the rest of the columns agree
+    // NOTE: With one parity column any column could be wrong, so this only says something with two or more
+    pub suspects: Vec<usize>,
+}
+
+impl StripeCheck {
+    pub fn is_consistent(&self) -> bool {
+        self.parity_matches.iter().all(|matches| *matches)
+    }
+}
+
+// Multiplication by 2 in GF(2^8) with the polynomial x^8 + x^4 + x^3 + x^2 + 1, which is what zfs uses
+fn gf_mul2(x: u8) -> u8 {
+    (x << 1) ^ if x & 0x80 != 0 { 0x1d } else { 0 }
+}
+
+// Returns: The parity columns for the data columns, each parity_size bytes long
+// NOTE: The data columns can be shorter than the parity columns (if the data doesn't fill the last row), they are treated as if they were padded with zeros
+pub fn generate_parity(data_columns: &[&[u8]], nparity: usize, parity_size: usize) -> Vec<Vec<u8>> {
+    let mut parity = vec![vec![0u8; parity_size]; nparity];
+    for column in data_columns {
+        for offset in 0..parity_size {
+            let byte = column.get(offset).copied().unwrap_or(0);
+            // Horner's method, so the first column gets multiplied by the highest power of the generator
+            parity[0][offset] ^= byte;
+            if nparity >= 2 {
+                parity[1][offset] = gf_mul2(parity[1][offset]) ^ byte;
+            }
+            if nparity >= 3 {
+                parity[2][offset] = gf_mul2(gf_mul2(parity[2][offset])) ^ byte;
+            }
+        }
+    }
+    parity
+}
+
+// Returns: For every parity column except `skip`, if it matches the one generated from the data columns
+fn check_parity_columns(
+    parity_columns: &[&[u8]],
+    data_columns: &[&[u8]],
+    skip: Option<usize>,
+) -> Vec<bool> {
+    let parity_size = parity_columns.first().map_or(0, |column| column.len());
+    generate_parity(data_columns, parity_columns.len(), parity_size)
+        .iter()
+        .zip(parity_columns)
+        .enumerate()
+        .filter(|(index, _)| Some(*index) != skip)
+        .map(|(_, (generated, stored))| generated == stored)
+        .collect()
+}
+
+// Recomputes the parity of a block and checks it against the parity columns on the disks
+// columns have to be in the order zfs lays them out, the nparity parity columns first
+pub fn check_columns(columns: &[RaidzColumn], nparity: usize) -> StripeCheck {
+    let parity_columns = columns[..nparity]
+        .iter()
+        .map(|column| column.data.as_slice())
+        .collect::<Vec<_>>();
+    let data_columns = columns[nparity..]
+        .iter()
+        .map(|column| column.data.as_slice())
+        .collect::<Vec<_>>();
+
+    let parity_matches = check_parity_columns(&parity_columns, &data_columns, None);
+    if parity_matches.iter().all(|matches| *matches) {
+        return StripeCheck {
+            parity_matches,
+            suspects: Vec::new(),
+        };
+    }
+
+    let mut suspects = Vec::new();
+    for (index, column) in columns.iter().enumerate() {
+        let is_consistent_without_column = if index < nparity {
+            // If a parity column is wrong, the other parity columns still match
+            check_parity_columns(&parity_columns, &data_columns, Some(index))
+                .iter()
+                .all(|matches| *matches)
+        } else {
+            // If a data column is wrong, rebuilding it from P makes the other parity columns match
+            let mut rebuilt = parity_columns[0].to_vec();
+            for (other_index, other_column) in data_columns.iter().enumerate() {
+                if other_index + nparity != index {
+                    rebuilt
+                        .iter_mut()
+                        .zip(other_column.iter())
+                        .for_each(|(byte, other_byte)| *byte ^= other_byte);
+                }
+            }
+            // A column that is shorter than the parity columns is padded with zeros, so the rebuilt padding has to be zero too
+            let (rebuilt, padding) = rebuilt.split_at(column.data.len());
+            let mut rebuilt_data_columns = data_columns.clone();
+            rebuilt_data_columns[index - nparity] = rebuilt;
+            padding.iter().all(|byte| *byte == 0)
+                && check_parity_columns(&parity_columns, &rebuilt_data_columns, Some(0))
+                    .iter()
+                    .all(|matches| *matches)
+        };
+
+        if is_consistent_without_column {
+            suspects.push(column.device);
+        }
+    }
+
+    StripeCheck {
+        parity_matches,
+        suspects,
+    }
+}
@@ -0,0 +1,140 @@
+// Images of whole disks have a partition table in front of the vdev, so the labels aren't at the start of the image
+// this finds the partitions zfs uses in them, in both GPT and MBR partition tables
+// Source: https://uefi.org/specs/UEFI/2.10/05_GUID_Partition_Table_Format.html
+// Source: https://en.wikipedia.org/wiki/Master_boot_record#PTE
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionTableKind {
+    Gpt,
+    Mbr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    pub table: PartitionTableKind,
+    // The index of the partition in the table, starting at 0
+    pub index: usize,
+    // In bytes
+    pub start: u64,
+    pub size: u64,
+}
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+// The gpt header is at lba 1, but the size of an lba depends on the disk
+const GPT_SECTOR_SIZES: [u64; 2] = [512, 4096];
+// Anything bigger than this is corrupt, 128 entries of 128 bytes is what everything uses
+const GPT_MAX_ENTRIES_SIZE: usize = 1024 * 1024;
+
+// GUIDs are stored mixed endian, so these are the bytes as they are on disk
+// "Solaris /usr & Apple ZFS" 6A898CC3-1DD2-11B2-99A6-080020736631, what zfs uses when it is given a whole disk
+const GPT_TYPE_SOLARIS_ZFS: [u8; 16] = [
+    0xC3, 0x8C, 0x89, 0x6A, 0xD2, 0x1D, 0xB2, 0x11, 0x99, 0xA6, 0x08, 0x00, 0x20, 0x73, 0x66, 0x31,
+];
+// "freebsd-zfs" 516E7CBA-6ECF-11D6-8FF8-00022D09712B
+const GPT_TYPE_FREEBSD_ZFS: [u8; 16] = [
+    0xBA, 0x7C, 0x6E, 0x51, 0xCF, 0x6E, 0xD6, 0x11, 0x8F, 0xF8, 0x00, 0x02, 0x2D, 0x09, 0x71, 0x2B,
+];
+
+const MBR_SECTOR_SIZE: u64 = 512;
+const MBR_TYPE_SOLARIS: u8 = 0xBF;
+// The protective mbr in front of a gpt has one partition of this type covering the whole disk
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+// Returns: The partitions that have a zfs partition type, in the order they are in the table
+// if the disk has a gpt only it is used, even if the mbr in front of it says something else
+pub fn find_zfs_partitions(
+    read: &mut impl FnMut(u64, usize) -> Result<Vec<u8>, ()>,
+) -> Vec<Partition> {
+    for sector_size in GPT_SECTOR_SIZES {
+        if let Some(partitions) = find_gpt_zfs_partitions(read, sector_size) {
+            return partitions;
+        }
+    }
+
+    find_mbr_zfs_partitions(read).unwrap_or_default()
+}
+
+// Returns: None if there is no gpt header at lba 1 for this sector size
+fn find_gpt_zfs_partitions(
+    read: &mut impl FnMut(u64, usize) -> Result<Vec<u8>, ()>,
+    sector_size: u64,
+) -> Option<Vec<Partition>> {
+    let header = read(sector_size, 92).ok()?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return None;
+    }
+
+    let get_u32 = |off: usize| u32::from_le_bytes(header[off..off + 4].try_into().unwrap());
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+    let nentries = get_u32(80) as usize;
+    let entry_size = get_u32(84) as usize;
+    // Entries are at least 128 bytes, and always a multiple of that
+    if entry_size < 128 || !entry_size.is_multiple_of(128) {
+        use crate::ansi_color::*;
+        println!("{YELLOW}Warning{WHITE}: The gpt has an invalid partition entry size of {entry_size}, ignoring it!");
+        return None;
+    }
+
+    let entries_size = nentries.checked_mul(entry_size)?;
+    if entries_size > GPT_MAX_ENTRIES_SIZE {
+        use crate::ansi_color::*;
+        println!("{YELLOW}Warning{WHITE}: The gpt says it has {nentries} partition entries, that is way too many, ignoring it!");
+        return None;
+    }
+    let entries = read(entries_lba.checked_mul(sector_size)?, entries_size).ok()?;
+
+    let mut partitions = Vec::new();
+    for (index, entry) in entries.chunks_exact(entry_size).enumerate() {
+        let partition_type: [u8; 16] = entry[0..16].try_into().unwrap();
+        if partition_type != GPT_TYPE_SOLARIS_ZFS && partition_type != GPT_TYPE_FREEBSD_ZFS {
+            continue;
+        }
+        let first_lba = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+        // The last lba is inclusive
+        let last_lba = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+        if last_lba < first_lba {
+            continue;
+        }
+        partitions.push(Partition {
+            table: PartitionTableKind::Gpt,
+            index,
+            start: first_lba.checked_mul(sector_size)?,
+            size: (last_lba - first_lba + 1).checked_mul(sector_size)?,
+        });
+    }
+    Some(partitions)
+}
+
+// Returns: None if there is no mbr, or if it is just the protective mbr of a gpt
+// NOTE: Only the 4 primary partitions are looked at, zfs doesn't go in extended partitions
+fn find_mbr_zfs_partitions(
+    read: &mut impl FnMut(u64, usize) -> Result<Vec<u8>, ()>,
+) -> Option<Vec<Partition>> {
+    let mbr = read(0, MBR_SECTOR_SIZE as usize).ok()?;
+    if mbr[510..512] != [0x55, 0xAA] {
+        return None;
+    }
+
+    let mut partitions = Vec::new();
+    for (index, entry) in mbr[446..510].chunks_exact(16).enumerate() {
+        let partition_type = entry[4];
+        if partition_type == MBR_TYPE_GPT_PROTECTIVE {
+            return None;
+        }
+        if partition_type != MBR_TYPE_SOLARIS {
+            continue;
+        }
+        let first_lba = u32::from_le_bytes(entry[8..12].try_into().unwrap());
+        let nsectors = u32::from_le_bytes(entry[12..16].try_into().unwrap());
+        if nsectors == 0 {
+            continue;
+        }
+        partitions.push(Partition {
+            table: PartitionTableKind::Mbr,
+            index,
+            start: u64::from(first_lba) * MBR_SECTOR_SIZE,
+            size: u64::from(nsectors) * MBR_SECTOR_SIZE,
+        });
+    }
+    Some(partitions)
+}
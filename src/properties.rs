@@ -0,0 +1,196 @@
+// Dataset properties (zfs get), they are stored in a zap per dsl directory
+// Properties that aren't set on a directory are inherited from its parent, and if no directory sets them they have their default value
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dsl_prop.c (dsl_prop_get_dd)
+// NOTE: The encryption settings (except keylocation) are stored in the dsl crypto key object, not in the props zap
+// NOTE: Snapshots can have their own properties (ds_props_obj), those aren't read as that field isn't parsed, so snapshots get the properties of their directory
+
+use std::collections::HashMap;
+
+use crate::{
+    dmu::{DNode, ObjSet, ObjType},
+    zap,
+    zio::{ChecksumMethod, CompressionMethod, Vdevs},
+};
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h (ZPROP_RECVD_SUFFIX, ZPROP_INHERIT_SUFFIX)
+const RECEIVED_SUFFIX: &str = "$recvd";
+const INHERIT_SUFFIX: &str = "$inherit";
+
+// These only apply to the dataset they are set on
+// Source: https://github.com/openzfs/zfs/blob/master/module/zcommon/zfs_prop.c (the ones registered with PROP_ONETIME or without inheritance)
+const NON_INHERITABLE: [&str; 6] = [
+    "quota",
+    "reservation",
+    "refquota",
+    "refreservation",
+    "filesystem_limit",
+    "snapshot_limit",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropertyValue {
+    U64(u64),
+    String(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertySource {
+    Local,
+    // Set by zfs receive
+    Received,
+    // Inherited from the dsl directory with this object number
+    Inherited(u64),
+}
+
+fn parse_value(value: &zap::Value) -> Option<PropertyValue> {
+    Some(match value {
+        zap::Value::U64(value) => PropertyValue::U64(*value),
+        // Strings are stored as arrays of 1 byte integers, with the null terminator
+        zap::Value::Byte(_) => PropertyValue::String(String::new()),
+        zap::Value::ByteArray(bytes) => PropertyValue::String(
+            String::from_utf8_lossy(bytes)
+                .trim_end_matches('\0')
+                .to_string(),
+        ),
+        _ => return None,
+    })
+}
+
+// Returns: The raw contents of the props zap of the dsl directory, and the object number of its parent directory (0 if there is none)
+fn read_directory_props(
+    mos: &mut ObjSet,
+    directory_id: u64,
+    vdevs: &mut Vdevs,
+) -> Option<(HashMap<String, zap::Value>, u64)> {
+    let DNode::DSLDirectory(directory) = mos.get_dnode_at(directory_id as usize, vdevs)? else {
+        return None;
+    };
+    let directory = directory.parse_bonus_data()?;
+
+    // Directories that never had a property set might not have a props zap
+    let props = if directory.get_props_object_number() == 0 {
+        HashMap::new()
+    } else {
+        let (mut props_zap, obj_type) =
+            mos.get_zap_dnode_at(directory.get_props_object_number() as usize, vdevs)?;
+        if obj_type != ObjType::DSLProperties {
+            return None;
+        }
+        props_zap.dump_zap_contents(vdevs)?
+    };
+    Some((props, directory.get_parent_object_number()))
+}
+
+// Returns: The properties that are set on the dsl directory itself (locally or by zfs receive, local ones win), without inherited ones
+pub fn read_directory_properties(
+    mos: &mut ObjSet,
+    directory_id: u64,
+    vdevs: &mut Vdevs,
+) -> Option<HashMap<String, (PropertyValue, PropertySource)>> {
+    let (props, _) = read_directory_props(mos, directory_id, vdevs)?;
+    let mut properties = HashMap::new();
+    for (name, value) in props.iter() {
+        let Some(value) = parse_value(value) else {
+            continue;
+        };
+        if let Some(name) = name.strip_suffix(RECEIVED_SUFFIX) {
+            if !props.contains_key(name) && !props.contains_key(&format!("{name}{INHERIT_SUFFIX}"))
+            {
+                properties.insert(name.to_string(), (value, PropertySource::Received));
+            }
+        } else if !name.contains('$') {
+            properties.insert(name.clone(), (value, PropertySource::Local));
+        }
+    }
+    Some(properties)
+}
+
+// Returns: The value of the property for the dsl dataset with object number `dataset_id`, and where it was set
+//          None if the property isn't set anywhere, so it has its default value
+pub fn get_property(
+    mos: &mut ObjSet,
+    dataset_id: u64,
+    name: &str,
+    vdevs: &mut Vdevs,
+) -> Option<(PropertyValue, PropertySource)> {
+    let DNode::DSLDataset(dataset) = mos.get_dnode_at(dataset_id as usize, vdevs)? else {
+        return None;
+    };
+    let dataset = dataset.parse_bonus_data()?;
+    let target_directory_id = dataset.get_parent_directory_object_number();
+
+    let mut directory_id = target_directory_id;
+    while directory_id != 0 {
+        // Snapshots inherit everything from the directory of the dataset they are a snapshot of
+        let inherited = dataset.is_snapshot() || directory_id != target_directory_id;
+        if inherited && NON_INHERITABLE.contains(&name) {
+            return None;
+        }
+
+        let (props, parent_directory_id) = read_directory_props(mos, directory_id, vdevs)?;
+        let source = |own_source| {
+            if inherited {
+                PropertySource::Inherited(directory_id)
+            } else {
+                own_source
+            }
+        };
+        if let Some(value) = props.get(name) {
+            return Some((parse_value(value)?, source(PropertySource::Local)));
+        }
+
+        // An explicit zfs inherit hides the received value
+        if !props.contains_key(&format!("{name}{INHERIT_SUFFIX}")) {
+            if let Some(value) = props.get(&format!("{name}{RECEIVED_SUFFIX}")) {
+                return Some((parse_value(value)?, source(PropertySource::Received)));
+            }
+        }
+        directory_id = parent_directory_id;
+    }
+    None
+}
+
+// Returns: The object number of the head dataset of the root dataset (the one with the name of the pool)
+pub fn get_root_dataset_id(mos: &mut ObjSet, vdevs: &mut Vdevs) -> Option<u64> {
+    let DNode::ObjectDirectory(mut object_directory) = mos.get_dnode_at(1, vdevs)? else {
+        return None;
+    };
+    let objdir_zap_data = object_directory.dump_zap_contents(vdevs)?;
+    let Some(zap::Value::U64(root_directory_id)) = objdir_zap_data.get("root_dataset") else {
+        return None;
+    };
+    let DNode::DSLDirectory(root_directory) =
+        mos.get_dnode_at(*root_directory_id as usize, vdevs)?
+    else {
+        return None;
+    };
+    Some(
+        root_directory
+            .parse_bonus_data()?
+            .get_head_dataset_object_number(),
+    )
+}
+
+// Returns: The value like zfs get shows it, the properties that are stored as enum values are translated to their names
+pub fn format_property(name: &str, value: &PropertyValue) -> String {
+    match (name, value) {
+        ("compression", PropertyValue::U64(value)) => {
+            // The zstd level is stored above the compression method
+            // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zio_compress.h (ZIO_COMPLEVEL_ZSTD, SPA_COMPRESSBITS)
+            let method = CompressionMethod::from_value((value & 0x7F) as usize)
+                .map(|method| method.get_name())
+                .unwrap_or("unknown");
+            // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zstd/zstd.h (ZIO_ZSTD_LEVEL_FAST)
+            match value >> 7 {
+                0 => String::from(method),
+                level if level > 1000 => format!("{method}-fast-{}", level - 1000),
+                level => format!("{method}-{level}"),
+            }
+        }
+        ("checksum", PropertyValue::U64(value)) => ChecksumMethod::from_value(*value as usize)
+            .map(|method| String::from(method.get_name()))
+            .unwrap_or(format!("unknown ({value})")),
+        (_, PropertyValue::U64(value)) => value.to_string(),
+        (_, PropertyValue::String(value)) => value.clone(),
+    }
+}
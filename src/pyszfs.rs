@@ -0,0 +1,206 @@
+// Python bindings, most recovery scripting is done in python, this lets those scripts read pools (including raidz) with szfs
+// build them with maturin (the settings are in pyproject.toml): maturin develop --release
+// NOTE: This is only compiled with the "python" feature
+// Example:
+//     import pyszfs
+//     pool = pyszfs.Pool(["/dev/sda", "/dev/sdb", "/dev/sdc"])
+//     for name, object_id, is_snapshot in pool.datasets(): ...
+//     for dirpath, dirnames, filenames in pool.walk("tank/home"): ...
+//     data = pool.open("tank/home", "user/notes.txt").read()
+
+use std::{collections::HashSet, path::PathBuf};
+
+use pyo3::{
+    exceptions::{PyIOError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+
+use crate::{
+    reader::{FileReader, PoolReader},
+    zpl::DirectoryEntryKind,
+};
+
+// (dirpath, dirnames, filenames), like os.walk gives
+type WalkEntry = (String, Vec<String>, Vec<String>);
+
+fn join_path(directory: &str, name: &str) -> String {
+    if directory.ends_with('/') {
+        format!("{directory}{name}")
+    } else {
+        format!("{directory}/{name}")
+    }
+}
+
+// The disks are only ever opened read only
+#[pyclass(unsendable, name = "Pool", module = "pyszfs")]
+struct Pool {
+    reader: PoolReader,
+}
+
+#[pymethods]
+impl Pool {
+    // The disks can be given in any order if their labels can be read
+    #[new]
+    fn new(vdevs: Vec<PathBuf>) -> PyResult<Pool> {
+        PoolReader::open(&vdevs)
+            .map(|reader| Pool { reader })
+            .map_err(PyIOError::new_err)
+    }
+
+    #[getter]
+    fn name(&self) -> String {
+        String::from(self.reader.get_name())
+    }
+
+    // Returns: (name, object id, is snapshot) for every dataset, including snapshots
+    fn datasets(&mut self) -> Vec<(String, u64, bool)> {
+        self.reader
+            .list_datasets()
+            .into_iter()
+            .map(|dataset| (dataset.name, dataset.object_id, dataset.is_snapshot))
+            .collect()
+    }
+
+    // Returns: (name, object id, kind) for every entry of the directory, kind is ex. "file", "directory" or "symlink"
+    #[pyo3(signature = (dataset, path = "/"))]
+    fn listdir(&mut self, dataset: &str, path: &str) -> PyResult<Vec<(String, u64, &'static str)>> {
+        Ok(self
+            .reader
+            .list_directory(dataset, path)
+            .map_err(PyIOError::new_err)?
+            .into_iter()
+            .map(|entry| (entry.name, entry.object_id, entry.kind.get_name()))
+            .collect())
+    }
+
+    // Like os.walk, (dirpath, dirnames, filenames) for the directory and everything under it, top down
+    // Directories that can't be read are skipped, like os.walk does by default, except for the first one
+    #[pyo3(signature = (dataset, path = "/"))]
+    fn walk(&mut self, dataset: &str, path: &str) -> PyResult<Vec<WalkEntry>> {
+        let mut result = Vec::new();
+        let mut directories = vec![String::from(path)];
+        // A corrupted directory can have an entry that points back at one of the directories above it, which would never end
+        let mut visited_object_ids = HashSet::new();
+        while let Some(directory) = directories.pop() {
+            let entries = match self.reader.list_directory(dataset, &directory) {
+                Ok(entries) => entries,
+                Err(err) if result.is_empty() => return Err(PyIOError::new_err(err)),
+                Err(_) => continue,
+            };
+            let (subdirectories, files): (Vec<_>, Vec<_>) = entries
+                .into_iter()
+                .partition(|entry| entry.kind == DirectoryEntryKind::Directory);
+            // Pushed in reverse so they are popped in order, the ones that were already walked are still listed, but not walked again
+            for entry in subdirectories.iter().rev() {
+                if visited_object_ids.insert(entry.object_id) {
+                    directories.push(join_path(&directory, &entry.name));
+                }
+            }
+            let dirnames = subdirectories
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect::<Vec<_>>();
+            result.push((
+                directory,
+                dirnames,
+                files.into_iter().map(|entry| entry.name).collect(),
+            ));
+        }
+        Ok(result)
+    }
+
+    // Opens the plain file at path (relative to the root of the dataset) for reading
+    fn open(slf: Bound<'_, Self>, dataset: &str, path: &str) -> PyResult<File> {
+        let file = slf
+            .borrow_mut()
+            .reader
+            .open_file(dataset, path)
+            .map_err(PyIOError::new_err)?;
+        Ok(File {
+            pool: slf.unbind(),
+            file,
+            position: 0,
+        })
+    }
+}
+
+// A file in a dataset, it keeps its pool open
+#[pyclass(unsendable, name = "File", module = "pyszfs")]
+struct File {
+    pool: Py<Pool>,
+    file: FileReader,
+    position: u64,
+}
+
+impl File {
+    fn read_at(&mut self, py: Python<'_>, offset: u64, count: usize) -> PyResult<Vec<u8>> {
+        self.pool
+            .borrow_mut(py)
+            .reader
+            .read_file(&mut self.file, offset, count)
+            .map_err(|_| {
+                PyIOError::new_err(format!(
+                    "{count} bytes at offset {offset} of the file can't be read"
+                ))
+            })
+    }
+}
+
+#[pymethods]
+impl File {
+    #[getter]
+    fn size(&self) -> u64 {
+        self.file.get_size()
+    }
+
+    // Like os.pread, doesn't change the position
+    fn pread<'py>(
+        &mut self,
+        py: Python<'py>,
+        count: usize,
+        offset: u64,
+    ) -> PyResult<Bound<'py, PyBytes>> {
+        let data = self.read_at(py, offset, count)?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    // Reads size bytes from the position, or everything until the end of the file if size is negative
+    #[pyo3(signature = (size = -1))]
+    fn read<'py>(&mut self, py: Python<'py>, size: i64) -> PyResult<Bound<'py, PyBytes>> {
+        let count = if size < 0 {
+            self.file.get_size().saturating_sub(self.position)
+        } else {
+            size as u64
+        };
+        let data = self.read_at(py, self.position, count as usize)?;
+        self.position += data.len() as u64;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    // whence is like for io.IOBase.seek, 0 is from the start, 1 from the position and 2 from the end
+    #[pyo3(signature = (offset, whence = 0))]
+    fn seek(&mut self, offset: i64, whence: i32) -> PyResult<u64> {
+        let base = match whence {
+            0 => 0,
+            1 => self.position,
+            2 => self.file.get_size(),
+            _ => return Err(PyValueError::new_err(format!("Invalid whence {whence}"))),
+        };
+        self.position = base
+            .checked_add_signed(offset)
+            .ok_or_else(|| PyValueError::new_err("Seeking before the start of the file"))?;
+        Ok(self.position)
+    }
+
+    fn tell(&self) -> u64 {
+        self.position
+    }
+}
+
+#[pymodule]
+fn pyszfs(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Pool>()?;
+    module.add_class::<File>()?;
+    Ok(())
+}
@@ -0,0 +1,366 @@
+// Reading files out of a pool for code that uses szfs as a library instead of through the binaries (ex. the c api)
+// everything here owns what it needs, so it can be kept around between calls, the raidz is made again for every call because it borrows the disks
+
+use std::{collections::HashSet, path::PathBuf};
+
+use crate::{
+    byte_iter::FromSliceLE,
+    cli,
+    dmu::{DNode, DNodePlainFileContents, IndirectTreeCursor, ObjSet, ObjType},
+    nvlist,
+    recovery::select::FileAttributes,
+    rewind, zap,
+    zio::Vdevs,
+    zpl,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatasetInfo {
+    // Object number of the dsl dataset in the MOS
+    pub object_id: u64,
+    // The full name, like zfs list shows it, ex. tank/home@monday
+    pub name: String,
+    pub is_snapshot: bool,
+}
+
+pub struct PoolReader {
+    pool: cli::Pool,
+    mos: ObjSet,
+    name: String,
+}
+
+// A plain file in a dataset, opened with PoolReader::open_file
+pub struct FileReader {
+    file: DNodePlainFileContents,
+    size: u64,
+}
+
+impl FileReader {
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+}
+
+// Makes the raidz out of the disks of the pool, and runs f with it
+fn with_vdevs<R>(pool: &mut cli::Pool, f: impl FnOnce(&mut Vdevs) -> R) -> R {
+    let mut vdev_raidz = pool.get_raidz();
+    let mut vdevs = Vdevs::new();
+    vdevs.insert(0usize, &mut vdev_raidz);
+    f(&mut vdevs)
+}
+
+// Returns: Every dataset that can be reached from the root dsl directory of the pool called `pool_name`, including snapshots
+pub fn collect_all_datasets(
+    mos: &mut ObjSet,
+    pool_name: &str,
+    vdevs: &mut Vdevs,
+) -> Vec<DatasetInfo> {
+    let mut datasets = Vec::new();
+    let Some(DNode::ObjectDirectory(mut object_directory)) = mos.get_dnode_at(1, vdevs) else {
+        return datasets;
+    };
+    let Some(zap::Value::U64(root_directory_id)) = object_directory
+        .dump_zap_contents(vdevs)
+        .and_then(|mut objdir_zap_data| objdir_zap_data.remove("root_dataset"))
+    else {
+        return datasets;
+    };
+    collect_datasets(
+        mos,
+        root_directory_id,
+        String::from(pool_name),
+        &mut HashSet::new(),
+        &mut datasets,
+        vdevs,
+    );
+    datasets
+}
+
+// Adds the datasets of the dsl directory, its snapshots and then the ones of its children, to `datasets`
+fn collect_datasets(
+    mos: &mut ObjSet,
+    directory_id: u64,
+    name: String,
+    visited: &mut HashSet<u64>,
+    datasets: &mut Vec<DatasetInfo>,
+    vdevs: &mut Vdevs,
+) {
+    // A corrupted child map could point back up the tree
+    if !visited.insert(directory_id) {
+        return;
+    }
+    let Some(DNode::DSLDirectory(directory)) = mos.get_dnode_at(directory_id as usize, vdevs)
+    else {
+        return;
+    };
+    let Some(directory) = directory.parse_bonus_data() else {
+        return;
+    };
+
+    let head_dataset_id = directory.get_head_dataset_object_number();
+    if let Some(DNode::DSLDataset(head_dataset)) = mos.get_dnode_at(head_dataset_id as usize, vdevs)
+    {
+        datasets.push(DatasetInfo {
+            object_id: head_dataset_id,
+            name: name.clone(),
+            is_snapshot: false,
+        });
+
+        let snapshot_names_id = head_dataset
+            .parse_bonus_data()
+            .map_or(0, |dataset| dataset.get_snapshot_names_object_number());
+        if let Some(snapshots) = read_name_map(
+            mos,
+            snapshot_names_id,
+            ObjType::DSLDataSetSnapshotMap,
+            vdevs,
+        ) {
+            for (snapshot_name, snapshot_id) in snapshots {
+                datasets.push(DatasetInfo {
+                    object_id: snapshot_id,
+                    name: format!("{name}@{snapshot_name}"),
+                    is_snapshot: true,
+                });
+            }
+        }
+    }
+
+    if let Some(children) = read_name_map(
+        mos,
+        directory.get_children_directory_object_number(),
+        ObjType::DSLDirectoryChildMap,
+        vdevs,
+    ) {
+        for (child_name, child_id) in children {
+            // Names starting with $ are internal (ex. $ORIGIN, $FREE, $MOS), they aren't datasets
+            if child_name.starts_with('$') {
+                continue;
+            }
+            collect_datasets(
+                mos,
+                child_id,
+                format!("{name}/{child_name}"),
+                visited,
+                datasets,
+                vdevs,
+            );
+        }
+    }
+}
+
+// Returns: The entries of a zap that maps names to object numbers, sorted by name
+fn read_name_map(
+    mos: &mut ObjSet,
+    zap_id: u64,
+    expected_type: ObjType,
+    vdevs: &mut Vdevs,
+) -> Option<Vec<(String, u64)>> {
+    if zap_id == 0 {
+        return None;
+    }
+    let (mut zap_dnode, obj_type) = mos.get_zap_dnode_at(zap_id as usize, vdevs)?;
+    if obj_type != expected_type {
+        return None;
+    }
+    let mut entries = zap_dnode
+        .dump_zap_contents(vdevs)?
+        .into_iter()
+        .filter_map(|(name, value)| match value {
+            zap::Value::U64(object_id) => Some((name, object_id)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+    Some(entries)
+}
+
+// Returns: The object set of the dataset with the name `dataset_name`
+pub fn open_dataset(
+    mos: &mut ObjSet,
+    pool_name: &str,
+    dataset_name: &str,
+    vdevs: &mut Vdevs,
+) -> Result<ObjSet, String> {
+    let dataset_id = collect_all_datasets(mos, pool_name, vdevs)
+        .into_iter()
+        .find(|dataset| dataset.name == dataset_name)
+        .ok_or(format!("There is no dataset named {dataset_name:?}"))?
+        .object_id;
+    open_dataset_by_id(mos, dataset_id, vdevs)
+}
+
+// Returns: The object set of the dsl dataset with this object id
+pub fn open_dataset_by_id(
+    mos: &mut ObjSet,
+    dataset_id: u64,
+    vdevs: &mut Vdevs,
+) -> Result<ObjSet, String> {
+    let Some(DNode::DSLDataset(dataset)) = mos.get_dnode_at(dataset_id as usize, vdevs) else {
+        return Err(format!("Dataset {dataset_id} can't be read"));
+    };
+    let mut dataset_bonus = dataset
+        .parse_bonus_data()
+        .ok_or(format!("Dataset {dataset_id} has invalid bonus data"))?;
+    let objset_data = dataset_bonus
+        .get_block_pointer()
+        .dereference(vdevs)
+        .map_err(|_| format!("The object set of dataset {dataset_id} can't be read"))?;
+    ObjSet::from_slice_le(&objset_data)
+        .ok_or(format!("The object set of dataset {dataset_id} is invalid"))
+}
+
+// Returns: The size of the file from its attributes, using the attribute registry of the dataset if it can be read
+// NOTE: Datasets from before system attributes have no registry, their files have the old znode attributes which don't need one
+fn get_file_size(dataset: &mut ObjSet, file: &DNodePlainFileContents, vdevs: &mut Vdevs) -> u64 {
+    let registered_size = (|| {
+        let DNode::MasterNode(mut master_node) = dataset.get_dnode_at(1, vdevs)? else {
+            return None;
+        };
+        let master_node_zap_data = master_node.dump_zap_contents(vdevs)?;
+        let mut system_attributes = match master_node_zap_data.get("SA_ATTRS") {
+            Some(zap::Value::U64(system_attributes_info_number)) => Some(
+                zpl::SystemAttributes::from_attributes_node_number(
+                    *system_attributes_info_number as usize,
+                    dataset,
+                    vdevs,
+                )
+                .ok()?,
+            ),
+            _ => None,
+        };
+        let file_info =
+            zpl::parse_zpl_attributes(system_attributes.as_mut(), file.0.get_bonus_data(), &file.1)
+                .ok()?;
+        match file_info.get("ZPL_SIZE") {
+            Some(zpl::Value::U64(size)) => Some(*size),
+            _ => None,
+        }
+    })();
+
+    registered_size
+        .or_else(|| FileAttributes::guess_from_dnode(file).map(|attributes| attributes.size))
+        .unwrap_or(file.0.get_data_size() as u64)
+}
+
+impl PoolReader {
+    // Opens the pool on the disks, they can be given in any order if their labels can be read
+    // NOTE: The disks are always opened read only
+    pub fn open(vdev_paths: &[PathBuf]) -> Result<PoolReader, String> {
+        let args = cli::PoolArgs {
+            vdevs: vdev_paths.to_vec(),
+            label_index: None,
+            txg: None,
+            output_dir: None,
+            config: None,
+            max_read_rate: None,
+            damage_maps: Vec::new(),
+            allow_write: false,
+            no_verify_checksums: false,
+            accept_partial_decompression: false,
+            read_retries: None,
+        };
+        let mut pool = cli::Pool::open(&args)?;
+        pool.order_devices(0)?;
+
+        let name = match pool.name_value_pairs.get("name") {
+            Some(nvlist::Value::String(name)) => name.clone(),
+            _ => String::from("unknown"),
+        };
+
+        let mut uberblocks = pool.collect_uberblocks();
+        let (mos, _) = with_vdevs(&mut pool, |vdevs| {
+            rewind::open_newest_mos(&mut uberblocks, vdevs)
+        })
+        .ok_or("There is no uberblock whose MOS can be read")?;
+
+        Ok(PoolReader { pool, mos, name })
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    // Returns: Every dataset that can be reached from the root dataset, including snapshots
+    pub fn list_datasets(&mut self) -> Vec<DatasetInfo> {
+        let (mos, name) = (&mut self.mos, &self.name);
+        with_vdevs(&mut self.pool, |vdevs| {
+            collect_all_datasets(mos, name, vdevs)
+        })
+    }
+
+    // Opens the plain file at `path` (relative to the root of the dataset) in the dataset with the name `dataset_name`
+    pub fn open_file(&mut self, dataset_name: &str, path: &str) -> Result<FileReader, String> {
+        let (mos, name) = (&mut self.mos, &self.name);
+        with_vdevs(&mut self.pool, |vdevs| {
+            let mut dataset_objset = open_dataset(mos, name, dataset_name, vdevs)?;
+            let object_id = zpl::lookup_path(&mut dataset_objset, path, vdevs)
+                .ok_or(format!("{path:?} can't be found in {dataset_name}"))?;
+            let Some(DNode::PlainFileContents(file)) =
+                dataset_objset.get_dnode_at(object_id as usize, vdevs)
+            else {
+                return Err(format!("{path:?} (object {object_id}) is not a plain file"));
+            };
+            let size = get_file_size(&mut dataset_objset, &file, vdevs);
+            Ok(FileReader { file, size })
+        })
+    }
+
+    // Returns: The entries of the directory at `path` (relative to the root of the dataset) in the dataset with the name `dataset_name`, sorted by name
+    pub fn list_directory(
+        &mut self,
+        dataset_name: &str,
+        path: &str,
+    ) -> Result<Vec<zpl::DirectoryEntry>, String> {
+        let (mos, name) = (&mut self.mos, &self.name);
+        with_vdevs(&mut self.pool, |vdevs| {
+            let mut dataset_objset = open_dataset(mos, name, dataset_name, vdevs)?;
+            let object_id = zpl::lookup_path(&mut dataset_objset, path, vdevs)
+                .ok_or(format!("{path:?} can't be found in {dataset_name}"))?;
+            let Some(DNode::DirectoryContents(mut directory)) =
+                dataset_objset.get_dnode_at(object_id as usize, vdevs)
+            else {
+                return Err(format!("{path:?} (object {object_id}) is not a directory"));
+            };
+            let directory_zap_data = directory
+                .dump_zap_contents(vdevs)
+                .ok_or(format!("{path:?} (object {object_id}) can't be read"))?;
+            Ok(zpl::parse_directory_entries(&directory_zap_data))
+        })
+    }
+
+    // Reads up to `amount` bytes of the file starting at `offset`, less at the end of the file
+    // Holes read as zeros
+    pub fn read_file(
+        &mut self,
+        file: &mut FileReader,
+        offset: u64,
+        amount: usize,
+    ) -> Result<Vec<u8>, ()> {
+        let end = file.size.min(offset.saturating_add(amount as u64));
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        let block_size = file.file.0.parse_data_block_size() as u64;
+        if block_size == 0 {
+            return Err(());
+        }
+
+        with_vdevs(&mut self.pool, |vdevs| {
+            let mut result = Vec::with_capacity((end - offset) as usize);
+            let mut position = offset;
+            let mut cursor = IndirectTreeCursor::new();
+            while position < end {
+                let block_id = position / block_size;
+                let block_start = block_id * block_size;
+                let in_block = (position - block_start) as usize
+                    ..(end.min(block_start + block_size) - block_start) as usize;
+                match cursor.read_block_or_hole(&file.file.0, block_id as usize, vdevs)? {
+                    Some(block) => result.extend_from_slice(block.get(in_block.clone()).ok_or(())?),
+                    None => result.resize(result.len() + in_block.len(), 0),
+                }
+                position += in_block.len() as u64;
+            }
+            Ok(result)
+        })
+    }
+}
@@ -0,0 +1,103 @@
+// Which recovered fragments of a file have a block pointer for a given block of the file
+// recover used to try every fragment for every block, which is O(fragments * blocks) reads of indirect blocks and way too slow for big files
+// So the indirect trees of all fragments are walked once up front, and the result is stored as runs of blocks that have the same candidates
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    recovery::fragment::{Fragment, FragmentData},
+    zio::Vdevs,
+};
+
+pub struct BlockIndex {
+    // Every block id from a key up to (not including) the next key has the same candidates
+    runs: BTreeMap<u64, Vec<[u64; 4]>>,
+}
+
+impl BlockIndex {
+    // The fragments should be sorted by priority, the candidates of every block will be in the same order
+    // Block ids are of blocks of `block_size` bytes, the versions of a file don't all have the same block size (ex. a file that is only one block has a block the size of the file)
+    // so a fragment is a candidate for every block it has data in, whatever its own block size is
+    // NOTE: Holes and blocks under indirect blocks that couldn't be read aren't indexed, as no fragment could read them anyways
+    // but a fragment with smaller blocks is still a candidate for a block if only some of its blocks in it are there
+    pub fn build(
+        fragments: &mut [([u64; 4], Fragment)],
+        block_size: u64,
+        vdevs: &mut Vdevs,
+    ) -> BlockIndex {
+        // Block id -> (fragment priority, true if the fragment starts covering blocks at that id, false if it stops)
+        let mut events = BTreeMap::<u64, Vec<(usize, bool)>>::new();
+        if block_size == 0 {
+            return BlockIndex {
+                runs: BTreeMap::new(),
+            };
+        }
+        for (priority, (_, fragment)) in fragments.iter_mut().enumerate() {
+            let FragmentData::FileDNode(file) = &mut fragment.data else {
+                continue;
+            };
+            let fragment_block_size = file.0.parse_data_block_size() as u64;
+            if fragment_block_size == 0 {
+                continue;
+            }
+
+            let mut covered_block_ids = file
+                .map_blocks(vdevs)
+                .into_iter()
+                .flat_map(|mapping| {
+                    mapping.file_offset / block_size
+                        ..(mapping.file_offset + fragment_block_size).div_ceil(block_size)
+                })
+                .collect::<Vec<_>>();
+            // Smaller blocks of the fragment can be in the same block
+            covered_block_ids.dedup();
+            let mut covered_block_ids = covered_block_ids.into_iter().peekable();
+            // Merge consecutive blocks into one range, so there are only 2 events per range and not per block
+            while let Some(start) = covered_block_ids.next() {
+                let mut end = start + 1;
+                while covered_block_ids.next_if_eq(&end).is_some() {
+                    end += 1;
+                }
+                events.entry(start).or_default().push((priority, true));
+                events.entry(end).or_default().push((priority, false));
+            }
+        }
+
+        let mut runs = BTreeMap::new();
+        let mut active = BTreeSet::new();
+        let mut last_candidates = Vec::new();
+        for (block_id, changes) in events {
+            for (priority, starts) in changes {
+                if starts {
+                    active.insert(priority);
+                } else {
+                    active.remove(&priority);
+                }
+            }
+
+            let candidates = active
+                .iter()
+                .map(|&priority| fragments[priority].0)
+                .collect::<Vec<_>>();
+            if candidates != last_candidates {
+                runs.insert(block_id, candidates.clone());
+                last_candidates = candidates;
+            }
+        }
+        BlockIndex { runs }
+    }
+
+    // Returns: The hashes of the fragments that have the block, in order of priority
+    pub fn get_candidates(&self, block_id: u64) -> &[[u64; 4]] {
+        self.runs
+            .range(..=block_id)
+            .next_back()
+            .map(|(_, candidates)| candidates.as_slice())
+            .unwrap_or(&[])
+    }
+
+    // Returns: The number of runs of blocks with the same candidates, useful to see how fragmented the file is
+    pub fn get_n_runs(&self) -> usize {
+        self.runs.len()
+    }
+}
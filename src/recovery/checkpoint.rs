@@ -0,0 +1,269 @@
+// An append-only log of checkpoint segments, so long running scans can save their progress
+// without re-serializing everything they found so far every time, and can be resumed after a crash
+// Layout: magic (u64), schema version (u64), followed by segments of:
+// payload size (u64), resume cursor (u64), payload (bincode, padded to a multiple of 8 bytes), fletcher4 of the padded payload ([u64; 4])
+// The resume cursor is up to where the work was done when the segment was written, ex. the disk offset for a scan
+// NOTE: A segment that was only partially written (ex. because of a crash) is dropped when opening the log
+// NOTE: A log of an older schema version is converted to the current one when its segments are read, see migrate.rs
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    fletcher::do_fletcher4,
+    recovery::migrate::{self, CheckpointEntry},
+};
+
+// "SZFSCKPT" in ascii
+pub const CHECKPOINT_LOG_MAGIC: u64 = 0x54504B4353465A53;
+const CHECKPOINT_LOG_HEADER_SIZE: u64 = 16;
+
+pub struct CheckpointLog {
+    file: File,
+    path: PathBuf,
+    // The schema version the segments were written with
+    version: u64,
+    resume_cursor: u64,
+    nsegments: usize,
+}
+
+fn read_u64_le(reader: &mut impl Read) -> Option<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf).ok()?;
+    Some(u64::from_le_bytes(buf))
+}
+
+// `bytes_left` is how much of the file there is from the start of the segment
+// Returns: The payload and the resume cursor of the segment, or None if the segment is incomplete or corrupted
+fn read_segment(reader: &mut impl Read, bytes_left: u64) -> Option<(Vec<u8>, u64)> {
+    let payload_size = read_u64_le(reader)?;
+    let resume_cursor = read_u64_le(reader)?;
+    // The size is whatever is in the file, so for a corrupted segment it can be anything, but the segment has to fit in what's left of the file
+    let segment_size = payload_size
+        .checked_next_multiple_of(8)?
+        .checked_add(get_segment_ondisk_size(0))?;
+    if segment_size > bytes_left {
+        return None;
+    }
+    let mut payload = vec![0u8; usize::try_from(payload_size.next_multiple_of(8)).ok()?];
+    reader.read_exact(&mut payload).ok()?;
+    let mut checksum = [0u64; 4];
+    for word in checksum.iter_mut() {
+        *word = read_u64_le(reader)?;
+    }
+
+    if do_fletcher4(&payload) != checksum {
+        return None;
+    }
+
+    payload.truncate(payload_size as usize);
+    Some((payload, resume_cursor))
+}
+
+fn get_segment_ondisk_size(payload_size: u64) -> u64 {
+    2 * 8 + payload_size.next_multiple_of(8) + 4 * 8
+}
+
+impl CheckpointLog {
+    // Creates a new empty log, overwriting anything that was at `path` before
+    pub fn create(path: &Path) -> Result<CheckpointLog, ()> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|_| ())?;
+        file.write_all(&CHECKPOINT_LOG_MAGIC.to_le_bytes())
+            .map_err(|_| ())?;
+        file.write_all(&migrate::SCHEMA_VERSION.to_le_bytes())
+            .map_err(|_| ())?;
+        file.sync_data().map_err(|_| ())?;
+        Ok(CheckpointLog {
+            file,
+            path: path.to_path_buf(),
+            version: migrate::SCHEMA_VERSION,
+            resume_cursor: 0,
+            nsegments: 0,
+        })
+    }
+
+    // Opens an existing log so it can be resumed, or creates a new one if there is nothing at `path`
+    pub fn open(path: &Path) -> Result<CheckpointLog, ()> {
+        use crate::ansi_color::*;
+        if !path.exists() {
+            return Self::create(path);
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|_| ())?;
+
+        let file_size = file.metadata().map_err(|_| ())?.len();
+        let mut reader = BufReader::new(&mut file);
+        if read_u64_le(&mut reader) != Some(CHECKPOINT_LOG_MAGIC) {
+            println!("{YELLOW}Warning{WHITE}: {path:?} is not a checkpoint log!");
+            return Err(());
+        }
+
+        let version = read_u64_le(&mut reader).ok_or(())?;
+        if !(migrate::FIRST_SCHEMA_VERSION..=migrate::SCHEMA_VERSION).contains(&version) {
+            println!("{YELLOW}Warning{WHITE}: Checkpoint log {path:?} has unsupported version {version}, it's probably from a newer version of szfs!");
+            return Err(());
+        }
+
+        let mut resume_cursor = 0;
+        let mut nsegments = 0;
+        let mut valid_size = CHECKPOINT_LOG_HEADER_SIZE;
+        while let Some((payload, segment_resume_cursor)) =
+            read_segment(&mut reader, file_size.saturating_sub(valid_size))
+        {
+            resume_cursor = segment_resume_cursor;
+            nsegments += 1;
+            valid_size += get_segment_ondisk_size(payload.len() as u64);
+        }
+        drop(reader);
+
+        if valid_size != file_size {
+            println!("{YELLOW}Warning{WHITE}: Checkpoint log {path:?} ends with an incomplete segment ({} bytes), dropping it!", file_size - valid_size);
+            file.set_len(valid_size).map_err(|_| ())?;
+        }
+        file.seek(SeekFrom::Start(valid_size)).map_err(|_| ())?;
+
+        // There is nothing to convert, so it can just be started over in the current version
+        if nsegments == 0 && version != migrate::SCHEMA_VERSION {
+            drop(file);
+            return Self::create(path);
+        }
+        if version != migrate::SCHEMA_VERSION {
+            println!("{CYAN}Info{WHITE}: Checkpoint log {path:?} has the older version {version}, it will be converted to version {} when it's read", migrate::SCHEMA_VERSION);
+        }
+
+        Ok(CheckpointLog {
+            file,
+            path: path.to_path_buf(),
+            version,
+            resume_cursor,
+            nsegments,
+        })
+    }
+
+    // Returns: The resume cursor of the last segment, or 0 if the log is empty
+    pub fn get_resume_cursor(&self) -> u64 {
+        self.resume_cursor
+    }
+
+    pub fn get_nsegments(&self) -> usize {
+        self.nsegments
+    }
+
+    // NOTE: The segment is synced to disk before returning, so once this returns the segment will survive a crash
+    // NOTE: A log of an older version has to be read first, otherwise the new segment would be in another shape than the ones before it
+    pub fn append<T: Serialize>(&mut self, resume_cursor: u64, segment: &T) -> Result<(), ()> {
+        if self.version != migrate::SCHEMA_VERSION {
+            use crate::ansi_color::*;
+            println!("{YELLOW}Warning{WHITE}: Can't append to checkpoint log {:?}, it has the older version {} and wasn't read (and converted) yet!", self.path, self.version);
+            return Err(());
+        }
+        let mut payload = bincode::serialize(segment).map_err(|_| ())?;
+        let payload_size = payload.len() as u64;
+        payload.resize(payload.len().next_multiple_of(8), 0);
+
+        let mut data = Vec::with_capacity(get_segment_ondisk_size(payload_size) as usize);
+        data.extend(payload_size.to_le_bytes());
+        data.extend(resume_cursor.to_le_bytes());
+        data.extend(payload.iter());
+        for word in do_fletcher4(&payload) {
+            data.extend(word.to_le_bytes());
+        }
+
+        self.file.write_all(&data).map_err(|_| ())?;
+        self.file.sync_data().map_err(|_| ())?;
+        self.resume_cursor = resume_cursor;
+        self.nsegments += 1;
+        Ok(())
+    }
+
+    // Returns: All the segments in the log, in the order they were appended, every segment is a list of entries
+    // NOTE: If the log has an older version it's rewritten in the current one, so it can be appended to after this
+    pub fn read_segments<T: CheckpointEntry>(&mut self) -> Result<Vec<Vec<T>>, ()> {
+        let end = self.file.stream_position().map_err(|_| ())?;
+        self.file
+            .seek(SeekFrom::Start(CHECKPOINT_LOG_HEADER_SIZE))
+            .map_err(|_| ())?;
+
+        let mut segments = Vec::with_capacity(self.nsegments);
+        let mut resume_cursors = Vec::with_capacity(self.nsegments);
+        let mut reader = BufReader::new(&mut self.file);
+        let mut position = CHECKPOINT_LOG_HEADER_SIZE;
+        for _ in 0..self.nsegments {
+            let (payload, resume_cursor) =
+                read_segment(&mut reader, end.saturating_sub(position)).ok_or(())?;
+            position += get_segment_ondisk_size(payload.len() as u64);
+            segments.push(T::migrate_list(self.version, &payload)?);
+            resume_cursors.push(resume_cursor);
+        }
+        drop(reader);
+
+        self.file.seek(SeekFrom::Start(end)).map_err(|_| ())?;
+        if self.version != migrate::SCHEMA_VERSION {
+            self.rewrite(&segments, &resume_cursors)?;
+        }
+        Ok(segments)
+    }
+
+    // Writes the segments to a new log next to this one, which then replaces it, so a crash in the middle leaves the old log as it was
+    fn rewrite<T: Serialize>(&mut self, segments: &[T], resume_cursors: &[u64]) -> Result<(), ()> {
+        use crate::ansi_color::*;
+        let mut new_path = self.path.clone().into_os_string();
+        new_path.push(".migrating");
+        let new_path = PathBuf::from(new_path);
+
+        let mut new_log = Self::create(&new_path)?;
+        for (segment, resume_cursor) in segments.iter().zip(resume_cursors.iter()) {
+            new_log.append(*resume_cursor, segment)?;
+        }
+        fs::rename(&new_path, &self.path).map_err(|_| ())?;
+        println!(
+            "{CYAN}Info{WHITE}: Converted checkpoint log {:?} from version {} to version {}",
+            self.path,
+            self.version,
+            migrate::SCHEMA_VERSION
+        );
+        new_log.path = self.path.clone();
+        *self = new_log;
+        Ok(())
+    }
+}
+
+// Reads a checkpoint whose segments are lists of entries (ex. (hash, fragment) pairs) and concatenates them
+// NOTE: Old checkpoints were a single json list, so .json files are still read that way
+pub fn read_checkpoint_entries<T: CheckpointEntry>(path: &Path) -> Result<Vec<T>, ()> {
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        let file = File::open(path).map_err(|_| ())?;
+        return serde_json::from_reader(BufReader::new(file)).map_err(|_| ());
+    }
+
+    if !path.exists() {
+        return Err(());
+    }
+
+    let mut log = CheckpointLog::open(path)?;
+    Ok(log.read_segments::<T>()?.into_iter().flatten().collect())
+}
+
+// Writes a checkpoint that contains all of `entries` in one segment, overwriting anything that was at `path` before
+pub fn write_checkpoint_entries<T: Serialize>(path: &Path, entries: &[T]) -> Result<(), ()> {
+    let mut log = CheckpointLog::create(path)?;
+    log.append(0, &entries)
+}
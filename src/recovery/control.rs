@@ -0,0 +1,100 @@
+// Stopping and slowing down the long running operations (scans and checksum table builds), they can run for days
+// so there has to be a way to stop them without losing the progress since the last checkpoint
+// and a way to not hammer a disk that is already dying
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    Finished,
+    // Stopped because of a CancellationToken, everything that was done is saved so running again resumes from there
+    Cancelled,
+}
+
+// Cooperative, the operations check it between chunks, so they always stop at a point where their checkpoint is consistent
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // The first Ctrl-C cancels the token, a second one exits right away, for when waiting for the current chunk takes too long
+    pub fn cancel_on_ctrl_c(&self) -> Result<(), ()> {
+        use signal_hook::{consts::SIGINT, flag};
+        // Registered first, so on the second Ctrl-C it sees the flag already set by the first one
+        flag::register_conditional_shutdown(SIGINT, 1, self.cancelled.clone()).map_err(|_| ())?;
+        flag::register(SIGINT, self.cancelled.clone()).map_err(|_| ())?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct RateLimiterState {
+    // When the reads that were already let through will have used up their share of the rate
+    next_free: Instant,
+}
+
+// Limits how many bytes per second are read, shared between everything that is cloned from it (ex. the workers of a scan)
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    state: Arc<Mutex<RateLimiterState>>,
+}
+
+impl RateLimiter {
+    // Returns: None if the rate is 0
+    pub fn new(bytes_per_second: u64) -> Option<RateLimiter> {
+        if bytes_per_second == 0 {
+            return None;
+        }
+        Some(RateLimiter {
+            bytes_per_second,
+            state: Arc::new(Mutex::new(RateLimiterState {
+                next_free: Instant::now(),
+            })),
+        })
+    }
+
+    pub fn get_bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    // Blocks until reading `amount_in_bytes` more bytes stays within the rate
+    // NOTE: The time a read takes is reserved before sleeping, so reads from different threads queue up instead of all waking up at once
+    pub fn wait_for(&self, amount_in_bytes: usize) {
+        let duration =
+            Duration::from_secs_f64(amount_in_bytes as f64 / self.bytes_per_second as f64);
+        let start = {
+            // A panic while holding the lock can't leave the state invalid, so poisoning is ignored
+            let mut state = self
+                .state
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let start = state.next_free.max(Instant::now());
+            state.next_free = start + duration;
+            start
+        };
+        let now = Instant::now();
+        if start > now {
+            std::thread::sleep(start - now);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+// Exports the fragment graph in formats standard graph tools understand, so the recovered structures can be looked at visually
+// DOT: https://graphviz.org/doc/info/lang.html
+// GraphML: http://graphml.graphdrawing.org/specification.html
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use crate::{
+    recovery::fragment::{Fragment, FragmentData},
+    zio,
+};
+
+struct NodeInfo {
+    id: String,
+    kind: &'static str,
+    label: String,
+    // Size of the data the fragment describes in bytes, for indirect blocks this is the size of the block pointers themselves
+    size: usize,
+    nchildren: usize,
+    hash: [u64; 4],
+}
+
+fn get_node_infos(fragments: &HashMap<[u64; 4], Fragment>) -> Vec<NodeInfo> {
+    // Sorted so the output is the same every time for the same graph
+    let mut hashes = fragments.keys().copied().collect::<Vec<[u64; 4]>>();
+    hashes.sort_unstable();
+
+    hashes
+        .into_iter()
+        .enumerate()
+        .map(|(index, hash)| {
+            let fragment = &fragments[&hash];
+            let (kind, size, label) = match &fragment.data {
+                FragmentData::FileDNode(file) => {
+                    ("File", file.0.get_data_size(), format!("File{index}"))
+                }
+                FragmentData::DirectoryDNode(dir, contents) => (
+                    "Dir",
+                    dir.0.get_data_size(),
+                    format!("Dir{index}\n{}", contents.join(", ")),
+                ),
+                FragmentData::ObjSetDNode(objset) => (
+                    "ObjSet",
+                    objset.metadnode.get_data_size(),
+                    format!("ObjSet{index}"),
+                ),
+                FragmentData::IndirectBlock(indirect_block) => (
+                    "Indirect",
+                    indirect_block.bps.len() * zio::BlockPointer::get_ondisk_size(),
+                    format!("Indirect{index}"),
+                ),
+            };
+
+            NodeInfo {
+                id: format!("n{index}"),
+                kind,
+                label: format!(
+                    "{label}\nsize: {size}\nchildren: {}",
+                    fragment.children.len()
+                ),
+                size,
+                nchildren: fragment.children.len(),
+                hash,
+            }
+        })
+        .collect()
+}
+
+fn escape_dot(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn get_edges<'a>(
+    fragments: &'a HashMap<[u64; 4], Fragment>,
+    nodes: &'a [NodeInfo],
+) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+    let ids = nodes
+        .iter()
+        .map(|node| (node.hash, node.id.as_str()))
+        .collect::<HashMap<[u64; 4], &str>>();
+
+    nodes.iter().flat_map(move |node| {
+        let mut children = fragments[&node.hash]
+            .children
+            .iter()
+            // NOTE: A child might not be in the graph, ex. if the graph was filtered
+            .filter_map(|child| ids.get(child).copied())
+            .collect::<Vec<&str>>();
+        children.sort_unstable();
+        children
+            .into_iter()
+            .map(move |child_id| (node.id.as_str(), child_id))
+    })
+}
+
+pub fn write_graph_dot(
+    fragments: &HashMap<[u64; 4], Fragment>,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let nodes = get_node_infos(fragments);
+    writeln!(output, "digraph fragments {{")?;
+    writeln!(output, "    node [shape=box];")?;
+    for node in nodes.iter() {
+        let shape = match node.kind {
+            "ObjSet" => "doubleoctagon",
+            "Dir" => "folder",
+            "File" => "note",
+            _ => "box",
+        };
+        writeln!(
+            output,
+            "    {} [label=\"{}\", shape={}, type=\"{}\", size_bytes={}, nchildren={}, hash=\"{:?}\"];",
+            node.id,
+            escape_dot(&node.label),
+            shape,
+            node.kind,
+            node.size,
+            node.nchildren,
+            node.hash
+        )?;
+    }
+
+    for (parent, child) in get_edges(fragments, &nodes) {
+        writeln!(output, "    {parent} -> {child};")?;
+    }
+    writeln!(output, "}}")?;
+    Ok(())
+}
+
+pub fn write_graph_graphml(
+    fragments: &HashMap<[u64; 4], Fragment>,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    let nodes = get_node_infos(fragments);
+    writeln!(output, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        output,
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+    )?;
+    writeln!(
+        output,
+        "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>"
+    )?;
+    writeln!(
+        output,
+        "  <key id=\"type\" for=\"node\" attr.name=\"type\" attr.type=\"string\"/>"
+    )?;
+    writeln!(
+        output,
+        "  <key id=\"size\" for=\"node\" attr.name=\"size\" attr.type=\"long\"/>"
+    )?;
+    writeln!(
+        output,
+        "  <key id=\"nchildren\" for=\"node\" attr.name=\"nchildren\" attr.type=\"int\"/>"
+    )?;
+    writeln!(
+        output,
+        "  <key id=\"hash\" for=\"node\" attr.name=\"hash\" attr.type=\"string\"/>"
+    )?;
+    writeln!(
+        output,
+        "  <graph id=\"fragments\" edgedefault=\"directed\">"
+    )?;
+    for node in nodes.iter() {
+        writeln!(output, "    <node id=\"{}\">", node.id)?;
+        writeln!(
+            output,
+            "      <data key=\"label\">{}</data>",
+            escape_xml(&node.label)
+        )?;
+        writeln!(output, "      <data key=\"type\">{}</data>", node.kind)?;
+        writeln!(output, "      <data key=\"size\">{}</data>", node.size)?;
+        writeln!(
+            output,
+            "      <data key=\"nchildren\">{}</data>",
+            node.nchildren
+        )?;
+        writeln!(output, "      <data key=\"hash\">{:?}</data>", node.hash)?;
+        writeln!(output, "    </node>")?;
+    }
+
+    for (index, (parent, child)) in get_edges(fragments, &nodes).enumerate() {
+        writeln!(
+            output,
+            "    <edge id=\"e{index}\" source=\"{parent}\" target=\"{child}\"/>"
+        )?;
+    }
+    writeln!(output, "  </graph>")?;
+    writeln!(output, "</graphml>")?;
+    Ok(())
+}
@@ -0,0 +1,623 @@
+// The fragment graph that undelete builds out of the structures it finds on disk
+// A fragment is a structure (objset, dnode or indirect block) that was found on disk, identified by the hash of its data
+// and its children are the fragments it (probably) points to, so after building the graph the roots are the top most structures that survived
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    byte_iter::FromSliceLE,
+    dmu::{self, DNode, DNodeDirectoryContents, DNodePlainFileContents, ObjSet},
+    fletcher,
+    zio::{self, Vdevs},
+};
+
+// Fragments are identified by the hash of the data they were parsed from
+// NOTE: This code assumes the hash function is perfect
+pub fn hash_fragment_data(data: &[u8]) -> [u64; 4] {
+    fletcher::do_fletcher4(data)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndirectBlock {
+    pub bps: Vec<Option<zio::BlockPointer>>,
+}
+
+impl IndirectBlock {
+    pub fn from_bytes_le(data: &[u8], vdevs: &mut Vdevs) -> Option<IndirectBlock> {
+        let mut res = Vec::new();
+        let mut nfound = 0;
+        let data = data.chunks(zio::BlockPointer::get_ondisk_size());
+        for potential_bp in data {
+            if let Some(mut bp) = zio::BlockPointer::from_slice_le(potential_bp) {
+                // Verify block pointer
+                // NOTE: This might not necessarily guarantee that the block pointer
+                // wasn't just misinterpreted random data, especially if
+                // it is an embedded block pointer
+                if bp.dereference(vdevs).is_ok() {
+                    res.push(Some(bp));
+                    nfound += 1;
+                } else {
+                    res.push(None);
+                }
+            } else {
+                res.push(None);
+                continue;
+            }
+        }
+
+        if nfound == 0 {
+            return None;
+        }
+
+        Some(IndirectBlock { bps: res })
+    }
+
+    // Assumes that all block pointers point to blocks of the same size
+    // Will replace a missing block with a chunk of zeros, of the same size as all other blocks
+    pub fn get_data_with_gaps(&mut self, vdevs: &mut Vdevs) -> Option<Vec<u8>> {
+        let mut res = Vec::new();
+        let block_pointer_chunck_size = self.bps.iter().flatten().next()?.parse_logical_size();
+        for bp in self.bps.iter_mut() {
+            if let Some(ref mut bp) = bp {
+                if block_pointer_chunck_size != bp.parse_logical_size() {
+                    return None;
+                }
+                res.extend(bp.dereference(vdevs).unwrap());
+            } else {
+                res.resize(res.len() + block_pointer_chunck_size as usize, 0u8);
+            }
+        }
+        Some(res)
+    }
+}
+
+// NOTE: The objset is boxed since it's a lot bigger than the rest, serde doesn't care so the checkpoints are the same
+#[derive(Serialize, Deserialize)]
+pub enum FragmentData {
+    FileDNode(DNodePlainFileContents),
+    DirectoryDNode(DNodeDirectoryContents, Vec<String>),
+    ObjSetDNode(Box<ObjSet>),
+    IndirectBlock(IndirectBlock),
+}
+
+impl Debug for FragmentData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FragmentData::FileDNode(_) => write!(f, "File"),
+            FragmentData::DirectoryDNode(_, _) => write!(f, "Dir"),
+            FragmentData::ObjSetDNode(_) => write!(f, "ObjSet"),
+            FragmentData::IndirectBlock(_) => write!(f, "Indirect"),
+        }?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Fragment {
+    pub data: FragmentData,
+    pub children: HashSet<[u64; 4]>,
+}
+
+impl Debug for Fragment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.data)?;
+        write!(f, "(")?;
+        for child in self.children.iter() {
+            write!(f, "{:?}, ", child[0])?;
+        }
+        write!(f, ")")?;
+        Ok(())
+    }
+}
+
+impl Fragment {
+    // Returns: The block pointers of the dnode, or the ones that could be read of the indirect block
+    pub fn get_block_pointers(&mut self) -> Vec<&zio::BlockPointer> {
+        match &mut self.data {
+            FragmentData::FileDNode(dnode) => dnode.0.get_block_pointers().iter().collect(),
+            FragmentData::DirectoryDNode(dnode, _) => dnode.0.get_block_pointers().iter().collect(),
+            FragmentData::ObjSetDNode(objset) => {
+                objset.metadnode.get_block_pointers().iter().collect()
+            }
+            FragmentData::IndirectBlock(indirect_block) => {
+                indirect_block.bps.iter().flatten().collect()
+            }
+        }
+    }
+
+    // Returns: The newest birth txg of the block pointers in the fragment, which is roughly when it was last changed
+    pub fn get_newest_birth_txg(&mut self) -> Option<u64> {
+        self.get_block_pointers()
+            .into_iter()
+            .map(|bp| bp.get_logical_birth_txg())
+            .max()
+    }
+
+    pub fn is_child_of(
+        &mut self,
+        vdevs: &mut Vdevs,
+        self_hash: [u64; 4],
+        potential_parent: &mut Fragment,
+    ) -> bool {
+        if potential_parent.children.contains(&self_hash) {
+            return true;
+        }
+
+        match (&mut potential_parent.data, &mut self.data) {
+            (FragmentData::IndirectBlock(parent), FragmentData::IndirectBlock(_us)) => {
+                for bptr in parent.bps.iter_mut() {
+                    if let Some(Ok(data)) = bptr.as_mut().map(|val| val.dereference(vdevs)) {
+                        let hsh = hash_fragment_data(&data);
+                        if hsh == self_hash {
+                            return true;
+                        }
+                    }
+                }
+
+                return false;
+            }
+
+            (FragmentData::IndirectBlock(parent), FragmentData::FileDNode(_))
+            | (FragmentData::IndirectBlock(parent), FragmentData::DirectoryDNode(_, _)) => {
+                // Since indirect blocks have sizes that are multiples of 512 this is fine
+                let Some(parent_data) = parent.get_data_with_gaps(vdevs) else {
+                    return false;
+                };
+
+                return search_le_bytes_for_dnodes(&parent_data, vdevs, true)
+                    .iter()
+                    .any(|(hash, _)| *hash == self_hash);
+            }
+
+            (FragmentData::ObjSetDNode(parent), FragmentData::IndirectBlock(_us)) => {
+                for bptr in parent.metadnode.get_block_pointers().iter_mut() {
+                    if let Ok(data) = bptr.dereference(vdevs) {
+                        let hsh = hash_fragment_data(&data);
+                        if hsh == self_hash {
+                            return true;
+                        }
+                    }
+                }
+
+                return false;
+            }
+
+            (FragmentData::DirectoryDNode(parent, _), FragmentData::IndirectBlock(_us)) => {
+                for bptr in parent.0.get_block_pointers().iter_mut() {
+                    if let Ok(data) = bptr.dereference(vdevs) {
+                        let hsh = hash_fragment_data(&data);
+                        if hsh == self_hash {
+                            return true;
+                        }
+                    }
+                }
+
+                return false;
+            }
+
+            (FragmentData::FileDNode(parent), FragmentData::IndirectBlock(_us)) => {
+                for bptr in parent.0.get_block_pointers().iter_mut() {
+                    if let Ok(data) = bptr.dereference(vdevs) {
+                        let hsh = hash_fragment_data(&data);
+                        if hsh == self_hash {
+                            return true;
+                        }
+                    }
+                }
+
+                return false;
+            }
+
+            // Directories reference their children by object id, not by block pointer, so that's done separately in recovery::paths
+            (FragmentData::DirectoryDNode(_, _), FragmentData::FileDNode(_us)) => {
+                return false;
+            }
+            (FragmentData::DirectoryDNode(_, _), FragmentData::DirectoryDNode(_us, _)) => {
+                return false;
+            }
+
+            // The objset owns the indirect blocks which in turn own the file and directory dnodes
+            // So the objset doesn't need to directly own these types of fragments
+            (FragmentData::ObjSetDNode(_), FragmentData::FileDNode(_us)) => {
+                return false;
+            }
+            (FragmentData::ObjSetDNode(_), FragmentData::DirectoryDNode(_us, _)) => {
+                return false;
+            }
+
+            // A file can't have other file or directory children
+            (FragmentData::FileDNode(_), FragmentData::FileDNode(_us)) => {
+                return false;
+            }
+            (FragmentData::FileDNode(_), FragmentData::DirectoryDNode(_us, _)) => {
+                return false;
+            }
+
+            // Objsets don't have parents
+            (FragmentData::DirectoryDNode(_, _), FragmentData::ObjSetDNode(_us))
+            | (FragmentData::FileDNode(_), FragmentData::ObjSetDNode(_us))
+            | (FragmentData::ObjSetDNode(_), FragmentData::ObjSetDNode(_us))
+            | (FragmentData::IndirectBlock(_), FragmentData::ObjSetDNode(_us)) => {
+                return false;
+            }
+        }
+    }
+}
+
+impl From<FragmentData> for Fragment {
+    fn from(frag: FragmentData) -> Self {
+        Self {
+            data: frag,
+            children: HashSet::new(),
+        }
+    }
+}
+
+// Candidates that fail more checks than this are almost certainly junk that just happened to parse
+// NOTE: One failed check is allowed, as a dnode that was being changed when it was freed can be a bit off
+const MAX_FAILED_DNODE_SANITY_CHECKS: usize = 1;
+
+fn is_sane_dnode(sanity_score: usize) -> bool {
+    sanity_score + MAX_FAILED_DNODE_SANITY_CHECKS >= dmu::DNodeBase::MAX_SANITY_SCORE
+}
+
+// A dnode none of whose blocks can be read is of no use for recovery
+fn has_readable_block_pointer(dnode: &mut dmu::DNodeBase, vdevs: &mut Vdevs) -> bool {
+    dnode
+        .get_block_pointers()
+        .iter_mut()
+        .any(|bp| bp.dereference(vdevs).is_ok())
+}
+
+// Note: 'data' must be from a 512-byte aligned offset of the original device
+//       This is because of an optimization taking advantage of the fact that dva offsets are always multiples of 512 and a dnode "slot" is 512 bytes in size in the Objset
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L407 which uses SPA_MINBLOCKSHIFT and DVA_GET_OFFSET
+// SPA_MINBLOCKSHIFT and DVA_GET_OFFSET can be found at: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h#L1783 and https://github.com/openzfs/zfs/blob/master/include/sys/bitops.h#L66
+// As you can see SPA_MINBLOCKSHIFT is 9 and the macro shifts by 9
+// Thus proving that the current code is shifting the offset read from disk by 9
+// thus meaning that all DVA offsets are multiples of 512
+// If `search_objsets` is false only file and directory dnodes are searched for, which is quicker
+pub fn search_le_bytes_for_dnodes(
+    data: &[u8],
+    vdevs: &mut Vdevs,
+    search_objsets: bool,
+) -> HashMap<[u64; 4], Fragment> {
+    let mut res = HashMap::<[u64; 4], Fragment>::new();
+    if !data.len().is_multiple_of(512) && cfg!(feature = "verbose_debug") {
+        use crate::ansi_color::*;
+        println!("{YELLOW}Warning{WHITE}: Can't search data that is not a multiple of 512 bytes in size, ignoring {} extra bytes!", data.len()%512);
+    }
+
+    // A dnode (or objset) is always contiguous, so instead of copying every candidate into its own Vec
+    // we just parse sub slices of data
+    let data = &data[..data.len() - data.len() % 512];
+    for sector_offset in (0..data.len()).step_by(512) {
+        let sector = &data[sector_offset..sector_offset + 512];
+
+        // Try to parse objset
+        if search_objsets {
+            let objset_data = &data
+                [sector_offset..(sector_offset + dmu::ObjSet::get_ondisk_size()).min(data.len())];
+
+            let objset_data_hash = hash_fragment_data(objset_data);
+
+            // Note: This tries to parse it even if we don't have enough data, for a data recovery tool this seems like the better option
+            if let Some(mut objset) = dmu::ObjSet::from_slice_le(objset_data) {
+                if is_sane_dnode(objset.metadnode.get_sanity_score(false, None))
+                    && has_readable_block_pointer(&mut objset.metadnode, vdevs)
+                {
+                    res.insert(
+                        objset_data_hash,
+                        FragmentData::ObjSetDNode(Box::new(objset)).into(),
+                    );
+                }
+            };
+        }
+
+        // Try to parse file or directory dnode
+        let nsectors = dmu::DNode::get_n_slots_from_bytes_le(sector.iter().copied()).unwrap(); // NOTE: Unwrap should always succeed here, because we always have enough data
+
+        // NOTE: We always advance by only one sector
+        // so we don't accidentally ignore some sectors
+        // because we read an invalid nsectors from one sector
+        let dnode_data = &data[sector_offset..(sector_offset + nsectors * 512).min(data.len())];
+
+        let dnode_data_hash = hash_fragment_data(dnode_data);
+        // Note: This tries to parse it even if we don't have enough data, for a data recovery tool this seems like the better option
+        let mut dnode = dmu::DNode::from_slice_le(dnode_data);
+        if dnode
+            .as_mut()
+            .is_some_and(|dnode| !is_sane_dnode(dnode.get_sanity_score(None)))
+        {
+            continue;
+        }
+        match dnode {
+            Some(DNode::PlainFileContents(mut dnode)) => {
+                if !has_readable_block_pointer(&mut dnode.0, vdevs) {
+                    continue;
+                }
+                res.insert(dnode_data_hash, FragmentData::FileDNode(dnode).into());
+            }
+            Some(DNode::DirectoryContents(mut dnode)) => {
+                if !has_readable_block_pointer(&mut dnode.0, vdevs) {
+                    continue;
+                }
+                let Some(contents) = dnode.dump_zap_contents(vdevs) else {
+                    continue;
+                };
+                let contents = contents.keys().cloned().collect::<Vec<String>>();
+
+                res.insert(
+                    dnode_data_hash,
+                    FragmentData::DirectoryDNode(dnode, contents).into(),
+                );
+            }
+            _ => (),
+        }
+    }
+
+    res
+}
+
+// What type of fragment a child has to be, so we don't link a parent to a fragment of the wrong type that happens to have the same hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChildType {
+    // Children pointed to by a block pointer
+    IndirectBlock,
+    // Children contained inside of the data of an indirect block
+    FileOrDirectoryDNode,
+}
+
+impl FragmentData {
+    fn is_of_child_type(&self, child_type: ChildType) -> bool {
+        match child_type {
+            ChildType::IndirectBlock => matches!(self, FragmentData::IndirectBlock(_)),
+            ChildType::FileOrDirectoryDNode => matches!(
+                self,
+                FragmentData::FileDNode(_) | FragmentData::DirectoryDNode(_, _)
+            ),
+        }
+    }
+}
+
+// Returns: The hash of the data the block pointer points to
+// NOTE: The hashes of normal block pointers are cached by their checksum, so every block is only read once no matter how many fragments point to it
+fn dereference_hash(
+    bp: &mut zio::BlockPointer,
+    vdevs: &mut Vdevs,
+    dereferenced_hashes: &mut HashMap<[u64; 4], Option<[u64; 4]>>,
+) -> Option<[u64; 4]> {
+    let checksum = match bp {
+        zio::BlockPointer::Normal(normal_bp) => Some(normal_bp.get_checksum()),
+        zio::BlockPointer::Embedded(_) => None,
+    };
+
+    if let Some(hash) = checksum.and_then(|checksum| dereferenced_hashes.get(&checksum)) {
+        return *hash;
+    }
+
+    let hash = bp
+        .dereference(vdevs)
+        .ok()
+        .map(|data| hash_fragment_data(&data));
+    if let Some(checksum) = checksum {
+        dereferenced_hashes.insert(checksum, hash);
+    }
+    hash
+}
+
+impl Fragment {
+    // Returns: The hashes of all the fragments this fragment could be the parent of, this is the same relation as is_child_of
+    fn get_potential_children(
+        &mut self,
+        vdevs: &mut Vdevs,
+        dereferenced_hashes: &mut HashMap<[u64; 4], Option<[u64; 4]>>,
+    ) -> Vec<([u64; 4], ChildType)> {
+        let mut res = Vec::new();
+        let block_pointers = match &mut self.data {
+            FragmentData::IndirectBlock(indirect_block) => {
+                // Since indirect blocks have sizes that are multiples of 512 this is fine
+                if let Some(data) = indirect_block.get_data_with_gaps(vdevs) {
+                    res.extend(
+                        search_le_bytes_for_dnodes(&data, vdevs, false)
+                            .into_keys()
+                            .map(|hash| (hash, ChildType::FileOrDirectoryDNode)),
+                    );
+                }
+                indirect_block.bps.iter_mut().flatten().collect::<Vec<_>>()
+            }
+            FragmentData::ObjSetDNode(objset) => {
+                objset.metadnode.get_block_pointers().iter_mut().collect()
+            }
+            FragmentData::DirectoryDNode(dir, _) => dir.0.get_block_pointers().iter_mut().collect(),
+            FragmentData::FileDNode(file) => file.0.get_block_pointers().iter_mut().collect(),
+        };
+
+        for bp in block_pointers {
+            if let Some(hash) = dereference_hash(bp, vdevs, dereferenced_hashes) {
+                res.push((hash, ChildType::IndirectBlock));
+            }
+        }
+
+        res
+    }
+}
+
+// Returns: The roots of the graph
+pub fn build_graph(
+    nodes: &mut HashMap<[u64; 4], Fragment>,
+    vdevs: &mut Vdevs,
+) -> HashSet<[u64; 4]> {
+    // Instead of checking every pair of fragments (which dereferences the same block pointers over and over again)
+    // we figure out what each fragment points to once and look that up in the graph
+    let child_types = nodes
+        .iter()
+        .map(|(hash, fragment)| {
+            (
+                *hash,
+                [ChildType::IndirectBlock, ChildType::FileOrDirectoryDNode]
+                    .into_iter()
+                    .find(|child_type| fragment.data.is_of_child_type(*child_type)),
+            )
+        })
+        .collect::<HashMap<[u64; 4], Option<ChildType>>>();
+    let mut roots: HashSet<[u64; 4]> = nodes.keys().copied().collect::<_>();
+    let mut dereferenced_hashes = HashMap::<[u64; 4], Option<[u64; 4]>>::new();
+
+    let nnodes = nodes.len();
+    for (index, (hash, fragment)) in nodes.iter_mut().enumerate() {
+        println!(
+            "Figuring out children of node {}/{}, with hash: {:?}",
+            index + 1,
+            nnodes,
+            hash
+        );
+
+        // Children that were already known (ex. from expanding the fragment) are kept
+        for child_hash in fragment.children.iter() {
+            if child_types.contains_key(child_hash) {
+                roots.remove(child_hash);
+            }
+        }
+
+        for (child_hash, child_type) in
+            fragment.get_potential_children(vdevs, &mut dereferenced_hashes)
+        {
+            if child_hash == *hash || child_types.get(&child_hash) != Some(&Some(child_type)) {
+                continue;
+            }
+
+            fragment.children.insert(child_hash);
+            roots.remove(&child_hash); // The child has a parent so it's not a root
+        }
+    }
+
+    roots
+}
+
+// Returns fragments contained within the fragment to expand
+pub fn expand_fragment(
+    fragment_to_expand: &mut Fragment,
+    vdevs: &mut Vdevs,
+) -> Option<HashMap<[u64; 4], Fragment>> {
+    let mut subfragments = HashMap::<[u64; 4], Fragment>::new();
+    match &mut fragment_to_expand.data {
+        FragmentData::FileDNode(file) => {
+            for bp in file.0.get_block_pointers() {
+                if let Ok(data) = bp.dereference(vdevs) {
+                    if let Some(indirect_block) = IndirectBlock::from_bytes_le(&data, vdevs) {
+                        let hsh = hash_fragment_data(&data);
+                        subfragments
+                            .insert(hsh, FragmentData::IndirectBlock(indirect_block).into());
+                        fragment_to_expand.children.insert(hsh);
+                    }
+                }
+            }
+        }
+
+        FragmentData::DirectoryDNode(dir, _) => {
+            for bp in dir.0.get_block_pointers() {
+                if let Ok(data) = bp.dereference(vdevs) {
+                    if let Some(indirect_block) = IndirectBlock::from_bytes_le(&data, vdevs) {
+                        let hsh = hash_fragment_data(&data);
+                        subfragments
+                            .insert(hsh, FragmentData::IndirectBlock(indirect_block).into());
+                        fragment_to_expand.children.insert(hsh);
+                    }
+                }
+            }
+        }
+
+        FragmentData::ObjSetDNode(objset) => {
+            for bp in objset.metadnode.get_block_pointers() {
+                if let Ok(data) = bp.dereference(vdevs) {
+                    if let Some(indirect_block) = IndirectBlock::from_bytes_le(&data, vdevs) {
+                        let hsh = hash_fragment_data(&data);
+                        subfragments
+                            .insert(hsh, FragmentData::IndirectBlock(indirect_block).into());
+                        fragment_to_expand.children.insert(hsh);
+                    }
+                }
+            }
+        }
+
+        FragmentData::IndirectBlock(indir) => {
+            for bptr in indir.bps.iter_mut() {
+                if let Some(Ok(data)) = bptr.as_mut().map(|val| val.dereference(vdevs)) {
+                    if let Some(indirect_block) = IndirectBlock::from_bytes_le(&data, vdevs) {
+                        let hsh = hash_fragment_data(&data);
+                        subfragments
+                            .insert(hsh, FragmentData::IndirectBlock(indirect_block).into());
+                        fragment_to_expand.children.insert(hsh);
+                    }
+                }
+            }
+
+            if let Some(data) = indir.get_data_with_gaps(vdevs) {
+                subfragments.extend(search_le_bytes_for_dnodes(&data, vdevs, true));
+            }
+        }
+    }
+
+    let mut subsubfragments = HashMap::<_, _>::new();
+    for subfrag in subfragments.values_mut() {
+        if let Some(res) = expand_fragment(subfrag, vdevs) {
+            subsubfragments.extend(res);
+        }
+    }
+    subfragments.extend(subsubfragments);
+
+    Some(subfragments)
+}
+
+pub fn dump_graph_to_stdout(fragments: &mut HashMap<[u64; 4], Fragment>) {
+    println!("!!!Begin dump!!");
+    let mut hashes_to_info = HashMap::<[u64; 4], String>::new();
+
+    println!("Dumping id to hash mapping ...");
+    for (current_index, (hash, frag)) in fragments.iter().enumerate() {
+        match &frag.data {
+            FragmentData::DirectoryDNode(_, contents) => {
+                let mut dir_contents_str = String::new();
+                for file in contents {
+                    dir_contents_str += file;
+                    dir_contents_str += ", ";
+                }
+                dir_contents_str.pop();
+                dir_contents_str.pop();
+
+                println!(
+                    "\"{:?}{}({})\" -> {:?}",
+                    frag.data, current_index, dir_contents_str, hash
+                );
+                hashes_to_info.insert(
+                    *hash,
+                    format!("{:?}{}({})", frag.data, current_index, dir_contents_str),
+                );
+            }
+            _ => {
+                println!("\"{:?}{}\" -> {:?}", frag.data, current_index, hash);
+                hashes_to_info.insert(*hash, format!("{:?}{}", frag.data, current_index));
+            }
+        }
+    }
+    println!("Dumping graph using ids ...");
+    for (hash, fragment) in fragments.iter() {
+        for child_hash in fragment.children.iter() {
+            println!(
+                "\"{}\" -> \"{}\"",
+                hashes_to_info[hash], hashes_to_info[child_hash]
+            );
+        }
+
+        if fragment.children.is_empty() {
+            println!("\"{}\"", hashes_to_info[hash]);
+        }
+    }
+}
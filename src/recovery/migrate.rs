@@ -0,0 +1,340 @@
+// Checkpoints and fragment stores are serialized with bincode, which has no field names or tags, so when the shape of
+// anything in them changes the files written before can't be read anymore, and a scan that ran for days would have to start over
+// So every change to the shape of what's in them bumps SCHEMA_VERSION, and the old shapes are kept here to convert the old files
+// Versions:
+//     1: Everything from before the version was tracked
+//     2: Checksum and compression methods are their on disk value in a u8, they were the index of the variant in a u32 (see zio::deserialize_method)
+//     3: ObjSet got flags, portable_mac, local_mac, userused, groupused and projectused
+// NOTE: The json checkpoints undelete used to write are self describing and the new fields have serde defaults, so they are read as they are
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    dmu::{self, DNodeBase, DNodeDirectoryContents, DNodePlainFileContents, ObjSet, ObjSetType},
+    recovery::fragment::{Fragment, FragmentData, IndirectBlock},
+    zil::ZilHeader,
+};
+
+pub const SCHEMA_VERSION: u64 = 3;
+pub const FIRST_SCHEMA_VERSION: u64 = 1;
+
+// The shapes of version 1, they have to stay exactly like they were, including the order of the fields and variants
+// They are converted by serializing them again in the encoding of version 2 (the methods as u8s), which is then read like a file of version 2
+mod v1 {
+    use super::*;
+    use crate::{
+        dmu::ObjType,
+        zio::{self, DataVirtualAddress},
+    };
+
+    // The index of the variant is the on disk value
+    #[derive(Deserialize, Clone, Copy)]
+    pub enum ChecksumMethod {
+        Inherit,
+        On,
+        Off,
+        Label,
+        GangHeader,
+        Zilog,
+        Fletcher2,
+        Fletcher4,
+        Sha256,
+        Zilog2,
+        NoParity,
+        Sha512,
+        Skein,
+        Edonr,
+        Blake3,
+    }
+
+    impl Serialize for ChecksumMethod {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            zio::ChecksumMethod::from_value(*self as usize)
+                .unwrap()
+                .serialize(serializer)
+        }
+    }
+
+    // The index of the variant is the on disk value
+    #[derive(Deserialize, Clone, Copy)]
+    pub enum CompressionMethod {
+        Inherit,
+        On,
+        Off,
+        Lzjb,
+        Empty,
+        Gzip1,
+        Gzip2,
+        Gzip3,
+        Gzip4,
+        Gzip5,
+        Gzip6,
+        Gzip7,
+        Gzip8,
+        Gzip9,
+        Zle,
+        Lz4,
+        Zstd,
+    }
+
+    impl Serialize for CompressionMethod {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            zio::CompressionMethod::from_value(*self as usize)
+                .unwrap()
+                .serialize(serializer)
+        }
+    }
+
+    // SpaHistoryOffsets was put in front of SystemAttributes while the version wasn't tracked yet, so SystemAttributes can be at both indexes
+    // the one at 6 could also be SpaHistoryOffsets, but only the pool history has that and fragments are files, directories and objsets
+    #[derive(Deserialize, Clone, Copy)]
+    pub enum BonusType {
+        None,
+        PackedNVListSize,
+        SpaceMapHeader,
+        DSLDirectory,
+        DSLDataset,
+        ZNode,
+        SystemAttributes,
+        MovedSystemAttributes,
+        NewUInt64Metadata,
+    }
+
+    impl Serialize for BonusType {
+        fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let bonus_type = match self {
+                BonusType::None => dmu::BonusType::None,
+                BonusType::PackedNVListSize => dmu::BonusType::PackedNVListSize,
+                BonusType::SpaceMapHeader => dmu::BonusType::SpaceMapHeader,
+                BonusType::DSLDirectory => dmu::BonusType::DSLDirectory,
+                BonusType::DSLDataset => dmu::BonusType::DSLDataset,
+                BonusType::ZNode => dmu::BonusType::ZNode,
+                BonusType::SystemAttributes | BonusType::MovedSystemAttributes => {
+                    dmu::BonusType::SystemAttributes
+                }
+                BonusType::NewUInt64Metadata => dmu::BonusType::NewUInt64Metadata,
+            };
+            bonus_type.serialize(serializer)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct NormalBlockPointer {
+        dvas: [Option<DataVirtualAddress>; 3],
+        level: usize,
+        fill: u64,
+        logical_birth_txg: u64,
+        typ: ObjType,
+        checksum_method: ChecksumMethod,
+        compression_method: CompressionMethod,
+        physical_size_in_512b_sectors_minus_one: u16,
+        logical_size_in_512b_sectors_minus_one: u16,
+        checksum: [u64; 4],
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct EmbeddedBlockPointer {
+        payload: Vec<u8>,
+        logical_birth_txg: u64,
+        level: usize,
+        typ: ObjType,
+        embedded_data_type: zio::EmbeddedType,
+        compression_method: CompressionMethod,
+        physical_size_in_bytes: u8,
+        logical_size_in_bytes: u32,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum BlockPointer {
+        Normal(NormalBlockPointer),
+        Embedded(EmbeddedBlockPointer),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ZilHeader {
+        claim_txg: u64,
+        highest_replayed_seq_number: u64,
+        log: BlockPointer,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct DNodeBase {
+        indirect_blocksize_log2: u8,
+        n_indirect_levels: u8,
+        checksum_method: ChecksumMethod,
+        compression_method: CompressionMethod,
+        data_blocksize_in_512b_sectors: u16,
+        num_slots: u8,
+        max_indirect_block_id: u64,
+        total_allocated: u64,
+        total_allocated_is_in_bytes: bool,
+        block_pointers: Vec<BlockPointer>,
+        bonus_data: Vec<u8>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct DNodePlainFileContents(DNodeBase, BonusType);
+
+    #[derive(Serialize, Deserialize)]
+    pub struct DNodeDirectoryContents(DNodeBase, BonusType);
+
+    #[derive(Serialize, Deserialize)]
+    pub struct IndirectBlock {
+        bps: Vec<Option<BlockPointer>>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ObjSet {
+        metadnode: DNodeBase,
+        zil: Option<ZilHeader>,
+        typ: ObjSetType,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub enum FragmentData {
+        FileDNode(DNodePlainFileContents),
+        DirectoryDNode(DNodeDirectoryContents, Vec<String>),
+        ObjSetDNode(ObjSet),
+        IndirectBlock(IndirectBlock),
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct Fragment {
+        data: FragmentData,
+        children: std::collections::HashSet<[u64; 4]>,
+    }
+
+    // Returns: The data of version 1, in the encoding of version 2, if it's a T
+    pub fn upgrade<T: Serialize + DeserializeOwned>(data: &[u8]) -> Result<Vec<u8>, ()> {
+        let value: T = bincode::deserialize(data).map_err(|_| ())?;
+        bincode::serialize(&value).map_err(|_| ())
+    }
+}
+
+// The shapes of version 2, they have to stay exactly like they were, including the order of the fields and variants
+mod v2 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct ObjSet {
+        pub metadnode: DNodeBase,
+        pub zil: Option<ZilHeader>,
+        pub typ: ObjSetType,
+    }
+
+    #[derive(Deserialize)]
+    pub enum FragmentData {
+        FileDNode(DNodePlainFileContents),
+        DirectoryDNode(DNodeDirectoryContents, Vec<String>),
+        ObjSetDNode(ObjSet),
+        IndirectBlock(IndirectBlock),
+    }
+
+    #[derive(Deserialize)]
+    pub struct Fragment {
+        pub data: FragmentData,
+        pub children: std::collections::HashSet<[u64; 4]>,
+    }
+}
+
+impl From<v2::ObjSet> for ObjSet {
+    fn from(objset: v2::ObjSet) -> ObjSet {
+        ObjSet {
+            metadnode: objset.metadnode,
+            zil: objset.zil,
+            typ: objset.typ,
+            flags: 0,
+            portable_mac: [0u8; 32],
+            local_mac: [0u8; 32],
+            userused: None,
+            groupused: None,
+            projectused: None,
+        }
+    }
+}
+
+impl From<v2::FragmentData> for FragmentData {
+    fn from(data: v2::FragmentData) -> FragmentData {
+        match data {
+            v2::FragmentData::FileDNode(file) => FragmentData::FileDNode(file),
+            v2::FragmentData::DirectoryDNode(directory, names) => {
+                FragmentData::DirectoryDNode(directory, names)
+            }
+            v2::FragmentData::ObjSetDNode(objset) => {
+                FragmentData::ObjSetDNode(Box::new(objset.into()))
+            }
+            v2::FragmentData::IndirectBlock(indirect_block) => {
+                FragmentData::IndirectBlock(indirect_block)
+            }
+        }
+    }
+}
+
+impl From<v2::Fragment> for Fragment {
+    fn from(fragment: v2::Fragment) -> Fragment {
+        Fragment {
+            data: fragment.data.into(),
+            children: fragment.children,
+        }
+    }
+}
+
+// Returns: Err if the version is newer than this build knows about, then nothing can be done with the data
+fn check_version(version: u64) -> Result<(), ()> {
+    if !(FIRST_SCHEMA_VERSION..=SCHEMA_VERSION).contains(&version) {
+        return Err(());
+    }
+    Ok(())
+}
+
+// The types checkpoint segments are lists of
+pub trait CheckpointEntry: Serialize + DeserializeOwned {
+    // Returns: A list of entries that was serialized with bincode by this schema version, in the current shape
+    // The default is for types whose shape didn't change since the first version
+    fn migrate_list(version: u64, data: &[u8]) -> Result<Vec<Self>, ()> {
+        check_version(version)?;
+        bincode::deserialize(data).map_err(|_| ())
+    }
+}
+
+// The offsets find-block-with-checksum finds
+impl CheckpointEntry for u64 {}
+
+impl CheckpointEntry for ([u64; 4], Fragment) {
+    fn migrate_list(version: u64, data: &[u8]) -> Result<Vec<Self>, ()> {
+        check_version(version)?;
+        let upgraded_data;
+        let data = if version == 1 {
+            upgraded_data = v1::upgrade::<Vec<([u64; 4], v1::Fragment)>>(data)?;
+            &upgraded_data[..]
+        } else {
+            data
+        };
+        if version <= 2 {
+            let entries: Vec<([u64; 4], v2::Fragment)> =
+                bincode::deserialize(data).map_err(|_| ())?;
+            return Ok(entries
+                .into_iter()
+                .map(|(hash, fragment)| (hash, fragment.into()))
+                .collect());
+        }
+        bincode::deserialize(data).map_err(|_| ())
+    }
+}
+
+// Same as CheckpointEntry::migrate_list, for the data of a single fragment (ex. in the fragment store)
+pub fn migrate_fragment_data(version: u64, data: &[u8]) -> Result<FragmentData, ()> {
+    check_version(version)?;
+    let upgraded_data;
+    let data = if version == 1 {
+        upgraded_data = v1::upgrade::<v1::FragmentData>(data)?;
+        &upgraded_data[..]
+    } else {
+        data
+    };
+    if version <= 2 {
+        let data: v2::FragmentData = bincode::deserialize(data).map_err(|_| ())?;
+        return Ok(data.into());
+    }
+    bincode::deserialize(data).map_err(|_| ())
+}
@@ -0,0 +1,15 @@
+// Building blocks for the brute force recovery tools (undelete, recover, surgeon, ...)
+// that look for zfs structures on disk without going through a (working) uberblock
+
+pub mod block_index;
+pub mod checkpoint;
+pub mod control;
+pub mod export;
+pub mod fragment;
+pub mod migrate;
+pub mod paths;
+pub mod scan;
+pub mod select;
+#[cfg(feature = "sqlite")]
+pub mod store;
+pub mod surgeon;
@@ -0,0 +1,233 @@
+// Best effort reconstruction of the directory structure of the recovered fragments
+// Directories don't point to their children with block pointers, their zap maps names to object ids instead
+// So to put files in directories we need the object ids of the recovered dnodes, which we get by walking the meta dnodes of the recovered objsets
+// A dnode's object id is its position in the meta dnode: block id * (dnodes per block) + slot
+// NOTE: Every objset has its own object ids, so paths are only resolved inside of one objset
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dmu::{DNode, ObjSet},
+    recovery::fragment::{hash_fragment_data, Fragment, FragmentData},
+    zap,
+    zio::Vdevs,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub hash: [u64; 4],
+    pub is_directory: bool,
+    // All the object ids the dnode was found at (in any of the recovered objsets)
+    pub object_ids: Vec<u64>,
+    // All the paths the dnode was found at, a path starting with ?<object id> is relative to a directory we don't know the path of
+    // NOTE: This is empty if the object id of the dnode couldn't be figured out or no recovered directory contains it
+    pub paths: Vec<String>,
+}
+
+// Returns: The object ids of the file and directory dnodes in `dnode_hashes` that are part of the objset
+fn get_dnode_object_ids(
+    objset: &mut ObjSet,
+    dnode_hashes: &HashSet<[u64; 4]>,
+    vdevs: &mut Vdevs,
+) -> HashMap<u64, [u64; 4]> {
+    let mut res = HashMap::new();
+    let dnodes_per_block = objset.metadnode.parse_data_block_size() / 512;
+    for block_id in 0..=objset.metadnode.get_max_indirect_block_id() as usize {
+        let Ok(block_data) = objset.metadnode.read_block(block_id, vdevs) else {
+            continue;
+        };
+
+        // This has to hash the exact same data search_le_bytes_for_dnodes hashes, so it also advances one slot at a time
+        // NOTE: A short (ex. corrupted) block only has the slots that fit in it, the object ids still go by the block size
+        for slot in 0..dnodes_per_block.min(block_data.len() / 512) {
+            let sector = &block_data[slot * 512..];
+            let Some(nslots) = DNode::get_n_slots_from_bytes_le(sector.iter().copied()) else {
+                continue;
+            };
+            let dnode_data = &sector[..(nslots * 512).min(sector.len())];
+            let hash = hash_fragment_data(dnode_data);
+            if dnode_hashes.contains(&hash) {
+                res.insert((block_id * dnodes_per_block + slot) as u64, hash);
+            }
+        }
+    }
+    res
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h (MASTER_NODE_OBJ, ZFS_ROOT_OBJ)
+fn get_root_directory_object_id(objset: &mut ObjSet, vdevs: &mut Vdevs) -> Option<u64> {
+    let DNode::MasterNode(mut master_node) = objset.get_dnode_at(1, vdevs)? else {
+        return None;
+    };
+    let Some(zap::Value::U64(root_number)) = master_node.dump_zap_contents(vdevs)?.remove("ROOT")
+    else {
+        return None;
+    };
+    Some(root_number)
+}
+
+// Returns: The entries of the directory as (name, object id) pairs
+fn get_directory_entries(fragment: &mut Fragment, vdevs: &mut Vdevs) -> Vec<(String, u64)> {
+    let FragmentData::DirectoryDNode(dir, _) = &mut fragment.data else {
+        return Vec::new();
+    };
+    let Some(contents) = dir.dump_zap_contents(vdevs) else {
+        return Vec::new();
+    };
+
+    contents
+        .into_iter()
+        .filter_map(|(name, value)| {
+            let zap::Value::U64(value) = value else {
+                return None;
+            };
+            // Only bottom 48 bits are the actual object id
+            // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h#L152
+            Some((name, value & ((1 << 48) - 1)))
+        })
+        .collect()
+}
+
+// Returns: The path of the object by following the parents up, or None if the object isn't in any directory
+fn resolve_path(
+    object_id: u64,
+    root_directory_object_id: Option<u64>,
+    parents: &HashMap<u64, (u64, String)>,
+) -> Option<String> {
+    let mut components = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = object_id;
+    loop {
+        if Some(current) == root_directory_object_id {
+            components.reverse();
+            return Some(format!("/{}", components.join("/")));
+        }
+
+        // A directory can't (indirectly) contain itself, but recovered directories could be from different txgs
+        if !visited.insert(current) {
+            return None;
+        }
+
+        let Some((parent, name)) = parents.get(&current) else {
+            if components.is_empty() {
+                return None;
+            }
+            components.reverse();
+            return Some(format!("?{}/{}", current, components.join("/")));
+        };
+
+        components.push(name.clone());
+        current = *parent;
+    }
+}
+
+// Figures out the paths of all the recovered file and directory dnodes, through all the recovered objsets
+pub fn build_path_manifest(
+    nodes: &mut HashMap<[u64; 4], Fragment>,
+    vdevs: &mut Vdevs,
+) -> Vec<ManifestEntry> {
+    let dnode_hashes = nodes
+        .iter()
+        .filter(|(_, fragment)| {
+            matches!(
+                fragment.data,
+                FragmentData::FileDNode(_) | FragmentData::DirectoryDNode(_, _)
+            )
+        })
+        .map(|(hash, _)| *hash)
+        .collect::<HashSet<[u64; 4]>>();
+    let objset_hashes = nodes
+        .iter()
+        .filter(|(_, fragment)| matches!(fragment.data, FragmentData::ObjSetDNode(_)))
+        .map(|(hash, _)| *hash)
+        .collect::<Vec<[u64; 4]>>();
+
+    let mut paths = HashMap::<[u64; 4], HashSet<String>>::new();
+    let mut found_object_ids = HashMap::<[u64; 4], HashSet<u64>>::new();
+    // The same version of a directory is usually part of multiple versions of an objset, so only read its zap once
+    let mut directory_entries = HashMap::<[u64; 4], Vec<(String, u64)>>::new();
+    for (index, objset_hash) in objset_hashes.iter().enumerate() {
+        println!(
+            "Resolving paths in objset {}/{}, with hash: {:?}",
+            index + 1,
+            objset_hashes.len(),
+            objset_hash
+        );
+
+        let FragmentData::ObjSetDNode(objset) = &mut nodes.get_mut(objset_hash).unwrap().data
+        else {
+            unreachable!();
+        };
+        let object_ids = get_dnode_object_ids(objset, &dnode_hashes, vdevs);
+        if object_ids.is_empty() {
+            continue;
+        }
+        let root_directory_object_id = get_root_directory_object_id(objset, vdevs);
+
+        let mut parents = HashMap::<u64, (u64, String)>::new();
+        for (object_id, hash) in object_ids.iter() {
+            let entries = directory_entries
+                .entry(*hash)
+                .or_insert_with(|| get_directory_entries(nodes.get_mut(hash).unwrap(), vdevs));
+            for (name, child_object_id) in entries.iter() {
+                parents.insert(*child_object_id, (*object_id, name.clone()));
+            }
+        }
+
+        for (object_id, hash) in object_ids.iter() {
+            found_object_ids
+                .entry(*hash)
+                .or_default()
+                .insert(*object_id);
+            if let Some(path) = resolve_path(*object_id, root_directory_object_id, &parents) {
+                paths.entry(*hash).or_default().insert(path);
+            }
+        }
+    }
+
+    let mut manifest = dnode_hashes
+        .into_iter()
+        .map(|hash| {
+            let mut dnode_paths = paths
+                .remove(&hash)
+                .unwrap_or_default()
+                .into_iter()
+                .collect::<Vec<String>>();
+            dnode_paths.sort_unstable();
+            let mut object_ids = found_object_ids
+                .remove(&hash)
+                .unwrap_or_default()
+                .into_iter()
+                .collect::<Vec<u64>>();
+            object_ids.sort_unstable();
+            ManifestEntry {
+                hash,
+                object_ids,
+                is_directory: matches!(nodes[&hash].data, FragmentData::DirectoryDNode(_, _)),
+                paths: dnode_paths,
+            }
+        })
+        .collect::<Vec<ManifestEntry>>();
+    manifest.sort_unstable_by(|a, b| a.paths.cmp(&b.paths));
+    manifest
+}
+
+// The manifest undelete writes, it's read by the tools that select files by object id
+pub const PATH_MANIFEST_FILE_NAME: &str = "undelete-manifest.json";
+
+pub fn write_path_manifest(path: &Path, manifest: &[ManifestEntry]) -> Result<(), ()> {
+    let file = File::create(path).map_err(|_| ())?;
+    serde_json::to_writer_pretty(BufWriter::new(file), manifest).map_err(|_| ())
+}
+
+pub fn read_path_manifest(path: &Path) -> Result<Vec<ManifestEntry>, ()> {
+    let file = File::open(path).map_err(|_| ())?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|_| ())
+}
@@ -0,0 +1,286 @@
+// Describes how to brute force scan a disk for blocks we don't have the block pointers of
+// Since we don't know the size or compression of a block (if there is any) at an offset, we have to guess
+// What to guess depends a lot on the pool (recordsize, compression, ...) so it's configurable
+
+use std::ops::{Range, RangeInclusive};
+
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    recovery::control::{CancellationToken, Completion},
+    zio::{self, CompressionMethod, DataVirtualAddress, Vdevs},
+};
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L81 (SPA_MAXBLOCKSIZE)
+const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionGuess {
+    pub compression_method: CompressionMethod,
+    // Physical (on disk) sizes to try, in bytes
+    pub psizes: Vec<usize>,
+    // Logical (decompressed) sizes to try, in bytes, this is irrelevant for lz4 and uncompressed blocks
+    pub lsizes: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScanProfile {
+    // Only tries the sizes that compressed indirect blocks and dnode blocks usually have, this is what undelete used to do
+    MetadataOnly,
+    // Tries every possible psize for all supported compression methods, this is *a lot* slower
+    Exhaustive,
+}
+
+impl ScanProfile {
+    pub fn from_name(name: &str) -> Option<ScanProfile> {
+        Some(match name {
+            "metadata" | "metadata-only" => ScanProfile::MetadataOnly,
+            "exhaustive" => ScanProfile::Exhaustive,
+            _ => return None,
+        })
+    }
+}
+
+impl std::str::FromStr for ScanProfile {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ScanProfile, String> {
+        ScanProfile::from_name(name).ok_or(format!(
+            "Unknown scan profile {name}, it should be metadata or exhaustive"
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanConfig {
+    pub guesses: Vec<CompressionGuess>,
+    // Distance between offsets that are tried, in bytes
+    // NOTE: DVA offsets are always multiples of 512 so there is no point in making this smaller
+    pub step_size: u64,
+    // Which parts of the disk to scan
+    pub offset_ranges: Vec<Range<u64>>,
+    // If the data at an offset is an lz4 block then it starts with the compressed size, so we can guess the psize from that
+    pub guess_lz4_psize_from_header: bool,
+    // Only keep what was found if it was born in these txgs, ex. the txgs just before the files were deleted, to skip ancient freed blocks
+    // NOTE: Older configs don't have it, so it defaults to keeping everything
+    #[serde(default)]
+    pub birth_txgs: Option<RangeInclusive<u64>>,
+}
+
+impl ScanConfig {
+    pub fn from_profile(profile: ScanProfile, disk_size: u64) -> ScanConfig {
+        match profile {
+            ScanProfile::MetadataOnly => Self::metadata_only(disk_size),
+            ScanProfile::Exhaustive => Self::exhaustive(disk_size),
+        }
+    }
+
+    pub fn metadata_only(disk_size: u64) -> ScanConfig {
+        ScanConfig {
+            // The sizes are just the most common sizes i have seen while looking at the sizes of compressed indirect blocks
+            guesses: vec![CompressionGuess {
+                compression_method: CompressionMethod::Lz4,
+                psizes: vec![512 * 2, 512 * 3, 512 * 8, 512 * 21, 512 * 24, 512 * 256],
+                lsizes: vec![0],
+            }],
+            step_size: 512,
+            offset_ranges: vec![Range {
+                start: 0,
+                end: disk_size,
+            }],
+            guess_lz4_psize_from_header: true,
+            birth_txgs: None,
+        }
+    }
+
+    pub fn exhaustive(disk_size: u64) -> ScanConfig {
+        // Up to the default recordsize, bigger blocks are rare enough that they should be added by hand
+        let all_psizes = (1..=256).map(|nsectors| nsectors * 512).collect::<Vec<_>>();
+        let common_lsizes = (12..=17).map(|shift| 1 << shift).collect::<Vec<_>>();
+        ScanConfig {
+            guesses: vec![
+                CompressionGuess {
+                    compression_method: CompressionMethod::Lz4,
+                    psizes: all_psizes.clone(),
+                    lsizes: vec![0],
+                },
+                CompressionGuess {
+                    compression_method: CompressionMethod::Lzjb,
+                    psizes: all_psizes,
+                    lsizes: common_lsizes.clone(),
+                },
+                CompressionGuess {
+                    compression_method: CompressionMethod::Off,
+                    psizes: common_lsizes,
+                    lsizes: vec![0],
+                },
+            ],
+            step_size: 512,
+            offset_ranges: vec![Range {
+                start: 0,
+                end: disk_size,
+            }],
+            guess_lz4_psize_from_header: true,
+            birth_txgs: None,
+        }
+    }
+
+    // Removes everything before `offset` from the offset ranges, ex. because it was already scanned before resuming from a checkpoint
+    pub fn skip_before(&mut self, offset: u64) {
+        for range in self.offset_ranges.iter_mut() {
+            range.start = range.start.max(offset);
+        }
+        self.offset_ranges.retain(|range| range.start < range.end);
+    }
+
+    // Only keeps the parts of the offset ranges that are also in `ranges`, ex. to only scan the free space of the pool
+    // NOTE: `ranges` must be sorted
+    pub fn restrict_to(&mut self, ranges: &[Range<u64>]) {
+        let mut restricted_ranges = Vec::new();
+        for range in self.offset_ranges.iter() {
+            for allowed_range in ranges.iter() {
+                let start = range.start.max(allowed_range.start);
+                let end = range.end.min(allowed_range.end);
+                if start < end {
+                    restricted_ranges.push(start..end);
+                }
+            }
+        }
+        self.offset_ranges = restricted_ranges;
+    }
+
+    // Returns: All offsets that should be tried, in order
+    pub fn get_offsets(&self) -> impl Iterator<Item = u64> + '_ {
+        self.offset_ranges
+            .iter()
+            .flat_map(|range| self.get_offsets_in(range.clone()))
+    }
+
+    // Returns: The offsets in `range` that should be tried, in order
+    pub fn get_offsets_in(&self, range: Range<u64>) -> impl Iterator<Item = u64> {
+        let first_offset = range.start.div_ceil(self.step_size) * self.step_size;
+        (first_offset..range.end).step_by(self.step_size as usize)
+    }
+
+    // Splits the offset ranges into chunks of at most `chunk_size` bytes
+    pub fn get_chunks(&self, chunk_size: u64) -> Vec<Range<u64>> {
+        let mut chunks = Vec::new();
+        for range in self.offset_ranges.iter() {
+            let mut chunk_start = range.start;
+            while chunk_start < range.end {
+                let chunk_end = (chunk_start + chunk_size).min(range.end);
+                chunks.push(chunk_start..chunk_end);
+                chunk_start = chunk_end;
+            }
+        }
+        chunks
+    }
+
+    // Returns: The total amount of bytes covered by the offset ranges
+    pub fn get_scan_size(&self) -> u64 {
+        self.offset_ranges
+            .iter()
+            .map(|range| range.end.saturating_sub(range.start))
+            .sum()
+    }
+
+    // Returns: If something born in `birth_txg` should be kept, if the birth txg isn't known it's kept
+    pub fn keeps_birth_txg(&self, birth_txg: Option<u64>) -> bool {
+        match (&self.birth_txgs, birth_txg) {
+            (Some(birth_txgs), Some(birth_txg)) => birth_txgs.contains(&birth_txg),
+            _ => true,
+        }
+    }
+
+    // Returns: The decompressed data of every guess that could be read at the offset
+    // NOTE: Decompression errors are ignored and the partial data is returned instead, for a data recovery tool this seems like the better option
+    pub fn read_candidate_blocks(&self, offset: u64, vdevs: &mut Vdevs) -> Vec<Vec<u8>> {
+        // NOTE: Currently asize is just not used even though it's part of the data structure, because we read it form disk
+        let dva = DataVirtualAddress::from(0, offset, false);
+        let mut res = Vec::new();
+        for guess in self.guesses.iter() {
+            let mut psizes = guess.psizes.clone();
+            if self.guess_lz4_psize_from_header
+                && matches!(
+                    guess.compression_method,
+                    CompressionMethod::Lz4 | CompressionMethod::On
+                )
+            {
+                if let Some(psize) = guess_lz4_psize(&dva, vdevs) {
+                    if !psizes.contains(&psize) {
+                        psizes.push(psize);
+                    }
+                }
+            }
+
+            for psize in psizes {
+                let Ok(data) = dva.dereference_borrowed(vdevs, psize) else {
+                    continue;
+                };
+
+                for &lsize in guess.lsizes.iter() {
+                    res.push(
+                        zio::try_decompress_block(&data, guess.compression_method, lsize)
+                            .unwrap_or_else(|partial_data| partial_data),
+                    );
+                }
+            }
+        }
+        res
+    }
+}
+
+// The data of an lz4 compressed block starts with the size of the compressed stream as a big endian 32 bit int
+// and the psize is that rounded up to a multiple of 512
+fn guess_lz4_psize(dva: &DataVirtualAddress, vdevs: &mut Vdevs) -> Option<usize> {
+    let first_sector = dva.dereference_borrowed(vdevs, 512).ok()?;
+    let comp_size = u32::from_be_bytes(first_sector.get(0..4)?.try_into().unwrap()) as usize;
+    let psize = (comp_size + 4).div_ceil(512) * 512;
+    if psize > MAX_BLOCK_SIZE {
+        return None;
+    }
+    Some(psize)
+}
+
+// Scans the offset ranges of the config in parallel, in chunks of `chunk_size` bytes
+// Vdevs can't be shared between threads, so every worker gets its own state (ex. its own file handles and caches) from `init_worker`
+// The results of the chunks are passed to `on_chunk_scanned` in the order of their offsets,
+// so a checkpoint saved from there will always cover everything before the end of the chunk
+// When `cancel` is cancelled no new chunks are started, the ones that already started are finished
+// and passed on up to the first one that wasn't scanned, so the checkpoint stays consistent
+pub fn parallel_scan<W, T: Send>(
+    config: &ScanConfig,
+    chunk_size: u64,
+    cancel: &CancellationToken,
+    init_worker: impl Fn() -> W + Sync + Send,
+    scan_chunk: impl Fn(&mut W, Range<u64>) -> T + Sync + Send,
+    mut on_chunk_scanned: impl FnMut(Range<u64>, T),
+) -> Completion {
+    let chunks = config.get_chunks(chunk_size);
+    // Only a few chunks are scanned at the same time so the results of chunks
+    // that finished early don't pile up in memory while waiting for an earlier chunk
+    for batch in chunks.chunks(rayon::current_num_threads() * 2) {
+        if cancel.is_cancelled() {
+            return Completion::Cancelled;
+        }
+
+        let results = batch
+            .par_iter()
+            .map_init(&init_worker, |worker, chunk| {
+                if cancel.is_cancelled() {
+                    return None;
+                }
+                Some(scan_chunk(worker, chunk.clone()))
+            })
+            .collect::<Vec<Option<T>>>();
+
+        for (chunk, result) in batch.iter().zip(results) {
+            let Some(result) = result else {
+                return Completion::Cancelled;
+            };
+            on_chunk_scanned(chunk.clone(), result);
+        }
+    }
+    Completion::Finished
+}
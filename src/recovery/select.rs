@@ -0,0 +1,248 @@
+// Picks the recovered file dnodes that belong to the file that should be recovered
+// Recovered file dnodes don't know their own name, so files have to be picked by what we know about them,
+// ex. the size, timestamps and parent directory from the bonus buffer, the object id (from the path manifest) or the hash of the dnode
+// NOTE: Multiple versions of the same file will usually match, which is fine since recover merges them
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+};
+
+use crate::{
+    cli,
+    dmu::{BonusType, DNodePlainFileContents},
+    recovery::{
+        checkpoint,
+        fragment::{Fragment, FragmentData},
+        paths::{self, ManifestEntry},
+    },
+    zpl,
+};
+
+// The file fragments of the undelete checkpoints, written by filter-checkpoints
+pub const FILTERED_CHECKPOINT_FILE_NAME: &str = "undelete-filtered.ckpt";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub mode: u64,
+    pub size: u64,
+    pub uid: u64,
+    pub gid: u64,
+    pub parent: u64,
+    // Only the seconds part of the timestamps
+    pub atime: u64,
+    pub mtime: u64,
+    pub ctime: u64,
+    pub crtime: u64,
+}
+
+fn read_u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().unwrap(),
+    ))
+}
+
+impl FileAttributes {
+    // Without the system attributes registry of the dataset we can't know the layout of the attributes for sure
+    // so this assumes the layout zfs uses for plain files (MODE, SIZE, GEN, UID, GID, PARENT, FLAGS, ATIME, MTIME, CTIME, CRTIME, ...)
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zfs_znode.c (zfs_mknode, the sa_attrs array for new files)
+    // Legacy znode bonus buffers have a fixed layout, so they aren't a guess, see zpl::Znode
+    pub fn guess_from_dnode(file: &DNodePlainFileContents) -> Option<FileAttributes> {
+        FileAttributes::guess_from_bonus_data(file.0.get_bonus_data(), &file.1)
+    }
+
+    // Same as guess_from_dnode, for the dnodes of directories (or anything else with zpl attributes)
+    pub fn guess_from_bonus_data(bonus: &[u8], bonus_type: &BonusType) -> Option<FileAttributes> {
+        match bonus_type {
+            BonusType::SystemAttributes => {
+                let magic = u32::from_le_bytes(bonus.get(0..4)?.try_into().unwrap());
+                if magic != zpl::SYSTEM_ATTRIBUTES_MAGIC {
+                    return None;
+                }
+                let layout_info = u16::from_le_bytes(bonus.get(4..6)?.try_into().unwrap());
+                let header_size = usize::from((layout_info >> 10) & 0b1111_11) * 8;
+                Some(FileAttributes {
+                    mode: read_u64_at(bonus, header_size)?,
+                    size: read_u64_at(bonus, header_size + 8)?,
+                    uid: read_u64_at(bonus, header_size + 3 * 8)?,
+                    gid: read_u64_at(bonus, header_size + 4 * 8)?,
+                    parent: read_u64_at(bonus, header_size + 5 * 8)?,
+                    atime: read_u64_at(bonus, header_size + 7 * 8)?,
+                    mtime: read_u64_at(bonus, header_size + 9 * 8)?,
+                    ctime: read_u64_at(bonus, header_size + 11 * 8)?,
+                    crtime: read_u64_at(bonus, header_size + 13 * 8)?,
+                })
+            }
+            BonusType::ZNode => {
+                let znode = zpl::Znode::from_bonus_data(bonus)?;
+                Some(FileAttributes {
+                    mode: znode.mode,
+                    size: znode.size,
+                    uid: znode.uid,
+                    gid: znode.gid,
+                    parent: znode.parent,
+                    atime: znode.atime[0],
+                    mtime: znode.mtime[0],
+                    ctime: znode.ctime[0],
+                    crtime: znode.crtime[0],
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// Every filter that is set has to match, a selection with no filters matches every file
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct FileSelection {
+    /// Only files with a size in this range, in bytes, either side can be left out (ex. 1024: for at least 1KiB)
+    #[arg(long, value_name = "MIN:MAX", value_parser = parse_range_arg)]
+    pub size: Option<RangeInclusive<u64>>,
+    /// Only files created in this range, in seconds since the unix epoch
+    #[arg(long, value_name = "MIN:MAX", value_parser = parse_range_arg)]
+    pub crtime: Option<RangeInclusive<u64>>,
+    /// Only files last modified in this range, in seconds since the unix epoch
+    #[arg(long, value_name = "MIN:MAX", value_parser = parse_range_arg)]
+    pub mtime: Option<RangeInclusive<u64>>,
+    /// Only files in the directory with this object id
+    #[arg(long, value_name = "ID")]
+    pub parent: Option<u64>,
+    /// Only files owned by this user id
+    #[arg(long, value_name = "ID")]
+    pub uid: Option<u64>,
+    /// Only files owned by this group id
+    #[arg(long, value_name = "ID")]
+    pub gid: Option<u64>,
+    /// Only files found at this object id, needs the path manifest undelete writes since a dnode doesn't contain its own object id
+    #[arg(long = "object", value_name = "ID")]
+    pub object_id: Option<u64>,
+    /// Only the fragment with this hash, either the full hash (4 words) or just its first words (the graph dump prints the first one), separated by commas
+    #[arg(long, value_name = "WORD", num_args = 1..=4, value_delimiter = ',')]
+    pub hash: Option<Vec<u64>>,
+}
+
+// Parses "min:max" where either side can be left out, ex. "1024:" means at least 1024
+fn parse_range(value: &str) -> Option<RangeInclusive<u64>> {
+    let (start, end) = value.split_once(':')?;
+    let start = if start.is_empty() {
+        0
+    } else {
+        start.parse().ok()?
+    };
+    let end = if end.is_empty() {
+        u64::MAX
+    } else {
+        end.parse().ok()?
+    };
+    Some(start..=end)
+}
+
+pub fn parse_range_arg(value: &str) -> Result<RangeInclusive<u64>, String> {
+    parse_range(value).ok_or(format!("{value:?} is not a range like min:max"))
+}
+
+impl FileSelection {
+    pub fn needs_bonus_attributes(&self) -> bool {
+        self.size.is_some()
+            || self.crtime.is_some()
+            || self.mtime.is_some()
+            || self.parent.is_some()
+            || self.uid.is_some()
+            || self.gid.is_some()
+    }
+
+    // `object_ids` maps fragment hashes to the object ids they were found at, see recovery::paths
+    pub fn matches(
+        &self,
+        hash: &[u64; 4],
+        fragment: &Fragment,
+        object_ids: &HashMap<[u64; 4], HashSet<u64>>,
+    ) -> bool {
+        let FragmentData::FileDNode(file) = &fragment.data else {
+            return false;
+        };
+
+        if let Some(words) = &self.hash {
+            if !hash.starts_with(words) {
+                return false;
+            }
+        }
+
+        if let Some(object_id) = self.object_id {
+            if !object_ids
+                .get(hash)
+                .is_some_and(|ids| ids.contains(&object_id))
+            {
+                return false;
+            }
+        }
+
+        if !self.needs_bonus_attributes() {
+            return true;
+        }
+
+        let Some(attributes) = FileAttributes::guess_from_dnode(file) else {
+            return false;
+        };
+
+        self.size
+            .as_ref()
+            .is_none_or(|range| range.contains(&attributes.size))
+            && self
+                .crtime
+                .as_ref()
+                .is_none_or(|range| range.contains(&attributes.crtime))
+            && self
+                .mtime
+                .as_ref()
+                .is_none_or(|range| range.contains(&attributes.mtime))
+            && self.parent.is_none_or(|parent| parent == attributes.parent)
+            && self.uid.is_none_or(|uid| uid == attributes.uid)
+            && self.gid.is_none_or(|gid| gid == attributes.gid)
+    }
+}
+
+// Returns: The object ids of every fragment in the manifest
+pub fn get_object_ids_from_manifest(manifest: &[ManifestEntry]) -> HashMap<[u64; 4], HashSet<u64>> {
+    manifest
+        .iter()
+        .map(|entry| (entry.hash, entry.object_ids.iter().copied().collect()))
+        .collect()
+}
+
+// Returns: The recovered files that match the selection, from the checkpoint filter-checkpoints wrote, biggest first
+// NOTE: The path manifest is only read if the selection needs it (to select by object id)
+pub fn read_selected_fragments(
+    pool_args: &cli::PoolArgs,
+    selection: &FileSelection,
+) -> Result<Vec<([u64; 4], Fragment)>, String> {
+    let mut fragments: Vec<([u64; 4], Fragment)> = checkpoint::read_checkpoint_entries(
+        &pool_args.output_path(FILTERED_CHECKPOINT_FILE_NAME),
+    )
+    .map_err(|()| {
+        format!("The checkpoint {FILTERED_CHECKPOINT_FILE_NAME} can't be read, run undelete and filter-checkpoints first")
+    })?;
+
+    // The object ids are only known from the path manifest undelete writes
+    let object_ids = if selection.object_id.is_some() {
+        let manifest =
+            paths::read_path_manifest(&pool_args.output_path(paths::PATH_MANIFEST_FILE_NAME))
+                .map_err(|()| {
+                    format!(
+                        "The path manifest {} can't be read, it's needed to select by object id",
+                        paths::PATH_MANIFEST_FILE_NAME
+                    )
+                })?;
+        get_object_ids_from_manifest(&manifest)
+    } else {
+        HashMap::new()
+    };
+    // matches only accepts file dnodes, so everything that is left is one
+    fragments.retain(|(hash, fragment)| selection.matches(hash, fragment, &object_ids));
+    fragments.sort_unstable_by_key(|(_, fragment)| match &fragment.data {
+        FragmentData::FileDNode(file) => Reverse(file.0.get_data_size()),
+        _ => Reverse(0),
+    });
+    Ok(fragments)
+}
@@ -0,0 +1,434 @@
+// Saving the fragments undelete finds to an sqlite database instead of one big checkpoint, so they can be queried
+// without loading all of them in ram, ex. all the file dnodes bigger than X whose newest block pointer was born after txg Y
+// NOTE: This is only compiled with the "sqlite" feature
+// The database can also be queried directly with the sqlite3 shell, the tables are:
+//     fragments(hash, kind, size, mtime, newest_birth_txg, data), size and mtime are only set for file dnodes whose bonus data could be parsed
+//     block_pointers(fragment, checksum, logical_birth_txg, level, object_type), only the normal (not embedded) ones
+//     dvas(fragment, checksum, vdev, offset, allocated_size, is_gang)
+//     edges(parent, child)
+// hashes and checksums are stored as 32 byte blobs (the 4 u64s in little endian)
+// The data is the bincode of the FragmentData, its schema version is the user_version of the database (0 for databases from before it was tracked, which are version 1)
+
+use std::{collections::HashMap, ops::RangeInclusive, path::Path};
+
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    recovery::{
+        fragment::{Fragment, FragmentData},
+        migrate,
+        select::FileAttributes,
+    },
+    zio::BlockPointer,
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS fragments (
+        hash BLOB PRIMARY KEY NOT NULL,
+        kind TEXT NOT NULL,
+        size INTEGER,
+        mtime INTEGER,
+        newest_birth_txg INTEGER,
+        data BLOB NOT NULL
+    ) WITHOUT ROWID;
+    CREATE INDEX IF NOT EXISTS fragments_by_kind_and_size ON fragments(kind, size);
+    CREATE INDEX IF NOT EXISTS fragments_by_newest_birth_txg ON fragments(newest_birth_txg);
+
+    CREATE TABLE IF NOT EXISTS block_pointers (
+        fragment BLOB NOT NULL,
+        checksum BLOB NOT NULL,
+        logical_birth_txg INTEGER NOT NULL,
+        level INTEGER NOT NULL,
+        object_type TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS block_pointers_by_fragment ON block_pointers(fragment);
+    CREATE INDEX IF NOT EXISTS block_pointers_by_checksum ON block_pointers(checksum);
+
+    CREATE TABLE IF NOT EXISTS dvas (
+        fragment BLOB NOT NULL,
+        checksum BLOB NOT NULL,
+        vdev INTEGER NOT NULL,
+        offset INTEGER NOT NULL,
+        allocated_size INTEGER NOT NULL,
+        is_gang INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS dvas_by_fragment ON dvas(fragment);
+    CREATE INDEX IF NOT EXISTS dvas_by_offset ON dvas(vdev, offset);
+
+    CREATE TABLE IF NOT EXISTS edges (
+        parent BLOB NOT NULL,
+        child BLOB NOT NULL,
+        PRIMARY KEY (parent, child)
+    ) WITHOUT ROWID;
+    CREATE INDEX IF NOT EXISTS edges_by_child ON edges(child);
+";
+
+pub fn hash_to_blob(hash: &[u64; 4]) -> [u8; 32] {
+    let mut blob = [0u8; 32];
+    for (chunk, value) in blob.chunks_exact_mut(8).zip(hash.iter()) {
+        chunk.copy_from_slice(&value.to_le_bytes());
+    }
+    blob
+}
+
+pub fn blob_to_hash(blob: &[u8]) -> Option<[u64; 4]> {
+    if blob.len() != 32 {
+        return None;
+    }
+    let mut hash = [0u64; 4];
+    for (value, chunk) in hash.iter_mut().zip(blob.chunks_exact(8)) {
+        *value = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    Some(hash)
+}
+
+fn get_kind_name(data: &FragmentData) -> &'static str {
+    match data {
+        FragmentData::FileDNode(_) => "file",
+        FragmentData::DirectoryDNode(_, _) => "directory",
+        FragmentData::ObjSetDNode(_) => "objset",
+        FragmentData::IndirectBlock(_) => "indirect",
+    }
+}
+
+// sqlite integers are signed, so values that don't fit (only on corrupted data) are clamped instead of failing the whole insert
+fn to_sql_integer(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+// Filters for FragmentStore::find_files, every filter that is set has to match
+#[derive(Debug, Clone, Default)]
+pub struct FileQuery {
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    // Of the newest block pointer of the dnode
+    pub birth_txgs: Option<RangeInclusive<u64>>,
+    pub mtimes: Option<RangeInclusive<u64>>,
+}
+
+pub struct FragmentStore {
+    connection: Connection,
+}
+
+impl FragmentStore {
+    // Opens the database at `path`, creating it and the tables if they don't exist yet
+    pub fn open(path: &Path) -> Result<FragmentStore, String> {
+        let connection = Connection::open(path)
+            .map_err(|err| format!("{} can't be opened: {err}", path.display()))?;
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(|err| format!("The tables of {} can't be created: {err}", path.display()))?;
+        let mut store = FragmentStore { connection };
+        store
+            .migrate()
+            .map_err(|err| format!("{} can't be converted: {err}", path.display()))?;
+        Ok(store)
+    }
+
+    // Converts the data of every fragment to the current schema version, in one transaction, so it either all happens or none of it does
+    fn migrate(&mut self) -> Result<(), String> {
+        use crate::ansi_color::*;
+        let version: u64 = self
+            .connection
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .map_err(|err| err.to_string())?;
+        let version = version.max(migrate::FIRST_SCHEMA_VERSION);
+        if version == migrate::SCHEMA_VERSION {
+            return Ok(());
+        }
+        if version > migrate::SCHEMA_VERSION {
+            return Err(format!(
+                "it has the unsupported version {version}, it's probably from a newer version of szfs"
+            ));
+        }
+
+        let transaction = self
+            .connection
+            .transaction()
+            .map_err(|err| err.to_string())?;
+        let mut nconverted = 0usize;
+        {
+            let mut select_data = transaction
+                .prepare("SELECT hash, data FROM fragments")
+                .map_err(|err| err.to_string())?;
+            let mut update_data = transaction
+                .prepare("UPDATE fragments SET data = ?2 WHERE hash = ?1")
+                .map_err(|err| err.to_string())?;
+            let rows = select_data
+                .query_map([], |row| {
+                    Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+                })
+                .map_err(|err| err.to_string())?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| err.to_string())?;
+            for (hash, data) in rows {
+                let data = migrate::migrate_fragment_data(version, &data).map_err(|()| {
+                    format!(
+                        "the data of fragment {:?} can't be read",
+                        blob_to_hash(&hash)
+                    )
+                })?;
+                let data = bincode::serialize(&data).map_err(|err| err.to_string())?;
+                update_data
+                    .execute(params![hash, data])
+                    .map_err(|err| err.to_string())?;
+                nconverted += 1;
+            }
+        }
+        transaction
+            .pragma_update(None, "user_version", migrate::SCHEMA_VERSION)
+            .map_err(|err| err.to_string())?;
+        transaction.commit().map_err(|err| err.to_string())?;
+        if nconverted != 0 {
+            println!(
+                "{CYAN}Info{WHITE}: Converted {nconverted} fragments from version {version} to version {}",
+                migrate::SCHEMA_VERSION
+            );
+        }
+        Ok(())
+    }
+
+    // For queries that the functions below don't cover
+    pub fn get_connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    // Adds the fragments (or replaces them if they are already in the database) with their block pointers and children, in one transaction
+    // Returns: Err if anything can't be written, then nothing is
+    pub fn insert_fragments<'a>(
+        &mut self,
+        fragments: impl IntoIterator<Item = (&'a [u64; 4], &'a mut Fragment)>,
+    ) -> Result<(), String> {
+        let transaction = self
+            .connection
+            .transaction()
+            .map_err(|err| err.to_string())?;
+        {
+            let mut delete_block_pointers = transaction
+                .prepare("DELETE FROM block_pointers WHERE fragment = ?1")
+                .map_err(|err| err.to_string())?;
+            let mut delete_dvas = transaction
+                .prepare("DELETE FROM dvas WHERE fragment = ?1")
+                .map_err(|err| err.to_string())?;
+            let mut delete_edges = transaction
+                .prepare("DELETE FROM edges WHERE parent = ?1")
+                .map_err(|err| err.to_string())?;
+            let mut insert_fragment = transaction
+                .prepare("INSERT OR REPLACE INTO fragments (hash, kind, size, mtime, newest_birth_txg, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+                .map_err(|err| err.to_string())?;
+            let mut insert_block_pointer = transaction
+                .prepare("INSERT INTO block_pointers (fragment, checksum, logical_birth_txg, level, object_type) VALUES (?1, ?2, ?3, ?4, ?5)")
+                .map_err(|err| err.to_string())?;
+            let mut insert_dva = transaction
+                .prepare("INSERT INTO dvas (fragment, checksum, vdev, offset, allocated_size, is_gang) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")
+                .map_err(|err| err.to_string())?;
+            let mut insert_edge = transaction
+                .prepare("INSERT OR IGNORE INTO edges (parent, child) VALUES (?1, ?2)")
+                .map_err(|err| err.to_string())?;
+
+            for (hash, fragment) in fragments {
+                let hash_blob = hash_to_blob(hash);
+                let attributes = match &fragment.data {
+                    FragmentData::FileDNode(file) => FileAttributes::guess_from_dnode(file),
+                    _ => None,
+                };
+                let newest_birth_txg = fragment.get_newest_birth_txg();
+                let data = bincode::serialize(&fragment.data).map_err(|err| err.to_string())?;
+
+                // So re-inserting a fragment doesn't leave the rows of the old one behind
+                for delete in [
+                    &mut delete_block_pointers,
+                    &mut delete_dvas,
+                    &mut delete_edges,
+                ] {
+                    delete
+                        .execute(params![&hash_blob[..]])
+                        .map_err(|err| err.to_string())?;
+                }
+
+                insert_fragment
+                    .execute(params![
+                        &hash_blob[..],
+                        get_kind_name(&fragment.data),
+                        attributes.map(|attributes| to_sql_integer(attributes.size)),
+                        attributes.map(|attributes| to_sql_integer(attributes.mtime)),
+                        newest_birth_txg.map(to_sql_integer),
+                        data,
+                    ])
+                    .map_err(|err| err.to_string())?;
+
+                for child in fragment.children.iter() {
+                    insert_edge
+                        .execute(params![&hash_blob[..], &hash_to_blob(child)[..]])
+                        .map_err(|err| err.to_string())?;
+                }
+
+                for bp in fragment.get_block_pointers() {
+                    // Embedded block pointers have neither a checksum nor dvas
+                    let BlockPointer::Normal(normal_bp) = bp else {
+                        continue;
+                    };
+                    let checksum_blob = hash_to_blob(&normal_bp.get_checksum());
+                    insert_block_pointer
+                        .execute(params![
+                            &hash_blob[..],
+                            &checksum_blob[..],
+                            to_sql_integer(normal_bp.get_logical_birth_txg()),
+                            normal_bp.get_level() as i64,
+                            format!("{:?}", normal_bp.get_type()),
+                        ])
+                        .map_err(|err| err.to_string())?;
+                    for dva in normal_bp.get_dvas().iter().flatten() {
+                        insert_dva
+                            .execute(params![
+                                &hash_blob[..],
+                                &checksum_blob[..],
+                                dva.get_vdev_id(),
+                                to_sql_integer(dva.parse_offset()),
+                                to_sql_integer(dva.parse_allocated_size()),
+                                dva.is_gang(),
+                            ])
+                            .map_err(|err| err.to_string())?;
+                    }
+                }
+            }
+        }
+        transaction.commit().map_err(|err| err.to_string())
+    }
+
+    // Returns: Ok(None) if there is no fragment with that hash
+    pub fn get_fragment(&self, hash: &[u64; 4]) -> Result<Option<Fragment>, String> {
+        let Some(data) = self
+            .connection
+            .query_row(
+                "SELECT data FROM fragments WHERE hash = ?1",
+                params![&hash_to_blob(hash)[..]],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .map_err(|err| err.to_string())?
+        else {
+            return Ok(None);
+        };
+        let data: FragmentData = bincode::deserialize(&data).map_err(|err| err.to_string())?;
+        Ok(Some(Fragment {
+            data,
+            children: self.get_children(hash)?.into_iter().collect(),
+        }))
+    }
+
+    // Loads the whole graph back, like reading a checkpoint would
+    pub fn get_all_fragments(&self) -> Result<HashMap<[u64; 4], Fragment>, String> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT hash FROM fragments")
+            .map_err(|err| err.to_string())?;
+        let hashes = self.collect_hashes(&mut statement, params![])?;
+        let mut fragments = HashMap::with_capacity(hashes.len());
+        for hash in hashes {
+            if let Some(fragment) = self.get_fragment(&hash)? {
+                fragments.insert(hash, fragment);
+            }
+        }
+        Ok(fragments)
+    }
+
+    fn collect_hashes(
+        &self,
+        statement: &mut rusqlite::Statement<'_>,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<[u64; 4]>, String> {
+        let blobs = statement
+            .query_map(params, |row| row.get::<_, Vec<u8>>(0))
+            .map_err(|err| err.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| err.to_string())?;
+        blobs
+            .iter()
+            .map(|blob| blob_to_hash(blob).ok_or(String::from("A hash isn't 32 bytes")))
+            .collect()
+    }
+
+    pub fn get_children(&self, hash: &[u64; 4]) -> Result<Vec<[u64; 4]>, String> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT child FROM edges WHERE parent = ?1")
+            .map_err(|err| err.to_string())?;
+        self.collect_hashes(&mut statement, params![&hash_to_blob(hash)[..]])
+    }
+
+    pub fn get_parents(&self, hash: &[u64; 4]) -> Result<Vec<[u64; 4]>, String> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT parent FROM edges WHERE child = ?1")
+            .map_err(|err| err.to_string())?;
+        self.collect_hashes(&mut statement, params![&hash_to_blob(hash)[..]])
+    }
+
+    // Returns: The hashes of the file dnodes that match every filter of the query, sorted by size (biggest first)
+    pub fn find_files(&self, query: &FileQuery) -> Result<Vec<[u64; 4]>, String> {
+        let to_bounds = |range: &Option<RangeInclusive<u64>>| {
+            range.as_ref().map_or((None, None), |range| {
+                (
+                    Some(to_sql_integer(*range.start())),
+                    Some(to_sql_integer(*range.end())),
+                )
+            })
+        };
+        let (min_birth_txg, max_birth_txg) = to_bounds(&query.birth_txgs);
+        let (min_mtime, max_mtime) = to_bounds(&query.mtimes);
+
+        let mut statement = self
+            .connection
+            .prepare(
+                "SELECT hash FROM fragments WHERE kind = 'file'
+                    AND (?1 IS NULL OR size >= ?1) AND (?2 IS NULL OR size <= ?2)
+                    AND (?3 IS NULL OR newest_birth_txg >= ?3) AND (?4 IS NULL OR newest_birth_txg <= ?4)
+                    AND (?5 IS NULL OR mtime >= ?5) AND (?6 IS NULL OR mtime <= ?6)
+                    ORDER BY size DESC",
+            )
+            .map_err(|err| err.to_string())?;
+        self.collect_hashes(
+            &mut statement,
+            params![
+                query.min_size.map(to_sql_integer),
+                query.max_size.map(to_sql_integer),
+                min_birth_txg,
+                max_birth_txg,
+                min_mtime,
+                max_mtime,
+            ],
+        )
+    }
+
+    // Returns: The hashes of the fragments that have a block pointer with this checksum
+    pub fn find_by_checksum(&self, checksum: &[u64; 4]) -> Result<Vec<[u64; 4]>, String> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT DISTINCT fragment FROM block_pointers WHERE checksum = ?1")
+            .map_err(|err| err.to_string())?;
+        self.collect_hashes(&mut statement, params![&hash_to_blob(checksum)[..]])
+    }
+
+    // Returns: The hashes of the fragments that have a dva that starts in `offsets` of the vdev
+    pub fn find_by_dva(
+        &self,
+        vdev_id: u32,
+        offsets: RangeInclusive<u64>,
+    ) -> Result<Vec<[u64; 4]>, String> {
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                "SELECT DISTINCT fragment FROM dvas WHERE vdev = ?1 AND offset BETWEEN ?2 AND ?3",
+            )
+            .map_err(|err| err.to_string())?;
+        self.collect_hashes(
+            &mut statement,
+            params![
+                vdev_id,
+                to_sql_integer(*offsets.start()),
+                to_sql_integer(*offsets.end())
+            ],
+        )
+    }
+}
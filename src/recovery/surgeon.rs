@@ -0,0 +1,381 @@
+// Surgically recovers the bad blocks of a recovered file when there are multiple candidates for them
+// (ex. the offset the block pointer says and the extra offsets find-block-with-checksum-postrecover found)
+// The file is split into items by a geometry (ex. the compressed blocks of a squashfs image), for every item that overlaps a bad block
+// every combination of candidates is tried and a validator that knows the format of the item picks the combination that makes sense
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::zio::{DataVirtualAddress, Vdevs};
+
+// What undelete-postrecover and find-block-with-checksum-postrecover know about a bad block of the recovered file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BadBlockInfo {
+    pub block_number: u64,
+    pub checksum: [u64; 4],
+    // Offset in the (raidz) vdev the block pointer says the block is at
+    pub main_offset: u64,
+    // Other offsets where a block with the same checksum might be
+    #[serde(default)]
+    pub extra_offsets: Vec<u64>,
+}
+
+pub fn read_bad_block_info(path: &Path) -> Option<HashMap<u64, BadBlockInfo>> {
+    let bad_blocks_info: Vec<BadBlockInfo> =
+        serde_json::from_reader(File::open(path).ok()?).ok()?;
+    Some(
+        bad_blocks_info
+            .into_iter()
+            .map(|bad_block_info| (bad_block_info.block_number, bad_block_info))
+            .collect(),
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    Invalid,
+    // Looks like the right thing, but not everything could be checked (ex. only the start magic is correct)
+    Plausible,
+    Valid,
+}
+
+pub trait ContentValidator {
+    fn get_name(&self) -> &'static str;
+    fn validate(&self, data: &[u8]) -> Confidence;
+}
+
+// A compressed squashfs block when the image was made with -comp xz, every block is a whole xz stream
+// Source: https://tukaani.org/xz/xz-file-format.txt (2.1.1.1. Header Magic Bytes, 2.1.2.4. Footer Magic Bytes)
+pub struct XzStreamValidator;
+
+impl ContentValidator for XzStreamValidator {
+    fn get_name(&self) -> &'static str {
+        "xz"
+    }
+
+    fn validate(&self, data: &[u8]) -> Confidence {
+        if !data.starts_with(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]) {
+            Confidence::Invalid
+        } else if data.ends_with(b"YZ") {
+            Confidence::Valid
+        } else {
+            Confidence::Plausible
+        }
+    }
+}
+
+// A 512 byte tar header, the checksum is the sum of all bytes of the header with the checksum field itself counted as spaces
+// Source: https://www.gnu.org/software/tar/manual/html_node/Standard.html
+pub struct TarHeaderValidator;
+
+impl ContentValidator for TarHeaderValidator {
+    fn get_name(&self) -> &'static str {
+        "tar"
+    }
+
+    fn validate(&self, data: &[u8]) -> Confidence {
+        let Some(header) = data.get(0..512) else {
+            return Confidence::Invalid;
+        };
+        let stored_checksum = std::str::from_utf8(&header[148..156])
+            .ok()
+            .and_then(|field| {
+                u64::from_str_radix(field.trim_matches(|c: char| c == '\0' || c == ' '), 8).ok()
+            });
+        let checksum = header
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                if (148..156).contains(&i) {
+                    u64::from(b' ')
+                } else {
+                    u64::from(*b)
+                }
+            })
+            .sum::<u64>();
+
+        if stored_checksum == Some(checksum) {
+            Confidence::Valid
+        } else if &header[257..262] == b"ustar" {
+            Confidence::Plausible
+        } else {
+            Confidence::Invalid
+        }
+    }
+}
+
+// A zip entry, from its local file header up to the next header
+// Source: https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT (4.3.7 Local file header)
+pub struct ZipEntryValidator;
+
+impl ContentValidator for ZipEntryValidator {
+    fn get_name(&self) -> &'static str {
+        "zip"
+    }
+
+    fn validate(&self, data: &[u8]) -> Confidence {
+        if data.len() < 30 || !data.starts_with(b"PK\x03\x04") {
+            return Confidence::Invalid;
+        }
+        let flags = u16::from_le_bytes(data[6..8].try_into().unwrap());
+        let compressed_size = u32::from_le_bytes(data[18..22].try_into().unwrap()) as usize;
+        let name_len = u16::from_le_bytes(data[26..28].try_into().unwrap()) as usize;
+        let extra_len = u16::from_le_bytes(data[28..30].try_into().unwrap()) as usize;
+
+        // If the sizes are in a data descriptor after the data, there is nothing more to check
+        let has_data_descriptor = flags & (1 << 3) != 0;
+        if !has_data_descriptor && 30 + name_len + extra_len + compressed_size == data.len() {
+            Confidence::Valid
+        } else {
+            Confidence::Plausible
+        }
+    }
+}
+
+// The header of a qcow2 image, which is the only part of it that can be checked without knowing what's in the image
+// Source: https://gitlab.com/qemu-project/qemu/-/blob/master/docs/interop/qcow2.txt
+pub struct Qcow2HeaderValidator;
+
+impl ContentValidator for Qcow2HeaderValidator {
+    fn get_name(&self) -> &'static str {
+        "qcow2"
+    }
+
+    fn validate(&self, data: &[u8]) -> Confidence {
+        if data.len() < 8 || !data.starts_with(b"QFI\xfb") {
+            return Confidence::Invalid;
+        }
+        let version = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        if version == 2 || version == 3 {
+            Confidence::Valid
+        } else {
+            Confidence::Plausible
+        }
+    }
+}
+
+pub fn validator_from_name(name: &str) -> Option<Box<dyn ContentValidator>> {
+    Some(match name {
+        "xz" | "squashfs" => Box::new(XzStreamValidator),
+        "tar" => Box::new(TarHeaderValidator),
+        "zip" => Box::new(ZipEntryValidator),
+        "qcow2" => Box::new(Qcow2HeaderValidator),
+        _ => return None,
+    })
+}
+
+// A part of the recovered file that is validated as a whole
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ContentItem {
+    pub file_offset: u64,
+    pub size: u64,
+    // Some items can't be validated (ex. uncompressed squashfs blocks), so there is no point in trying to recover them
+    pub should_validate: bool,
+}
+
+pub trait BlockGeometry {
+    fn get_items(&self) -> Vec<ContentItem>;
+}
+
+// Any format can be described by just listing the items
+impl BlockGeometry for Vec<ContentItem> {
+    fn get_items(&self) -> Vec<ContentItem> {
+        self.clone()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SquashfsBlockInfo {
+    pub block_number: u64,
+    pub ondisk_size: u32,
+    pub is_compressed: bool,
+}
+
+// The data blocks of a squashfs image, they are right after each other starting after the superblock
+pub struct SquashfsGeometry {
+    pub blocks: Vec<SquashfsBlockInfo>,
+}
+
+impl SquashfsGeometry {
+    // Source: https://dr-emann.github.io/squashfs/squashfs.html#_the_superblock
+    pub const SUPERBLOCK_SIZE: u64 = 96;
+}
+
+impl BlockGeometry for SquashfsGeometry {
+    fn get_items(&self) -> Vec<ContentItem> {
+        let mut items = Vec::new();
+        let mut file_offset = Self::SUPERBLOCK_SIZE;
+        for block in self.blocks.iter() {
+            if block.ondisk_size != 0 {
+                items.push(ContentItem {
+                    file_offset,
+                    size: u64::from(block.ondisk_size),
+                    should_validate: block.is_compressed,
+                });
+            }
+            file_offset += u64::from(block.ondisk_size);
+        }
+        items
+    }
+}
+
+// Items of the same size right after each other, ex. the clusters of a qcow2 image or the records of a tar file
+pub struct FixedSizeGeometry {
+    pub start_offset: u64,
+    pub item_size: u64,
+    pub file_size: u64,
+}
+
+impl BlockGeometry for FixedSizeGeometry {
+    fn get_items(&self) -> Vec<ContentItem> {
+        if self.item_size == 0 {
+            return Vec::new();
+        }
+        (self.start_offset..self.file_size)
+            .step_by(self.item_size as usize)
+            .map(|file_offset| ContentItem {
+                file_offset,
+                size: self.item_size.min(self.file_size - file_offset),
+                should_validate: true,
+            })
+            .collect()
+    }
+}
+
+// Where the data for a block of the file comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CandidateSource {
+    // The block number in the recovered file, for blocks that aren't bad
+    File(u64),
+    // An offset in the (raidz) vdev
+    Raidz(u64),
+}
+
+#[derive(Debug)]
+pub enum StitchResult {
+    // The item doesn't overlap any bad block
+    NotNeeded,
+    Recovered {
+        data: Vec<u8>,
+        combination: Vec<CandidateSource>,
+    },
+    // No combination is valid, but these ones are plausible
+    OnlyPlausible(Vec<Vec<CandidateSource>>),
+    NoValidCombination,
+    // Multiple combinations are valid but have different data, so there is no way to know which one is right
+    Ambiguous(usize),
+}
+
+pub struct Surgeon<'a> {
+    pub file_block_size: u64,
+    pub bad_blocks: HashMap<u64, BadBlockInfo>,
+    pub recovered_file: File,
+    pub validator: &'a dyn ContentValidator,
+}
+
+impl Surgeon<'_> {
+    fn read_candidate(&self, source: CandidateSource, vdevs: &mut Vdevs) -> Option<Vec<u8>> {
+        match source {
+            CandidateSource::File(block_number) => {
+                let mut file = &self.recovered_file;
+                file.seek(SeekFrom::Start(block_number * self.file_block_size))
+                    .ok()?;
+                // The last block of the file might be shorter, the rest is zeros
+                let mut block_data = Vec::with_capacity(self.file_block_size as usize);
+                file.take(self.file_block_size)
+                    .read_to_end(&mut block_data)
+                    .ok()?;
+                block_data.resize(self.file_block_size as usize, 0);
+                Some(block_data)
+            }
+            CandidateSource::Raidz(offset) => DataVirtualAddress::from(0, offset, false)
+                .dereference(vdevs, self.file_block_size as usize)
+                .ok(),
+        }
+    }
+
+    pub fn stitch_item(&self, item: &ContentItem, vdevs: &mut Vdevs) -> StitchResult {
+        use crate::ansi_color::*;
+        if !item.should_validate || item.size == 0 || self.file_block_size == 0 {
+            return StitchResult::NotNeeded;
+        }
+
+        let first_block_number = item.file_offset / self.file_block_size;
+        let offset_in_first_block = (item.file_offset % self.file_block_size) as usize;
+        let last_block_number = (item.file_offset + item.size - 1) / self.file_block_size;
+        let block_numbers = first_block_number..=last_block_number;
+        if !block_numbers
+            .clone()
+            .any(|block_number| self.bad_blocks.contains_key(&block_number))
+        {
+            return StitchResult::NotNeeded;
+        }
+
+        // Every candidate is read only once, no matter how many combinations it is in
+        let mut candidates = Vec::<Vec<(CandidateSource, Vec<u8>)>>::new();
+        for block_number in block_numbers {
+            let sources = match self.bad_blocks.get(&block_number) {
+                Some(bad_block_info) => bad_block_info
+                    .extra_offsets
+                    .iter()
+                    .copied()
+                    .chain(std::iter::once(bad_block_info.main_offset))
+                    .map(CandidateSource::Raidz)
+                    .collect(),
+                None => vec![CandidateSource::File(block_number)],
+            };
+            candidates.push(
+                sources
+                    .into_iter()
+                    .filter_map(|source| Some((source, self.read_candidate(source, vdevs)?)))
+                    .collect(),
+            );
+        }
+
+        let mut valid = HashMap::<Vec<u8>, Vec<CandidateSource>>::new();
+        let mut plausible = Vec::new();
+        for combination in candidates
+            .iter()
+            .map(|block_candidates| block_candidates.iter())
+            .multi_cartesian_product()
+        {
+            let mut data = combination
+                .iter()
+                .flat_map(|(_, block_data)| block_data.iter().copied())
+                .skip(offset_in_first_block)
+                .collect::<Vec<u8>>();
+            data.resize(item.size as usize, 0);
+
+            let sources = combination.iter().map(|(source, _)| *source).collect();
+            match self.validator.validate(&data) {
+                Confidence::Valid => {
+                    valid.entry(data).or_insert(sources);
+                }
+                Confidence::Plausible => {
+                    if cfg!(feature = "debug") {
+                        println!("{YELLOW}Warning{WHITE}: Item at file offset {} looks like {} when using combination {:?}, but isn't valid!", item.file_offset, self.validator.get_name(), sources);
+                    }
+                    plausible.push(sources);
+                }
+                Confidence::Invalid => (),
+            }
+        }
+
+        match valid.len() {
+            0 if plausible.is_empty() => StitchResult::NoValidCombination,
+            0 => StitchResult::OnlyPlausible(plausible),
+            1 => {
+                let (data, combination) = valid.into_iter().next().unwrap();
+                StitchResult::Recovered { data, combination }
+            }
+            n => StitchResult::Ambiguous(n),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+// Finds out which blocks (and so which objects) use a given byte of the pool, the inverse of reading a dva
+// Useful to know which files are affected by a bad sector (ex. one reported by SMART)
+// NOTE: This walks every block pointer in the pool, so it's slow, but there's no other way to go from an offset to a block pointer
+// NOTE: For gang blocks only the gang header is matched, not the blocks it points to
+
+use crate::{
+    traverse::{self, BlockLocation},
+    zio::{BlockPointer, DataVirtualAddress, Vdevs},
+};
+
+#[derive(Debug)]
+pub struct DvaUser {
+    pub location: BlockLocation,
+    // Which of the (up to 3) copies of the block is stored at the offset
+    pub dva_index: usize,
+    pub dva: DataVirtualAddress,
+    pub logical_birth_txg: u64,
+}
+
+// Converts a byte offset on one of the devices of a raidz (counting from the very start of the device, so including the labels and boot block)
+// into an offset in the raidz, which is what dva offsets are
+// Returns: None if the offset is in the labels or the boot block at the start of the device
+// NOTE: This has to match the sector layout used by VdevRaidz::read_sector and VdevFile::read
+pub fn raidz_offset_from_device_offset(
+    device_index: usize,
+    device_offset: u64,
+    ndevices: usize,
+    asize: usize,
+) -> Option<u64> {
+    let device_offset = device_offset.checked_sub(4 * 1024 * 1024)?;
+    let device_sector_index = device_offset / asize as u64;
+    let sector_index = device_sector_index * ndevices as u64 + device_index as u64;
+    Some(sector_index * asize as u64 + device_offset % asize as u64)
+}
+
+// Returns: Every block reachable from `rootbp` that has a copy that covers `raidz_offset`
+pub fn find_dva_users(
+    rootbp: &mut BlockPointer,
+    raidz_offset: u64,
+    vdevs: &mut Vdevs,
+) -> Result<Vec<DvaUser>, ()> {
+    let mut users = Vec::new();
+    let mut nvisited: u64 = 0;
+    traverse::traverse_pool(rootbp, vdevs, &mut |location, bp, _| {
+        nvisited += 1;
+        if nvisited.is_multiple_of(1024 * 1024) {
+            println!("Looked at {nvisited} block pointers so far ...");
+        }
+
+        // Embedded block pointers don't point to anything on disk
+        let BlockPointer::Normal(normal_bp) = bp else {
+            return;
+        };
+
+        for (dva_index, dva) in normal_bp.get_dvas().iter().enumerate() {
+            let Some(dva) = dva else {
+                continue;
+            };
+
+            // NOTE: The allocated size includes the parity sectors
+            let dva_range = dva.parse_offset()..dva.parse_offset() + dva.parse_allocated_size();
+            if dva_range.contains(&raidz_offset) {
+                users.push(DvaUser {
+                    location: location.clone(),
+                    dva_index,
+                    dva: dva.clone(),
+                    logical_birth_txg: normal_bp.get_logical_birth_txg(),
+                });
+            }
+        }
+    })?;
+    Ok(users)
+}
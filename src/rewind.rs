@@ -0,0 +1,538 @@
+// Finds previous versions of a dnode by walking the object sets of older uberblocks
+// Because zfs is copy on write, the MOS of an older txg (and through it the object sets of the datasets at that txg)
+// can still be read starting from that txg's rootbp, as long as its blocks have not been overwritten yet
+// This is a lot cheaper than brute forcing the whole disk like undelete does, but only works for recently changed files
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa.c (spa_load_best, zpool import -T)
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    ops::{Range, RangeInclusive},
+};
+
+use crate::{
+    byte_iter::{FromBytes, FromBytesLE, FromSliceLE},
+    dmu::{
+        DNode, DNodePlainFileContents, ExtractOptions, ExtractReport, MergeOptions, MergeReport,
+        ObjSet, ObjType,
+    },
+    features,
+    recovery::select::FileAttributes,
+    zap,
+    zio::Vdevs,
+    Uberblock, Vdev, VdevLabel,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UberblockSkipReason {
+    // The block the rootbp points to couldn't be read, it was probably overwritten
+    RootBlockUnreadable,
+    // The block could be read, but it's not an object set
+    NotAnObjSet,
+}
+
+#[derive(Debug)]
+pub struct MosSelection {
+    // Txg of the uberblock whose MOS is used
+    pub used_txg: u64,
+    // Txgs of all uberblocks whose MOS can be read, newest first, these are the generations that can be fallen back to
+    pub readable_txgs: Vec<u64>,
+    // Uberblocks that couldn't be used, newest first, and why
+    pub skipped: Vec<(u64, UberblockSkipReason)>,
+}
+
+impl MosSelection {
+    pub fn print(&self) {
+        use crate::ansi_color::*;
+        for (txg, reason) in self.skipped.iter().filter(|(txg, _)| *txg > self.used_txg) {
+            println!(
+                "{YELLOW}Warning{WHITE}: Skipped the newer uberblock of txg {txg}: {reason:?}"
+            );
+        }
+        println!(
+            "{CYAN}Info{WHITE}: Using the MOS of txg {}, {} MOS generations are readable: {:?}",
+            self.used_txg,
+            self.readable_txgs.len(),
+            self.readable_txgs
+        );
+    }
+}
+
+// Returns: The uberblocks of all the labels of the vdev that can be read, sorted by txg
+// NOTE: The labels usually have copies of the same uberblocks, those are only returned once
+pub fn collect_uberblocks(vdev: &mut dyn Vdev) -> Vec<Uberblock> {
+    let mut seen = HashSet::<(u64, u64, u64)>::new();
+    let mut uberblocks = Vec::new();
+    for label_index in 0..vdev.get_nlables() {
+        let Ok(raw_label) = vdev.read_raw_label(label_index) else {
+            continue;
+        };
+        let label = VdevLabel::from_bytes(&raw_label);
+        for i in 0..label.get_raw_uberblock_count() {
+            let Some(raw_uberblock) = label.get_raw_uberblock(i) else {
+                continue;
+            };
+            let Some(uberblock) = Uberblock::from_bytes(&mut raw_uberblock.iter().copied()) else {
+                continue;
+            };
+            if seen.insert((uberblock.txg, uberblock.timestamp, uberblock.guid_sum)) {
+                uberblocks.push(uberblock);
+            }
+        }
+    }
+    uberblocks.sort_unstable_by_key(|ub| ub.txg);
+    uberblocks
+}
+
+// Where the scan found an uberblock, for forensics it's interesting to know where a copy survived
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UberblockLocation {
+    // The offset is from the start of the label, so it also finds uberblocks outside of the uberblock ring
+    Label {
+        device: usize,
+        label_index: usize,
+        offset: u64,
+    },
+    // The offset is the one Vdev::read uses, so after the labels and the boot block
+    Data {
+        device: usize,
+        offset: u64,
+    },
+}
+
+#[derive(Debug)]
+pub struct FoundUberblock {
+    pub uberblock: Uberblock,
+    // Every place the same uberblock was found at, the labels usually have many copies of it
+    pub locations: Vec<UberblockLocation>,
+    // If the rootbp points to an object set that can be read, see UberblockScan::rank
+    pub is_rootbp_readable: bool,
+}
+
+// Uberblocks found by looking for their magic, instead of only in the uberblock ring of the labels
+// This finds them when the rings were zeroed, but older copies of the labels or other parts of the disk still have some (ex. a disk that was in another pool, or an image of one in a file)
+#[derive(Debug, Default)]
+pub struct UberblockScan {
+    pub found: Vec<FoundUberblock>,
+    // Uberblocks written by a big endian machine, they are only reported as the pool can't be read anyway
+    pub big_endian: Vec<UberblockLocation>,
+}
+
+// The magic is the first u64 of an uberblock, so it's always 8 byte aligned
+// and this is the most of the uberblock that is parsed (the magic, 4 u64s and the rootbp), it's also how much scan chunks overlap
+const UBERBLOCK_PARSED_SIZE: usize = 5 * 8 + 128;
+
+impl UberblockScan {
+    // `offset` is the offset of data, `make_location` turns the offset of an uberblock into where it was found
+    pub fn scan_bytes(
+        &mut self,
+        data: &[u8],
+        offset: u64,
+        make_location: impl Fn(u64) -> UberblockLocation,
+    ) {
+        for position in (0..data.len().saturating_sub(7)).step_by(8) {
+            let word: [u8; 8] = data[position..position + 8].try_into().unwrap();
+            let location = make_location(offset + position as u64);
+            if u64::from_be_bytes(word) == crate::UBERBLOCK_MAGIC {
+                self.big_endian.push(location);
+                continue;
+            }
+            if u64::from_le_bytes(word) != crate::UBERBLOCK_MAGIC {
+                continue;
+            }
+            let Some(uberblock) = Uberblock::from_bytes_le(&mut data[position..].iter().copied())
+            else {
+                continue;
+            };
+            // The magic is only 3 bytes, so it does turn up in random data, but a real uberblock has a known version and a txg
+            if !features::is_valid_version(uberblock.version) || uberblock.txg == 0 {
+                continue;
+            }
+
+            match self
+                .found
+                .iter_mut()
+                .find(|found| is_same_uberblock(&found.uberblock, &uberblock))
+            {
+                Some(found) => found.locations.push(location),
+                None => self.found.push(FoundUberblock {
+                    uberblock,
+                    locations: vec![location],
+                    is_rootbp_readable: false,
+                }),
+            }
+        }
+    }
+
+    // Scans every label of the vdev, the whole label and not just the uberblock ring
+    pub fn scan_labels(&mut self, vdev: &mut dyn Vdev, device: usize) {
+        for label_index in 0..vdev.get_nlables() {
+            let Ok(raw_label) = vdev.read_raw_label(label_index) else {
+                continue;
+            };
+            self.scan_bytes(&raw_label, 0, |offset| UberblockLocation::Label {
+                device,
+                label_index,
+                offset,
+            });
+        }
+    }
+
+    // Scans the range of the vdev, it's read in chunks so any range can be given, ex. the whole disk
+    pub fn scan_range(&mut self, vdev: &mut dyn Vdev, device: usize, range: Range<u64>) {
+        const CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+        // Every chunk starts 8 byte aligned, so uberblocks stay aligned
+        let mut offset = range.start / 8 * 8;
+        while offset < range.end {
+            let chunk_end = (offset + CHUNK_SIZE).min(range.end);
+            // The chunks overlap by the size of an uberblock, so one that starts at the end of a chunk is still parsed
+            let amount = (chunk_end + UBERBLOCK_PARSED_SIZE as u64)
+                .min(range.end)
+                .min(vdev.get_size())
+                .saturating_sub(offset) as usize;
+            match vdev.read(offset, amount) {
+                Ok(data) => {
+                    // Only the uberblocks that start in this chunk, the rest are found with the next one
+                    let mut chunk_scan = UberblockScan::default();
+                    chunk_scan.scan_bytes(&data, offset, |offset| UberblockLocation::Data {
+                        device,
+                        offset,
+                    });
+                    chunk_scan.retain_starting_before(chunk_end);
+                    self.merge(chunk_scan);
+                }
+                Err(()) => {
+                    use crate::ansi_color::*;
+                    println!("{YELLOW}Warning{WHITE}: Bytes {offset}..{chunk_end} of vdev {device} can't be read, skipping them!");
+                }
+            }
+            offset = chunk_end;
+        }
+    }
+
+    fn retain_starting_before(&mut self, end: u64) {
+        let is_before = |location: &UberblockLocation| match location {
+            UberblockLocation::Data { offset, .. } => *offset < end,
+            UberblockLocation::Label { .. } => true,
+        };
+        self.big_endian.retain(is_before);
+        for found in self.found.iter_mut() {
+            found.locations.retain(is_before);
+        }
+        self.found.retain(|found| !found.locations.is_empty());
+    }
+
+    fn merge(&mut self, other: UberblockScan) {
+        self.big_endian.extend(other.big_endian);
+        for other_found in other.found {
+            match self
+                .found
+                .iter_mut()
+                .find(|found| is_same_uberblock(&found.uberblock, &other_found.uberblock))
+            {
+                Some(found) => found.locations.extend(other_found.locations),
+                None => self.found.push(other_found),
+            }
+        }
+    }
+
+    // Checks which uberblocks have a rootbp that can be read, and sorts them so the most useful ones are first:
+    // the ones that can be read, newest first, then the rest, newest first
+    pub fn rank(&mut self, vdevs: &mut Vdevs) {
+        for found in self.found.iter_mut() {
+            found.is_rootbp_readable = found
+                .uberblock
+                .rootbp
+                .dereference(vdevs)
+                .is_ok_and(|mos_data| ObjSet::from_slice_le(&mos_data).is_some());
+        }
+        self.found.sort_unstable_by_key(|found| {
+            (
+                std::cmp::Reverse(found.is_rootbp_readable),
+                std::cmp::Reverse(found.uberblock.txg),
+            )
+        });
+    }
+}
+
+fn is_same_uberblock(a: &Uberblock, b: &Uberblock) -> bool {
+    (a.txg, a.timestamp, a.guid_sum) == (b.txg, b.timestamp, b.guid_sum)
+}
+
+// Opens the MOS of the newest uberblock whose MOS can be read
+// Every uberblock is tried, not just until one works, so it's also known which older generations could be used instead
+pub fn open_newest_mos(
+    uberblocks: &mut [Uberblock],
+    vdevs: &mut Vdevs,
+) -> Option<(ObjSet, MosSelection)> {
+    let mut uberblocks = uberblocks.iter_mut().collect::<Vec<_>>();
+    uberblocks.sort_unstable_by_key(|ub| std::cmp::Reverse(ub.txg));
+
+    let mut mos = None;
+    let mut readable_txgs = Vec::new();
+    let mut skipped = Vec::new();
+    for ub in uberblocks {
+        let Ok(mos_data) = ub.rootbp.dereference(vdevs) else {
+            skipped.push((ub.txg, UberblockSkipReason::RootBlockUnreadable));
+            continue;
+        };
+        let Some(objset) = ObjSet::from_slice_le(&mos_data) else {
+            skipped.push((ub.txg, UberblockSkipReason::NotAnObjSet));
+            continue;
+        };
+
+        readable_txgs.push(ub.txg);
+        if mos.is_none() {
+            mos = Some(objset);
+        }
+    }
+
+    Some((
+        mos?,
+        MosSelection {
+            used_txg: *readable_txgs.first()?,
+            readable_txgs,
+            skipped,
+        },
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RewindObjSet {
+    Mos,
+    HeadDataset,
+}
+
+#[derive(Debug)]
+pub struct PreviousDNodeVersion {
+    // Txg of the uberblock the dnode was found through
+    pub uberblock_txg: u64,
+    // Birth txg of the block of the meta dnode that contains the dnode, so the dnode was last changed at or before this txg
+    pub dnode_block_birth_txg: u64,
+    pub dnode: DNode,
+}
+
+// Follows the object directory of the MOS to the object set of the head dataset of the root dataset
+pub fn read_head_dataset_objset(mos: &mut ObjSet, vdevs: &mut Vdevs) -> Option<ObjSet> {
+    let DNode::ObjectDirectory(mut object_directory) = mos.get_dnode_at(1, vdevs)? else {
+        return None;
+    };
+    let objdir_zap_data = object_directory.dump_zap_contents(vdevs)?;
+    let Some(zap::Value::U64(root_dataset_number)) = objdir_zap_data.get("root_dataset") else {
+        return None;
+    };
+
+    let DNode::DSLDirectory(root_dataset) =
+        mos.get_dnode_at(*root_dataset_number as usize, vdevs)?
+    else {
+        return None;
+    };
+    let head_dataset_number = root_dataset
+        .parse_bonus_data()?
+        .get_head_dataset_object_number();
+
+    let DNode::DSLDataset(head_dataset) = mos.get_dnode_at(head_dataset_number as usize, vdevs)?
+    else {
+        return None;
+    };
+    let mut head_dataset_bonus = head_dataset.parse_bonus_data()?;
+    let head_dataset_data = head_dataset_bonus
+        .get_block_pointer()
+        .dereference(vdevs)
+        .ok()?;
+    ObjSet::from_slice_le(&head_dataset_data)
+}
+
+// Returns: All distinct versions of object `object_id` of type `obj_type` that can still be read through uberblocks whose txg is in `txg_range`,
+// sorted from oldest to newest
+pub fn find_previous_dnode_versions(
+    uberblocks: &mut [Uberblock],
+    txg_range: RangeInclusive<u64>,
+    object_id: usize,
+    obj_type: ObjType,
+    objset: RewindObjSet,
+    vdevs: &mut Vdevs,
+) -> Vec<PreviousDNodeVersion> {
+    use crate::ansi_color::*;
+    let mut uberblocks = uberblocks
+        .iter_mut()
+        .filter(|ub| txg_range.contains(&ub.txg))
+        .collect::<Vec<_>>();
+    uberblocks.sort_unstable_by_key(|ub| ub.txg);
+
+    // Multiple uberblocks will usually point to the exact same version of the dnode
+    let mut seen_versions = HashSet::<Vec<u8>>::new();
+    let mut result = Vec::new();
+    for ub in uberblocks {
+        let Ok(mos_data) = ub.rootbp.dereference(vdevs) else {
+            if cfg!(feature = "debug") {
+                println!(
+                    "{YELLOW}Warning{WHITE}: Couldn't read the MOS of txg {}, it has probably been overwritten!",
+                    ub.txg
+                );
+            }
+            continue;
+        };
+
+        let Some(mut mos) = ObjSet::from_slice_le(&mos_data) else {
+            continue;
+        };
+
+        let mut objset = match objset {
+            RewindObjSet::Mos => mos,
+            RewindObjSet::HeadDataset => {
+                let Some(head_dataset_objset) = read_head_dataset_objset(&mut mos, vdevs) else {
+                    if cfg!(feature = "debug") {
+                        println!(
+                            "{YELLOW}Warning{WHITE}: Couldn't read the head dataset of txg {}!",
+                            ub.txg
+                        );
+                    }
+                    continue;
+                };
+                head_dataset_objset
+            }
+        };
+
+        let Some(raw_dnode) = objset.get_raw_dnode_at(object_id, vdevs) else {
+            continue;
+        };
+
+        if seen_versions.contains(&raw_dnode) {
+            continue;
+        }
+
+        let Some(dnode) = DNode::from_slice_le(&raw_dnode) else {
+            continue;
+        };
+
+        if dnode.get_obj_type() != obj_type {
+            continue;
+        }
+
+        let dnode_block_id = (object_id * 512) / objset.metadnode.parse_data_block_size();
+        let Ok(dnode_block_pointer) = objset
+            .metadnode
+            .get_data_block_pointer(dnode_block_id, vdevs)
+        else {
+            continue;
+        };
+
+        seen_versions.insert(raw_dnode);
+        result.push(PreviousDNodeVersion {
+            uberblock_txg: ub.txg,
+            dnode_block_birth_txg: dnode_block_pointer.get_logical_birth_txg(),
+            dnode,
+        });
+    }
+
+    result
+}
+
+// Recovers a file by its object id (ex. from an old zdb listing or a delete queue entry), even if the dnode was freed or reused since then
+// Every version of the dnode that can still be read through the uberblocks in `txg_range` is tried, newest first,
+// the first one that can be extracted without bad blocks is kept, if there is none the one with the least bad bytes is used
+// Returns: The txg of the uberblock the extracted version was found through and how the extraction went
+pub fn recover_file_by_object_id(
+    uberblocks: &mut [Uberblock],
+    txg_range: RangeInclusive<u64>,
+    object_id: usize,
+    output: &mut File,
+    vdevs: &mut Vdevs,
+) -> Option<(u64, ExtractReport)> {
+    use crate::ansi_color::*;
+    let versions = find_previous_dnode_versions(
+        uberblocks,
+        txg_range,
+        object_id,
+        ObjType::PlainFileContents,
+        RewindObjSet::HeadDataset,
+        vdevs,
+    );
+
+    let mut best: Option<(usize, u64)> = None;
+    let mut versions = versions
+        .into_iter()
+        .filter_map(|version| match version.dnode {
+            DNode::PlainFileContents(file) => Some((version.uberblock_txg, file)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    for (index, (uberblock_txg, file)) in versions.iter_mut().enumerate().rev() {
+        let report = extract_file_version(file, output, vdevs)?;
+        let bad_bytes = report
+            .bad_ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum::<u64>();
+        if bad_bytes == 0 {
+            return Some((*uberblock_txg, report));
+        }
+
+        println!("{YELLOW}Warning{WHITE}: The version of object {object_id} from txg {uberblock_txg} has {bad_bytes} bad bytes, trying older versions!");
+        if best.is_none_or(|(_, best_bad_bytes)| bad_bytes < best_bad_bytes) {
+            best = Some((index, bad_bytes));
+        }
+    }
+
+    // None of the versions were perfect, so start from the best one and fill its bad blocks from the other versions of the same file
+    // The object id might have been reused, so only versions created at the same time as the best one are the same file
+    let (index, _) = best?;
+    let best_crtime =
+        FileAttributes::guess_from_dnode(&versions[index].1).map(|attributes| attributes.crtime);
+    let uberblock_txg = versions[index].0;
+    let mut generations = vec![versions.remove(index).1];
+    generations.extend(
+        versions
+            .into_iter()
+            .rev()
+            .map(|(_, file)| file)
+            .filter(|file| {
+                best_crtime.is_some()
+                    && FileAttributes::guess_from_dnode(file).map(|attributes| attributes.crtime)
+                        == best_crtime
+            }),
+    );
+    let report = extract_file_generations(&mut generations, output, vdevs)?;
+    if generations.len() > 1 {
+        println!(
+            "{CYAN}Info{WHITE}: Merged {} versions of object {object_id}, blocks taken from each: {:?}",
+            generations.len(),
+            report.blocks_per_generation
+        );
+    }
+    Some((uberblock_txg, report.extract))
+}
+
+fn extract_file_version(
+    file: &mut DNodePlainFileContents,
+    output: &mut File,
+    vdevs: &mut Vdevs,
+) -> Option<ExtractReport> {
+    extract_file_generations(std::slice::from_mut(file), output, vdevs).map(|report| report.extract)
+}
+
+// The first generation decides the size of the file
+fn extract_file_generations(
+    generations: &mut [DNodePlainFileContents],
+    output: &mut File,
+    vdevs: &mut Vdevs,
+) -> Option<MergeReport> {
+    let newest = generations.first()?;
+    let size = FileAttributes::guess_from_dnode(newest)
+        .map(|attributes| attributes.size)
+        .unwrap_or(newest.0.get_data_size() as u64);
+    let options = MergeOptions {
+        extract: ExtractOptions {
+            size: Some(size),
+            sparse: true,
+            ..Default::default()
+        },
+        fill_holes: false,
+    };
+    let report =
+        DNodePlainFileContents::extract_merged_to(generations, output, &options, vdevs).ok()?;
+    // A previous attempt might have written more than this version's size
+    output.set_len(size).ok()?;
+    Some(report)
+}
@@ -0,0 +1,57 @@
+// The copy of the pool config the MOS keeps, it's what zdb -C shows and has the same vdev tree as the config in the labels
+// but for the whole pool, so when the labels of all the disks are damaged the layout of the pool can still be found in it
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa.c (spa_config_update, spa_sync_config_object)
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa_config.c (spa_config_generate)
+
+use crate::{
+    dmu::{DNode, ObjSet},
+    nvlist::{self, NVList},
+    zap,
+    zio::Vdevs,
+};
+
+pub fn read_mos_config(mos: &mut ObjSet, vdevs: &mut Vdevs) -> Result<NVList, ()> {
+    let Some(DNode::ObjectDirectory(mut object_directory)) = mos.get_dnode_at(1, vdevs) else {
+        return Err(());
+    };
+    let objdir_zap_data = object_directory.dump_zap_contents(vdevs).ok_or(())?;
+    let Some(zap::Value::U64(config_id)) = objdir_zap_data.get("config") else {
+        return Err(());
+    };
+
+    let Some(DNode::PackedNVList(mut config)) = mos.get_dnode_at(*config_id as usize, vdevs) else {
+        return Err(());
+    };
+    config.read_nvlist(vdevs).ok_or(())
+}
+
+// The vdev tree of the MOS config starts at the root vdev, with the top level vdevs as its children
+// but the one in a label starts at the top level vdev the disk is in, this turns the first into the second
+// so everything that reads the config from a label can use the MOS config instead
+// Returns: None if there is no top level vdev with that index
+// NOTE: The label also has the guid of its disk, which can't be known from the MOS config, so it's not there
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/vdev_label.c (vdev_label_init, vdev_config_generate)
+pub fn into_label_config(mut config: NVList, top_level_index: usize) -> Option<NVList> {
+    let Some(nvlist::Value::NVList(mut root)) = config.remove("vdev_tree") else {
+        return None;
+    };
+    let Some(nvlist::Value::NVListArray(mut top_level_vdevs)) = root.remove("children") else {
+        return None;
+    };
+    if top_level_index >= top_level_vdevs.len() {
+        return None;
+    }
+    let top_level_vdev = top_level_vdevs.swap_remove(top_level_index);
+
+    if let Some(nvlist::Value::U64(top_guid)) = top_level_vdev.get("guid") {
+        config.insert(String::from("top_guid"), nvlist::Value::U64(*top_guid));
+    }
+    config
+        .entry(String::from("vdev_children"))
+        .or_insert(nvlist::Value::U64(top_level_vdevs.len() as u64 + 1));
+    config.insert(
+        String::from("vdev_tree"),
+        nvlist::Value::NVList(top_level_vdev),
+    );
+    Some(config)
+}
@@ -0,0 +1,426 @@
+// Space maps are the logs zfs uses to keep track of which parts of a vdev are allocated, every metaslab has one
+// By replaying them we know which parts of the disk are free, which is where deleted data can be, so undelete doesn't have to scan everything
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/space_map.h
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/space_map.c (space_map_iterate)
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa_log_spacemap.c
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
+
+use crate::{
+    byte_iter::{ByteReader, FromBytesLE},
+    dmu::{DNode, DNodeBase, ObjSet, ObjType},
+    nvlist::{self, NVList},
+    warnings::{self, WarningKind},
+    zap,
+    zio::Vdevs,
+};
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/space_map.h#L55 (space_map_phys_t)
+// NOTE: Newer space maps have a histogram after these fields, we don't need it
+#[derive(Debug)]
+pub struct SpaceMapHeader {
+    // Object number of the space map itself
+    pub object: u64,
+    // Size of the entries in bytes
+    pub length: u64,
+    // Amount of space allocated according to the space map, in bytes
+    pub alloc: i64,
+}
+
+impl<It> FromBytesLE<It> for SpaceMapHeader
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<SpaceMapHeader> {
+        Some(SpaceMapHeader {
+            object: u64::from_bytes_le(data)?,
+            length: u64::from_bytes_le(data)?,
+            alloc: i64::from_bytes_le(data)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpaceMapEntryType {
+    Alloc,
+    Free,
+}
+
+#[derive(Debug, Clone)]
+pub struct SpaceMapEntry {
+    pub typ: SpaceMapEntryType,
+    // Offset and size in bytes, the shift and start of the space map have already been applied
+    pub offset: u64,
+    pub size: u64,
+    // Only two word entries have a vdev, and only log space maps actually use it
+    pub vdev: Option<u64>,
+}
+
+// Vdev id used by two word entries that aren't for a specific vdev
+const SM_NO_VDEVID: u64 = (1 << 24) - 1;
+
+// Decodes the raw entries of a space map, debug entries (and the padding, which is also a debug entry) are skipped
+// `start` is the offset the space map starts at and `shift` is the log2 of the unit offsets and sizes are stored in
+// Returns: None if a two word entry is cut off
+// NOTE: Entries whose range doesn't fit in a u64 are skipped, and counted as corrupt metadata
+pub fn parse_entries(data: &[u8], start: u64, shift: u32) -> Option<Vec<SpaceMapEntry>> {
+    let mut entries = Vec::new();
+    let mut data = ByteReader::new(data);
+    while let Some(word) = u64::from_bytes_le(&mut data) {
+        let (typ, raw_offset, raw_size, vdev) = match word >> 62 {
+            // Debug entry: action (2 bits), sync pass (10 bits), txg (50 bits)
+            0b10 => continue,
+            // Two word entry: padding (2 bits), run (36 bits), vdev (24 bits) then type (1 bit), offset (63 bits)
+            0b11 => {
+                let second_word = u64::from_bytes_le(&mut data)?;
+                let vdev = word & SM_NO_VDEVID;
+                (
+                    second_word >> 63,
+                    second_word & ((1 << 63) - 1),
+                    ((word >> 24) & ((1 << 36) - 1)) + 1,
+                    if vdev == SM_NO_VDEVID {
+                        None
+                    } else {
+                        Some(vdev)
+                    },
+                )
+            }
+            // Single word entry: offset (47 bits), type (1 bit), run (15 bits)
+            _ => (
+                (word >> 15) & 1,
+                (word >> 16) & ((1 << 47) - 1),
+                (word & ((1 << 15) - 1)) + 1,
+                None,
+            ),
+        };
+
+        // A corrupt entry (or shift) can put the range past the end of a u64, zfs would never write that so it's skipped
+        let shifted = |raw: u64| raw.checked_shl(shift).filter(|value| value >> shift == raw);
+        let range = shifted(raw_offset)
+            .and_then(|offset| offset.checked_add(start))
+            .zip(shifted(raw_size))
+            .filter(|(offset, size)| offset.checked_add(*size).is_some());
+        let Some((offset, size)) = range else {
+            warnings::count_warning(WarningKind::CorruptMetadata);
+            if cfg!(feature = "debug") {
+                use crate::ansi_color::*;
+                println!("{YELLOW}Warning{WHITE}: Space map entry with offset {raw_offset} and size {raw_size} doesn't fit in a u64 with shift {shift} and start {start}, skipping it!");
+            }
+            continue;
+        };
+
+        entries.push(SpaceMapEntry {
+            typ: if typ == 0 {
+                SpaceMapEntryType::Alloc
+            } else {
+                SpaceMapEntryType::Free
+            },
+            offset,
+            size,
+            vdev,
+        });
+    }
+    Some(entries)
+}
+
+// A set of non overlapping ranges, touching ranges are merged
+#[derive(Debug, Clone, Default)]
+pub struct RangeTree {
+    // Start -> end
+    ranges: BTreeMap<u64, u64>,
+}
+
+impl RangeTree {
+    pub fn new() -> RangeTree {
+        RangeTree::default()
+    }
+
+    pub fn add(&mut self, range: Range<u64>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        let (mut start, mut end) = (range.start, range.end);
+        if let Some((&prev_start, &prev_end)) = self.ranges.range(..=start).next_back() {
+            if prev_end >= start {
+                start = prev_start;
+                end = end.max(prev_end);
+            }
+        }
+
+        let overlapping = self
+            .ranges
+            .range(start..=end)
+            .map(|(&start, &end)| (start, end))
+            .collect::<Vec<(u64, u64)>>();
+        for (overlapping_start, overlapping_end) in overlapping {
+            self.ranges.remove(&overlapping_start);
+            end = end.max(overlapping_end);
+        }
+        self.ranges.insert(start, end);
+    }
+
+    pub fn remove(&mut self, range: Range<u64>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        // A range that starts before the removed range might need to be cut in two
+        if let Some((&prev_start, &prev_end)) = self.ranges.range(..range.start).next_back() {
+            if prev_end > range.start {
+                self.ranges.insert(prev_start, range.start);
+                if prev_end > range.end {
+                    self.ranges.insert(range.end, prev_end);
+                }
+            }
+        }
+
+        let overlapping = self
+            .ranges
+            .range(range.clone())
+            .map(|(&start, &end)| (start, end))
+            .collect::<Vec<(u64, u64)>>();
+        for (overlapping_start, overlapping_end) in overlapping {
+            self.ranges.remove(&overlapping_start);
+            if overlapping_end > range.end {
+                self.ranges.insert(range.end, overlapping_end);
+            }
+        }
+    }
+
+    pub fn contains(&self, offset: u64) -> bool {
+        self.ranges
+            .range(..=offset)
+            .next_back()
+            .is_some_and(|(_, &end)| offset < end)
+    }
+
+    // Returns: The ranges in order
+    pub fn get_ranges(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+        self.ranges.iter().map(|(&start, &end)| start..end)
+    }
+
+    // Returns: The total amount of bytes covered by the ranges
+    pub fn get_size(&self) -> u64 {
+        self.ranges.iter().map(|(start, end)| end - start).sum()
+    }
+
+    // Returns: Everything in `bounds` that is not in the tree
+    pub fn complement(&self, bounds: Range<u64>) -> RangeTree {
+        let mut result = RangeTree::new();
+        result.add(bounds);
+        for range in self.get_ranges() {
+            result.remove(range);
+        }
+        result
+    }
+
+    pub fn apply_entry(&mut self, entry: &SpaceMapEntry) {
+        let range = entry.offset..entry.offset + entry.size;
+        match entry.typ {
+            SpaceMapEntryType::Alloc => self.add(range),
+            SpaceMapEntryType::Free => self.remove(range),
+        }
+    }
+}
+
+fn get_u64(nvlist: &NVList, name: &str) -> Result<u64, ()> {
+    match nvlist.get(name) {
+        Some(nvlist::Value::U64(value)) => Ok(*value),
+        _ => Err(()),
+    }
+}
+
+// Returns: The raw contents of an object that doesn't have a DNode variant of its own (ex. an object array), if it is of one of the expected types
+fn read_object(
+    mos: &mut ObjSet,
+    object_id: u64,
+    obj_types: &[ObjType],
+    size: usize,
+    vdevs: &mut Vdevs,
+) -> Result<Vec<u8>, ()> {
+    let raw_dnode = mos.get_raw_dnode_at(object_id as usize, vdevs).ok_or(())?;
+    let (mut dnode, obj_type, _) = DNodeBase::from_slice_le(&raw_dnode).ok_or(())?;
+    if !obj_types.contains(&obj_type) {
+        return Err(());
+    }
+    dnode.read(0, size, vdevs)
+}
+
+fn read_zap_object(
+    mos: &mut ObjSet,
+    object_id: u64,
+    vdevs: &mut Vdevs,
+) -> Result<HashMap<String, zap::Value>, ()> {
+    let (mut zap_dnode, obj_type) = mos.get_zap_dnode_at(object_id as usize, vdevs).ok_or(())?;
+    if !matches!(
+        obj_type,
+        ObjType::NewZapMetadata | ObjType::NewZapData | ObjType::ZapOther
+    ) {
+        return Err(());
+    }
+    zap_dnode.dump_zap_contents(vdevs).ok_or(())
+}
+
+fn read_space_map(
+    mos: &mut ObjSet,
+    object_id: u64,
+    start: u64,
+    shift: u32,
+    vdevs: &mut Vdevs,
+) -> Result<Vec<SpaceMapEntry>, ()> {
+    let Some(DNode::SpaceMap(mut space_map)) = mos.get_dnode_at(object_id as usize, vdevs) else {
+        return Err(());
+    };
+    parse_entries(&space_map.read_raw_entries(vdevs)?, start, shift).ok_or(())
+}
+
+// Returns: The first txg of every metaslab whose changes are still in the log space maps, if the vdev has any
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/metaslab.c (metaslab_unflushed_txg)
+fn read_unflushed_txgs(
+    mos: &mut ObjSet,
+    vdev_tree: &NVList,
+    nmetaslabs: usize,
+    vdevs: &mut Vdevs,
+) -> Result<Vec<u64>, ()> {
+    let top_zap_id = get_u64(vdev_tree, "com.delphix:vdev_zap_top")?;
+    let top_zap = read_zap_object(mos, top_zap_id, vdevs)?;
+    let Some(zap::Value::U64(unflushed_txgs_id)) =
+        top_zap.get("com.delphix:ms_unflushed_phys_txgs")
+    else {
+        return Err(());
+    };
+
+    let data = read_object(
+        mos,
+        *unflushed_txgs_id,
+        &[ObjType::NewUInt64Metadata],
+        nmetaslabs * core::mem::size_of::<u64>(),
+        vdevs,
+    )?;
+    let mut data = ByteReader::new(&data);
+    Ok((0..nmetaslabs)
+        .map_while(|_| u64::from_bytes_le(&mut data))
+        .collect())
+}
+
+// Returns: The log space maps of the pool sorted by txg, as (txg, space map object) pairs
+fn read_log_space_maps(mos: &mut ObjSet, vdevs: &mut Vdevs) -> Result<Vec<(u64, u64)>, ()> {
+    let Some(DNode::ObjectDirectory(mut object_directory)) = mos.get_dnode_at(1, vdevs) else {
+        return Err(());
+    };
+    let objdir_zap_data = object_directory.dump_zap_contents(vdevs).ok_or(())?;
+    let Some(zap::Value::U64(log_space_map_zap_id)) =
+        objdir_zap_data.get("com.delphix:log_spacemap_zap")
+    else {
+        // The pool doesn't use log space maps
+        return Ok(Vec::new());
+    };
+
+    // The names are the txgs in hex
+    let mut log_space_maps = read_zap_object(mos, *log_space_map_zap_id, vdevs)?
+        .into_iter()
+        .filter_map(|(name, value)| match value {
+            zap::Value::U64(object_id) => Some((u64::from_str_radix(&name, 16).ok()?, object_id)),
+            _ => None,
+        })
+        .collect::<Vec<(u64, u64)>>();
+    log_space_maps.sort_unstable();
+    Ok(log_space_maps)
+}
+
+// Replays the space maps of all metaslabs of the top level vdev, and then the log space maps on top of them
+// `vdev_tree` is the vdev tree nvlist from the label
+// Returns: The allocated parts of the vdev, in the same offsets dvas use
+// NOTE: Metaslabs whose space map can't be read are treated as completely free, since for recovery scanning too much is better than missing data
+pub fn read_allocated_space(
+    mos: &mut ObjSet,
+    vdev_tree: &NVList,
+    vdevs: &mut Vdevs,
+) -> Result<RangeTree, ()> {
+    use crate::ansi_color::*;
+    let vdev_id = get_u64(vdev_tree, "id")?;
+    let metaslab_array_id = get_u64(vdev_tree, "metaslab_array")?;
+    let metaslab_shift = get_u64(vdev_tree, "metaslab_shift")?;
+    let ashift = get_u64(vdev_tree, "ashift")?;
+    let asize = get_u64(vdev_tree, "asize")?;
+    let nmetaslabs = (asize >> metaslab_shift) as usize;
+
+    let metaslab_array = read_object(
+        mos,
+        metaslab_array_id,
+        &[ObjType::ObjectArray],
+        nmetaslabs * core::mem::size_of::<u64>(),
+        vdevs,
+    )?;
+    let mut metaslab_array = ByteReader::new(&metaslab_array);
+
+    let mut allocated = RangeTree::new();
+    for metaslab_id in 0..nmetaslabs {
+        let space_map_id = u64::from_bytes_le(&mut metaslab_array).ok_or(())?;
+        // Metaslabs that were never allocated from don't have a space map yet
+        if space_map_id == 0 {
+            continue;
+        }
+
+        let metaslab_start = (metaslab_id as u64) << metaslab_shift;
+        let Ok(entries) = read_space_map(mos, space_map_id, metaslab_start, ashift as u32, vdevs)
+        else {
+            println!("{YELLOW}Warning{WHITE}: Couldn't read the space map of metaslab {metaslab_id}, assuming it's free!");
+            continue;
+        };
+        for entry in entries.iter() {
+            allocated.apply_entry(entry);
+        }
+    }
+
+    let log_space_maps = read_log_space_maps(mos, vdevs)?;
+    if log_space_maps.is_empty() {
+        return Ok(allocated);
+    }
+
+    // Log space maps that are older than a metaslab's last flush are already part of its space map and must not be replayed again
+    let unflushed_txgs = read_unflushed_txgs(mos, vdev_tree, nmetaslabs, vdevs)
+        .map_err(|_| {
+            println!("{YELLOW}Warning{WHITE}: Couldn't read when the metaslabs were last flushed, replaying all log space maps!");
+        })
+        .unwrap_or_default();
+
+    for (txg, space_map_id) in log_space_maps {
+        // Log space maps cover the whole pool, in 512 byte units
+        let Ok(entries) = read_space_map(mos, space_map_id, 0, 9, vdevs) else {
+            println!("{YELLOW}Warning{WHITE}: Couldn't read the log space map of txg {txg}, skipping it!");
+            continue;
+        };
+
+        for entry in entries.iter() {
+            if entry.vdev != Some(vdev_id) {
+                continue;
+            }
+
+            let metaslab_id = (entry.offset >> metaslab_shift) as usize;
+            if unflushed_txgs
+                .get(metaslab_id)
+                .is_some_and(|&unflushed_txg| txg < unflushed_txg)
+            {
+                continue;
+            }
+            allocated.apply_entry(entry);
+        }
+    }
+
+    Ok(allocated)
+}
+
+// Returns: The free parts of the vdev, in order
+pub fn read_free_space(
+    mos: &mut ObjSet,
+    vdev_tree: &NVList,
+    vdevs: &mut Vdevs,
+) -> Result<RangeTree, ()> {
+    let asize = get_u64(vdev_tree, "asize")?;
+    Ok(read_allocated_space(mos, vdev_tree, vdevs)?.complement(0..asize))
+}
@@ -0,0 +1,1029 @@
+// Builds small pools in memory with known contents, so reading pools can be tested without real disks or the zfs tools (see tests/synthetic_pool.rs)
+// Everything is laid out like zfs does it: the blocks are spread over the disks of a raidz with their parity, the objects are dnodes under a metadnode
+// and the labels have the config and the uberblocks, but only what szfs reads is filled in
+// NOTE: The embedded checksums of the labels and uberblocks are just the magic, there are no space maps, and the datasets use the old znodes instead of system attributes
+// so zfs itself wouldn't import these
+// Source: http://www.giis.co.in/Zfs_ondiskformat.pdf
+
+use std::collections::BTreeMap;
+
+use crate::{
+    dmu::{BonusType, ObjSetType, ObjType},
+    fletcher, nvlist, parity,
+    zap::MicroZap,
+    zio::{BlockPointer, ChecksumMethod, CompressionMethod},
+    VdevLabel, EMBEDDED_CHECKSUM_MAGIC, UBERBLOCK_MAGIC, VDEV_LABEL_END_SIZE, VDEV_LABEL_SIZE,
+    VDEV_LABEL_START_SIZE,
+};
+
+// Pools with feature flags have this spa version
+const SPA_VERSION_FEATURES: u64 = 5000;
+// The last zpl version before system attributes, so files have the old znodes
+const ZPL_VERSION: u64 = 4;
+// Every timestamp in the image, so images are always the same
+const TIMESTAMP: u64 = 1_700_000_000;
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dnode.h (DNODE_FLAG_USED_BYTES, DNODE_FLAG_SPILL_BLKPTR)
+const DNODE_FLAG_USED_BYTES: u8 = 1 << 0;
+const DNODE_FLAG_SPILL_BLKPTR: u8 = 1 << 2;
+// How big the blocks of the metadnode and the indirect blocks are
+const DNODE_BLOCK_SIZE: usize = 16 * 1024;
+const INDIRECT_BLOCKSIZE_LOG2: u8 = 14;
+// The bonus buffer of a dnode with one block pointer can be this big
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dnode.h (DN_OLD_MAX_BONUSLEN)
+const MAX_BONUS_LEN: usize = 320;
+// The sizes the bonus buffers have in zfs, only the start of them is parsed
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dsl_dir.h (dsl_dir_phys_t)
+const DSL_DIRECTORY_BONUS_LEN: usize = 256;
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/dsl_dataset.h (dsl_dataset_phys_t)
+const DSL_DATASET_BONUS_LEN: usize = 320;
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h (ZFS_OLD_ZNODE_PHYS_SIZE)
+const ZNODE_BONUS_LEN: usize = 0x108;
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/os/linux/spl/sys/stat.h
+const S_IFDIR: u64 = 0o040000;
+const S_IFREG: u64 = 0o100000;
+// The type of a directory entry is in its top 4 bits, see zpl::DirectoryEntryKind
+const DIRECTORY_ENTRY_DIRECTORY: u64 = 4;
+const DIRECTORY_ENTRY_FILE: u64 = 8;
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zio.h (ZIO_GANG_MAGIC)
+const GANG_HEADER_MAGIC: u64 = 0x210da7ab10c7a11;
+
+#[derive(Debug, Clone)]
+pub struct ImageConfig {
+    pub pool_name: String,
+    pub pool_guid: u64,
+    pub ndevices: usize,
+    pub nparity: usize,
+    pub ashift: u64,
+    // How big every disk image is, labels included, it has to be a multiple of the size of a label
+    pub disk_size: u64,
+    // The txg of the uberblock ImageBuilder::build writes, every block is born in it
+    pub txg: u64,
+    // Files bigger than this are split into blocks of this size, smaller ones are one block, like the recordsize property of a dataset
+    pub record_size: usize,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            pool_name: String::from("testpool"),
+            pool_guid: 0x5a17_0000_0000_1000,
+            ndevices: 3,
+            nparity: 1,
+            ashift: 9,
+            disk_size: 32 * 1024 * 1024,
+            txg: 10,
+            record_size: 128 * 1024,
+        }
+    }
+}
+
+impl ImageConfig {
+    pub fn get_top_guid(&self) -> u64 {
+        self.pool_guid.wrapping_add(1)
+    }
+
+    pub fn get_device_guid(&self, device: usize) -> u64 {
+        self.pool_guid.wrapping_add(2 + device as u64)
+    }
+
+    // The sum of the guids of every vdev, starting at the root whose guid is the pool guid, this is what the uberblocks have
+    pub fn get_guid_sum(&self) -> u64 {
+        (0..self.ndevices).fold(
+            self.pool_guid.wrapping_add(self.get_top_guid()),
+            |sum, device| sum.wrapping_add(self.get_device_guid(device)),
+        )
+    }
+
+    pub fn get_sector_size(&self) -> usize {
+        1 << self.ashift
+    }
+}
+
+// A dva like it's stored in a block pointer, see zio::DataVirtualAddress
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DvaSpec {
+    pub vdev_id: u32,
+    // In bytes, from the end of the boot block
+    pub offset: u64,
+    // In bytes, including the parity and the padding
+    pub allocated_size: u64,
+    pub is_gang: bool,
+}
+
+impl DvaSpec {
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (dva_t, DVA_GET_ASIZE, DVA_GET_OFFSET)
+    pub fn to_bytes_le(&self) -> [u8; 16] {
+        let mut res = [0u8; 16];
+        res[0..4]
+            .copy_from_slice(&(((self.allocated_size / 512) as u32) & 0x00_FF_FF_FF).to_le_bytes());
+        res[4..8].copy_from_slice(&self.vdev_id.to_le_bytes());
+        let offset_and_gang_bit = (self.offset / 512) | if self.is_gang { 1 << 63 } else { 0 };
+        res[8..16].copy_from_slice(&offset_and_gang_bit.to_le_bytes());
+        res
+    }
+}
+
+// A normal block pointer, written without going through zio::NormalBlockPointer so tests don't check the parser against itself
+#[derive(Debug, Clone)]
+pub struct BlockPointerSpec {
+    pub dvas: Vec<DvaSpec>,
+    pub level: usize,
+    pub typ: ObjType,
+    pub checksum_method: ChecksumMethod,
+    pub compression_method: CompressionMethod,
+    // In bytes, both have to be multiples of 512
+    pub physical_size: usize,
+    pub logical_size: usize,
+    pub birth_txg: u64,
+    pub fill: u64,
+    pub checksum: [u64; 4],
+}
+
+impl BlockPointerSpec {
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (blkptr_t, BP_SET_*)
+    pub fn to_bytes_le(&self) -> [u8; 128] {
+        let mut res = [0u8; 128];
+        for (index, dva) in self.dvas.iter().take(3).enumerate() {
+            res[index * 16..(index + 1) * 16].copy_from_slice(&dva.to_bytes_le());
+        }
+        let info = (1u64 << 63) // little endian
+            | ((self.level as u64 & 0b1_1111) << 56)
+            | ((self.typ as u64 & 0b1111_1111) << 48)
+            | ((self.checksum_method as u64 & 0b1111_1111) << 40)
+            | ((self.compression_method as u64 & 0b0111_1111) << 32)
+            | (((self.physical_size / 512 - 1) as u64 & 0xFFFF) << 16)
+            | ((self.logical_size / 512 - 1) as u64 & 0xFFFF);
+        res[48..56].copy_from_slice(&info.to_le_bytes());
+        // The physical birth txg is 0 when it's the same as the logical one
+        res[80..88].copy_from_slice(&self.birth_txg.to_le_bytes());
+        res[88..96].copy_from_slice(&self.fill.to_le_bytes());
+        for (index, word) in self.checksum.iter().enumerate() {
+            res[96 + index * 8..104 + index * 8].copy_from_slice(&word.to_le_bytes());
+        }
+        res
+    }
+}
+
+// An embedded block pointer, the data is in the block pointer itself
+#[derive(Debug, Clone)]
+pub struct EmbeddedBlockPointerSpec {
+    // At most 112 bytes, the rest of the payload is zeros
+    pub payload: Vec<u8>,
+    pub level: usize,
+    pub typ: ObjType,
+    // The raw value, so invalid ones can be written too, see zio::EmbeddedType
+    pub embedded_type: u64,
+    pub compression_method: CompressionMethod,
+    // In bytes, they don't have to be multiples of 512
+    pub physical_size: usize,
+    pub logical_size: usize,
+    pub birth_txg: u64,
+}
+
+impl EmbeddedBlockPointerSpec {
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (BPE_SET_ETYPE, BPE_SET_LSIZE, BPE_SET_PSIZE)
+    // And: https://github.com/openzfs/zfs/blob/master/module/zfs/blkptr.c (encode_embedded_bp_compressed)
+    pub fn to_bytes_le(&self) -> [u8; 128] {
+        assert!(self.payload.len() <= 112, "The payload can hold 112 bytes");
+        let mut res = [0u8; 128];
+        // The payload is everything but the info word and the logical birth txg
+        let payload_ranges = [0..48, 56..80, 88..128];
+        let mut payload = self.payload.iter().copied();
+        for range in payload_ranges {
+            for (byte, value) in res[range].iter_mut().zip(&mut payload) {
+                *byte = value;
+            }
+        }
+        // Redacted block pointers are made without setting the byte order
+        // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dbuf.c (dmu_buf_redact)
+        let byte_order = if self.embedded_type == 2 {
+            0
+        } else {
+            1u64 << 63
+        };
+        let info = byte_order
+            | ((self.level as u64 & 0b1_1111) << 56)
+            | ((self.typ as u64 & 0b1111_1111) << 48)
+            | ((self.embedded_type & 0b1111_1111) << 40)
+            | (1 << 39) // embedded
+            | ((self.compression_method as u64 & 0b0111_1111) << 32)
+            | (((self.physical_size - 1) as u64 & 0b111_1111) << 25)
+            | ((self.logical_size - 1) as u64 & ((1 << 25) - 1));
+        res[48..56].copy_from_slice(&info.to_le_bytes());
+        res[80..88].copy_from_slice(&self.birth_txg.to_le_bytes());
+        res
+    }
+}
+
+// A gang header with up to 3 block pointers, pointed to by a block pointer whose first dva is first_dva
+// The checksum is computed here, not with zio::GangBlock::compute_checksum, so the tests don't check it against itself
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zio.h (zio_gbh_phys_t)
+// And: https://github.com/openzfs/zfs/blob/master/module/zfs/zio_checksum.c (zio_checksum_gang_verifier)
+pub fn gang_header_bytes(bps: &[[u8; 128]], first_dva: &DvaSpec, birth_txg: u64) -> Vec<u8> {
+    use sha2::{Digest, Sha256};
+    assert!(bps.len() <= 3, "A gang header has at most 3 block pointers");
+    let mut res = vec![0u8; 512];
+    for (index, bp) in bps.iter().enumerate() {
+        res[index * 128..(index + 1) * 128].copy_from_slice(bp);
+    }
+    res[472..480].copy_from_slice(&GANG_HEADER_MAGIC.to_le_bytes());
+    for (index, word) in [u64::from(first_dva.vdev_id), first_dva.offset, birth_txg, 0]
+        .iter()
+        .enumerate()
+    {
+        res[480 + index * 8..488 + index * 8].copy_from_slice(&word.to_le_bytes());
+    }
+    let digest = Sha256::digest(&res);
+    // The words of the digest are big endian, and stored in the byte order of the pool like the rest of the checksums
+    for (index, bytes) in digest.chunks_exact(8).enumerate() {
+        let word = u64::from_be_bytes(bytes.try_into().unwrap());
+        res[480 + index * 8..488 + index * 8].copy_from_slice(&word.to_le_bytes());
+    }
+    res
+}
+
+// A dnode as it's stored in a block of the metadnode
+#[derive(Debug, Clone)]
+pub struct DNodeSpec {
+    pub typ: ObjType,
+    pub bonus_type: BonusType,
+    pub n_indirect_levels: u8,
+    pub data_block_size: usize,
+    pub max_block_id: u64,
+    // In bytes
+    pub used: u64,
+    // The top level of the tree of the object, holes included
+    pub block_pointers: Vec<[u8; 128]>,
+    // How many block pointers there is room for, at least block_pointers.len()
+    pub n_block_pointer_slots: usize,
+    pub bonus: Vec<u8>,
+    // Written in the last 128 bytes of the dnode, after the padding
+    pub spill_block_pointer: Option<[u8; 128]>,
+}
+
+impl DNodeSpec {
+    // Like zfs, a dnode has as many block pointers as fit next to its bonus buffer in a 512 byte dnode, but at least one
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dnode.c (dnode_allocate)
+    pub fn get_n_block_pointer_slots_for_bonus(bonus_len: usize) -> usize {
+        (1 + MAX_BONUS_LEN.saturating_sub(bonus_len) / BlockPointer::get_ondisk_size()).min(3)
+    }
+
+    // Returns: The dnode padded to a whole number of 512 byte slots
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/dnode.h (dnode_phys_t)
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let n_block_pointer_slots = self.n_block_pointer_slots.max(self.block_pointers.len());
+        let spill_size = if self.spill_block_pointer.is_some() {
+            128
+        } else {
+            0
+        };
+        let size = (64 + n_block_pointer_slots * 128 + self.bonus.len() + spill_size)
+            .next_multiple_of(512);
+        let flags = if self.spill_block_pointer.is_some() {
+            DNODE_FLAG_USED_BYTES | DNODE_FLAG_SPILL_BLKPTR
+        } else {
+            DNODE_FLAG_USED_BYTES
+        };
+
+        let mut res = Vec::with_capacity(size);
+        res.push(self.typ as u8);
+        res.push(INDIRECT_BLOCKSIZE_LOG2);
+        res.push(self.n_indirect_levels);
+        res.push(n_block_pointer_slots as u8);
+        res.push(self.bonus_type as u8);
+        res.push(ChecksumMethod::Inherit as u8);
+        res.push(CompressionMethod::Inherit as u8);
+        res.push(flags);
+        res.extend(((self.data_block_size / 512) as u16).to_le_bytes());
+        res.extend((self.bonus.len() as u16).to_le_bytes());
+        res.push((size / 512 - 1) as u8); // extra slots
+        res.extend([0u8; 3]);
+        res.extend(self.max_block_id.to_le_bytes());
+        res.extend(self.used.to_le_bytes());
+        res.extend([0u8; 4 * 8]);
+        for block_pointer in self.block_pointers.iter() {
+            res.extend(block_pointer);
+        }
+        res.resize(64 + n_block_pointer_slots * 128, 0);
+        res.extend(self.bonus.iter());
+        res.resize(size - spill_size, 0);
+        if let Some(spill_block_pointer) = &self.spill_block_pointer {
+            res.extend(spill_block_pointer);
+        }
+        res
+    }
+}
+
+// Returns: A micro zap with these entries, in a block just big enough for them
+pub fn micro_zap_bytes(entries: &[(&str, u64)]) -> Vec<u8> {
+    let size = (64 + entries.len() * 64).next_multiple_of(512);
+    let mut empty_zap = vec![0u8; size];
+    // The type of a micro zap and a salt, the salt is only used for the hashes of the names
+    empty_zap[0..8].copy_from_slice(&((1u64 << 63) + 3).to_le_bytes());
+    empty_zap[8..16].copy_from_slice(&0x5a17_5a17_5a17_5a17u64.to_le_bytes());
+
+    let mut zap = MicroZap::from_bytes_le(&empty_zap).expect("An empty micro zap should parse");
+    for (name, value) in entries {
+        zap.set_entry(name, *value)
+            .expect("Micro zap entry names should fit in an entry");
+    }
+    let mut res = zap.to_bytes_le();
+    res.resize(size, 0);
+    res
+}
+
+// Returns: The bonus buffer of a file or directory from before system attributes
+pub fn znode_bonus(mode: u64, size: u64, parent: u64, links: u64) -> Vec<u8> {
+    let mut res = Vec::with_capacity(ZNODE_BONUS_LEN);
+    // atime, mtime, ctime, crtime as [seconds, nanoseconds]
+    for _ in 0..4 {
+        res.extend(TIMESTAMP.to_le_bytes());
+        res.extend(0u64.to_le_bytes());
+    }
+    // gen, mode, size, parent, links, xattr, rdev, flags, uid, gid
+    for value in [1, mode, size, parent, links, 0, 0, 0, 0, 0] {
+        res.extend(u64::to_le_bytes(value));
+    }
+    res.resize(ZNODE_BONUS_LEN, 0);
+    res
+}
+
+// Returns: Bytes that don't repeat every sector or compress, different for every seed, so data read from the wrong place is noticed
+pub fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len as u64)
+        .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8 ^ seed)
+        .collect()
+}
+
+// A file or a directory in a dataset of the image
+enum Node {
+    Directory,
+    File(Vec<u8>),
+}
+
+// The contents of a dataset, by the path of everything in it, the root directory is ""
+type DatasetContents = BTreeMap<String, Node>;
+
+// Returns: The path of the directory that has `path` in it, "" is the root directory
+fn get_parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(parent, _)| parent)
+}
+
+pub struct ImageBuilder {
+    config: ImageConfig,
+    disks: Vec<Vec<u8>>,
+    // Blocks are allocated one after the other, starting at the first sector of the raidz
+    next_sector: u64,
+    // The uberblocks that are written as well as the one build writes, by txg
+    extra_uberblocks: BTreeMap<u64, [u8; 128]>,
+    // By the name of the dataset relative to the pool, the root dataset is ""
+    datasets: BTreeMap<String, DatasetContents>,
+}
+
+impl ImageBuilder {
+    pub fn new(config: ImageConfig) -> ImageBuilder {
+        assert!(
+            config.ndevices > config.nparity && (1..=3).contains(&config.nparity),
+            "A raidz needs 1 to 3 parity disks and at least one data disk"
+        );
+        assert!(
+            config.disk_size.is_multiple_of(VDEV_LABEL_SIZE)
+                && config.disk_size > VDEV_LABEL_START_SIZE + VDEV_LABEL_END_SIZE,
+            "The disks have to be a whole number of labels, and bigger than the labels and the boot block"
+        );
+        let disks = vec![vec![0u8; config.disk_size as usize]; config.ndevices];
+        let mut builder = ImageBuilder {
+            config,
+            disks,
+            next_sector: 0,
+            extra_uberblocks: BTreeMap::new(),
+            datasets: BTreeMap::new(),
+        };
+        builder.add_dataset("");
+        builder
+    }
+
+    pub fn get_config(&self) -> &ImageConfig {
+        &self.config
+    }
+
+    // `name` is relative to the pool, ex. "home" is the dataset testpool/home, only children of the root dataset can be made
+    pub fn add_dataset(&mut self, name: &str) {
+        self.datasets
+            .entry(String::from(name))
+            .or_insert_with(|| DatasetContents::from([(String::new(), Node::Directory)]));
+    }
+
+    // The directories above it are made if they aren't there, so is the dataset
+    pub fn add_directory(&mut self, dataset: &str, path: &str) {
+        self.add_dataset(dataset);
+        let contents = self.datasets.get_mut(dataset).unwrap();
+        let mut parent = String::new();
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            if !parent.is_empty() {
+                parent.push('/');
+            }
+            parent.push_str(component);
+            contents.entry(parent.clone()).or_insert(Node::Directory);
+        }
+    }
+
+    pub fn add_file(&mut self, dataset: &str, path: &str, data: &[u8]) {
+        let path = path.trim_matches('/');
+        let (parent, _) = path.rsplit_once('/').unwrap_or(("", path));
+        self.add_directory(dataset, parent);
+        self.datasets
+            .get_mut(dataset)
+            .unwrap()
+            .insert(String::from(path), Node::File(data.to_vec()));
+    }
+
+    // Puts another uberblock in the labels, ex. a newer one whose rootbp can't be read
+    pub fn add_uberblock(&mut self, txg: u64, rootbp: [u8; 128]) {
+        self.extra_uberblocks.insert(txg, rootbp);
+    }
+
+    // Writes a sector of the raidz, sector_index is in the sectors of the raidz like VdevRaidz::read_sector
+    fn write_sector(&mut self, sector_index: u64, data: &[u8]) {
+        let sector_size = self.config.get_sector_size();
+        let device = (sector_index % self.config.ndevices as u64) as usize;
+        let offset = VDEV_LABEL_START_SIZE as usize
+            + (sector_index / self.config.ndevices as u64) as usize * sector_size;
+        assert!(
+            offset + sector_size <= self.disks[device].len() - VDEV_LABEL_END_SIZE as usize,
+            "The image is full, make the disks bigger"
+        );
+        self.disks[device][offset..offset + sector_size].copy_from_slice(data);
+    }
+
+    // Writes the data on the raidz with its parity, with the columns where VdevRaidz::read_columns expects them
+    // Returns: The dva of the data, blocks are allocated one after the other
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/vdev_raidz.c (vdev_raidz_map_alloc, vdev_raidz_asize)
+    pub fn write_raw(&mut self, data: &[u8]) -> DvaSpec {
+        let sector_size = self.config.get_sector_size();
+        let (ndevices, nparity) = (self.config.ndevices, self.config.nparity);
+        let first_sector = self.next_sector;
+        let offset = first_sector * sector_size as u64;
+
+        let ndata_sectors = data.len().div_ceil(sector_size).max(1);
+        let ndata_columns = ndevices - nparity;
+        let full_rows = ndata_sectors / ndata_columns;
+        let remainder = ndata_sectors % ndata_columns;
+        let nbig_columns = if remainder == 0 {
+            0
+        } else {
+            remainder + nparity
+        };
+        let ncolumns = if full_rows == 0 {
+            nbig_columns
+        } else {
+            ndevices
+        };
+        let nrows = |column: usize| full_rows + usize::from(column < nbig_columns);
+
+        // The data is split into columns in column major order
+        let mut padded_data = data.to_vec();
+        padded_data.resize(ndata_sectors * sector_size, 0);
+        let mut data_columns = Vec::new();
+        let mut position = 0;
+        for column in nparity..ncolumns {
+            let column_size = nrows(column) * sector_size;
+            data_columns.push(&padded_data[position..position + column_size]);
+            position += column_size;
+        }
+        let parity_columns =
+            parity::generate_parity(&data_columns, nparity, nrows(0) * sector_size);
+        let columns = parity_columns
+            .iter()
+            .map(|column| column.as_slice())
+            .chain(data_columns.iter().copied())
+            .map(|column| column.to_vec())
+            .collect::<Vec<_>>();
+
+        // The raidz1 quirk, on odd megabyte offsets the parity and the first data column switch places
+        let mut column_mapping = (0..ncolumns).collect::<Vec<usize>>();
+        if nparity == 1 && !(offset / (1024 * 1024)).is_multiple_of(2) {
+            column_mapping.swap(0, 1);
+        }
+        for (column_index, column) in columns.iter().enumerate() {
+            for (row, sector) in column.chunks(sector_size).enumerate() {
+                self.write_sector(
+                    first_sector + (column_mapping[column_index] + row * ndevices) as u64,
+                    sector,
+                );
+            }
+        }
+
+        // Allocations are rounded up to a multiple of nparity + 1 sectors, so no free space is too small to be used
+        let nsectors = (ndata_sectors + nparity * nrows(0)).next_multiple_of(nparity + 1);
+        self.next_sector += nsectors as u64;
+        DvaSpec {
+            vdev_id: 0,
+            offset,
+            allocated_size: (nsectors * sector_size) as u64,
+            is_gang: false,
+        }
+    }
+
+    // Returns: Where write_raw will put the next data, in bytes from the end of the boot block
+    pub fn get_next_offset(&self) -> u64 {
+        self.next_sector * self.config.get_sector_size() as u64
+    }
+
+    // Writes a gang header with the block pointers, for a block born in the txg of the image
+    // Returns: The gang dva of the header, it has to be the first dva of the block pointer to the header
+    pub fn write_gang_header(&mut self, bps: &[[u8; 128]]) -> DvaSpec {
+        let first_dva = DvaSpec {
+            vdev_id: 0,
+            offset: self.get_next_offset(),
+            allocated_size: 0,
+            is_gang: true,
+        };
+        let header = gang_header_bytes(bps, &first_dva, self.config.txg);
+        let dva = self.write_raw(&header);
+        assert_eq!(dva.offset, first_dva.offset);
+        DvaSpec {
+            is_gang: true,
+            ..dva
+        }
+    }
+
+    // Writes the data as an uncompressed block with a fletcher4 checksum, the data is padded to a multiple of 512 bytes
+    pub fn write_block(&mut self, data: &[u8], typ: ObjType, level: usize) -> BlockPointerSpec {
+        let mut data = data.to_vec();
+        data.resize(data.len().next_multiple_of(512).max(512), 0);
+        let dva = self.write_raw(&data);
+        BlockPointerSpec {
+            dvas: vec![dva],
+            level,
+            typ,
+            checksum_method: ChecksumMethod::Fletcher4,
+            compression_method: CompressionMethod::Off,
+            physical_size: data.len(),
+            logical_size: data.len(),
+            birth_txg: self.config.txg,
+            fill: 1,
+            checksum: fletcher::do_fletcher4(&data),
+        }
+    }
+
+    // Writes the data of an object in blocks of block_size, and the indirect blocks above them until the top level fits in the dnode
+    // NOTE: Empty data isn't written at all, the dnode only has a hole
+    pub fn write_object(
+        &mut self,
+        typ: ObjType,
+        bonus_type: BonusType,
+        bonus: Vec<u8>,
+        data: &[u8],
+        block_size: usize,
+    ) -> DNodeSpec {
+        let n_block_pointer_slots = DNodeSpec::get_n_block_pointer_slots_for_bonus(bonus.len());
+        let mut used = 0;
+        let mut block_pointers = Vec::new();
+        for block in data.chunks(block_size) {
+            let mut block = block.to_vec();
+            block.resize(block_size, 0);
+            let block_pointer = self.write_block(&block, typ, 0);
+            used += block_pointer.dvas[0].allocated_size;
+            block_pointers.push(block_pointer.to_bytes_le());
+        }
+        let max_block_id = block_pointers.len().saturating_sub(1) as u64;
+
+        let mut n_indirect_levels = 1;
+        let block_pointers_per_indirect_block =
+            (1 << INDIRECT_BLOCKSIZE_LOG2) / BlockPointer::get_ondisk_size();
+        while block_pointers.len() > n_block_pointer_slots {
+            block_pointers = block_pointers
+                .chunks(block_pointers_per_indirect_block)
+                .map(|children| {
+                    let mut indirect_block = children.concat();
+                    indirect_block.resize(1 << INDIRECT_BLOCKSIZE_LOG2, 0);
+                    let block_pointer =
+                        self.write_block(&indirect_block, typ, usize::from(n_indirect_levels));
+                    used += block_pointer.dvas[0].allocated_size;
+                    block_pointer.to_bytes_le()
+                })
+                .collect();
+            n_indirect_levels += 1;
+        }
+
+        DNodeSpec {
+            typ,
+            bonus_type,
+            n_indirect_levels,
+            data_block_size: block_size,
+            max_block_id,
+            used,
+            block_pointers,
+            n_block_pointer_slots,
+            bonus,
+            spill_block_pointer: None,
+        }
+    }
+
+    // Same as write_object, for a zap that fits in one block
+    fn write_micro_zap(
+        &mut self,
+        typ: ObjType,
+        bonus_type: BonusType,
+        bonus: Vec<u8>,
+        entries: &[(&str, u64)],
+    ) -> DNodeSpec {
+        let zap = micro_zap_bytes(entries);
+        self.write_object(typ, bonus_type, bonus, &zap, zap.len())
+    }
+
+    // Writes the dnodes under a metadnode, and the objset with it
+    // Returns: The block pointer to the objset
+    pub fn write_objset(
+        &mut self,
+        typ: ObjSetType,
+        dnodes: &BTreeMap<u64, DNodeSpec>,
+    ) -> BlockPointerSpec {
+        // Object 0 is the metadnode itself, its slot is left empty
+        let nslots = dnodes
+            .keys()
+            .max()
+            .map_or(1, |max_object_id| max_object_id + 1);
+        let mut dnode_data = vec![0u8; (nslots as usize * 512).next_multiple_of(DNODE_BLOCK_SIZE)];
+        for (object_id, dnode) in dnodes {
+            let dnode = dnode.to_bytes_le();
+            let start = *object_id as usize * 512;
+            assert!(
+                start / DNODE_BLOCK_SIZE == (start + dnode.len() - 1) / DNODE_BLOCK_SIZE,
+                "A dnode can't cross the end of a block of the metadnode"
+            );
+            dnode_data[start..start + dnode.len()].copy_from_slice(&dnode);
+        }
+        let metadnode = self.write_object(
+            ObjType::DNode,
+            BonusType::None,
+            Vec::new(),
+            &dnode_data,
+            DNODE_BLOCK_SIZE,
+        );
+
+        // Source: https://github.com/openzfs/zfs/blob/master/include/sys/dmu_objset.h (objset_phys_t)
+        let mut objset = metadnode.to_bytes_le();
+        objset.resize(512 + 192, 0); // The zil header
+        objset.extend((typ as u64).to_le_bytes());
+        objset.resize(1024, 0);
+        self.write_block(&objset, ObjType::ObjSet, 0)
+    }
+
+    // Writes the objects of the files and directories of a dataset, and its objset
+    fn write_dataset(&mut self, contents: &DatasetContents) -> BlockPointerSpec {
+        const MASTER_NODE_OBJECT_ID: u64 = 1;
+        // Everything else is numbered in the order of the paths, so the root directory is first
+        let object_ids = contents
+            .keys()
+            .zip(MASTER_NODE_OBJECT_ID + 1..)
+            .map(|(path, object_id)| (path.as_str(), object_id))
+            .collect::<BTreeMap<&str, u64>>();
+
+        let mut dnodes = BTreeMap::new();
+        for (path, node) in contents {
+            let object_id = object_ids[path.as_str()];
+            // The parent of the root directory is itself
+            let parent_id = if path.is_empty() {
+                object_id
+            } else {
+                object_ids[get_parent(path)]
+            };
+            let dnode = match node {
+                Node::Directory => {
+                    let children = contents
+                        .iter()
+                        .filter(|(child_path, _)| {
+                            !child_path.is_empty() && get_parent(child_path) == path
+                        })
+                        .collect::<Vec<_>>();
+                    let entries = children
+                        .iter()
+                        .map(|(child_path, child)| {
+                            let name = child_path.rsplit('/').next().unwrap();
+                            let kind = match child {
+                                Node::Directory => DIRECTORY_ENTRY_DIRECTORY,
+                                Node::File(_) => DIRECTORY_ENTRY_FILE,
+                            };
+                            (name, object_ids[child_path.as_str()] | (kind << 60))
+                        })
+                        .collect::<Vec<_>>();
+                    let nsubdirectories = children
+                        .iter()
+                        .filter(|(_, child)| matches!(child, Node::Directory))
+                        .count() as u64;
+                    // Like in zfs, the size of a directory is its number of entries with . and ..
+                    let bonus = znode_bonus(
+                        S_IFDIR | 0o755,
+                        entries.len() as u64 + 2,
+                        parent_id,
+                        nsubdirectories + 2,
+                    );
+                    self.write_micro_zap(
+                        ObjType::DirectoryContents,
+                        BonusType::ZNode,
+                        bonus,
+                        &entries,
+                    )
+                }
+                Node::File(data) => {
+                    let bonus = znode_bonus(S_IFREG | 0o644, data.len() as u64, parent_id, 1);
+                    // A file smaller than the record size is one block, rounded up to a whole sector
+                    let block_size = if data.len() <= self.config.record_size {
+                        data.len().next_multiple_of(512).max(512)
+                    } else {
+                        self.config.record_size
+                    };
+                    self.write_object(
+                        ObjType::PlainFileContents,
+                        BonusType::ZNode,
+                        bonus,
+                        data,
+                        block_size,
+                    )
+                }
+            };
+            dnodes.insert(object_id, dnode);
+        }
+
+        let master_node = self.write_micro_zap(
+            ObjType::MasterNode,
+            BonusType::None,
+            Vec::new(),
+            &[("VERSION", ZPL_VERSION), ("ROOT", object_ids[""])],
+        );
+        dnodes.insert(MASTER_NODE_OBJECT_ID, master_node);
+        self.write_objset(ObjSetType::Zfs, &dnodes)
+    }
+
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/dsl_dir.h (dsl_dir_phys_t)
+    fn dsl_directory_bonus(head_dataset: u64, parent: u64, child_map: u64) -> Vec<u8> {
+        let mut res = Vec::with_capacity(DSL_DIRECTORY_BONUS_LEN);
+        // creation time, head dataset, parent, origin, child dir zap, used, compressed, uncompressed, quota, reserved, props
+        for value in [
+            TIMESTAMP,
+            head_dataset,
+            parent,
+            0,
+            child_map,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ] {
+            res.extend(u64::to_le_bytes(value));
+        }
+        res.resize(DSL_DIRECTORY_BONUS_LEN, 0);
+        res
+    }
+
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/dsl_dataset.h (dsl_dataset_phys_t)
+    fn dsl_dataset_bonus(&self, directory: u64, guid: u64, objset: &BlockPointerSpec) -> Vec<u8> {
+        let mut res = Vec::with_capacity(DSL_DATASET_BONUS_LEN);
+        // directory, prev snap, prev snap txg, next snap, snapnames zap, num children, creation time, creation txg, deadlist,
+        // referenced, compressed, uncompressed, unique, fsid guid, guid, flags
+        let used = objset.physical_size as u64;
+        for value in [
+            directory,
+            0,
+            0,
+            0,
+            0,
+            0,
+            TIMESTAMP,
+            self.config.txg,
+            0,
+            used,
+            used,
+            used,
+            used,
+            guid,
+            guid,
+            0,
+        ] {
+            res.extend(u64::to_le_bytes(value));
+        }
+        res.extend(objset.to_bytes_le());
+        res.resize(DSL_DATASET_BONUS_LEN, 0);
+        res
+    }
+
+    // Writes the datasets and the MOS that points to them
+    // Returns: The block pointer to the MOS, for the uberblock
+    fn write_mos(&mut self) -> BlockPointerSpec {
+        const OBJECT_DIRECTORY_OBJECT_ID: u64 = 1;
+        let datasets = std::mem::take(&mut self.datasets);
+        let mut dnodes = BTreeMap::new();
+
+        // Every dataset has a dsl directory, a dsl dataset and a child map, in that order, the root dataset is first
+        let object_ids = datasets
+            .keys()
+            .zip((OBJECT_DIRECTORY_OBJECT_ID + 1..).step_by(3))
+            .map(|(name, directory_id)| (name.as_str(), directory_id))
+            .collect::<BTreeMap<&str, u64>>();
+        let root_directory_id = object_ids[""];
+        for (name, contents) in datasets.iter() {
+            let directory_id = object_ids[name.as_str()];
+            let (dataset_id, child_map_id) = (directory_id + 1, directory_id + 2);
+
+            let children = if name.is_empty() {
+                object_ids
+                    .iter()
+                    .filter(|(child_name, _)| !child_name.is_empty())
+                    .map(|(child_name, child_id)| (*child_name, *child_id))
+                    .collect::<Vec<_>>()
+            } else {
+                Vec::new()
+            };
+            let child_map = self.write_micro_zap(
+                ObjType::DSLDirectoryChildMap,
+                BonusType::None,
+                Vec::new(),
+                &children,
+            );
+            dnodes.insert(child_map_id, child_map);
+
+            let parent_id = if name.is_empty() {
+                0
+            } else {
+                root_directory_id
+            };
+            let directory = self.write_object(
+                ObjType::DSLDirectory,
+                BonusType::DSLDirectory,
+                Self::dsl_directory_bonus(dataset_id, parent_id, child_map_id),
+                &[],
+                512,
+            );
+            dnodes.insert(directory_id, directory);
+
+            let objset = self.write_dataset(contents);
+            let guid = self.config.pool_guid.wrapping_add(0x100 + dataset_id);
+            let dataset = self.write_object(
+                ObjType::DSLDataset,
+                BonusType::DSLDataset,
+                self.dsl_dataset_bonus(directory_id, guid, &objset),
+                &[],
+                512,
+            );
+            dnodes.insert(dataset_id, dataset);
+        }
+        self.datasets = datasets;
+
+        let object_directory = self.write_micro_zap(
+            ObjType::ObjectDirectory,
+            BonusType::None,
+            Vec::new(),
+            &[("root_dataset", root_directory_id)],
+        );
+        dnodes.insert(OBJECT_DIRECTORY_OBJECT_ID, object_directory);
+        self.write_objset(ObjSetType::Meta, &dnodes)
+    }
+
+    // Returns: The config that is in the labels of the disk
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/vdev_label.c (vdev_label_init, spa_config_generate)
+    pub fn get_label_config(&self, device: usize) -> nvlist::NVList {
+        let config = &self.config;
+        let children = (0..config.ndevices)
+            .map(|child| {
+                nvlist::NVList::from([
+                    (
+                        String::from("type"),
+                        nvlist::Value::String(String::from("file")),
+                    ),
+                    (String::from("id"), nvlist::Value::U64(child as u64)),
+                    (
+                        String::from("guid"),
+                        nvlist::Value::U64(config.get_device_guid(child)),
+                    ),
+                    (
+                        String::from("path"),
+                        nvlist::Value::String(format!("/images/{}-{child}", config.pool_name)),
+                    ),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let vdev_tree = nvlist::NVList::from([
+            (
+                String::from("type"),
+                nvlist::Value::String(String::from("raidz")),
+            ),
+            (String::from("id"), nvlist::Value::U64(0)),
+            (
+                String::from("guid"),
+                nvlist::Value::U64(config.get_top_guid()),
+            ),
+            (
+                String::from("nparity"),
+                nvlist::Value::U64(config.nparity as u64),
+            ),
+            (String::from("ashift"), nvlist::Value::U64(config.ashift)),
+            (
+                String::from("asize"),
+                nvlist::Value::U64(
+                    (config.disk_size - VDEV_LABEL_START_SIZE - VDEV_LABEL_END_SIZE)
+                        * config.ndevices as u64,
+                ),
+            ),
+            (
+                String::from("children"),
+                nvlist::Value::NVListArray(children),
+            ),
+        ]);
+        nvlist::NVList::from([
+            (
+                String::from("version"),
+                nvlist::Value::U64(SPA_VERSION_FEATURES),
+            ),
+            (
+                String::from("name"),
+                nvlist::Value::String(config.pool_name.clone()),
+            ),
+            (String::from("state"), nvlist::Value::U64(0)),
+            (String::from("txg"), nvlist::Value::U64(config.txg)),
+            (
+                String::from("pool_guid"),
+                nvlist::Value::U64(config.pool_guid),
+            ),
+            (
+                String::from("top_guid"),
+                nvlist::Value::U64(config.get_top_guid()),
+            ),
+            (
+                String::from("guid"),
+                nvlist::Value::U64(config.get_device_guid(device)),
+            ),
+            (String::from("vdev_children"), nvlist::Value::U64(1)),
+            (String::from("vdev_tree"), nvlist::Value::NVList(vdev_tree)),
+            (
+                String::from("features_for_read"),
+                nvlist::Value::NVList(nvlist::NVList::new()),
+            ),
+        ])
+    }
+
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/uberblock_impl.h (uberblock_t)
+    fn uberblock_bytes(&self, txg: u64, rootbp: &[u8; 128]) -> Vec<u8> {
+        let mut res = Vec::new();
+        for value in [
+            UBERBLOCK_MAGIC,
+            SPA_VERSION_FEATURES,
+            txg,
+            self.config.get_guid_sum(),
+            TIMESTAMP + txg,
+        ] {
+            res.extend(u64::to_le_bytes(value));
+        }
+        res.extend(rootbp);
+        res
+    }
+
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/vdev_impl.h (vdev_label_t)
+    fn write_labels(&mut self, uberblocks: &BTreeMap<u64, [u8; 128]>) {
+        let uberblock_size = VdevLabel::get_uberblock_size_for_ashift(self.config.ashift);
+        let nuberblocks = (VDEV_LABEL_SIZE as usize - 128 * 1024) / uberblock_size;
+        for device in 0..self.config.ndevices {
+            let mut label = vec![0u8; VDEV_LABEL_SIZE as usize];
+            let config = nvlist::to_bytes_xdr(&self.get_label_config(device))
+                .expect("The label config should only have values that can be written");
+            label[16 * 1024..16 * 1024 + config.len()].copy_from_slice(&config);
+            // Only the magic of the embedded checksum, the checksum itself is sha256 which nothing checks
+            let checksum_start = 128 * 1024 - 5 * 8;
+            label[checksum_start..checksum_start + 8]
+                .copy_from_slice(&EMBEDDED_CHECKSUM_MAGIC.to_le_bytes());
+
+            // Like zfs, an uberblock goes in the slot of its txg
+            for (txg, rootbp) in uberblocks {
+                let uberblock = self.uberblock_bytes(*txg, rootbp);
+                let start = 128 * 1024 + (*txg as usize % nuberblocks) * uberblock_size;
+                label[start..start + uberblock.len()].copy_from_slice(&uberblock);
+            }
+
+            let disk = &mut self.disks[device];
+            let disk_size = disk.len();
+            for label_offset in [
+                0,
+                VDEV_LABEL_SIZE as usize,
+                disk_size - 2 * VDEV_LABEL_SIZE as usize,
+                disk_size - VDEV_LABEL_SIZE as usize,
+            ] {
+                disk[label_offset..label_offset + label.len()].copy_from_slice(&label);
+            }
+        }
+    }
+
+    // Returns: The images of the disks, in the order they are in the raidz
+    pub fn build(mut self) -> Vec<Vec<u8>> {
+        let rootbp = self.write_mos();
+        let mut uberblocks = std::mem::take(&mut self.extra_uberblocks);
+        uberblocks.insert(self.config.txg, rootbp.to_bytes_le());
+        self.write_labels(&uberblocks);
+        self.disks
+    }
+}
@@ -0,0 +1,287 @@
+// Walks every block pointer tree that can be reached from a root block pointer (usually the rootbp of an uberblock)
+// That is the MOS, the objsets of all datasets (including snapshots) and every object in all of them
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dmu_traverse.c
+// NOTE: Objects whose type or bonus type we can't parse are skipped, as are spill blocks and the zil
+
+use crate::{
+    byte_iter::FromSliceLE,
+    dmu::{DNodeBase, DNodeDSLDataset, IndirectTreeCursor, ObjSet, ObjType, TypeMismatch},
+    dsl::DSLDatasetData,
+    zio::{BlockPointer, Vdevs},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ObjSetId {
+    Mos,
+    // The object number of the dataset's dsl dataset dnode in the MOS
+    Dataset(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TraversedObject {
+    // The block that contains the objset itself
+    ObjSet,
+    // The meta dnode of the objset, its data is the dnodes of all the objects
+    MetaDNode,
+    Object(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockLocation {
+    pub objset: ObjSetId,
+    pub object: TraversedObject,
+    // The type of the object, which every block pointer in its tree should have (objsets are ObjSet and meta dnodes are DNode)
+    pub obj_type: ObjType,
+    pub level: usize,
+    // Id of the block within its level
+    pub block_id: u64,
+    pub data_block_size: usize,
+    pub indirect_block_size: usize,
+}
+
+impl BlockLocation {
+    // Returns: The offset in the object of the first byte of data this block (indirectly) points to
+    pub fn get_object_offset(&self) -> u64 {
+        let bps_per_indirect_block =
+            (self.indirect_block_size / BlockPointer::get_ondisk_size()) as u64;
+        let mut first_data_block_id = self.block_id;
+        for _ in 0..self.level {
+            first_data_block_id = first_data_block_id.saturating_mul(bps_per_indirect_block);
+        }
+        first_data_block_id.saturating_mul(self.data_block_size as u64)
+    }
+
+    // Returns: Some if the type or level of the block pointer doesn't match where it is in the tree, which is a cheap way to spot corruption
+    pub fn check_block_pointer(&self, bp: &BlockPointer) -> Option<TypeMismatch> {
+        TypeMismatch::check(bp, self.obj_type, self.level)
+    }
+}
+
+fn walk_block_pointer(
+    bp: &mut BlockPointer,
+    location: BlockLocation,
+    vdevs: &mut Vdevs,
+    visit: &mut dyn FnMut(&BlockLocation, &mut BlockPointer, &mut Vdevs),
+    data_block_pointers: &mut Option<&mut Vec<(u64, BlockPointer)>>,
+) {
+    visit(&location, bp, vdevs);
+    if location.level == 0 {
+        if let Some(data_block_pointers) = data_block_pointers {
+            data_block_pointers.push((location.block_id, bp.clone()));
+        }
+        return;
+    }
+
+    // If the indirect block can't be read there is nothing under it we can visit, the visitor already saw its block pointer
+    let Ok(indirect_block_data) = bp.dereference(vdevs) else {
+        return;
+    };
+
+    for (index, bp_data) in indirect_block_data
+        .chunks(BlockPointer::get_ondisk_size())
+        .enumerate()
+    {
+        // NOTE: Holes are all zeros, which won't parse
+        let Some(mut child_bp) = BlockPointer::from_slice_le(bp_data) else {
+            continue;
+        };
+
+        let bps_per_indirect_block =
+            (location.indirect_block_size / BlockPointer::get_ondisk_size()) as u64;
+        walk_block_pointer(
+            &mut child_bp,
+            BlockLocation {
+                level: location.level - 1,
+                block_id: location.block_id * bps_per_indirect_block + index as u64,
+                ..location.clone()
+            },
+            vdevs,
+            visit,
+            data_block_pointers,
+        );
+    }
+}
+
+// Visits all block pointers of the dnode, and returns the block pointers of the data blocks if `collect_data_block_pointers` is set
+fn walk_dnode(
+    dnode: &mut DNodeBase,
+    objset: ObjSetId,
+    object: TraversedObject,
+    obj_type: ObjType,
+    vdevs: &mut Vdevs,
+    visit: &mut dyn FnMut(&BlockLocation, &mut BlockPointer, &mut Vdevs),
+    collect_data_block_pointers: bool,
+) -> Vec<(u64, BlockPointer)> {
+    let mut data_block_pointers = Vec::new();
+    let n_indirect_levels = dnode.get_n_indirect_levels();
+    if n_indirect_levels == 0 {
+        return data_block_pointers;
+    }
+
+    let data_block_size = dnode.parse_data_block_size();
+    let indirect_block_size = dnode.parse_indirect_block_size();
+    for (index, bp) in dnode.get_block_pointers().iter_mut().enumerate() {
+        walk_block_pointer(
+            bp,
+            BlockLocation {
+                objset,
+                object,
+                obj_type,
+                level: n_indirect_levels - 1,
+                block_id: index as u64,
+                data_block_size,
+                indirect_block_size,
+            },
+            vdevs,
+            visit,
+            &mut if collect_data_block_pointers {
+                Some(&mut data_block_pointers)
+            } else {
+                None
+            },
+        );
+    }
+    data_block_pointers
+}
+
+// Returns: The object sets of the datasets found in the objset (only the MOS has those)
+fn walk_objset(
+    objset: &mut ObjSet,
+    objset_id: ObjSetId,
+    vdevs: &mut Vdevs,
+    visit: &mut dyn FnMut(&BlockLocation, &mut BlockPointer, &mut Vdevs),
+    visit_object: &mut dyn FnMut(ObjSetId, u64, ObjType, &DNodeBase),
+) -> Vec<(u64, BlockPointer)> {
+    use crate::ansi_color::*;
+    let mut datasets = Vec::new();
+    let dnode_block_pointers = walk_dnode(
+        &mut objset.metadnode,
+        objset_id,
+        TraversedObject::MetaDNode,
+        ObjType::DNode,
+        vdevs,
+        visit,
+        true,
+    );
+
+    let dnodes_per_block = (objset.metadnode.parse_data_block_size() / 512) as u64;
+    for (block_id, mut bp) in dnode_block_pointers {
+        let Ok(dnode_block) = bp.dereference(vdevs) else {
+            if cfg!(feature = "debug") {
+                println!("{YELLOW}Warning{WHITE}: Couldn't read dnode block {block_id} of {objset_id:?}, skipping its objects!");
+            }
+            continue;
+        };
+
+        let mut slot = 0;
+        while slot < dnode_block.len() / 512 {
+            let object_id = block_id * dnodes_per_block + slot as u64;
+            let Some((mut dnode, obj_type, _)) =
+                DNodeBase::from_slice_le(&dnode_block[slot * 512..])
+            else {
+                slot += 1;
+                continue;
+            };
+            slot += dnode.get_num_slots();
+
+            visit_object(objset_id, object_id, obj_type, &dnode);
+            walk_dnode(
+                &mut dnode,
+                objset_id,
+                TraversedObject::Object(object_id),
+                obj_type,
+                vdevs,
+                visit,
+                false,
+            );
+
+            if objset_id == ObjSetId::Mos && obj_type == ObjType::DSLDataset {
+                if let Some(mut dataset) = DNodeDSLDataset(dnode).parse_bonus_data() {
+                    datasets.push((object_id, dataset.get_block_pointer().clone()));
+                }
+            }
+        }
+    }
+
+    datasets
+}
+
+// Calls `visit` for every block pointer reachable from `rootbp`, parents are always visited before their children
+// NOTE: Blocks shared between datasets (ex. with snapshots) are visited once for every dataset that uses them
+pub fn traverse_pool(
+    rootbp: &mut BlockPointer,
+    vdevs: &mut Vdevs,
+    visit: &mut dyn FnMut(&BlockLocation, &mut BlockPointer, &mut Vdevs),
+) -> Result<(), ()> {
+    traverse_pool_with_objects(rootbp, vdevs, visit, &mut |_, _, _, _| ())
+}
+
+// Like traverse_pool, but also calls `visit_object` for every object (with its object id and type), before visiting its block pointers
+pub fn traverse_pool_with_objects(
+    rootbp: &mut BlockPointer,
+    vdevs: &mut Vdevs,
+    visit: &mut dyn FnMut(&BlockLocation, &mut BlockPointer, &mut Vdevs),
+    visit_object: &mut dyn FnMut(ObjSetId, u64, ObjType, &DNodeBase),
+) -> Result<(), ()> {
+    let objset_location = |objset| BlockLocation {
+        objset,
+        object: TraversedObject::ObjSet,
+        obj_type: ObjType::ObjSet,
+        level: 0,
+        block_id: 0,
+        data_block_size: ObjSet::get_ondisk_size(),
+        indirect_block_size: 0,
+    };
+
+    visit(&objset_location(ObjSetId::Mos), rootbp, vdevs);
+    let mos_data = rootbp.dereference(vdevs)?;
+    let mut mos = ObjSet::from_slice_le(&mos_data).ok_or(())?;
+    let datasets = walk_objset(&mut mos, ObjSetId::Mos, vdevs, visit, visit_object);
+
+    for (dataset_object_id, mut dataset_bp) in datasets {
+        let objset_id = ObjSetId::Dataset(dataset_object_id);
+        visit(&objset_location(objset_id), &mut dataset_bp, vdevs);
+        let Ok(objset_data) = dataset_bp.dereference(vdevs) else {
+            continue;
+        };
+        let Some(mut objset) = ObjSet::from_slice_le(&objset_data) else {
+            continue;
+        };
+        walk_objset(&mut objset, objset_id, vdevs, visit, visit_object);
+    }
+
+    Ok(())
+}
+
+// Returns: The object number and bonus data of every dsl dataset in the MOS (including snapshots)
+// This only reads the meta dnode of the MOS, so it's a lot faster than traversing the pool when only the datasets are needed
+pub fn find_datasets(mos: &mut ObjSet, vdevs: &mut Vdevs) -> Vec<(u64, DSLDatasetData)> {
+    let mut datasets = Vec::new();
+    let dnodes_per_block = (mos.metadnode.parse_data_block_size() / 512) as u64;
+    let mut cursor = IndirectTreeCursor::new();
+    for block_id in 0..=mos.metadnode.get_max_indirect_block_id() {
+        let Ok(Some(dnode_block)) =
+            cursor.read_block_or_hole(&mos.metadnode, block_id as usize, vdevs)
+        else {
+            continue;
+        };
+
+        let mut slot = 0;
+        while slot < dnode_block.len() / 512 {
+            let Some((dnode, obj_type, _)) = DNodeBase::from_slice_le(&dnode_block[slot * 512..])
+            else {
+                slot += 1;
+                continue;
+            };
+            let object_id = block_id * dnodes_per_block + slot as u64;
+            slot += dnode.get_num_slots();
+
+            if obj_type == ObjType::DSLDataset {
+                if let Some(dataset) = DNodeDSLDataset(dnode).parse_bonus_data() {
+                    datasets.push((object_id, dataset));
+                }
+            }
+        }
+    }
+    datasets
+}
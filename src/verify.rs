@@ -0,0 +1,214 @@
+// A read-only scrub, reads every copy of every block reachable from an uberblock and checks it against its block pointer
+// Unlike zfs we can't repair anything, this just reports which blocks are damaged and which of those can't be read at all anymore
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dsl_scan.c
+
+use std::collections::BTreeMap;
+
+use crate::{
+    dmu::TypeMismatch,
+    traverse::{self, BlockLocation, ObjSetId},
+    zio::{self, BlockPointer, DataVirtualAddress, NormalBlockPointer, ReadPipeline, Vdevs},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyStatus {
+    Ok,
+    // The copy couldn't be read from the disks at all
+    Unreadable,
+    // The copy was read but the checksum doesn't match, or it doesn't decompress to the right size
+    Corrupt,
+    // We don't implement the checksum method, so we can't tell
+    Unverifiable,
+}
+
+#[derive(Debug)]
+pub struct BadBlock {
+    pub location: BlockLocation,
+    // The status of every copy (dva) of the block, in order
+    pub copies: Vec<CopyStatus>,
+    // Where the copies are, in the same order, so the damaged parts of the disks can be found
+    pub dvas: Vec<DataVirtualAddress>,
+}
+
+impl BadBlock {
+    // Returns: true if no copy of the block is good, so the data is actually gone, otherwise only the redundancy is
+    pub fn is_lost(&self) -> bool {
+        !self
+            .copies
+            .iter()
+            .any(|status| matches!(status, CopyStatus::Ok | CopyStatus::Unverifiable))
+    }
+}
+
+// One copy of a block whose copies don't all have the same data
+#[derive(Debug, Clone)]
+pub struct CopyCheck {
+    pub dva: DataVirtualAddress,
+    pub status: CopyStatus,
+    // The index of the first copy with exactly the same data as this one (which can be this copy), None if it couldn't be read
+    pub same_data_as: Option<usize>,
+}
+
+// A block whose copies don't all have the same data, so at least one of the disks they are on returned the wrong data
+// NOTE: If all the copies have the same data but the checksum still fails, it's the block pointer that is wrong, not the disks
+#[derive(Debug)]
+pub struct DivergingBlock {
+    pub location: BlockLocation,
+    pub copies: Vec<CopyCheck>,
+}
+
+// A block pointer whose type or level doesn't match where it is in the tree, its checksum can still be fine if the indirect block it's in was written wrong
+#[derive(Debug)]
+pub struct MismatchedBlock {
+    pub location: BlockLocation,
+    pub mismatch: TypeMismatch,
+}
+
+#[derive(Debug, Default)]
+pub struct DatasetReport {
+    pub nblocks: u64,
+    // Physical size of all the blocks, copies are only counted once
+    pub nbytes: u64,
+    pub nunverifiable: u64,
+    pub bad_blocks: Vec<BadBlock>,
+    // Only filled in if the copies are compared, see verify_pool
+    pub diverging_blocks: Vec<DivergingBlock>,
+    pub mismatched_blocks: Vec<MismatchedBlock>,
+}
+
+fn check_copy_data(data: &[u8], bp: &NormalBlockPointer) -> CopyStatus {
+    // No recovery, we want to know what's actually on disk
+    let pipeline = ReadPipeline {
+        use_yolo_recovery: false,
+        use_l2arc: false,
+        use_known_blocks: false,
+        ..ReadPipeline::default()
+    };
+
+    if zio::try_checksum_block(data, bp.get_checksum_method()).is_none() {
+        return CopyStatus::Unverifiable;
+    }
+
+    if pipeline.finish_read(data, bp).is_ok() {
+        CopyStatus::Ok
+    } else {
+        CopyStatus::Corrupt
+    }
+}
+
+// Returns: The status of every copy of the block
+pub fn verify_copies(bp: &NormalBlockPointer, vdevs: &mut Vdevs) -> Vec<CopyStatus> {
+    let psize = bp.parse_physical_size() as usize;
+    let gang_verifier = bp.get_gang_verifier();
+    bp.get_dvas()
+        .iter()
+        .flatten()
+        .map(|dva| {
+            let data = dva.dereference_with_gang_verifier(vdevs, psize, gang_verifier.as_ref());
+            match data {
+                Ok(data) => check_copy_data(&data, bp),
+                Err(()) => CopyStatus::Unreadable,
+            }
+        })
+        .collect()
+}
+
+// Like verify_copies, but the copies are also compared to each other
+// this tells apart a disk that returned the wrong data from a block pointer that is wrong, and it's the only check there is for unverifiable blocks
+pub fn compare_copies(bp: &NormalBlockPointer, vdevs: &mut Vdevs) -> Vec<CopyCheck> {
+    let psize = bp.parse_physical_size() as usize;
+    let gang_verifier = bp.get_gang_verifier();
+    let copies_data = bp
+        .get_dvas()
+        .iter()
+        .flatten()
+        .map(|dva| {
+            let data = dva.dereference_with_gang_verifier(vdevs, psize, gang_verifier.as_ref());
+            (dva.clone(), data.ok())
+        })
+        .collect::<Vec<_>>();
+
+    copies_data
+        .iter()
+        .map(|(dva, data)| CopyCheck {
+            dva: dva.clone(),
+            status: match data {
+                Some(data) => check_copy_data(data, bp),
+                None => CopyStatus::Unreadable,
+            },
+            same_data_as: data.as_ref().and_then(|data| {
+                copies_data
+                    .iter()
+                    .position(|(_, other_data)| other_data.as_ref() == Some(data))
+            }),
+        })
+        .collect()
+}
+
+// Returns: true if the copies that could be read don't all have the same data
+pub fn copies_diverge(copies: &[CopyCheck]) -> bool {
+    let mut groups = copies.iter().filter_map(|copy| copy.same_data_as);
+    let first_group = groups.next();
+    groups.any(|group| Some(group) != first_group)
+}
+
+// Returns: A report for every objset that was reached, damaged blocks are included even if some of their copies are fine
+// If compare_copies is set the copies of blocks with more than one are also compared to each other, see compare_copies
+pub fn verify_pool(
+    rootbp: &mut BlockPointer,
+    vdevs: &mut Vdevs,
+    compare_copies: bool,
+) -> Result<BTreeMap<ObjSetId, DatasetReport>, ()> {
+    let mut reports = BTreeMap::<ObjSetId, DatasetReport>::new();
+    let mut nverified: u64 = 0;
+    traverse::traverse_pool(rootbp, vdevs, &mut |location, bp, vdevs| {
+        nverified += 1;
+        if nverified.is_multiple_of(64 * 1024) {
+            println!("Verified {nverified} blocks so far ...");
+        }
+
+        let report = reports.entry(location.objset).or_default();
+        report.nblocks += 1;
+        if let Some(mismatch) = location.check_block_pointer(bp) {
+            report.mismatched_blocks.push(MismatchedBlock {
+                location: location.clone(),
+                mismatch,
+            });
+        }
+
+        // The data of embedded block pointers is in the block pointer itself, so there is nothing to read
+        let BlockPointer::Normal(normal_bp) = bp else {
+            return;
+        };
+        report.nbytes += normal_bp.parse_physical_size();
+
+        let copies = if compare_copies && normal_bp.get_dvas().iter().flatten().count() > 1 {
+            let checks = self::compare_copies(normal_bp, vdevs);
+            let statuses = checks.iter().map(|check| check.status).collect();
+            if copies_diverge(&checks) {
+                report.diverging_blocks.push(DivergingBlock {
+                    location: location.clone(),
+                    copies: checks,
+                });
+            }
+            statuses
+        } else {
+            verify_copies(normal_bp, vdevs)
+        };
+        if copies.contains(&CopyStatus::Unverifiable) {
+            report.nunverifiable += 1;
+        }
+
+        if copies
+            .iter()
+            .any(|status| matches!(status, CopyStatus::Unreadable | CopyStatus::Corrupt))
+        {
+            report.bad_blocks.push(BadBlock {
+                location: location.clone(),
+                copies,
+                dvas: normal_bp.get_dvas().iter().flatten().cloned().collect(),
+            });
+        }
+    })?;
+    Ok(reports)
+}
@@ -0,0 +1,124 @@
+// Counts of the warnings the library runs into, by category
+// Most of them are only printed with the debug feature, and then there are thousands of them that scroll away
+// so the tools print a summary of the counts at the end instead, to show how healthy a pool or a scan is at a glance
+// NOTE: The counts are global, like the read policy, so everything that runs in the process adds to them
+
+use lazy_static::lazy_static;
+use std::{collections::BTreeMap, sync::Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningKind {
+    // A dva couldn't be read from the disks
+    BadDva,
+    // The data of a dva was read, but its checksum doesn't match the block pointer
+    ChecksumMismatch,
+    // The data of a dva had the right checksum, but couldn't be decompressed to the size the block pointer says
+    DecompressionFailed,
+    UnsupportedCompression,
+    // Blocks with these checksums can't be verified, so they are only read if the pipeline accepts unverifiable data
+    UnsupportedChecksum,
+    // No copy of a block could be read, even with l2arc and yolo recovery
+    UnreadableBlock,
+    // No copy of a block was good, but the read policy let a damaged one through
+    UnverifiedBlock,
+    // Things that are valid zfs but can't be read yet, ex. spill blocks or fat zaps with a pointer table outside of the header
+    UnsupportedFeature,
+    // On disk structures that fail their sanity checks, ex. a zap leaf with the wrong magic or a loop in a gang tree
+    CorruptMetadata,
+    // A read from a disk or image failed
+    DeviceIo,
+    // Every copy of a block is on parts of the disks the damage maps say are bad, see damage_map
+    DamagedCopies,
+}
+
+impl WarningKind {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            WarningKind::BadDva => "bad-dva",
+            WarningKind::ChecksumMismatch => "checksum-mismatch",
+            WarningKind::DecompressionFailed => "decompression-failed",
+            WarningKind::UnsupportedCompression => "unsupported-compression",
+            WarningKind::UnsupportedChecksum => "unsupported-checksum",
+            WarningKind::UnreadableBlock => "unreadable-block",
+            WarningKind::UnverifiedBlock => "unverified-block",
+            WarningKind::UnsupportedFeature => "unsupported-feature",
+            WarningKind::CorruptMetadata => "corrupt-metadata",
+            WarningKind::DeviceIo => "device-io",
+            WarningKind::DamagedCopies => "damaged-copies",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WarningCollector {
+    counts: BTreeMap<WarningKind, u64>,
+}
+
+impl WarningCollector {
+    pub fn count(&mut self, kind: WarningKind) {
+        *self.counts.entry(kind).or_insert(0) += 1;
+    }
+
+    pub fn get_count(&self, kind: WarningKind) -> u64 {
+        self.counts.get(&kind).copied().unwrap_or(0)
+    }
+
+    pub fn get_total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.get_total() == 0
+    }
+
+    // Returns: One line per category that was counted, in the order of WarningKind
+    // NOTE: A block with an unsupported compression is also counted as a failed decompression
+    pub fn format_summary(&self) -> String {
+        let name_width = self
+            .counts
+            .keys()
+            .map(|kind| kind.get_name().len())
+            .max()
+            .unwrap_or(0);
+        let mut res = String::new();
+        for (kind, count) in self.counts.iter() {
+            res += &format!("{:<name_width$}  {count}\n", kind.get_name());
+        }
+        res
+    }
+}
+
+lazy_static! {
+    static ref WARNINGS: Mutex<WarningCollector> = Mutex::new(WarningCollector::default());
+}
+
+pub fn count_warning(kind: WarningKind) {
+    if let Ok(mut lock) = WARNINGS.lock() {
+        lock.count(kind);
+    }
+}
+
+// Returns: A copy of the counts so far
+pub fn get_warnings() -> WarningCollector {
+    WARNINGS.lock().map(|lock| lock.clone()).unwrap_or_default()
+}
+
+pub fn reset_warnings() {
+    if let Ok(mut lock) = WARNINGS.lock() {
+        *lock = WarningCollector::default();
+    }
+}
+
+// Meant for the end of a run, it prints nothing if there were no warnings
+pub fn print_warning_summary() {
+    use crate::ansi_color::*;
+    let warnings = get_warnings();
+    if warnings.is_empty() {
+        return;
+    }
+    println!(
+        "{YELLOW}Warning{WHITE}: Ran into {} problems while reading the pool:",
+        warnings.get_total()
+    );
+    print!("{}", warnings.format_summary());
+}
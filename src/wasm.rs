@@ -0,0 +1,14 @@
+// Bindings for using szfs from javascript, ex. a static page where someone drops in a label they dumped with dd
+// build with: cargo build --lib --release --target wasm32-unknown-unknown --no-default-features --features wasm
+// then: wasm-bindgen --target web target/wasm32-unknown-unknown/release/szfs.wasm --out-dir pkg
+// NOTE: This is only compiled with the "wasm" feature, and only the parsers are usable (there are no disks in a browser)
+
+use wasm_bindgen::prelude::*;
+
+use crate::inspect;
+
+// Returns: The config and uberblock ring of a 256K label dump, as text
+#[wasm_bindgen]
+pub fn inspect_label(label: &[u8]) -> Result<String, String> {
+    inspect::inspect_label(label).map(|report| inspect::format_label_report(&report))
+}
@@ -1,22 +1,363 @@
 use std::{
     collections::HashMap,
     fs::{File, OpenOptions},
-    io::{Read, Seek, SeekFrom, Write},
-    sync::{atomic::AtomicU64, Mutex},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicU64, Mutex, RwLock},
 };
 
-use fftconvolve::fftconvolve;
 use lazy_static::lazy_static;
-use ndarray::arr1;
 use rayon::prelude::ParallelIterator;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    byte_iter::FromBytesLE,
     fletcher::do_fletcher4,
+    recovery::control::{CancellationToken, Completion},
     zio::{DataVirtualAddress, Vdevs},
+    RaidzInfo, Vdev,
 };
 
 type ChecksumTableEntry = u32;
 
+// "SZFSCSUM" in ascii
+pub const CHECKSUM_TABLE_MAGIC: u64 = 0x4D55534353465A53;
+// "SZFSCSM2" in ascii
+pub const SECONDARY_CHECKSUM_TABLE_MAGIC: u64 = 0x324D534353465A53;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumTableKind {
+    // Has the first word of the fletcher4 checksum of every sector, this is what the partial block checksums are calculated from
+    Primary,
+    // Has the second word of the fletcher4 checksum of every sector
+    // this is only used to get rid of false positives from the primary table before doing an expensive read
+    Secondary,
+}
+
+impl ChecksumTableKind {
+    pub fn get_magic(&self) -> u64 {
+        match self {
+            ChecksumTableKind::Primary => CHECKSUM_TABLE_MAGIC,
+            ChecksumTableKind::Secondary => SECONDARY_CHECKSUM_TABLE_MAGIC,
+        }
+    }
+
+    pub fn from_magic(magic: u64) -> Option<ChecksumTableKind> {
+        Some(match magic {
+            CHECKSUM_TABLE_MAGIC => ChecksumTableKind::Primary,
+            SECONDARY_CHECKSUM_TABLE_MAGIC => ChecksumTableKind::Secondary,
+            _ => return None,
+        })
+    }
+
+    // Which word of the fletcher4 checksum is stored in the table
+    pub fn get_checksum_word_index(&self) -> usize {
+        match self {
+            ChecksumTableKind::Primary => 0,
+            ChecksumTableKind::Secondary => 1,
+        }
+    }
+}
+
+// The checksum table is just a tightly packed array of ChecksumTableEntry's in little endian (the truncated fletcher4 checksum of every sector)
+// preceded by this header so a table can't be used with the wrong pool by accident
+// NOTE: Tables made by older versions don't have a header, those are still accepted, but nothing can be checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumTableHeader {
+    pub kind: ChecksumTableKind,
+    pub sector_size: u64,
+    pub vdev_guid: u64,
+    pub entry_width: u64, // in bytes
+}
+
+impl<It> FromBytesLE<It> for ChecksumTableHeader
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<Self> {
+        let kind = ChecksumTableKind::from_magic(u64::from_bytes_le(data)?)?;
+
+        Some(ChecksumTableHeader {
+            kind,
+            sector_size: u64::from_bytes_le(data)?,
+            vdev_guid: u64::from_bytes_le(data)?,
+            entry_width: u64::from_bytes_le(data)?,
+        })
+    }
+}
+
+impl ChecksumTableHeader {
+    pub fn new(kind: ChecksumTableKind, sector_size: u64, vdev_guid: u64) -> ChecksumTableHeader {
+        ChecksumTableHeader {
+            kind,
+            sector_size,
+            vdev_guid,
+            entry_width: core::mem::size_of::<ChecksumTableEntry>() as u64,
+        }
+    }
+
+    pub const fn get_ondisk_size() -> usize {
+        core::mem::size_of::<u64>() * 4
+    }
+
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(Self::get_ondisk_size());
+        res.extend(self.kind.get_magic().to_le_bytes());
+        res.extend(self.sector_size.to_le_bytes());
+        res.extend(self.vdev_guid.to_le_bytes());
+        res.extend(self.entry_width.to_le_bytes());
+        res
+    }
+}
+
+// Returns: The header of the table, or None if the table doesn't have one (it was made by an older version, or it's empty)
+pub fn read_checksum_table_header(checksum_map_file: &mut File) -> Option<ChecksumTableHeader> {
+    let mut data = [0u8; ChecksumTableHeader::get_ondisk_size()];
+    checksum_map_file.seek(SeekFrom::Start(0)).ok()?;
+    checksum_map_file.read_exact(&mut data).ok()?;
+    ChecksumTableHeader::from_bytes_le(&mut data.iter().copied())
+}
+
+// Builds the checksum table used by find-block-with-checksum and yolo block recovery
+// If the table already exists, building resumes from where it stopped, as long as it was made for the same vdev
+// progress_callback is called after every chunk with the amount of bytes done so far and the total size of the vdev
+// When `cancel` is cancelled the table is flushed after the current chunk, so building it again resumes from there
+pub fn build_checksum_table(
+    vdev: &mut dyn Vdev,
+    vdev_guid: u64,
+    path: &Path,
+    cancel: &CancellationToken,
+    progress_callback: impl FnMut(u64, u64),
+) -> Result<Completion, ()> {
+    build_checksum_table_of_kind(
+        ChecksumTableKind::Primary,
+        vdev,
+        vdev_guid,
+        path,
+        cancel,
+        progress_callback,
+    )
+}
+
+// The secondary table is optional, it doubles the space used, but it makes yolo recovery on big disks a lot faster
+// as most of the false positives can be thrown away without reading anything from the disks
+pub fn build_secondary_checksum_table(
+    vdev: &mut dyn Vdev,
+    vdev_guid: u64,
+    path: &Path,
+    cancel: &CancellationToken,
+    progress_callback: impl FnMut(u64, u64),
+) -> Result<Completion, ()> {
+    build_checksum_table_of_kind(
+        ChecksumTableKind::Secondary,
+        vdev,
+        vdev_guid,
+        path,
+        cancel,
+        progress_callback,
+    )
+}
+
+// A plain disk (or any side of a mirror, as every side has all of the data) stores a block contiguously
+// which is the same as a raidz with a single device and no parity, so all of the raidz math here works for it as is
+fn get_layout(vdev: &dyn Vdev) -> RaidzInfo {
+    vdev.get_raidz_info().unwrap_or(RaidzInfo {
+        ndevices: 1,
+        nparity: 0,
+    })
+}
+
+// Plain disks don't know their ashift, but dva offsets are always in 512 byte units
+// and a table with 512 byte sectors can find blocks with any bigger sector size too, it's just bigger
+fn get_sector_size(vdev: &dyn Vdev) -> usize {
+    if vdev.get_raidz_info().is_some() {
+        vdev.get_asize()
+    } else {
+        512
+    }
+}
+
+fn build_checksum_table_of_kind(
+    kind: ChecksumTableKind,
+    vdev: &mut dyn Vdev,
+    vdev_guid: u64,
+    path: &Path,
+    cancel: &CancellationToken,
+    progress_callback: impl FnMut(u64, u64),
+) -> Result<Completion, ()> {
+    let header = ChecksumTableHeader::new(kind, get_sector_size(vdev) as u64, vdev_guid);
+    let disk_size = vdev.get_size();
+    build_checksum_table_with_header(header, vdev, disk_size, path, cancel, progress_callback)
+}
+
+// A table of only one of the disks of a raidz, of the first `device_size` bytes of its data (the raidz only uses as much of every disk as the smallest one has)
+// the header is the one of the table of the whole raidz, see merge_checksum_table_shards
+// The disks can be read at the same time, one thread per disk, so building the shards is about as many times faster as there are disks
+// NOTE: Entry n of the shard of disk d is sector n * ndevices + d of the raidz
+pub fn build_checksum_table_shard(
+    header: ChecksumTableHeader,
+    device: &mut dyn Vdev,
+    device_size: u64,
+    path: &Path,
+    cancel: &CancellationToken,
+    progress_callback: impl FnMut(u64, u64),
+) -> Result<Completion, ()> {
+    build_checksum_table_with_header(header, device, device_size, path, cancel, progress_callback)
+}
+
+fn build_checksum_table_with_header(
+    header: ChecksumTableHeader,
+    vdev: &mut dyn Vdev,
+    disk_size: u64,
+    path: &Path,
+    cancel: &CancellationToken,
+    mut progress_callback: impl FnMut(u64, u64),
+) -> Result<Completion, ()> {
+    use crate::ansi_color::*;
+    let kind = header.kind;
+    let sector_size = header.sector_size;
+
+    let mut checksum_map_file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false) // We resume from whatever is already there
+        .open(path)
+        .map_err(|_| ())?;
+    let checksum_map_file_size = checksum_map_file.seek(SeekFrom::End(0)).map_err(|_| ())?;
+
+    if checksum_map_file_size == 0 {
+        checksum_map_file
+            .write_all(&header.to_bytes_le())
+            .map_err(|_| ())?;
+    } else {
+        let Some(existing_header) = read_checksum_table_header(&mut checksum_map_file) else {
+            println!("{RED}Fatal{WHITE}: Checksum table {path:?} has no header, it was probably made by an older version, refusing to append to it!");
+            return Err(());
+        };
+
+        if existing_header != header {
+            println!("{RED}Fatal{WHITE}: Checksum table {path:?} was made for a different vdev ({existing_header:?}), but we are building it for {header:?}!");
+            return Err(());
+        }
+    }
+
+    // Resume from the last complete entry, a partial entry can happen if we got interrupted in the middle of a write
+    let entries_done = (checksum_map_file_size.max(ChecksumTableHeader::get_ondisk_size() as u64)
+        - ChecksumTableHeader::get_ondisk_size() as u64)
+        / header.entry_width;
+    checksum_map_file
+        .set_len(ChecksumTableHeader::get_ondisk_size() as u64 + entries_done * header.entry_width)
+        .map_err(|_| ())?;
+    checksum_map_file.seek(SeekFrom::End(0)).map_err(|_| ())?;
+
+    let last_off = entries_done * sector_size;
+    if last_off != 0 {
+        println!(
+            "{CYAN}Info{WHITE}: Resuming from offset {}, which is sector {}, with sector size being: {}",
+            last_off,
+            last_off / sector_size,
+            sector_size
+        );
+    }
+
+    // Reading is sequential, but the checksumming of every chunk is done in parallel
+    const CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+    let mut writer = BufWriter::new(checksum_map_file);
+    let mut off = last_off;
+    while off < disk_size {
+        if cancel.is_cancelled() {
+            // Everything up to here is a whole number of entries, so this is a valid point to resume from
+            writer.flush().map_err(|_| ())?;
+            return Ok(Completion::Cancelled);
+        }
+
+        let size = CHUNK_SIZE.min(disk_size - off);
+        // Round down to a whole number of sectors
+        let size = (size / sector_size) * sector_size;
+        if size == 0 {
+            break;
+        }
+
+        let data = vdev.read(off, size as usize)?;
+
+        use rayon::prelude::*;
+        let entries = data
+            .par_chunks_exact(sector_size as usize)
+            // Truncate to size
+            .map(|sector| {
+                do_fletcher4(sector)[kind.get_checksum_word_index()] as ChecksumTableEntry
+            })
+            .collect::<Vec<ChecksumTableEntry>>();
+
+        for entry in entries {
+            writer.write_all(&entry.to_le_bytes()).map_err(|_| ())?;
+        }
+
+        off += size;
+        progress_callback(off, disk_size);
+    }
+
+    writer.flush().map_err(|_| ())?;
+    Ok(Completion::Finished)
+}
+
+// Makes the table of the whole raidz out of the shards of its disks (in the order of the raidz), by interleaving their entries like the raidz does with the sectors
+// the result is the same as what build_checksum_table makes, so the lookups don't know the difference
+// Returns: Err if a shard can't be read, isn't finished (they all have the same amount of entries), or was made for something else
+pub fn merge_checksum_table_shards(
+    shard_paths: &[PathBuf],
+    header: ChecksumTableHeader,
+    path: &Path,
+) -> Result<(), ()> {
+    use crate::ansi_color::*;
+    let header_size = ChecksumTableHeader::get_ondisk_size() as u64;
+    let entry_width = header.entry_width as usize;
+    let mut shards = Vec::new();
+    let mut nentries = None;
+    for shard_path in shard_paths {
+        let mut shard_file = File::open(shard_path).map_err(|_| ())?;
+        if read_checksum_table_header(&mut shard_file) != Some(header) {
+            println!("{RED}Fatal{WHITE}: Checksum table shard {shard_path:?} was made for a different vdev, or has no header!");
+            return Err(());
+        }
+        let shard_size = shard_file.seek(SeekFrom::End(0)).map_err(|_| ())?;
+        let shard_nentries = shard_size.saturating_sub(header_size) / header.entry_width;
+        if *nentries.get_or_insert(shard_nentries) != shard_nentries {
+            println!("{RED}Fatal{WHITE}: Checksum table shard {shard_path:?} has {shard_nentries} entries, but the others have {}, it's probably not finished!", nentries.unwrap());
+            return Err(());
+        }
+        shard_file
+            .seek(SeekFrom::Start(header_size))
+            .map_err(|_| ())?;
+        shards.push(std::io::BufReader::new(shard_file));
+    }
+    let nentries = nentries.unwrap_or(0);
+
+    let mut writer = BufWriter::new(File::create(path).map_err(|_| ())?);
+    writer.write_all(&header.to_bytes_le()).map_err(|_| ())?;
+    const ENTRIES_PER_CHUNK: u64 = 1024 * 1024;
+    let mut chunks = vec![Vec::new(); shards.len()];
+    let mut entries_done = 0;
+    while entries_done < nentries {
+        let chunk_nentries = ENTRIES_PER_CHUNK.min(nentries - entries_done) as usize;
+        for (shard, chunk) in shards.iter_mut().zip(chunks.iter_mut()) {
+            chunk.resize(chunk_nentries * entry_width, 0u8);
+            shard.read_exact(chunk).map_err(|_| ())?;
+        }
+        for index in 0..chunk_nentries {
+            for chunk in chunks.iter() {
+                writer
+                    .write_all(&chunk[index * entry_width..(index + 1) * entry_width])
+                    .map_err(|_| ())?;
+            }
+        }
+        entries_done += chunk_nentries as u64;
+    }
+    writer.flush().map_err(|_| ())?;
+    Ok(())
+}
+
 pub fn calculate_convolution_vector_for_block(
     off: u64,
     mut psize: usize,
@@ -57,6 +398,10 @@ pub fn calculate_convolution_vector_for_block(
     res
 }
 
+// Returns: For every sector offset, the sum of the checksums of the sectors the block would use if it started there
+// i.e. the correlation of the sector checksums with the convolution vector, the convolution vector is true for whole columns
+// at a time so every column is a run of sectors that are raidz_ndevices apart, which can be summed exactly with prefix sums
+// NOTE: This used to be an fft convolution over f64s, but that has to round the result, which can miss matches for big sums
 pub fn calculate_fletcher4_partial_block_checksums(
     off: u64,
     psize: usize,
@@ -66,60 +411,277 @@ pub fn calculate_fletcher4_partial_block_checksums(
     raidz_nparity: usize,
     sector_checksums: &[ChecksumTableEntry],
 ) -> Vec<u64> {
-    let cv: Vec<f64> = calculate_convolution_vector_for_block(
+    let cv = calculate_convolution_vector_for_block(
         off,
         psize,
         is_raidz1,
         sector_size,
         raidz_ndevices,
         raidz_nparity,
-    )
-    .into_iter()
-    .map(|val| val as u8 as f64)
-    .rev()
-    .collect();
-    let sv: Vec<f64> = sector_checksums.iter().map(|val| *val as f64).collect();
-    let res = fftconvolve(&arr1(&sv), &arr1(&cv), fftconvolve::Mode::Full).unwrap();
-    let mut res: Vec<u64> = res
-        .into_iter()
-        .skip(cv.len() - 1)
-        .map(|val| val.round() as u64)
-        .collect();
-
-    res.resize(sector_checksums.len() - (cv.len() - 1), 0);
-    res
+    );
+    // A block of 0 bytes has no sectors, so there is nothing to sum
+    if cv.is_empty() {
+        return Vec::new();
+    }
+    let stride = raidz_ndevices;
+
+    // The runs of used sectors, as (index of the first sector, number of sectors), the sectors of a run are stride apart
+    let mut runs = Vec::new();
+    for column in 0..stride {
+        let mut index = column;
+        while index < cv.len() {
+            if !cv[index] {
+                index += stride;
+                continue;
+            }
+            let run_start = index;
+            let mut run_length = 0;
+            while index < cv.len() && cv[index] {
+                run_length += 1;
+                index += stride;
+            }
+            runs.push((run_start, run_length));
+        }
+    }
+
+    // strided_prefix_sums[i + stride] = sector_checksums[i] + sector_checksums[i - stride] + ..., the first stride entries are 0
+    // Wrapping is fine as every sum we take out of it fits in a u64 so the difference is still exact
+    let mut strided_prefix_sums = vec![0u64; stride + sector_checksums.len()];
+    for (index, checksum) in sector_checksums.iter().enumerate() {
+        strided_prefix_sums[index + stride] =
+            strided_prefix_sums[index].wrapping_add(u64::from(*checksum));
+    }
+
+    let nresults = sector_checksums.len().saturating_sub(cv.len() - 1);
+    (0..nresults)
+        .map(|block_start| {
+            runs.iter()
+                .map(|&(run_start, run_length)| {
+                    let first = block_start + run_start;
+                    let last = first + (run_length - 1) * stride;
+                    strided_prefix_sums[last + stride].wrapping_sub(strided_prefix_sums[first])
+                })
+                .fold(0u64, |sum, run_sum| sum.wrapping_add(run_sum))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct YoloConfig {
+    // The table built by build-checksum-table, it has the fletcher4 checksum of every sector on the disk
+    pub checksum_map_path: PathBuf,
+    // Optional, see build_secondary_checksum_table
+    pub secondary_checksum_map_path: Option<PathBuf>,
+    // Where to remember the results of previous searches, as a search takes a long time
+    pub cache_path: PathBuf,
+    pub enabled: bool,
+    // The guid of the top level vdev the tables have to be made for, cli::open_pool sets it, None if it's not known
+    pub vdev_guid: Option<u64>,
+}
+
+impl Default for YoloConfig {
+    fn default() -> Self {
+        YoloConfig {
+            checksum_map_path: PathBuf::from("checksum-map.bin"),
+            secondary_checksum_map_path: None,
+            cache_path: PathBuf::from("yolo-cache.json"),
+            enabled: cfg!(feature = "yolo"),
+            vdev_guid: None,
+        }
+    }
+}
+
+// (checksum, psize)
+type YoloCacheKey = ([u64; 4], usize);
+// (first word of the checksum, psize)
+type PartialMatchesKey = (u32, usize);
+
+#[derive(Debug, Default)]
+struct YoloCache {
+    // The result of every search, None means the block wasn't found
+    results: HashMap<YoloCacheKey, Option<u64>>,
+    // The offsets where the partial block checksum matched the first word of a checksum
+    // Finding these is the slow part of a search, so they are kept even if none of them turned out to be the block
+    // that way searching again (ex. after getting interrupted while checking them, or for another block with the same first word) doesn't have to go through the whole checksum table
+    partial_matches: HashMap<PartialMatchesKey, Vec<u64>>,
+}
+
+// json can't have tuples as keys, so the maps are stored as lists of pairs
+#[derive(Serialize, Deserialize)]
+struct YoloCacheOnDisk {
+    results: Vec<(YoloCacheKey, Option<u64>)>,
+    partial_matches: Vec<(PartialMatchesKey, Vec<u64>)>,
 }
 
 lazy_static! {
-    static ref YOLO_CACHE: Mutex<HashMap<([u64; 4], usize), Option<u64>>> = Mutex::new(
-        serde_json::from_reader::<_, Vec<(_, _)>>(File::open("yolo-cache.json").unwrap(),)
-            .unwrap()
-            .into_iter()
-            .collect()
-    );
+    static ref YOLO_CONFIG: RwLock<YoloConfig> = RwLock::new(YoloConfig::default());
+    // None means the cache was not loaded from disk yet
+    static ref YOLO_CACHE: Mutex<Option<YoloCache>> = Mutex::new(None);
+}
+
+pub fn set_yolo_config(config: YoloConfig) {
+    if let Ok(mut lock) = YOLO_CONFIG.write() {
+        *lock = config;
+    }
+
+    // The cache might be in a different place now, so load it again next time
+    if let Ok(mut lock) = YOLO_CACHE.lock() {
+        *lock = None;
+    }
+}
+
+pub fn get_yolo_config() -> YoloConfig {
+    YOLO_CONFIG
+        .read()
+        .map(|config| config.clone())
+        .unwrap_or_default()
+}
+
+pub fn is_enabled() -> bool {
+    get_yolo_config().enabled
+}
+
+// A missing or broken cache is not a problem, we just start with an empty one
+fn load_yolo_cache(config: &YoloConfig) -> YoloCache {
+    let Ok(contents) = std::fs::read(&config.cache_path) else {
+        return YoloCache::default();
+    };
+
+    if let Ok(on_disk) = serde_json::from_slice::<YoloCacheOnDisk>(&contents) {
+        return YoloCache {
+            results: on_disk.results.into_iter().collect(),
+            partial_matches: on_disk.partial_matches.into_iter().collect(),
+        };
+    }
+
+    // Older versions only saved the results, as a plain list
+    match serde_json::from_slice::<Vec<(_, _)>>(&contents) {
+        Ok(entries) => YoloCache {
+            results: entries.into_iter().collect(),
+            partial_matches: HashMap::new(),
+        },
+        Err(_) => {
+            use crate::ansi_color::*;
+            println!(
+                "{YELLOW}Warning{WHITE}: Couldn't parse yolo cache {:?}, starting with an empty cache!",
+                config.cache_path
+            );
+            YoloCache::default()
+        }
+    }
+}
+
+fn save_yolo_cache(config: &YoloConfig, cache: &YoloCache) {
+    let Ok(mut file) = OpenOptions::new()
+        .truncate(true)
+        .create(true)
+        .write(true)
+        .open(&config.cache_path)
+    else {
+        use crate::ansi_color::*;
+        println!(
+            "{YELLOW}Warning{WHITE}: Couldn't save yolo cache to {:?}!",
+            config.cache_path
+        );
+        return;
+    };
+
+    let on_disk = YoloCacheOnDisk {
+        results: cache.results.iter().map(|(k, v)| (*k, *v)).collect(),
+        partial_matches: cache
+            .partial_matches
+            .iter()
+            .map(|(k, v)| (*k, v.clone()))
+            .collect(),
+    };
+    let _ = write!(file, "{}", serde_json::to_string(&on_disk).unwrap());
+}
+
+// Eh.. it's not that big a deal if we can't lock, we just miss some optimisations, just don't crash the app that's the main priority
+fn with_yolo_cache<T>(config: &YoloConfig, f: impl FnOnce(&mut YoloCache) -> T) -> Option<T> {
+    let mut lock = YOLO_CACHE.lock().ok()?;
+    Some(f(lock.get_or_insert_with(|| load_yolo_cache(config))))
+}
+
+fn get_from_yolo_cache(config: &YoloConfig, key: &YoloCacheKey) -> Option<Option<u64>> {
+    with_yolo_cache(config, |cache| cache.results.get(key).copied())?
+}
+
+fn put_in_yolo_cache(config: &YoloConfig, key: YoloCacheKey, value: Option<u64>) {
+    with_yolo_cache(config, |cache| {
+        cache.results.insert(key, value);
+        save_yolo_cache(config, cache);
+    });
+}
+
+fn get_partial_matches_from_yolo_cache(
+    config: &YoloConfig,
+    key: &PartialMatchesKey,
+) -> Option<Vec<u64>> {
+    with_yolo_cache(config, |cache| cache.partial_matches.get(key).cloned())?
+}
+
+fn put_partial_matches_in_yolo_cache(config: &YoloConfig, key: PartialMatchesKey, value: Vec<u64>) {
+    with_yolo_cache(config, |cache| {
+        cache.partial_matches.insert(key, value);
+        save_yolo_cache(config, cache);
+    });
 }
 
 // Returns: Iterator that yields possible offsets for every checksum
 // NOTE: Will *not* work for finding the contents of gang blocks
 // but will work for finding the gang block itself
+// For a plain disk or a mirror use raidz_ndevices = 1 and raidz_nparity = 0
+// Returns: None if the table was made for another vdev (vdev_guid is the guid of the top level vdev that is searched, None if it's not known) or with another sector size
 
 pub fn potential_matches_for_block_with_fletcher4_checksum_vectorized(
     raidz_ndevices: usize,
     raidz_nparity: usize,
     sector_size: usize,
+    vdev_guid: Option<u64>,
     psize: usize,
     checksums_to_look_for: HashMap<u32, [u64; 4]>,
-    open_checksum_map: fn() -> File,
+    open_checksum_map: impl Fn() -> File + Send + Sync,
 ) -> Option<impl ParallelIterator<Item = ([u64; 4], u64)>> {
     let mut checksum_map_file = open_checksum_map();
-    let checksum_map_file_size = checksum_map_file.seek(SeekFrom::End(0)).unwrap();
+    let table_start = match read_checksum_table_header(&mut checksum_map_file) {
+        Some(header) => {
+            use crate::ansi_color::*;
+            if header.kind != ChecksumTableKind::Primary
+                || header.sector_size != sector_size as u64
+                || header.entry_width != core::mem::size_of::<ChecksumTableEntry>() as u64
+            {
+                println!("{RED}Fatal{WHITE}: Checksum table was made with {header:?}, but we are searching using a sector size of {sector_size}!");
+                return None;
+            }
+            match vdev_guid {
+                Some(vdev_guid) if vdev_guid != header.vdev_guid => {
+                    println!("{RED}Fatal{WHITE}: Checksum table was made for the vdev with guid {}, but we are searching the vdev with guid {vdev_guid}, it's probably from another pool!", header.vdev_guid);
+                    return None;
+                }
+                Some(_) => (),
+                None => println!("{YELLOW}Warning{WHITE}: The guid of the vdev that is searched isn't known, can't check that the checksum table (made for the vdev with guid {}) is for this pool!", header.vdev_guid),
+            }
+            ChecksumTableHeader::get_ondisk_size() as u64
+        }
+        None => {
+            use crate::ansi_color::*;
+            if cfg!(feature = "debug") {
+                println!("{YELLOW}Warning{WHITE}: Checksum table has no header, can't check that it was made for this pool!");
+            }
+            0
+        }
+    };
+    let checksum_map_file_size = checksum_map_file.seek(SeekFrom::End(0)).unwrap() - table_start;
 
     // Extrapolate disk size from checksum map file size
     let disk_size = (checksum_map_file_size / core::mem::size_of::<ChecksumTableEntry>() as u64)
         * sector_size as u64;
 
-    let block_size_upper_bound =
-        psize / sector_size + psize / sector_size / (raidz_ndevices - 1) + 1;
+    // The data sectors plus the parity sectors of every stripe, a plain disk is a raidz with one device and no parity so it has none
+    let number_of_data_sectors = psize.div_ceil(sector_size);
+    let block_size_upper_bound = number_of_data_sectors
+        + number_of_data_sectors.div_ceil(raidz_ndevices - raidz_nparity) * raidz_nparity;
 
     let is_raidz1 = raidz_nparity == 1;
 
@@ -156,8 +718,9 @@ pub fn potential_matches_for_block_with_fletcher4_checksum_vectorized(
                             * core::mem::size_of::<ChecksumTableEntry>()
                     ];
 
-                    let checksum_file_offset = (off / sector_size as u64)
-                        * core::mem::size_of::<ChecksumTableEntry>() as u64;
+                    let checksum_file_offset = table_start
+                        + (off / sector_size as u64)
+                            * core::mem::size_of::<ChecksumTableEntry>() as u64;
                     checksum_map_file
                         .seek(SeekFrom::Start(checksum_file_offset))
                         .unwrap();
@@ -202,21 +765,159 @@ pub fn potential_matches_for_block_with_fletcher4_checksum_vectorized(
     )
 }
 
+// Returns: The index (relative to the first sector of the block) of every data sector in the block, in the order in which they appear in the data
+// This has to match how zio::DataVirtualAddress::dereference_raw puts the data back together
+pub fn get_data_sector_order_for_block(
+    off: u64,
+    psize: usize,
+    is_raidz1: bool,
+    sector_size: usize,
+    raidz_ndevices: usize,
+    raidz_nparity: usize,
+) -> Vec<u64> {
+    let ndata_columns = raidz_ndevices - raidz_nparity;
+    let number_of_data_sectors = psize.div_ceil(sector_size);
+    let number_of_stripes = number_of_data_sectors.div_ceil(ndata_columns);
+    let total_number_of_sectors = number_of_data_sectors + number_of_stripes * raidz_nparity;
+
+    let mut column_mapping = (0..raidz_ndevices).collect::<Vec<usize>>();
+    if is_raidz1 && !(off / (1024 * 1024)).is_multiple_of(2) {
+        column_mapping.swap(0, 1);
+    }
+
+    let mut res = Vec::with_capacity(number_of_data_sectors);
+    for column_number in raidz_nparity..raidz_ndevices {
+        let actual_column = column_mapping[column_number];
+        res.extend(
+            (actual_column..total_number_of_sectors)
+                .step_by(raidz_ndevices)
+                .map(|index| index as u64),
+        );
+    }
+    res.truncate(number_of_data_sectors);
+    res
+}
+
+fn read_checksum_table_entries(
+    checksum_map_file: &mut File,
+    table_start: u64,
+    first_sector: u64,
+    nsectors: usize,
+) -> Option<Vec<ChecksumTableEntry>> {
+    let mut data = vec![0u8; nsectors * core::mem::size_of::<ChecksumTableEntry>()];
+    checksum_map_file
+        .seek(SeekFrom::Start(
+            table_start + first_sector * core::mem::size_of::<ChecksumTableEntry>() as u64,
+        ))
+        .ok()?;
+    checksum_map_file.read_exact(&mut data).ok()?;
+    Some(
+        data.chunks_exact(core::mem::size_of::<ChecksumTableEntry>())
+            .map(|entry| ChecksumTableEntry::from_le_bytes(entry.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+// Checks the second word of the checksum of a partial match using only the checksum tables
+// For fletcher4, if X and Y are two pieces of data, and Y has m words then:
+// A(X||Y) = A(X) + A(Y), B(X||Y) = B(X) + B(Y) + m*A(X)
+// So B of the whole block can be computed from the A and B of every sector, as long as we know the order of the sectors
+// Returns: false only if the match is definitely wrong, if we can't tell it returns true
+#[allow(clippy::too_many_arguments)]
+pub fn check_partial_match_with_secondary_table(
+    primary_checksum_map_file: &mut File,
+    secondary_checksum_map_file: &mut File,
+    off: u64,
+    psize: usize,
+    sector_size: usize,
+    raidz_ndevices: usize,
+    raidz_nparity: usize,
+    checksum: &[u64; 4],
+) -> bool {
+    // The tables have the checksum of whole sectors, so we can't do anything for blocks that end in the middle of a sector
+    if !psize.is_multiple_of(sector_size) {
+        return true;
+    }
+
+    let (Some(primary_header), Some(secondary_header)) = (
+        read_checksum_table_header(primary_checksum_map_file),
+        read_checksum_table_header(secondary_checksum_map_file),
+    ) else {
+        return true;
+    };
+
+    if primary_header.kind != ChecksumTableKind::Primary
+        || secondary_header.kind != ChecksumTableKind::Secondary
+        || primary_header.sector_size != secondary_header.sector_size
+        || primary_header.vdev_guid != secondary_header.vdev_guid
+        || primary_header.sector_size != sector_size as u64
+    {
+        return true;
+    }
+
+    let sector_order = get_data_sector_order_for_block(
+        off,
+        psize,
+        raidz_nparity == 1,
+        sector_size,
+        raidz_ndevices,
+        raidz_nparity,
+    );
+    let nsectors = sector_order
+        .iter()
+        .max()
+        .map(|max| *max as usize + 1)
+        .unwrap_or(0);
+    let first_sector = off / sector_size as u64;
+    let table_start = ChecksumTableHeader::get_ondisk_size() as u64;
+    let (Some(a), Some(b)) = (
+        read_checksum_table_entries(
+            primary_checksum_map_file,
+            table_start,
+            first_sector,
+            nsectors,
+        ),
+        read_checksum_table_entries(
+            secondary_checksum_map_file,
+            table_start,
+            first_sector,
+            nsectors,
+        ),
+    ) else {
+        return true;
+    };
+
+    // All of this is mod 2^32 as the tables only have the lower 32 bits
+    let words_per_sector = (sector_size / core::mem::size_of::<u32>()) as u32;
+    let mut words_after = (sector_order.len() as u32).wrapping_mul(words_per_sector);
+    let mut block_b: u32 = 0;
+    for sector in sector_order {
+        words_after = words_after.wrapping_sub(words_per_sector);
+        block_b = block_b
+            .wrapping_add(b[sector as usize])
+            .wrapping_add(a[sector as usize].wrapping_mul(words_after));
+    }
+
+    block_b == checksum[1] as u32
+}
+
 pub fn find_block_with_fletcher4_checksum(
     vdevs: &mut Vdevs,
     checksum: &[u64; 4],
     psize: usize,
 ) -> Option<u64> {
-    if let Ok(Some(res_off)) = YOLO_CACHE
-        .lock()
-        .map(|m| m.get(&(*checksum, psize)).copied())
-    {
+    let config = get_yolo_config();
+    if !config.enabled {
+        return None;
+    }
+
+    if let Some(res_off) = get_from_yolo_cache(&config, &(*checksum, psize)) {
         return res_off;
     }
 
-    let raidz_vdev = vdevs.get_mut(&0)?;
-    let raidz_vdev_info = raidz_vdev.get_raidz_info()?;
-    let sector_size = raidz_vdev.get_asize();
+    let top_level_vdev = &**vdevs.get(&0)?;
+    let layout = get_layout(top_level_vdev);
+    let sector_size = get_sector_size(top_level_vdev);
     let vdevs = Mutex::from(vdevs);
 
     use crate::ansi_color::*;
@@ -227,44 +928,87 @@ pub fn find_block_with_fletcher4_checksum(
             sector_size
         );
 
-    use rayon::prelude::*;
-    let result: Option<u64> = potential_matches_for_block_with_fletcher4_checksum_vectorized(
-        raidz_vdev_info.ndevices,
-        raidz_vdev_info.nparity,
-        sector_size,
-        psize,
-        HashMap::from([(checksum[0] as u32, *checksum)]),
-        || File::open("checksum-map.bin").unwrap(),
-    )?
-    .map(|(_, match_off)| match_off)
-    .find_any(move |&partial_match_off| {
-        // Check to see if the match is correct
-        let dva = DataVirtualAddress::from(0, partial_match_off, false);
-        let Ok(data) = dva.dereference(&mut vdevs.lock().unwrap(), psize) else { return false; };
-        let checksum_of_match = do_fletcher4(&data);
-        return checksum_of_match == *checksum;
-    });
+    let partial_matches_key = (checksum[0] as u32, psize);
+    let partial_matches = match get_partial_matches_from_yolo_cache(&config, &partial_matches_key) {
+        Some(partial_matches) => {
+            println!(
+                "{CYAN}Info{WHITE}: Using the {} partial matches found by a previous search!",
+                partial_matches.len()
+            );
+            partial_matches
+        }
+        None => {
+            // Without the checksum map there is nothing to search through
+            if File::open(&config.checksum_map_path).is_err() {
+                if cfg!(feature = "debug") {
+                    println!(
+                        "{YELLOW}Warning{WHITE}: Can't do YOLO block recovery, checksum map {:?} couldn't be opened!",
+                        config.checksum_map_path
+                    );
+                }
+                return None;
+            }
 
-    let save_yolo_cache = |map: &HashMap<_, _>| {
-        // Save the new cache
-        write!(
-            OpenOptions::new()
-                .truncate(true)
-                .create(true)
-                .write(true)
-                .open("yolo-cache.json")
-                .unwrap(),
-            "{}",
-            serde_json::to_string(&map.iter().collect::<Vec<(_, _)>>()).unwrap()
-        )
-        .unwrap();
+            // NOTE: This has to go through the whole table, instead of stopping at the first match that is the block, so that all of the partial matches can be saved
+            let checksum_map_path = config.checksum_map_path.clone();
+            let partial_matches: Vec<u64> =
+                potential_matches_for_block_with_fletcher4_checksum_vectorized(
+                    layout.ndevices,
+                    layout.nparity,
+                    sector_size,
+                    config.vdev_guid,
+                    psize,
+                    HashMap::from([(checksum[0] as u32, *checksum)]),
+                    move || File::open(&checksum_map_path).unwrap(),
+                )?
+                .map(|(_, match_off)| match_off)
+                .collect();
+            put_partial_matches_in_yolo_cache(
+                &config,
+                partial_matches_key,
+                partial_matches.clone(),
+            );
+            partial_matches
+        }
     };
 
+    use rayon::prelude::*;
+    let result: Option<u64> = partial_matches
+        .into_par_iter()
+        .filter(|&partial_match_off| {
+            // Weed out false positives using the secondary table, if there is one, this is a lot cheaper than reading the block
+            let Some(secondary_checksum_map_path) = &config.secondary_checksum_map_path else {
+                return true;
+            };
+            let (Ok(mut primary), Ok(mut secondary)) = (
+                File::open(&config.checksum_map_path),
+                File::open(secondary_checksum_map_path),
+            ) else {
+                return true;
+            };
+            check_partial_match_with_secondary_table(
+                &mut primary,
+                &mut secondary,
+                partial_match_off,
+                psize,
+                sector_size,
+                layout.ndevices,
+                layout.nparity,
+                checksum,
+            )
+        })
+        .find_any(move |&partial_match_off| {
+            // Check to see if the match is correct
+            let dva = DataVirtualAddress::from(0, partial_match_off, false);
+            let Ok(data) = dva.dereference(&mut vdevs.lock().unwrap(), psize) else {
+                return false;
+            };
+            let checksum_of_match = do_fletcher4(&data);
+            return checksum_of_match == *checksum;
+        });
+
     if let Some(off) = result {
-        if let Ok(mut lock) = YOLO_CACHE.lock() {
-            lock.insert((*checksum, psize), Some(off));
-            save_yolo_cache(&*lock);
-        } // Eh.. it's not that big a deal if we can't lock, we just miss some optimisations, just don't crash the app that's the main priority
+        put_in_yolo_cache(&config, (*checksum, psize), Some(off));
 
         println!(
                 "{CYAN}Info{WHITE}: YOLO block recovery succeded for block with checksum: {:?}, the result was offset {:?}!",
@@ -274,10 +1018,7 @@ pub fn find_block_with_fletcher4_checksum(
 
         return Some(off);
     } else {
-        if let Ok(mut lock) = YOLO_CACHE.lock() {
-            lock.insert((*checksum, psize), None);
-            save_yolo_cache(&*lock);
-        } // Eh.. it's not that big a deal if we can't lock, we just miss some optimisations, just don't crash the app that's the main priority
+        put_in_yolo_cache(&config, (*checksum, psize), None);
 
         println!(
             "{YELLOW}Warning{WHITE}: YOLO block recovery failed for block with checksum: {:?}!",
@@ -1,8 +1,9 @@
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 
-use crate::byte_iter::{ByteIter, FromBytes, FromBytesBE, FromBytesLE};
+use crate::byte_iter::{self, read_le_at, FromBytes, FromBytesBE, FromBytesLE, FromSliceLE};
 use crate::dmu::DNodeBase;
+use crate::warnings::{self, WarningKind};
 use crate::zio::Vdevs;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -55,6 +56,44 @@ pub enum Value {
     U16Array(Vec<u16>),
 }
 
+impl Value {
+    // The size of the integers the value is made of, like it's stored in a fat zap entry
+    pub fn get_int_size(&self) -> usize {
+        match self {
+            Value::U64(_) | Value::U64Array(_) => 8,
+            Value::U16(_) | Value::U16Array(_) => 2,
+            Value::Byte(_) | Value::ByteArray(_) => 1,
+        }
+    }
+
+    pub fn get_nvalues(&self) -> usize {
+        match self {
+            Value::U64(_) | Value::U16(_) | Value::Byte(_) => 1,
+            Value::U64Array(values) => values.len(),
+            Value::U16Array(values) => values.len(),
+            Value::ByteArray(values) => values.len(),
+        }
+    }
+
+    // NOTE: Integers are stored big endian in the chunks
+    pub fn to_bytes_be(&self) -> Vec<u8> {
+        match self {
+            Value::U64(value) => value.to_be_bytes().to_vec(),
+            Value::U16(value) => value.to_be_bytes().to_vec(),
+            Value::Byte(value) => vec![*value],
+            Value::U64Array(values) => values
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect(),
+            Value::U16Array(values) => values
+                .iter()
+                .flat_map(|value| value.to_be_bytes())
+                .collect(),
+            Value::ByteArray(values) => values.clone(),
+        }
+    }
+}
+
 impl Debug for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -68,12 +107,20 @@ impl Debug for Value {
     }
 }
 
-fn micro_zap_name_repeated() {
-    panic!("Micro Zap name repeated, this is not supported!");
+fn micro_zap_name_repeated() -> Option<()> {
+    use crate::ansi_color::*;
+    if cfg!(feature = "debug") {
+        println!("{YELLOW}Warning{WHITE}: Micro Zap name repeated, this is not supported!");
+    }
+    None
 }
 
-fn fat_zap_name_repeated() {
-    panic!("Fat Zap name repeated, this is not supported!");
+fn fat_zap_name_repeated() -> Option<()> {
+    use crate::ansi_color::*;
+    if cfg!(feature = "debug") {
+        println!("{YELLOW}Warning{WHITE}: Fat Zap name repeated, this is not supported!");
+    }
+    None
 }
 
 pub struct MicroZapEntry {
@@ -82,21 +129,25 @@ pub struct MicroZapEntry {
     name: Vec<u8>,
 }
 
+impl FromSliceLE for MicroZapEntry {
+    fn from_slice_le(data: &[u8]) -> Option<MicroZapEntry> {
+        let data = data.get(..Self::get_ondisk_size())?;
+        // The 2 bytes after the collision differentiator are padding
+        Some(MicroZapEntry {
+            value: read_le_at::<u64>(data, 0)?,
+            collision_differentiator: read_le_at::<u32>(data, 8)?,
+            name: data[Self::get_ondisk_size() - Self::get_name_length()..].to_vec(),
+        })
+    }
+}
+
 impl<It> FromBytesLE<It> for MicroZapEntry
 where
     It: Iterator<Item = u8>,
 {
     fn from_bytes_le(data: &mut It) -> Option<MicroZapEntry> {
-        let value = u64::from_bytes_le(data)?;
-        let collision_differentiator = u32::from_bytes_le(data)?;
-        data.skip_n_bytes(core::mem::size_of::<u16>())?;
-        let name = Vec::from_iter(data.take(Self::get_name_length()));
-        assert!(name.len() == Self::get_name_length());
-        Some(MicroZapEntry {
-            value,
-            collision_differentiator,
-            name,
-        })
+        let data = byte_iter::read_array::<{ MicroZapEntry::get_ondisk_size() }>(data)?;
+        Self::from_slice_le(&data)
     }
 }
 
@@ -123,13 +174,159 @@ impl MicroZapEntry {
             .insert(name.to_string(), Value::U64(self.value))
             .is_some()
         {
-            micro_zap_name_repeated()
+            return micro_zap_name_repeated();
         }
         Some(())
     }
+
+    // Returns: The name without the nul, None for empty entries
+    pub fn get_name(&self) -> Option<&[u8]> {
+        let nul_index = self.name.iter().position(|byte| *byte == 0)?;
+        if nul_index == 0 {
+            return None;
+        }
+        Some(&self.name[0..nul_index])
+    }
+
+    pub fn to_bytes_le(&self) -> [u8; Self::get_ondisk_size()] {
+        let mut res = [0u8; Self::get_ondisk_size()];
+        res[0..8].copy_from_slice(&self.value.to_le_bytes());
+        res[8..12].copy_from_slice(&self.collision_differentiator.to_le_bytes());
+        res[14..14 + self.name.len()].copy_from_slice(&self.name);
+        res
+    }
+}
+
+// Writing zaps, for repairs like putting the entry of a recovered file back into its directory before importing the pool again
+// Only what fits in the blocks that are already there can be written, zfs would upgrade a full micro zap to a fat zap or split a full leaf instead
+// the blocks still have to be written back like any other repaired block (ex. with a binpatch), and the block pointers above them updated
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zap_micro.c (mzap_addent)
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zap_leaf.c (zap_entry_create, zap_entry_remove)
+
+// The header of a micro zap, the entries are after it
+const MICRO_ZAP_HEADER_SIZE: usize = 64;
+
+pub struct MicroZap {
+    salt: u64,
+    normalization_flags: u64,
+    // One per slot of the block, including the empty ones
+    entries: Vec<MicroZapEntry>,
+}
+
+impl MicroZap {
+    // `data` is the whole first block of the zap, like dump_contents reads it
+    pub fn from_bytes_le(data: &[u8]) -> Option<MicroZap> {
+        if ZapType::from_value(read_le_at::<u64>(data, 0)?)? != ZapType::MicroZap {
+            return None;
+        }
+        let salt = read_le_at::<u64>(data, 8)?;
+        let normalization_flags = read_le_at::<u64>(data, 16)?;
+        let entries = data
+            .get(MICRO_ZAP_HEADER_SIZE..)?
+            .chunks_exact(MicroZapEntry::get_ondisk_size())
+            .map(MicroZapEntry::from_slice_le)
+            .collect::<Option<Vec<MicroZapEntry>>>()?;
+        Some(MicroZap {
+            salt,
+            normalization_flags,
+            entries,
+        })
+    }
+
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(
+            MICRO_ZAP_HEADER_SIZE + self.entries.len() * MicroZapEntry::get_ondisk_size(),
+        );
+        res.extend((ZapType::MicroZap as u64).to_le_bytes());
+        res.extend(self.salt.to_le_bytes());
+        res.extend(self.normalization_flags.to_le_bytes());
+        res.resize(MICRO_ZAP_HEADER_SIZE, 0);
+        for entry in self.entries.iter() {
+            res.extend(entry.to_bytes_le());
+        }
+        res
+    }
+
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.entries
+            .iter()
+            .find(|entry| entry.get_name() == Some(name.as_bytes()))
+            .map(|entry| entry.value)
+    }
+
+    // Returns: Some(true) if the entry is new, Some(false) if an entry with the name was changed
+    // None if the name doesn't fit, the block is full, or the zap normalizes names (then the hash can't be calculated)
+    pub fn set_entry(&mut self, name: &str, value: u64) -> Option<bool> {
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.get_name() == Some(name.as_bytes()))
+        {
+            entry.value = value;
+            return Some(false);
+        }
+        // The name has to fit with its nul
+        if name.is_empty()
+            || name.len() >= MicroZapEntry::get_name_length()
+            || self.normalization_flags != 0
+        {
+            return None;
+        }
+
+        // Entries with the same hash are told apart by the collision differentiator, the new one gets the lowest one that isn't used yet
+        let hash = calculate_zap_hash(self.salt, name.as_bytes(), MICRO_ZAP_HASH_BITS);
+        let used_collision_differentiators = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let entry_hash =
+                    calculate_zap_hash(self.salt, entry.get_name()?, MICRO_ZAP_HASH_BITS);
+                (entry_hash == hash).then_some(entry.collision_differentiator)
+            })
+            .collect::<HashSet<u32>>();
+        let collision_differentiator =
+            (0..).find(|cd| !used_collision_differentiators.contains(cd))?;
+
+        let free_entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.get_name().is_none())?;
+        let mut entry_name = name.as_bytes().to_vec();
+        entry_name.resize(MicroZapEntry::get_name_length(), 0);
+        *free_entry = MicroZapEntry {
+            value,
+            collision_differentiator,
+            name: entry_name,
+        };
+        Some(true)
+    }
+
+    // Returns: false if there was no entry with the name
+    pub fn remove_entry(&mut self, name: &str) -> bool {
+        let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.get_name() == Some(name.as_bytes()))
+        else {
+            return false;
+        };
+        *entry = MicroZapEntry {
+            value: 0,
+            collision_differentiator: 0,
+            name: vec![0u8; MicroZapEntry::get_name_length()],
+        };
+        true
+    }
 }
 
 #[derive(Debug)]
+pub struct ZapRawEntry {
+    pub name: Vec<u8>,
+    pub int_size: usize,
+    pub value: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
 pub struct ZapLeaf {
     header: ZapLeafHeader,
     hash_table: Vec<u16>,
@@ -142,26 +339,28 @@ impl ZapLeaf {
         block_size / 32
     }
 
-    pub fn from_bytes_le(
-        data: &mut impl Iterator<Item = u8>,
-        block_size: usize,
-    ) -> Option<ZapLeaf> {
-        let header = ZapLeafHeader::from_bytes_le(data)?;
-        let mut hash_table = vec![0u16; Self::get_hash_table_numentries(block_size)];
-        for value in hash_table.iter_mut() {
-            *value = u16::from_bytes_le(data)?;
-        }
+    pub fn from_slice_le(data: &[u8], block_size: usize) -> Option<ZapLeaf> {
+        let header = ZapLeafHeader::from_slice_le(data)?;
+        let hash_table_end = ZapLeafHeader::get_ondisk_size()
+            + Self::get_hash_table_numentries(block_size) * core::mem::size_of::<u16>();
+        let hash_table = data
+            .get(ZapLeafHeader::get_ondisk_size()..hash_table_end)?
+            .chunks_exact(core::mem::size_of::<u16>())
+            .map(u16::from_slice_le)
+            .collect::<Option<Vec<u16>>>()?;
 
         // Calculate length of chunk array
         // https://github.com/openzfs/zfs/blob/master/include/sys/zap_leaf.h#L45
-        let remaining_bytes = block_size
-            - ZapLeafHeader::get_ondisk_size()
-            - Self::get_hash_table_numentries(block_size) * core::mem::size_of::<u16>();
+        let remaining_bytes = block_size.checked_sub(
+            ZapLeafHeader::get_ondisk_size()
+                + Self::get_hash_table_numentries(block_size) * core::mem::size_of::<u16>(),
+        )?;
         let nchunks = remaining_bytes / ZapLeafChunk::get_ondisk_size();
-        let mut chunks = Vec::<ZapLeafChunk>::new();
-        for _ in 0..nchunks {
-            chunks.push(ZapLeafChunk::from_bytes_le(data)?);
-        }
+        let chunks = data
+            .get(hash_table_end..hash_table_end + nchunks * ZapLeafChunk::get_ondisk_size())?
+            .chunks_exact(ZapLeafChunk::get_ondisk_size())
+            .map(ZapLeafChunk::from_slice_le)
+            .collect::<Option<Vec<ZapLeafChunk>>>()?;
 
         Some(ZapLeaf {
             header,
@@ -174,8 +373,13 @@ impl ZapLeaf {
         &self.chunks
     }
 
+    // NOTE: Entries whose hash doesn't match their name are still dumped, but a warning is printed as the entry is probably corrupted
     #[must_use]
-    pub fn dump_contents_into(&self, hashmap: &mut HashMap<String, Value>) -> Option<()> {
+    pub fn dump_contents_into(
+        &self,
+        hashmap: &mut HashMap<String, Value>,
+        header: &FatZapHeader,
+    ) -> Option<()> {
         for chunk in self.get_chunks() {
             match chunk {
                 ZapLeafChunk::Entry {
@@ -186,7 +390,7 @@ impl ZapLeaf {
                     value_chunk_id,
                     nvalues,
                     collision_differentiator: _,
-                    hash: _,
+                    hash,
                 } => {
                     let int_size = usize::from(*int_size);
                     let name_length = usize::from(*name_length);
@@ -194,7 +398,7 @@ impl ZapLeaf {
 
                     let name_chunk = self.read_data_starting_at_chunk(
                         usize::from(*name_chunk_id),
-                        name_length - 1,
+                        name_length.checked_sub(1)?,
                     )?;
                     let value_chunk = self.read_data_starting_at_chunk(
                         usize::from(*value_chunk_id),
@@ -202,11 +406,18 @@ impl ZapLeaf {
                     )?;
                     let name = std::str::from_utf8(&name_chunk).ok()?;
 
+                    if let Some(name_hash) = header.calculate_name_hash(&name_chunk) {
+                        if name_hash != *hash {
+                            use crate::ansi_color::*;
+                            println!("{YELLOW}Warning{WHITE}: Fat zap entry {name:?} has hash {hash:#018x}, but its name hashes to {name_hash:#018x}, the entry is probably corrupted!");
+                        }
+                    }
+
                     match int_size {
                         8 if nvalues == 1 => {
                             let value = u64::from_bytes_be(&mut value_chunk.iter().copied())?;
                             if hashmap.insert(name.to_owned(), Value::U64(value)).is_some() {
-                                fat_zap_name_repeated()
+                                return fat_zap_name_repeated();
                             }
                         }
 
@@ -220,7 +431,7 @@ impl ZapLeaf {
                                 .insert(name.to_owned(), Value::U64Array(values))
                                 .is_some()
                             {
-                                fat_zap_name_repeated()
+                                return fat_zap_name_repeated();
                             }
                         }
 
@@ -230,7 +441,7 @@ impl ZapLeaf {
                                 .insert(name.to_owned(), Value::Byte(value))
                                 .is_some()
                             {
-                                fat_zap_name_repeated()
+                                return fat_zap_name_repeated();
                             }
                         }
 
@@ -244,14 +455,14 @@ impl ZapLeaf {
                                 .insert(name.to_owned(), Value::ByteArray(values))
                                 .is_some()
                             {
-                                fat_zap_name_repeated()
+                                return fat_zap_name_repeated();
                             }
                         }
 
                         2 if nvalues == 1 => {
                             let value = u16::from_bytes_be(&mut value_chunk.iter().copied())?;
                             if hashmap.insert(name.to_owned(), Value::U16(value)).is_some() {
-                                fat_zap_name_repeated()
+                                return fat_zap_name_repeated();
                             }
                         }
 
@@ -265,15 +476,17 @@ impl ZapLeaf {
                                 .insert(name.to_owned(), Value::U16Array(values))
                                 .is_some()
                             {
-                                fat_zap_name_repeated()
+                                return fat_zap_name_repeated();
                             }
                         }
 
-                        _ => todo!(
-                            "Implement reading: {} values of size: {} in ZAP.",
-                            nvalues,
-                            int_size
-                        ),
+                        _ => {
+                            use crate::ansi_color::*;
+                            warnings::count_warning(WarningKind::UnsupportedFeature);
+                            if cfg!(feature = "debug") {
+                                println!("{YELLOW}Warning{WHITE}: Reading {nvalues} values of size {int_size} from a ZAP is not supported, skipping entry {name}!");
+                            }
+                        }
                     }
                 }
                 ZapLeafChunk::Array {
@@ -286,9 +499,42 @@ impl ZapLeaf {
         Some(())
     }
 
+    // Returns: The names and values of all entries as they are stored, for zaps whose names aren't strings (ex. the dedup tables)
+    // `name_int_size` is the size of the integers the names are made of, 1 for normal zaps and 8 for zaps with u64 arrays as keys
+    // NOTE: Integers are stored big endian in the chunks
+    pub fn get_raw_entries(&self, name_int_size: usize) -> Option<Vec<ZapRawEntry>> {
+        let mut entries = Vec::new();
+        for chunk in self.get_chunks() {
+            let ZapLeafChunk::Entry {
+                int_size,
+                name_chunk_id,
+                name_length,
+                value_chunk_id,
+                nvalues,
+                ..
+            } = chunk
+            else {
+                continue;
+            };
+
+            entries.push(ZapRawEntry {
+                name: self.read_data_starting_at_chunk(
+                    usize::from(*name_chunk_id),
+                    usize::from(*name_length) * name_int_size,
+                )?,
+                int_size: usize::from(*int_size),
+                value: self.read_data_starting_at_chunk(
+                    usize::from(*value_chunk_id),
+                    usize::from(*nvalues) * usize::from(*int_size),
+                )?,
+            });
+        }
+        Some(entries)
+    }
+
     pub fn read_data_starting_at_chunk(&self, chunk_id: usize, size: usize) -> Option<Vec<u8>> {
         let mut data = Vec::<u8>::new();
-        let mut chunk_to_read = &self.chunks[chunk_id];
+        let mut chunk_to_read = self.chunks.get(chunk_id)?;
         while data.len() < size {
             match chunk_to_read {
                 ZapLeafChunk::Entry {
@@ -309,7 +555,7 @@ impl ZapLeaf {
                     if *next_chunk_id == u16::MAX {
                         break;
                     }
-                    chunk_to_read = &self.chunks[usize::from(*next_chunk_id)];
+                    chunk_to_read = self.chunks.get(usize::from(*next_chunk_id))?;
                 }
                 ZapLeafChunk::Free { next_chunk_id: _ } => return None,
             }
@@ -322,7 +568,261 @@ impl ZapLeaf {
     }
 }
 
-#[derive(Debug)]
+// The end of a chain of array chunks, and of the chain of entries of a bucket of the hash table
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zap_leaf.h (CHAIN_END)
+const CHAIN_END: u16 = u16::MAX;
+
+impl ZapLeaf {
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let block_size = self.hash_table.len() * 32;
+        let mut res = self.header.to_bytes_le();
+        for value in self.hash_table.iter() {
+            res.extend(value.to_le_bytes());
+        }
+        for chunk in self.chunks.iter() {
+            res.extend(chunk.to_bytes_le());
+        }
+        res.resize(block_size, 0);
+        res
+    }
+
+    // Which bucket of the hash table the entries with this hash are in, the top bits are the prefix of the leaf so they are skipped
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zap_leaf.h (LEAF_HASH)
+    fn get_hash_table_index(&self, hash: u64) -> Option<usize> {
+        let shift = self.hash_table.len().trailing_zeros() + u32::from(self.header.prefix_len);
+        let index = hash.checked_shr(64u32.checked_sub(shift)?).unwrap_or(0);
+        Some(index as usize & (self.hash_table.len() - 1))
+    }
+
+    // Returns: true if the entries with this hash belong in this leaf
+    fn has_prefix_of(&self, hash: u64) -> bool {
+        self.header.prefix_len == 0
+            || hash.checked_shr(64 - u32::from(self.header.prefix_len)) == Some(self.header.prefix)
+    }
+
+    fn allocate_chunk(&mut self) -> Option<u16> {
+        let chunk_id = self.header.freelist;
+        let ZapLeafChunk::Free { next_chunk_id } = self.chunks.get(usize::from(chunk_id))? else {
+            return None;
+        };
+        self.header.freelist = *next_chunk_id;
+        self.header.nfree = self.header.nfree.checked_sub(1)?;
+        Some(chunk_id)
+    }
+
+    fn free_chunk(&mut self, chunk_id: u16) {
+        if let Some(chunk) = self.chunks.get_mut(usize::from(chunk_id)) {
+            *chunk = ZapLeafChunk::Free {
+                next_chunk_id: self.header.freelist,
+            };
+            self.header.freelist = chunk_id;
+            self.header.nfree += 1;
+        }
+    }
+
+    // Returns: The first chunk of the chain of array chunks the data was written to
+    fn write_array(&mut self, data: &[u8]) -> Option<u16> {
+        let chunk_ids = data
+            .chunks(ZapLeafChunk::get_byte_array_size())
+            .map(|_| self.allocate_chunk())
+            .collect::<Option<Vec<u16>>>()?;
+        for (index, array) in data.chunks(ZapLeafChunk::get_byte_array_size()).enumerate() {
+            let mut array = array.to_vec();
+            array.resize(ZapLeafChunk::get_byte_array_size(), 0);
+            self.chunks[usize::from(chunk_ids[index])] = ZapLeafChunk::Array {
+                array,
+                next_chunk_id: chunk_ids.get(index + 1).copied().unwrap_or(CHAIN_END),
+            };
+        }
+        Some(chunk_ids.first().copied().unwrap_or(CHAIN_END))
+    }
+
+    fn free_array(&mut self, first_chunk_id: u16) {
+        let mut chunk_id = first_chunk_id;
+        // A loop in a corrupt chain would otherwise never end
+        for _ in 0..self.chunks.len() {
+            let Some(ZapLeafChunk::Array { next_chunk_id, .. }) =
+                self.chunks.get(usize::from(chunk_id))
+            else {
+                return;
+            };
+            let next_chunk_id = *next_chunk_id;
+            self.free_chunk(chunk_id);
+            chunk_id = next_chunk_id;
+        }
+    }
+
+    // Returns: The chunks of the entries in the bucket the hash is in, in the order of the chain
+    fn get_chain(&self, hash: u64) -> Option<Vec<u16>> {
+        let mut chain = Vec::new();
+        let mut chunk_id = *self.hash_table.get(self.get_hash_table_index(hash)?)?;
+        while chunk_id != CHAIN_END {
+            let ZapLeafChunk::Entry { next_chunk_id, .. } =
+                self.chunks.get(usize::from(chunk_id))?
+            else {
+                return None;
+            };
+            // A loop in a corrupt chain would otherwise never end
+            if chain.len() >= self.chunks.len() {
+                return None;
+            }
+            chain.push(chunk_id);
+            chunk_id = *next_chunk_id;
+        }
+        Some(chain)
+    }
+
+    // Makes the entry before `chunk_id` in the chain of the bucket point to it, or the bucket itself if there is no entry before it
+    fn set_chain_link(
+        &mut self,
+        previous_chunk_id: Option<u16>,
+        bucket_index: usize,
+        chunk_id: u16,
+    ) {
+        match previous_chunk_id {
+            Some(previous_chunk_id) => {
+                if let Some(ZapLeafChunk::Entry { next_chunk_id, .. }) =
+                    self.chunks.get_mut(usize::from(previous_chunk_id))
+                {
+                    *next_chunk_id = chunk_id;
+                }
+            }
+            None => self.hash_table[bucket_index] = chunk_id,
+        }
+    }
+
+    fn get_entry_hash_and_cd(&self, chunk_id: u16) -> Option<(u64, u16)> {
+        match self.chunks.get(usize::from(chunk_id))? {
+            ZapLeafChunk::Entry {
+                hash,
+                collision_differentiator,
+                ..
+            } => Some((*hash, *collision_differentiator)),
+            _ => None,
+        }
+    }
+
+    fn find_entry(&self, name: &[u8], hash: u64) -> Option<u16> {
+        self.get_chain(hash)?.into_iter().find(|chunk_id| {
+            let ZapLeafChunk::Entry {
+                name_chunk_id,
+                name_length,
+                hash: entry_hash,
+                ..
+            } = &self.chunks[usize::from(*chunk_id)]
+            else {
+                return false;
+            };
+            *entry_hash == hash
+                && usize::from(*name_length) == name.len() + 1
+                && self
+                    .read_data_starting_at_chunk(usize::from(*name_chunk_id), name.len())
+                    .as_deref()
+                    == Some(name)
+        })
+    }
+
+    // `hash` is the hash of the name, see FatZapHeader::calculate_name_hash
+    // Returns: false if there is no entry with the name
+    pub fn remove_entry(&mut self, name: &str, hash: u64) -> bool {
+        let (Some(bucket_index), Some(chain), Some(chunk_id)) = (
+            self.get_hash_table_index(hash),
+            self.get_chain(hash),
+            self.find_entry(name.as_bytes(), hash),
+        ) else {
+            return false;
+        };
+        let ZapLeafChunk::Entry {
+            next_chunk_id,
+            name_chunk_id,
+            value_chunk_id,
+            ..
+        } = self.chunks[usize::from(chunk_id)]
+        else {
+            return false;
+        };
+        let position = chain.iter().position(|id| *id == chunk_id).unwrap();
+        let previous_chunk_id = position.checked_sub(1).map(|index| chain[index]);
+        self.set_chain_link(previous_chunk_id, bucket_index, next_chunk_id);
+        self.free_array(name_chunk_id);
+        self.free_array(value_chunk_id);
+        self.free_chunk(chunk_id);
+        self.header.nentries = self.header.nentries.saturating_sub(1);
+        true
+    }
+
+    // Writes the entry into the chunks of the leaf, replacing the one with the same name if there is one
+    // `hash` is the hash of the name, see FatZapHeader::calculate_name_hash
+    // Returns: Some(true) if the entry is new, Some(false) if an entry with the name was replaced
+    // None if the entry doesn't belong in this leaf or there aren't enough free chunks, then the leaf is left as it was
+    pub fn set_entry(&mut self, name: &str, hash: u64, value: &Value) -> Option<bool> {
+        if !self.has_prefix_of(hash) {
+            return None;
+        }
+        let mut new_leaf = self.clone();
+        let is_new = !new_leaf.remove_entry(name, hash);
+
+        let mut name_data = name.as_bytes().to_vec();
+        name_data.push(0);
+        let value_data = value.to_bytes_be();
+        let name_length = u16::try_from(name_data.len()).ok()?;
+        let nvalues = u16::try_from(value.get_nvalues()).ok()?;
+        let nchunks = 1
+            + name_data
+                .len()
+                .div_ceil(ZapLeafChunk::get_byte_array_size())
+            + value_data
+                .len()
+                .div_ceil(ZapLeafChunk::get_byte_array_size());
+        if nchunks > usize::from(new_leaf.header.nfree) {
+            return None;
+        }
+
+        // Entries with the same hash are told apart by the collision differentiator, the new one gets the lowest one that isn't used yet
+        let chain = new_leaf
+            .get_chain(hash)?
+            .into_iter()
+            .map(|chunk_id| Some((chunk_id, new_leaf.get_entry_hash_and_cd(chunk_id)?)))
+            .collect::<Option<Vec<_>>>()?;
+        let used_collision_differentiators = chain
+            .iter()
+            .filter(|(_, (entry_hash, _))| *entry_hash == hash)
+            .map(|(_, (_, collision_differentiator))| *collision_differentiator)
+            .collect::<HashSet<u16>>();
+        let collision_differentiator =
+            (0..u16::MAX).find(|cd| !used_collision_differentiators.contains(cd))?;
+        // The chain is kept sorted by the collision differentiator, like zfs does (ZLF_ENTRIES_CDSORTED)
+        let position = chain
+            .iter()
+            .position(|(_, (_, cd))| *cd > collision_differentiator)
+            .unwrap_or(chain.len());
+        let previous_chunk_id = position.checked_sub(1).map(|index| chain[index].0);
+        let next_chunk_id = chain
+            .get(position)
+            .map_or(CHAIN_END, |(chunk_id, _)| *chunk_id);
+
+        let chunk_id = new_leaf.allocate_chunk()?;
+        let name_chunk_id = new_leaf.write_array(&name_data)?;
+        let value_chunk_id = new_leaf.write_array(&value_data)?;
+        new_leaf.chunks[usize::from(chunk_id)] = ZapLeafChunk::Entry {
+            int_size: value.get_int_size() as u8,
+            next_chunk_id,
+            name_chunk_id,
+            name_length,
+            value_chunk_id,
+            nvalues,
+            collision_differentiator,
+            hash,
+        };
+        let bucket_index = new_leaf.get_hash_table_index(hash)?;
+        new_leaf.set_chain_link(previous_chunk_id, bucket_index, chunk_id);
+        new_leaf.header.nentries += 1;
+        *self = new_leaf;
+        Some(is_new)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ZapLeafHeader {
     next_leaf: u64,
     prefix: u64,
@@ -330,16 +830,15 @@ pub struct ZapLeafHeader {
     nentries: u16,
     prefix_len: u16,
     freelist: u16,
+    flags: u8,
 }
 
 pub const ZAP_LEAF_MAGIC: u32 = 0x2AB1EAF;
 
-impl<It> FromBytesLE<It> for ZapLeafHeader
-where
-    It: Iterator<Item = u8>,
-{
-    fn from_bytes_le(data: &mut It) -> Option<ZapLeafHeader> {
-        let zap_type = ZapType::from_value(u64::from_bytes_le(data)?)?;
+impl FromSliceLE for ZapLeafHeader {
+    fn from_slice_le(data: &[u8]) -> Option<ZapLeafHeader> {
+        let data = data.get(..Self::get_ondisk_size())?;
+        let zap_type = ZapType::from_value(read_le_at::<u64>(data, 0)?)?;
         use crate::ansi_color::*;
         if zap_type != ZapType::FatZapLeaf {
             println!(
@@ -348,33 +847,61 @@ where
             );
             return None;
         };
-        let next_leaf = u64::from_bytes_le(data)?;
-        let prefix = u64::from_bytes_le(data)?;
-        let magic = u32::from_bytes_le(data)?;
-        assert!(magic == ZAP_LEAF_MAGIC);
-        let nfree = u16::from_bytes_le(data)?;
-        let nentries = u16::from_bytes_le(data)?;
-        let prefix_len = u16::from_bytes_le(data)?;
-        let freelist = u16::from_bytes_le(data)?;
-        data.skip_n_bytes(12)?;
+        let magic = read_le_at::<u32>(data, 24)?;
+        if magic != ZAP_LEAF_MAGIC {
+            warnings::count_warning(WarningKind::CorruptMetadata);
+            if cfg!(feature = "debug") {
+                println!(
+                    "{YELLOW}Warning{WHITE}: Zap leaf has the wrong magic, sanity check failed!"
+                );
+            }
+            return None;
+        }
+        // The 11 bytes after the flags are padding
         Some(ZapLeafHeader {
-            next_leaf,
-            prefix,
-            nfree,
-            nentries,
-            prefix_len,
-            freelist,
+            next_leaf: read_le_at::<u64>(data, 8)?,
+            prefix: read_le_at::<u64>(data, 16)?,
+            nfree: read_le_at::<u16>(data, 28)?,
+            nentries: read_le_at::<u16>(data, 30)?,
+            prefix_len: read_le_at::<u16>(data, 32)?,
+            freelist: read_le_at::<u16>(data, 34)?,
+            flags: data[36],
         })
     }
 }
 
+impl<It> FromBytesLE<It> for ZapLeafHeader
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<ZapLeafHeader> {
+        let data = byte_iter::read_array::<{ ZapLeafHeader::get_ondisk_size() }>(data)?;
+        Self::from_slice_le(&data)
+    }
+}
+
 impl ZapLeafHeader {
     pub const fn get_ondisk_size() -> usize {
         48
     }
+
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(Self::get_ondisk_size());
+        res.extend((ZapType::FatZapLeaf as u64).to_le_bytes());
+        res.extend(self.next_leaf.to_le_bytes());
+        res.extend(self.prefix.to_le_bytes());
+        res.extend(ZAP_LEAF_MAGIC.to_le_bytes());
+        res.extend(self.nfree.to_le_bytes());
+        res.extend(self.nentries.to_le_bytes());
+        res.extend(self.prefix_len.to_le_bytes());
+        res.extend(self.freelist.to_le_bytes());
+        res.push(self.flags);
+        res.resize(Self::get_ondisk_size(), 0);
+        res
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ZapLeafChunk {
     Entry {
         int_size: u8,
@@ -395,54 +922,47 @@ pub enum ZapLeafChunk {
     },
 }
 
-impl<It> FromBytesLE<It> for ZapLeafChunk
-where
-    It: Iterator<Item = u8>,
-{
-    fn from_bytes_le(data: &mut It) -> Option<ZapLeafChunk> {
-        let chunk_type = ZapLeafChunkType::from_value(u8::from_bytes(data)?)?;
+impl FromSliceLE for ZapLeafChunk {
+    fn from_slice_le(data: &[u8]) -> Option<ZapLeafChunk> {
+        let data = data.get(..Self::get_ondisk_size())?;
+        // Array and free chunks have the next chunk id at the end
+        let next_chunk_id_offset = 1 + Self::get_byte_array_size();
+        let chunk_type = ZapLeafChunkType::from_value(data[0])?;
         match chunk_type {
             ZapLeafChunkType::Entry => {
-                let int_size = u8::from_bytes(data)?;
-                let next_chunk_id = u16::from_bytes_le(data)?;
-                let name_chunk_id = u16::from_bytes_le(data)?;
-                let name_length = u16::from_bytes_le(data)?;
-                let value_chunk_id = u16::from_bytes_le(data)?;
-                let nvalues = u16::from_bytes_le(data)?;
-                let collision_differentiator = u16::from_bytes_le(data)?;
-                data.skip_n_bytes(2)?; // padding
-                let hash = u64::from_bytes_le(data)?;
+                // The 2 bytes after the collision differentiator are padding
                 Some(ZapLeafChunk::Entry {
-                    int_size,
-                    next_chunk_id,
-                    name_chunk_id,
-                    name_length,
-                    value_chunk_id,
-                    nvalues,
-                    collision_differentiator,
-                    hash,
-                })
-            }
-            ZapLeafChunkType::Array => {
-                let mut array = vec![0u8; Self::get_byte_array_size()];
-                for byte in array.iter_mut() {
-                    *byte = u8::from_bytes(data)?;
-                }
-                let next_chunk_id = u16::from_bytes_le(data)?;
-                Some(ZapLeafChunk::Array {
-                    array,
-                    next_chunk_id,
+                    int_size: data[1],
+                    next_chunk_id: read_le_at::<u16>(data, 2)?,
+                    name_chunk_id: read_le_at::<u16>(data, 4)?,
+                    name_length: read_le_at::<u16>(data, 6)?,
+                    value_chunk_id: read_le_at::<u16>(data, 8)?,
+                    nvalues: read_le_at::<u16>(data, 10)?,
+                    collision_differentiator: read_le_at::<u16>(data, 12)?,
+                    hash: read_le_at::<u64>(data, 16)?,
                 })
             }
-            ZapLeafChunkType::Free => {
-                data.skip_n_bytes(Self::get_byte_array_size())?;
-                let next_chunk_id = u16::from_bytes_le(data)?;
-                Some(ZapLeafChunk::Free { next_chunk_id })
-            }
+            ZapLeafChunkType::Array => Some(ZapLeafChunk::Array {
+                array: data[1..next_chunk_id_offset].to_vec(),
+                next_chunk_id: read_le_at::<u16>(data, next_chunk_id_offset)?,
+            }),
+            ZapLeafChunkType::Free => Some(ZapLeafChunk::Free {
+                next_chunk_id: read_le_at::<u16>(data, next_chunk_id_offset)?,
+            }),
         }
     }
 }
 
+impl<It> FromBytesLE<It> for ZapLeafChunk
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<ZapLeafChunk> {
+        let data = byte_iter::read_array::<{ ZapLeafChunk::get_ondisk_size() }>(data)?;
+        Self::from_slice_le(&data)
+    }
+}
+
 impl ZapLeafChunk {
     pub const fn get_ondisk_size() -> usize {
         // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zap_leaf.h#L42
@@ -453,6 +973,48 @@ impl ZapLeafChunk {
         // https://github.com/openzfs/zfs/blob/master/include/sys/zap_leaf.h#L62
         Self::get_ondisk_size() - 3
     }
+
+    pub fn to_bytes_le(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(Self::get_ondisk_size());
+        match self {
+            ZapLeafChunk::Entry {
+                int_size,
+                next_chunk_id,
+                name_chunk_id,
+                name_length,
+                value_chunk_id,
+                nvalues,
+                collision_differentiator,
+                hash,
+            } => {
+                res.push(ZapLeafChunkType::Entry as u8);
+                res.push(*int_size);
+                res.extend(next_chunk_id.to_le_bytes());
+                res.extend(name_chunk_id.to_le_bytes());
+                res.extend(name_length.to_le_bytes());
+                res.extend(value_chunk_id.to_le_bytes());
+                res.extend(nvalues.to_le_bytes());
+                res.extend(collision_differentiator.to_le_bytes());
+                res.extend([0u8; 2]); // padding
+                res.extend(hash.to_le_bytes());
+            }
+            ZapLeafChunk::Array {
+                array,
+                next_chunk_id,
+            } => {
+                res.push(ZapLeafChunkType::Array as u8);
+                res.extend(array);
+                res.resize(1 + Self::get_byte_array_size(), 0);
+                res.extend(next_chunk_id.to_le_bytes());
+            }
+            ZapLeafChunk::Free { next_chunk_id } => {
+                res.push(ZapLeafChunkType::Free as u8);
+                res.resize(1 + Self::get_byte_array_size(), 0);
+                res.extend(next_chunk_id.to_le_bytes());
+            }
+        }
+        res
+    }
 }
 
 #[derive(Debug)]
@@ -464,18 +1026,25 @@ pub struct ZapPointerTable {
     blocks_copied: u64,
 }
 
+impl FromSliceLE for ZapPointerTable {
+    fn from_slice_le(data: &[u8]) -> Option<ZapPointerTable> {
+        Some(ZapPointerTable {
+            block_id: read_le_at::<u64>(data, 0)?,
+            num_blocks: read_le_at::<u64>(data, 8)?,
+            shift: read_le_at::<u64>(data, 16)?,
+            next_block: read_le_at::<u64>(data, 24)?,
+            blocks_copied: read_le_at::<u64>(data, 32)?,
+        })
+    }
+}
+
 impl<It> FromBytesLE<It> for ZapPointerTable
 where
     It: Iterator<Item = u8>,
 {
     fn from_bytes_le(data: &mut It) -> Option<ZapPointerTable> {
-        Some(ZapPointerTable {
-            block_id: u64::from_bytes_le(data)?,
-            num_blocks: u64::from_bytes_le(data)?,
-            shift: u64::from_bytes_le(data)?,
-            next_block: u64::from_bytes_le(data)?,
-            blocks_copied: u64::from_bytes_le(data)?,
-        })
+        let data = byte_iter::read_array::<{ ZapPointerTable::get_ondisk_size() }>(data)?;
+        Self::from_slice_le(&data)
     }
 }
 
@@ -491,58 +1060,199 @@ pub struct FatZapHeader {
     free_blocks: u64,
     num_leafs: u64,
     num_entries: u64,
+    salt: u64,
+    normalization_flags: u64,
+    flags: u64,
     table: ZapPointerTable,
     embbeded_leafs_pointer_table: Vec<u64>,
 }
 
 pub const FAT_ZAP_MAGIC: u64 = 0x2F52AB2AB;
 
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zap.h (zap_flags_t)
+const ZAP_FLAG_HASH64: u64 = 1 << 0;
+const ZAP_FLAG_UINT64_KEY: u64 = 1 << 1;
+const ZAP_FLAG_PRE_HASHED_KEY: u64 = 1 << 2;
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zio.h (ZFS_CRC64_POLY)
+const ZFS_CRC64_POLY: u64 = 0xC96C5795D7870F42;
+
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa_misc.c (spa_init, where zfs_crc64_table is filled in)
+const ZFS_CRC64_TABLE: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut ct = i as u64;
+        let mut j = 0;
+        while j < 8 {
+            ct = (ct >> 1) ^ ((ct & 1).wrapping_neg() & ZFS_CRC64_POLY);
+            j += 1;
+        }
+        table[i] = ct;
+        i += 1;
+    }
+    table
+};
+
+// Micro zaps have no flags, so their hashes always have the short length
+const MICRO_ZAP_HASH_BITS: u32 = 28;
+
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zap_micro.c (zap_hash)
+fn calculate_zap_hash(salt: u64, name: &[u8], hash_bits: u32) -> u64 {
+    let mut hash = salt;
+    for byte in name {
+        hash = (hash >> 8) ^ ZFS_CRC64_TABLE[((hash ^ u64::from(*byte)) & 0xFF) as usize];
+    }
+    // Only the top bits are used, the rest are left for the collision differentiator
+    hash & !((1u64 << (64 - hash_bits)) - 1)
+}
+
 impl FatZapHeader {
-    pub fn from_bytes_le(
-        data: &mut impl Iterator<Item = u8>,
-        block_size: usize,
-    ) -> Option<FatZapHeader> {
-        let zap_magic = u64::from_bytes_le(data)?;
+    // `data` is the whole first block of the zap, so it starts with the type of the block
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zap_impl.h (zap_phys_t)
+    pub fn from_slice_le(data: &[u8], block_size: usize) -> Option<FatZapHeader> {
+        let zap_magic = read_le_at::<u64>(data, 8)?;
         if zap_magic != FAT_ZAP_MAGIC {
             return None;
         }
 
-        let table = ZapPointerTable::from_bytes_le(data)?;
-        let free_blocks = u64::from_bytes_le(data)?;
-        let num_leafs = u64::from_bytes_le(data)?;
-        let num_entries = u64::from_bytes_le(data)?;
-        let _salt = u64::from_bytes_le(data)?;
-        data.skip_n_bytes(
-            block_size / 2 - (core::mem::size_of::<u64>() * 6 + ZapPointerTable::get_ondisk_size()),
-        )?;
-        let mut embbeded_leafs_pointer_table =
-            vec![0u64; block_size / 2 / core::mem::size_of::<u64>()];
-        for value in embbeded_leafs_pointer_table.iter_mut() {
-            *value = u64::from_bytes_le(data)?;
+        let table = ZapPointerTable::from_slice_le(data.get(16..)?)?;
+        let fields_offset = 16 + ZapPointerTable::get_ondisk_size();
+        let free_blocks = read_le_at::<u64>(data, fields_offset)?;
+        let num_leafs = read_le_at::<u64>(data, fields_offset + 8)?;
+        let num_entries = read_le_at::<u64>(data, fields_offset + 16)?;
+        let salt = read_le_at::<u64>(data, fields_offset + 24)?;
+        let normalization_flags = read_le_at::<u64>(data, fields_offset + 32)?;
+        let flags = read_le_at::<u64>(data, fields_offset + 40)?;
+        // The embedded pointer table is the second half of the block
+        if fields_offset + 48 > block_size / 2 {
+            return None;
         }
+        let table_size = block_size / 2 / core::mem::size_of::<u64>() * core::mem::size_of::<u64>();
+        let embbeded_leafs_pointer_table = data
+            .get(block_size / 2..block_size / 2 + table_size)?
+            .chunks_exact(core::mem::size_of::<u64>())
+            .map(u64::from_slice_le)
+            .collect::<Option<Vec<u64>>>()?;
 
         Some(FatZapHeader {
             free_blocks,
             num_leafs,
             num_entries,
+            salt,
+            normalization_flags,
+            flags,
             table,
             embbeded_leafs_pointer_table,
         })
     }
 
-    pub fn get_hash_table_size(&self) -> usize {
+    // Returns: The hash zfs would store in the entry with this name, or None if we can't calculate it for this zap
+    // NOTE: Names are hashed after normalization, which we don't do, so zaps that normalize names (ex. case insensitive directories) can't be checked
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zap_micro.c (zap_hash)
+    pub fn calculate_name_hash(&self, name: &[u8]) -> Option<u64> {
+        if self.normalization_flags != 0
+            || self.flags & (ZAP_FLAG_UINT64_KEY | ZAP_FLAG_PRE_HASHED_KEY) != 0
+            || self.salt == 0
+        {
+            return None;
+        }
+
+        // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zap_micro.c (zap_hashbits)
+        let hash_bits = if self.flags & ZAP_FLAG_HASH64 != 0 {
+            48
+        } else {
+            MICRO_ZAP_HASH_BITS
+        };
+        Some(calculate_zap_hash(self.salt, name, hash_bits))
+    }
+
+    // The inverse of from_slice_le, so it includes the type of the block
+    pub fn to_bytes_le(&self, block_size: usize) -> Vec<u8> {
+        let mut res = Vec::with_capacity(block_size);
+        res.extend((ZapType::FatZapHeader as u64).to_le_bytes());
+        res.extend(FAT_ZAP_MAGIC.to_le_bytes());
+        res.extend(self.table.block_id.to_le_bytes());
+        res.extend(self.table.num_blocks.to_le_bytes());
+        res.extend(self.table.shift.to_le_bytes());
+        res.extend(self.table.next_block.to_le_bytes());
+        res.extend(self.table.blocks_copied.to_le_bytes());
+        res.extend(self.free_blocks.to_le_bytes());
+        res.extend(self.num_leafs.to_le_bytes());
+        res.extend(self.num_entries.to_le_bytes());
+        res.extend(self.salt.to_le_bytes());
+        res.extend(self.normalization_flags.to_le_bytes());
+        res.extend(self.flags.to_le_bytes());
+        res.resize(block_size / 2, 0);
+        for value in self.embbeded_leafs_pointer_table.iter() {
+            res.extend(value.to_le_bytes());
+        }
+        res.resize(block_size, 0);
+        res
+    }
+
+    pub fn get_num_entries(&self) -> u64 {
+        self.num_entries
+    }
+
+    pub fn set_num_entries(&mut self, num_entries: u64) {
+        self.num_entries = num_entries;
+    }
+
+    // Returns: The block id of the leaf the entries with this hash are in
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zap_impl.h (ZAP_HASH_IDX)
+    pub fn get_leaf_block_id(&self, hash: u64) -> Option<u64> {
+        let index = if self.table.shift == 0 {
+            0
+        } else {
+            hash.checked_shr(u32::try_from(64u64.checked_sub(self.table.shift)?).ok()?)?
+        };
+        self.read_hash_table_at(usize::try_from(index).ok()?)
+    }
+
+    // Writes the entry into its leaf (the one get_leaf_block_id says), and counts it in the header if it's new
+    // Returns: Some(true) if the entry is new, None if it can't be written, see ZapLeaf::set_entry
+    // NOTE: Both the header and the leaf have to be written back
+    pub fn set_entry(&mut self, leaf: &mut ZapLeaf, name: &str, value: &Value) -> Option<bool> {
+        let hash = self.calculate_name_hash(name.as_bytes())?;
+        let is_new = leaf.set_entry(name, hash, value)?;
+        if is_new {
+            self.num_entries += 1;
+        }
+        Some(is_new)
+    }
+
+    // Returns: false if the leaf has no entry with the name, or the hash of the name can't be calculated
+    pub fn remove_entry(&mut self, leaf: &mut ZapLeaf, name: &str) -> bool {
+        let Some(hash) = self.calculate_name_hash(name.as_bytes()) else {
+            return false;
+        };
+        let is_removed = leaf.remove_entry(name, hash);
+        if is_removed {
+            self.num_entries = self.num_entries.saturating_sub(1);
+        }
+        is_removed
+    }
+
+    // TODO: Implement non-embedded fat zap tables
+    pub fn get_hash_table_size(&self) -> Option<usize> {
         if self.table.block_id == 0 {
-            return self.embbeded_leafs_pointer_table.len();
+            Some(self.embbeded_leafs_pointer_table.len())
         } else {
-            todo!("Implement non-embedded fat zap tables!");
+            use crate::ansi_color::*;
+            warnings::count_warning(WarningKind::UnsupportedFeature);
+            if cfg!(feature = "debug") {
+                println!("{YELLOW}Warning{WHITE}: Fat zap uses a non-embedded pointer table, this is not supported!");
+            }
+            None
         }
     }
 
-    pub fn read_hash_table_at(&self, index: usize) -> u64 {
+    pub fn read_hash_table_at(&self, index: usize) -> Option<u64> {
         if self.table.block_id == 0 {
-            return self.embbeded_leafs_pointer_table[index];
+            self.embbeded_leafs_pointer_table.get(index).copied()
         } else {
-            todo!("Implement non-embedded fat zap tables!");
+            None
         }
     }
 }
@@ -554,18 +1264,17 @@ pub enum ZapHeader {
 }
 
 impl ZapHeader {
-    pub fn from_bytes_le(
-        data: &mut impl Iterator<Item = u8>,
-        block_size: usize,
-    ) -> Option<ZapHeader> {
-        let zap_type = ZapType::from_value(u64::from_bytes_le(data)?)?;
+    pub fn from_slice_le(data: &[u8], block_size: usize) -> Option<ZapHeader> {
+        let zap_type = ZapType::from_value(read_le_at::<u64>(data, 0)?)?;
         return match zap_type {
             ZapType::FatZapHeader => {
-                FatZapHeader::from_bytes_le(data, block_size).map(ZapHeader::FatZap)
+                FatZapHeader::from_slice_le(data, block_size).map(ZapHeader::FatZap)
             }
 
             ZapType::MicroZap => {
-                data.skip_n_bytes(64 - core::mem::size_of::<u64>())?;
+                if data.len() < MICRO_ZAP_HEADER_SIZE {
+                    return None;
+                }
                 Some(Self::MicroZap)
             }
 
@@ -582,30 +1291,26 @@ impl ZapHeader {
         match self {
             ZapHeader::FatZap(header) => {
                 let mut leafs_read = HashSet::<u64>::new();
-                for i in 0..header.get_hash_table_size() {
-                    let block_id = header.read_hash_table_at(i);
+                for i in 0..header.get_hash_table_size()? {
+                    let block_id = header.read_hash_table_at(i)?;
                     if !leafs_read.insert(block_id) {
                         continue;
                     }
-                    let leaf = ZapLeaf::from_bytes_le(
-                        &mut parent_dnode
-                            .read_block(block_id as usize, vdevs)
-                            .ok()?
-                            .iter()
-                            .copied(),
+                    let leaf = ZapLeaf::from_slice_le(
+                        &parent_dnode.read_block(block_id as usize, vdevs).ok()?,
                         parent_dnode.parse_data_block_size(),
                     )?;
-                    leaf.dump_contents_into(&mut result)?;
+                    leaf.dump_contents_into(&mut result, header)?;
                 }
             }
             ZapHeader::MicroZap => {
-                let data = parent_dnode.read_block(0, vdevs).ok()?;
-                let mut data = data.iter().copied();
-                data.skip_n_bytes(64)?;
-                let nentries =
-                    (parent_dnode.parse_data_block_size() - 64) / MicroZapEntry::get_ondisk_size();
-                for _ in 0..nentries {
-                    let entry = MicroZapEntry::from_bytes_le(&mut data)?;
+                // Small micro zaps are usually in an embedded block pointer, so the block can be shorter than the data block size
+                let data = parent_dnode.read_block_allow_embedded_size(0, vdevs).ok()?;
+                for entry_data in data
+                    .get(MICRO_ZAP_HEADER_SIZE..)?
+                    .chunks_exact(MicroZapEntry::get_ondisk_size())
+                {
+                    let entry = MicroZapEntry::from_slice_le(entry_data)?;
                     // Ignore empty/broken entries
                     // NOTE: Empty entries (entries that are all zeroes) are normal, as far as i can tell
                     // TODO: Should we bail out on broken entries, which is what we do for fat zaps?
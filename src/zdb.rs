@@ -0,0 +1,382 @@
+// Formats structures the same way zdb does so the output of szfs can be diffed against the output of zdb
+// Sources:
+// https://github.com/openzfs/zfs/blob/master/cmd/zdb/zdb.c (dump_dnode, print_indirect, dump_zap)
+// https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L591 (SNPRINTF_BLKPTR)
+// https://github.com/openzfs/zfs/blob/master/lib/libzfs/libzfs_util.c (zfs_nicenum)
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::{
+    byte_iter::FromSliceLE,
+    dmu::{DNodeBase, ObjSet, ObjType, TypeMismatch},
+    zap,
+    zio::{BlockPointer, DataVirtualAddress, Vdevs},
+};
+
+// Formats a number the way zfs_nicenum does (ex. 512, 16K, 1.50M)
+pub fn nicenum(num: u64) -> String {
+    const UNITS: [char; 7] = [' ', 'K', 'M', 'G', 'T', 'P', 'E'];
+    let mut index = 0;
+    let mut n = num;
+    while n >= 1024 && index < UNITS.len() - 1 {
+        n /= 1024;
+        index += 1;
+    }
+
+    if index == 0 {
+        return format!("{}", num);
+    }
+
+    let unit = UNITS[index];
+    if num.is_multiple_of(1u64 << (10 * index)) {
+        return format!("{}{}", n, unit);
+    }
+
+    // Try the most precise representation that still fits in 5 characters
+    let value = num as f64 / (1u64 << (10 * index)) as f64;
+    for precision in (0..=2).rev() {
+        let res = format!("{:.*}{}", precision, value, unit);
+        if res.len() <= 5 {
+            return res;
+        }
+    }
+    format!("{:.0}{}", value, unit)
+}
+
+// zdb notation: vdev:offset:asize, offset and asize are in hex
+pub fn format_dva(dva: &DataVirtualAddress) -> String {
+    format!(
+        "{}:{:x}:{:x}",
+        dva.get_vdev_id(),
+        dva.parse_offset(),
+        dva.parse_allocated_size()
+    )
+}
+
+fn copies_name(ncopies: usize) -> &'static str {
+    match ncopies {
+        0 => "zero",
+        1 => "single",
+        2 => "double",
+        _ => "triple",
+    }
+}
+
+// Equivalent to SNPRINTF_BLKPTR, used by zdb -R, zdb -b and zdb -ddddd
+pub fn format_block_pointer(bp: &BlockPointer) -> String {
+    match bp {
+        BlockPointer::Normal(bp) => {
+            let mut res = String::new();
+            let dvas = bp.get_dvas();
+            for (index, dva) in dvas.iter().enumerate() {
+                if let Some(dva) = dva {
+                    let _ = write!(res, "DVA[{}]=<{}> ", index, format_dva(dva));
+                }
+            }
+            let ncopies = dvas.iter().filter(|dva| dva.is_some()).count();
+            let is_gang = dvas.iter().flatten().any(|dva| dva.is_gang());
+            let checksum = bp.get_checksum();
+            let _ = write!(
+                res,
+                "[L{} {}] {} {} unencrypted LE {} unique {} size={:x}L/{:x}P birth={}L/{}P fill={} cksum={:016x}:{:016x}:{:016x}:{:016x}",
+                bp.get_level(),
+                bp.get_type().get_name(),
+                bp.get_checksum_method().get_name(),
+                bp.get_compression_method().get_name(),
+                if is_gang { "gang" } else { "contiguous" },
+                copies_name(ncopies),
+                bp.parse_logical_size(),
+                bp.parse_physical_size(),
+                bp.get_logical_birth_txg(),
+                bp.get_physical_birth_txg(),
+                bp.get_fill(),
+                checksum[0],
+                checksum[1],
+                checksum[2],
+                checksum[3]
+            );
+            res
+        }
+        BlockPointer::Embedded(bp) => {
+            format!(
+                "EMBEDDED [L{} {}] et={} {} size={:x}L/{:x}P birth={}L",
+                bp.get_level(),
+                bp.get_type().get_name(),
+                bp.get_embedded_data_type() as usize,
+                bp.get_compression_method().get_name(),
+                bp.parse_logical_size(),
+                bp.parse_physical_size(),
+                bp.get_logical_birth_txg()
+            )
+        }
+    }
+}
+
+// The compact version used when printing indirect blocks (zdb -ddddd)
+pub fn format_block_pointer_compact(bp: &BlockPointer) -> String {
+    match bp {
+        BlockPointer::Normal(bp) => {
+            let mut res = String::new();
+            for dva in bp.get_dvas().iter().flatten() {
+                let _ = write!(res, "{} ", format_dva(dva));
+            }
+            let _ = write!(
+                res,
+                "{:x}L/{:x}P F={} B={}/{}",
+                bp.parse_logical_size(),
+                bp.parse_physical_size(),
+                bp.get_fill(),
+                bp.get_logical_birth_txg(),
+                bp.get_physical_birth_txg()
+            );
+            res
+        }
+        BlockPointer::Embedded(bp) => {
+            format!(
+                "EMBEDDED et={} {:x}L/{:x}P B={}",
+                bp.get_embedded_data_type() as usize,
+                bp.parse_logical_size(),
+                bp.parse_physical_size(),
+                bp.get_logical_birth_txg()
+            )
+        }
+    }
+}
+
+pub fn format_dnode_table_header() -> String {
+    format!(
+        "{:>10}  {:>3}  {:>5}  {:>5}  {:>5}  {:>6}  {:>5}  {:>6}  {}",
+        "Object", "lvl", "iblk", "dblk", "dsize", "dnsize", "lsize", "%full", "type"
+    )
+}
+
+// One line in the table printed by zdb -dd
+pub fn format_dnode(object_id: u64, dnode: &mut DNodeBase, typ: ObjType) -> String {
+    let fill: u64 = dnode
+        .get_block_pointers()
+        .iter()
+        .map(|bp| match bp {
+            BlockPointer::Normal(bp) => bp.get_fill(),
+            BlockPointer::Embedded(_) => 1,
+        })
+        .sum();
+
+    // Source: https://github.com/openzfs/zfs/blob/master/cmd/zdb/zdb.c (dump_dnode, the fill percentage calculation)
+    let bps_per_indirect_block =
+        (dnode.parse_indirect_block_size() / BlockPointer::get_ondisk_size()) as f64;
+    let max_fill = dnode.get_block_pointers().len() as f64
+        * bps_per_indirect_block.powi(dnode.get_n_indirect_levels() as i32 - 1);
+    let percent_full = if max_fill > 0.0 {
+        100.0 * fill as f64 / max_fill
+    } else {
+        0.0
+    };
+
+    format!(
+        "{:>10}  {:>3}  {:>5}  {:>5}  {:>5}  {:>6}  {:>5}  {:>6.2}  {}",
+        object_id,
+        dnode.get_n_indirect_levels(),
+        nicenum(dnode.parse_indirect_block_size() as u64),
+        nicenum(dnode.parse_data_block_size() as u64),
+        nicenum(dnode.parse_total_allocated()),
+        nicenum(dnode.get_ondisk_size() as u64),
+        nicenum(dnode.get_data_size() as u64),
+        percent_full,
+        typ.get_name()
+    )
+}
+
+// NOTE: zdb asserts that every block pointer has the type of the object and the level it should have, we print what it should have been instead, so corrupted trees can still be dumped
+#[allow(clippy::too_many_arguments)]
+fn format_indirect_block_pointer(
+    res: &mut String,
+    bp: &mut BlockPointer,
+    first_block_id: u64,
+    n_indirect_levels: usize,
+    data_block_size: u64,
+    blocks_per_indirect_block: u64,
+    typ: ObjType,
+    expected_level: usize,
+    vdevs: &mut Vdevs,
+) {
+    let level = bp.get_level();
+    // The levels come from the disk, on a corrupted tree the offsets they give don't fit in a u64
+    let Some(offset) = first_block_id.checked_mul(data_block_size) else {
+        let _ = writeln!(res, "{:>16} <bad level>", "");
+        return;
+    };
+    // Higher levels are printed further to the left just like in zdb
+    let _ = writeln!(
+        res,
+        "{:>16x} {:indent$}L{}{:pad$} {}",
+        offset,
+        "",
+        level,
+        "",
+        format_block_pointer_compact(bp),
+        indent = n_indirect_levels.saturating_sub(1 + level),
+        pad = level
+    );
+    if let Some(mismatch) = TypeMismatch::check(bp, typ, expected_level) {
+        let _ = writeln!(
+            res,
+            "{:>16} <wrong block pointer: L{} {}, should be L{} {}>",
+            "",
+            mismatch.found_level,
+            mismatch.found_type.get_name(),
+            mismatch.expected_level,
+            mismatch.expected_type.get_name()
+        );
+    }
+
+    if level == 0 {
+        return;
+    }
+
+    let Ok(data) = bp.dereference(vdevs) else {
+        let _ = writeln!(res, "{:>16} <unreadable indirect block>", "");
+        return;
+    };
+
+    let Some(blocks_per_child) = blocks_per_indirect_block.checked_pow((level - 1) as u32) else {
+        let _ = writeln!(res, "{:>16} <bad level>", "");
+        return;
+    };
+    for (index, chunk) in data
+        .chunks_exact(BlockPointer::get_ondisk_size())
+        .enumerate()
+    {
+        // Holes are all zeroes and won't parse so they are skipped, zdb does the same
+        let Some(mut child) = BlockPointer::from_slice_le(chunk) else {
+            continue;
+        };
+        let Some(child_first_block_id) = (index as u64)
+            .checked_mul(blocks_per_child)
+            .and_then(|child_offset| first_block_id.checked_add(child_offset))
+        else {
+            let _ = writeln!(res, "{:>16} <bad level>", "");
+            return;
+        };
+
+        format_indirect_block_pointer(
+            res,
+            &mut child,
+            child_first_block_id,
+            n_indirect_levels,
+            data_block_size,
+            blocks_per_indirect_block,
+            typ,
+            expected_level.saturating_sub(1),
+            vdevs,
+        );
+    }
+}
+
+// The "Indirect blocks:" section printed by zdb -ddddd, `typ` is the type of the object the dnode is of
+pub fn format_indirect_blocks(dnode: &mut DNodeBase, typ: ObjType, vdevs: &mut Vdevs) -> String {
+    let mut res = String::from("Indirect blocks:\n");
+    let n_indirect_levels = dnode.get_n_indirect_levels();
+    let data_block_size = dnode.parse_data_block_size() as u64;
+    let blocks_per_indirect_block =
+        (dnode.parse_indirect_block_size() / BlockPointer::get_ondisk_size()) as u64;
+    let Some(blocks_per_top_level_bp) =
+        blocks_per_indirect_block.checked_pow(n_indirect_levels.saturating_sub(1) as u32)
+    else {
+        res.push_str("<bad level>\n");
+        return res;
+    };
+
+    let mut bps = dnode.get_block_pointers().clone();
+    for (index, bp) in bps.iter_mut().enumerate() {
+        let Some(first_block_id) = (index as u64).checked_mul(blocks_per_top_level_bp) else {
+            res.push_str("<bad level>\n");
+            break;
+        };
+        format_indirect_block_pointer(
+            &mut res,
+            bp,
+            first_block_id,
+            n_indirect_levels,
+            data_block_size,
+            blocks_per_indirect_block,
+            typ,
+            n_indirect_levels.saturating_sub(1),
+            vdevs,
+        );
+    }
+    res
+}
+
+fn format_zap_value(value: &zap::Value) -> String {
+    match value {
+        zap::Value::U64(v) => format!("{}", v),
+        zap::Value::U16(v) => format!("{}", v),
+        zap::Value::Byte(v) => format!("{}", v),
+        // zdb prints byte arrays as strings
+        zap::Value::ByteArray(v) => String::from_utf8_lossy(v)
+            .trim_end_matches('\0')
+            .to_string(),
+        zap::Value::U64Array(v) => v
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+        zap::Value::U16Array(v) => v
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+// Equivalent to the entry listing part of dump_zap
+// NOTE: zdb prints the entries in hash order, we sort them by name so the output is stable, so sort the zdb output before diffing
+pub fn format_zap(contents: &HashMap<String, zap::Value>) -> String {
+    let mut names = contents.keys().collect::<Vec<&String>>();
+    names.sort();
+    let mut res = String::new();
+    for name in names {
+        let _ = writeln!(res, "\t\t{} = {}", name, format_zap_value(&contents[name]));
+    }
+    res
+}
+
+// Dumps every allocated object in the object set, with the same layout as zdb -dd (or zdb -ddddd if with_indirect_blocks is set)
+pub fn format_objset(objset: &mut ObjSet, with_indirect_blocks: bool, vdevs: &mut Vdevs) -> String {
+    let mut res = String::new();
+    let _ = writeln!(res, "    {}", format_dnode_table_header());
+
+    let nslots = (objset.metadnode.get_data_size() / 512) as u64;
+    let mut object_id = 0;
+    while object_id < nslots {
+        let Ok(slot) = objset.metadnode.read(object_id * 512, 512, vdevs) else {
+            object_id += 1;
+            continue;
+        };
+
+        let nslots_used = DNodeBase::get_n_slots_from_bytes_le(slot.iter().copied()).unwrap_or(1);
+        let mut data = slot;
+        if nslots_used > 1 {
+            if let Ok(extra) =
+                objset
+                    .metadnode
+                    .read((object_id + 1) * 512, (nslots_used - 1) * 512, vdevs)
+            {
+                data.extend(extra);
+            }
+        }
+
+        if let Some((mut dnode, typ, _)) = DNodeBase::from_slice_le(&data) {
+            if typ != ObjType::None {
+                let _ = writeln!(res, "    {}", format_dnode(object_id, &mut dnode, typ));
+                if with_indirect_blocks {
+                    let _ = writeln!(res, "{}", format_indirect_blocks(&mut dnode, typ, vdevs));
+                }
+            }
+        }
+
+        object_id += nslots_used as u64;
+    }
+    res
+}
@@ -1,7 +1,14 @@
+// The ZIL (ZFS intent log), every objset has a chain of log blocks with the synchronous writes that weren't part of a txg yet
+// If the pool crashed (or was never imported again) before those txgs were synced, the log is the only place the data is
+// With a separate log device (slog) the log blocks are on that device, not on the normal vdevs
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zil.h
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zil.c (zil_parse, zil_read_log_block)
+
 use serde::{Deserialize, Serialize};
 
-use crate::byte_iter::{ByteIter, FromBytesLE};
-use crate::zio::BlockPointer;
+use crate::byte_iter::{self, read_le_at, FromBytesLE, FromSliceLE};
+use crate::fletcher;
+use crate::zio::{BlockPointer, ChecksumMethod, NormalBlockPointer, Vdevs};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ZilHeader {
@@ -9,18 +16,25 @@ pub struct ZilHeader {
     highest_replayed_seq_number: u64,
     log: BlockPointer,
 }
+impl FromSliceLE for ZilHeader {
+    fn from_slice_le(data: &[u8]) -> Option<ZilHeader> {
+        // The 6 words after the log block pointer are padding
+        let data = data.get(..Self::get_ondisk_size())?;
+        Some(ZilHeader {
+            claim_txg: read_le_at::<u64>(data, 0)?,
+            highest_replayed_seq_number: read_le_at::<u64>(data, 8)?,
+            log: BlockPointer::from_slice_le(&data[16..])?,
+        })
+    }
+}
+
 impl<It> FromBytesLE<It> for ZilHeader
 where
-    It: Iterator<Item = u8> + Clone,
+    It: Iterator<Item = u8>,
 {
     fn from_bytes_le(data: &mut It) -> Option<ZilHeader> {
-        let res = ZilHeader {
-            claim_txg: u64::from_bytes_le(data)?,
-            highest_replayed_seq_number: u64::from_bytes_le(data)?,
-            log: BlockPointer::from_bytes_le(data)?,
-        };
-        data.skip_n_bytes(core::mem::size_of::<u64>() * 6)?;
-        Some(res)
+        let data = byte_iter::read_array::<{ ZilHeader::get_ondisk_size() }>(data)?;
+        Self::from_slice_le(&data)
     }
 }
 
@@ -28,4 +42,237 @@ impl ZilHeader {
     pub const fn get_ondisk_size() -> usize {
         BlockPointer::get_ondisk_size() + 8 * core::mem::size_of::<u64>()
     }
+
+    // Returns: The txg in which the log was claimed after an import, 0 if it was never claimed
+    pub fn get_claim_txg(&self) -> u64 {
+        self.claim_txg
+    }
+
+    // Records up to and including this sequence number were already replayed
+    pub fn get_highest_replayed_seq_number(&self) -> u64 {
+        self.highest_replayed_seq_number
+    }
+
+    // Returns: The first log block of the chain
+    pub fn get_log_block_pointer(&self) -> &BlockPointer {
+        &self.log
+    }
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zio.h (ZEC_MAGIC)
+const EMBEDDED_CHECKSUM_MAGIC: u64 = 0x210da7ab10c7a11;
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zil.h (zil_chain_t, ZIL_MIN_BLKSZ)
+const ZIL_CHAIN_SIZE: usize = 184;
+const ZIL_CHAIN_NUSED_OFFSET: usize = 136;
+const ZIL_CHAIN_CHECKSUM_OFFSET: usize = 144;
+const ZIL_MIN_BLOCK_SIZE: usize = 4096;
+
+// Log blocks don't have a normal checksum, the checksum is in the block itself and the block pointer only has the verifier
+// (the objset's random guid and the sequence number of the block), so the end of the chain is where a block doesn't verify
+// Returns: The used part of the block (the records) and the block pointer of the next block in the chain
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zio_checksum.c (zio_checksum_error_impl, ZCHECKSUM_FLAG_EMBEDDED)
+fn verify_log_block<'a>(
+    data: &'a mut [u8],
+    bp: &NormalBlockPointer,
+) -> Option<(&'a [u8], Option<BlockPointer>)> {
+    // ZILOG2 blocks have the chain header at the start, older ZILOG blocks at the end
+    let (chain_offset, records) = match bp.get_checksum_method() {
+        ChecksumMethod::Zilog2 => (0, ZIL_CHAIN_SIZE..),
+        ChecksumMethod::Zilog => (data.len().checked_sub(ZIL_CHAIN_SIZE)?, 0..),
+        _ => return None,
+    };
+    let chain = data.get(chain_offset..chain_offset + ZIL_CHAIN_SIZE)?;
+    let next_bp = BlockPointer::from_slice_le(&chain[8..8 + BlockPointer::get_ondisk_size()]);
+    let nused = usize::try_from(u64::from_slice_le(&chain[ZIL_CHAIN_NUSED_OFFSET..])?).ok()?;
+    if u64::from_slice_le(&chain[ZIL_CHAIN_CHECKSUM_OFFSET..])? != EMBEDDED_CHECKSUM_MAGIC {
+        return None;
+    }
+
+    // The checksum is calculated with the verifier where the checksum goes
+    let checksum_offset = chain_offset + ZIL_CHAIN_CHECKSUM_OFFSET + 8;
+    let mut expected_checksum = [0u64; 4];
+    for (index, word) in expected_checksum.iter_mut().enumerate() {
+        *word = u64::from_slice_le(&data[checksum_offset + index * 8..])?;
+    }
+    for (index, word) in bp.get_checksum().iter().enumerate() {
+        data[checksum_offset + index * 8..checksum_offset + (index + 1) * 8]
+            .copy_from_slice(&word.to_le_bytes());
+    }
+    let checksum = match bp.get_checksum_method() {
+        ChecksumMethod::Zilog2 => {
+            let size = nused.next_multiple_of(ZIL_MIN_BLOCK_SIZE).min(data.len());
+            fletcher::do_fletcher4(&data[..size])
+        }
+        _ => fletcher::do_fletcher2(data),
+    };
+    if checksum != expected_checksum {
+        return None;
+    }
+
+    let records = match bp.get_checksum_method() {
+        ChecksumMethod::Zilog2 => data.get(records.start..nused)?,
+        _ => data.get(..nused.min(chain_offset))?,
+    };
+    Some((records, next_bp))
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zil.h (TX_WRITE, TX_WRITE2)
+const TX_WRITE: u64 = 9;
+const TX_WRITE2: u64 = 20;
+// Case insensitive lookups set the top bit
+const TX_TYPE_MASK: u64 = !(1 << 63);
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zil.h (lr_t)
+const RECORD_HEADER_SIZE: usize = 4 * core::mem::size_of::<u64>();
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zil.h (lr_write_t)
+const WRITE_RECORD_SIZE: usize = RECORD_HEADER_SIZE + 4 * core::mem::size_of::<u64>() + 128;
+
+#[derive(Debug)]
+pub enum WriteData {
+    // The data was small enough to be copied into the log record
+    Copied(Vec<u8>),
+    // The data was written to a block of its own (on the normal vdevs), the record only has its block pointer
+    // `block_offset` is where the write starts in that block
+    Indirect {
+        block_pointer: Option<BlockPointer>,
+        block_offset: u64,
+    },
+}
+
+#[derive(Debug)]
+pub struct WriteRecord {
+    pub object: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub data: WriteData,
+}
+
+impl WriteRecord {
+    // Returns: The data that was written, an indirect write needs to read its block
+    pub fn read_data(&self, vdevs: &mut Vdevs) -> Option<Vec<u8>> {
+        let length = usize::try_from(self.length).ok()?;
+        match &self.data {
+            WriteData::Copied(data) => Some(data.get(..length)?.to_vec()),
+            WriteData::Indirect {
+                block_pointer,
+                block_offset,
+            } => {
+                let block_data = block_pointer.clone()?.dereference(vdevs).ok()?;
+                let block_offset = usize::try_from(*block_offset).ok()?;
+                Some(
+                    block_data
+                        .get(block_offset..block_offset + length)?
+                        .to_vec(),
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LogRecord {
+    pub txtype: u64,
+    pub txg: u64,
+    pub seq: u64,
+    // Only writes are parsed, those are what has the data
+    pub write: Option<WriteRecord>,
+}
+
+fn parse_write_record(record: &[u8]) -> Option<WriteRecord> {
+    let mut data = record.get(RECORD_HEADER_SIZE..)?.iter().copied();
+    let object = u64::from_bytes_le(&mut data)?;
+    let offset = u64::from_bytes_le(&mut data)?;
+    let length = u64::from_bytes_le(&mut data)?;
+    let block_offset = u64::from_bytes_le(&mut data)?;
+    let block_pointer_data = record.get(WRITE_RECORD_SIZE - 128..WRITE_RECORD_SIZE)?;
+    let data = if record.len() > WRITE_RECORD_SIZE {
+        WriteData::Copied(record[WRITE_RECORD_SIZE..].to_vec())
+    } else {
+        WriteData::Indirect {
+            block_pointer: BlockPointer::from_slice_le(block_pointer_data),
+            block_offset,
+        }
+    };
+    Some(WriteRecord {
+        object,
+        offset,
+        length,
+        data,
+    })
+}
+
+fn parse_log_records(mut records: &[u8], result: &mut Vec<LogRecord>) {
+    while records.len() >= RECORD_HEADER_SIZE {
+        let mut header = records.iter().copied();
+        let (Some(txtype), Some(reclen), Some(txg), Some(seq)) = (
+            u64::from_bytes_le(&mut header),
+            u64::from_bytes_le(&mut header),
+            u64::from_bytes_le(&mut header),
+            u64::from_bytes_le(&mut header),
+        ) else {
+            return;
+        };
+        let Some(record) = usize::try_from(reclen)
+            .ok()
+            .filter(|reclen| *reclen >= RECORD_HEADER_SIZE)
+            .and_then(|reclen| records.get(..reclen))
+        else {
+            return;
+        };
+
+        let txtype = txtype & TX_TYPE_MASK;
+        result.push(LogRecord {
+            txtype,
+            txg,
+            seq,
+            write: if txtype == TX_WRITE || txtype == TX_WRITE2 {
+                parse_write_record(record)
+            } else {
+                None
+            },
+        });
+        records = &records[record.len()..];
+    }
+}
+
+// Follows the log chain of an objset to its end
+// Returns: The records in the order they were logged, the ones that were already replayed are left out
+// NOTE: If the log is on a separate log device, that device has to be in `vdevs` under its top level vdev id, or nothing will be found
+pub fn read_log_records(header: &ZilHeader, vdevs: &mut Vdevs) -> Vec<LogRecord> {
+    use crate::ansi_color::*;
+    let mut result = Vec::new();
+    let mut next_bp = Some(header.get_log_block_pointer().clone());
+    // A broken chain could point back to itself
+    let mut nblocks = 0;
+    while let Some(BlockPointer::Normal(bp)) = next_bp.take() {
+        nblocks += 1;
+        if nblocks > 1_000_000 {
+            break;
+        }
+
+        let psize = bp.parse_physical_size() as usize;
+        let Some(mut data) = bp
+            .get_dvas()
+            .iter()
+            .flatten()
+            .find_map(|dva| dva.dereference(vdevs, psize).ok())
+        else {
+            if cfg!(feature = "debug") {
+                println!(
+                    "{YELLOW}Warning{WHITE}: Couldn't read log block {bp:?}, the log ends here!"
+                );
+            }
+            break;
+        };
+
+        // The block after the last one was never written, so it won't verify, that's the normal end of the chain
+        let Some((records, next)) = verify_log_block(&mut data, &bp) else {
+            break;
+        };
+        parse_log_records(records, &mut result);
+        next_bp = next;
+    }
+
+    result.retain(|record| record.seq > header.get_highest_replayed_seq_number());
+    result
 }
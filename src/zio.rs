@@ -1,9 +1,20 @@
+#[cfg(feature = "disk")]
+use crate::yolo_block_recovery;
 use crate::{
-    byte_iter::{ByteIter, FromBytes, FromBytesLE},
-    dmu, fletcher, lz4, lzjb, yolo_block_recovery, Vdev,
+    byte_iter::{self, read_le_at, ByteIter, FromBytesLE, FromSliceLE},
+    damage_map, device_map, dmu, fletcher, known_blocks, l2arc, lz4, lzjb,
+    warnings::{self, WarningKind},
+    zdb, zle, Vdev,
 };
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt::Debug};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    io::Read,
+    sync::RwLock,
+};
 
 const GANGBLOCK_MAGIC: u64 = 0x210da7ab10c7a11;
 
@@ -50,12 +61,47 @@ impl GangBlock {
         // And: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h#L1802
         512
     }
+
+    // The checksum of a gang header is a sha256 of the header with this in place of the checksum, so a header can't be mistaken for the one of another block
+    // NOTE: It always uses the first dva of the block pointer, even when the header is read through one of the other copies
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zio_checksum.c (zio_checksum_gang_verifier)
+    pub fn get_verifier(first_dva: &DataVirtualAddress, birth_txg: u64) -> [u64; 4] {
+        [
+            u64::from(first_dva.vdev_id),
+            first_dva.parse_offset(),
+            birth_txg,
+            0,
+        ]
+    }
+
+    // Returns: The checksum zfs stores in the gang header in data, None if data isn't the size of a gang header
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zio_checksum.c (zio_checksum_compute, the ZCHECKSUM_FLAG_EMBEDDED case)
+    // And: https://github.com/openzfs/zfs/blob/master/module/zfs/sha2_zfs.c (the digest is stored as big endian words)
+    pub fn compute_checksum(data: &[u8], verifier: &[u64; 4]) -> Option<[u64; 4]> {
+        use sha2::{Digest, Sha256};
+        if data.len() != Self::get_ondisk_size() {
+            return None;
+        }
+        let checksum_offset = Self::get_ondisk_size() - 4 * core::mem::size_of::<u64>();
+        let mut hasher = Sha256::new();
+        hasher.update(&data[..checksum_offset]);
+        for word in verifier {
+            hasher.update(word.to_le_bytes());
+        }
+        let digest = hasher.finalize();
+        let mut checksum = [0u64; 4];
+        for (word, bytes) in checksum.iter_mut().zip(digest.chunks_exact(8)) {
+            *word = u64::from_be_bytes(bytes.try_into().unwrap());
+        }
+        Some(checksum)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct DataVirtualAddress {
     vdev_id: u32,
-    data_allocated_size_minus_one_in_512b_sectors: u32, // technically a u24
+    #[serde(alias = "data_allocated_size_minus_one_in_512b_sectors")]
+    allocated_size_in_512b_sectors: u32, // technically a u24
     offset_in_512b_sectors: u64, // offset is after the labels and the boot block
     is_gang: bool,
 }
@@ -73,14 +119,14 @@ impl Debug for DataVirtualAddress {
     }
 }
 
-impl<It> FromBytesLE<It> for DataVirtualAddress
-where
-    It: Iterator<Item = u8>,
-{
-    fn from_bytes_le(data: &mut It) -> Option<Self> {
-        let vdev_id = ((u32::from_bytes_le(data)?) & 0xFF_FF_FF_00) >> 8; // ignore padding ( https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L129 )
-        let grid_and_asize = u32::from_bytes_le(data)?;
-        let offset_and_gang_bit = u64::from_bytes_le(data)?;
+impl FromSliceLE for DataVirtualAddress {
+    fn from_slice_le(data: &[u8]) -> Option<Self> {
+        // The first word is vdev (32 bits) | grid (8 bits) | asize (24 bits), from the most to the least significant bits
+        // so in little endian the asize comes first
+        // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (DVA_GET_ASIZE, DVA_GET_GRID, DVA_GET_VDEV)
+        let grid_and_asize = read_le_at::<u32>(data, 0)?;
+        let vdev_id = read_le_at::<u32>(data, 4)?;
+        let offset_and_gang_bit = read_le_at::<u64>(data, 8)?;
 
         // A non-existent dva is marked by all zeroes
         if vdev_id == 0 && grid_and_asize == 0 && offset_and_gang_bit == 0 {
@@ -89,22 +135,46 @@ where
 
         Some(DataVirtualAddress {
             vdev_id,
-            data_allocated_size_minus_one_in_512b_sectors: (grid_and_asize & 0xFF_FF_FF_00) >> 8, // ignore GRID as it is reserved
+            allocated_size_in_512b_sectors: grid_and_asize & 0x00_FF_FF_FF, // ignore GRID as it is reserved
             offset_in_512b_sectors: offset_and_gang_bit & ((1 << 63) - 1), // bit 64 is the gang bit
             is_gang: offset_and_gang_bit & (1 << 63) != 0,
         })
     }
 }
 
+// NOTE: The whole dva is always consumed, even if it's empty
+impl<It> FromBytesLE<It> for DataVirtualAddress
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<Self> {
+        let data = byte_iter::read_array::<{ DataVirtualAddress::get_ondisk_size() }>(data)?;
+        Self::from_slice_le(&data)
+    }
+}
+
 impl DataVirtualAddress {
     pub const fn get_ondisk_size() -> usize {
         core::mem::size_of::<u64>() * 2
     }
 
+    // The inverse of from_bytes_le
+    // NOTE: The grid is reserved so it's always written as 0
+    pub fn to_bytes_le(&self) -> [u8; Self::get_ondisk_size()] {
+        let mut res = [0u8; Self::get_ondisk_size()];
+        res[0..4]
+            .copy_from_slice(&(self.allocated_size_in_512b_sectors & 0x00_FF_FF_FF).to_le_bytes());
+        res[4..8].copy_from_slice(&self.vdev_id.to_le_bytes());
+        let offset_and_gang_bit = (self.offset_in_512b_sectors & ((1 << 63) - 1))
+            | if self.is_gang { 1 << 63 } else { 0 };
+        res[8..16].copy_from_slice(&offset_and_gang_bit.to_le_bytes());
+        res
+    }
+
     pub fn from(vdev_id: u32, offset_in_bytes: u64, is_gang: bool) -> DataVirtualAddress {
         DataVirtualAddress {
             vdev_id,
-            data_allocated_size_minus_one_in_512b_sectors: 0, /* unused */
+            allocated_size_in_512b_sectors: 0, /* unused */
             offset_in_512b_sectors: offset_in_bytes / 512,
             is_gang,
         }
@@ -112,99 +182,277 @@ impl DataVirtualAddress {
 
     // Returns: allocated size in bytes
     pub fn parse_allocated_size(&self) -> u64 {
-        // All sizes are stored as the number of 512 byte sectors (minus one) needed to represent the size of this block. ( http://www.giis.co.in/Zfs_ondiskformat.pdf ( section 2.6 ) )
-        (self.data_allocated_size_minus_one_in_512b_sectors as u64 + 1) * 512
+        // Unlike the sizes in the block pointer, the asize is *not* stored minus one, it's in 512 byte sectors no matter what the ashift is
+        // it includes the parity and the padding up to a whole number of sectors of the vdev
+        // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (DVA_GET_ASIZE, the bias is 0)
+        u64::from(self.allocated_size_in_512b_sectors) * 512
     }
 
     // Returns: offset in bytes from beginning of vdev
     pub fn parse_offset(&self) -> u64 {
-        self.offset_in_512b_sectors * 512
+        // The offset is 63 bits, so multiplying could overflow on corrupt dvas, shifting drops the top bits like zfs does
+        // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (DVA_GET_OFFSET)
+        self.offset_in_512b_sectors << 9
+    }
+
+    pub fn get_vdev_id(&self) -> u32 {
+        self.vdev_id
+    }
+
+    pub fn is_gang(&self) -> bool {
+        self.is_gang
     }
 
     pub fn dereference(&self, vdevs: &mut Vdevs, size: usize) -> Result<Vec<u8>, ()> {
-        let data = self.dereference_raw(vdevs, size)?;
+        self.dereference_with_gang_verifier(vdevs, size, None)
+    }
 
+    // Same as dereference, but if this is a gang dva its header is checked against the verifier of the block pointer the dva is from (see NormalBlockPointer::get_gang_verifier)
+    // Without a verifier only the magic of the gang headers can be checked
+    pub fn dereference_with_gang_verifier(
+        &self,
+        vdevs: &mut Vdevs,
+        size: usize,
+        gang_verifier: Option<&[u64; 4]>,
+    ) -> Result<Vec<u8>, ()> {
         if self.is_gang {
-            use crate::ansi_color::*;
-            println!("{YELLOW}Warning{WHITE}: Trying to dereference GANG DVA {self:?}, this code was untested when it was written, so i don't know if it will actually work on real data!");
+            self.dereference_gang(vdevs, size, gang_verifier)
+        } else {
+            self.dereference_raw(vdevs, size)
+        }
+    }
 
-            let computed_checksum =
-                try_checksum_block(&data, ChecksumMethod::GangHeader).ok_or(())?;
+    // Reads and verifies the gang header pointed to by this dva
+    fn read_gang_header(
+        &self,
+        vdevs: &mut Vdevs,
+        verifier: Option<&[u64; 4]>,
+    ) -> Result<GangBlock, ()> {
+        let data = self.dereference_raw(vdevs, GangBlock::get_ondisk_size())?;
 
-            let gang_block = GangBlock::from_bytes_le(&mut data.into_iter()).ok_or(())?;
+        let gang_block = GangBlock::from_bytes_le(&mut data.iter().copied()).ok_or(())?;
+        if gang_block.magic != GANGBLOCK_MAGIC {
+            return Err(());
+        }
 
-            // First check the gang_block's checksum
-            if computed_checksum != gang_block.checksum {
+        // Gang headers are never unverifiable when we know the verifier, we always know how to checksum them
+        if let Some(verifier) = verifier {
+            if GangBlock::compute_checksum(&data, verifier) != Some(gang_block.checksum) {
                 return Err(());
             }
+        }
 
-            // Now theoretically we just dereference each block pointer sequentially
-            // and concatenate the results right?
-            let mut gang_data = Vec::<u8>::with_capacity(size);
-            for bp in gang_block.bps {
-                // NOTE: On any normal gang header
-                // if the checksum passes then the following code shouldn't be a problem
-                // BUT you could craft a valid gang header with a block pointer to itself
-                // which would cause infinite recursion
-                if let Some(Ok(data)) = bp.map(|mut bp| bp.dereference(vdevs)) {
-                    gang_data.extend(data);
-                } else {
-                    // We break when we hit the first unparsable block pointer of the gang
-                    // In theory assuming no corruption
-                    // which should not be possible because we checked the checksum of the gang
-                    // this should only happen when we have hit the last block pointer
-                    // in the gang, so it should be ok
-                    break;
-                }
+        Ok(gang_block)
+    }
+
+    // A gang header has up to 3 block pointers, the data is just the concatenation of the data they point to
+    // But those block pointers can also be gang blocks (gang-of-gang), so instead of recursing through BlockPointer::dereference
+    // we walk the gang tree with an explicit stack, so a crafted gang header that points to itself or a very deep chain
+    // can't blow up the stack
+    // NOTE: zfs never actually needs many levels, every level splits the data in at most 3, and the smallest piece is a sector
+    // so even a 16M block will not need more than ~10 levels
+    fn dereference_gang(
+        &self,
+        vdevs: &mut Vdevs,
+        size: usize,
+        verifier: Option<&[u64; 4]>,
+    ) -> Result<Vec<u8>, ()> {
+        use crate::ansi_color::*;
+        const MAX_GANG_DEPTH: usize = 16;
+
+        let mut visited_headers = HashSet::<(u32, u64)>::new();
+        visited_headers.insert((self.vdev_id, self.offset_in_512b_sectors));
+
+        // Each entry is a gang header and the index of the next block pointer in it that we need to read
+        let mut stack = vec![(self.read_gang_header(vdevs, verifier)?, 0usize)];
+        let mut gang_data = Vec::<u8>::with_capacity(size);
+
+        while let Some((gang_block, next_bp_index)) = stack.last_mut() {
+            if gang_data.len() >= size {
+                break;
             }
 
-            if gang_data.len() > size {
-                gang_data.resize(size, 0);
+            // We stop going through a header when we hit the first unparsable block pointer
+            // In theory assuming no corruption
+            // which should not be possible because we checked the checksum of the gang header
+            // this should only happen when we have hit the last block pointer in the gang
+            let Some(Some(mut bp)) = gang_block.bps.get(*next_bp_index).cloned() else {
+                stack.pop();
+                continue;
+            };
+            *next_bp_index += 1;
+
+            let (gang_dvas, inner_verifier) = match &bp {
+                BlockPointer::Normal(bp) => (
+                    bp.get_dvas()
+                        .iter()
+                        .flatten()
+                        .filter(|dva| dva.is_gang())
+                        .cloned()
+                        .collect::<Vec<_>>(),
+                    bp.get_gang_verifier(),
+                ),
+                BlockPointer::Embedded(_) => (Vec::new(), None),
+            };
+
+            if gang_dvas.is_empty() {
+                // A normal block pointer, this is a leaf of the gang tree
+                gang_data.extend(bp.dereference(vdevs)?);
+                continue;
             }
 
-            if gang_data.len() != size {
+            // Gang-of-gang, descend into the first readable gang header
+            // NOTE: The checksum in the block pointer of an inner gang covers the stitched together data of that gang
+            // we don't verify it here, but the checksum of the top level block pointer covers all of the data anyways
+            if stack.len() >= MAX_GANG_DEPTH {
+                warnings::count_warning(WarningKind::CorruptMetadata);
+                if cfg!(feature = "debug") {
+                    println!("{YELLOW}Warning{WHITE}: Gang block chain starting at {self} is more than {MAX_GANG_DEPTH} levels deep, refusing to follow it!");
+                }
                 return Err(());
             }
 
-            Ok(gang_data)
+            let mut inner_gang_block = None;
+            for dva in gang_dvas {
+                if !visited_headers.insert((dva.vdev_id, dva.offset_in_512b_sectors)) {
+                    warnings::count_warning(WarningKind::CorruptMetadata);
+                    if cfg!(feature = "debug") {
+                        println!("{YELLOW}Warning{WHITE}: Gang block {dva} was already visited, the gang tree starting at {self} has a loop, ignoring this dva!");
+                    }
+                    continue;
+                }
+
+                if let Ok(gang_block) = dva.read_gang_header(vdevs, inner_verifier.as_ref()) {
+                    inner_gang_block = Some(gang_block);
+                    break;
+                }
+            }
+
+            stack.push((inner_gang_block.ok_or(())?, 0));
+        }
+
+        if gang_data.len() > size {
+            gang_data.resize(size, 0);
+        }
+
+        if gang_data.len() != size {
+            return Err(());
+        }
+
+        Ok(gang_data)
+    }
+
+    // Every dva is read from the vdev with its id, if it was added to the vdevs (ex. log devices, or the other top level vdevs of a pool with more than one)
+    // NOTE: The copies of a block (copies=2 or 3) can be on different top level vdevs, so one of them might be readable when the others aren't
+    pub fn get_routing(&self, vdevs: &Vdevs) -> VdevRouting {
+        if vdevs.contains_key(&(self.vdev_id as usize)) {
+            VdevRouting::Direct(self.vdev_id as usize)
+        } else if get_top_level_vdev_count().is_some_and(|count| u64::from(self.vdev_id) < count) {
+            VdevRouting::Unreachable
         } else {
-            Ok(data)
+            VdevRouting::Corrected
         }
     }
 
     // Dereference the actual block
     // So if this is a gang block this will return the gang header
-    pub fn dereference_raw(&self, vdevs: &mut Vdevs, size: usize) -> Result<Vec<u8>, ()> {
-        if cfg!(feature = "verbose_debug") {
-            if self.vdev_id != 0 {
-                use crate::ansi_color::*;
-                println!(
-                    "{YELLOW}Warning{WHITE}: DVA has invalid vdev id {}, automatically correcting!",
-                    self.vdev_id
-                );
+    // Returns: None if the vdev of the dva wasn't added to the vdevs, see get_routing
+    fn get_vdevs_key(&self, vdevs: &Vdevs) -> Option<usize> {
+        match self.get_routing(vdevs) {
+            VdevRouting::Direct(vdev_id) => Some(vdev_id),
+            VdevRouting::Corrected => {
+                if cfg!(feature = "verbose_debug") {
+                    use crate::ansi_color::*;
+                    println!(
+                        "{YELLOW}Warning{WHITE}: DVA has invalid vdev id {}, automatically correcting!",
+                        self.vdev_id
+                    );
+                }
+                Some(0)
             }
+            VdevRouting::Unreachable => None,
         }
+    }
 
-        // TODO: Figure out why some DVAs don't have vdev 0 even though they should
-        let Some(vdev) = vdevs.get_mut(&0) else { return Err(()); };
+    // Same as dereference, but if the data doesn't have to be put together (no gang blocks, and for raidz all of the data is in one column)
+    // and the vdev has it in memory (ex. a memory mapped file) it's returned without copying it
+    pub fn dereference_borrowed<'v>(
+        &self,
+        vdevs: &'v mut Vdevs,
+        size: usize,
+    ) -> Result<Cow<'v, [u8]>, ()> {
+        if self.is_gang {
+            return self.dereference(vdevs, size).map(Cow::Owned);
+        }
+        let vdev_id = self.get_vdevs_key(vdevs).ok_or(())?;
+        let vdev = vdevs.get(&vdev_id).ok_or(())?;
+        let Some(raidz_info) = vdev.get_raidz_info() else {
+            return vdevs
+                .get_mut(&vdev_id)
+                .ok_or(())?
+                .read_borrowed(self.parse_offset(), size);
+        };
 
-        if let Some(raidz_info) = vdev.get_raidz_info() {
-            let number_of_data_sectors = if size % vdev.get_asize() == 0 {
-                size / vdev.get_asize()
-            } else {
-                (size / vdev.get_asize()) + 1
-            };
+        // A block that fits in one sector, or is on a raidz with only one data disk, only has one data column
+        // and a column is one contiguous range on one disk, anything bigger is spread over the disks and has to be put together
+        let asize = vdev.get_asize();
+        let ndata_columns = raidz_info.ndevices - raidz_info.nparity;
+        if !self.parse_offset().is_multiple_of(asize as u64)
+            || (size.div_ceil(asize) > 1 && ndata_columns > 1)
+        {
+            return self.dereference_raw(vdevs, size).map(Cow::Owned);
+        }
 
-            let number_of_stripes =
-                if number_of_data_sectors % (raidz_info.ndevices - raidz_info.nparity) == 0 {
-                    number_of_data_sectors / (raidz_info.ndevices - raidz_info.nparity)
-                } else {
-                    number_of_data_sectors / (raidz_info.ndevices - raidz_info.nparity) + 1
-                };
-            let number_of_parity_sectors = number_of_stripes * raidz_info.nparity;
+        // The same raidz1 quirk as in dereference_raw, on odd megabyte offsets the parity and the first data column switch places
+        let data_column = if raidz_info.nparity == 1
+            && !(self.parse_offset() / (1024 * 1024)).is_multiple_of(2)
+        {
+            0
+        } else {
+            raidz_info.nparity
+        };
+        vdevs
+            .get_mut(&vdev_id)
+            .ok_or(())?
+            .read_raidz_column_borrowed(
+                self.parse_offset() / asize as u64 + data_column as u64,
+                size,
+            )
+    }
+
+    // Returns: How many bytes dereference_raw reads from the vdev to get `size` bytes of data, for raidz that includes a parity sector for every row of data sectors
+    fn get_raw_size(vdev: &dyn Vdev, size: usize) -> usize {
+        let Some(raidz_info) = vdev.get_raidz_info() else {
+            return size;
+        };
+        let number_of_data_sectors = size.div_ceil(vdev.get_asize());
+        let number_of_stripes =
+            number_of_data_sectors.div_ceil(raidz_info.ndevices - raidz_info.nparity);
+        let number_of_parity_sectors = number_of_stripes * raidz_info.nparity;
+        (number_of_data_sectors + number_of_parity_sectors) * vdev.get_asize()
+    }
+
+    // Returns: The key of the vdev and the (offset, amount) that dereference would read from it
+    // None for gang blocks, where the pieces are only known once the gang header is read
+    pub fn get_raw_range(&self, vdevs: &Vdevs, size: usize) -> Option<(usize, (u64, usize))> {
+        if self.is_gang {
+            return None;
+        }
+        let vdev_id = self.get_vdevs_key(vdevs)?;
+        let vdev = vdevs.get(&vdev_id)?;
+        Some((
+            vdev_id,
+            (self.parse_offset(), Self::get_raw_size(&**vdev, size)),
+        ))
+    }
+
+    pub fn dereference_raw(&self, vdevs: &mut Vdevs, size: usize) -> Result<Vec<u8>, ()> {
+        let vdev_id = self.get_vdevs_key(vdevs).ok_or(())?;
+        let Some(vdev) = vdevs.get_mut(&vdev_id) else { return Err(()); };
 
-            let size_with_parity =
-                (number_of_data_sectors + number_of_parity_sectors) * vdev.get_asize();
+        if let Some(raidz_info) = vdev.get_raidz_info() {
+            let number_of_data_sectors = size.div_ceil(vdev.get_asize());
+            let size_with_parity = Self::get_raw_size(&**vdev, size);
 
             let res = vdev.read(self.parse_offset(), size_with_parity)?;
 
@@ -250,7 +498,41 @@ impl DataVirtualAddress {
 
 pub type Vdevs<'a> = HashMap<usize, &'a mut dyn Vdev>;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, Hash)]
+// Tells the vdevs that these blocks are going to be read soon, so they can read all of them at once instead of seeking back and forth for every one
+// Only the first copy of a block is prefetched, the others are only read if it's damaged
+// NOTE: Blocks that are in the block cache are skipped, their data won't be read from the vdevs anyway
+pub fn prefetch(block_pointers: &[BlockPointer], vdevs: &mut Vdevs) {
+    let mut ranges = HashMap::<usize, Vec<(u64, usize)>>::new();
+    for block_pointer in block_pointers {
+        let BlockPointer::Normal(block_pointer) = block_pointer else {
+            continue;
+        };
+        let cache_key = (block_pointer.checksum, block_pointer.checksum_method);
+        if vdevs
+            .get_mut(&0)
+            .is_some_and(|vdev| vdev.get_from_block_cache(&cache_key).is_some())
+        {
+            continue;
+        }
+        let Some(dva) = block_pointer.dvas.iter().flatten().next() else {
+            continue;
+        };
+        let Ok(psize) = usize::try_from(block_pointer.parse_physical_size()) else {
+            continue;
+        };
+        if let Some((vdev_id, range)) = dva.get_raw_range(vdevs, psize) {
+            ranges.entry(vdev_id).or_default().push(range);
+        }
+    }
+
+    for (vdev_id, ranges) in ranges {
+        if let Some(vdev) = vdevs.get_mut(&vdev_id) {
+            vdev.prefetch(&ranges);
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum ChecksumMethod {
     Inherit = 0,
     On = 1, // equivalent to fletcher4 ( https://github.com/openzfs/zfs/blob/master/include/sys/zio.h#L122 )
@@ -290,9 +572,61 @@ impl ChecksumMethod {
             _ => return None,
         })
     }
+
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zio_checksum.c#L165 (zio_checksum_table)
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ChecksumMethod::Inherit => "inherit",
+            ChecksumMethod::On => "on",
+            ChecksumMethod::Off => "off",
+            ChecksumMethod::Label => "label",
+            ChecksumMethod::GangHeader => "gang_header",
+            ChecksumMethod::Zilog => "zilog",
+            ChecksumMethod::Fletcher2 => "fletcher2",
+            ChecksumMethod::Fletcher4 => "fletcher4",
+            ChecksumMethod::Sha256 => "sha256",
+            ChecksumMethod::Zilog2 => "zilog2",
+            ChecksumMethod::NoParity => "noparity",
+            ChecksumMethod::Sha512 => "sha512",
+            ChecksumMethod::Skein => "skein",
+            ChecksumMethod::Edonr => "edonr",
+            ChecksumMethod::Blake3 => "blake3",
+        }
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+impl TryFrom<u8> for ChecksumMethod {
+    type Error = ();
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        ChecksumMethod::from_value(value.into()).ok_or(())
+    }
+}
+
+impl From<ChecksumMethod> for u8 {
+    fn from(method: ChecksumMethod) -> u8 {
+        method as u8
+    }
+}
+
+impl std::fmt::Display for ChecksumMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.get_name())
+    }
+}
+
+impl Serialize for ChecksumMethod {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8((*self).into())
+    }
+}
+
+impl<'de> Deserialize<'de> for ChecksumMethod {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_method(deserializer)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum CompressionMethod {
     Inherit = 0,
     On = 1, // Equivalent to lz4 (https://github.com/openzfs/zfs/blob/master/include/sys/zio.h#L122)
@@ -336,9 +670,130 @@ impl CompressionMethod {
             _ => return None,
         })
     }
+
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zio_compress.c#L52 (zio_compress_table)
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            CompressionMethod::Inherit => "inherit",
+            CompressionMethod::On => "on",
+            CompressionMethod::Off => "uncompressed",
+            CompressionMethod::Lzjb => "lzjb",
+            CompressionMethod::Empty => "empty",
+            CompressionMethod::Gzip1 => "gzip-1",
+            CompressionMethod::Gzip2 => "gzip-2",
+            CompressionMethod::Gzip3 => "gzip-3",
+            CompressionMethod::Gzip4 => "gzip-4",
+            CompressionMethod::Gzip5 => "gzip-5",
+            CompressionMethod::Gzip6 => "gzip-6",
+            CompressionMethod::Gzip7 => "gzip-7",
+            CompressionMethod::Gzip8 => "gzip-8",
+            CompressionMethod::Gzip9 => "gzip-9",
+            CompressionMethod::Zle => "zle",
+            CompressionMethod::Lz4 => "lz4",
+            CompressionMethod::Zstd => "zstd",
+        }
+    }
+}
+
+impl TryFrom<u8> for CompressionMethod {
+    type Error = ();
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        CompressionMethod::from_value(value.into()).ok_or(())
+    }
+}
+
+impl From<CompressionMethod> for u8 {
+    fn from(method: CompressionMethod) -> u8 {
+        method as u8
+    }
+}
+
+impl std::fmt::Display for CompressionMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.get_name())
+    }
+}
+
+impl Serialize for CompressionMethod {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8((*self).into())
+    }
+}
+
+impl<'de> Deserialize<'de> for CompressionMethod {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_method(deserializer)
+    }
+}
+
+// The methods are serialized as their on disk values, so renaming a variant doesn't break old checkpoints and dumps
+// older versions serialized them as the names of the variants, so when reading json those are still accepted
+// NOTE: bincode used to write them as the index of the variant in a u32, now it's the on disk value in a u8,
+//       so binary checkpoints and fragment stores got a new schema version for it, see recovery::migrate
+struct MethodVisitor<T>(std::marker::PhantomData<T>);
+
+impl<'de, T: TryFrom<u8> + Debug> serde::de::Visitor<'de> for MethodVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "an on disk value or the name of a variant")
+    }
+
+    fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<T, E> {
+        u8::try_from(value)
+            .ok()
+            .and_then(|value| T::try_from(value).ok())
+            .ok_or_else(|| E::invalid_value(serde::de::Unexpected::Unsigned(value), &self))
+    }
+
+    fn visit_str<E: serde::de::Error>(self, name: &str) -> Result<T, E> {
+        (0..=u8::MAX)
+            .filter_map(|value| T::try_from(value).ok())
+            .find(|method| format!("{method:?}") == name)
+            .ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(name), &self))
+    }
+}
+
+fn deserialize_method<'de, D: serde::Deserializer<'de>, T: TryFrom<u8> + Debug>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    // Formats like bincode can't tell what is next in the input, but they never stored names anyway, old bincode is converted by recovery::migrate
+    if deserializer.is_human_readable() {
+        deserializer.deserialize_any(MethodVisitor(std::marker::PhantomData))
+    } else {
+        deserializer.deserialize_u8(MethodVisitor(std::marker::PhantomData))
+    }
+}
+
+// Zstd compressed blocks start with this header, the zstd frame comes after it
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zstd/zstd.h (zfs_zstdhdr_t)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZstdHeader {
+    // Size of the zstd frame, without the header
+    pub compressed_size: u32,
+    // The version of the zstd library that compressed the block (e.g. 10405 for 1.4.5)
+    pub version: u32,
+    // The zio_zstd_levels value the block was compressed with, 0 on pools from before the level was stored
+    pub level: u8,
+}
+
+impl ZstdHeader {
+    pub const SIZE: usize = 8;
+
+    pub fn from_bytes(data: &[u8]) -> Option<ZstdHeader> {
+        let compressed_size = u32::from_be_bytes(data.get(0..4)?.try_into().unwrap());
+        // The version and level are bitfields that are byteswapped together to big endian, so after swapping them back the version is in the low 24 bits
+        // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zstd/zstd.h (zfs_get_hdrversion, zfs_get_hdrlevel)
+        let raw_version_level = u32::from_be_bytes(data.get(4..8)?.try_into().unwrap());
+        Some(ZstdHeader {
+            compressed_size,
+            version: raw_version_level & 0xFF_FFFF,
+            level: (raw_version_level >> 24) as u8,
+        })
+    }
 }
 
-// NOTE: output_size is currently only used for lzjb
+// NOTE: output_size is currently only used for lzjb, zle and zstd (as a capacity hint)
 // NOTE: It is up to the caller to ensure the decompressed data is
 //       of size output_size and valid
 pub fn try_decompress_block(
@@ -370,13 +825,37 @@ pub fn try_decompress_block(
             )?
         }
 
+        CompressionMethod::Zstd => {
+            let header = ZstdHeader::from_bytes(block_data).ok_or(Vec::new())?;
+
+            // Note: compressed_size+8 may be equal to block_data.len(), just not greater
+            let frame_end = usize::try_from(header.compressed_size).unwrap() + ZstdHeader::SIZE;
+            let Some(frame) = block_data.get(ZstdHeader::SIZE..frame_end) else {
+                return Err(Vec::new());
+            };
+
+            let mut decoder =
+                ruzstd::decoding::StreamingDecoder::new(frame).map_err(|_| Vec::new())?;
+            let mut data = Vec::with_capacity(output_size);
+            if decoder.read_to_end(&mut data).is_err() {
+                return Err(data);
+            }
+            data
+        }
+
         CompressionMethod::Lzjb => {
-            lzjb::lzjb_decompress(&mut block_data.iter().copied(), output_size)
+            lzjb::lzjb_decompress(&mut block_data.iter().copied(), Some(output_size))
+                .map_err(|_| Vec::new())?
+        }
+
+        CompressionMethod::Zle => {
+            zle::zle_decompress(&mut block_data.iter().copied(), output_size, zle::DEFAULT_N)
                 .map_err(|_| Vec::new())?
         }
 
         _ => {
             use crate::ansi_color::*;
+            warnings::count_warning(WarningKind::UnsupportedCompression);
             if cfg!(feature = "debug") {
                 println!(
                     "{MAGENTA}TODO{WHITE}: {:?} compression is not implemented, returning error",
@@ -391,7 +870,42 @@ pub fn try_decompress_block(
     Ok(data)
 }
 
-fn try_checksum_block(block_data: &[u8], checksum_method: ChecksumMethod) -> Option<[u64; 4]> {
+// The inverse of try_decompress_block
+// NOTE: zfs only stores a block compressed if it saves enough space, it's up to the caller to check that
+pub fn try_compress_block(
+    block_data: &[u8],
+    compression_method: CompressionMethod,
+) -> Result<Vec<u8>, ()> {
+    Ok(match compression_method {
+        CompressionMethod::Off => Vec::from(block_data),
+        CompressionMethod::Lz4 | CompressionMethod::On => {
+            let compressed = lz4::lz4_compress_blocks(block_data);
+            // The data contains the size of the input as a big endian 32 bit int at the beginning before the lz4 stream starts
+            let mut data = Vec::with_capacity(compressed.len() + 4);
+            data.extend(
+                u32::try_from(compressed.len())
+                    .map_err(|_| ())?
+                    .to_be_bytes(),
+            );
+            data.extend(compressed);
+            data
+        }
+        CompressionMethod::Lzjb => lzjb::lzjb_compress(block_data),
+        _ => {
+            use crate::ansi_color::*;
+            if cfg!(feature = "debug") {
+                println!(
+                    "{MAGENTA}TODO{WHITE}: {:?} compression is not implemented, returning error",
+                    compression_method
+                );
+            }
+
+            return Err(());
+        }
+    })
+}
+
+pub fn try_checksum_block(block_data: &[u8], checksum_method: ChecksumMethod) -> Option<[u64; 4]> {
     Some(match checksum_method {
         ChecksumMethod::Fletcher4 | ChecksumMethod::GangHeader | ChecksumMethod::On => {
             fletcher::do_fletcher4(block_data)
@@ -411,66 +925,543 @@ fn try_checksum_block(block_data: &[u8], checksum_method: ChecksumMethod) -> Opt
     })
 }
 
-// Byte order (https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L591)
-// 0 = big endian
-// 1 = little endian
-// Note: This is mostly useless as it is a part of a 64-bit integer which is stored in *native* endianess ( i think ) in the block pointer
-// The correct way to do this is probably to check the uberblock magic, but if an uberblock with a txg of n can ever lead to blocks with txg < n this would probably not work either
-// That or i'm stupid, idk
+// Returns: true if the checksum of the data matches the expected checksum
+// If we don't know how to calculate the checksum, accept_unverifiable decides
+fn verify_checksum(
+    data: &[u8],
+    checksum_method: ChecksumMethod,
+    expected_checksum: &[u64; 4],
+    accept_unverifiable: bool,
+) -> bool {
+    match try_checksum_block(data, checksum_method) {
+        Some(computed_checksum) => computed_checksum == *expected_checksum,
+        None => accept_unverifiable,
+    }
+}
 
-// Embedded block pointer info
-// BDX LVL   TYP      ETYP     E COMP    PSIZE   LSIZE
-// 100 00000 00001011 00000111 1 0001111 0000000 0000000000000000000000111
-// 3   5     8        8        1 7       7       25
+// The last step of reading any block, decompress it and make sure we got as much data as we expected
+fn decompress_and_check_size(
+    data: &[u8],
+    compression_method: CompressionMethod,
+    logical_size: usize,
+) -> Result<Vec<u8>, ()> {
+    let Ok(data) = try_decompress_block(data, compression_method, logical_size) else {
+        warnings::count_warning(WarningKind::DecompressionFailed);
+        return Err(());
+    };
 
-// Normal block pointer info
-// BDX LVL   TYP      CKSUM    E COMP    PSIZE            LSIZE
-// 100 00000 00001011 00000111 0 0001111 0000000000000000 0000000000000111
-// 3   5     8        8        1 7       16	              16
+    if data.len() != logical_size {
+        use crate::ansi_color::*;
+        warnings::count_warning(WarningKind::DecompressionFailed);
+        if cfg!(feature = "debug") {
+            println!("{YELLOW}Warning{WHITE}: Block pointer doesn't point to as much data as it says it should, i refuse to return it's data!");
+        }
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct NormalBlockPointer {
-    dvas: [Option<DataVirtualAddress>; 3],
-    level: usize,
-    fill: u64,
-    logical_birth_txg: u64,
-    typ: dmu::ObjType,
-    checksum_method: ChecksumMethod,
-    compression_method: CompressionMethod,
-    physical_size_in_512b_sectors_minus_one: u16,
-    logical_size_in_512b_sectors_minus_one: u16,
-    checksum: [u64; 4],
+        return Err(());
+    }
+
+    Ok(data)
 }
 
-impl Debug for NormalBlockPointer {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("NormalBlockPointer")
-            .field("dvas", &self.dvas)
-            .field("level", &self.level)
-            .field("fill", &self.fill)
-            .field("logical_birth_txg", &self.logical_birth_txg)
-            .field("typ", &self.typ)
-            .field("checksum_method", &self.checksum_method)
-            .field("compression_method", &self.compression_method)
-            .field("physical_size", &self.parse_physical_size())
-            .field("logical_size", &self.parse_logical_size())
-            .field("checksum", &self.checksum)
-            .finish()
+// Where the data of a block came from, for forensics it matters which copy was actually used
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum BlockSource {
+    // The index of the dva in the block pointer
+    Dva(usize),
+    // The dva at this index was a gang block, the data was stitched together from its members
+    GangMember(usize),
+    // All the dvas were bad, the data was found by searching the disks for its checksum, this is the offset it was found at
+    YoloOffset(u64),
+    // All the dvas were bad, the data was a copy on the cache device at this offset
+    L2Arc(u64),
+    // The data is in the block pointer itself
+    Embedded,
+    // No copy was good, this is the one at this index that the read policy let through anyway (see ReadPolicy), so the data is probably damaged
+    Unverified(usize),
+    // All the dvas were bad, but the same data was already read from this offset for another block pointer (see known_blocks)
+    KnownCopy(u64),
+}
+
+impl BlockSource {
+    pub fn is_unverified(&self) -> bool {
+        matches!(self, BlockSource::Unverified(_))
     }
 }
 
-impl NormalBlockPointer {
-    pub fn from_bytes_le<Iter>(data: &mut Iter) -> Option<NormalBlockPointer>
-    where
-        Iter: Iterator<Item = u8> + Clone,
-    {
-        let dva1 = DataVirtualAddress::from_bytes_le(&mut data.clone());
-        data.skip_n_bytes(DataVirtualAddress::get_ondisk_size())?;
-        let dva2 = DataVirtualAddress::from_bytes_le(&mut data.clone());
-        data.skip_n_bytes(DataVirtualAddress::get_ondisk_size())?;
-        let dva3 = DataVirtualAddress::from_bytes_le(&mut data.clone());
-        data.skip_n_bytes(DataVirtualAddress::get_ondisk_size())?;
-        let info = u64::from_bytes_le(data)?;
+// Like zdb prints dvas, and where they are on the disks if that's known, see device_map
+// NOTE: For gang dvas that is where the gang header is, the data is wherever the gang header points to
+impl std::fmt::Display for DataVirtualAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<{}{}>", zdb::format_dva(self), if self.is_gang { ":G" } else { "" })?;
+        if let Some(ranges) = device_map::format_device_ranges(
+            self.vdev_id,
+            self.parse_offset(),
+            self.parse_allocated_size(),
+        ) {
+            write!(f, " ({ranges})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for BlockSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockSource::Dva(index) => write!(f, "dva {index}"),
+            BlockSource::GangMember(index) => write!(f, "gang block at dva {index}"),
+            BlockSource::YoloOffset(offset) => write!(f, "yolo recovery at offset {offset}"),
+            BlockSource::L2Arc(offset) => write!(f, "cache device at offset {offset}"),
+            BlockSource::Embedded => write!(f, "embedded data"),
+            BlockSource::Unverified(index) => write!(f, "unverified dva {index}"),
+            BlockSource::KnownCopy(offset) => write!(f, "known copy at offset {offset}"),
+        }
+    }
+}
+
+// How picky reads are about the data of a block, the default only returns data that is exactly what was written
+// For forensics damaged data can be better than a gap of zeros, so the checks can be loosened, but only if no copy of the block passes them
+// the data is then flagged with BlockSource::Unverified and a warning
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ReadPolicy {
+    // When false, a copy whose checksum doesn't match can still be used
+    pub verify_checksums: bool,
+    // When true, the part of a copy that could be decompressed is used, padded with zeros up to the logical size of the block
+    pub accept_partial_decompression: bool,
+    // How many more times to read a copy when the disk returns an error, a flaky disk (or usb adapter) often reads fine the second time
+    // NOTE: Data that was read but is wrong isn't read again, the raidz sector cache would just return the same data
+    pub max_retries: usize,
+}
+
+impl Default for ReadPolicy {
+    fn default() -> Self {
+        ReadPolicy {
+            verify_checksums: true,
+            accept_partial_decompression: false,
+            max_retries: 0,
+        }
+    }
+}
+
+impl ReadPolicy {
+    // Returns: If only blocks that are exactly what was written are returned
+    pub fn is_strict(&self) -> bool {
+        self.verify_checksums && !self.accept_partial_decompression
+    }
+}
+
+lazy_static! {
+    static ref READ_POLICY: RwLock<ReadPolicy> = RwLock::new(ReadPolicy::default());
+    // None means it's not known, then every dva whose vdev is missing is read from vdev 0
+    static ref TOP_LEVEL_VDEV_COUNT: RwLock<Option<u64>> = RwLock::new(None);
+}
+
+// How a dva is read with the vdevs that were added, see DataVirtualAddress::get_routing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VdevRouting {
+    // From the vdev with its id
+    Direct(usize),
+    // Its vdev wasn't added, and the pool doesn't have a top level vdev with that id either, so the id is probably corrupt and it's read from vdev 0
+    Corrected,
+    // Its vdev is a top level vdev of the pool that wasn't added, so its data isn't anywhere in the vdevs
+    Unreachable,
+}
+
+// After this dvas on top level vdevs of the pool that weren't added to the vdevs are skipped, instead of being read from vdev 0
+// which would only give the data of whatever is at the same offset of vdev 0
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/spa_config.c (ZPOOL_CONFIG_VDEV_CHILDREN)
+pub fn set_top_level_vdev_count(count: u64) {
+    if let Ok(mut lock) = TOP_LEVEL_VDEV_COUNT.write() {
+        *lock = Some(count);
+    }
+}
+
+pub fn get_top_level_vdev_count() -> Option<u64> {
+    TOP_LEVEL_VDEV_COUNT.read().ok().and_then(|lock| *lock)
+}
+
+// After this every read pipeline made with ReadPipeline::default uses this policy, a pipeline can still be given its own
+pub fn set_read_policy(policy: ReadPolicy) {
+    if let Ok(mut lock) = READ_POLICY.write() {
+        *lock = policy;
+    }
+}
+
+pub fn get_read_policy() -> ReadPolicy {
+    READ_POLICY.read().map(|lock| *lock).unwrap_or_default()
+}
+
+// Reading a block is always the same sequence: read a copy -> checksum it -> decompress it -> check the size
+// The only things that change between normal reads and recovery are where the copies come from and how picky we are
+// so those are the policy hooks
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zio.c (the read pipeline: ZIO_READ_PIPELINE)
+#[derive(Clone, Copy)]
+pub struct ReadPipeline {
+    // Called with the index of the dva in the block pointer, return false to skip that copy
+    pub should_try_dva: fn(usize, &DataVirtualAddress) -> bool,
+    // If all copies fail, search the disks for a block with the right checksum (only works for fletcher4 on raidz)
+    // see yolo_block_recovery::YoloConfig
+    pub use_yolo_recovery: bool,
+    // Accept blocks whose checksum method is not implemented, instead of treating them as corrupted
+    pub accept_unverifiable: bool,
+    // Use the vdev block cache, a pipeline that only tries some of the copies should probably not, as it would cache a failure for everybody else
+    pub use_block_cache: bool,
+    // If all copies fail, look for the block on the cache device, see l2arc::set_cache_device
+    pub use_l2arc: bool,
+    // What to do when no copy is good, see set_read_policy
+    pub policy: ReadPolicy,
+    // Skip dvas that are known to hold other data, and if all copies fail read the block from where it was found before, see known_blocks::enable
+    pub use_known_blocks: bool,
+}
+
+impl Default for ReadPipeline {
+    fn default() -> Self {
+        // Yolo recovery needs the checksum table files, so without the disk feature it's never on
+        #[cfg(feature = "disk")]
+        let use_yolo_recovery = yolo_block_recovery::is_enabled();
+        #[cfg(not(feature = "disk"))]
+        let use_yolo_recovery = false;
+        ReadPipeline {
+            should_try_dva: |_, _| true,
+            use_yolo_recovery,
+            accept_unverifiable: false,
+            use_block_cache: true,
+            use_l2arc: l2arc::is_loaded(),
+            policy: get_read_policy(),
+            use_known_blocks: known_blocks::is_enabled(),
+        }
+    }
+}
+
+impl ReadPipeline {
+    // Runs the checksum and decompression stages on data read from somewhere
+    // this is also useful for recovery code that finds the data some other way than through a dva
+    pub fn finish_read(&self, data: &[u8], bp: &NormalBlockPointer) -> Result<Vec<u8>, ()> {
+        match try_checksum_block(data, bp.checksum_method) {
+            Some(computed_checksum) if computed_checksum != bp.checksum => {
+                warnings::count_warning(WarningKind::ChecksumMismatch);
+                return Err(());
+            }
+            None if !self.accept_unverifiable => {
+                warnings::count_warning(WarningKind::UnsupportedChecksum);
+                return Err(());
+            }
+            _ => (),
+        }
+
+        decompress_and_check_size(
+            data,
+            bp.compression_method,
+            usize::try_from(bp.parse_logical_size()).unwrap(),
+        )
+    }
+
+    // Like finish_read, but only does the checks the read policy asks for
+    fn finish_read_with_policy(&self, data: &[u8], bp: &NormalBlockPointer) -> Result<Vec<u8>, ()> {
+        if self.policy.verify_checksums
+            && !verify_checksum(
+                data,
+                bp.checksum_method,
+                &bp.checksum,
+                self.accept_unverifiable,
+            )
+        {
+            return Err(());
+        }
+
+        let logical_size = usize::try_from(bp.parse_logical_size()).unwrap();
+        let mut data = match try_decompress_block(data, bp.compression_method, logical_size) {
+            Ok(data) => data,
+            Err(partial_data) if self.policy.accept_partial_decompression => partial_data,
+            Err(_) => return Err(()),
+        };
+        if data.len() != logical_size {
+            if !self.policy.accept_partial_decompression {
+                return Err(());
+            }
+            data.resize(logical_size, 0);
+        }
+        Ok(data)
+    }
+
+    pub fn read(&self, bp: &NormalBlockPointer, vdevs: &mut Vdevs) -> Result<Vec<u8>, ()> {
+        let cache_key = (bp.checksum, bp.checksum_method);
+
+        // The cache lives on vdev 0, if only other top level vdevs were opened there is no cache
+        if self.use_block_cache {
+            if let Some(vdev) = vdevs.get_mut(&0) {
+                match vdev.get_from_block_cache(&cache_key) {
+                    Some(Some(data)) => return Ok(data.to_vec()),
+                    // A loose policy might still accept one of the copies
+                    Some(None) if self.policy.is_strict() => return Err(()),
+                    _ => (),
+                }
+            }
+        }
+
+        self.read_with_source(bp, vdevs).map(|(data, _)| data)
+    }
+
+    // Like read, but also returns which copy the data came from
+    // NOTE: The cache doesn't remember where the data came from, so this always reads from the disks, but it still fills the cache
+    pub fn read_with_source(
+        &self,
+        bp: &NormalBlockPointer,
+        vdevs: &mut Vdevs,
+    ) -> Result<(Vec<u8>, BlockSource), ()> {
+        let cache_key = (bp.checksum, bp.checksum_method);
+        let psize = usize::try_from(bp.parse_physical_size()).unwrap();
+
+        let res = self.read_uncached(bp, psize, vdevs);
+
+        // Unverified data is not what the checksum is of, so it must not be cached under it
+        let is_unverified = matches!(&res, Ok((_, source)) if source.is_unverified());
+        if self.use_block_cache && !is_unverified {
+            // TODO: If there are many vdevs, this will only use the first one for the cache
+            if let Some(vdev) = vdevs.get_mut(&0) {
+                vdev.put_in_block_cache(cache_key, res.as_ref().ok().map(|(data, _)| data.clone()));
+            }
+        }
+
+        res
+    }
+
+    #[cfg(feature = "disk")]
+    fn try_yolo_recovery(
+        &self,
+        bp: &NormalBlockPointer,
+        psize: usize,
+        vdevs: &mut Vdevs,
+    ) -> Option<(Vec<u8>, BlockSource)> {
+        if !self.use_yolo_recovery || bp.checksum_method != ChecksumMethod::Fletcher4 {
+            return None;
+        }
+        let res_off =
+            yolo_block_recovery::find_block_with_fletcher4_checksum(vdevs, &bp.checksum, psize)?;
+        let dva = DataVirtualAddress::from(0 /* just a guess */, res_off, false);
+        let data = dva.dereference(vdevs, psize).ok()?;
+        // NOTE: The yolo search already checked the checksum, but it's cheap to check again
+        let data = self.finish_read(&data, bp).ok()?;
+        Some((data, BlockSource::YoloOffset(res_off)))
+    }
+
+    fn read_uncached(
+        &self,
+        bp: &NormalBlockPointer,
+        psize: usize,
+        vdevs: &mut Vdevs,
+    ) -> Result<(Vec<u8>, BlockSource), ()> {
+        // The first copy that only the read policy lets through, it's only used if nothing else works
+        let mut fallback = None;
+        // The copies on their own vdev are tried first, a copy whose vdev id had to be corrected is the least likely to be right
+        // and then the copies that aren't on parts of the disks the damage maps say are bad
+        // NOTE: The sort is stable, so otherwise the copies are tried in the order of the block pointer
+        let mut dvas = bp
+            .dvas
+            .iter()
+            .enumerate()
+            .filter_map(|(index, dva)| Some((index, dva.as_ref()?)))
+            .collect::<Vec<_>>();
+        dvas.sort_by_key(|(_, dva)| {
+            (
+                !matches!(dva.get_routing(vdevs), VdevRouting::Direct(_)),
+                damage_map::is_dva_damaged(dva),
+            )
+        });
+        let is_every_copy_damaged =
+            !dvas.is_empty() && dvas.iter().all(|(_, dva)| damage_map::is_dva_damaged(dva));
+        if is_every_copy_damaged {
+            warnings::count_warning(WarningKind::DamagedCopies);
+            if cfg!(feature = "debug") {
+                use crate::ansi_color::*;
+                println!("{YELLOW}Warning{WHITE}: Every copy of the block is on a bad part of the disks: {:?}", dvas.iter().map(|(_, dva)| dva.to_string()).collect::<Vec<_>>());
+            }
+            // Their data is probably zeros, so the yolo search is more likely to find the block than reading them
+            #[cfg(feature = "disk")]
+            if let Some(res) = self.try_yolo_recovery(bp, psize, vdevs) {
+                return Ok(res);
+            }
+        }
+        let gang_verifier = bp.get_gang_verifier();
+        for (index, dva) in dvas {
+            if !(self.should_try_dva)(index, dva) {
+                continue;
+            }
+
+            if dva.get_routing(vdevs) == VdevRouting::Unreachable {
+                if cfg!(feature = "debug") {
+                    use crate::ansi_color::*;
+                    println!("{YELLOW}Warning{WHITE}: Dva {index} of the block is on top level vdev {}, which wasn't opened, skipping it!", dva.get_vdev_id());
+                }
+                continue;
+            }
+
+            if self.use_known_blocks
+                && known_blocks::should_skip(dva, psize, bp.checksum_method, &bp.checksum)
+            {
+                continue;
+            }
+
+            let mut data = dva.dereference_with_gang_verifier(vdevs, psize, gang_verifier.as_ref());
+            for _ in 0..self.policy.max_retries {
+                if data.is_ok() {
+                    break;
+                }
+                data = dva.dereference_with_gang_verifier(vdevs, psize, gang_verifier.as_ref());
+            }
+            let Ok(data) = data else {
+                warnings::count_warning(WarningKind::BadDva);
+                if cfg!(feature = "debug") {
+                    use crate::ansi_color::*;
+                    println!("{YELLOW}Warning{WHITE}: Invalid dva {dva}");
+                }
+                continue;
+            };
+
+            let Ok(data) = self.finish_read(&data, bp) else {
+                if self.use_known_blocks {
+                    if let Some(checksum) = try_checksum_block(&data, bp.checksum_method) {
+                        known_blocks::remember(dva, psize, bp.checksum_method, checksum);
+                    }
+                }
+                if fallback.is_none() && !self.policy.is_strict() {
+                    fallback = self
+                        .finish_read_with_policy(&data, bp)
+                        .ok()
+                        .map(|data| (data, index, dva));
+                }
+                if cfg!(feature = "debug") {
+                    use crate::ansi_color::*;
+                    println!("{YELLOW}Warning{WHITE}: Invalid checksum or data for dva: {dva}, ignoring this dva.");
+                }
+                continue;
+            };
+
+            if cfg!(feature = "verbose_debug") {
+                use crate::ansi_color::*;
+                println!("{CYAN}Info{WHITE}: Using dva: {dva}");
+            }
+            if self.use_known_blocks {
+                known_blocks::remember(dva, psize, bp.checksum_method, bp.checksum);
+            }
+
+            let source = if dva.is_gang() {
+                BlockSource::GangMember(index)
+            } else {
+                BlockSource::Dva(index)
+            };
+            return Ok((data, source));
+        }
+
+        if self.use_known_blocks {
+            for (vdev_id, offset, _) in
+                known_blocks::get_locations(bp.checksum_method, &bp.checksum, psize)
+            {
+                let dva = DataVirtualAddress::from(vdev_id, offset, false);
+                if let Ok(data) = dva
+                    .dereference(vdevs, psize)
+                    .and_then(|data| self.finish_read(&data, bp))
+                {
+                    known_blocks::count_redirected_read();
+                    return Ok((data, BlockSource::KnownCopy(offset)));
+                }
+            }
+        }
+
+        // The cache device knows blocks by their first dva
+        if let (true, Some(dva)) = (self.use_l2arc, &bp.dvas[0]) {
+            for (data, entry) in l2arc::read_cached_copies(dva, bp.get_logical_birth_txg()) {
+                if let Ok(data) = self.finish_read(&data, bp) {
+                    return Ok((data, BlockSource::L2Arc(entry.device_address)));
+                }
+            }
+        }
+
+        #[cfg(feature = "disk")]
+        if !is_every_copy_damaged {
+            if let Some(res) = self.try_yolo_recovery(bp, psize, vdevs) {
+                return Ok(res);
+            }
+        }
+
+        if let Some((data, index, dva)) = fallback {
+            use crate::ansi_color::*;
+            warnings::count_warning(WarningKind::UnverifiedBlock);
+            println!("{YELLOW}Warning{WHITE}: No copy of the block is good, using dva {index} ({dva}) anyway because the read policy allows it, its data is probably damaged!");
+            return Ok((data, BlockSource::Unverified(index)));
+        }
+
+        warnings::count_warning(WarningKind::UnreadableBlock);
+        if cfg!(feature = "debug") {
+            use crate::ansi_color::*;
+            println!(
+                "{YELLOW}Warning{WHITE}: Failed to dereference block pointer: {:?}.",
+                bp
+            );
+        }
+
+        Err(())
+    }
+}
+
+// Byte order (https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L591)
+// 0 = big endian
+// 1 = little endian
+// Note: This is mostly useless as it is a part of a 64-bit integer which is stored in *native* endianess ( i think ) in the block pointer
+// The correct way to do this is probably to check the uberblock magic, but if an uberblock with a txg of n can ever lead to blocks with txg < n this would probably not work either
+// That or i'm stupid, idk
+
+// Embedded block pointer info
+// BDX LVL   TYP      ETYP     E COMP    PSIZE   LSIZE
+// 100 00000 00001011 00000111 1 0001111 0000000 0000000000000000000000111
+// 3   5     8        8        1 7       7       25
+
+// Normal block pointer info
+// BDX LVL   TYP      CKSUM    E COMP    PSIZE            LSIZE
+// 100 00000 00001011 00000111 0 0001111 0000000000000000 0000000000000111
+// 3   5     8        8        1 7       16	              16
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NormalBlockPointer {
+    dvas: [Option<DataVirtualAddress>; 3],
+    level: usize,
+    fill: u64,
+    logical_birth_txg: u64,
+    // 0 if it's the same as the logical birth txg, it's only different for blocks that were written again without changing their contents (ex. by dedup)
+    // It isn't saved, so checkpoints made before it was added can still be read, deserialized block pointers have 0
+    #[serde(skip)]
+    physical_birth_txg: u64,
+    typ: dmu::ObjType,
+    checksum_method: ChecksumMethod,
+    compression_method: CompressionMethod,
+    physical_size_in_512b_sectors_minus_one: u16,
+    logical_size_in_512b_sectors_minus_one: u16,
+    checksum: [u64; 4],
+}
+
+impl Debug for NormalBlockPointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NormalBlockPointer")
+            .field("dvas", &self.dvas)
+            .field("level", &self.level)
+            .field("fill", &self.fill)
+            .field("logical_birth_txg", &self.logical_birth_txg)
+            .field("typ", &self.typ)
+            .field("checksum_method", &self.checksum_method)
+            .field("compression_method", &self.compression_method)
+            .field("physical_size", &self.parse_physical_size())
+            .field("logical_size", &self.parse_logical_size())
+            .field("checksum", &self.checksum)
+            .finish()
+    }
+}
+
+impl FromSliceLE for NormalBlockPointer {
+    fn from_slice_le(data: &[u8]) -> Option<NormalBlockPointer> {
+        let data = data.get(..BlockPointer::get_ondisk_size())?;
+        let dva_size = DataVirtualAddress::get_ondisk_size();
+        let dvas =
+            [0, 1, 2].map(|index| DataVirtualAddress::from_slice_le(&data[index * dva_size..]));
+        let info = read_le_at::<u64>(data, 3 * dva_size)?;
 
         // Make sure we don't accidentally read an embedded block pointer
         if (info >> 39) & 1 != 0 {
@@ -490,27 +1481,28 @@ impl NormalBlockPointer {
         }
 
         // Check endianness bit just in case
-        if (info >> 63) & 1 != 1 {
+        // NOTE: Redacted block pointers are made without setting the byte order, so they don't have it
+        // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dbuf.c (dmu_buf_redact)
+        let is_redacted = ((info >> 40) & 0b1111_1111) as usize == EmbeddedType::Redacted as usize;
+        if (info >> 63) & 1 != 1 && !is_redacted {
             return None;
         }
 
-        // Skip padding
-        data.skip_n_bytes(core::mem::size_of::<u64>() * 3)?;
-
-        let logical_birth_txg = u64::from_bytes_le(data)?;
-        let fill_count = u64::from_bytes_le(data)?;
-        let checksum = [
-            u64::from_bytes_le(data)?,
-            u64::from_bytes_le(data)?,
-            u64::from_bytes_le(data)?,
-            u64::from_bytes_le(data)?,
-        ];
+        // The 2 words after the info are padding
+        let physical_birth_txg = read_le_at::<u64>(data, 72)?;
+        let logical_birth_txg = read_le_at::<u64>(data, 80)?;
+        let fill_count = read_le_at::<u64>(data, 88)?;
+        let mut checksum = [0u64; 4];
+        for (index, word) in checksum.iter_mut().enumerate() {
+            *word = read_le_at::<u64>(data, 96 + index * 8)?;
+        }
 
         Some(NormalBlockPointer {
-            dvas: [dva1, dva2, dva3],
+            dvas,
             level: ((info >> 56) & 0b1_1111) as usize,
             fill: fill_count,
             logical_birth_txg,
+            physical_birth_txg,
             typ: dmu::ObjType::from_value(((info >> 48) & 0b1111_1111) as usize)?,
             checksum_method: ChecksumMethod::from_value(((info >> 40) & 0b1111_1111) as usize)?,
             compression_method: CompressionMethod::from_value(
@@ -521,6 +1513,48 @@ impl NormalBlockPointer {
             checksum,
         })
     }
+}
+
+impl NormalBlockPointer {
+    pub fn from_bytes_le<Iter>(data: &mut Iter) -> Option<NormalBlockPointer>
+    where
+        Iter: Iterator<Item = u8>,
+    {
+        let data = byte_iter::read_array::<{ BlockPointer::get_ondisk_size() }>(data)?;
+        Self::from_slice_le(&data)
+    }
+
+    // The inverse of from_bytes_le, so block pointers can be written back (ex. to patch an indirect block)
+    // NOTE: The parser doesn't keep the dedup bit, so it's written as 0, which zfs reads as not deduped
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (blkptr_t, BP_GET_*, BP_PHYSICAL_BIRTH)
+    pub fn to_bytes_le(&self) -> [u8; BlockPointer::get_ondisk_size()] {
+        let mut res = [0u8; BlockPointer::get_ondisk_size()];
+        for (index, dva) in self.dvas.iter().enumerate() {
+            if let Some(dva) = dva {
+                let start = index * DataVirtualAddress::get_ondisk_size();
+                res[start..start + DataVirtualAddress::get_ondisk_size()]
+                    .copy_from_slice(&dva.to_bytes_le());
+            }
+        }
+
+        let info = (1u64 << 63) // little endian
+            | ((self.level as u64 & 0b1_1111) << 56)
+            | ((self.typ as u64 & 0b1111_1111) << 48)
+            | ((self.checksum_method as u64 & 0b1111_1111) << 40)
+            | ((self.compression_method as u64 & 0b0111_1111) << 32)
+            | (u64::from(self.physical_size_in_512b_sectors_minus_one) << 16)
+            | u64::from(self.logical_size_in_512b_sectors_minus_one);
+        res[48..56].copy_from_slice(&info.to_le_bytes());
+
+        // The 2 words after the info are padding
+        res[72..80].copy_from_slice(&self.physical_birth_txg.to_le_bytes());
+        res[80..88].copy_from_slice(&self.logical_birth_txg.to_le_bytes());
+        res[88..96].copy_from_slice(&self.fill.to_le_bytes());
+        for (index, word) in self.checksum.iter().enumerate() {
+            res[96 + index * 8..104 + index * 8].copy_from_slice(&word.to_le_bytes());
+        }
+        res
+    }
 
     // Returns: Logical size of the data pointed to by the block pointer, in bytes
     pub fn parse_logical_size(&self) -> u64 {
@@ -542,111 +1576,78 @@ impl NormalBlockPointer {
         &self.dvas
     }
 
-    // NOTE: zfs always checksums the data once put together, so the checksum is of the data pointed to by the gang blocks once stitched together, and it is done before decompression
-    pub fn dereference(&mut self, vdevs: &mut Vdevs) -> Result<Vec<u8>, ()> {
-        if let Some(res) = vdevs
-            .get_mut(&0)
-            .unwrap()
-            .get_from_block_cache(&(self.checksum, self.checksum_method))
-        {
-            return res.map(|val| val.to_vec()).ok_or(());
-        }
-
-        for dva in self.dvas.iter().filter_map(|val| val.as_ref()) {
-            let Ok(data) = dva.dereference(vdevs, usize::try_from(self.parse_physical_size()).unwrap()) else {
-                if cfg!(feature = "debug") {
-                    use crate::ansi_color::*;
-                    println!("{YELLOW}Warning{WHITE}: Invalid dva {:?}", dva);
-                }
-                continue;
-            };
+    pub fn get_level(&self) -> usize {
+        self.level
+    }
 
-            let Some(computed_checksum) = try_checksum_block(&data, self.checksum_method) else {
-                continue;
-            };
+    pub fn get_fill(&self) -> u64 {
+        self.fill
+    }
 
-            if computed_checksum != self.checksum {
-                use crate::ansi_color::*;
-                if cfg!(feature = "debug") {
-                    println!("{YELLOW}Warning{WHITE}: Invalid checksum for dva: {:?}, ignoring this dva.", dva);
-                }
-                continue;
-            }
+    pub fn get_logical_birth_txg(&self) -> u64 {
+        self.logical_birth_txg
+    }
 
-            let Ok(data) = try_decompress_block(&data, self.compression_method, usize::try_from(self.parse_logical_size()).unwrap()) else {
-                continue;
-            };
+    // Returns: The txg the data was written in, like BP_GET_BIRTH, which is the logical birth txg unless the block has a physical one
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (BP_GET_BIRTH)
+    pub fn get_physical_birth_txg(&self) -> u64 {
+        if self.physical_birth_txg != 0 {
+            self.physical_birth_txg
+        } else {
+            self.logical_birth_txg
+        }
+    }
 
-            if data.len() as u64 != self.parse_logical_size() {
-                use crate::ansi_color::*;
-                if cfg!(feature = "debug") {
-                    println!("{YELLOW}Warning{WHITE}: Normal block pointer doesn't point to as much data as it says it should, i refuse to return it's data!");
-                }
+    // Returns: What the gang headers of this block are checked against, None if it doesn't have a first dva
+    pub fn get_gang_verifier(&self) -> Option<[u64; 4]> {
+        let first_dva = self.dvas[0].as_ref()?;
+        Some(GangBlock::get_verifier(
+            first_dva,
+            self.get_physical_birth_txg(),
+        ))
+    }
 
-                continue;
-            }
+    pub fn get_type(&self) -> dmu::ObjType {
+        self.typ
+    }
 
-            if cfg!(feature = "verbose_debug") {
-                use crate::ansi_color::*;
-                println!("{CYAN}Info{WHITE}: Using dva: {:?}", dva);
-            }
+    pub fn get_checksum_method(&self) -> ChecksumMethod {
+        self.checksum_method
+    }
 
-            // TODO: If there are many vdevs, this will only use the first one for the cache
-            vdevs
-                .get_mut(&0)
-                .unwrap()
-                .put_in_block_cache((self.checksum, self.checksum_method), Some(data.clone()));
-            return Ok(data);
-        }
-
-        if cfg!(feature = "yolo") && self.checksum_method == ChecksumMethod::Fletcher4 {
-            if let Some(res_off) = yolo_block_recovery::find_block_with_fletcher4_checksum(
-                vdevs,
-                &self.checksum,
-                usize::try_from(self.parse_physical_size()).unwrap(),
-            ) {
-                let dva = DataVirtualAddress::from(0 /* just a guess */, res_off, false);
-                if let Ok(Ok(data)) = dva
-                    .dereference(vdevs, usize::try_from(self.parse_physical_size()).unwrap())
-                    .map(|data| {
-                        try_decompress_block(
-                            &data,
-                            self.compression_method,
-                            usize::try_from(self.parse_logical_size()).unwrap(),
-                        )
-                    })
-                {
-                    if data.len() as u64 != self.parse_logical_size() {
-                        use crate::ansi_color::*;
-                        if cfg!(feature = "debug") {
-                            println!("{YELLOW}Warning{WHITE}: Normal block pointer doesn't point to as much data as it says it should, i refuse to return it's data!");
-                        }
+    pub fn get_compression_method(&self) -> CompressionMethod {
+        self.compression_method
+    }
 
-                        return Err(());
-                    }
+    // NOTE: zfs always checksums the data once put together, so the checksum is of the data pointed to by the gang blocks once stitched together, and it is done before decompression
+    pub fn dereference(&mut self, vdevs: &mut Vdevs) -> Result<Vec<u8>, ()> {
+        ReadPipeline::default().read(self, vdevs)
+    }
 
-                    vdevs.get_mut(&0).unwrap().put_in_block_cache(
-                        (self.checksum, self.checksum_method),
-                        Some(data.clone()),
-                    );
-                    return Ok(data);
-                };
-            }
-        }
+    pub fn dereference_with_source(
+        &mut self,
+        vdevs: &mut Vdevs,
+    ) -> Result<(Vec<u8>, BlockSource), ()> {
+        ReadPipeline::default().read_with_source(self, vdevs)
+    }
+}
 
-        if cfg!(feature = "debug") {
-            use crate::ansi_color::*;
-            println!(
-                "{YELLOW}Warning{WHITE}: Failed to dereference block pointer: {:?}.",
-                self
-            );
-        }
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (bp_embedded_type_t)
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum EmbeddedType {
+    Data = 0,
+    Reserved = 1, // Reserved for an unintegrated feature
+    Redacted = 2, // The block was redacted, so there is no data, only the size
+}
 
-        vdevs.get_mut(&0).unwrap().put_in_block_cache(
-            (self.checksum, self.checksum_method),
-            None,
-        );
-        Err(())
+impl EmbeddedType {
+    pub fn from_value(value: usize) -> Option<EmbeddedType> {
+        Some(match value {
+            0 => EmbeddedType::Data,
+            1 => EmbeddedType::Reserved,
+            2 => EmbeddedType::Redacted,
+            _ => return None,
+        })
     }
 }
 
@@ -658,10 +1659,10 @@ pub struct EmbeddedBlockPointer {
     logical_birth_txg: u64,
     level: usize,
     typ: dmu::ObjType,
-    embedded_data_type: dmu::ObjType,
+    embedded_data_type: EmbeddedType,
     compression_method: CompressionMethod,
-    physical_size_in_bytes: u8,
-    logical_size_in_bytes: u32, // only takes up 24 bits on disk
+    physical_size_in_bytes: u8, // only takes up 7 bits on disk
+    logical_size_in_bytes: u32, // only takes up 25 bits on disk
 }
 
 impl Debug for EmbeddedBlockPointer {
@@ -679,17 +1680,10 @@ impl Debug for EmbeddedBlockPointer {
     }
 }
 
-impl<It> FromBytesLE<It> for EmbeddedBlockPointer
-where
-    It: Iterator<Item = u8>,
-{
-    fn from_bytes_le(data: &mut It) -> Option<EmbeddedBlockPointer> {
-        let mut payload = Vec::<u8>::new();
-        for _ in 0..6 * core::mem::size_of::<u64>() {
-            payload.push(u8::from_bytes(data)?);
-        }
-
-        let info = u64::from_bytes_le(data)?;
+impl FromSliceLE for EmbeddedBlockPointer {
+    fn from_slice_le(data: &[u8]) -> Option<EmbeddedBlockPointer> {
+        let data = data.get(..BlockPointer::get_ondisk_size())?;
+        let info = read_le_at::<u64>(data, 48)?;
 
         // Make sure we don't accidentally read an embedded block pointer
         if (info >> 39) & 1 != 1 {
@@ -709,18 +1703,29 @@ where
         }
 
         // Check endianness bit just in case
-        if (info >> 63) & 1 != 1 {
+        // NOTE: Redacted block pointers are made without setting the byte order, so they don't have it
+        // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dbuf.c (dmu_buf_redact)
+        let is_redacted = ((info >> 40) & 0b1111_1111) as usize == EmbeddedType::Redacted as usize;
+        if (info >> 63) & 1 != 1 && !is_redacted {
             return None;
         }
 
-        for _ in 0..3 * core::mem::size_of::<u64>() {
-            payload.push(u8::from_bytes(data)?);
-        }
+        // The payload is split around the info word and the logical birth txg
+        let payload = [&data[0..48], &data[56..80], &data[88..]].concat();
+        let logical_birth_txg = read_le_at::<u64>(data, 80)?;
 
-        let logical_birth_txg = u64::from_bytes_le(data)?;
+        // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L333
+        let physical_size_in_bytes = ((info >> 25) & 0b111_1111) as u8;
+        let logical_size_in_bytes = ((info >> 0) & ((1 << 25) - 1)) as u32;
 
-        for _ in 0..5 * core::mem::size_of::<u64>() {
-            payload.push(u8::from_bytes(data)?);
+        // The payload can hold at most 112 bytes, anything bigger means we are not actually looking at an embedded block pointer
+        // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (BPE_PAYLOAD_SIZE)
+        if usize::from(physical_size_in_bytes) + 1 > payload.len() {
+            use crate::ansi_color::*;
+            if cfg!(feature = "debug") {
+                println!("{YELLOW}Warning{WHITE}: Embedded block pointer says it has a payload of {} bytes, which is more than the payload can hold!", usize::from(physical_size_in_bytes) + 1);
+            }
+            return None;
         }
 
         Some(EmbeddedBlockPointer {
@@ -728,17 +1733,56 @@ where
             logical_birth_txg,
             level: ((info >> 56) & 0b1_1111) as usize,
             typ: dmu::ObjType::from_value(((info >> 48) & 0b1111_1111) as usize)?,
-            embedded_data_type: dmu::ObjType::from_value(((info >> 40) & 0b1111_1111) as usize)?,
+            embedded_data_type: EmbeddedType::from_value(((info >> 40) & 0b1111_1111) as usize)?,
             compression_method: CompressionMethod::from_value(
                 ((info >> 32) & 0b0111_1111) as usize,
             )?,
-            physical_size_in_bytes: ((info >> 24) & 0xFF) as u8,
-            logical_size_in_bytes: ((info >> 0) & 0xFF_FF_FF) as u32,
+            physical_size_in_bytes,
+            logical_size_in_bytes,
         })
     }
 }
 
+impl<It> FromBytesLE<It> for EmbeddedBlockPointer
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<EmbeddedBlockPointer> {
+        let data = byte_iter::read_array::<{ BlockPointer::get_ondisk_size() }>(data)?;
+        Self::from_slice_le(&data)
+    }
+}
+
 impl EmbeddedBlockPointer {
+    // The inverse of from_bytes_le, the payload is split around the info word and the logical birth txg like on disk
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L265
+    pub fn to_bytes_le(&self) -> [u8; BlockPointer::get_ondisk_size()] {
+        let mut res = [0u8; BlockPointer::get_ondisk_size()];
+        let mut payload = self.payload.iter().copied();
+        for (byte, value) in res[0..48].iter_mut().zip(&mut payload) {
+            *byte = value;
+        }
+
+        let info = (1u64 << 63) // little endian
+            | ((self.level as u64 & 0b1_1111) << 56)
+            | ((self.typ as u64 & 0b1111_1111) << 48)
+            | ((self.embedded_data_type as u64 & 0b1111_1111) << 40)
+            | (1 << 39) // embedded
+            | ((self.compression_method as u64 & 0b0111_1111) << 32)
+            | ((u64::from(self.physical_size_in_bytes) & 0b111_1111) << 25)
+            | (u64::from(self.logical_size_in_bytes) & ((1 << 25) - 1));
+        res[48..56].copy_from_slice(&info.to_le_bytes());
+
+        for (byte, value) in res[56..80].iter_mut().zip(&mut payload) {
+            *byte = value;
+        }
+        res[80..88].copy_from_slice(&self.logical_birth_txg.to_le_bytes());
+        for (byte, value) in res[88..128].iter_mut().zip(&mut payload) {
+            *byte = value;
+        }
+        res
+    }
+
     // Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h#L333
     // And: https://github.com/openzfs/zfs/blob/master/include/sys/bitops.h#L66
     pub fn parse_logical_size(&self) -> u64 {
@@ -751,27 +1795,53 @@ impl EmbeddedBlockPointer {
         u64::from(self.physical_size_in_bytes) + 1
     }
 
-    pub fn dereference(&mut self) -> Result<Vec<u8>, ()> {
-        let mut data = self.payload.clone();
+    pub fn get_level(&self) -> usize {
+        self.level
+    }
 
-        if data.len() as u64 > self.parse_physical_size() {
-            data.resize(usize::try_from(self.parse_physical_size()).unwrap(), 0);
-        }
+    pub fn get_logical_birth_txg(&self) -> u64 {
+        self.logical_birth_txg
+    }
 
-        let Ok(data) = try_decompress_block(&data, self.compression_method, usize::try_from(self.parse_logical_size()).unwrap()) else {
-            return Err(());
-        };
+    pub fn get_type(&self) -> dmu::ObjType {
+        self.typ
+    }
 
-        if data.len() as u64 != self.parse_logical_size() {
-            use crate::ansi_color::*;
-            if cfg!(feature = "debug") {
-                println!("{YELLOW}Warning{WHITE}: Embedded block pointer doesn't contain as much data as it says it should, i refuse to return it's data!");
+    pub fn get_embedded_data_type(&self) -> EmbeddedType {
+        self.embedded_data_type
+    }
+
+    pub fn get_compression_method(&self) -> CompressionMethod {
+        self.compression_method
+    }
+
+    pub fn dereference(&mut self) -> Result<Vec<u8>, ()> {
+        match self.embedded_data_type {
+            EmbeddedType::Data => (),
+            // A redacted block's data was never sent, so there is nothing to return
+            // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/dmu_redact.c
+            EmbeddedType::Redacted => return Err(()),
+            EmbeddedType::Reserved => {
+                use crate::ansi_color::*;
+                warnings::count_warning(WarningKind::UnsupportedFeature);
+                if cfg!(feature = "debug") {
+                    println!("{YELLOW}Warning{WHITE}: Embedded block pointer has reserved embedded type, i don't know how to read it!");
+                }
+                return Err(());
             }
+        }
 
-            return Err(());
+        let mut data = self.payload.clone();
+
+        if data.len() as u64 > self.parse_physical_size() {
+            data.resize(usize::try_from(self.parse_physical_size()).unwrap(), 0);
         }
 
-        Ok(data)
+        decompress_and_check_size(
+            &data,
+            self.compression_method,
+            usize::try_from(self.parse_logical_size()).unwrap(),
+        )
     }
 }
 
@@ -781,28 +1851,42 @@ pub enum BlockPointer {
     Embedded(EmbeddedBlockPointer),
 }
 
-impl<It> FromBytesLE<It> for BlockPointer
-where
-    It: Iterator<Item = u8> + Clone,
-{
-    fn from_bytes_le(data: &mut It) -> Option<BlockPointer> {
-        let info = Self::get_info_form_bytes_le(data.clone())?;
+impl FromSliceLE for BlockPointer {
+    fn from_slice_le(data: &[u8]) -> Option<BlockPointer> {
+        let info = read_le_at::<u64>(data, 6 * core::mem::size_of::<u64>())?;
         let is_embedded = ((info >> 39) & 1) != 0;
         if is_embedded {
-            Some(BlockPointer::Embedded(EmbeddedBlockPointer::from_bytes_le(
+            Some(BlockPointer::Embedded(EmbeddedBlockPointer::from_slice_le(
                 data,
             )?))
         } else {
-            Some(Self::Normal(NormalBlockPointer::from_bytes_le(data)?))
+            Some(Self::Normal(NormalBlockPointer::from_slice_le(data)?))
         }
     }
 }
 
+impl<It> FromBytesLE<It> for BlockPointer
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<BlockPointer> {
+        let data = byte_iter::read_array::<{ BlockPointer::get_ondisk_size() }>(data)?;
+        Self::from_slice_le(&data)
+    }
+}
+
 impl BlockPointer {
     pub const fn get_ondisk_size() -> usize {
         128
     }
 
+    pub fn to_bytes_le(&self) -> [u8; BlockPointer::get_ondisk_size()] {
+        match self {
+            BlockPointer::Normal(block_pointer) => block_pointer.to_bytes_le(),
+            BlockPointer::Embedded(block_pointer) => block_pointer.to_bytes_le(),
+        }
+    }
+
     pub fn get_info_form_bytes_le(mut data: impl Iterator<Item = u8>) -> Option<u64> {
         data.skip_n_bytes(6 * core::mem::size_of::<u64>())?;
         u64::from_bytes_le(&mut data)
@@ -824,10 +1908,53 @@ impl BlockPointer {
         }
     }
 
+    pub fn get_level(&self) -> usize {
+        match self {
+            BlockPointer::Normal(block_pointer) => block_pointer.get_level(),
+            BlockPointer::Embedded(block_pointer) => block_pointer.get_level(),
+        }
+    }
+
+    pub fn get_type(&self) -> dmu::ObjType {
+        match self {
+            BlockPointer::Normal(block_pointer) => block_pointer.get_type(),
+            BlockPointer::Embedded(block_pointer) => block_pointer.get_type(),
+        }
+    }
+
+    pub fn get_logical_birth_txg(&self) -> u64 {
+        match self {
+            BlockPointer::Normal(block_pointer) => block_pointer.get_logical_birth_txg(),
+            BlockPointer::Embedded(block_pointer) => block_pointer.get_logical_birth_txg(),
+        }
+    }
+
+    // A redacted block was left out of the send stream this dataset was received from, so its data was never on this pool
+    pub fn is_redacted(&self) -> bool {
+        match self {
+            BlockPointer::Normal(_) => false,
+            BlockPointer::Embedded(block_pointer) => {
+                block_pointer.get_embedded_data_type() == EmbeddedType::Redacted
+            }
+        }
+    }
+
     pub fn dereference(&mut self, vdevs: &mut Vdevs) -> Result<Vec<u8>, ()> {
         match self {
             BlockPointer::Normal(block_poiner) => block_poiner.dereference(vdevs),
             BlockPointer::Embedded(block_pointer) => block_pointer.dereference(),
         }
     }
+
+    pub fn dereference_with_source(
+        &mut self,
+        vdevs: &mut Vdevs,
+    ) -> Result<(Vec<u8>, BlockSource), ()> {
+        match self {
+            BlockPointer::Normal(block_pointer) => block_pointer.dereference_with_source(vdevs),
+            BlockPointer::Embedded(block_pointer) => block_pointer
+                .dereference()
+                .map(|data| (data, BlockSource::Embedded)),
+        }
+    }
 }
@@ -0,0 +1,38 @@
+// Zero length encoding, only runs of zeros are compressed
+// Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zle.c
+
+// Every compression level that zfs uses, and the dedup tables, use 64
+pub const DEFAULT_N: usize = 64;
+
+// A byte `b` is followed by b + 1 literal bytes if b + 1 <= n, otherwise it stands for b + 1 - n zeros
+pub fn zle_decompress(
+    data: &mut impl Iterator<Item = u8>,
+    output_length: usize,
+    n: usize,
+) -> Result<Vec<u8>, ()> {
+    let mut output_buf = Vec::with_capacity(output_length);
+    while output_buf.len() < output_length {
+        let Some(len) = data.next() else {
+            break;
+        };
+        let len = usize::from(len) + 1;
+        let is_literal = len <= n;
+        let output_len = if is_literal { len } else { len - n };
+        if output_buf.len() + output_len > output_length {
+            return Err(());
+        }
+
+        if is_literal {
+            for _ in 0..len {
+                output_buf.push(data.next().ok_or(())?);
+            }
+        } else {
+            output_buf.resize(output_buf.len() + output_len, 0);
+        }
+    }
+
+    if output_buf.len() != output_length {
+        return Err(());
+    }
+    Ok(output_buf)
+}
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use crate::{
     byte_iter::{ByteIter, FromBytesLE},
-    dmu::{DNode, ObjSet},
+    dmu::{BonusType, DNode, ObjSet},
     zap,
     zio::Vdevs,
     zpl,
@@ -81,6 +81,8 @@ impl SystemAttributesHeader {
 pub enum Value {
     U64(u64),
     U64Array(Vec<u64>),
+    // Variable sized attributes, ex. the target of a symlink
+    Bytes(Vec<u8>),
 }
 
 impl Debug for Value {
@@ -88,6 +90,7 @@ impl Debug for Value {
         match self {
             Self::U64(arg0) => write!(f, "{:?}", arg0),
             Self::U64Array(arg0) => write!(f, "{:?}", arg0),
+            Self::Bytes(arg0) => write!(f, "{:?}", String::from_utf8_lossy(arg0)),
         }
     }
 }
@@ -108,6 +111,25 @@ impl Debug for SystemAttribute {
     }
 }
 
+// Why the system attributes of a dataset or a file couldn't be read
+#[derive(Debug)]
+pub enum SystemAttributesError {
+    // One of the dnodes or zaps that describe the system attributes couldn't be read
+    Unreadable(&'static str),
+    // One of the dnodes or zap entries that describe the system attributes is not what it should be
+    Invalid(&'static str),
+    // The bonus buffer has a bad header or ended before all the attributes were read
+    BadHeader,
+    Truncated,
+    // The bonus buffer uses a layout or an attribute that isn't registered
+    UnknownLayout(u16),
+    UnknownAttribute(u16),
+    // A fixed size attribute was registered as having a variable size
+    UnexpectedVariableSize(String),
+    // The bonus buffer doesn't have zpl attributes, ex. the dnode isn't a file or a directory
+    WrongBonusType,
+}
+
 #[derive(Debug)]
 pub struct SystemAttributes {
     layouts: HashMap<usize, Vec<u16>>,
@@ -119,41 +141,60 @@ impl SystemAttributes {
         system_attributes_info_number: usize,
         dataset_object_set: &mut ObjSet,
         vdevs: &mut Vdevs,
-    ) -> Option<SystemAttributes> {
+    ) -> Result<SystemAttributes, SystemAttributesError> {
         use crate::ansi_color::*;
 
-        let DNode::SystemAttributesMasterNode(mut sa_info) = dataset_object_set.get_dnode_at(system_attributes_info_number, vdevs)? else {
+        let Some(sa_info) = dataset_object_set.get_dnode_at(system_attributes_info_number, vdevs)
+        else {
+            return Err(SystemAttributesError::Unreadable("master node"));
+        };
+        let DNode::SystemAttributesMasterNode(mut sa_info) = sa_info else {
             println!("{YELLOW}Warning{WHITE}: System attributes master node is of the wrong type!");
-            return None;
+            return Err(SystemAttributesError::Invalid("master node"));
         };
 
-        let sa_info_zap_data = sa_info.dump_zap_contents(vdevs)?;
+        let sa_info_zap_data = sa_info
+            .dump_zap_contents(vdevs)
+            .ok_or(SystemAttributesError::Unreadable("master node zap"))?;
         println!(
             "{CYAN}Info{WHITE}: System attributes master node zap: {:?}",
             sa_info_zap_data
         );
 
         let mut system_attributes_layouts_zap_data = {
-            let zap::Value::U64(system_attributes_layouts_number) = sa_info_zap_data["LAYOUTS"] else {
+            let Some(zap::Value::U64(system_attributes_layouts_number)) =
+                sa_info_zap_data.get("LAYOUTS")
+            else {
                 println!("{YELLOW}Warning{WHITE}: System attributes layouts node number is not a number!");
-                return None;
+                return Err(SystemAttributesError::Invalid("LAYOUTS entry"));
             };
 
-            let DNode::SystemAttributesLayouts(mut system_attributes_layouts) = dataset_object_set.get_dnode_at(system_attributes_layouts_number as usize, vdevs)? else {
-                println!("{YELLOW}Warning{WHITE}: System attributes layouts node is of the wrong type!");
-                return None;
+            let Some(system_attributes_layouts) =
+                dataset_object_set.get_dnode_at(*system_attributes_layouts_number as usize, vdevs)
+            else {
+                return Err(SystemAttributesError::Unreadable("layouts node"));
+            };
+            let DNode::SystemAttributesLayouts(mut system_attributes_layouts) =
+                system_attributes_layouts
+            else {
+                println!(
+                    "{YELLOW}Warning{WHITE}: System attributes layouts node is of the wrong type!"
+                );
+                return Err(SystemAttributesError::Invalid("layouts node"));
             };
 
-            system_attributes_layouts
-                .dump_zap_contents(vdevs)?
-                .into_iter()
-                .map(|(key, value)| {
-                    let zap::Value::U16Array(value) = value else {
-                    panic!("Layout is not of the right type (a u16 array) in the zap data!");
+            let mut layouts = HashMap::<usize, Vec<u16>>::new();
+            for (key, value) in system_attributes_layouts
+                .dump_zap_contents(vdevs)
+                .ok_or(SystemAttributesError::Unreadable("layouts zap"))?
+            {
+                let (Ok(layout_id), zap::Value::U16Array(value)) = (str::parse(&key), value) else {
+                    println!("{YELLOW}Warning{WHITE}: Layout {key} is not of the right type (a u16 array) in the zap data, ignoring it!");
+                    continue;
                 };
-                    (str::parse(&key).unwrap(), value)
-                })
-                .collect::<HashMap<usize, Vec<u16>>>()
+                layouts.insert(layout_id, value);
+            }
+            layouts
         };
 
         // Legacy layout
@@ -163,30 +204,48 @@ impl SystemAttributes {
         );
 
         let system_attributes_registrations = {
-            let zap::Value::U64(system_attributes_registrations_number) = sa_info_zap_data["REGISTRY"] else {
-                panic!("System attributes registrations node number is not a number!");
+            let Some(zap::Value::U64(system_attributes_registrations_number)) =
+                sa_info_zap_data.get("REGISTRY")
+            else {
+                println!("{YELLOW}Warning{WHITE}: System attributes registrations node number is not a number!");
+                return Err(SystemAttributesError::Invalid("REGISTRY entry"));
             };
 
-            let DNode::SystemAttributesRegistrations(mut system_attributes_registrations) = dataset_object_set.get_dnode_at(system_attributes_registrations_number as usize, vdevs).unwrap() else {
-                panic!("System attributes registrations node is of the wrong type!");
+            let Some(system_attributes_registrations) = dataset_object_set
+                .get_dnode_at(*system_attributes_registrations_number as usize, vdevs)
+            else {
+                return Err(SystemAttributesError::Unreadable("registrations node"));
+            };
+            let DNode::SystemAttributesRegistrations(mut system_attributes_registrations) =
+                system_attributes_registrations
+            else {
+                println!("{YELLOW}Warning{WHITE}: System attributes registrations node is of the wrong type!");
+                return Err(SystemAttributesError::Invalid("registrations node"));
             };
 
-            system_attributes_registrations
-            .dump_zap_contents(vdevs)?
-            .into_iter()
-            .map(|(key, value)| {
-                let zap::Value::U64(val) = value else { panic!("System attributes registration is invalid!"); };
+            let mut registrations = HashMap::<u16, SystemAttribute>::new();
+            for (key, value) in system_attributes_registrations
+                .dump_zap_contents(vdevs)
+                .ok_or(SystemAttributesError::Unreadable("registrations zap"))?
+            {
+                let zap::Value::U64(val) = value else {
+                    println!("{YELLOW}Warning{WHITE}: System attributes registration {key} is invalid, ignoring it!");
+                    continue;
+                };
                 let registration = zpl::SystemAttributesRegistration::from_value(val);
-                (registration.attribute_id, SystemAttribute{
-                    name: key,
-                    byteswap_function: registration.bswap,
-                    len: registration.len,
-                })
-            })
-            .collect::<HashMap<u16, SystemAttribute>>()
+                registrations.insert(
+                    registration.attribute_id,
+                    SystemAttribute {
+                        name: key,
+                        byteswap_function: registration.bswap,
+                        len: registration.len,
+                    },
+                );
+            }
+            registrations
         };
 
-        Some(SystemAttributes {
+        Ok(SystemAttributes {
             layouts: system_attributes_layouts_zap_data,
             attributes: system_attributes_registrations,
         })
@@ -195,14 +254,25 @@ impl SystemAttributes {
     pub fn parse_system_attributes_bytes_le(
         &mut self,
         data: &mut impl Iterator<Item = u8>,
-    ) -> Option<HashMap<String, Value>> {
-        let system_attributes_header = zpl::SystemAttributesHeader::from_bytes_le(data)?;
-        let layout = &self.layouts[&system_attributes_header.layout_id.into()];
+    ) -> Result<HashMap<String, Value>, SystemAttributesError> {
+        let system_attributes_header = zpl::SystemAttributesHeader::from_bytes_le(data)
+            .ok_or(SystemAttributesError::BadHeader)?;
+        let layout = self
+            .layouts
+            .get(&system_attributes_header.layout_id.into())
+            .ok_or(SystemAttributesError::UnknownLayout(
+                system_attributes_header.layout_id,
+            ))?;
+        // The sizes of the variable sized attributes are in the header, in the same order as the attributes
+        let mut variable_lengths = system_attributes_header.lengths.iter();
         let mut attributes: HashMap<String, Value> = HashMap::new();
 
         use crate::ansi_color::*;
-        for (attribute_index, attribute_id) in layout.iter().enumerate() {
-            let attribute_info = &self.attributes[attribute_id];
+        for attribute_id in layout.iter() {
+            let attribute_info = self
+                .attributes
+                .get(attribute_id)
+                .ok_or(SystemAttributesError::UnknownAttribute(*attribute_id))?;
             match attribute_info.name.as_str() {
                 // All of these are u64 array or single u64 system attributes with known sizes
                 "ZPL_ATIME" | "ZPL_MTIME" | "ZPL_CTIME" | "ZPL_CRTIME" | "ZPL_GEN" | "ZPL_MODE"
@@ -210,27 +280,30 @@ impl SystemAttributes {
                 | "ZPL_FLAGS" | "ZPL_UID" | "ZPL_GID" | "ZPL_PAD" | "ZPL_DACL_COUNT"
                 | "ZPL_PROJID" => {
                     if attribute_info.len == 0 {
-                        panic!("System Attribute \"{}\" does not have a variable size according to the zfs source code (the scond column contains the size of the attribute in bytes, it's 0 for variable size): (https://github.com/openzfs/zfs/blob/master/module/zfs/zfs_sa.c#L34), but was read from disk as having a variable size!", attribute_info.name);
+                        // These don't have a variable size according to the zfs source code (the scond column contains the size of the attribute in bytes, it's 0 for variable size)
+                        // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zfs_sa.c#L34
+                        return Err(SystemAttributesError::UnexpectedVariableSize(
+                            attribute_info.name.clone(),
+                        ));
                     }
                     if attribute_info.byteswap_function != 0 {
                         println!("{YELLOW}Warning{WHITE}: Unsupported byte swap function on attribute \"{}\", ignoring!", attribute_info.name);
-                        // NOTE: If it's the last attribute, even if we don't know how much to skip, it doesn't matter
-                        if attribute_info.len == 0 && attribute_index != layout.len() - 1 {
-                            panic!("Unsupported system attribute \"{}\" has variable size, can't ignore it if we don't know how much to ignore!", attribute_info.name);
-                        }
-
-                        data.skip_n_bytes(attribute_info.len as usize)?;
+                        data.skip_n_bytes(attribute_info.len as usize)
+                            .ok_or(SystemAttributesError::Truncated)?;
                         continue;
                     }
 
                     let nvalues = attribute_info.len / 8;
                     if nvalues == 1 {
-                        let attribute_value = u64::from_bytes_le(data)?;
+                        let attribute_value =
+                            u64::from_bytes_le(data).ok_or(SystemAttributesError::Truncated)?;
                         attributes.insert(attribute_info.name.clone(), Value::U64(attribute_value));
                     } else {
                         let mut attribute_values = Vec::<u64>::new();
                         for _ in 0..nvalues {
-                            attribute_values.push(u64::from_bytes_le(data)?);
+                            attribute_values.push(
+                                u64::from_bytes_le(data).ok_or(SystemAttributesError::Truncated)?,
+                            );
                         }
                         attributes.insert(
                             attribute_info.name.clone(),
@@ -239,20 +312,409 @@ impl SystemAttributes {
                     }
                 }
 
+                // The target of a symlink, it's stored without a nul terminator
+                // Source: https://github.com/openzfs/zfs/blob/master/module/os/linux/zfs/zfs_vnops_os.c (zfs_symlink, SA_ZPL_SYMLINK)
+                "ZPL_SYMLINK" => {
+                    let len = if attribute_info.len == 0 {
+                        *variable_lengths
+                            .next()
+                            .ok_or(SystemAttributesError::Truncated)?
+                    } else {
+                        attribute_info.len
+                    };
+                    let mut target = Vec::with_capacity(usize::from(len));
+                    for _ in 0..len {
+                        target.push(data.next().ok_or(SystemAttributesError::Truncated)?);
+                    }
+                    // The padding up to a multiple of 8 bytes, if this isn't the last attribute
+                    let _ = data.skip_n_bytes(usize::from(len).next_multiple_of(8) - usize::from(len));
+                    attributes.insert(attribute_info.name.clone(), Value::Bytes(target));
+                }
+
                 _ => {
                     println!(
                         "{YELLOW}Warning{WHITE}: Unsupported system attribute \"{}\", ignoring!",
                         attribute_info.name
                     );
-                    // NOTE: If it's the last attribute, even if we don't know how much to skip, it doesn't matter
-                    if attribute_info.len == 0 && attribute_index != layout.len() - 1 {
-                        panic!("Unsupported system attribute \"{}\" has variable size, can't ignore it if we don't know how much to ignore!", attribute_info.name);
-                    }
-                    data.skip_n_bytes(attribute_info.len as usize)?;
+                    let len = if attribute_info.len == 0 {
+                        // If the header doesn't have enough lengths this must be the last attribute, so we don't need to skip it
+                        let Some(len) = variable_lengths.next() else {
+                            continue;
+                        };
+                        *len
+                    } else {
+                        attribute_info.len
+                    };
+                    // Every attribute starts at a multiple of 8 bytes
+                    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/sa.c (sa_attr_iter)
+                    data.skip_n_bytes(usize::from(len).next_multiple_of(8))
+                        .ok_or(SystemAttributesError::Truncated)?;
                 }
             }
         }
 
-        Some(attributes)
+        Ok(attributes)
+    }
+}
+
+// Before system attributes (pool version 24, zpl version 5) the attributes of a file were this fixed struct in its bonus buffer
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h (znode_phys_t, ZFS_OLD_ZNODE_PHYS_SIZE)
+pub const OLD_ZNODE_PHYS_SIZE: usize = 0x108;
+
+// The file type bits of the mode of a symlink
+// Source: https://github.com/openzfs/zfs/blob/master/include/os/linux/spl/sys/stat.h
+const S_IFMT: u64 = 0o170000;
+const S_IFLNK: u64 = 0o120000;
+
+// NOTE: The timestamps are [seconds, nanoseconds] like the system attributes, the acl after the attributes is not read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Znode {
+    pub atime: [u64; 2],
+    pub mtime: [u64; 2],
+    pub ctime: [u64; 2],
+    pub crtime: [u64; 2],
+    pub gen: u64,
+    pub mode: u64,
+    pub size: u64,
+    pub parent: u64,
+    pub links: u64,
+    pub xattr: u64,
+    pub rdev: u64,
+    pub flags: u64,
+    pub uid: u64,
+    pub gid: u64,
+}
+
+impl<It> FromBytesLE<It> for Znode
+where
+    It: Iterator<Item = u8>,
+{
+    fn from_bytes_le(data: &mut It) -> Option<Znode> {
+        let read_timestamp =
+            |data: &mut It| Some([u64::from_bytes_le(data)?, u64::from_bytes_le(data)?]);
+        Some(Znode {
+            atime: read_timestamp(data)?,
+            mtime: read_timestamp(data)?,
+            ctime: read_timestamp(data)?,
+            crtime: read_timestamp(data)?,
+            gen: u64::from_bytes_le(data)?,
+            mode: u64::from_bytes_le(data)?,
+            size: u64::from_bytes_le(data)?,
+            parent: u64::from_bytes_le(data)?,
+            links: u64::from_bytes_le(data)?,
+            xattr: u64::from_bytes_le(data)?,
+            rdev: u64::from_bytes_le(data)?,
+            flags: u64::from_bytes_le(data)?,
+            uid: u64::from_bytes_le(data)?,
+            gid: u64::from_bytes_le(data)?,
+        })
+    }
+}
+
+impl Znode {
+    // Returns: None if the bonus buffer is too small to be a znode
+    pub fn from_bonus_data(bonus: &[u8]) -> Option<Znode> {
+        if bonus.len() < OLD_ZNODE_PHYS_SIZE {
+            return None;
+        }
+        Znode::from_bytes_le(&mut bonus.iter().copied())
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+
+    // Short symlink targets are right after the znode in the bonus buffer, long ones are in the data of the symlink
+    // Returns: None if the target isn't in the bonus buffer
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zfs_sa.c (zfs_sa_readlink)
+    pub fn get_symlink_target<'a>(&self, bonus: &'a [u8]) -> Option<&'a [u8]> {
+        if !self.is_symlink() {
+            return None;
+        }
+        let size = usize::try_from(self.size).ok()?;
+        bonus.get(OLD_ZNODE_PHYS_SIZE..OLD_ZNODE_PHYS_SIZE.checked_add(size)?)
+    }
+
+    // Returns: The attributes by the names of the system attributes, so everything that reads them doesn't have to care which kind a file has
+    pub fn to_attributes(&self) -> HashMap<String, Value> {
+        let mut attributes: HashMap<String, Value> = HashMap::new();
+        for (name, timestamp) in [
+            ("ZPL_ATIME", self.atime),
+            ("ZPL_MTIME", self.mtime),
+            ("ZPL_CTIME", self.ctime),
+            ("ZPL_CRTIME", self.crtime),
+        ] {
+            attributes.insert(String::from(name), Value::U64Array(timestamp.to_vec()));
+        }
+        for (name, value) in [
+            ("ZPL_GEN", self.gen),
+            ("ZPL_MODE", self.mode),
+            ("ZPL_SIZE", self.size),
+            ("ZPL_PARENT", self.parent),
+            ("ZPL_LINKS", self.links),
+            ("ZPL_XATTR", self.xattr),
+            ("ZPL_RDEV", self.rdev),
+            ("ZPL_FLAGS", self.flags),
+            ("ZPL_UID", self.uid),
+            ("ZPL_GID", self.gid),
+        ] {
+            attributes.insert(String::from(name), Value::U64(value));
+        }
+        attributes
+    }
+}
+
+// Same as SystemAttributes::parse_system_attributes_bytes_le, for the bonus buffers of files from before system attributes
+// NOTE: ZPL_SYMLINK is only there if the target is in the bonus buffer
+pub fn parse_old_znode_bytes_le(
+    bonus: &[u8],
+) -> Result<HashMap<String, Value>, SystemAttributesError> {
+    let znode = Znode::from_bonus_data(bonus).ok_or(SystemAttributesError::Truncated)?;
+    let mut attributes = znode.to_attributes();
+    if let Some(target) = znode.get_symlink_target(bonus) {
+        attributes.insert(String::from("ZPL_SYMLINK"), Value::Bytes(target.to_vec()));
+    }
+    Ok(attributes)
+}
+
+// Reads the zpl attributes of a file or directory, whichever kind of bonus buffer it has
+// system_attributes is the registry of the dataset, it's only needed for system attribute bonus buffers, datasets from before them don't have one
+pub fn parse_zpl_attributes(
+    system_attributes: Option<&mut SystemAttributes>,
+    bonus: &[u8],
+    bonus_type: &BonusType,
+) -> Result<HashMap<String, Value>, SystemAttributesError> {
+    match bonus_type {
+        BonusType::SystemAttributes => system_attributes
+            .ok_or(SystemAttributesError::Unreadable("attribute registry"))?
+            .parse_system_attributes_bytes_le(&mut bonus.iter().copied()),
+        BonusType::ZNode => parse_old_znode_bytes_le(bonus),
+        _ => Err(SystemAttributesError::WrongBonusType),
     }
 }
+
+// The version of the zpl (the layer that makes files and directories out of objects) a dataset was made with, it's the VERSION entry of the master node
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h (ZPL_VERSION_*)
+pub const ZPL_VERSION_FUID: u64 = 3;
+pub const ZPL_VERSION_USERSPACE: u64 = 4;
+// Datasets made with an older version don't have SA_ATTRS, their files have the old znode attributes
+pub const ZPL_VERSION_SA: u64 = 5;
+
+// Returns: None if the master node has no version, every filesystem has one so the master node is probably damaged
+pub fn get_zpl_version(master_node_zap_data: &HashMap<String, zap::Value>) -> Option<u64> {
+    match master_node_zap_data.get("VERSION") {
+        Some(zap::Value::U64(version)) => Some(*version),
+        _ => None,
+    }
+}
+
+// Returns: The attribute registry of the dataset, None if it doesn't have one because it's from before system attributes
+// NOTE: Files with the old znode attributes can be read without it, see parse_zpl_attributes, so callers can go on without it when it's Err too
+pub fn open_system_attributes(
+    master_node_zap_data: &HashMap<String, zap::Value>,
+    dataset_object_set: &mut ObjSet,
+    vdevs: &mut Vdevs,
+) -> Result<Option<SystemAttributes>, SystemAttributesError> {
+    match master_node_zap_data.get("SA_ATTRS") {
+        Some(zap::Value::U64(system_attributes_info_number)) => {
+            SystemAttributes::from_attributes_node_number(
+                *system_attributes_info_number as usize,
+                dataset_object_set,
+                vdevs,
+            )
+            .map(Some)
+        }
+        Some(_) => Err(SystemAttributesError::Invalid("SA_ATTRS entry")),
+        None => Ok(None),
+    }
+}
+
+// How names in the directories of a dataset are compared, set when the dataset is created and can't be changed after that
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/fs/zfs.h (zfs_case_t)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseSensitivity {
+    Sensitive,
+    Insensitive,
+    // Case insensitive lookups are only done when asked for (ex. by SMB), normal lookups are case sensitive
+    Mixed,
+}
+
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/u8_textprep.h (U8_TEXTPREP_NFD, U8_TEXTPREP_NFKD, ...)
+const NORMALIZATION_COMPATIBILITY: u64 = 0x20;
+
+#[derive(Debug, Clone, Copy)]
+pub struct NameMatching {
+    pub case_sensitivity: CaseSensitivity,
+    // 0 means names aren't normalized, otherwise it's one of the U8_TEXTPREP_NF* flags
+    pub normalization: u64,
+    pub utf8_only: bool,
+}
+
+impl NameMatching {
+    // These live in the master node, not in the dsl props zap, missing ones have their default value
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zfs_vfsops.c (zfs_get_zplprop)
+    pub fn from_master_node(master_node_zap_data: &HashMap<String, zap::Value>) -> NameMatching {
+        let get = |name: &str| match master_node_zap_data.get(name) {
+            Some(zap::Value::U64(value)) => *value,
+            _ => 0,
+        };
+        NameMatching {
+            case_sensitivity: match get("casesensitivity") {
+                1 => CaseSensitivity::Insensitive,
+                2 => CaseSensitivity::Mixed,
+                _ => CaseSensitivity::Sensitive,
+            },
+            normalization: get("normalization"),
+            utf8_only: get("utf8only") != 0,
+        }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        self.case_sensitivity != CaseSensitivity::Insensitive && self.normalization == 0
+    }
+
+    // Returns: The form of the name that is compared, two names that give the same result refer to the same entry
+    // Like zfs the case is folded to upper case first, then the name is normalized
+    // NOTE: Composed and decomposed forms compare the same, so NFC is done as NFD and NFKC as NFKD
+    // NOTE: zfs uses its own unicode 3.2 tables, names with characters added later might compare differently
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zap_micro.c (zap_normalize)
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/zfs_vfsops.c (zfs_init_fs, U8_TEXTPREP_TOUPPER)
+    pub fn normalize_name(&self, name: &str) -> String {
+        use unicode_normalization::UnicodeNormalization;
+        let name = if self.case_sensitivity == CaseSensitivity::Insensitive {
+            name.to_uppercase()
+        } else {
+            String::from(name)
+        };
+        if self.normalization == 0 {
+            name
+        } else if self.normalization & NORMALIZATION_COMPATIBILITY != 0 {
+            name.as_str().nfkd().collect()
+        } else {
+            name.as_str().nfd().collect()
+        }
+    }
+
+    // Returns: The object id of the entry called `name` in the directory, if there is one
+    // An exact match is always preferred, so if a mixed or badly normalized directory has several matching entries the right one is used
+    pub fn lookup_directory_entry(
+        &self,
+        directory_zap_data: &HashMap<String, zap::Value>,
+        name: &str,
+    ) -> Option<u64> {
+        let value = match directory_zap_data.get(name) {
+            Some(value) => value,
+            None if self.is_exact() => return None,
+            None => {
+                let name = self.normalize_name(name);
+                directory_zap_data
+                    .iter()
+                    .find(|(entry_name, _)| self.normalize_name(entry_name) == name)?
+                    .1
+            }
+        };
+        let zap::Value::U64(value) = value else {
+            return None;
+        };
+        // Only bottom 48 bits are the actual object id
+        // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h#L152
+        Some(value & ((1 << 48) - 1))
+    }
+}
+
+// Returns: The object id of the file or directory at `path` (relative to the root of the dataset), looked up like the dataset would
+pub fn lookup_path(dataset_object_set: &mut ObjSet, path: &str, vdevs: &mut Vdevs) -> Option<u64> {
+    // Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h (MASTER_NODE_OBJ)
+    let DNode::MasterNode(mut master_node) = dataset_object_set.get_dnode_at(1, vdevs)? else {
+        return None;
+    };
+    let master_node_zap_data = master_node.dump_zap_contents(vdevs)?;
+    let matching = NameMatching::from_master_node(&master_node_zap_data);
+    let Some(zap::Value::U64(root_number)) = master_node_zap_data.get("ROOT") else {
+        return None;
+    };
+
+    let mut object_id = *root_number;
+
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        let DNode::DirectoryContents(mut directory) =
+            dataset_object_set.get_dnode_at(object_id as usize, vdevs)?
+        else {
+            return None;
+        };
+        let directory_zap_data = directory.dump_zap_contents(vdevs)?;
+        object_id = matching.lookup_directory_entry(&directory_zap_data, component)?;
+    }
+    Some(object_id)
+}
+
+// The type of a directory entry, it's in the top 4 bits of the entry (the bits lookup_directory_entry masks out), as the file type bits of the mode shifted down
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h (ZFS_DIRENT_TYPE, IFTODT)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectoryEntryKind {
+    Fifo,
+    CharacterDevice,
+    Directory,
+    BlockDevice,
+    File,
+    Symlink,
+    Socket,
+    // Entries written by old versions don't have a type
+    Unknown,
+}
+
+impl DirectoryEntryKind {
+    pub fn from_value(value: u64) -> DirectoryEntryKind {
+        match value {
+            1 => DirectoryEntryKind::Fifo,
+            2 => DirectoryEntryKind::CharacterDevice,
+            4 => DirectoryEntryKind::Directory,
+            6 => DirectoryEntryKind::BlockDevice,
+            8 => DirectoryEntryKind::File,
+            10 => DirectoryEntryKind::Symlink,
+            12 => DirectoryEntryKind::Socket,
+            _ => DirectoryEntryKind::Unknown,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            DirectoryEntryKind::Fifo => "fifo",
+            DirectoryEntryKind::CharacterDevice => "character device",
+            DirectoryEntryKind::Directory => "directory",
+            DirectoryEntryKind::BlockDevice => "block device",
+            DirectoryEntryKind::File => "file",
+            DirectoryEntryKind::Symlink => "symlink",
+            DirectoryEntryKind::Socket => "socket",
+            DirectoryEntryKind::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryEntry {
+    pub name: String,
+    pub object_id: u64,
+    pub kind: DirectoryEntryKind,
+}
+
+// Returns: The entries of a directory, sorted by name, entries that aren't numbers are skipped
+pub fn parse_directory_entries(
+    directory_zap_data: &HashMap<String, zap::Value>,
+) -> Vec<DirectoryEntry> {
+    let mut entries = directory_zap_data
+        .iter()
+        .filter_map(|(name, value)| {
+            let zap::Value::U64(value) = value else {
+                return None;
+            };
+            Some(DirectoryEntry {
+                name: name.clone(),
+                object_id: value & ((1 << 48) - 1),
+                kind: DirectoryEntryKind::from_value(value >> 60),
+            })
+        })
+        .collect::<Vec<_>>();
+    entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+    entries
+}
@@ -0,0 +1,182 @@
+// Pools with 4K sectors (ashift 12), where dva offsets and sizes are still in 512 byte units but the raidz works in whole 4K sectors
+#![cfg(feature = "disk")]
+
+use std::fs::File;
+
+use szfs::{
+    byte_iter::FromBytesLE,
+    check_labels, cli, rewind,
+    test_image::{pattern, DvaSpec, ImageBuilder, ImageConfig},
+    zio::{DataVirtualAddress, Vdevs},
+    VdevFile, VdevLabel, UBERBLOCK_MAGIC, VDEV_LABEL_START_SIZE,
+};
+
+fn ashift_12_config(ndevices: usize, nparity: usize) -> ImageConfig {
+    ImageConfig {
+        ndevices,
+        nparity,
+        ashift: 12,
+        ..Default::default()
+    }
+}
+
+// Returns: The dva the way it's stored, packed by hand
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/spa.h (dva_t)
+fn raw_dva(vdev_id: u32, asize_in_512b_sectors: u64, offset_in_512b_sectors: u64) -> [u8; 16] {
+    let mut res = [0u8; 16];
+    let word0 = (u64::from(vdev_id) << 32) | asize_in_512b_sectors;
+    res[0..8].copy_from_slice(&word0.to_le_bytes());
+    res[8..16].copy_from_slice(&offset_in_512b_sectors.to_le_bytes());
+    res
+}
+
+#[test]
+fn dva_sizes_are_in_512_byte_units() {
+    // 3 sectors of 4K with 1 sector of parity, at an offset that is a multiple of 4K
+    let bytes = raw_dva(3, 32, 0x1234 * 8);
+    let dva = DataVirtualAddress::from_bytes_le(&mut bytes.iter().copied()).unwrap();
+    assert_eq!(dva.get_vdev_id(), 3);
+    assert_eq!(dva.parse_allocated_size(), 16 * 1024);
+    assert_eq!(dva.parse_offset(), 0x1234 * 4096);
+    assert!(!dva.is_gang());
+    assert_eq!(dva.to_bytes_le(), bytes);
+
+    // The biggest asize doesn't spill into the vdev id
+    let bytes = raw_dva(1, (1 << 24) - 8, 8);
+    let dva = DataVirtualAddress::from_bytes_le(&mut bytes.iter().copied()).unwrap();
+    assert_eq!(dva.get_vdev_id(), 1);
+    assert_eq!(dva.parse_allocated_size(), ((1 << 24) - 8) * 512);
+    assert_eq!(dva.parse_offset(), 4096);
+}
+
+#[test]
+fn uberblock_slots_are_one_sector_up_to_8k() {
+    assert_eq!(VdevLabel::get_uberblock_size_for_ashift(9), 1024);
+    assert_eq!(VdevLabel::get_uberblock_size_for_ashift(12), 4096);
+    assert_eq!(VdevLabel::get_uberblock_size_for_ashift(13), 8192);
+    assert_eq!(VdevLabel::get_uberblock_size_for_ashift(16), 8192);
+}
+
+#[test]
+fn uberblocks_are_found_with_4k_sectors() {
+    let config = ashift_12_config(3, 1);
+    let disks = ImageBuilder::new(config.clone()).build();
+
+    // The uberblock of txg 10 is in the slot 10 of the 32 4K slots, after the 128K of the config
+    let slot = 128 * 1024 + (config.txg as usize % 32) * 4096;
+    assert_eq!(disks[0][slot..slot + 8], UBERBLOCK_MAGIC.to_le_bytes());
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("disk0.img");
+    std::fs::write(&path, &disks[0]).unwrap();
+    let mut device = VdevFile::from(File::open(&path).unwrap());
+    for label in check_labels(&mut device) {
+        assert!(label.readable && label.has_checksum_magic);
+        assert_eq!(label.txg, Some(config.txg));
+    }
+    let uberblocks = rewind::collect_uberblocks(&mut device);
+    assert_eq!(uberblocks.len(), 1);
+    assert_eq!(uberblocks[0].txg, config.txg);
+}
+
+#[test]
+fn raidz_sectors_are_4k() {
+    let config = ashift_12_config(3, 1);
+    let mut builder = ImageBuilder::new(config.clone());
+    let data = pattern(2 * 4096, 1);
+    let dva = builder.write_raw(&data);
+    let disks = builder.build();
+
+    // 2 data sectors and a parity sector, one on every disk, parity first
+    // Source: https://github.com/openzfs/zfs/blob/master/module/zfs/vdev_raidz.c (vdev_raidz_map_alloc)
+    assert_eq!((dva.offset / (1024 * 1024)) % 2, 0);
+    assert_eq!(dva.allocated_size, 4 * 4096);
+    let first_sector = dva.offset / 4096;
+    let sector_bytes = |sector: u64| {
+        let device = (sector % 3) as usize;
+        let offset = VDEV_LABEL_START_SIZE as usize + (sector / 3) as usize * 4096;
+        &disks[device][offset..offset + 4096]
+    };
+    assert_eq!(sector_bytes(first_sector + 1), &data[..4096]);
+    assert_eq!(sector_bytes(first_sector + 2), &data[4096..]);
+    let parity = data[..4096]
+        .iter()
+        .zip(&data[4096..])
+        .map(|(a, b)| a ^ b)
+        .collect::<Vec<_>>();
+    assert_eq!(sector_bytes(first_sector), parity);
+}
+
+// Returns: What reading every dva through the raidz gives, and if its parity is right
+fn read_dvas(config: &ImageConfig, disks: &[Vec<u8>], dvas: &[(DvaSpec, usize)]) -> Vec<Vec<u8>> {
+    let dir = tempfile::tempdir().unwrap();
+    let mut devices = disks
+        .iter()
+        .enumerate()
+        .map(|(index, disk)| {
+            let path = dir.path().join(format!("disk{index}.img"));
+            std::fs::write(&path, disk).unwrap();
+            VdevFile::from(File::open(path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    let mut raidz = cli::make_raidz(&mut devices, config.nparity, config.get_sector_size());
+    for (dva, size) in dvas {
+        assert!(raidz
+            .verify_stripe(dva.offset, *size)
+            .unwrap()
+            .is_consistent());
+    }
+    let mut vdevs = Vdevs::new();
+    vdevs.insert(0usize, &mut raidz);
+    dvas.iter()
+        .map(|(dva, size)| {
+            let dva =
+                DataVirtualAddress::from_bytes_le(&mut dva.to_bytes_le().into_iter()).unwrap();
+            dva.dereference(&mut vdevs, *size).unwrap()
+        })
+        .collect()
+}
+
+#[test]
+fn raidz_reads_with_4k_sectors() {
+    for (ndevices, nparity) in [(3, 1), (4, 2), (5, 1), (6, 3)] {
+        let config = ashift_12_config(ndevices, nparity);
+        let mut builder = ImageBuilder::new(config.clone());
+        let mut blocks = Vec::new();
+        // Less than a sector, whole sectors and rows that aren't full, up to a few rows
+        let sizes = [512, 4096, 5120]
+            .into_iter()
+            .chain((2..=3 * ndevices).map(|nsectors| nsectors * 4096 - 1024));
+        for (index, size) in sizes.enumerate() {
+            let data = pattern(size, index as u8);
+            let dva = builder.write_raw(&data);
+            assert_eq!(dva.offset % 4096, 0);
+            assert_eq!(dva.allocated_size % 4096, 0);
+            blocks.push((dva, data));
+        }
+        // Past the first megabyte, where raidz1 switches the parity and the first data column
+        while builder.get_next_offset() < 1024 * 1024 {
+            builder.write_raw(&[0; 4096]);
+        }
+        for size in [4096, 3 * 4096] {
+            let data = pattern(size, 0xaa);
+            blocks.push((builder.write_raw(&data), data));
+        }
+        let disks = builder.build();
+
+        let dvas = blocks
+            .iter()
+            .map(|(dva, data)| (*dva, data.len()))
+            .collect::<Vec<_>>();
+        let read = read_dvas(&config, &disks, &dvas);
+        for ((dva, data), read) in blocks.iter().zip(read) {
+            assert_eq!(
+                &read,
+                data,
+                "raidz{nparity} with {ndevices} disks, {} bytes at {}",
+                data.len(),
+                dva.offset
+            );
+        }
+    }
+}
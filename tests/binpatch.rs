@@ -0,0 +1,172 @@
+// Patches written by PatchWriter have to apply the same bytes they were given, and a damaged patch must not write anything wrong
+use std::io::Cursor;
+
+use szfs::{
+    binpatch::{self, ApplyReport, PatchEntryError, PatchReader, PatchWriter, MAGIC, VERSION},
+    test_image::pattern,
+};
+
+fn write_patch(entries: &[(u64, Vec<u8>)]) -> Vec<u8> {
+    let mut writer = PatchWriter::new(Vec::new()).unwrap();
+    for (target_offset, data) in entries {
+        writer.write_entry(*target_offset, data).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+// Returns: The target after applying the patch to it, and the report
+fn apply(patch: Vec<u8>, target: Vec<u8>, dry_run: bool) -> (Vec<u8>, ApplyReport) {
+    let mut reader = PatchReader::new(Cursor::new(patch)).unwrap();
+    let mut target = Cursor::new(target);
+    let report = binpatch::apply_patch(&mut reader, &mut target, dry_run).unwrap();
+    (target.into_inner(), report)
+}
+
+fn test_entries() -> Vec<(u64, Vec<u8>)> {
+    vec![
+        (0, pattern(512, 1)),
+        (4096, pattern(1000, 2)),
+        // An empty entry is fine, it just doesn't write anything
+        (100, Vec::new()),
+        // Past the end of the target, it grows like any file written past its end
+        (10_000, pattern(3, 3)),
+    ]
+}
+
+#[test]
+fn crc32_matches_the_reference() {
+    // The check value of CRC-32
+    assert_eq!(binpatch::crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(binpatch::crc32(&[]), 0);
+}
+
+#[test]
+fn patch_round_trips() {
+    let entries = test_entries();
+    let patch = write_patch(&entries);
+    assert_eq!(patch[0..8], MAGIC);
+    assert_eq!(patch[8..12], VERSION.to_le_bytes());
+
+    let mut reader = PatchReader::new(Cursor::new(patch.clone())).unwrap();
+    assert_eq!(reader.get_version(), VERSION);
+    for (target_offset, data) in &entries {
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(entry.target_offset, *target_offset);
+        assert_eq!(&entry.data, data);
+    }
+    assert!(reader.next_entry().unwrap().is_none());
+
+    let (target, report) = apply(patch, vec![0xee; 8192], false);
+    let mut expected = vec![0xee; 8192];
+    expected[0..512].copy_from_slice(&entries[0].1);
+    expected[4096..5096].copy_from_slice(&entries[1].1);
+    expected.resize(10_000, 0);
+    expected.extend(&entries[3].1);
+    assert_eq!(target, expected);
+    assert_eq!(report.entries_applied, 4);
+    assert_eq!(report.bytes_written, 512 + 1000 + 3);
+    assert!(report.bad_entries.is_empty());
+    assert!(!report.truncated);
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let (target, report) = apply(write_patch(&test_entries()), vec![0xee; 8192], true);
+    assert_eq!(target, vec![0xee; 8192]);
+    assert_eq!(report.entries_applied, 4);
+}
+
+#[test]
+fn entry_with_bad_crc_is_skipped() {
+    let entries = test_entries();
+    let mut patch = write_patch(&entries);
+    // The first data byte of the second entry, after the header, the first entry and the offset, size and crc of the second one
+    let second_data = 12 + (20 + 512) + 20;
+    patch[second_data] ^= 1;
+
+    let mut reader = PatchReader::new(Cursor::new(patch.clone())).unwrap();
+    assert!(reader.next_entry().unwrap().is_some());
+    assert!(matches!(
+        reader.next_entry(),
+        Err(PatchEntryError::ChecksumMismatch {
+            target_offset: 4096,
+            size: 1000
+        })
+    ));
+    // The entries after the bad one are still read
+    assert_eq!(reader.next_entry().unwrap().unwrap().target_offset, 100);
+
+    let (target, report) = apply(patch, vec![0xee; 8192], false);
+    assert_eq!(target[4096..5096], vec![0xee; 1000]);
+    assert_eq!(target[0..512], entries[0].1);
+    assert_eq!(report.entries_applied, 3);
+    assert_eq!(report.bad_entries, vec![(4096, 1000)]);
+    assert!(!report.truncated);
+}
+
+#[test]
+fn truncated_patch_stops_at_the_cut() {
+    let entries = test_entries();
+    let patch = write_patch(&entries);
+    let first_entry_end = 12 + 20 + 512;
+    // Cut in the offset, the size, the crc and the data of the second entry
+    for cut in [4, 10, 18, 100] {
+        let (target, report) = apply(
+            patch[..first_entry_end + cut].to_vec(),
+            vec![0xee; 8192],
+            false,
+        );
+        assert_eq!(target[0..512], entries[0].1);
+        assert_eq!(target[512..], vec![0xee; 8192 - 512]);
+        assert_eq!(report.entries_applied, 1);
+        assert!(report.truncated);
+    }
+
+    // Cutting between entries is just a shorter patch
+    let (_, report) = apply(patch[..first_entry_end].to_vec(), vec![0; 512], false);
+    assert_eq!(report.entries_applied, 1);
+    assert!(!report.truncated);
+}
+
+#[test]
+fn huge_size_is_truncated_not_allocated() {
+    // A corrupt size can't be trusted to allocate the data up front
+    let mut patch = write_patch(&[(0, pattern(16, 4))]);
+    patch[12 + 8..12 + 16].copy_from_slice(&u64::MAX.to_le_bytes());
+    let mut reader = PatchReader::new(Cursor::new(patch)).unwrap();
+    assert!(matches!(
+        reader.next_entry(),
+        Err(PatchEntryError::Truncated)
+    ));
+}
+
+#[test]
+fn legacy_patch_is_applied() {
+    // Version 0 patches have no header and no checksums
+    let mut patch = Vec::new();
+    for (target_offset, data) in [(8u64, pattern(16, 5)), (100, pattern(4, 6))] {
+        patch.extend(target_offset.to_le_bytes());
+        patch.extend((data.len() as u64).to_le_bytes());
+        patch.extend(data);
+    }
+    let reader = PatchReader::new(Cursor::new(patch.clone())).unwrap();
+    assert_eq!(reader.get_version(), 0);
+
+    let (target, report) = apply(patch, vec![0; 128], false);
+    assert_eq!(target[8..24], pattern(16, 5));
+    assert_eq!(target[100..104], pattern(4, 6));
+    assert_eq!(report.entries_applied, 2);
+    assert!(!report.truncated);
+
+    // An empty legacy patch has no entries
+    let (_, report) = apply(Vec::new(), vec![0; 128], false);
+    assert_eq!(report.entries_applied, 0);
+    assert!(!report.truncated);
+}
+
+#[test]
+fn newer_version_is_rejected() {
+    let mut patch = write_patch(&test_entries());
+    patch[8..12].copy_from_slice(&(VERSION + 1).to_le_bytes());
+    assert!(PatchReader::new(Cursor::new(patch)).is_none());
+}
@@ -0,0 +1,166 @@
+// Block pointers that are parsed and written again have to come out the same, so patched block pointers only change what was patched
+use szfs::{
+    byte_iter::FromBytesLE,
+    dmu::ObjType,
+    test_image::{BlockPointerSpec, DvaSpec, EmbeddedBlockPointerSpec},
+    zio::{BlockPointer, ChecksumMethod, CompressionMethod, EmbeddedType},
+};
+
+fn parse(bytes: &[u8; 128]) -> BlockPointer {
+    BlockPointer::from_bytes_le(&mut bytes.iter().copied()).unwrap()
+}
+
+fn normal_block_pointer(dvas: Vec<DvaSpec>) -> BlockPointerSpec {
+    BlockPointerSpec {
+        dvas,
+        level: 2,
+        typ: ObjType::DNode,
+        checksum_method: ChecksumMethod::Fletcher4,
+        compression_method: CompressionMethod::Lz4,
+        physical_size: 4096,
+        logical_size: 16 * 1024,
+        birth_txg: 123_456,
+        fill: 97,
+        checksum: [
+            0x0123_4567_89ab_cdef,
+            0xfedc_ba98_7654_3210,
+            0x1111_2222_3333_4444,
+            0x5555_6666_7777_8888,
+        ],
+    }
+}
+
+fn dva(vdev_id: u32, offset: u64, allocated_size: u64) -> DvaSpec {
+    DvaSpec {
+        vdev_id,
+        offset,
+        allocated_size,
+        is_gang: false,
+    }
+}
+
+#[test]
+fn normal_block_pointer_round_trips() {
+    let copies = vec![
+        dva(0, 0x1234_5000, 6144),
+        dva(1, 0x7_0000_0000_0000, 4096),
+        // The biggest asize the dva can say, 24 bits of sectors
+        dva(2, 512, ((1 << 24) - 1) * 512),
+    ];
+    for ncopies in 1..=3 {
+        let spec = normal_block_pointer(copies[..ncopies].to_vec());
+        let bytes = spec.to_bytes_le();
+        let BlockPointer::Normal(bp) = parse(&bytes) else {
+            panic!("The block pointer should be normal");
+        };
+        assert_eq!(bp.to_bytes_le(), bytes);
+        assert_eq!(parse(&bytes).to_bytes_le(), bytes);
+
+        assert_eq!(bp.get_dvas().iter().flatten().count(), ncopies);
+        for (dva, copy) in bp.get_dvas().iter().flatten().zip(&copies) {
+            assert_eq!(dva.get_vdev_id(), copy.vdev_id);
+            assert_eq!(dva.parse_offset(), copy.offset);
+            assert_eq!(dva.parse_allocated_size(), copy.allocated_size);
+            assert!(!dva.is_gang());
+        }
+        assert_eq!(bp.get_level(), 2);
+        assert_eq!(bp.get_type(), ObjType::DNode);
+        assert_eq!(bp.get_compression_method(), CompressionMethod::Lz4);
+        assert_eq!(bp.parse_physical_size(), 4096);
+        assert_eq!(bp.parse_logical_size(), 16 * 1024);
+        assert_eq!(bp.get_logical_birth_txg(), 123_456);
+        assert_eq!(bp.get_physical_birth_txg(), 123_456);
+        assert_eq!(bp.get_fill(), 97);
+        assert_eq!(bp.get_checksum(), spec.checksum);
+    }
+}
+
+#[test]
+fn physical_birth_txg_round_trips() {
+    let mut bytes = normal_block_pointer(vec![dva(0, 0x8000, 4096)]).to_bytes_le();
+    bytes[72..80].copy_from_slice(&200_000u64.to_le_bytes());
+    let BlockPointer::Normal(bp) = parse(&bytes) else {
+        panic!("The block pointer should be normal");
+    };
+    assert_eq!(bp.get_logical_birth_txg(), 123_456);
+    assert_eq!(bp.get_physical_birth_txg(), 200_000);
+    assert_eq!(bp.to_bytes_le(), bytes);
+}
+
+#[test]
+fn biggest_sizes_round_trip() {
+    // 16 bits of sectors is 32M
+    let spec = BlockPointerSpec {
+        physical_size: 32 * 1024 * 1024,
+        logical_size: 32 * 1024 * 1024,
+        level: 31,
+        ..normal_block_pointer(vec![dva(0, 0x8000, 32 * 1024 * 1024)])
+    };
+    let bytes = spec.to_bytes_le();
+    let bp = parse(&bytes);
+    assert_eq!(bp.parse_logical_size(), 32 * 1024 * 1024);
+    assert_eq!(bp.to_bytes_le(), bytes);
+}
+
+#[test]
+fn gang_block_pointer_round_trips() {
+    let gang_header = DvaSpec {
+        is_gang: true,
+        ..dva(0, 0x10_0000, 512)
+    };
+    let spec = normal_block_pointer(vec![gang_header, dva(0, 0x20_0000, 512)]);
+    let bytes = spec.to_bytes_le();
+    let BlockPointer::Normal(bp) = parse(&bytes) else {
+        panic!("The block pointer should be normal");
+    };
+    let dvas = bp.get_dvas();
+    assert!(dvas[0].as_ref().unwrap().is_gang());
+    assert_eq!(dvas[0].as_ref().unwrap().parse_offset(), 0x10_0000);
+    assert!(!dvas[1].as_ref().unwrap().is_gang());
+    assert!(dvas[2].is_none());
+    assert_eq!(bp.to_bytes_le(), bytes);
+}
+
+#[test]
+fn dedup_bit_is_the_only_thing_lost() {
+    let mut bytes = normal_block_pointer(vec![dva(0, 0x8000, 4096)]).to_bytes_le();
+    let without_dedup = bytes;
+    // The dedup bit is the bit 62 of the info word
+    bytes[55] |= 1 << 6;
+    assert_eq!(parse(&bytes).to_bytes_le(), without_dedup);
+}
+
+#[test]
+fn embedded_block_pointer_round_trips() {
+    let payload = (0..112u8).map(|i| i.wrapping_mul(37)).collect::<Vec<_>>();
+    for (compression_method, level, typ) in [
+        (CompressionMethod::Off, 0, ObjType::PlainFileContents),
+        (CompressionMethod::Lz4, 0, ObjType::DirectoryContents),
+        (CompressionMethod::Zstd, 3, ObjType::DNode),
+    ] {
+        for physical_size in [1, 60, 112] {
+            let spec = EmbeddedBlockPointerSpec {
+                payload: payload[..physical_size].to_vec(),
+                level,
+                typ,
+                embedded_type: EmbeddedType::Data as u64,
+                compression_method,
+                physical_size,
+                logical_size: 1000,
+                birth_txg: 77,
+            };
+            let bytes = spec.to_bytes_le();
+            let BlockPointer::Embedded(bp) = parse(&bytes) else {
+                panic!("The block pointer should be embedded");
+            };
+            assert_eq!(bp.to_bytes_le(), bytes);
+            assert_eq!(parse(&bytes).to_bytes_le(), bytes);
+            assert_eq!(bp.get_level(), level);
+            assert_eq!(bp.get_type(), typ);
+            assert_eq!(bp.get_compression_method(), compression_method);
+            assert_eq!(bp.parse_physical_size(), physical_size as u64);
+            assert_eq!(bp.parse_logical_size(), 1000);
+            assert_eq!(bp.get_logical_birth_txg(), 77);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+// Dnodes that are parsed and written again have to come out the same, so a repaired dnode only changes what was repaired
+use szfs::{
+    dmu::{BonusType, DNodeBase, ObjType},
+    test_image::{BlockPointerSpec, DNodeSpec, DvaSpec},
+    zio::{ChecksumMethod, CompressionMethod},
+};
+
+fn block_pointer(offset: u64, typ: ObjType, level: usize) -> [u8; 128] {
+    BlockPointerSpec {
+        dvas: vec![DvaSpec {
+            vdev_id: 0,
+            offset,
+            allocated_size: 4096,
+            is_gang: false,
+        }],
+        level,
+        typ,
+        checksum_method: ChecksumMethod::Fletcher4,
+        compression_method: CompressionMethod::Lz4,
+        physical_size: 4096,
+        logical_size: 128 * 1024,
+        birth_txg: 42,
+        fill: 1,
+        checksum: [offset, 2, 3, 4],
+    }
+    .to_bytes_le()
+}
+
+fn dnode(typ: ObjType, bonus_type: BonusType, bonus: Vec<u8>, nbps: usize) -> DNodeSpec {
+    DNodeSpec {
+        typ,
+        bonus_type,
+        n_indirect_levels: 2,
+        data_block_size: 128 * 1024,
+        max_block_id: 1000,
+        used: 77 * 4096,
+        block_pointers: (0..nbps)
+            .map(|index| block_pointer(0x10_0000 * (index as u64 + 1), typ, 1))
+            .collect(),
+        n_block_pointer_slots: DNodeSpec::get_n_block_pointer_slots_for_bonus(bonus.len()),
+        bonus,
+        spill_block_pointer: None,
+    }
+}
+
+fn bonus(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + 1) as u8).collect()
+}
+
+// Returns: The parsed dnode, after checking that writing it gives back the same bytes
+fn assert_round_trips(spec: &DNodeSpec) -> DNodeBase {
+    let bytes = spec.to_bytes_le();
+    let (dnode, typ, bonus_type) = DNodeBase::from_bytes_le(&mut bytes.iter().copied()).unwrap();
+    assert_eq!(typ, spec.typ);
+    assert_eq!(bonus_type, spec.bonus_type);
+    assert_eq!(dnode.to_bytes_le(typ, bonus_type).unwrap(), bytes);
+    dnode
+}
+
+#[test]
+fn dnode_without_bonus_round_trips() {
+    let spec = dnode(ObjType::PlainFileContents, BonusType::None, Vec::new(), 3);
+    let mut dnode = assert_round_trips(&spec);
+    assert_eq!(dnode.get_num_slots(), 1);
+    assert_eq!(dnode.get_block_pointers().len(), 3);
+    assert!(dnode.get_bonus_data().is_empty());
+    assert!(dnode.get_spill_block_pointer().is_none());
+}
+
+#[test]
+fn dnode_with_bonus_round_trips() {
+    // A znode leaves room for one block pointer
+    let spec = dnode(
+        ObjType::PlainFileContents,
+        BonusType::ZNode,
+        bonus(0x108),
+        1,
+    );
+    let mut dnode = assert_round_trips(&spec);
+    assert_eq!(dnode.get_bonus_data(), bonus(0x108));
+    assert_eq!(dnode.get_block_pointers().len(), 1);
+    assert_eq!(dnode.get_max_indirect_block_id(), 1000);
+    assert_eq!(dnode.get_n_indirect_levels(), 2);
+    assert_eq!(dnode.parse_data_block_size(), 128 * 1024);
+    assert_eq!(dnode.parse_total_allocated(), 77 * 4096);
+
+    // The biggest bonus buffer of a 512 byte dnode
+    assert_round_trips(&dnode_with_bonus_len(320));
+}
+
+fn dnode_with_bonus_len(len: usize) -> DNodeSpec {
+    dnode(ObjType::DSLDataset, BonusType::DSLDataset, bonus(len), 1)
+}
+
+#[test]
+fn dnode_with_unused_block_pointers_round_trips() {
+    // The empty slots are holes, they are dropped when parsing and written back as zeros
+    let spec = DNodeSpec {
+        n_block_pointer_slots: 3,
+        ..dnode(ObjType::DirectoryContents, BonusType::ZNode, bonus(64), 1)
+    };
+    let mut dnode = assert_round_trips(&spec);
+    assert_eq!(dnode.get_block_pointers().len(), 1);
+}
+
+#[test]
+fn dnode_with_spill_block_pointer_round_trips() {
+    // System attributes that don't fit in the bonus buffer go in the spill block
+    // with it the bonus buffer can be 192 bytes next to one block pointer
+    let spill = block_pointer(0x40_0000, ObjType::SystemAttributes, 0);
+    let spec = DNodeSpec {
+        n_block_pointer_slots: 1,
+        spill_block_pointer: Some(spill),
+        ..dnode(
+            ObjType::PlainFileContents,
+            BonusType::SystemAttributes,
+            bonus(192),
+            1,
+        )
+    };
+    let bytes = spec.to_bytes_le();
+    assert_eq!(bytes.len(), 512);
+    let mut dnode = assert_round_trips(&spec);
+    assert_eq!(dnode.get_bonus_data(), bonus(192));
+    assert_eq!(dnode.get_block_pointers().len(), 1);
+    assert_eq!(
+        dnode.get_spill_block_pointer().unwrap().to_bytes_le(),
+        spill
+    );
+}
+
+#[test]
+fn large_dnode_with_spill_block_pointer_round_trips() {
+    // A dnode that takes up 2 slots, with a bonus buffer that only fits in a large dnode
+    let spill = block_pointer(0x80_0000, ObjType::SystemAttributes, 0);
+    let spec = DNodeSpec {
+        n_block_pointer_slots: 1,
+        spill_block_pointer: Some(spill),
+        ..dnode(
+            ObjType::PlainFileContents,
+            BonusType::SystemAttributes,
+            bonus(600),
+            1,
+        )
+    };
+    assert_eq!(spec.to_bytes_le().len(), 1024);
+    let mut dnode = assert_round_trips(&spec);
+    assert_eq!(dnode.get_num_slots(), 2);
+    assert_eq!(dnode.get_bonus_data(), bonus(600));
+    assert_eq!(
+        dnode.get_spill_block_pointer().unwrap().to_bytes_le(),
+        spill
+    );
+}
+
+#[test]
+fn large_dnode_without_spill_round_trips() {
+    let spec = DNodeSpec {
+        n_block_pointer_slots: 3,
+        ..dnode(
+            ObjType::PlainFileContents,
+            BonusType::SystemAttributes,
+            bonus(900),
+            3,
+        )
+    };
+    assert_eq!(spec.to_bytes_le().len(), 1536);
+    let mut dnode = assert_round_trips(&spec);
+    assert_eq!(dnode.get_num_slots(), 3);
+    assert_eq!(dnode.get_block_pointers().len(), 3);
+    assert!(dnode.get_spill_block_pointer().is_none());
+}
+
+#[test]
+fn repaired_dnode_only_changes_the_repaired_field() {
+    let spec = dnode(
+        ObjType::PlainFileContents,
+        BonusType::ZNode,
+        bonus(0x108),
+        1,
+    );
+    let bytes = spec.to_bytes_le();
+    let (mut dnode, typ, bonus_type) =
+        DNodeBase::from_bytes_le(&mut bytes.iter().copied()).unwrap();
+    dnode.set_max_indirect_block_id(5);
+    let repaired = dnode.to_bytes_le(typ, bonus_type).unwrap();
+    let mut expected = bytes.clone();
+    expected[16..24].copy_from_slice(&5u64.to_le_bytes());
+    assert_eq!(repaired, expected);
+}
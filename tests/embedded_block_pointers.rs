@@ -0,0 +1,123 @@
+// Embedded block pointers at the edges of what their size fields can hold, and the embedded types that don't have data
+use szfs::{
+    byte_iter::FromBytesLE,
+    dmu::ObjType,
+    lzjb,
+    test_image::{pattern, EmbeddedBlockPointerSpec},
+    zio::{BlockPointer, CompressionMethod, EmbeddedBlockPointer, EmbeddedType},
+};
+
+fn data_block_pointer(payload: Vec<u8>, logical_size: usize) -> EmbeddedBlockPointerSpec {
+    EmbeddedBlockPointerSpec {
+        physical_size: payload.len(),
+        payload,
+        level: 0,
+        typ: ObjType::PlainFileContents,
+        embedded_type: EmbeddedType::Data as u64,
+        compression_method: CompressionMethod::Off,
+        logical_size,
+        birth_txg: 10,
+    }
+}
+
+fn parse(spec: &EmbeddedBlockPointerSpec) -> Option<EmbeddedBlockPointer> {
+    match BlockPointer::from_bytes_le(&mut spec.to_bytes_le().into_iter())? {
+        BlockPointer::Embedded(bp) => Some(bp),
+        BlockPointer::Normal(_) => panic!("The block pointer should be embedded"),
+    }
+}
+
+#[test]
+fn full_payload_is_read() {
+    // 112 bytes is all the payload can hold
+    let data = pattern(112, 1);
+    let spec = data_block_pointer(data.clone(), 112);
+    let mut bp = parse(&spec).unwrap();
+    assert_eq!(bp.parse_physical_size(), 112);
+    assert_eq!(bp.parse_logical_size(), 112);
+    assert_eq!(bp.get_logical_birth_txg(), 10);
+    assert_eq!(bp.to_bytes_le(), spec.to_bytes_le());
+    assert_eq!(bp.dereference(), Ok(data));
+}
+
+#[test]
+fn short_payload_is_read() {
+    let data = pattern(1, 2);
+    let mut bp = parse(&data_block_pointer(data.clone(), 1)).unwrap();
+    assert_eq!(bp.parse_physical_size(), 1);
+    assert_eq!(bp.dereference(), Ok(data));
+}
+
+#[test]
+fn payload_bigger_than_the_block_pointer_is_rejected() {
+    // The psize field has 7 bits, so it can say up to 128 bytes, but only 112 fit
+    for physical_size in [113, 128] {
+        let spec = EmbeddedBlockPointerSpec {
+            physical_size,
+            ..data_block_pointer(pattern(112, 3), 112)
+        };
+        assert!(parse(&spec).is_none());
+    }
+}
+
+#[test]
+fn compressed_payload_is_read() {
+    // lzjb copies the last 66 bytes without compressing them, so the data has to be small to fit
+    let data = [pattern(8, 4), vec![0; 592]].concat();
+    let compressed = lzjb::lzjb_compress(&data);
+    assert!(compressed.len() <= 112);
+    let spec = EmbeddedBlockPointerSpec {
+        compression_method: CompressionMethod::Lzjb,
+        ..data_block_pointer(compressed, data.len())
+    };
+    let mut bp = parse(&spec).unwrap();
+    assert_eq!(bp.parse_logical_size(), 600);
+    assert_eq!(bp.dereference(), Ok(data));
+}
+
+#[test]
+fn logical_size_uses_all_25_bits() {
+    // Above 16M the logical size doesn't fit in 24 bits, and the physical size starts right after it
+    for logical_size in [(1 << 24) + 1, 20 * 1024 * 1024, 1 << 25] {
+        let spec = data_block_pointer(pattern(100, 5), logical_size);
+        let mut bp = parse(&spec).unwrap();
+        assert_eq!(bp.parse_logical_size(), logical_size as u64);
+        assert_eq!(bp.parse_physical_size(), 100);
+        assert_eq!(bp.to_bytes_le(), spec.to_bytes_le());
+        // The payload isn't compressed, so it can't be that big
+        assert!(bp.dereference().is_err());
+    }
+}
+
+#[test]
+fn redacted_block_pointer_has_no_data() {
+    // Redacted block pointers don't have the byte order set, they are still parsed
+    // Like for any other embedded block pointer, a psize field of 0 means 1 byte
+    let spec = EmbeddedBlockPointerSpec {
+        embedded_type: EmbeddedType::Redacted as u64,
+        physical_size: 1,
+        ..data_block_pointer(Vec::new(), 128 * 1024)
+    };
+    assert_eq!(spec.to_bytes_le()[55] & 0x80, 0);
+    let mut bp = parse(&spec).unwrap();
+    assert_eq!(bp.get_embedded_data_type(), EmbeddedType::Redacted);
+    assert_eq!(bp.parse_logical_size(), 128 * 1024);
+    assert!(bp.dereference().is_err());
+}
+
+#[test]
+fn other_embedded_types_are_not_read() {
+    let reserved = EmbeddedBlockPointerSpec {
+        embedded_type: EmbeddedType::Reserved as u64,
+        ..data_block_pointer(pattern(50, 6), 50)
+    };
+    let mut bp = parse(&reserved).unwrap();
+    assert_eq!(bp.get_embedded_data_type(), EmbeddedType::Reserved);
+    assert!(bp.dereference().is_err());
+
+    let unknown = EmbeddedBlockPointerSpec {
+        embedded_type: 3,
+        ..data_block_pointer(pattern(50, 6), 50)
+    };
+    assert!(parse(&unknown).is_none());
+}
@@ -0,0 +1,133 @@
+// Small directories keep their micro zap in an embedded block pointer, and its logical size doesn't have to be a multiple of 512
+use szfs::{
+    byte_iter::FromBytesLE,
+    dmu::{BonusType, DNode, ObjType},
+    test_image::{self, DNodeSpec, EmbeddedBlockPointerSpec},
+    zap::Value,
+    zio::{self, CompressionMethod, EmbeddedType, Vdevs},
+    zpl::{self, DirectoryEntry, DirectoryEntryKind},
+};
+
+// The type of a file in a directory entry, in the top 4 bits
+// Source: https://github.com/openzfs/zfs/blob/master/include/sys/zfs_znode.h (ZFS_DIRENT_MAKE)
+const FILE_ENTRY: u64 = 8 << 60;
+
+// Returns: A directory dnode with a micro zap of `logical_size` bytes with the entries in an embedded block pointer
+fn directory_dnode(entries: &[(&str, u64)], logical_size: usize, data_block_size: usize) -> DNode {
+    let mut zap = test_image::micro_zap_bytes(entries);
+    zap.resize(logical_size, 0);
+    let payload = zio::try_compress_block(&zap, CompressionMethod::Lz4).unwrap();
+    assert!(payload.len() <= 112);
+    let block_pointer = EmbeddedBlockPointerSpec {
+        physical_size: payload.len(),
+        payload,
+        level: 0,
+        typ: ObjType::DirectoryContents,
+        embedded_type: EmbeddedType::Data as u64,
+        compression_method: CompressionMethod::Lz4,
+        logical_size,
+        birth_txg: 10,
+    };
+    let bonus = test_image::znode_bonus(0o40755, entries.len() as u64 + 2, 34, 2);
+    let spec = DNodeSpec {
+        typ: ObjType::DirectoryContents,
+        bonus_type: BonusType::ZNode,
+        n_indirect_levels: 1,
+        data_block_size,
+        max_block_id: 0,
+        used: 0,
+        block_pointers: vec![block_pointer.to_bytes_le()],
+        n_block_pointer_slots: DNodeSpec::get_n_block_pointer_slots_for_bonus(bonus.len()),
+        bonus,
+        spill_block_pointer: None,
+    };
+    DNode::from_bytes_le(&mut spec.to_bytes_le().into_iter()).unwrap()
+}
+
+fn dump_directory(dnode: DNode) -> Option<Vec<DirectoryEntry>> {
+    let DNode::DirectoryContents(mut directory) = dnode else {
+        panic!("The dnode should be a directory");
+    };
+    let contents = directory.dump_zap_contents(&mut Vdevs::new())?;
+    Some(zpl::parse_directory_entries(&contents))
+}
+
+fn file_entry(name: &str, object_id: u64) -> DirectoryEntry {
+    DirectoryEntry {
+        name: name.to_string(),
+        object_id,
+        kind: DirectoryEntryKind::File,
+    }
+}
+
+#[test]
+fn micro_zap_shorter_than_the_data_block_is_read() {
+    // zfs only stores as much of the micro zap as it uses, here 64 bytes of header and 2 entries
+    let dnode = directory_dnode(
+        &[("a.txt", FILE_ENTRY | 128), ("b.txt", FILE_ENTRY | 129)],
+        192,
+        512,
+    );
+    assert_eq!(
+        dump_directory(dnode),
+        Some(vec![file_entry("a.txt", 128), file_entry("b.txt", 129)])
+    );
+}
+
+#[test]
+fn micro_zap_with_room_for_more_entries_is_read() {
+    // 4 entries fit but only 3 are used, the empty one is skipped
+    let dnode = directory_dnode(
+        &[
+            ("notes", FILE_ENTRY | 200),
+            ("README", FILE_ENTRY | 201),
+            ("todo", FILE_ENTRY | 202),
+        ],
+        320,
+        512,
+    );
+    assert_eq!(
+        dump_directory(dnode),
+        Some(vec![
+            file_entry("README", 201),
+            file_entry("notes", 200),
+            file_entry("todo", 202),
+        ])
+    );
+}
+
+#[test]
+fn micro_zap_bigger_than_a_sector_is_read() {
+    // 576 bytes is neither a multiple of 512 nor the data block size
+    let dnode = directory_dnode(&[("big", FILE_ENTRY | 300)], 576, 1024);
+    assert_eq!(dump_directory(dnode), Some(vec![file_entry("big", 300)]));
+}
+
+#[test]
+fn micro_zap_header_is_read_from_a_short_block() {
+    let DNode::DirectoryContents(mut directory) =
+        directory_dnode(&[("x", FILE_ENTRY | 5)], 128, 512)
+    else {
+        panic!("The dnode should be a directory");
+    };
+    let mut vdevs = Vdevs::new();
+    // A normal read wants the whole data block, only the embedded read takes the short one
+    assert!(directory.0.read_block(0, &mut vdevs).is_err());
+    assert_eq!(
+        directory
+            .0
+            .read_block_allow_embedded_size(0, &mut vdevs)
+            .map(|data| data.len()),
+        Ok(128)
+    );
+    assert!(directory.get_zap_header(&mut vdevs).is_some());
+    let contents = directory.dump_zap_contents(&mut vdevs).unwrap();
+    assert!(matches!(contents.get("x"), Some(Value::U64(value)) if *value == FILE_ENTRY | 5));
+}
+
+#[test]
+fn embedded_block_bigger_than_the_data_block_is_rejected() {
+    // The logical size of the embedded block can't be more than the data block size of its dnode
+    let dnode = directory_dnode(&[("y", FILE_ENTRY | 6)], 1024, 512);
+    assert_eq!(dump_directory(dnode), None);
+}
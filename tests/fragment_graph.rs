@@ -0,0 +1,351 @@
+// Builds the fragment graph of a dataset made by test_image, the way undelete does after finding its structures on disk
+#![cfg(feature = "disk")]
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::File,
+};
+
+use szfs::{
+    byte_iter::{FromBytesLE, FromSliceLE},
+    cli,
+    dmu::{BonusType, DNodeBase, ObjSet, ObjSetType, ObjType},
+    recovery::fragment::{self, Fragment, FragmentData, IndirectBlock},
+    test_image::{self, pattern, ImageBuilder, ImageConfig},
+    zio::{BlockPointer, Vdevs},
+    VdevFile,
+};
+
+const BIG_FILE_OBJECT_ID: u64 = 2;
+const DIRECTORY_OBJECT_ID: u64 = 3;
+// Far enough that the metadnode needs more block pointers than fit in it, so it gets an indirect block
+const SMALL_FILE_OBJECT_ID: u64 = 100;
+
+struct TestPool {
+    disks: Vec<Vec<u8>>,
+    config: ImageConfig,
+    objset: [u8; 128],
+    big_file: Vec<u8>,
+    directory: Vec<u8>,
+    small_file: Vec<u8>,
+}
+
+fn build_pool() -> TestPool {
+    let config = ImageConfig::default();
+    let mut builder = ImageBuilder::new(config.clone());
+    let bonus = |mode, size| test_image::znode_bonus(mode, size, DIRECTORY_OBJECT_ID, 1);
+    // 3 blocks don't fit next to the bonus buffer, so the file gets an indirect block
+    let big_file = builder.write_object(
+        ObjType::PlainFileContents,
+        BonusType::ZNode,
+        bonus(0o100644, 1536),
+        &pattern(1536, 1),
+        512,
+    );
+    let small_file = builder.write_object(
+        ObjType::PlainFileContents,
+        BonusType::ZNode,
+        bonus(0o100644, 100),
+        &pattern(100, 2),
+        512,
+    );
+    let directory = builder.write_object(
+        ObjType::DirectoryContents,
+        BonusType::ZNode,
+        bonus(0o40755, 4),
+        &test_image::micro_zap_bytes(&[
+            ("big.bin", (8 << 60) | BIG_FILE_OBJECT_ID),
+            ("small", (8 << 60) | SMALL_FILE_OBJECT_ID),
+        ]),
+        512,
+    );
+    let dnodes = BTreeMap::from([
+        (BIG_FILE_OBJECT_ID, big_file.clone()),
+        (DIRECTORY_OBJECT_ID, directory.clone()),
+        (SMALL_FILE_OBJECT_ID, small_file.clone()),
+    ]);
+    let objset = builder.write_objset(ObjSetType::Zfs, &dnodes).to_bytes_le();
+    TestPool {
+        disks: builder.build(),
+        config,
+        objset,
+        big_file: big_file.to_bytes_le(),
+        directory: directory.to_bytes_le(),
+        small_file: small_file.to_bytes_le(),
+    }
+}
+
+fn with_vdevs<T>(pool: &TestPool, f: impl FnOnce(&mut Vdevs) -> T) -> T {
+    let dir = tempfile::tempdir().unwrap();
+    let mut devices = pool
+        .disks
+        .iter()
+        .enumerate()
+        .map(|(index, disk)| {
+            let path = dir.path().join(format!("disk{index}.img"));
+            std::fs::write(&path, disk).unwrap();
+            VdevFile::from(File::open(path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    let mut raidz = cli::make_raidz(
+        &mut devices,
+        pool.config.nparity,
+        pool.config.get_sector_size(),
+    );
+    let mut vdevs = Vdevs::new();
+    vdevs.insert(0usize, &mut raidz);
+    f(&mut vdevs)
+}
+
+fn dereference(bp: &mut BlockPointer, vdevs: &mut Vdevs) -> Vec<u8> {
+    bp.dereference(vdevs).unwrap()
+}
+
+// Returns: The hash and the fragment of the objset
+fn read_objset(pool: &TestPool, vdevs: &mut Vdevs) -> ([u64; 4], Fragment) {
+    let mut bp = BlockPointer::from_bytes_le(&mut pool.objset.iter().copied()).unwrap();
+    let data = dereference(&mut bp, vdevs);
+    let objset = ObjSet::from_slice_le(&data).unwrap();
+    (
+        fragment::hash_fragment_data(&data),
+        FragmentData::ObjSetDNode(Box::new(objset)).into(),
+    )
+}
+
+// Returns: The data of the first block the dnode points to
+fn read_first_block(dnode: &[u8], vdevs: &mut Vdevs) -> Vec<u8> {
+    let (mut dnode, _, _) = DNodeBase::from_bytes_le(&mut dnode.iter().copied()).unwrap();
+    dereference(&mut dnode.get_block_pointers()[0], vdevs)
+}
+
+struct Hashes {
+    objset: [u64; 4],
+    metadnode_indirect_block: [u64; 4],
+    big_file: [u64; 4],
+    big_file_indirect_block: [u64; 4],
+    directory: [u64; 4],
+    small_file: [u64; 4],
+}
+
+fn get_hashes(pool: &TestPool, vdevs: &mut Vdevs) -> Hashes {
+    let (objset, mut objset_fragment) = read_objset(pool, vdevs);
+    let FragmentData::ObjSetDNode(objset_data) = &mut objset_fragment.data else {
+        unreachable!();
+    };
+    let metadnode_indirect_block = fragment::hash_fragment_data(&dereference(
+        &mut objset_data.metadnode.get_block_pointers()[0],
+        vdevs,
+    ));
+    Hashes {
+        objset,
+        metadnode_indirect_block,
+        big_file: fragment::hash_fragment_data(&pool.big_file),
+        big_file_indirect_block: fragment::hash_fragment_data(&read_first_block(
+            &pool.big_file,
+            vdevs,
+        )),
+        directory: fragment::hash_fragment_data(&pool.directory),
+        small_file: fragment::hash_fragment_data(&pool.small_file),
+    }
+}
+
+// Returns: Every fragment under the objset, and the objset itself
+fn expand_objset(pool: &TestPool, vdevs: &mut Vdevs) -> HashMap<[u64; 4], Fragment> {
+    let (hash, mut objset) = read_objset(pool, vdevs);
+    let mut fragments = fragment::expand_fragment(&mut objset, vdevs).unwrap();
+    fragments.insert(hash, objset);
+    fragments
+}
+
+fn get_children(fragments: &HashMap<[u64; 4], Fragment>, hash: [u64; 4]) -> HashSet<[u64; 4]> {
+    fragments[&hash].children.clone()
+}
+
+#[test]
+fn dnodes_are_found_in_the_dnode_blocks() {
+    let pool = build_pool();
+    with_vdevs(&pool, |vdevs| {
+        let hashes = get_hashes(&pool, vdevs);
+        let (_, mut objset) = read_objset(&pool, vdevs);
+        let FragmentData::ObjSetDNode(objset) = &mut objset.data else {
+            unreachable!();
+        };
+        let data = dereference(&mut objset.metadnode.get_block_pointers()[0], vdevs);
+        let mut indirect_block = IndirectBlock::from_bytes_le(&data, vdevs).unwrap();
+        // 4 blocks of dnodes, the rest of the indirect block is holes
+        assert_eq!(indirect_block.bps.iter().flatten().count(), 4);
+        let dnode_blocks = indirect_block.get_data_with_gaps(vdevs).unwrap();
+
+        let found = fragment::search_le_bytes_for_dnodes(&dnode_blocks, vdevs, false);
+        assert_eq!(
+            found.keys().copied().collect::<HashSet<_>>(),
+            HashSet::from([hashes.big_file, hashes.directory, hashes.small_file])
+        );
+        assert!(matches!(
+            found[&hashes.big_file].data,
+            FragmentData::FileDNode(_)
+        ));
+        let FragmentData::DirectoryDNode(_, names) = &found[&hashes.directory].data else {
+            panic!("The directory should be found as a directory");
+        };
+        let mut names = names.clone();
+        names.sort();
+        assert_eq!(names, ["big.bin", "small"]);
+        assert!(found.values().all(|fragment| fragment.children.is_empty()));
+    });
+}
+
+#[test]
+fn objsets_are_only_searched_for_when_asked() {
+    let pool = build_pool();
+    with_vdevs(&pool, |vdevs| {
+        let hashes = get_hashes(&pool, vdevs);
+        let mut bp = BlockPointer::from_bytes_le(&mut pool.objset.iter().copied()).unwrap();
+        let data = dereference(&mut bp, vdevs);
+
+        let found = fragment::search_le_bytes_for_dnodes(&data, vdevs, true);
+        assert!(matches!(
+            found.get(&hashes.objset).map(|fragment| &fragment.data),
+            Some(FragmentData::ObjSetDNode(_))
+        ));
+        let found = fragment::search_le_bytes_for_dnodes(&data, vdevs, false);
+        assert!(!found.contains_key(&hashes.objset));
+    });
+}
+
+#[test]
+fn expanding_the_objset_finds_everything_under_it() {
+    let pool = build_pool();
+    with_vdevs(&pool, |vdevs| {
+        let hashes = get_hashes(&pool, vdevs);
+        let fragments = expand_objset(&pool, vdevs);
+        assert_eq!(
+            fragments.keys().copied().collect::<HashSet<_>>(),
+            HashSet::from([
+                hashes.objset,
+                hashes.metadnode_indirect_block,
+                hashes.big_file,
+                hashes.big_file_indirect_block,
+                hashes.directory,
+                hashes.small_file,
+            ])
+        );
+        // Expanding only links the block pointers, the dnodes inside of the indirect blocks are linked by build_graph
+        assert_eq!(
+            get_children(&fragments, hashes.objset),
+            HashSet::from([hashes.metadnode_indirect_block])
+        );
+        assert_eq!(
+            get_children(&fragments, hashes.big_file),
+            HashSet::from([hashes.big_file_indirect_block])
+        );
+        assert!(get_children(&fragments, hashes.small_file).is_empty());
+    });
+}
+
+#[test]
+fn graph_has_the_objset_as_its_root() {
+    let pool = build_pool();
+    with_vdevs(&pool, |vdevs| {
+        let hashes = get_hashes(&pool, vdevs);
+        let mut fragments = expand_objset(&pool, vdevs);
+        let roots = fragment::build_graph(&mut fragments, vdevs);
+        assert_eq!(roots, HashSet::from([hashes.objset]));
+
+        let edges = [
+            (hashes.objset, vec![hashes.metadnode_indirect_block]),
+            (
+                hashes.metadnode_indirect_block,
+                vec![hashes.big_file, hashes.directory, hashes.small_file],
+            ),
+            (hashes.big_file, vec![hashes.big_file_indirect_block]),
+            (hashes.big_file_indirect_block, vec![]),
+            (hashes.directory, vec![]),
+            (hashes.small_file, vec![]),
+        ];
+        for (parent, children) in edges {
+            assert_eq!(
+                get_children(&fragments, parent),
+                children.into_iter().collect::<HashSet<_>>()
+            );
+        }
+    });
+}
+
+#[test]
+fn is_child_of_agrees_with_the_graph() {
+    let pool = build_pool();
+    with_vdevs(&pool, |vdevs| {
+        // A fresh copy for the checks, so is_child_of can't just find the children build_graph recorded
+        let mut graph = expand_objset(&pool, vdevs);
+        fragment::build_graph(&mut graph, vdevs);
+        let mut fragments = expand_objset(&pool, vdevs);
+        for fragment in fragments.values_mut() {
+            fragment.children.clear();
+        }
+
+        let all_hashes = graph.keys().copied().collect::<Vec<_>>();
+        assert_eq!(all_hashes.len(), 6);
+        for parent_hash in &all_hashes {
+            for child_hash in &all_hashes {
+                if parent_hash == child_hash {
+                    continue;
+                }
+                let mut parent = fragments.remove(parent_hash).unwrap();
+                let mut child = fragments.remove(child_hash).unwrap();
+                assert_eq!(
+                    child.is_child_of(vdevs, *child_hash, &mut parent),
+                    graph[parent_hash].children.contains(child_hash),
+                    "{parent:?} -> {child:?}"
+                );
+                fragments.insert(*parent_hash, parent);
+                fragments.insert(*child_hash, child);
+            }
+        }
+    });
+}
+
+#[test]
+fn merged_fragments_make_the_same_graph() {
+    let pool = build_pool();
+    with_vdevs(&pool, |vdevs| {
+        let hashes = get_hashes(&pool, vdevs);
+        // The same dnodes found by scanning the disk again, like when merging the checkpoints of two runs
+        let mut fragments = expand_objset(&pool, vdevs);
+        let nfragments = fragments.len();
+        for dnode in [&pool.big_file, &pool.directory, &pool.small_file] {
+            let mut data = dnode.clone();
+            data.resize(data.len().next_multiple_of(512), 0);
+            fragments.extend(fragment::search_le_bytes_for_dnodes(&data, vdevs, false));
+        }
+        assert_eq!(fragments.len(), nfragments);
+
+        let roots = fragment::build_graph(&mut fragments, vdevs);
+        assert_eq!(roots, HashSet::from([hashes.objset]));
+    });
+}
+
+#[test]
+fn fragments_without_parents_are_roots() {
+    let pool = build_pool();
+    with_vdevs(&pool, |vdevs| {
+        let hashes = get_hashes(&pool, vdevs);
+        // The objset and the metadnode were overwritten, only the dnodes and what's under them survived
+        let mut fragments = expand_objset(&pool, vdevs);
+        fragments.remove(&hashes.objset);
+        fragments.remove(&hashes.metadnode_indirect_block);
+        // Children that are already known (ex. from the directory entries) are kept
+        fragments
+            .get_mut(&hashes.directory)
+            .unwrap()
+            .children
+            .insert(hashes.small_file);
+
+        let roots = fragment::build_graph(&mut fragments, vdevs);
+        assert_eq!(roots, HashSet::from([hashes.big_file, hashes.directory]));
+        assert_eq!(
+            get_children(&fragments, hashes.directory),
+            HashSet::from([hashes.small_file])
+        );
+    });
+}
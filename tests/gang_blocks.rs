@@ -0,0 +1,204 @@
+// Reads blocks that were split into gang blocks, from gang headers written by test_image
+#![cfg(feature = "disk")]
+
+use std::fs::File;
+
+use szfs::{
+    byte_iter::FromBytesLE,
+    cli,
+    dmu::ObjType,
+    fletcher,
+    test_image::{
+        gang_header_bytes, pattern, BlockPointerSpec, DvaSpec, ImageBuilder, ImageConfig,
+    },
+    zio::{BlockPointer, BlockSource, Vdevs},
+    VdevFile,
+};
+
+// The block pointer to a gang block with the data, the gang header is the first dva
+fn gang_block_pointer(gang_dva: DvaSpec, data: &[u8], birth_txg: u64) -> BlockPointerSpec {
+    BlockPointerSpec {
+        dvas: vec![gang_dva],
+        level: 0,
+        typ: ObjType::PlainFileContents,
+        checksum_method: szfs::zio::ChecksumMethod::Fletcher4,
+        compression_method: szfs::zio::CompressionMethod::Off,
+        physical_size: data.len(),
+        logical_size: data.len(),
+        birth_txg,
+        fill: 1,
+        checksum: fletcher::do_fletcher4(data),
+    }
+}
+
+// Returns: The result of reading the block pointers from the image made by the builder, and where it was read from
+fn read_block_pointers(
+    builder: ImageBuilder,
+    bps: &[BlockPointerSpec],
+) -> Vec<Result<(Vec<u8>, BlockSource), ()>> {
+    let config = builder.get_config().clone();
+    let dir = tempfile::tempdir().unwrap();
+    let mut devices = builder
+        .build()
+        .iter()
+        .enumerate()
+        .map(|(index, disk)| {
+            let path = dir.path().join(format!("disk{index}.img"));
+            std::fs::write(&path, disk).unwrap();
+            VdevFile::from(File::open(path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    let mut raidz = cli::make_raidz(&mut devices, config.nparity, config.get_sector_size());
+    let mut vdevs = Vdevs::new();
+    vdevs.insert(0usize, &mut raidz);
+    bps.iter()
+        .map(|bp| {
+            let mut bp = BlockPointer::from_bytes_le(&mut bp.to_bytes_le().into_iter()).unwrap();
+            bp.dereference_with_source(&mut vdevs)
+        })
+        .collect()
+}
+
+#[test]
+fn gang_block_is_read() {
+    let mut builder = ImageBuilder::new(ImageConfig::default());
+    let txg = builder.get_config().txg;
+    let data = pattern(1536, 1);
+    let leaves = data
+        .chunks(512)
+        .map(|chunk| {
+            builder
+                .write_block(chunk, ObjType::PlainFileContents, 0)
+                .to_bytes_le()
+        })
+        .collect::<Vec<_>>();
+    let gang_dva = builder.write_gang_header(&leaves);
+    let bp = gang_block_pointer(gang_dva, &data, txg);
+
+    let results = read_block_pointers(builder, &[bp]);
+    assert_eq!(results[0], Ok((data, BlockSource::GangMember(0))));
+}
+
+#[test]
+fn nested_gang_block_is_read() {
+    let mut builder = ImageBuilder::new(ImageConfig::default());
+    let txg = builder.get_config().txg;
+    // A header with a leaf, a gang of two leaves and another leaf, like zfs makes when a piece of a gang block can't be allocated either
+    let data = pattern(2048, 2);
+    let leaf = |builder: &mut ImageBuilder, range: std::ops::Range<usize>| {
+        builder
+            .write_block(&data[range], ObjType::PlainFileContents, 0)
+            .to_bytes_le()
+    };
+    let first = leaf(&mut builder, 0..512);
+    let inner_leaves = [
+        leaf(&mut builder, 512..1024),
+        leaf(&mut builder, 1024..1536),
+    ];
+    let last = leaf(&mut builder, 1536..2048);
+    let inner_dva = builder.write_gang_header(&inner_leaves);
+    let inner = gang_block_pointer(inner_dva, &data[512..1536], txg).to_bytes_le();
+    let gang_dva = builder.write_gang_header(&[first, inner, last]);
+    let bp = gang_block_pointer(gang_dva, &data, txg);
+
+    let results = read_block_pointers(builder, &[bp]);
+    assert_eq!(results[0], Ok((data, BlockSource::GangMember(0))));
+}
+
+#[test]
+fn gang_header_is_verified() {
+    let mut builder = ImageBuilder::new(ImageConfig::default());
+    let txg = builder.get_config().txg;
+    let data = pattern(1024, 3);
+    let leaves = data
+        .chunks(512)
+        .map(|chunk| {
+            builder
+                .write_block(chunk, ObjType::PlainFileContents, 0)
+                .to_bytes_le()
+        })
+        .collect::<Vec<_>>();
+    let gang_dva = builder.write_gang_header(&leaves);
+
+    // The checksum of the header depends on the birth txg of the block pointer to it, so the header of an older block doesn't pass for it
+    let other_birth = gang_block_pointer(gang_dva, &data, txg + 1);
+    // Not a gang header at all
+    let not_a_header = gang_block_pointer(
+        DvaSpec {
+            is_gang: true,
+            ..leaves_dva(&leaves[0])
+        },
+        &data,
+        txg,
+    );
+    let valid = gang_block_pointer(gang_dva, &data, txg);
+
+    let results = read_block_pointers(builder, &[other_birth, not_a_header, valid]);
+    assert!(results[0].is_err());
+    assert!(results[1].is_err());
+    assert_eq!(results[2], Ok((data, BlockSource::GangMember(0))));
+}
+
+// Returns: The first dva of the block pointer
+fn leaves_dva(bp: &[u8; 128]) -> DvaSpec {
+    let BlockPointer::Normal(bp) = BlockPointer::from_bytes_le(&mut bp.iter().copied()).unwrap()
+    else {
+        panic!("The leaves are normal block pointers");
+    };
+    let dva = bp.get_dvas()[0].as_ref().unwrap();
+    DvaSpec {
+        vdev_id: dva.get_vdev_id(),
+        offset: dva.parse_offset(),
+        allocated_size: dva.parse_allocated_size(),
+        is_gang: dva.is_gang(),
+    }
+}
+
+#[test]
+fn self_referencing_gang_header_is_not_followed() {
+    let mut builder = ImageBuilder::new(ImageConfig::default());
+    let txg = builder.get_config().txg;
+    let data = pattern(1024, 4);
+    let leaf = builder
+        .write_block(&data[..512], ObjType::PlainFileContents, 0)
+        .to_bytes_le();
+
+    // The second block pointer of the header points back to the header, so following it would never end
+    let gang_dva = DvaSpec {
+        vdev_id: 0,
+        offset: builder.get_next_offset(),
+        allocated_size: 512,
+        is_gang: true,
+    };
+    let itself = gang_block_pointer(gang_dva, &data, txg).to_bytes_le();
+    let header = gang_header_bytes(&[leaf, itself], &gang_dva, txg);
+    assert_eq!(builder.write_raw(&header).offset, gang_dva.offset);
+    let bp = gang_block_pointer(gang_dva, &data, txg);
+
+    let results = read_block_pointers(builder, &[bp]);
+    assert!(results[0].is_err());
+}
+
+// Returns: The block pointer to a chain of `depth` gang headers, each one has the next one as its only block pointer, the last one has the data
+fn write_gang_chain(builder: &mut ImageBuilder, data: &[u8], depth: usize) -> BlockPointerSpec {
+    let txg = builder.get_config().txg;
+    let mut bp = builder.write_block(data, ObjType::PlainFileContents, 0);
+    for _ in 0..depth {
+        let gang_dva = builder.write_gang_header(&[bp.to_bytes_le()]);
+        bp = gang_block_pointer(gang_dva, data, txg);
+    }
+    bp
+}
+
+#[test]
+fn gang_chains_stop_at_the_depth_limit() {
+    let mut builder = ImageBuilder::new(ImageConfig::default());
+    let data = pattern(512, 5);
+    // The limit is 16 levels of gang headers
+    let deepest_readable = write_gang_chain(&mut builder, &data, 16);
+    let too_deep = write_gang_chain(&mut builder, &data, 17);
+
+    let results = read_block_pointers(builder, &[deepest_readable, too_deep]);
+    assert_eq!(results[0], Ok((data, BlockSource::GangMember(0))));
+    assert!(results[1].is_err());
+}
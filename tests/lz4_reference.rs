@@ -0,0 +1,160 @@
+// The lz4 decoder checked against the lz4_flex reference implementation on crafted streams, the same check the lz4_reference fuzz target does on random ones
+use szfs::{lz4, test_image::pattern};
+
+// Every byte of the stream can at most add 255 bytes of output (an extended size byte of a lookback)
+const MAX_EXPANSION: usize = 256;
+
+// Returns: What both decoders decompress the stream to, after checking they agree, None if they both reject it
+fn decompress_with_both(stream: &[u8]) -> Option<Vec<u8>> {
+    let mut reference_output = vec![0u8; stream.len() * MAX_EXPANSION + 64];
+    let reference = lz4_flex::block::decompress_into(stream, &mut reference_output)
+        .map(|size| reference_output[..size].to_vec())
+        .ok();
+    let ours = lz4::lz4_decompress_blocks(&mut stream.iter().copied(), None).ok();
+    assert_eq!(ours, reference, "Stream: {stream:02x?}");
+    ours
+}
+
+// Returns: A sequence with the literals and a match, the sizes are extended when they don't fit in the token
+fn sequence(literals: &[u8], lookback: u16, match_size: usize) -> Vec<u8> {
+    let mut res = Vec::new();
+    let extended_sizes = |res: &mut Vec<u8>, mut size: usize| {
+        while size >= 0xFF {
+            res.push(0xFF);
+            size -= 0xFF;
+        }
+        res.push(size as u8);
+    };
+    let match_code = match_size - 4;
+    res.push(((literals.len().min(0xF) as u8) << 4) | match_code.min(0xF) as u8);
+    if literals.len() >= 0xF {
+        extended_sizes(&mut res, literals.len() - 0xF);
+    }
+    res.extend(literals);
+    res.extend(lookback.to_le_bytes());
+    if match_code >= 0xF {
+        extended_sizes(&mut res, match_code - 0xF);
+    }
+    res
+}
+
+// Returns: The last sequence, which only has literals
+fn last_sequence(literals: &[u8]) -> Vec<u8> {
+    let mut res = vec![(literals.len().min(0xF) as u8) << 4];
+    let mut size = literals.len().saturating_sub(0xF);
+    if literals.len() >= 0xF {
+        while size >= 0xFF {
+            res.push(0xFF);
+            size -= 0xFF;
+        }
+        res.push(size as u8);
+    }
+    res.extend(literals);
+    res
+}
+
+#[test]
+fn literals_only() {
+    for len in [0, 1, 14, 15, 16, 15 + 254, 15 + 255, 15 + 255 + 1, 1000] {
+        let data = pattern(len, 1);
+        assert_eq!(decompress_with_both(&last_sequence(&data)), Some(data));
+    }
+}
+
+#[test]
+fn matches() {
+    // Match sizes around where the size is extended, with lookbacks that overlap the match and ones that don't
+    for match_size in [4, 18, 19, 20, 19 + 254, 19 + 255, 19 + 256, 5000] {
+        for lookback in [1, 2, 7, 8] {
+            let literals = pattern(8, 2);
+            let stream = [
+                sequence(&literals, lookback, match_size),
+                last_sequence(b"tail!"),
+            ]
+            .concat();
+            let data = decompress_with_both(&stream).unwrap();
+            assert_eq!(data.len(), 8 + match_size + 5);
+        }
+    }
+}
+
+#[test]
+fn max_lookback() {
+    let literals = pattern(0xFFFF, 3);
+    let stream = [sequence(&literals, 0xFFFF, 100), last_sequence(b"end")].concat();
+    let data = decompress_with_both(&stream).unwrap();
+    assert_eq!(data[0xFFFF..0xFFFF + 100], literals[..100]);
+}
+
+#[test]
+fn bad_lookbacks_are_rejected() {
+    // A lookback of 0 and one that points before the start of the output
+    for lookback in [0, 9] {
+        let stream = [sequence(&pattern(8, 4), lookback, 4), last_sequence(b"x")].concat();
+        assert_eq!(decompress_with_both(&stream), None);
+    }
+}
+
+#[test]
+fn truncated_streams_are_rejected() {
+    let stream = [
+        sequence(&pattern(20, 5), 3, 300),
+        last_sequence(&pattern(20, 6)),
+    ]
+    .concat();
+    assert!(decompress_with_both(&stream).is_some());
+    // Cut inside the extended sizes, the literals and the lookback
+    for len in [0, 1, 2, 10, 23] {
+        assert_eq!(decompress_with_both(&stream[..len]), None, "Length: {len}");
+    }
+}
+
+#[test]
+fn last_sequence_with_a_match_size_is_read() {
+    // Regression: The token of the last sequence can have a match size, it's ignored since there is no match after the literals
+    let stream = [0x54, b'h', b'e', b'l', b'l', b'o'];
+    assert_eq!(decompress_with_both(&stream), Some(b"hello".to_vec()));
+}
+
+#[test]
+fn half_lookback_is_rejected() {
+    // Regression: A stream that ends in the middle of a lookback used to be read as if it had ended after the literals
+    let stream = [0x54, b'h', b'e', b'l', b'l', b'o', 0x05];
+    assert_eq!(decompress_with_both(&stream), None);
+}
+
+#[test]
+fn reference_compressed_data_is_read() {
+    for (len, seed) in [(0, 0), (1, 1), (100, 2), (4096, 3), (128 * 1024, 4)] {
+        // Half repeated data so there are matches
+        let data = [pattern(len / 2, seed), vec![seed; len - len / 2]].concat();
+        let compressed = lz4_flex::block::compress(&data);
+        assert_eq!(
+            lz4::lz4_decompress_blocks(&mut compressed.iter().copied(), Some(len)),
+            Ok(data)
+        );
+    }
+}
+
+#[test]
+fn compressed_data_is_read_by_the_reference() {
+    for (len, seed) in [(0, 0), (1, 1), (100, 2), (4096, 3), (128 * 1024, 4)] {
+        let data = [pattern(len / 2, seed), vec![seed; len - len / 2]].concat();
+        let compressed = lz4::lz4_compress_blocks(&data);
+        assert_eq!(
+            lz4_flex::block::decompress(&compressed, len).ok(),
+            Some(data.clone())
+        );
+        assert_eq!(decompress_with_both(&compressed), Some(data));
+    }
+}
+
+#[test]
+fn garbage_is_handled_the_same() {
+    // Random bytes are rarely valid lz4, but whatever they are both decoders have to agree
+    for seed in 0..=255 {
+        for len in [1, 2, 3, 7, 64, 300] {
+            decompress_with_both(&pattern(len, seed));
+        }
+    }
+}
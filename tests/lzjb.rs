@@ -0,0 +1,162 @@
+// Data compressed by lzjb_compress has to come back the same from lzjb_decompress, since this is what repaired lzjb blocks are written with
+use szfs::{
+    lzjb::{self, LzjbError, MATCH_MAX, MATCH_MIN, OFFSET_MASK},
+    test_image::pattern,
+};
+
+// Returns: The compressed data, after checking that it decompresses back to the input, with and without the size
+fn assert_round_trips(data: &[u8]) -> Vec<u8> {
+    let compressed = lzjb::lzjb_compress(data);
+    assert_eq!(
+        lzjb::lzjb_decompress(&mut compressed.iter().copied(), Some(data.len())).as_deref(),
+        Ok(data)
+    );
+    assert_eq!(
+        lzjb::lzjb_decompress(&mut compressed.iter().copied(), None).as_deref(),
+        Ok(data)
+    );
+    compressed
+}
+
+// Returns: The lookbacks of all the matches in the compressed data
+fn get_lookbacks(compressed: &[u8]) -> Vec<usize> {
+    let mut lookbacks = Vec::new();
+    let mut pos = 0;
+    while pos < compressed.len() {
+        let copymap = compressed[pos];
+        pos += 1;
+        for bit in 0..8 {
+            if pos >= compressed.len() {
+                break;
+            }
+            if copymap & (1 << bit) != 0 {
+                let lookback = ((usize::from(compressed[pos]) << 8)
+                    | usize::from(compressed[pos + 1]))
+                    & OFFSET_MASK;
+                lookbacks.push(lookback);
+                pos += 2;
+            } else {
+                pos += 1;
+            }
+        }
+    }
+    lookbacks
+}
+
+#[test]
+fn empty_input_round_trips() {
+    assert!(assert_round_trips(&[]).is_empty());
+    assert_eq!(
+        lzjb::lzjb_decompress(&mut std::iter::empty(), Some(0)),
+        Ok(Vec::new())
+    );
+}
+
+#[test]
+fn short_inputs_round_trip() {
+    // Shorter than a match, they can only be copied
+    for len in 1..=MATCH_MAX + 1 {
+        let data = pattern(len, 1);
+        let compressed = assert_round_trips(&data);
+        assert!(get_lookbacks(&compressed).is_empty());
+    }
+}
+
+#[test]
+fn incompressible_input_round_trips() {
+    // Every 8 bytes that can't be compressed cost one more byte for the copymap
+    for len in [100, 4096, 128 * 1024] {
+        let data = pattern(len, 2);
+        let compressed = assert_round_trips(&data);
+        assert!(compressed.len() <= len + len.div_ceil(8));
+    }
+}
+
+#[test]
+fn all_zero_input_round_trips() {
+    for len in [MATCH_MAX + 1, 512, 128 * 1024] {
+        let data = vec![0; len];
+        let compressed = assert_round_trips(&data);
+        // Everything but the start and the last bytes are matches of the longest length
+        assert!(compressed.len() < len / 16 + 2 * MATCH_MAX);
+    }
+}
+
+#[test]
+fn max_offset_round_trips() {
+    // The same bytes again 1023 bytes later, the furthest back a match can point
+    let repeated = pattern(MATCH_MAX + 2, 3);
+    let mut data = repeated.clone();
+    data.resize(OFFSET_MASK, 0);
+    data.extend(&repeated);
+    data.extend(vec![0xff; MATCH_MAX]);
+    let compressed = assert_round_trips(&data);
+    assert!(get_lookbacks(&compressed).contains(&OFFSET_MASK));
+
+    // One byte further and it doesn't fit in the offset anymore, so it's copied instead
+    let mut data = repeated.clone();
+    data.resize(OFFSET_MASK + 1, 0);
+    data.extend(&repeated);
+    data.extend(vec![0xff; MATCH_MAX]);
+    let compressed = assert_round_trips(&data);
+    assert!(get_lookbacks(&compressed)
+        .iter()
+        .all(|lookback| *lookback < OFFSET_MASK));
+}
+
+#[test]
+fn mixed_inputs_round_trip() {
+    for seed in 0..16u8 {
+        // Runs of repeated data between incompressible data, of lengths around the match lengths
+        let mut data = Vec::new();
+        for (index, len) in [1, MATCH_MIN, MATCH_MAX, MATCH_MAX + 1, 700, 2000]
+            .into_iter()
+            .enumerate()
+        {
+            data.extend(pattern(len, seed ^ index as u8));
+            data.extend(vec![seed; len]);
+            data.extend(pattern(len, seed).iter().rev());
+        }
+        assert_round_trips(&data);
+    }
+}
+
+#[test]
+fn truncated_input_is_an_error() {
+    let data = [pattern(200, 4), vec![0; 200]].concat();
+    let compressed = lzjb::lzjb_compress(&data);
+    for len in [1, compressed.len() / 2, compressed.len() - 1] {
+        assert!(matches!(
+            lzjb::lzjb_decompress(&mut compressed[..len].iter().copied(), Some(data.len())),
+            Err(LzjbError::TruncatedInput { .. })
+        ));
+    }
+    // Without a size, only a match that is cut in half is an error
+    let match_start = [0b1, 0x00];
+    assert_eq!(
+        lzjb::lzjb_decompress(&mut match_start.into_iter(), None),
+        Err(LzjbError::TruncatedInput { output_len: 0 })
+    );
+}
+
+#[test]
+fn bad_lookback_is_an_error() {
+    // A literal and then a match 2 bytes back
+    let data = [0b10, b'a', 0x00, 0x02];
+    assert_eq!(
+        lzjb::lzjb_decompress(&mut data.into_iter(), Some(4)),
+        Err(LzjbError::BadLookback {
+            lookback: 2,
+            output_len: 1
+        })
+    );
+    // A match with a lookback of 0 would copy bytes that aren't there yet
+    let data = [0b10, b'a', 0x00, 0x00];
+    assert_eq!(
+        lzjb::lzjb_decompress(&mut data.into_iter(), Some(4)),
+        Err(LzjbError::BadLookback {
+            lookback: 0,
+            output_len: 1
+        })
+    );
+}
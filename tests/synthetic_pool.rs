@@ -0,0 +1,412 @@
+// Reads pools made by test_image::ImageBuilder back, so the whole path from the labels to the data of a file is checked against known contents
+#![cfg(feature = "disk")]
+
+use std::{fs::File, path::PathBuf};
+
+use szfs::{
+    check_labels, cli,
+    dmu::{DNode, DNodeReadError, ObjSetType, ObjType},
+    reader::{self, PoolReader},
+    rewind::{self, UberblockSkipReason},
+    test_image::{pattern, BlockPointerSpec, DvaSpec, ImageBuilder, ImageConfig},
+    zio::{BlockPointer, ChecksumMethod, CompressionMethod, Vdevs},
+    zpl::{self, DirectoryEntryKind},
+    VdevFile,
+};
+use tempfile::TempDir;
+
+fn big_file() -> Vec<u8> {
+    // More than 3 records, so it needs an indirect block, and it goes past the first megabyte of the raidz
+    pattern(1536 * 1024 + 1000, 7)
+}
+
+fn build_image(config: ImageConfig) -> ImageBuilder {
+    let mut builder = ImageBuilder::new(config);
+    builder.add_file("", "README", b"This pool was made by test_image\n");
+    builder.add_file("", "docs/notes.txt", &pattern(3000, 1));
+    builder.add_file("", "docs/empty", &[]);
+    builder.add_file("", "data/big.bin", &big_file());
+    builder.add_directory("", "emptydir");
+    builder.add_file("home", "user/hello.txt", b"hello\n");
+    builder
+}
+
+fn write_disks(disks: &[Vec<u8>]) -> (TempDir, Vec<PathBuf>) {
+    let dir = tempfile::tempdir().unwrap();
+    let paths = disks
+        .iter()
+        .enumerate()
+        .map(|(index, disk)| {
+            let path = dir.path().join(format!("disk{index}.img"));
+            std::fs::write(&path, disk).unwrap();
+            path
+        })
+        .collect();
+    (dir, paths)
+}
+
+fn open_files(paths: &[PathBuf]) -> Vec<VdevFile> {
+    paths
+        .iter()
+        .map(|path| VdevFile::from(File::open(path).unwrap()))
+        .collect()
+}
+
+#[test]
+fn uberblocks_are_found_in_all_labels() {
+    let config = ImageConfig::default();
+    let (_dir, paths) = write_disks(&build_image(config.clone()).build());
+    let mut devices = open_files(&paths);
+
+    for (index, device) in devices.iter_mut().enumerate() {
+        let labels = check_labels(device);
+        assert_eq!(labels.len(), 4);
+        for label in labels {
+            assert!(label.readable && label.has_checksum_magic);
+            assert_eq!(label.guid, Some(config.get_device_guid(index)));
+            assert_eq!(label.txg, Some(config.txg));
+        }
+    }
+
+    let uberblocks = rewind::collect_uberblocks(&mut devices[0]);
+    assert_eq!(uberblocks.len(), 1);
+    assert_eq!(uberblocks[0].txg, config.txg);
+    assert_eq!(uberblocks[0].guid_sum, config.get_guid_sum());
+}
+
+#[test]
+fn uberblocks_are_found_without_the_front_labels() {
+    let config = ImageConfig::default();
+    let mut disks = build_image(config.clone()).build();
+    disks[0][..2 * szfs::VDEV_LABEL_SIZE as usize].fill(0);
+    let (_dir, paths) = write_disks(&disks);
+    let mut devices = open_files(&paths);
+
+    let uberblocks = rewind::collect_uberblocks(&mut devices[0]);
+    assert_eq!(uberblocks.len(), 1);
+    assert_eq!(uberblocks[0].txg, config.txg);
+}
+
+#[test]
+fn newest_readable_uberblock_is_used() {
+    let config = ImageConfig::default();
+    let mut builder = build_image(config.clone());
+    // A newer uberblock whose MOS was never written
+    let unwritten = BlockPointerSpec {
+        dvas: vec![DvaSpec {
+            vdev_id: 0,
+            offset: 16 * 1024 * 1024,
+            allocated_size: 2048,
+            is_gang: false,
+        }],
+        level: 0,
+        typ: ObjType::ObjSet,
+        checksum_method: ChecksumMethod::Fletcher4,
+        compression_method: CompressionMethod::Off,
+        physical_size: 1024,
+        logical_size: 1024,
+        birth_txg: config.txg + 5,
+        fill: 1,
+        checksum: [1, 2, 3, 4],
+    };
+    builder.add_uberblock(config.txg + 5, unwritten.to_bytes_le());
+    let (_dir, paths) = write_disks(&builder.build());
+
+    let mut devices = open_files(&paths);
+    let mut uberblocks = rewind::collect_uberblocks(&mut devices[0]);
+    assert_eq!(
+        uberblocks.iter().map(|ub| ub.txg).collect::<Vec<_>>(),
+        vec![config.txg, config.txg + 5]
+    );
+    let mut raidz = cli::make_raidz(&mut devices, config.nparity, config.get_sector_size());
+    let mut vdevs = Vdevs::new();
+    vdevs.insert(0usize, &mut raidz);
+    let (mos, selection) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs).unwrap();
+    assert_eq!(mos.typ, ObjSetType::Meta);
+    assert_eq!(selection.used_txg, config.txg);
+    assert_eq!(
+        selection.skipped,
+        vec![(config.txg + 5, UberblockSkipReason::RootBlockUnreadable)]
+    );
+
+    // The pool can still be opened
+    let mut pool = PoolReader::open(&paths).unwrap();
+    assert_eq!(pool.list_datasets().len(), 2);
+}
+
+#[test]
+fn mos_walk_finds_the_dsl_objects() {
+    let config = ImageConfig::default();
+    let (_dir, paths) = write_disks(&build_image(config.clone()).build());
+    let mut devices = open_files(&paths);
+    let mut uberblocks = rewind::collect_uberblocks(&mut devices[0]);
+    let mut raidz = cli::make_raidz(&mut devices, config.nparity, config.get_sector_size());
+    let mut vdevs = Vdevs::new();
+    vdevs.insert(0usize, &mut raidz);
+    let (mut mos, _) = rewind::open_newest_mos(&mut uberblocks, &mut vdevs).unwrap();
+
+    // The dnodes fit in one block of the metadnode
+    assert_eq!(mos.get_object_count(), 32);
+    let mut types = Vec::new();
+    for object_id in 0..mos.get_object_count() {
+        match mos.try_get_raw_dnode_at(object_id, &mut vdevs) {
+            Ok(_) => types.push((
+                object_id,
+                mos.get_dnode_base_at(object_id as usize, &mut vdevs)
+                    .unwrap()
+                    .1,
+            )),
+            Err(err) => assert_eq!(err, DNodeReadError::Unallocated),
+        }
+    }
+    assert_eq!(
+        types,
+        vec![
+            (1, ObjType::ObjectDirectory),
+            (2, ObjType::DSLDirectory),
+            (3, ObjType::DSLDataset),
+            (4, ObjType::DSLDirectoryChildMap),
+            (5, ObjType::DSLDirectory),
+            (6, ObjType::DSLDataset),
+            (7, ObjType::DSLDirectoryChildMap),
+        ]
+    );
+    assert_eq!(
+        mos.try_get_dnode_at(32, &mut vdevs).err(),
+        Some(DNodeReadError::OutOfBounds)
+    );
+
+    let Some(DNode::DSLDirectory(root_directory)) = mos.get_dnode_at(2, &mut vdevs) else {
+        panic!("Object 2 should be the root dsl directory");
+    };
+    let root_directory = root_directory.parse_bonus_data().unwrap();
+    assert_eq!(root_directory.get_head_dataset_object_number(), 3);
+    assert_eq!(root_directory.get_parent_object_number(), 0);
+    assert_eq!(root_directory.get_children_directory_object_number(), 4);
+
+    let datasets = reader::collect_all_datasets(&mut mos, &config.pool_name, &mut vdevs);
+    let mut home =
+        reader::open_dataset(&mut mos, &config.pool_name, "testpool/home", &mut vdevs).unwrap();
+    assert_eq!(datasets.len(), 2);
+    assert_eq!(home.typ, ObjSetType::Zfs);
+    assert!(matches!(
+        home.get_dnode_at(1, &mut vdevs),
+        Some(DNode::MasterNode(_))
+    ));
+}
+
+#[test]
+fn datasets_are_listed() {
+    let (_dir, paths) = write_disks(&build_image(ImageConfig::default()).build());
+    let mut pool = PoolReader::open(&paths).unwrap();
+    assert_eq!(pool.get_name(), "testpool");
+    let datasets = pool.list_datasets();
+    assert_eq!(
+        datasets
+            .iter()
+            .map(|dataset| (dataset.name.as_str(), dataset.is_snapshot))
+            .collect::<Vec<_>>(),
+        vec![("testpool", false), ("testpool/home", false)]
+    );
+}
+
+#[test]
+fn disks_can_be_given_in_any_order() {
+    let (_dir, mut paths) = write_disks(&build_image(ImageConfig::default()).build());
+    paths.reverse();
+    let mut pool = PoolReader::open(&paths).unwrap();
+    let mut file = pool.open_file("testpool", "data/big.bin").unwrap();
+    assert_eq!(
+        pool.read_file(&mut file, 0, usize::MAX).unwrap(),
+        big_file()
+    );
+}
+
+#[test]
+fn directories_are_listed() {
+    let (_dir, paths) = write_disks(&build_image(ImageConfig::default()).build());
+    let mut pool = PoolReader::open(&paths).unwrap();
+    let list = |pool: &mut PoolReader, dataset: &str, path: &str| {
+        pool.list_directory(dataset, path).map(|entries| {
+            entries
+                .into_iter()
+                .map(|entry| (entry.name, entry.kind))
+                .collect::<Vec<_>>()
+        })
+    };
+
+    assert_eq!(
+        list(&mut pool, "testpool", "/").unwrap(),
+        vec![
+            (String::from("README"), DirectoryEntryKind::File),
+            (String::from("data"), DirectoryEntryKind::Directory),
+            (String::from("docs"), DirectoryEntryKind::Directory),
+            (String::from("emptydir"), DirectoryEntryKind::Directory),
+        ]
+    );
+    assert_eq!(
+        list(&mut pool, "testpool", "docs").unwrap(),
+        vec![
+            (String::from("empty"), DirectoryEntryKind::File),
+            (String::from("notes.txt"), DirectoryEntryKind::File),
+        ]
+    );
+    assert_eq!(list(&mut pool, "testpool", "emptydir").unwrap(), vec![]);
+    assert_eq!(
+        list(&mut pool, "testpool/home", "user").unwrap(),
+        vec![(String::from("hello.txt"), DirectoryEntryKind::File)]
+    );
+    assert!(list(&mut pool, "testpool", "README").is_err());
+    assert!(list(&mut pool, "testpool", "nothing/here").is_err());
+    assert!(list(&mut pool, "testpool/nothing", "/").is_err());
+}
+
+#[test]
+fn files_are_read() {
+    let (_dir, paths) = write_disks(&build_image(ImageConfig::default()).build());
+    let mut pool = PoolReader::open(&paths).unwrap();
+
+    let mut readme = pool.open_file("testpool", "README").unwrap();
+    assert_eq!(readme.get_size(), 33);
+    assert_eq!(
+        pool.read_file(&mut readme, 0, 100).unwrap(),
+        b"This pool was made by test_image\n"
+    );
+    assert_eq!(pool.read_file(&mut readme, 10, 4).unwrap(), b"was ");
+    assert_eq!(pool.read_file(&mut readme, 33, 10).unwrap(), b"");
+
+    let mut notes = pool.open_file("testpool", "/docs/notes.txt").unwrap();
+    assert_eq!(
+        pool.read_file(&mut notes, 0, 5000).unwrap(),
+        pattern(3000, 1)
+    );
+
+    let mut empty = pool.open_file("testpool", "docs/empty").unwrap();
+    assert_eq!(empty.get_size(), 0);
+    assert_eq!(pool.read_file(&mut empty, 0, 10).unwrap(), b"");
+
+    // Reads that start and end in the middle of the records
+    let data = big_file();
+    let mut big = pool.open_file("testpool", "data/big.bin").unwrap();
+    assert_eq!(big.get_size(), data.len() as u64);
+    for (offset, amount) in [
+        (0, data.len()),
+        (128 * 1024 - 100, 300),
+        (1_000_000, 400_000),
+    ] {
+        assert_eq!(
+            pool.read_file(&mut big, offset as u64, amount).unwrap(),
+            data[offset..(offset + amount).min(data.len())]
+        );
+    }
+
+    let mut hello = pool.open_file("testpool/home", "user/hello.txt").unwrap();
+    assert_eq!(pool.read_file(&mut hello, 0, 100).unwrap(), b"hello\n");
+
+    assert!(pool.open_file("testpool", "docs").is_err());
+    assert!(pool.open_file("testpool", "docs/missing").is_err());
+    assert!(pool.open_file("testpool/home", "README").is_err());
+}
+
+// Returns: The (offset, physical size) of the blocks of the file, from the dnode of the file
+fn get_file_blocks(
+    vdevs: &mut Vdevs,
+    config: &ImageConfig,
+    devices: &mut [VdevFile],
+    path: &str,
+) -> Vec<(u64, usize)> {
+    let mut uberblocks = rewind::collect_uberblocks(&mut devices[0]);
+    let (mut mos, _) = rewind::open_newest_mos(&mut uberblocks, vdevs).unwrap();
+    let mut dataset =
+        reader::open_dataset(&mut mos, &config.pool_name, &config.pool_name, vdevs).unwrap();
+    let object_id = zpl::lookup_path(&mut dataset, path, vdevs).unwrap();
+    let Some(DNode::PlainFileContents(mut file)) = dataset.get_dnode_at(object_id as usize, vdevs)
+    else {
+        panic!("{path} should be a plain file");
+    };
+    (0..=file.0.get_max_indirect_block_id() as usize)
+        .map(|block_id| {
+            let BlockPointer::Normal(block_pointer) =
+                file.0.get_data_block_pointer(block_id, vdevs).unwrap()
+            else {
+                panic!("The blocks of the file shouldn't be embedded");
+            };
+            (
+                block_pointer.get_dvas()[0].as_ref().unwrap().parse_offset(),
+                block_pointer.parse_physical_size() as usize,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn raidz_layouts_are_read_back() {
+    for (ndevices, nparity, ashift) in [(3, 1, 9), (4, 2, 9), (5, 1, 12), (7, 3, 9), (2, 1, 9)] {
+        let config = ImageConfig {
+            ndevices,
+            nparity,
+            ashift,
+            ..Default::default()
+        };
+        let mut builder = build_image(config.clone());
+        // Blocks of every size up to a few rows, so there are blocks that don't fill their last row
+        for nsectors in 1..=2 * ndevices {
+            builder.add_file(
+                "",
+                &format!("sizes/{nsectors}"),
+                &pattern(nsectors * config.get_sector_size() - 100, nsectors as u8),
+            );
+        }
+        let (_dir, paths) = write_disks(&builder.build());
+
+        let mut pool = PoolReader::open(&paths).unwrap();
+        let mut big = pool.open_file("testpool", "data/big.bin").unwrap();
+        assert_eq!(
+            pool.read_file(&mut big, 0, usize::MAX).unwrap(),
+            big_file(),
+            "raidz{nparity} with {ndevices} disks and ashift {ashift}"
+        );
+        for nsectors in 1..=2 * ndevices {
+            let mut file = pool
+                .open_file("testpool", &format!("sizes/{nsectors}"))
+                .unwrap();
+            assert_eq!(
+                pool.read_file(&mut file, 0, usize::MAX).unwrap(),
+                pattern(nsectors * config.get_sector_size() - 100, nsectors as u8),
+                "raidz{nparity} with {ndevices} disks and ashift {ashift}, {nsectors} sectors"
+            );
+        }
+
+        // The parity has to match the data too, or reading with a damaged disk would give the wrong data
+        let mut devices = open_files(&paths);
+        let mut label_devices = open_files(&paths);
+        let mut raidz = cli::make_raidz(&mut devices, nparity, config.get_sector_size());
+        let blocks = {
+            let mut vdevs = Vdevs::new();
+            vdevs.insert(0usize, &mut raidz);
+            let mut blocks =
+                get_file_blocks(&mut vdevs, &config, &mut label_devices, "data/big.bin");
+            for nsectors in 1..=2 * ndevices {
+                blocks.extend(get_file_blocks(
+                    &mut vdevs,
+                    &config,
+                    &mut label_devices,
+                    &format!("sizes/{nsectors}"),
+                ));
+            }
+            blocks
+        };
+        if nparity == 1 {
+            // The big file is past the first megabyte, so the raidz1 quirk on odd megabytes is read through
+            assert!(blocks
+                .iter()
+                .any(|(offset, _)| (offset / (1024 * 1024)) % 2 != 0));
+        }
+        for (offset, psize) in blocks {
+            assert!(
+                raidz.verify_stripe(offset, psize).unwrap().is_consistent(),
+                "raidz{nparity} with {ndevices} disks and ashift {ashift}, block at {offset}"
+            );
+        }
+    }
+}